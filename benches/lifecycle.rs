@@ -0,0 +1,184 @@
+//! Times each phase of a VM's life and writes the results to a JSON file.
+//!
+//! ```bash
+//! cargo bench --bench lifecycle                 # mock execute path only
+//! FC_BENCH=1 sudo -E cargo bench --bench lifecycle
+//! ```
+//!
+//! With `FC_BENCH=1` real Firecracker VMs are booted, configured from the same `FC_*` variables
+//! as the server, and each phase is timed separately: TAP setup, Firecracker spawn to API
+//! socket, `configure_and_run_vm`, boot to agent ready and warm-pool execute round trip. Every
+//! VM is created and destroyed here; the process-wide pool is never used.
+//!
+//! `FC_BENCH_ITERATIONS` sets the number of boots (default 5), and `FC_BENCH_OUTPUT` the
+//! results file (default `target/bench-lifecycle.json`).
+
+use firecracker_poc::backend::BackendKind;
+use firecracker_poc::config::RunnerConfig;
+use firecracker_poc::deadline::Deadline;
+use firecracker_poc::executor::ExecutorService;
+use firecracker_poc::runner::{EXECUTION_BUDGET, ExecutionSpec, VMManager};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CODE: &str = "print('bench')";
+
+/// Round trips timed per boot, on each executor's warm VM
+const EXECUTES_PER_ITERATION: usize = 20;
+
+/// Round trips timed on the mock backend
+const MOCK_EXECUTES: usize = 1000;
+
+type Error = Box<dyn std::error::Error>;
+
+/// Samples of every phase, by phase name
+#[derive(Default)]
+struct Results(BTreeMap<&'static str, Vec<Duration>>);
+
+impl Results {
+    async fn time<T, E>(
+        &mut self,
+        phase: &'static str,
+        step: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, E> {
+        let start = Instant::now();
+        let output = step.await?;
+        self.0.entry(phase).or_default().push(start.elapsed());
+        Ok(output)
+    }
+
+    fn to_json(&self) -> Value {
+        let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+        let phases: serde_json::Map<_, _> = self
+            .0
+            .iter()
+            .map(|(phase, samples)| {
+                let mut sorted = samples.clone();
+                sorted.sort();
+                // Nearest rank
+                let percentile = |p: f64| {
+                    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+                    ms(&sorted[rank.clamp(1, sorted.len()) - 1])
+                };
+                let summary = json!({
+                    "samples": sorted.len(),
+                    "mean_ms": sorted.iter().map(ms).sum::<f64>() / sorted.len() as f64,
+                    "p50_ms": percentile(50.0),
+                    "p95_ms": percentile(95.0),
+                    "min_ms": ms(&sorted[0]),
+                    "max_ms": ms(&sorted[sorted.len() - 1]),
+                });
+                (phase.to_string(), summary)
+            })
+            .collect();
+        Value::Object(phases)
+    }
+}
+
+/// Time `count` round trips on a private executor whose one VM is booted beforehand
+async fn execute_round_trips(
+    results: &mut Results,
+    phase: &'static str,
+    config: RunnerConfig,
+    count: usize,
+) -> Result<(), Error> {
+    let executor = ExecutorService::new(Arc::new(config));
+    if executor.warm(1).await != 1 {
+        executor.shutdown().await;
+        return Err(format!("{phase}: could not boot a VM to warm the pool").into());
+    }
+    let mut outcome = Ok(());
+    for _ in 0..count {
+        if let Err(e) = results
+            .time(phase, executor.execute(ExecutionSpec::code(CODE)))
+            .await
+        {
+            outcome = Err(format!("{phase}: {e}").into());
+            break;
+        }
+    }
+    executor.shutdown().await;
+    outcome
+}
+
+/// Boot one VM a phase at a time, then tear it down whatever happened
+async fn boot_phases(results: &mut Results, config: &Arc<RunnerConfig>) -> Result<(), Error> {
+    let mut vm = VMManager::with_config(config.clone());
+    let booted = async {
+        results.time("tap_setup", vm.setup_networking()).await?;
+        results
+            .time("spawn_to_socket", vm.start_firecracker())
+            .await?;
+        results
+            .time("configure_and_run_vm", vm.configure_and_run_vm())
+            .await?;
+        results
+            .time(
+                "boot_to_agent_ready",
+                vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET)),
+            )
+            .await
+    }
+    .await;
+    vm.cleanup().await?;
+    Ok(booted?)
+}
+
+async fn run(results: &mut Results, firecracker: bool) -> Result<(), Error> {
+    let mock = RunnerConfig {
+        backend: BackendKind::Mock,
+        ..RunnerConfig::default()
+    };
+    execute_round_trips(results, "mock_execute_round_trip", mock, MOCK_EXECUTES).await?;
+    if !firecracker {
+        return Ok(());
+    }
+
+    let config = RunnerConfig {
+        backend: BackendKind::Firecracker,
+        ..RunnerConfig::from_env()
+    };
+    let iterations: usize = std::env::var("FC_BENCH_ITERATIONS")
+        .ok()
+        .and_then(|raw| raw.parse().ok())
+        .unwrap_or(5);
+    let shared = Arc::new(config.clone());
+    for _ in 0..iterations {
+        boot_phases(results, &shared).await?;
+    }
+    execute_round_trips(
+        results,
+        "warm_execute_round_trip",
+        config,
+        iterations * EXECUTES_PER_ITERATION,
+    )
+    .await
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    // Only `cargo bench` passes `--bench`; `cargo test --benches` just checks this builds
+    if !std::env::args().any(|arg| arg == "--bench") {
+        return Ok(());
+    }
+    let firecracker = std::env::var("FC_BENCH").is_ok_and(|v| v == "1");
+    if !firecracker {
+        eprintln!("FC_BENCH is not 1: only the mock execute path is measured");
+    }
+
+    let mut results = Results::default();
+    let outcome = run(&mut results, firecracker).await;
+    let report = json!({
+        "firecracker": firecracker,
+        "phases": results.to_json(),
+    });
+    let output =
+        std::env::var("FC_BENCH_OUTPUT").unwrap_or_else(|_| "target/bench-lifecycle.json".into());
+    std::fs::write(&output, serde_json::to_string_pretty(&report)? + "\n")?;
+    println!("{}", serde_json::to_string_pretty(&report["phases"])?);
+    eprintln!("results written to {output}");
+    outcome
+}
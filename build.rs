@@ -0,0 +1,27 @@
+fn main() {
+    // Baked into the binary for `/version`; builds outside a git checkout report "unknown"
+    let sha = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=FC_GIT_SHA={sha}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+/// Generate the `Executor` service and messages from `proto/executor.proto`
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    let descriptors = protox::compile(["proto/executor.proto"], ["proto"])
+        .expect("proto/executor.proto should compile");
+    tonic_build::configure()
+        .compile_fds(descriptors)
+        .expect("generating gRPC code should succeed");
+    println!("cargo:rerun-if-changed=proto/executor.proto");
+}
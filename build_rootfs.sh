@@ -328,3 +328,4 @@ echo "Endpoints:"
 echo "  GET  /health   - Health check"
 echo "  POST /execute  - Execute Python code"
 echo "  POST /shutdown - Shutdown VM"
+echo "  POST /set-time - Set the guest clock to the host's"
@@ -0,0 +1,42 @@
+//! Run a snippet through a running server with the typed client.
+//!
+//! ```bash
+//! cargo run --example client --features client -- http://127.0.0.1:3000 "print(6 * 7)"
+//! ```
+//!
+//! `FC_API_KEY` is sent as the bearer token when set.
+
+use firecracker_poc::ExecuteRequest;
+use firecracker_poc::client::FirecrackerPocClient;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let base_url = args
+        .next()
+        .unwrap_or_else(|| "http://127.0.0.1:3000".to_string());
+    let code = args
+        .next()
+        .unwrap_or_else(|| "print('Hello from Firecracker!')".to_string());
+
+    let mut client = FirecrackerPocClient::new(base_url);
+    if let Ok(key) = std::env::var("FC_API_KEY") {
+        client = client.with_api_key(key);
+    }
+
+    let health = client.health().await?;
+    println!("Server is {}", health.status);
+
+    let response = client
+        .execute(&ExecuteRequest {
+            code: Some(code),
+            ..Default::default()
+        })
+        .await?;
+    print!("{}", response.stdout);
+    eprint!("{}", response.stderr);
+    if !response.success {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,32 @@
+//! Run code on Firecracker VMs from your own binary, without the bundled HTTP server.
+//!
+//! ```bash
+//! cargo run --example embedded -- "print(6 * 7)"
+//! ```
+//!
+//! The executor is configured from the same `FC_*` variables as the server.
+
+use firecracker_poc::config::RunnerConfig;
+use firecracker_poc::executor::ExecutorService;
+use firecracker_poc::runner::ExecutionSpec;
+use std::sync::Arc;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let code = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "print('Hello from Firecracker!')".to_string());
+
+    let executor = ExecutorService::new(Arc::new(RunnerConfig::from_env()));
+    executor.warm(1).await;
+
+    let result = executor.execute(ExecutionSpec::code(code)).await;
+    println!("{:?}", executor.stats().await);
+    // Stops the pooled VM and removes its TAP device, socket and logs
+    executor.shutdown().await;
+
+    let response = result?;
+    print!("{}", response.stdout);
+    eprint!("{}", response.stderr);
+    Ok(())
+}
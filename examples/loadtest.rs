@@ -0,0 +1,38 @@
+//! Fire concurrent `/execute` requests at a running server and report throughput, latency
+//! percentiles, errors and VM reuse.
+//!
+//! ```bash
+//! cargo run --release --example loadtest --features client -- \
+//!     --target http://127.0.0.1:3000 --concurrency 16 --duration 60 --ramp-up 10
+//! # CI smoke run against a server started with FC_BACKEND=mock
+//! cargo run --example loadtest --features client -- --mock
+//! ```
+//!
+//! Exits with status 1 when more than `--max-error-rate` (default 0.01) of the requests fail.
+
+use firecracker_poc::loadtest::{self, LoadTestSpec};
+
+#[tokio::main]
+async fn main() {
+    let spec = match LoadTestSpec::from_args(std::env::args().skip(1)) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    println!(
+        "{} workers against {} for {:.0?} (ramp-up {:.0?})",
+        spec.concurrency, spec.target, spec.duration, spec.ramp_up
+    );
+    let report = loadtest::run(&spec).await;
+    println!("{}", report.render());
+    if !report.passes(spec.max_error_rate) {
+        eprintln!(
+            "Error rate {:.2}% exceeds the {:.2}% threshold",
+            report.error_rate() * 100.0,
+            spec.max_error_rate * 100.0
+        );
+        std::process::exit(1);
+    }
+}
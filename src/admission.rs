@@ -0,0 +1,196 @@
+use crate::ExecutionError;
+use crate::config::RunnerConfig;
+use serde::Serialize;
+
+/// Seconds clients are asked to wait after an admission rejection
+pub const ADMISSION_RETRY_AFTER_SECS: u64 = 5;
+
+/// Source of host resource usage, injectable for tests
+pub trait ResourceProbe {
+    /// Memory available for new allocations in MiB, if known
+    fn available_memory_mib(&self) -> Option<u64>;
+    /// Number of live Firecracker processes owned by this service
+    fn live_vm_count(&self) -> usize;
+}
+
+/// Probe reading `/proc/meminfo` and the runner's live VM registry
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostProbe;
+
+impl ResourceProbe for HostProbe {
+    fn available_memory_mib(&self) -> Option<u64> {
+        let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+        parse_mem_available_mib(&meminfo)
+    }
+
+    fn live_vm_count(&self) -> usize {
+        crate::runner::VM_REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+}
+
+/// Extract `MemAvailable` from `/proc/meminfo` contents, in MiB
+pub fn parse_mem_available_mib(meminfo: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let rest = line.strip_prefix("MemAvailable:")?;
+        let kib: u64 = rest.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kib / 1024)
+    })
+}
+
+/// Current resource usage and the budgets it is checked against
+#[derive(Debug, Clone, Serialize, PartialEq, utoipa::ToSchema)]
+pub struct ResourceUsage {
+    pub live_vms: usize,
+    pub max_vms: Option<usize>,
+    /// Memory committed to live VMs, in MiB
+    pub vm_memory_mib: u64,
+    pub max_vm_memory_mib: Option<u64>,
+    pub host_available_memory_mib: Option<u64>,
+    pub min_host_available_mib: u64,
+}
+
+impl ResourceUsage {
+    /// Sample usage through `probe`
+    pub fn sample(config: &RunnerConfig, probe: &impl ResourceProbe) -> Self {
+        let live_vms = probe.live_vm_count();
+        Self {
+            live_vms,
+            max_vms: config.max_vms,
+            vm_memory_mib: live_vms as u64 * config.vm_memory_mib,
+            max_vm_memory_mib: config.max_vm_memory_mib,
+            host_available_memory_mib: probe.available_memory_mib(),
+            min_host_available_mib: config.min_host_available_mib,
+        }
+    }
+
+    /// Export usage as gauges
+    pub fn record(&self) {
+        crate::telemetry::set_gauge("fc_live_vms", &[], self.live_vms as f64);
+        crate::telemetry::set_gauge("fc_vm_memory_mib", &[], self.vm_memory_mib as f64);
+        if let Some(available) = self.host_available_memory_mib {
+            crate::telemetry::set_gauge("fc_host_available_memory_mib", &[], available as f64);
+        }
+    }
+}
+
+/// Check whether one more VM fits within the configured budgets
+pub fn admit(config: &RunnerConfig, probe: &impl ResourceProbe) -> Result<(), ExecutionError> {
+    let usage = ResourceUsage::sample(config, probe);
+    usage.record();
+
+    if let Some(max_vms) = usage.max_vms
+        && usage.live_vms >= max_vms
+    {
+        return Err(exhausted(format!(
+            "{} of {max_vms} VMs running",
+            usage.live_vms
+        )));
+    }
+    if let Some(max_memory) = usage.max_vm_memory_mib
+        && usage.vm_memory_mib + config.vm_memory_mib > max_memory
+    {
+        return Err(exhausted(format!(
+            "{} MiB of {max_memory} MiB VM memory committed",
+            usage.vm_memory_mib
+        )));
+    }
+    // An unreadable meminfo (e.g. non-Linux development hosts) doesn't block VM creation
+    if let Some(available) = usage.host_available_memory_mib
+        && available < config.vm_memory_mib + usage.min_host_available_mib
+    {
+        return Err(exhausted(format!(
+            "{available} MiB host memory available, need {} MiB",
+            config.vm_memory_mib + usage.min_host_available_mib
+        )));
+    }
+    Ok(())
+}
+
+fn exhausted(detail: String) -> ExecutionError {
+    crate::telemetry::increment_counter("fc_admission_rejections_total", &[], 1);
+    ExecutionError::ResourceExhausted(detail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeProbe {
+        available_mib: Option<u64>,
+        live_vms: usize,
+    }
+
+    impl ResourceProbe for FakeProbe {
+        fn available_memory_mib(&self) -> Option<u64> {
+            self.available_mib
+        }
+
+        fn live_vm_count(&self) -> usize {
+            self.live_vms
+        }
+    }
+
+    fn config() -> RunnerConfig {
+        RunnerConfig {
+            vm_memory_mib: 512,
+            max_vms: Some(4),
+            max_vm_memory_mib: Some(1536),
+            min_host_available_mib: 256,
+            ..Default::default()
+        }
+    }
+
+    fn probe(available_mib: Option<u64>, live_vms: usize) -> FakeProbe {
+        FakeProbe {
+            available_mib,
+            live_vms,
+        }
+    }
+
+    #[test]
+    fn test_admits_within_budget() {
+        assert!(admit(&config(), &probe(Some(4096), 1)).is_ok());
+        // Unknown host memory is not a reason to reject
+        assert!(admit(&config(), &probe(None, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_over_vm_count() {
+        let config = RunnerConfig {
+            max_vm_memory_mib: None,
+            ..config()
+        };
+        let err = admit(&config, &probe(Some(8192), 4)).unwrap_err();
+        assert!(matches!(err, ExecutionError::ResourceExhausted(_)));
+        assert_eq!(err.code(), "resource_exhausted");
+    }
+
+    #[test]
+    fn test_rejects_over_vm_memory() {
+        // 3 * 512 MiB is committed; a fourth VM would exceed 1536 MiB
+        let err = admit(&config(), &probe(Some(8192), 3)).unwrap_err();
+        assert!(err.to_string().contains("1536 MiB"));
+    }
+
+    #[test]
+    fn test_rejects_low_host_memory() {
+        assert!(admit(&config(), &probe(Some(767), 0)).is_err());
+        assert!(admit(&config(), &probe(Some(768), 0)).is_ok());
+    }
+
+    #[test]
+    fn test_unlimited_by_default() {
+        let config = RunnerConfig::default();
+        assert!(admit(&config, &probe(Some(u64::MAX / 2), 1000)).is_ok());
+    }
+
+    #[test]
+    fn test_parse_mem_available() {
+        let meminfo = "MemTotal:       16318480 kB\nMemFree:         1023456 kB\nMemAvailable:    8388608 kB\n";
+        assert_eq!(parse_mem_available_mib(meminfo), Some(8192));
+        assert_eq!(parse_mem_available_mib("MemTotal: 1 kB\n"), None);
+    }
+}
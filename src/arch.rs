@@ -0,0 +1,256 @@
+use crate::config::ConfigError;
+use std::path::PathBuf;
+
+/// Kernel command line of every guest; `{console}` is filled in per architecture
+pub const BOOT_ARGS_TEMPLATE: &str =
+    "{console} reboot=k panic=1 pci=off init=/usr/local/bin/startup.sh";
+
+/// Host architectures Firecracker runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// Architecture named like `std::env::consts::ARCH`
+    pub fn from_name(name: &str) -> Option<Arch> {
+        match name {
+            "x86_64" => Some(Arch::X86_64),
+            "aarch64" => Some(Arch::Aarch64),
+            _ => None,
+        }
+    }
+
+    /// Architecture of this host, if Firecracker supports it
+    pub fn host() -> Option<Arch> {
+        Arch::from_name(std::env::consts::ARCH)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+        }
+    }
+
+    /// Suffix of the `FC_KERNEL_*` / `FC_ROOTFS_*` variables of this architecture
+    pub fn env_suffix(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "X86_64",
+            Arch::Aarch64 => "AARCH64",
+        }
+    }
+
+    /// Console part of the kernel command line; the aarch64 serial console comes up late, so
+    /// the boot console is kept until it does
+    pub fn console(self) -> &'static str {
+        match self {
+            Arch::X86_64 => "console=ttyS0",
+            Arch::Aarch64 => "keep_bootcon console=ttyS0",
+        }
+    }
+
+    /// `BOOT_ARGS_TEMPLATE` filled in for this architecture
+    pub fn boot_args(self) -> String {
+        BOOT_ARGS_TEMPLATE.replace("{console}", self.console())
+    }
+}
+
+/// Guest kernel and rootfs configured for one architecture
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArtifactSet {
+    pub kernel: Option<PathBuf>,
+    pub rootfs: Option<PathBuf>,
+}
+
+/// Guest kernel and rootfs a VM boots from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Artifacts {
+    pub kernel: PathBuf,
+    pub rootfs: PathBuf,
+}
+
+/// Artifact sets of every supported architecture, so one config serves a mixed fleet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchArtifacts {
+    pub x86_64: ArtifactSet,
+    pub aarch64: ArtifactSet,
+}
+
+impl Default for ArchArtifacts {
+    fn default() -> Self {
+        // The development images built by the setup scripts are x86_64 only
+        Self {
+            x86_64: ArtifactSet {
+                kernel: Some(PathBuf::from("./hello-vmlinux.bin")),
+                rootfs: Some(PathBuf::from("./alpine-python-api.ext4")),
+            },
+            aarch64: ArtifactSet::default(),
+        }
+    }
+}
+
+impl ArchArtifacts {
+    /// Load `FC_KERNEL_<ARCH>` and `FC_ROOTFS_<ARCH>`, keeping the defaults for unset ones
+    pub fn from_env() -> Self {
+        let mut artifacts = Self::default();
+        for arch in [Arch::X86_64, Arch::Aarch64] {
+            let suffix = arch.env_suffix();
+            let set = artifacts.get_mut(arch);
+            if let Ok(kernel) = std::env::var(format!("FC_KERNEL_{suffix}")) {
+                set.kernel = Some(PathBuf::from(kernel));
+            }
+            if let Ok(rootfs) = std::env::var(format!("FC_ROOTFS_{suffix}")) {
+                set.rootfs = Some(PathBuf::from(rootfs));
+            }
+        }
+        artifacts
+    }
+
+    pub fn get(&self, arch: Arch) -> &ArtifactSet {
+        match arch {
+            Arch::X86_64 => &self.x86_64,
+            Arch::Aarch64 => &self.aarch64,
+        }
+    }
+
+    fn get_mut(&mut self, arch: Arch) -> &mut ArtifactSet {
+        match arch {
+            Arch::X86_64 => &mut self.x86_64,
+            Arch::Aarch64 => &mut self.aarch64,
+        }
+    }
+
+    /// The artifacts configured for `arch`
+    pub fn select(&self, arch: Arch) -> Result<Artifacts, ConfigError> {
+        let set = self.get(arch);
+        let missing = |kind: &str, var: &str| {
+            ConfigError::Invalid(format!(
+                "no guest {kind} configured for {}; set {var}_{}",
+                arch.as_str(),
+                arch.env_suffix()
+            ))
+        };
+        Ok(Artifacts {
+            kernel: set
+                .kernel
+                .clone()
+                .ok_or_else(|| missing("kernel", "FC_KERNEL"))?,
+            rootfs: set
+                .rootfs
+                .clone()
+                .ok_or_else(|| missing("rootfs", "FC_ROOTFS"))?,
+        })
+    }
+
+    /// The artifacts for this host; empty paths when there are none, which preflight reports
+    pub fn for_host(&self) -> Artifacts {
+        Arch::host()
+            .and_then(|arch| self.select(arch).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Check this host's architecture is supported and its artifacts exist, so a missing kernel
+/// fails at startup instead of as a boot timeout
+pub fn preflight(artifacts: &ArchArtifacts) -> Result<Arch, ConfigError> {
+    let arch = Arch::host().ok_or_else(|| {
+        ConfigError::Invalid(format!(
+            "Firecracker does not support host architecture {}",
+            std::env::consts::ARCH
+        ))
+    })?;
+    let selected = artifacts.select(arch)?;
+    for (kind, path) in [("kernel", &selected.kernel), ("rootfs", &selected.rootfs)] {
+        if !path.is_file() {
+            return Err(ConfigError::Invalid(format!(
+                "guest {kind} for {} not found at {}",
+                arch.as_str(),
+                path.display()
+            )));
+        }
+    }
+    Ok(arch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifacts() -> ArchArtifacts {
+        ArchArtifacts {
+            x86_64: ArtifactSet {
+                kernel: Some(PathBuf::from("/srv/x86/vmlinux")),
+                rootfs: Some(PathBuf::from("/srv/x86/rootfs.ext4")),
+            },
+            aarch64: ArtifactSet {
+                kernel: Some(PathBuf::from("/srv/arm/Image")),
+                rootfs: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_select_per_arch() {
+        let selected = artifacts().select(Arch::X86_64).unwrap();
+        assert_eq!(selected.kernel, PathBuf::from("/srv/x86/vmlinux"));
+        assert_eq!(selected.rootfs, PathBuf::from("/srv/x86/rootfs.ext4"));
+
+        let err = artifacts().select(Arch::Aarch64).unwrap_err();
+        assert!(
+            err.to_string().contains("rootfs configured for aarch64"),
+            "{err}"
+        );
+        assert!(err.to_string().contains("FC_ROOTFS_AARCH64"), "{err}");
+
+        let err = ArchArtifacts::default().select(Arch::Aarch64).unwrap_err();
+        assert!(err.to_string().contains("FC_KERNEL_AARCH64"), "{err}");
+    }
+
+    #[test]
+    fn test_arch_names_and_boot_args() {
+        assert_eq!(Arch::from_name("x86_64"), Some(Arch::X86_64));
+        assert_eq!(Arch::from_name("aarch64"), Some(Arch::Aarch64));
+        assert_eq!(Arch::from_name("riscv64"), None);
+        assert!(
+            Arch::X86_64
+                .boot_args()
+                .starts_with("console=ttyS0 reboot=k")
+        );
+        assert!(
+            Arch::Aarch64
+                .boot_args()
+                .starts_with("keep_bootcon console=ttyS0 reboot=k")
+        );
+        assert!(!Arch::Aarch64.boot_args().contains('{'));
+    }
+
+    #[test]
+    fn test_preflight_requires_existing_artifacts() {
+        let Some(arch) = Arch::host() else {
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("arch-preflight-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let kernel = dir.join("vmlinux");
+        std::fs::write(&kernel, b"").unwrap();
+        let set = ArtifactSet {
+            kernel: Some(kernel),
+            rootfs: Some(dir.join("missing.ext4")),
+        };
+        let mut artifacts = ArchArtifacts {
+            x86_64: set.clone(),
+            aarch64: set,
+        };
+        let err = preflight(&artifacts).unwrap_err();
+        assert!(err.to_string().contains("guest rootfs"), "{err}");
+
+        let rootfs = dir.join("rootfs.ext4");
+        std::fs::write(&rootfs, b"").unwrap();
+        artifacts.get_mut(arch).rootfs = Some(rootfs);
+        assert_eq!(preflight(&artifacts).unwrap(), arch);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
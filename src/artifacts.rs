@@ -0,0 +1,729 @@
+use crate::output::{self, OutputEncoding};
+use crate::{ExecuteResponse, history::now_millis};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Output streams larger than this are stored as artifacts by default
+pub const DEFAULT_ARTIFACT_THRESHOLD_BYTES: usize = 64 * 1024;
+
+/// Longest name accepted for a job ID or artifact name
+const MAX_NAME_LEN: usize = 128;
+
+/// Where artifacts are kept
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArtifactBackend {
+    /// A directory on this host; artifacts are served at `/artifacts/{job_id}/{name}`
+    Local(PathBuf),
+    /// An S3-compatible bucket (`s3` feature); artifacts are linked through presigned URLs
+    S3 {
+        bucket: String,
+        /// Prepended to every object key, e.g. `fc/`
+        prefix: String,
+        /// Endpoint of an S3-compatible service such as MinIO; `None` uses AWS
+        endpoint: Option<String>,
+    },
+}
+
+/// Artifact storage settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactConfig {
+    /// `None` always returns output inline
+    pub backend: Option<ArtifactBackend>,
+    /// Output streams longer than this many bytes are stored instead of returned inline
+    pub threshold_bytes: usize,
+}
+
+impl Default for ArtifactConfig {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            threshold_bytes: DEFAULT_ARTIFACT_THRESHOLD_BYTES,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ArtifactError {
+    #[error("Artifact {0} not found")]
+    NotFound(String),
+    /// Job IDs and names are limited to ASCII letters, digits, `-`, `_` and `.`
+    #[error("Invalid artifact name {0:?}")]
+    InvalidName(String),
+    #[error("Artifact store error: {0}")]
+    Backend(String),
+}
+
+/// Reference to an output stream moved out of a response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Artifact {
+    /// `stdout` or `stderr`
+    pub name: String,
+    pub size: u64,
+    /// Presigned or service-relative URL the content can be downloaded from
+    pub url: String,
+}
+
+/// Blob storage for large execution output, keyed by job (or request) ID and name
+#[async_trait::async_trait]
+pub trait ArtifactStore: Send + Sync + std::fmt::Debug {
+    /// Store `bytes`, returning the URL clients download them from
+    async fn put(&self, job_id: &str, name: &str, bytes: Vec<u8>) -> Result<String, ArtifactError>;
+    async fn get(&self, job_id: &str, name: &str) -> Result<Vec<u8>, ArtifactError>;
+    /// Delete every artifact of `job_id`
+    async fn delete(&self, job_id: &str) -> Result<(), ArtifactError>;
+    /// Delete artifacts written before `cutoff` (milliseconds since the Unix epoch), returning
+    /// how many went
+    async fn remove_expired(&self, cutoff: u64) -> Result<usize, ArtifactError>;
+}
+
+/// Reject IDs and names that could escape their directory or object prefix
+fn check_name(name: &str) -> Result<(), ArtifactError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(ArtifactError::InvalidName(name.to_string()))
+    }
+}
+
+/// Service-relative URL of an artifact, answered by `GET /artifacts/{job_id}/{name}`
+pub fn proxy_url(job_id: &str, name: &str) -> String {
+    format!("/artifacts/{job_id}/{name}")
+}
+
+/// Artifacts in a directory, one subdirectory per job
+#[derive(Debug)]
+pub struct LocalArtifactStore {
+    root: PathBuf,
+}
+
+impl LocalArtifactStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, job_id: &str, name: &str) -> Result<PathBuf, ArtifactError> {
+        check_name(job_id)?;
+        check_name(name)?;
+        Ok(self.root.join(job_id).join(name))
+    }
+}
+
+fn io_error(e: std::io::Error) -> ArtifactError {
+    ArtifactError::Backend(e.to_string())
+}
+
+#[async_trait::async_trait]
+impl ArtifactStore for LocalArtifactStore {
+    async fn put(&self, job_id: &str, name: &str, bytes: Vec<u8>) -> Result<String, ArtifactError> {
+        let path = self.path(job_id, name)?;
+        tokio::fs::create_dir_all(self.root.join(job_id))
+            .await
+            .map_err(io_error)?;
+        // Readers never see a partly written file
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, bytes).await.map_err(io_error)?;
+        tokio::fs::rename(&partial, &path).await.map_err(io_error)?;
+        Ok(proxy_url(job_id, name))
+    }
+
+    async fn get(&self, job_id: &str, name: &str) -> Result<Vec<u8>, ArtifactError> {
+        let path = self.path(job_id, name)?;
+        tokio::fs::read(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => ArtifactError::NotFound(format!("{job_id}/{name}")),
+            _ => io_error(e),
+        })
+    }
+
+    async fn delete(&self, job_id: &str) -> Result<(), ArtifactError> {
+        check_name(job_id)?;
+        match tokio::fs::remove_dir_all(self.root.join(job_id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn remove_expired(&self, cutoff: u64) -> Result<usize, ArtifactError> {
+        let cutoff = UNIX_EPOCH + Duration::from_millis(cutoff);
+        let mut jobs = match tokio::fs::read_dir(&self.root).await {
+            Ok(jobs) => jobs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(io_error(e)),
+        };
+        let mut removed = 0;
+        while let Some(job) = jobs.next_entry().await.map_err(io_error)? {
+            let mut files = tokio::fs::read_dir(job.path()).await.map_err(io_error)?;
+            let mut kept = 0;
+            while let Some(file) = files.next_entry().await.map_err(io_error)? {
+                let modified = file
+                    .metadata()
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .map_err(io_error)?;
+                if modified < cutoff {
+                    tokio::fs::remove_file(file.path())
+                        .await
+                        .map_err(io_error)?;
+                    removed += 1;
+                } else {
+                    kept += 1;
+                }
+            }
+            if kept == 0 {
+                tokio::fs::remove_dir(job.path()).await.map_err(io_error)?;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(feature = "s3")]
+pub use s3::S3ArtifactStore;
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use super::{ArtifactError, ArtifactStore, check_name};
+    use aws_sdk_s3::presigning::PresigningConfig;
+    use aws_sdk_s3::primitives::ByteStream;
+    use std::time::Duration;
+
+    /// Longest validity SigV4 allows for a presigned URL
+    const MAX_PRESIGN_TTL: Duration = Duration::from_secs(7 * 24 * 3600);
+
+    /// Artifacts as objects `{prefix}{job_id}/{name}` in an S3-compatible bucket
+    #[derive(Debug)]
+    pub struct S3ArtifactStore {
+        client: aws_sdk_s3::Client,
+        bucket: String,
+        prefix: String,
+        /// Validity of the presigned download URLs returned by `put`
+        url_ttl: Duration,
+    }
+
+    impl S3ArtifactStore {
+        pub fn new(
+            client: aws_sdk_s3::Client,
+            bucket: impl Into<String>,
+            prefix: impl Into<String>,
+            url_ttl: Duration,
+        ) -> Self {
+            Self {
+                client,
+                bucket: bucket.into(),
+                prefix: prefix.into(),
+                url_ttl: url_ttl.min(MAX_PRESIGN_TTL),
+            }
+        }
+
+        /// Client configured from the standard AWS environment (credentials, `AWS_REGION`),
+        /// talking to `endpoint` when set
+        pub async fn client_from_env(endpoint: Option<&str>) -> aws_sdk_s3::Client {
+            let shared = aws_config::load_from_env().await;
+            let mut config = aws_sdk_s3::config::Builder::from(&shared)
+                // Checksum trailers and chunked uploads are not understood by every
+                // S3-compatible service
+                .request_checksum_calculation(
+                    aws_sdk_s3::config::RequestChecksumCalculation::WhenRequired,
+                );
+            if let Some(endpoint) = endpoint {
+                config = config.endpoint_url(endpoint).force_path_style(true);
+            }
+            aws_sdk_s3::Client::from_conf(config.build())
+        }
+
+        fn key(&self, job_id: &str, name: &str) -> Result<String, ArtifactError> {
+            check_name(job_id)?;
+            check_name(name)?;
+            Ok(format!("{}{job_id}/{name}", self.prefix))
+        }
+
+        /// Keys and write times (ms since the Unix epoch) of the objects under `prefix`
+        async fn list(&self, prefix: &str) -> Result<Vec<(String, u64)>, ArtifactError> {
+            let mut objects = Vec::new();
+            let mut pages = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(sdk_error)?;
+                for object in page.contents() {
+                    let Some(key) = object.key() else { continue };
+                    let modified = object
+                        .last_modified()
+                        .and_then(|at| at.to_millis().ok())
+                        .unwrap_or_default();
+                    objects.push((key.to_string(), modified.max(0) as u64));
+                }
+            }
+            Ok(objects)
+        }
+
+        async fn delete_key(&self, key: &str) -> Result<(), ArtifactError> {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(sdk_error)?;
+            Ok(())
+        }
+    }
+
+    fn sdk_error(e: impl std::error::Error) -> ArtifactError {
+        ArtifactError::Backend(aws_sdk_s3::error::DisplayErrorContext(e).to_string())
+    }
+
+    #[async_trait::async_trait]
+    impl ArtifactStore for S3ArtifactStore {
+        async fn put(
+            &self,
+            job_id: &str,
+            name: &str,
+            bytes: Vec<u8>,
+        ) -> Result<String, ArtifactError> {
+            let key = self.key(job_id, name)?;
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .content_type("application/octet-stream")
+                .body(ByteStream::from(bytes))
+                .send()
+                .await
+                .map_err(sdk_error)?;
+            let presigning = PresigningConfig::expires_in(self.url_ttl).map_err(sdk_error)?;
+            let request = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .presigned(presigning)
+                .await
+                .map_err(sdk_error)?;
+            Ok(request.uri().to_string())
+        }
+
+        async fn get(&self, job_id: &str, name: &str) -> Result<Vec<u8>, ArtifactError> {
+            let key = self.key(job_id, name)?;
+            let object = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .send()
+                .await
+                .map_err(|e| match e.as_service_error() {
+                    Some(service) if service.is_no_such_key() => {
+                        ArtifactError::NotFound(format!("{job_id}/{name}"))
+                    }
+                    _ => sdk_error(e),
+                })?;
+            let bytes = object.body.collect().await.map_err(sdk_error)?;
+            Ok(bytes.into_bytes().to_vec())
+        }
+
+        async fn delete(&self, job_id: &str) -> Result<(), ArtifactError> {
+            check_name(job_id)?;
+            for (key, _) in self.list(&format!("{}{job_id}/", self.prefix)).await? {
+                self.delete_key(&key).await?;
+            }
+            Ok(())
+        }
+
+        async fn remove_expired(&self, cutoff: u64) -> Result<usize, ArtifactError> {
+            let mut removed = 0;
+            for (key, modified) in self.list(&self.prefix).await? {
+                if modified < cutoff {
+                    self.delete_key(&key).await?;
+                    removed += 1;
+                }
+            }
+            Ok(removed)
+        }
+    }
+}
+
+/// The store selected by `config`, or `None` when output always stays inline. `url_ttl`
+/// bounds how long presigned URLs stay valid.
+pub async fn open_store(
+    config: &ArtifactConfig,
+    url_ttl: Duration,
+) -> Result<Option<Arc<dyn ArtifactStore>>, ArtifactError> {
+    match &config.backend {
+        None => Ok(None),
+        Some(ArtifactBackend::Local(root)) => Ok(Some(Arc::new(LocalArtifactStore::new(root)))),
+        #[cfg(feature = "s3")]
+        Some(ArtifactBackend::S3 {
+            bucket,
+            prefix,
+            endpoint,
+        }) => {
+            let client = S3ArtifactStore::client_from_env(endpoint.as_deref()).await;
+            Ok(Some(Arc::new(S3ArtifactStore::new(
+                client,
+                bucket.clone(),
+                prefix.clone(),
+                url_ttl,
+            ))))
+        }
+        #[cfg(not(feature = "s3"))]
+        Some(ArtifactBackend::S3 { .. }) => {
+            let _ = url_ttl;
+            Err(ArtifactError::Backend(
+                "FC_ARTIFACT_S3_BUCKET requires the s3 feature".to_string(),
+            ))
+        }
+    }
+}
+
+/// Moves oversized output streams of responses into a store
+#[derive(Debug, Clone)]
+pub struct Offloader {
+    pub store: Arc<dyn ArtifactStore>,
+    pub threshold_bytes: usize,
+}
+
+impl Offloader {
+    pub fn new(store: Arc<dyn ArtifactStore>, threshold_bytes: usize) -> Self {
+        Self {
+            store,
+            threshold_bytes,
+        }
+    }
+
+    /// Replace stdout and stderr longer than the threshold with artifacts of `job_id`. A
+    /// stream whose upload fails stays inline.
+    pub async fn offload(&self, job_id: &str, response: &mut ExecuteResponse) {
+        let ExecuteResponse {
+            stdout,
+            stderr,
+            stdout_encoding,
+            stderr_encoding,
+            artifacts,
+            ..
+        } = response;
+        for (name, text, encoding) in [
+            ("stdout", stdout, stdout_encoding),
+            ("stderr", stderr, stderr_encoding),
+        ] {
+            if text.len() <= self.threshold_bytes {
+                continue;
+            }
+            // Artifacts hold the raw bytes, so base64 output is decoded first
+            let bytes =
+                output::decode_stream(text, *encoding).unwrap_or_else(|| text.as_bytes().to_vec());
+            let size = bytes.len() as u64;
+            match self.store.put(job_id, name, bytes).await {
+                Ok(url) => {
+                    artifacts.push(Artifact {
+                        name: name.to_string(),
+                        size,
+                        url,
+                    });
+                    text.clear();
+                    *encoding = OutputEncoding::Utf8;
+                }
+                Err(e) => tracing::warn!("Keeping {} of {} inline: {}", name, job_id, e),
+            }
+        }
+    }
+}
+
+/// Periodically delete artifacts older than `ttl`, the lifetime of their job or record
+pub fn spawn_cleanup(store: Arc<dyn ArtifactStore>, ttl: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ttl.min(crate::jobs::CLEANUP_INTERVAL));
+        loop {
+            interval.tick().await;
+            let cutoff = now_millis().saturating_sub(ttl.as_millis() as u64);
+            match store.remove_expired(cutoff).await {
+                Ok(0) => {}
+                Ok(removed) => tracing::debug!("Removed {} expired artifacts", removed),
+                Err(e) => tracing::warn!("Artifact cleanup failed: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("fc-artifacts-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        root
+    }
+
+    /// Behaviour every `ArtifactStore` implementation must share
+    async fn check_store_contract(store: &dyn ArtifactStore) {
+        let url = store
+            .put("job-1", "stdout", b"hello".to_vec())
+            .await
+            .unwrap();
+        assert!(!url.is_empty());
+        store
+            .put("job-1", "stderr", b"oops".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(store.get("job-1", "stdout").await.unwrap(), b"hello");
+        assert!(matches!(
+            store.get("job-1", "missing").await,
+            Err(ArtifactError::NotFound(_))
+        ));
+        for (job_id, name) in [("../etc", "passwd"), ("job-1", ".hidden"), ("", "stdout")] {
+            assert!(matches!(
+                store.put(job_id, name, Vec::new()).await,
+                Err(ArtifactError::InvalidName(_))
+            ));
+        }
+
+        store
+            .put("job-2", "stdout", b"kept".to_vec())
+            .await
+            .unwrap();
+        store.delete("job-1").await.unwrap();
+        assert!(matches!(
+            store.get("job-1", "stderr").await,
+            Err(ArtifactError::NotFound(_))
+        ));
+        store.delete("job-1").await.unwrap();
+
+        // Everything was written after the epoch and before the far future
+        assert_eq!(store.remove_expired(0).await.unwrap(), 0);
+        assert_eq!(store.get("job-2", "stdout").await.unwrap(), b"kept");
+        assert_eq!(store.remove_expired(u64::MAX / 2).await.unwrap(), 1);
+        assert!(store.get("job-2", "stdout").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_store_contract() {
+        let root = temp_root("contract");
+        let store = LocalArtifactStore::new(&root);
+        assert_eq!(
+            store.remove_expired(0).await.unwrap(),
+            0,
+            "missing root is empty"
+        );
+        check_store_contract(&store).await;
+        assert_eq!(
+            store.put("job-3", "stdout", Vec::new()).await.unwrap(),
+            "/artifacts/job-3/stdout"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_local_store_removes_only_expired_artifacts() {
+        let root = temp_root("expiry");
+        let store = LocalArtifactStore::new(&root);
+        store.put("old", "stdout", b"1".to_vec()).await.unwrap();
+        store.put("new", "stdout", b"2".to_vec()).await.unwrap();
+        let file = std::fs::File::options()
+            .write(true)
+            .open(root.join("old").join("stdout"))
+            .unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(1_000))
+            .unwrap();
+
+        assert_eq!(
+            store.remove_expired(now_millis() - 60_000).await.unwrap(),
+            1
+        );
+        assert!(!root.join("old").exists(), "empty job directories go too");
+        assert_eq!(store.get("new", "stdout").await.unwrap(), b"2");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn test_offload_moves_large_streams() {
+        let root = temp_root("offload");
+        let offloader = Offloader::new(Arc::new(LocalArtifactStore::new(&root)), 4);
+        let (stderr, stderr_encoding) = output::encode_bytes(&[0xff, 0xfe, 0xfd, 0xfc, 0xfb]);
+        let mut response = ExecuteResponse {
+            stdout: "tiny".to_string(),
+            stderr,
+            stderr_encoding,
+            ..Default::default()
+        };
+        offloader.offload("req-1", &mut response).await;
+
+        assert_eq!(
+            response.stdout, "tiny",
+            "output at the threshold stays inline"
+        );
+        assert!(response.stderr.is_empty());
+        assert_eq!(response.stderr_encoding, OutputEncoding::Utf8);
+        assert_eq!(
+            response.artifacts,
+            vec![Artifact {
+                name: "stderr".to_string(),
+                size: 5,
+                url: "/artifacts/req-1/stderr".to_string(),
+            }]
+        );
+        // The artifact holds the raw bytes, not their base64 form
+        assert_eq!(
+            offloader.store.get("req-1", "stderr").await.unwrap(),
+            [0xff, 0xfe, 0xfd, 0xfc, 0xfb]
+        );
+
+        // A failed upload leaves the stream inline
+        let mut response = ExecuteResponse {
+            stdout: "long enough".to_string(),
+            ..Default::default()
+        };
+        offloader.offload("../bad", &mut response).await;
+        assert_eq!(response.stdout, "long enough");
+        assert!(response.artifacts.is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[cfg(feature = "s3")]
+    mod s3 {
+        use super::*;
+        use axum::Router;
+        use axum::body::Bytes;
+        use axum::extract::{Path, Query, State};
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+        use axum::routing::get;
+        use std::collections::{BTreeMap, HashMap};
+        use std::sync::Mutex;
+
+        type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+        /// Fake S3 endpoint speaking just the path-style requests the store sends. Every
+        /// object reports a modification time in 2020.
+        async fn mock_s3() -> (String, Objects) {
+            let objects = Objects::default();
+            let app = Router::new()
+                .route("/{bucket}", get(list))
+                .route("/{bucket}/", get(list))
+                .route(
+                    "/{bucket}/{*key}",
+                    get(get_object).put(put_object).delete(delete_object),
+                )
+                .with_state(objects.clone());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            (format!("http://{addr}"), objects)
+        }
+
+        async fn list(
+            State(objects): State<Objects>,
+            Query(query): Query<HashMap<String, String>>,
+        ) -> impl IntoResponse {
+            let prefix = query.get("prefix").cloned().unwrap_or_default();
+            let contents: String = objects
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix))
+                .map(|(key, bytes)| {
+                    format!(
+                        "<Contents><Key>{key}</Key><LastModified>2020-01-01T00:00:00.000Z</LastModified><Size>{}</Size></Contents>",
+                        bytes.len()
+                    )
+                })
+                .collect();
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Name>bucket</Name><Prefix>{prefix}</Prefix><IsTruncated>false</IsTruncated>{contents}</ListBucketResult>"#
+            )
+        }
+
+        async fn get_object(
+            State(objects): State<Objects>,
+            Path((_, key)): Path<(String, String)>,
+        ) -> axum::response::Response {
+            match objects.lock().unwrap().get(&key) {
+                Some(bytes) => bytes.clone().into_response(),
+                None => (
+                    StatusCode::NOT_FOUND,
+                    "<Error><Code>NoSuchKey</Code><Message>missing</Message></Error>",
+                )
+                    .into_response(),
+            }
+        }
+
+        async fn put_object(
+            State(objects): State<Objects>,
+            Path((_, key)): Path<(String, String)>,
+            body: Bytes,
+        ) -> StatusCode {
+            objects.lock().unwrap().insert(key, body.to_vec());
+            StatusCode::OK
+        }
+
+        async fn delete_object(
+            State(objects): State<Objects>,
+            Path((_, key)): Path<(String, String)>,
+        ) -> StatusCode {
+            objects.lock().unwrap().remove(&key);
+            StatusCode::NO_CONTENT
+        }
+
+        fn client(endpoint: &str) -> aws_sdk_s3::Client {
+            let config = aws_sdk_s3::Config::builder()
+                .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+                .region(aws_sdk_s3::config::Region::new("us-east-1"))
+                .credentials_provider(aws_sdk_s3::config::Credentials::new(
+                    "test", "test", None, None, "test",
+                ))
+                .endpoint_url(endpoint)
+                .force_path_style(true)
+                .request_checksum_calculation(
+                    aws_sdk_s3::config::RequestChecksumCalculation::WhenRequired,
+                )
+                .build();
+            aws_sdk_s3::Client::from_conf(config)
+        }
+
+        #[tokio::test]
+        async fn test_s3_store_against_mock() {
+            let (endpoint, objects) = mock_s3().await;
+            let store =
+                S3ArtifactStore::new(client(&endpoint), "bucket", "fc/", Duration::from_secs(600));
+
+            let url = store
+                .put("job-1", "stdout", b"hello".to_vec())
+                .await
+                .unwrap();
+            assert!(url.starts_with(&format!("{endpoint}/bucket/fc/job-1/stdout?")));
+            assert!(url.contains("X-Amz-Signature="));
+            assert!(url.contains("X-Amz-Expires=600"));
+            assert!(objects.lock().unwrap().contains_key("fc/job-1/stdout"));
+            // The presigned URL downloads the object without further credentials
+            let downloaded = reqwest::get(&url).await.unwrap().bytes().await.unwrap();
+            assert_eq!(&downloaded[..], b"hello");
+
+            // The mock dates every object to 2020, before any cutoff after it
+            store.delete("job-1").await.unwrap();
+            store.put("job-2", "stdout", b"x".to_vec()).await.unwrap();
+            objects
+                .lock()
+                .unwrap()
+                .insert("other/kept".to_string(), Vec::new());
+            let cutoff_2019 = 1_546_300_800_000;
+            assert_eq!(store.remove_expired(cutoff_2019).await.unwrap(), 0);
+            check_store_contract(&store).await;
+            assert!(
+                objects.lock().unwrap().contains_key("other/kept"),
+                "keys outside the prefix are left alone"
+            );
+        }
+    }
+}
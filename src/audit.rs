@@ -0,0 +1,402 @@
+use crate::program::Program;
+use crate::{ExecuteRequest, ExecuteResponse};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+
+/// Records waiting for the writer before new ones are dropped
+pub const AUDIT_QUEUE_CAPACITY: usize = 1024;
+
+/// Default size at which the audit log is rotated
+pub const DEFAULT_AUDIT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+/// Default number of rotated audit logs kept next to the current one
+pub const DEFAULT_AUDIT_MAX_FILES: usize = 5;
+
+/// Language of every execution; the guest only runs Python
+pub const AUDIT_LANGUAGE: &str = "python";
+
+/// Where and how the audit log is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditConfig {
+    /// JSON lines file of executions; `None` disables the audit log
+    pub path: Option<PathBuf>,
+    /// Size past which the file is rotated to `<path>.1`
+    pub max_bytes: u64,
+    /// Rotated files kept, `<path>.1` being the newest
+    pub max_files: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: DEFAULT_AUDIT_MAX_BYTES,
+            max_files: DEFAULT_AUDIT_MAX_FILES,
+        }
+    }
+}
+
+/// One line of the audit log: who ran what, when, and how it ended. The code itself is never
+/// written, only its hash and length.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Completion time in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub request_id: String,
+    /// Identifier of the caller's API key; `None` when authentication is off
+    pub api_key_id: Option<String>,
+    /// VM that ran the code; `None` for rejected requests and cached results
+    pub vm_id: Option<String>,
+    pub code_sha256: String,
+    /// Length of the submitted source, all files together
+    pub code_length: usize,
+    pub language: String,
+    /// Whether the code ran and exited cleanly
+    pub success: bool,
+    /// Why the request failed on the host side, e.g. `quota_exceeded` or `timeout`
+    pub error_code: Option<String>,
+    /// Whether the result was served from the cache
+    pub cached: bool,
+    pub duration_ms: u64,
+}
+
+/// An execution under way, turned into its audit record once it finishes
+#[derive(Debug)]
+pub struct PendingAudit {
+    request_id: String,
+    api_key_id: Option<String>,
+    code_sha256: String,
+    code_length: usize,
+    started: std::time::Instant,
+}
+
+impl PendingAudit {
+    /// Start auditing `request`. The hash covers the program as executed; a request too
+    /// malformed to be a program is identified by its raw `code`, if any.
+    pub fn new(request: &ExecuteRequest, request_id: &str, api_key_id: Option<&str>) -> Self {
+        let (code_sha256, code_length) = match Program::from_request(request) {
+            Ok(program) => (program.sha256(), program.source_len()),
+            Err(_) => {
+                let code = request.code.as_deref().unwrap_or_default();
+                (crate::history::code_sha256(code), code.len())
+            }
+        };
+        Self {
+            request_id: request_id.to_string(),
+            api_key_id: api_key_id.map(str::to_string),
+            code_sha256,
+            code_length,
+            started: std::time::Instant::now(),
+        }
+    }
+
+    /// The record of the execution ending with `outcome`: its response, or the error code it
+    /// was turned away with
+    pub fn finish(self, outcome: Result<&ExecuteResponse, &str>) -> AuditRecord {
+        let (vm_id, success, error_code, cached) = match outcome {
+            Ok(response) => (
+                response.vm_id.clone(),
+                response.success,
+                None,
+                response.cached,
+            ),
+            Err(code) => (None, false, Some(code.to_string()), false),
+        };
+        AuditRecord {
+            timestamp: crate::history::now_millis(),
+            request_id: self.request_id,
+            api_key_id: self.api_key_id,
+            vm_id,
+            code_sha256: self.code_sha256,
+            code_length: self.code_length,
+            language: AUDIT_LANGUAGE.to_string(),
+            success,
+            error_code,
+            cached,
+            duration_ms: self.started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+/// Appends JSON lines to a file, rotating it by size
+#[derive(Debug)]
+struct AuditWriter {
+    config: AuditConfig,
+    path: PathBuf,
+    /// Current file and its size, opened on the first write
+    file: Option<(File, u64)>,
+}
+
+impl AuditWriter {
+    fn new(path: PathBuf, config: AuditConfig) -> Self {
+        Self {
+            config,
+            path,
+            file: None,
+        }
+    }
+
+    fn write(&mut self, record: &AuditRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let (mut file, mut size) = match self.file.take() {
+            Some(file) => file,
+            None => self.open()?,
+        };
+        if size > 0 && size + line.len() as u64 > self.config.max_bytes {
+            drop(file);
+            self.rotate()?;
+            (file, size) = self.open()?;
+        }
+        file.write_all(&line)?;
+        self.file = Some((file, size + line.len() as u64));
+        Ok(())
+    }
+
+    /// Open the current file for appending, with its size so far
+    fn open(&self) -> std::io::Result<(File, u64)> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+
+    /// Shift `<path>.N` to `<path>.N+1`, dropping the oldest, and move the current file to
+    /// `<path>.1`
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.config.max_files == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        for index in (1..self.config.max_files).rev() {
+            let from = rotated(&self.path, index);
+            if from.exists() {
+                std::fs::rename(from, rotated(&self.path, index + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated(&self.path, 1))
+    }
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+/// Append-only audit log of executions. Records are handed to a dedicated writer thread, so
+/// requests never wait on the disk; when the queue is full or a write fails, the record is
+/// dropped and counted in `fc_audit_dropped_total` instead.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    records: mpsc::Sender<AuditRecord>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditLog {
+    /// Start the writer for the log at `config.path`, or return `None` when there is none
+    pub fn spawn(config: &AuditConfig) -> Option<Self> {
+        let path = config.path.clone()?;
+        let (log, mut records) = Self::channel(AUDIT_QUEUE_CAPACITY);
+        let dropped = log.dropped.clone();
+        let mut writer = AuditWriter::new(path, config.clone());
+        tokio::task::spawn_blocking(move || {
+            while let Some(record) = records.blocking_recv() {
+                if let Err(e) = writer.write(&record) {
+                    tracing::warn!("Failed to write audit record {}: {}", record.request_id, e);
+                    count_drop(&dropped);
+                }
+            }
+        });
+        Some(log)
+    }
+
+    fn channel(capacity: usize) -> (Self, mpsc::Receiver<AuditRecord>) {
+        let (records, receiver) = mpsc::channel(capacity);
+        let log = Self {
+            records,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (log, receiver)
+    }
+
+    /// Queue `record` for writing without waiting
+    pub fn record(&self, record: AuditRecord) {
+        if self.records.try_send(record).is_err() {
+            count_drop(&self.dropped);
+        }
+    }
+
+    /// Records lost to a full queue or a failed write
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn count_drop(dropped: &AtomicU64) {
+    dropped.fetch_add(1, Ordering::Relaxed);
+    crate::telemetry::increment_counter("fc_audit_dropped_total", &[], 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(request_id: &str) -> AuditRecord {
+        AuditRecord {
+            timestamp: 1_700_000_000_000,
+            request_id: request_id.to_string(),
+            api_key_id: Some("1a2b3c4d".to_string()),
+            vm_id: None,
+            code_sha256: crate::history::code_sha256("print(1)"),
+            code_length: 8,
+            language: AUDIT_LANGUAGE.to_string(),
+            success: false,
+            error_code: Some("quota_exceeded".to_string()),
+            cached: false,
+            duration_ms: 3,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fc-audit-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_record_line_format() {
+        let dir = temp_dir("format");
+        let path = dir.join("audit.log");
+        let mut writer = AuditWriter::new(path.clone(), AuditConfig::default());
+        writer.write(&record("req-1")).unwrap();
+        writer.write(&record("req-2")).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(
+            line,
+            serde_json::json!({
+                "timestamp": 1_700_000_000_000u64,
+                "request_id": "req-1",
+                "api_key_id": "1a2b3c4d",
+                "vm_id": null,
+                "code_sha256": crate::history::code_sha256("print(1)"),
+                "code_length": 8,
+                "language": "python",
+                "success": false,
+                "error_code": "quota_exceeded",
+                "cached": false,
+                "duration_ms": 3,
+            })
+        );
+        assert!(!contents.contains("print(1)"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rotates_past_max_bytes() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("audit.log");
+        let line_len = serde_json::to_vec(&record("req-0")).unwrap().len() as u64 + 1;
+        let mut writer = AuditWriter::new(
+            path.clone(),
+            AuditConfig {
+                path: Some(path.clone()),
+                max_bytes: 2 * line_len,
+                max_files: 2,
+            },
+        );
+        for i in 0..7 {
+            writer.write(&record(&format!("req-{i}"))).unwrap();
+        }
+
+        let ids = |path: PathBuf| -> Vec<String> {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<AuditRecord>(line)
+                        .unwrap()
+                        .request_id
+                })
+                .collect()
+        };
+        // Two records per file, with the oldest file beyond `max_files` gone
+        assert_eq!(ids(path.clone()), ["req-6"]);
+        assert_eq!(ids(rotated(&path, 1)), ["req-4", "req-5"]);
+        assert_eq!(ids(rotated(&path, 2)), ["req-2", "req-3"]);
+        assert!(!rotated(&path, 3).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_full_queue_drops_and_counts() {
+        let (log, _receiver) = AuditLog::channel(1);
+        log.record(record("req-1"));
+        log.record(record("req-2"));
+        log.record(record("req-3"));
+        assert_eq!(log.dropped(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_writes_drop_and_count() {
+        let dir = temp_dir("unwritable");
+        // A directory where the file should be makes every write fail
+        let path = dir.join("audit.log");
+        std::fs::create_dir_all(&path).unwrap();
+        let log = AuditLog::spawn(&AuditConfig {
+            path: Some(path),
+            ..Default::default()
+        })
+        .unwrap();
+        log.record(record("req-1"));
+        log.record(record("req-2"));
+        for _ in 0..100 {
+            if log.dropped() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(log.dropped(), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_pending_audit_hashes_the_program() {
+        let request = ExecuteRequest {
+            code: Some("print(1)".to_string()),
+            ..Default::default()
+        };
+        let response = ExecuteResponse {
+            success: true,
+            vm_id: Some("vm-1".to_string()),
+            ..Default::default()
+        };
+        let record = PendingAudit::new(&request, "req-1", Some("1a2b3c4d")).finish(Ok(&response));
+        assert_eq!(record.code_sha256, crate::history::code_sha256("print(1)"));
+        assert_eq!((record.code_length, record.success), (8, true));
+        assert_eq!(record.vm_id.as_deref(), Some("vm-1"));
+        assert_eq!(record.error_code, None);
+
+        let record =
+            PendingAudit::new(&ExecuteRequest::default(), "req-2", None).finish(Err("bad_request"));
+        assert_eq!(record.code_sha256, crate::history::code_sha256(""));
+        assert_eq!(record.error_code.as_deref(), Some("bad_request"));
+        assert!(!record.success);
+    }
+
+    #[test]
+    fn test_disabled_without_a_path() {
+        assert!(AuditLog::spawn(&AuditConfig::default()).is_none());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::ErrorResponse;
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+/// Routes reachable without an API key
+const PUBLIC_PATHS: &[&str] = &["/health", "/ready", "/openapi.json", "/docs"];
+
+/// Non-secret identifier of an accepted API key (hash prefix), attached to requests for auditing
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApiKeyId(pub String);
+
+/// Set of accepted API keys, stored as SHA-256 digests
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    digests: Vec<[u8; 32]>,
+}
+
+impl ApiKeys {
+    pub fn new(keys: &[String]) -> Self {
+        Self {
+            digests: keys.iter().map(|key| digest(key)).collect(),
+        }
+    }
+
+    /// Whether authentication is enforced at all
+    pub fn is_enabled(&self) -> bool {
+        !self.digests.is_empty()
+    }
+
+    /// Check a presented key in constant time, returning its identifier when accepted
+    pub fn verify(&self, presented: &str) -> Option<ApiKeyId> {
+        let presented = digest(presented);
+        // Compare against every key so timing doesn't reveal which one matched
+        let matched = self
+            .digests
+            .iter()
+            .fold(subtle::Choice::from(0), |acc, key| {
+                acc | key.ct_eq(&presented)
+            });
+        bool::from(matched).then(|| key_id(&presented))
+    }
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+fn key_id(digest: &[u8; 32]) -> ApiKeyId {
+    ApiKeyId(hex::encode(&digest[..4]))
+}
+
+fn unauthorized(message: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(ErrorResponse::new("unauthorized", message)),
+    )
+        .into_response()
+}
+
+/// Middleware requiring `Authorization: Bearer <key>` on every non-public route.
+///
+/// When no keys are configured every request passes through untouched.
+pub async fn require_api_key(
+    State(keys): State<Arc<ApiKeys>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !keys.is_enabled() || PUBLIC_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(presented) = presented else {
+        return unauthorized("Missing bearer token");
+    };
+    let Some(key_id) = keys.verify(presented.trim()) else {
+        return unauthorized("Invalid API key");
+    };
+
+    tracing::Span::current().record("api_key_id", key_id.0.as_str());
+    request.extensions_mut().insert(key_id);
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_keys() {
+        let keys = ApiKeys::new(&["alpha".to_string(), "beta".to_string()]);
+        assert!(keys.is_enabled());
+
+        let id = keys.verify("beta").unwrap();
+        assert_eq!(id.0.len(), 8);
+        assert_eq!(keys.verify("beta"), Some(id));
+        assert_ne!(keys.verify("alpha"), keys.verify("beta"));
+        assert!(keys.verify("gamma").is_none());
+        assert!(keys.verify("").is_none());
+    }
+
+    #[test]
+    fn test_no_keys_disables_auth() {
+        let keys = ApiKeys::new(&[]);
+        assert!(!keys.is_enabled());
+        assert!(keys.verify("anything").is_none());
+    }
+}
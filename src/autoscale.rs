@@ -0,0 +1,320 @@
+use crate::executor::{ExecutorService, ExecutorStats};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How often the autoscaler samples the pool
+pub const AUTOSCALE_TICK: Duration = Duration::from_secs(5);
+
+/// Hit ratio below which the pool grows
+pub const SCALE_UP_HIT_RATIO: f64 = 0.9;
+
+/// Demand-driven sizing of an executor's warm pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoscaleConfig {
+    /// Resize the pool to demand; when off, it is warmed once and holds at most `VM_POOL_SIZE`
+    pub enabled: bool,
+    /// Fewest warm VMs kept, even with no demand
+    pub min: usize,
+    /// Most warm VMs kept, which is also the pool's capacity
+    pub max: usize,
+    /// Time over which arrivals and pool hits are measured
+    pub window: Duration,
+    /// Least time between two changes of the target
+    pub cooldown: Duration,
+    /// Time a VM above the target stays in the pool before it is shut down
+    pub idle_ttl: Duration,
+}
+
+impl Default for AutoscaleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min: 1,
+            max: 8,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+            idle_ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+impl AutoscaleConfig {
+    pub fn clamp(&self, target: usize) -> usize {
+        target.clamp(self.min, self.max.max(self.min))
+    }
+}
+
+/// Pool demand over the autoscaler's window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Demand {
+    /// Executions asking the pool for a VM, per minute
+    pub arrivals_per_min: f64,
+    /// Share of those served by a warm VM; `None` without arrivals
+    pub hit_ratio: Option<f64>,
+    /// Warm VMs within the target that sat unused throughout the window
+    pub spare: usize,
+}
+
+/// What the autoscaler does with the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Hold,
+    Scale { to: usize, reason: &'static str },
+}
+
+/// Decide the pool's next target from `demand`. `since_change` is the time since the target
+/// last changed, if it ever did; no change is made within `cooldown` of the previous one.
+pub fn decide(
+    config: &AutoscaleConfig,
+    target: usize,
+    demand: &Demand,
+    since_change: Option<Duration>,
+) -> Decision {
+    let (desired, reason) = match demand.hit_ratio {
+        None => (config.min, "no demand"),
+        Some(hit_ratio) if hit_ratio < SCALE_UP_HIT_RATIO => {
+            // Grow in proportion to the misses, by at least one VM
+            let misses = (target as f64 * (1.0 - hit_ratio)).ceil() as usize;
+            (target + misses.max(1), "pool misses")
+        }
+        Some(_) if demand.spare > 0 => (target - demand.spare.min(target), "spare VMs"),
+        Some(_) => (target, ""),
+    };
+    let desired = config.clamp(desired);
+    if desired == target || since_change.is_some_and(|since| since < config.cooldown) {
+        return Decision::Hold;
+    }
+    Decision::Scale {
+        to: desired,
+        reason,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    pool_hits: u64,
+    pool_misses: u64,
+    /// Idle VMs within the target
+    spare: usize,
+}
+
+/// Samples of an executor's stats over the window, turned into demand
+#[derive(Debug)]
+pub struct DemandTracker {
+    window: Duration,
+    samples: VecDeque<Sample>,
+}
+
+impl DemandTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record `stats` taken at `at` and return the demand over the window up to then
+    pub fn observe(&mut self, at: Instant, stats: &ExecutorStats) -> Demand {
+        self.samples.push_back(Sample {
+            at,
+            pool_hits: stats.pool_hits,
+            pool_misses: stats.pool_misses,
+            spare: stats.idle_vms.min(stats.pool_target),
+        });
+        // Keep the newest sample at least a window old as the baseline
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|next| at.saturating_duration_since(next.at) >= self.window)
+        {
+            self.samples.pop_front();
+        }
+        let (first, last) = (self.samples[0], self.samples[self.samples.len() - 1]);
+        let hits = last.pool_hits - first.pool_hits;
+        let arrivals = hits + last.pool_misses - first.pool_misses;
+        let minutes = last.at.saturating_duration_since(first.at).as_secs_f64() / 60.0;
+        Demand {
+            arrivals_per_min: if minutes > 0.0 {
+                arrivals as f64 / minutes
+            } else {
+                0.0
+            },
+            hit_ratio: (arrivals > 0).then(|| hits as f64 / arrivals as f64),
+            spare: self.samples.iter().map(|s| s.spare).min().unwrap_or(0),
+        }
+    }
+
+    /// Whether the samples span a whole window, so the demand isn't a partial picture
+    pub fn is_full(&self) -> bool {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) => last.at.saturating_duration_since(first.at) >= self.window,
+            _ => false,
+        }
+    }
+}
+
+/// Resize `executor`'s pool to demand every `AUTOSCALE_TICK`, warming VMs up to the target
+/// and shutting down those left above it for longer than the idle TTL
+pub fn spawn(executor: ExecutorService) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let config = executor.config().autoscale.clone();
+        let mut tracker = DemandTracker::new(config.window);
+        let mut last_change: Option<Instant> = None;
+        let mut ticks = tokio::time::interval(AUTOSCALE_TICK);
+        loop {
+            ticks.tick().await;
+            let stats = executor.stats().await;
+            if stats.shutting_down {
+                return;
+            }
+            crate::debug_state::heartbeat("autoscaler");
+            let now = Instant::now();
+            let demand = tracker.observe(now, &stats);
+            let target = stats.pool_target;
+            let since_change = last_change.map(|at| now.saturating_duration_since(at));
+            if tracker.is_full()
+                && let Decision::Scale { to, reason } =
+                    decide(&config, target, &demand, since_change)
+            {
+                tracing::info!(
+                    from = target,
+                    to,
+                    reason,
+                    arrivals_per_min = demand.arrivals_per_min,
+                    hit_ratio = ?demand.hit_ratio,
+                    spare = demand.spare,
+                    "Resizing VM pool"
+                );
+                executor.set_pool_target(to);
+                last_change = Some(now);
+            }
+
+            let target = executor.pool_target();
+            let idle = executor.stats().await.idle_vms;
+            if idle < target {
+                executor.warm(target - idle).await;
+            }
+            // Reloads can change the TTL while the autoscaler runs
+            executor.evict_idle(executor.tunables().idle_ttl).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> AutoscaleConfig {
+        AutoscaleConfig {
+            enabled: true,
+            min: 1,
+            max: 6,
+            ..Default::default()
+        }
+    }
+
+    fn demand(arrivals_per_min: f64, hit_ratio: Option<f64>, spare: usize) -> Demand {
+        Demand {
+            arrivals_per_min,
+            hit_ratio,
+            spare,
+        }
+    }
+
+    #[test]
+    fn test_scales_up_in_proportion_to_misses() {
+        let config = config();
+        let scale =
+            |target, hit_ratio| decide(&config, target, &demand(120.0, Some(hit_ratio), 0), None);
+        assert_eq!(
+            scale(2, 0.5),
+            Decision::Scale {
+                to: 3,
+                reason: "pool misses"
+            }
+        );
+        assert_eq!(
+            scale(4, 0.2),
+            Decision::Scale {
+                to: 6,
+                reason: "pool misses"
+            }
+        );
+        // Capped at the maximum, and no change once there
+        assert!(matches!(scale(5, 0.0), Decision::Scale { to: 6, .. }));
+        assert_eq!(scale(6, 0.0), Decision::Hold);
+        // A pool serving nearly everything is left alone
+        assert_eq!(scale(3, 0.95), Decision::Hold);
+    }
+
+    #[test]
+    fn test_scales_down_on_spare_vms_and_no_demand() {
+        let config = config();
+        assert_eq!(
+            decide(&config, 5, &demand(30.0, Some(1.0), 2), None),
+            Decision::Scale {
+                to: 3,
+                reason: "spare VMs"
+            }
+        );
+        assert_eq!(
+            decide(&config, 5, &demand(0.0, None, 5), None),
+            Decision::Scale {
+                to: 1,
+                reason: "no demand"
+            }
+        );
+        // Never below the minimum
+        assert_eq!(
+            decide(&config, 1, &demand(0.0, None, 1), None),
+            Decision::Hold
+        );
+        assert_eq!(
+            decide(&config, 1, &demand(10.0, Some(1.0), 1), None),
+            Decision::Hold
+        );
+    }
+
+    #[test]
+    fn test_changes_are_rate_limited() {
+        let config = config();
+        let misses = demand(60.0, Some(0.5), 0);
+        assert_eq!(
+            decide(&config, 2, &misses, Some(Duration::from_secs(10))),
+            Decision::Hold
+        );
+        assert!(matches!(
+            decide(&config, 2, &misses, Some(config.cooldown)),
+            Decision::Scale { to: 3, .. }
+        ));
+    }
+
+    #[test]
+    fn test_demand_over_the_window() {
+        let stats = |hits, misses, idle_vms| ExecutorStats {
+            idle_vms,
+            pool_target: 3,
+            pool_hits: hits,
+            pool_misses: misses,
+            ..ExecutorStats::default()
+        };
+        let start = Instant::now();
+        let mut tracker = DemandTracker::new(Duration::from_secs(60));
+        assert_eq!(tracker.observe(start, &stats(0, 0, 3)).hit_ratio, None);
+        assert!(!tracker.is_full());
+
+        let demand = tracker.observe(start + Duration::from_secs(30), &stats(8, 2, 1));
+        assert_eq!(demand.arrivals_per_min, 20.0);
+        assert_eq!(demand.hit_ratio, Some(0.8));
+        assert_eq!(demand.spare, 1);
+
+        // The first sample falls out once a newer one is a window old
+        let demand = tracker.observe(start + Duration::from_secs(90), &stats(20, 2, 5));
+        assert!(tracker.is_full());
+        assert_eq!(demand.arrivals_per_min, 12.0);
+        assert_eq!(demand.hit_ratio, Some(1.0));
+        // Idle VMs above the target don't count as spare
+        assert_eq!(demand.spare, 1);
+    }
+}
@@ -0,0 +1,694 @@
+use crate::config::ConfigError;
+use crate::deadline::Deadline;
+use crate::guest_protocol::{self, GuestFeature};
+use crate::inputs::RemoteInput;
+use crate::program::Program;
+use crate::runner::{OutputEvent, OutputSink, VMManager};
+use crate::{ExecuteResponse, ExecutionError};
+use std::time::Duration;
+
+/// What runs the VMs of an executor, selected with `FC_BACKEND`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Firecracker microVMs, the only isolated backend
+    #[default]
+    Firecracker,
+    /// Plain `python3` processes on the host, with no isolation at all; for development
+    /// machines that can't run Firecracker. Only built with the `local-backend` feature.
+    #[cfg(feature = "local-backend")]
+    Local,
+    /// Canned responses echoing the submitted code, as in the test suite
+    Mock,
+}
+
+impl BackendKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendKind::Firecracker => "firecracker",
+            #[cfg(feature = "local-backend")]
+            BackendKind::Local => "local",
+            BackendKind::Mock => "mock",
+        }
+    }
+
+    /// Whether VMs are real Firecracker guests with the host preflight checks that implies
+    pub fn is_firecracker(&self) -> bool {
+        *self == BackendKind::Firecracker
+    }
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "firecracker" => Ok(BackendKind::Firecracker),
+            #[cfg(feature = "local-backend")]
+            "local" => Ok(BackendKind::Local),
+            #[cfg(not(feature = "local-backend"))]
+            "local" => Err(ConfigError::Invalid(
+                "the local backend needs a build with --features local-backend".to_string(),
+            )),
+            "mock" => Ok(BackendKind::Mock),
+            other => Err(ConfigError::Invalid(format!(
+                "unknown backend {other:?} (expected firecracker, local or mock)"
+            ))),
+        }
+    }
+}
+
+/// How a VM is brought up and runs code. The executor's pool, retries and cleanup are the
+/// same whatever the backend.
+#[async_trait::async_trait]
+pub trait VmBackend: Send + Sync + std::fmt::Debug {
+    fn kind(&self) -> BackendKind;
+
+    /// Get `vm` ready to run code by `deadline`
+    async fn boot(&self, vm: &mut VMManager, deadline: Deadline) -> Result<(), ExecutionError>;
+
+    /// Run `program` on the booted `vm`, installing `requirements` and downloading `inputs`
+    /// first, and capping each output stream at `max_output_bytes`. `output`, given only when
+    /// `streams_output` says so, receives stdout and stderr as the code prints them.
+    async fn execute(
+        &self,
+        vm: &VMManager,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        max_output_bytes: usize,
+        output: Option<&OutputSink>,
+    ) -> Result<ExecuteResponse, ExecutionError>;
+
+    /// Whether `execute` can send `vm`'s output as it is printed; the runner sends the output
+    /// of other backends once the code exits
+    fn streams_output(&self, _vm: &VMManager) -> bool {
+        false
+    }
+
+    /// Release what `boot` set up on the host, apart from the VM's process and files
+    async fn tear_down(&self, vm: &VMManager) -> Result<(), ExecutionError>;
+
+    /// Check that the booted `vm` can still run code, within `timeout`; backends without a
+    /// guest to lose are always healthy
+    async fn check_health(
+        &self,
+        _vm: &VMManager,
+        _timeout: Duration,
+    ) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+
+    /// Number of execution workspaces the booted `vm` failed to delete, within `timeout`;
+    /// backends without guest workspaces leave none
+    async fn leftover_workspaces(
+        &self,
+        _vm: &VMManager,
+        _timeout: Duration,
+    ) -> Result<u64, ExecutionError> {
+        Ok(0)
+    }
+}
+
+/// The backend of `kind`
+pub fn backend(kind: BackendKind) -> &'static dyn VmBackend {
+    match kind {
+        BackendKind::Firecracker => &FirecrackerBackend,
+        #[cfg(feature = "local-backend")]
+        BackendKind::Local => &LocalProcessBackend,
+        BackendKind::Mock => &MockBackend,
+    }
+}
+
+/// Firecracker microVMs reached over their API socket and guest agent
+#[derive(Debug)]
+pub struct FirecrackerBackend;
+
+#[async_trait::async_trait]
+impl VmBackend for FirecrackerBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Firecracker
+    }
+
+    async fn boot(&self, vm: &mut VMManager, deadline: Deadline) -> Result<(), ExecutionError> {
+        vm.boot_firecracker(deadline).await
+    }
+
+    async fn execute(
+        &self,
+        vm: &VMManager,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        max_output_bytes: usize,
+        output: Option<&OutputSink>,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        vm.request_execution(program, requirements, inputs, max_output_bytes, output)
+            .await
+    }
+
+    /// Agents stream from protocol v4; VMs simulated in test mode answer at once
+    fn streams_output(&self, vm: &VMManager) -> bool {
+        !vm.simulated() && guest_protocol::supports(vm.agent_protocol(), GuestFeature::LiveOutput)
+    }
+
+    async fn tear_down(&self, vm: &VMManager) -> Result<(), ExecutionError> {
+        vm.cleanup_networking().await
+    }
+
+    async fn check_health(&self, vm: &VMManager, timeout: Duration) -> Result<(), ExecutionError> {
+        vm.check_agent_health(timeout).await
+    }
+
+    async fn leftover_workspaces(
+        &self,
+        vm: &VMManager,
+        timeout: Duration,
+    ) -> Result<u64, ExecutionError> {
+        vm.agent_leftover_workspaces(timeout).await
+    }
+}
+
+/// VMs that boot instantly and answer every execution with a description of the program
+#[derive(Debug)]
+pub struct MockBackend;
+
+#[async_trait::async_trait]
+impl VmBackend for MockBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Mock
+    }
+
+    async fn boot(&self, vm: &mut VMManager, _: Deadline) -> Result<(), ExecutionError> {
+        let latency = vm.config().mock_boot_latency;
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        Ok(())
+    }
+
+    async fn execute(
+        &self,
+        vm: &VMManager,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        _: usize,
+        output: Option<&OutputSink>,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        let latency = vm.config().mock_latency;
+        let response = mock_response(program, requirements, inputs);
+        match output {
+            // Printed a line at a time over the latency, like code that prints as it goes
+            Some(output) if !response.stdout.is_empty() => {
+                let lines: Vec<_> = response.stdout.split_inclusive('\n').collect();
+                let pause = latency / lines.len() as u32;
+                for line in lines {
+                    if !pause.is_zero() {
+                        tokio::time::sleep(pause).await;
+                    }
+                    let _ = output.send(OutputEvent::Stdout {
+                        data: line.to_string(),
+                    });
+                }
+            }
+            _ if !latency.is_zero() => tokio::time::sleep(latency).await,
+            _ => {}
+        }
+        Ok(response)
+    }
+
+    fn streams_output(&self, _: &VMManager) -> bool {
+        true
+    }
+
+    async fn tear_down(&self, _: &VMManager) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+}
+
+/// The response the mock backend, and the Firecracker backend in test mode, give `program`
+pub(crate) fn mock_response(
+    program: &Program,
+    requirements: &[String],
+    inputs: &[RemoteInput],
+) -> ExecuteResponse {
+    let setup_requested = !requirements.is_empty() || !inputs.is_empty();
+    let mut setup_stdout = String::new();
+    if !requirements.is_empty() {
+        setup_stdout.push_str(&format!("Mock install of: {}\n", requirements.join(", ")));
+    }
+    for input in inputs {
+        setup_stdout.push_str(&format!(
+            "Mock download of: {} to {}\n",
+            input.url, input.path
+        ));
+    }
+    let stdout = match program {
+        Program::Code(code) => format!("Mock execution of: {code}\n"),
+        Program::Eval(code) => format!("Mock evaluation of: {code}\n"),
+        Program::Files { files, entrypoint } => {
+            let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+            format!(
+                "Mock execution of: {entrypoint} with {}\n",
+                paths.join(", ")
+            )
+        }
+    };
+    // Nothing is evaluated: a final line that is a JSON literal stands for its value, and any
+    // other for its repr
+    let last_line = match program {
+        Program::Eval(code) => code.trim_end().lines().last().map(str::trim),
+        _ => None,
+    };
+    let value = last_line.and_then(|line| serde_json::from_str(line).ok());
+    let value_repr = last_line.filter(|_| value.is_none()).map(str::to_string);
+    ExecuteResponse {
+        stdout,
+        value,
+        value_repr,
+        setup_stdout: setup_requested.then_some(setup_stdout),
+        setup_stderr: setup_requested.then(String::new),
+        stderr: "".to_string(),
+        success: true,
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "local-backend")]
+pub use local::LocalProcessBackend;
+
+#[cfg(feature = "local-backend")]
+mod local {
+    use super::{BackendKind, VmBackend};
+    use crate::deadline::Deadline;
+    use crate::inputs::{RemoteInput, SetupError, SetupErrorCode};
+    use crate::program::Program;
+    use crate::runner::VMManager;
+    use crate::{ExecuteResponse, ExecutionError, output};
+    use std::path::Path;
+    use std::process::Stdio;
+    use std::time::Duration;
+    use tokio::io::{AsyncRead, AsyncReadExt};
+
+    /// Matches the guest agent's execution timeout
+    const EXECUTE_TIMEOUT: Duration = Duration::from_secs(30);
+    /// Matches the guest agent's pip install timeout
+    const SETUP_TIMEOUT: Duration = Duration::from_secs(300);
+    /// Matches the guest agent's timeout for downloading every input
+    const INPUTS_TIMEOUT: Duration = Duration::from_secs(120);
+    /// Stands in for the guest's inputs directory, inside the working directory
+    const INPUTS_DIR: &str = "inputs";
+    /// Where an `eval` run leaves its value, inside the working directory
+    const VALUE_FILE: &str = ".value.json";
+    /// Runs `argv[1]` like the guest agent's `eval` mode, writing the `value` or `value_repr`
+    /// field of its final expression to `argv[2]`
+    const EVAL_DRIVER: &str = r#"
+import ast, json, sys
+path, value_path = sys.argv[1], sys.argv[2]
+tree = ast.parse(open(path).read(), path)
+last = tree.body.pop() if tree.body and isinstance(tree.body[-1], ast.Expr) else None
+namespace = {"__name__": "__main__"}
+exec(compile(tree, path, "exec"), namespace)
+if last is not None:
+    value = eval(compile(ast.Expression(last.value), path, "eval"), namespace)
+    try:
+        fields = {"value": json.loads(json.dumps(value, allow_nan=False))}
+    except (TypeError, ValueError, RecursionError):
+        fields = {"value_repr": repr(value)}
+    with open(value_path, "w") as f:
+        json.dump(fields, f)
+"#;
+
+    /// INSECURE: runs submitted code as `python3` processes of the server's own user, with
+    /// its access to the host's files and network. It keeps the guest agent's timeouts,
+    /// empty stdin, minimal environment and output caps so handlers and the pool behave as
+    /// with real VMs; never enable it where untrusted code is submitted.
+    #[derive(Debug)]
+    pub struct LocalProcessBackend;
+
+    #[async_trait::async_trait]
+    impl VmBackend for LocalProcessBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Local
+        }
+
+        async fn boot(&self, _: &mut VMManager, _: Deadline) -> Result<(), ExecutionError> {
+            Ok(())
+        }
+
+        async fn execute(
+            &self,
+            vm: &VMManager,
+            program: &Program,
+            requirements: &[String],
+            inputs: &[RemoteInput],
+            max_output_bytes: usize,
+            _: Option<&crate::runner::OutputSink>,
+        ) -> Result<ExecuteResponse, ExecutionError> {
+            // A fresh directory per execution, like the agent's temporary project directory
+            let workdir = vm
+                .config()
+                .runtime_dir
+                .join(format!("fc-local-{}", vm.vm_id()));
+            let _ = tokio::fs::remove_dir_all(&workdir).await;
+            let result = run(
+                vm,
+                &workdir,
+                program,
+                requirements,
+                inputs,
+                max_output_bytes,
+            )
+            .await;
+            let _ = tokio::fs::remove_dir_all(&workdir).await;
+            result
+        }
+
+        async fn tear_down(&self, _: &VMManager) -> Result<(), ExecutionError> {
+            Ok(())
+        }
+    }
+
+    async fn run(
+        vm: &VMManager,
+        workdir: &Path,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        max_output_bytes: usize,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        let write_error =
+            |e: std::io::Error| ExecutionError::ResourceError(format!("cannot write program: {e}"));
+        let entrypoint = match program {
+            Program::Code(code) | Program::Eval(code) => {
+                tokio::fs::create_dir_all(workdir)
+                    .await
+                    .map_err(write_error)?;
+                tokio::fs::write(workdir.join("main.py"), code)
+                    .await
+                    .map_err(write_error)?;
+                "main.py"
+            }
+            Program::Files { files, entrypoint } => {
+                // Paths were validated to stay inside the program's directory
+                for file in files {
+                    let path = workdir.join(&file.path);
+                    if let Some(parent) = path.parent() {
+                        tokio::fs::create_dir_all(parent)
+                            .await
+                            .map_err(write_error)?;
+                    }
+                    tokio::fs::write(&path, &file.content)
+                        .await
+                        .map_err(write_error)?;
+                }
+                entrypoint.as_str()
+            }
+        };
+
+        let site_packages = workdir.join(".site-packages");
+        let mut response = ExecuteResponse::default();
+        if !requirements.is_empty() {
+            let mut install = command(vm, workdir);
+            install
+                .args([
+                    "-m",
+                    "pip",
+                    "install",
+                    "--disable-pip-version-check",
+                    "--no-input",
+                ])
+                .arg("--target")
+                .arg(&site_packages)
+                .arg("--")
+                .args(requirements);
+            let setup = spawn_and_capture(install, SETUP_TIMEOUT, max_output_bytes).await?;
+            response.setup_stdout = Some(setup.stdout.0);
+            response.setup_stderr = Some(setup.stderr.0);
+            if setup.status != Some(0) {
+                response.stderr = "Failed to install requirements".to_string();
+                return Ok(response);
+            }
+        }
+
+        if !inputs.is_empty() {
+            let inputs_dir = workdir.join(INPUTS_DIR);
+            let download = download_inputs(&inputs_dir, inputs, vm.config().inputs.max_bytes);
+            let failed = match tokio::time::timeout(INPUTS_TIMEOUT, download).await {
+                Ok(result) => result.err(),
+                Err(_) => Some(SetupError::new(
+                    SetupErrorCode::DownloadFailed,
+                    &inputs[0],
+                    format!("downloads timed out ({} seconds)", INPUTS_TIMEOUT.as_secs()),
+                )),
+            };
+            if let Some(error) = failed {
+                response.stderr = "Failed to download inputs".to_string();
+                response.setup_error = Some(error);
+                return Ok(response);
+            }
+        }
+
+        let evaluate = matches!(program, Program::Eval(_));
+        let mut python = command(vm, workdir);
+        if evaluate {
+            python.args(["-c", EVAL_DRIVER, entrypoint, VALUE_FILE]);
+        } else {
+            python.arg(entrypoint);
+        }
+        if !requirements.is_empty() {
+            python.env("PYTHONPATH", &site_packages);
+        }
+        if !inputs.is_empty() {
+            python.env("FC_INPUTS_DIR", workdir.join(INPUTS_DIR));
+        }
+        let finished = spawn_and_capture(python, EXECUTE_TIMEOUT, max_output_bytes).await?;
+        let Some(status) = finished.status else {
+            response.stderr = format!(
+                "Code execution timed out ({} seconds)",
+                EXECUTE_TIMEOUT.as_secs()
+            );
+            return Ok(response);
+        };
+        (response.stdout, response.stdout_encoding) = finished.stdout;
+        (response.stderr, response.stderr_encoding) = finished.stderr;
+        response.stdout_truncated = finished.stdout_truncated;
+        response.stderr_truncated = finished.stderr_truncated;
+        response.success = status == 0;
+        if evaluate
+            && let Ok(fields) = tokio::fs::read(workdir.join(VALUE_FILE)).await
+            && let Ok(fields) = serde_json::from_slice::<serde_json::Value>(&fields)
+        {
+            response.value = Some(fields["value"].clone()).filter(|value| !value.is_null());
+            response.value_repr = fields["value_repr"].as_str().map(str::to_string);
+        }
+        Ok(response)
+    }
+
+    /// Download each input under `dir`, like the guest agent does, stopping at the first
+    /// that fails
+    async fn download_inputs(
+        dir: &Path,
+        inputs: &[RemoteInput],
+        max_bytes: u64,
+    ) -> Result<(), SetupError> {
+        use sha2::{Digest, Sha256};
+
+        // Redirect targets were never checked against the allowed hosts
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| {
+                SetupError::new(SetupErrorCode::DownloadFailed, &inputs[0], e.to_string())
+            })?;
+        for input in inputs {
+            let failed = |e: &dyn std::fmt::Display| {
+                SetupError::new(SetupErrorCode::DownloadFailed, input, e.to_string())
+            };
+            let mut response = client
+                .get(&input.url)
+                .send()
+                .await
+                .map_err(|e| failed(&e))?;
+            if !response.status().is_success() {
+                return Err(failed(&format!("HTTP {}", response.status())));
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await.map_err(|e| failed(&e))? {
+                if (body.len() + chunk.len()) as u64 > max_bytes {
+                    return Err(SetupError::new(
+                        SetupErrorCode::TooLarge,
+                        input,
+                        format!("download exceeds {max_bytes} bytes"),
+                    ));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            if let Some(expected) = &input.sha256 {
+                let actual = hex::encode(Sha256::digest(&body));
+                if !actual.eq_ignore_ascii_case(expected) {
+                    return Err(SetupError {
+                        expected_sha256: Some(expected.clone()),
+                        actual_sha256: Some(actual),
+                        ..SetupError::new(
+                            SetupErrorCode::ChecksumMismatch,
+                            input,
+                            "sha256 of the download doesn't match",
+                        )
+                    });
+                }
+            }
+            // Paths were validated to stay inside the inputs directory
+            let path = dir.join(&input.path);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| failed(&e))?;
+            }
+            tokio::fs::write(&path, &body)
+                .await
+                .map_err(|e| failed(&e))?;
+        }
+        Ok(())
+    }
+
+    /// `python3` in `workdir` with the guest's stdin and environment
+    fn command(vm: &VMManager, workdir: &Path) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new("python3");
+        command
+            .current_dir(workdir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", workdir)
+            .env("LANG", crate::determinism::DETERMINISTIC_LOCALE)
+            .env("PYTHONUNBUFFERED", "1")
+            .env("PYTHONDONTWRITEBYTECODE", "1");
+        if let Some(settings) = vm.deterministic() {
+            // Fake time needs the guest's clock, which a host process can't have
+            command
+                .env("PYTHONHASHSEED", settings.hash_seed.to_string())
+                .env("TZ", crate::determinism::DETERMINISTIC_TZ)
+                .env("LC_ALL", crate::determinism::DETERMINISTIC_LOCALE);
+        }
+        command
+    }
+
+    struct Finished {
+        /// Exit code; `None` when the process was killed at the timeout or by a signal
+        status: Option<i32>,
+        stdout: (String, output::OutputEncoding),
+        stderr: (String, output::OutputEncoding),
+        stdout_truncated: bool,
+        stderr_truncated: bool,
+    }
+
+    async fn spawn_and_capture(
+        mut command: tokio::process::Command,
+        limit: Duration,
+        max_output_bytes: usize,
+    ) -> Result<Finished, ExecutionError> {
+        let mut child = command
+            .spawn()
+            .map_err(|e| ExecutionError::ResourceError(format!("cannot start python3: {e}")))?;
+        let stdout = capture(child.stdout.take(), max_output_bytes);
+        let stderr = capture(child.stderr.take(), max_output_bytes);
+        let (status, (stdout, stdout_truncated), (stderr, stderr_truncated)) = tokio::join!(
+            async {
+                match tokio::time::timeout(limit, child.wait()).await {
+                    Ok(status) => status.ok().and_then(|status| status.code()),
+                    Err(_) => {
+                        let _ = child.kill().await;
+                        None
+                    }
+                }
+            },
+            stdout,
+            stderr
+        );
+        Ok(Finished {
+            status,
+            stdout: encode(&stdout, stdout_truncated),
+            stderr: encode(&stderr, stderr_truncated),
+            stdout_truncated,
+            stderr_truncated,
+        })
+    }
+
+    /// Everything read from `pipe` up to `max_bytes`, and whether more was discarded. The
+    /// pipe is drained to the end so the process never blocks on a full buffer.
+    async fn capture(pipe: Option<impl AsyncRead + Unpin>, max_bytes: usize) -> (Vec<u8>, bool) {
+        let (mut kept, mut truncated) = (Vec::new(), false);
+        let Some(mut pipe) = pipe else {
+            return (kept, truncated);
+        };
+        let mut chunk = [0; 8192];
+        while let Ok(read @ 1..) = pipe.read(&mut chunk).await {
+            let room = max_bytes.saturating_sub(kept.len());
+            kept.extend_from_slice(&chunk[..read.min(room)]);
+            truncated |= read > room;
+        }
+        (kept, truncated)
+    }
+
+    /// Text or base64 as the agent sends it: a character split by the output cap alone
+    /// doesn't make a stream binary
+    fn encode(bytes: &[u8], truncated: bool) -> (String, output::OutputEncoding) {
+        match std::str::from_utf8(bytes) {
+            Err(e) if truncated && e.error_len().is_none() => {
+                output::encode_bytes(&bytes[..e.valid_up_to()])
+            }
+            _ => output::encode_bytes(bytes),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_encode_drops_a_character_split_by_the_cap() {
+            let text = "héllo".as_bytes();
+            assert_eq!(
+                encode(&text[..2], true),
+                ("h".to_string(), output::OutputEncoding::Utf8)
+            );
+            assert_eq!(encode(&text[..2], false).1, output::OutputEncoding::Base64);
+            assert_eq!(encode(b"\xff", true).1, output::OutputEncoding::Base64);
+        }
+
+        #[tokio::test]
+        async fn test_capture_keeps_the_head_and_drains_the_rest() {
+            let data = vec![b'x'; 100_000];
+            assert_eq!(capture(Some(&data[..]), 10).await, (vec![b'x'; 10], true));
+            assert_eq!(capture(Some(&b"ok"[..]), 10).await, (b"ok".to_vec(), false));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_kind_parses() {
+        assert_eq!(
+            "firecracker".parse::<BackendKind>().unwrap(),
+            BackendKind::Firecracker
+        );
+        assert_eq!(" mock ".parse::<BackendKind>().unwrap(), BackendKind::Mock);
+        assert!("docker".parse::<BackendKind>().is_err());
+        #[cfg(feature = "local-backend")]
+        assert_eq!("local".parse::<BackendKind>().unwrap(), BackendKind::Local);
+        // Without the feature the insecure backend can't be selected by accident
+        #[cfg(not(feature = "local-backend"))]
+        assert!("local".parse::<BackendKind>().is_err());
+        for kind in [BackendKind::Firecracker, BackendKind::Mock] {
+            assert_eq!(backend(kind).kind(), kind);
+            assert_eq!(kind.as_str().parse::<BackendKind>().unwrap(), kind);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use crate::ExecutionError;
+use serde::Serialize;
+use std::future::Future;
+
+/// Memory ballooning of pooled VMs, so idle guests hand memory back to the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalloonConfig {
+    /// Attach a balloon device and inflate it while a VM sits in the pool
+    pub enabled: bool,
+    /// Balloon size while a VM is idle in the pool, in MiB
+    pub idle_target_mib: u64,
+    /// How often the guest reports balloon statistics, in seconds; 0 disables them
+    pub stats_interval_secs: u64,
+}
+
+impl Default for BalloonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_target_mib: 64,
+            stats_interval_secs: 5,
+        }
+    }
+}
+
+/// Body of Firecracker's `PUT /balloon`; the balloon starts deflated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalloonDevice {
+    pub amount_mib: u64,
+    /// Let the guest reclaim balloon pages rather than OOM-kill the code under test
+    pub deflate_on_oom: bool,
+    pub stats_polling_interval_s: u64,
+}
+
+impl BalloonDevice {
+    pub fn new(config: &BalloonConfig) -> Self {
+        Self {
+            amount_mib: 0,
+            deflate_on_oom: true,
+            stats_polling_interval_s: config.stats_interval_secs,
+        }
+    }
+}
+
+/// Body of Firecracker's `PATCH /balloon`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalloonUpdate {
+    pub amount_mib: u64,
+}
+
+/// A VM whose balloon can be resized, injectable for tests
+pub trait Balloon {
+    fn set_balloon_target(
+        &mut self,
+        amount_mib: u64,
+    ) -> impl Future<Output = Result<(), ExecutionError>> + Send;
+}
+
+/// Inflate the balloon of a VM about to be parked in the pool
+pub async fn on_release<B: Balloon>(
+    vm: &mut B,
+    config: &BalloonConfig,
+) -> Result<(), ExecutionError> {
+    if !config.enabled {
+        return Ok(());
+    }
+    vm.set_balloon_target(config.idle_target_mib).await
+}
+
+/// Deflate the balloon of a VM taken from the pool, before it runs code
+pub async fn on_acquire<B: Balloon>(
+    vm: &mut B,
+    config: &BalloonConfig,
+) -> Result<(), ExecutionError> {
+    if !config.enabled {
+        return Ok(());
+    }
+    vm.set_balloon_target(0).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeVm {
+        targets: Vec<u64>,
+        fail: bool,
+    }
+
+    impl Balloon for FakeVm {
+        async fn set_balloon_target(&mut self, amount_mib: u64) -> Result<(), ExecutionError> {
+            self.targets.push(amount_mib);
+            if self.fail {
+                return Err(ExecutionError::ApiCommunicationError("balloon".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    fn enabled() -> BalloonConfig {
+        BalloonConfig {
+            enabled: true,
+            ..BalloonConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inflate_on_release_deflate_on_acquire() {
+        let mut vm = FakeVm::default();
+        on_release(&mut vm, &enabled()).await.unwrap();
+        on_acquire(&mut vm, &enabled()).await.unwrap();
+        on_release(&mut vm, &enabled()).await.unwrap();
+        assert_eq!(vm.targets, vec![64, 0, 64]);
+
+        let mut vm = FakeVm::default();
+        on_release(&mut vm, &BalloonConfig::default())
+            .await
+            .unwrap();
+        on_acquire(&mut vm, &BalloonConfig::default())
+            .await
+            .unwrap();
+        assert!(vm.targets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deflate_failure_is_reported() {
+        let mut vm = FakeVm {
+            fail: true,
+            ..FakeVm::default()
+        };
+        assert!(on_acquire(&mut vm, &enabled()).await.is_err());
+    }
+
+    #[test]
+    fn test_device_body() {
+        let body = serde_json::to_value(BalloonDevice::new(&enabled())).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "amount_mib": 0,
+                "deflate_on_oom": true,
+                "stats_polling_interval_s": 5
+            })
+        );
+    }
+}
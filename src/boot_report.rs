@@ -0,0 +1,394 @@
+use crate::history::now_millis;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// Failed boots whose reports and log files are kept at most
+pub const BOOT_REPORT_CAPACITY: usize = 32;
+
+/// How often retained log files are checked for expiry
+pub const BOOT_REPORT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Stands in for the host directories of paths in a report
+const REDACTED_DIR: &str = "<redacted>";
+
+/// Keeping failed VMs' console and Firecracker logs for inspection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootDiagnosticsConfig {
+    /// Keep the logs of every VM that fails to boot; requests can ask for it with `debug_boot`
+    pub enabled: bool,
+    /// Lines of the kernel console and Firecracker log attached to a report
+    pub console_lines: usize,
+    /// Time a failed VM's logs stay on the host before they are deleted
+    pub retention: Duration,
+}
+
+impl Default for BootDiagnosticsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            console_lines: 50,
+            retention: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// What a VM that failed to boot left behind
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BootReport {
+    pub vm_id: String,
+    /// When the boot failed, in milliseconds since the Unix epoch
+    pub failed_at: u64,
+    /// Why the boot failed
+    pub error: String,
+    /// Kernel command line the VM booted with
+    pub boot_args: String,
+    /// Last lines of the kernel console, which Firecracker writes to its stdout
+    pub console_tail: Vec<String>,
+    /// Last lines of Firecracker's own log
+    pub firecracker_log_tail: Vec<String>,
+    /// Firecracker configuration of the VM, with host directories redacted
+    #[schema(value_type = Object)]
+    pub firecracker_config: serde_json::Value,
+    /// Names of the log files kept on the host
+    pub retained_files: Vec<String>,
+    /// When the kept files are deleted, in milliseconds since the Unix epoch
+    pub expires_at: u64,
+}
+
+/// Logs of a failed boot, as read from the VM's files
+#[derive(Debug, Clone, Default)]
+pub struct BootLogs {
+    pub console: String,
+    pub firecracker_log: String,
+    /// Contents of the `--config-file`, or the configuration the API would have been sent
+    pub firecracker_config: String,
+}
+
+impl BootLogs {
+    /// Read the logs from their files; missing ones are left empty
+    pub fn read(console: &Path, firecracker_log: &Path, firecracker_config: &str) -> Self {
+        let read = |path: &Path| std::fs::read_to_string(path).unwrap_or_default();
+        Self {
+            console: read(console),
+            firecracker_log: read(firecracker_log),
+            firecracker_config: firecracker_config.to_string(),
+        }
+    }
+}
+
+impl BootReport {
+    /// Assemble the report of VM `vm_id`, which failed with `error`, keeping the last
+    /// `lines` lines of each log
+    pub fn assemble(
+        vm_id: &str,
+        error: &str,
+        boot_args: &str,
+        logs: &BootLogs,
+        lines: usize,
+    ) -> Self {
+        let mut firecracker_config =
+            serde_json::from_str(&logs.firecracker_config).unwrap_or(serde_json::Value::Null);
+        redact_paths(&mut firecracker_config);
+        Self {
+            vm_id: vm_id.to_string(),
+            failed_at: now_millis(),
+            error: error.to_string(),
+            boot_args: boot_args.to_string(),
+            console_tail: tail_lines(&logs.console, lines),
+            firecracker_log_tail: tail_lines(&logs.firecracker_log, lines),
+            firecracker_config,
+            retained_files: Vec::new(),
+            expires_at: 0,
+        }
+    }
+
+    /// The report as text, for error messages
+    pub fn summary(&self) -> String {
+        let config = serde_json::to_string_pretty(&self.firecracker_config).unwrap_or_default();
+        format!(
+            "Boot args: {}\n\nKernel console (last {} lines):\n{}\n\nFirecracker log (last {} lines):\n{}\n\nFirecracker configuration:\n{}",
+            self.boot_args,
+            self.console_tail.len(),
+            self.console_tail.join("\n"),
+            self.firecracker_log_tail.len(),
+            self.firecracker_log_tail.join("\n"),
+            config
+        )
+    }
+}
+
+/// The last `n` lines of `text`
+pub fn tail_lines(text: &str, n: usize) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(n)..]
+        .iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Replace the directory of every `*path*` value in `value` so reports don't reveal the
+/// host's layout; file names are kept as they tell the artifacts apart
+pub fn redact_paths(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    serde_json::Value::String(path) if key.contains("path") => {
+                        let name = Path::new(path.as_str())
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_default();
+                        *path = format!("{REDACTED_DIR}/{name}");
+                    }
+                    _ => redact_paths(value),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_paths),
+        _ => {}
+    }
+}
+
+#[derive(Debug)]
+struct Retained {
+    report: BootReport,
+    files: Vec<PathBuf>,
+}
+
+impl Retained {
+    fn delete_files(&self) {
+        for file in &self.files {
+            if let Err(e) = std::fs::remove_file(file)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                tracing::warn!(
+                    "Failed to remove retained boot log {}: {}",
+                    file.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Reports of recently failed boots along with the log files kept for them, which are
+/// deleted once they expire or make room for newer ones
+#[derive(Debug)]
+pub struct BootReports {
+    capacity: usize,
+    retained: Mutex<VecDeque<Retained>>,
+}
+
+/// Reports of the VMs this process failed to boot
+pub static BOOT_REPORTS: once_cell::sync::Lazy<BootReports> =
+    once_cell::sync::Lazy::new(|| BootReports::new(BOOT_REPORT_CAPACITY));
+
+impl BootReports {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            retained: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Keep `report` along with `files` for `retention`
+    pub fn retain(&self, mut report: BootReport, files: Vec<PathBuf>, retention: Duration) {
+        report.expires_at = report.failed_at + retention.as_millis() as u64;
+        report.retained_files = files
+            .iter()
+            .filter_map(|file| file.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .collect();
+        let mut retained = self.retained.lock().unwrap_or_else(|e| e.into_inner());
+        retained.retain(|entry| entry.report.vm_id != report.vm_id);
+        while retained.len() >= self.capacity.max(1) {
+            if let Some(oldest) = retained.pop_front() {
+                oldest.delete_files();
+            }
+        }
+        retained.push_back(Retained { report, files });
+    }
+
+    /// Report of VM `vm_id`, if it failed to boot recently
+    pub fn get(&self, vm_id: &str) -> Option<BootReport> {
+        self.retained
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|entry| entry.report.vm_id == vm_id)
+            .map(|entry| entry.report.clone())
+    }
+
+    /// Whether `file` is kept for a report that hasn't expired yet
+    pub fn is_retained(&self, file: &Path) -> bool {
+        self.retained
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .any(|entry| entry.files.iter().any(|kept| kept == file))
+    }
+
+    /// Drop the reports expired by `now` and delete their files, returning how many went
+    pub fn sweep(&self, now: u64) -> usize {
+        let mut retained = self.retained.lock().unwrap_or_else(|e| e.into_inner());
+        let before = retained.len();
+        retained.retain(|entry| {
+            let expired = entry.report.expires_at <= now;
+            if expired {
+                entry.delete_files();
+            }
+            !expired
+        });
+        before - retained.len()
+    }
+}
+
+/// Delete expired boot logs every `BOOT_REPORT_SWEEP_INTERVAL`
+pub fn spawn_gc() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(BOOT_REPORT_SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            crate::debug_state::heartbeat("boot_report_sweeper");
+            let removed = BOOT_REPORTS.sweep(now_millis());
+            if removed > 0 {
+                tracing::debug!("Removed the retained logs of {} failed boots", removed);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_logs() -> BootLogs {
+        let dir = Path::new("fixtures/boot-failure");
+        let config = std::fs::read_to_string(dir.join("vm-config.json")).unwrap();
+        BootLogs::read(
+            &dir.join("console.log"),
+            &dir.join("firecracker.log"),
+            &config,
+        )
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("fc-boot-report-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_report_from_fixture_logs() {
+        let report = BootReport::assemble(
+            "0a1b2c3d",
+            "VM API server did not become ready",
+            "console=ttyS0 reboot=k panic=1",
+            &fixture_logs(),
+            3,
+        );
+        assert_eq!(
+            report.console_tail,
+            [
+                "[    0.072881] Kernel panic - not syncing: Attempted to kill init! exitcode=0x00007f00",
+                "[    0.073102] Kernel Offset: disabled",
+                "[    0.073399] Rebooting in 1 seconds..",
+            ]
+        );
+        assert_eq!(report.firecracker_log_tail.len(), 3);
+        assert!(report.firecracker_log_tail[2].ends_with("Vmm is stopping."));
+
+        // Host directories are gone, file names and everything else are kept
+        let config = &report.firecracker_config;
+        assert_eq!(
+            config["boot-source"]["kernel_image_path"],
+            "<redacted>/vmlinux-6.1"
+        );
+        assert_eq!(
+            config["drives"][0]["path_on_host"],
+            "<redacted>/rootfs.ext4"
+        );
+        assert_eq!(
+            config["logger"]["log_path"],
+            "<redacted>/fc-log-0a1b2c3d.log"
+        );
+        assert_eq!(config["machine-config"]["mem_size_mib"], 128);
+        assert_eq!(
+            config["network-interfaces"][0]["host_dev_name"],
+            "tap-0a1b2c3d"
+        );
+
+        let summary = report.summary();
+        assert!(summary.contains("Kernel console (last 3 lines)"));
+        assert!(summary.contains("Kernel panic"));
+        assert!(summary.contains("<redacted>/rootfs.ext4"));
+        assert!(!summary.contains("/var/lib/firecracker"));
+    }
+
+    #[test]
+    fn test_report_with_missing_logs() {
+        let logs = BootLogs::read(
+            Path::new("fixtures/boot-failure/missing.log"),
+            Path::new("fixtures/boot-failure/missing.log"),
+            "",
+        );
+        let report = BootReport::assemble("vm", "boot failed", "", &logs, 50);
+        assert!(report.console_tail.is_empty());
+        assert!(report.firecracker_log_tail.is_empty());
+        assert_eq!(report.firecracker_config, serde_json::Value::Null);
+        assert_eq!(tail_lines("a\nb\n", 50), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_retained_files_are_deleted_on_expiry_and_eviction() {
+        let dir = temp_dir("retention");
+        let file = |name: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, "log").unwrap();
+            path
+        };
+        let report =
+            |vm_id: &str| BootReport::assemble(vm_id, "failed", "", &BootLogs::default(), 1);
+        let reports = BootReports::new(2);
+
+        let first = file("fc-stdout-first.log");
+        reports.retain(
+            report("first"),
+            vec![first.clone()],
+            Duration::from_secs(60),
+        );
+        let kept = reports.get("first").unwrap();
+        assert_eq!(kept.retained_files, ["fc-stdout-first.log"]);
+        assert_eq!(kept.expires_at, kept.failed_at + 60_000);
+
+        // Nothing has expired yet
+        assert_eq!(reports.sweep(kept.failed_at), 0);
+        assert!(first.exists());
+
+        // A full registry makes room by dropping the oldest report and its files
+        let second = file("fc-stdout-second.log");
+        let third = file("fc-stdout-third.log");
+        reports.retain(report("second"), vec![second.clone()], Duration::ZERO);
+        reports.retain(
+            report("third"),
+            vec![third.clone()],
+            Duration::from_secs(60),
+        );
+        assert!(reports.get("first").is_none());
+        assert!(!first.exists());
+
+        let removed = reports.sweep(now_millis());
+        assert_eq!(removed, 1);
+        assert!(reports.get("second").is_none());
+        assert!(!second.exists());
+        assert!(reports.get("third").is_some());
+        assert!(third.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
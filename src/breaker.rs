@@ -0,0 +1,414 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// VM creations whose outcome the recent boot failure rate is taken over
+pub const RECENT_BOOTS: usize = 20;
+
+/// When repeated VM creation failures stop new cold starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the circuit; 0 never opens it
+    pub failure_threshold: u32,
+    /// Failures further apart than this don't add up
+    pub window: Duration,
+    /// Time the circuit stays open before a single probe boot is let through
+    pub open_for: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window: Duration::from_secs(60),
+            open_for: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// VMs are created as usual
+    Closed,
+    /// Cold starts fail at once
+    Open,
+    /// One probe boot is running to find out whether the host has recovered
+    HalfOpen,
+}
+
+/// State of the VM creation circuit, as shown in `/health`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+    /// Failures since the last successful boot within the window
+    pub consecutive_failures: u32,
+    /// Error of the latest failed boot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// A VM creation the breaker let through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attempt {
+    Normal,
+    /// The one creation tried while the circuit is half-open
+    Probe,
+}
+
+/// Why a VM creation was refused without being tried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitOpen {
+    /// Until the next probe is let through
+    pub retry_in: Duration,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    state: BreakerState,
+    failures: u32,
+    first_failure: Option<Instant>,
+    /// When the circuit opened, or when the running probe started
+    since: Option<Instant>,
+    last_error: Option<String>,
+    /// Whether each of the last `RECENT_BOOTS` creations failed, oldest first
+    recent: VecDeque<bool>,
+}
+
+impl BreakerInner {
+    fn remember(&mut self, failed: bool) {
+        if self.recent.len() == RECENT_BOOTS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(failed);
+    }
+}
+
+/// Circuit breaker around VM creation, so a misconfigured host fails requests in milliseconds
+/// instead of spending a boot timeout on each
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    inner: Mutex<BreakerInner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                failures: 0,
+                first_failure: None,
+                since: None,
+                last_error: None,
+                recent: VecDeque::with_capacity(RECENT_BOOTS),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BreakerInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether a VM may be created at `now`. Once the circuit has been open for `open_for`,
+    /// a single probe is let through; a probe that never reports back is replaced after
+    /// another `open_for`.
+    pub fn try_acquire_at(&self, now: Instant) -> Result<Attempt, CircuitOpen> {
+        let mut inner = self.lock();
+        if inner.state == BreakerState::Closed {
+            return Ok(Attempt::Normal);
+        }
+        let since = inner.since.unwrap_or(now);
+        let elapsed = now.saturating_duration_since(since);
+        if elapsed >= self.config.open_for {
+            inner.state = BreakerState::HalfOpen;
+            inner.since = Some(now);
+            self.export(&inner);
+            return Ok(Attempt::Probe);
+        }
+        Err(CircuitOpen {
+            retry_in: self.config.open_for - elapsed,
+            last_error: inner.last_error.clone(),
+        })
+    }
+
+    pub fn try_acquire(&self) -> Result<Attempt, CircuitOpen> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    /// A VM was created; the circuit closes
+    pub fn record_success(&self) {
+        let mut inner = self.lock();
+        if inner.state != BreakerState::Closed {
+            tracing::info!("VM creation recovered; closing the circuit");
+        }
+        inner.remember(false);
+        inner.state = BreakerState::Closed;
+        inner.failures = 0;
+        inner.first_failure = None;
+        inner.since = None;
+        self.export(&inner);
+    }
+
+    /// A VM creation failed at `now` with `error`
+    pub fn record_failure_at(&self, error: &str, now: Instant) {
+        let mut inner = self.lock();
+        inner.remember(true);
+        inner.last_error = Some(error.to_string());
+        match inner.state {
+            BreakerState::Closed => {
+                let within_window = inner.first_failure.is_some_and(|first| {
+                    now.saturating_duration_since(first) <= self.config.window
+                });
+                if within_window {
+                    inner.failures += 1;
+                } else {
+                    inner.failures = 1;
+                    inner.first_failure = Some(now);
+                }
+                if self.config.failure_threshold > 0
+                    && inner.failures >= self.config.failure_threshold
+                {
+                    tracing::error!(
+                        "{} consecutive VM creation failures; failing cold starts for {:?}: {}",
+                        inner.failures,
+                        self.config.open_for,
+                        error
+                    );
+                    inner.state = BreakerState::Open;
+                    inner.since = Some(now);
+                }
+            }
+            // A failed probe, or a boot started before the circuit opened
+            BreakerState::HalfOpen | BreakerState::Open => {
+                inner.failures += 1;
+                if inner.state == BreakerState::HalfOpen {
+                    inner.state = BreakerState::Open;
+                    inner.since = Some(now);
+                }
+            }
+        }
+        self.export(&inner);
+    }
+
+    pub fn record_failure(&self, error: &str) {
+        self.record_failure_at(error, Instant::now());
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        let inner = self.lock();
+        BreakerStatus {
+            state: inner.state,
+            consecutive_failures: inner.failures,
+            last_error: inner.last_error.clone(),
+        }
+    }
+
+    /// Creations among the last `RECENT_BOOTS`, and the share of them that failed
+    pub fn recent_failure_rate(&self) -> (usize, Option<f64>) {
+        let inner = self.lock();
+        let boots = inner.recent.len();
+        let failed = inner.recent.iter().filter(|failed| **failed).count();
+        (boots, (boots > 0).then(|| failed as f64 / boots as f64))
+    }
+
+    fn export(&self, inner: &BreakerInner) {
+        crate::telemetry::set_gauge(
+            "fc_vm_creation_circuit_open",
+            &[],
+            if inner.state == BreakerState::Closed {
+                0.0
+            } else {
+                1.0
+            },
+        );
+        crate::telemetry::set_gauge(
+            "fc_vm_creation_consecutive_failures",
+            &[],
+            inner.failures as f64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+    const OPEN_FOR: Duration = Duration::from_secs(30);
+
+    fn breaker(failure_threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(BreakerConfig {
+            failure_threshold,
+            window: WINDOW,
+            open_for: OPEN_FOR,
+        })
+    }
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    /// A breaker opened by three failures at `start`
+    fn opened(start: Instant) -> CircuitBreaker {
+        let breaker = breaker(3);
+        for _ in 0..3 {
+            breaker.try_acquire_at(start).unwrap();
+            breaker.record_failure_at("no kernel", start);
+        }
+        assert_eq!(breaker.status().state, BreakerState::Open);
+        breaker
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures_within_the_window() {
+        let start = Instant::now();
+        let breaker = breaker(3);
+        breaker.record_failure_at("boom", start);
+        breaker.record_failure_at("boom", start + secs(10));
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+        assert_eq!(
+            breaker.try_acquire_at(start + secs(10)),
+            Ok(Attempt::Normal)
+        );
+        breaker.record_failure_at("sudo: a password is required", start + secs(20));
+        assert_eq!(
+            breaker.status(),
+            BreakerStatus {
+                state: BreakerState::Open,
+                consecutive_failures: 3,
+                last_error: Some("sudo: a password is required".to_string()),
+            }
+        );
+        let refused = breaker.try_acquire_at(start + secs(25)).unwrap_err();
+        assert_eq!(refused.retry_in, secs(25));
+        assert_eq!(
+            refused.last_error.as_deref(),
+            Some("sudo: a password is required")
+        );
+    }
+
+    #[test]
+    fn test_failures_outside_the_window_start_over() {
+        let start = Instant::now();
+        let breaker = breaker(3);
+        breaker.record_failure_at("boom", start);
+        breaker.record_failure_at("boom", start + secs(30));
+        breaker.record_failure_at("boom", start + WINDOW + secs(1));
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn test_success_resets_the_count() {
+        let start = Instant::now();
+        let breaker = breaker(3);
+        breaker.record_failure_at("boom", start);
+        breaker.record_failure_at("boom", start);
+        breaker.record_success();
+        breaker.record_failure_at("boom", start);
+        breaker.record_failure_at("boom", start);
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 2);
+    }
+
+    #[test]
+    fn test_recent_failure_rate() {
+        let start = Instant::now();
+        let breaker = breaker(0);
+        assert_eq!(breaker.recent_failure_rate(), (0, None));
+        breaker.record_failure_at("boom", start);
+        breaker.record_success();
+        breaker.record_success();
+        breaker.record_success();
+        assert_eq!(breaker.recent_failure_rate(), (4, Some(0.25)));
+        // Only the latest creations count
+        for _ in 0..RECENT_BOOTS {
+            breaker.record_success();
+        }
+        assert_eq!(breaker.recent_failure_rate(), (RECENT_BOOTS, Some(0.0)));
+    }
+
+    #[test]
+    fn test_zero_threshold_never_opens() {
+        let start = Instant::now();
+        let breaker = breaker(0);
+        for _ in 0..100 {
+            breaker.record_failure_at("boom", start);
+        }
+        assert_eq!(breaker.try_acquire_at(start), Ok(Attempt::Normal));
+    }
+
+    #[test]
+    fn test_a_single_probe_is_let_through_once_open_for_passes() {
+        let start = Instant::now();
+        let breaker = opened(start);
+        assert!(breaker.try_acquire_at(start + OPEN_FOR - secs(1)).is_err());
+        assert_eq!(breaker.try_acquire_at(start + OPEN_FOR), Ok(Attempt::Probe));
+        assert_eq!(breaker.status().state, BreakerState::HalfOpen);
+        // Everyone else keeps failing fast while the probe runs
+        let refused = breaker.try_acquire_at(start + OPEN_FOR).unwrap_err();
+        assert_eq!(refused.retry_in, OPEN_FOR);
+    }
+
+    #[test]
+    fn test_successful_probe_closes_the_circuit() {
+        let start = Instant::now();
+        let breaker = opened(start);
+        breaker.try_acquire_at(start + OPEN_FOR).unwrap();
+        breaker.record_success();
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 0);
+        assert_eq!(
+            breaker.try_acquire_at(start + OPEN_FOR),
+            Ok(Attempt::Normal)
+        );
+    }
+
+    #[test]
+    fn test_failed_probe_reopens_for_another_period() {
+        let start = Instant::now();
+        let breaker = opened(start);
+        let probe_at = start + OPEN_FOR;
+        breaker.try_acquire_at(probe_at).unwrap();
+        breaker.record_failure_at("still no kernel", probe_at + secs(2));
+        let status = breaker.status();
+        assert_eq!(status.state, BreakerState::Open);
+        assert_eq!(status.consecutive_failures, 4);
+        assert_eq!(status.last_error.as_deref(), Some("still no kernel"));
+        assert!(breaker.try_acquire_at(probe_at + OPEN_FOR).is_err());
+        assert_eq!(
+            breaker.try_acquire_at(probe_at + secs(2) + OPEN_FOR),
+            Ok(Attempt::Probe)
+        );
+    }
+
+    #[test]
+    fn test_lost_probe_is_replaced() {
+        let start = Instant::now();
+        let breaker = opened(start);
+        let probe_at = start + OPEN_FOR;
+        breaker.try_acquire_at(probe_at).unwrap();
+        // The probe never reports back, so after another period a new one goes out
+        assert!(breaker.try_acquire_at(probe_at + secs(29)).is_err());
+        assert_eq!(
+            breaker.try_acquire_at(probe_at + OPEN_FOR),
+            Ok(Attempt::Probe)
+        );
+    }
+
+    #[test]
+    fn test_late_failures_while_open_keep_it_open() {
+        let start = Instant::now();
+        let breaker = opened(start);
+        // Boots started before the circuit opened finish failing afterwards
+        breaker.record_failure_at("no kernel", start + secs(5));
+        assert_eq!(breaker.status().state, BreakerState::Open);
+        assert_eq!(breaker.status().consecutive_failures, 4);
+        assert_eq!(breaker.try_acquire_at(start + OPEN_FOR), Ok(Attempt::Probe));
+    }
+}
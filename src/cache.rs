@@ -0,0 +1,364 @@
+use crate::ExecuteResponse;
+use crate::program::Program;
+use crate::runner::ExecutionSpec;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Names whose presence suggests the output differs from run to run
+const NONDETERMINISTIC_HINTS: &[&str] = &["random", "time", "uuid", "secrets", "urandom"];
+
+/// Result cache settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    /// Cache requests that don't set `cache` explicitly
+    pub enabled: bool,
+    /// Maximum number of cached results; 0 disables the cache entirely
+    pub capacity: usize,
+    /// How long a cached result stays valid
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 1000,
+            ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+/// Cache key: hex SHA-256 over every request field that affects the output, and over
+/// `max_output_bytes`, the effective output cap, since it decides where the output is cut
+pub fn cache_key(request: &ExecutionSpec, max_output_bytes: usize) -> String {
+    let mut hasher = Sha256::new();
+    // Length-prefix fields so adjacent ones can't run into each other
+    let mut update = |field: &str| {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    };
+    match &request.program {
+        Program::Code(code) => update(code),
+        Program::Eval(code) => {
+            update("eval");
+            update(code);
+        }
+        Program::Files { files, entrypoint } => {
+            update("files");
+            update(entrypoint);
+            // File order doesn't change what runs
+            let mut files: Vec<_> = files.iter().collect();
+            files.sort_by(|a, b| a.path.cmp(&b.path));
+            for file in files {
+                update(&file.path);
+                update(&file.content);
+            }
+        }
+    }
+    for requirement in &request.requirements {
+        update(requirement);
+    }
+    if let Some(profile) = &request.deps_profile {
+        update("deps_profile");
+        update(profile);
+    }
+    if let Some(image) = &request.image {
+        update("image");
+        update(image);
+    }
+    if !request.mount_reference_data {
+        update("no_reference_data");
+    }
+    if let Some(dns) = &request.dns {
+        update("dns");
+        for server in dns {
+            update(server);
+        }
+    }
+    update(&max_output_bytes.to_string());
+    if let Some(settings) = &request.deterministic {
+        update("deterministic");
+        update(&settings.hash_seed.to_string());
+        update(
+            &settings
+                .fake_time
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+        );
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Whether a successful result is worth caching, judging by the code that produced it
+pub fn looks_deterministic(program: &Program) -> bool {
+    !program.sources().iter().any(|source| {
+        NONDETERMINISTIC_HINTS
+            .iter()
+            .any(|hint| source.contains(hint))
+    })
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: ExecuteResponse,
+    inserted: Instant,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+/// In-memory LRU of execution results with a per-entry TTL
+#[derive(Debug)]
+pub struct ResultCache {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<ExecuteResponse> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Same as [`ResultCache::get`] with an explicit clock, for tests
+    pub fn get_at(&self, key: &str, now: Instant) -> Option<ExecuteResponse> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.clock += 1;
+        let clock = inner.clock;
+        let entry = inner.entries.get_mut(key)?;
+        if now.saturating_duration_since(entry.inserted) < self.ttl {
+            entry.last_used = clock;
+            return Some(entry.response.clone());
+        }
+        inner.entries.remove(key);
+        None
+    }
+
+    pub fn insert(&self, key: String, response: ExecuteResponse) {
+        self.insert_at(key, response, Instant::now());
+    }
+
+    /// Same as [`ResultCache::insert`] with an explicit clock, for tests
+    pub fn insert_at(&self, key: String, response: ExecuteResponse, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.clock += 1;
+        let clock = inner.clock;
+        if inner.entries.len() >= self.capacity && !inner.entries.contains_key(&key) {
+            let oldest = inner
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                inner.entries.remove(&oldest);
+            }
+        }
+        inner.entries.insert(
+            key,
+            Entry {
+                response,
+                inserted: now,
+                last_used: clock,
+            },
+        );
+    }
+
+    /// Drop every entry, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let count = inner.entries.len();
+        inner.entries.clear();
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entries
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_success_response;
+    use crate::determinism::DeterministicSettings;
+
+    fn response(stdout: &str) -> ExecuteResponse {
+        create_success_response(stdout.to_string(), String::new())
+    }
+
+    fn code(code: &str) -> Program {
+        Program::Code(code.to_string())
+    }
+
+    /// Output cap the keys are computed under
+    const LIMIT: usize = 1024;
+
+    fn key(program: Program) -> String {
+        cache_key(
+            &ExecutionSpec {
+                program,
+                ..ExecutionSpec::code("")
+            },
+            LIMIT,
+        )
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = ResultCache::new(10, Duration::from_secs(60));
+        let key = cache_key(&ExecutionSpec::code("print(1)"), LIMIT);
+        assert!(cache.get(&key).is_none());
+        cache.insert(key.clone(), response("1\n"));
+        assert_eq!(cache.get(&key).unwrap().stdout, "1\n");
+        assert!(
+            cache
+                .get(&cache_key(&ExecutionSpec::code("print(2)"), LIMIT))
+                .is_none()
+        );
+        assert_ne!(
+            key,
+            cache_key(
+                &ExecutionSpec {
+                    requirements: vec!["numpy".to_string()],
+                    ..ExecutionSpec::code("print(1)")
+                },
+                LIMIT
+            )
+        );
+        assert_ne!(
+            key,
+            cache_key(
+                &ExecutionSpec {
+                    deps_profile: Some("numpy".to_string()),
+                    ..ExecutionSpec::code("print(1)")
+                },
+                LIMIT
+            )
+        );
+        assert_ne!(
+            key,
+            cache_key(
+                &ExecutionSpec {
+                    image: Some("ds".to_string()),
+                    ..ExecutionSpec::code("print(1)")
+                },
+                LIMIT
+            )
+        );
+        assert_ne!(
+            cache_key(
+                &ExecutionSpec {
+                    deterministic: Some(DeterministicSettings::new(None)),
+                    ..ExecutionSpec::code("print(1)")
+                },
+                LIMIT
+            ),
+            cache_key(
+                &ExecutionSpec {
+                    deterministic: Some(DeterministicSettings::new(Some(0))),
+                    ..ExecutionSpec::code("print(1)")
+                },
+                LIMIT
+            )
+        );
+    }
+
+    #[test]
+    fn test_output_cap_is_part_of_the_key() {
+        let spec = ExecutionSpec::code("print(1)");
+        // A result cut at a low cap must not answer a request allowed the full output
+        assert_ne!(cache_key(&spec, LIMIT), cache_key(&spec, 10));
+        assert_eq!(cache_key(&spec, LIMIT), cache_key(&spec, LIMIT));
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let cache = ResultCache::new(10, Duration::from_secs(60));
+        let now = Instant::now();
+        cache.insert_at("k".to_string(), response("x"), now);
+        assert!(cache.get_at("k", now + Duration::from_secs(59)).is_some());
+        assert!(cache.get_at("k", now + Duration::from_secs(60)).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = ResultCache::new(2, Duration::from_secs(60));
+        cache.insert("a".to_string(), response("a"));
+        cache.insert("b".to_string(), response("b"));
+        // Touch "a" so "b" becomes the least recently used
+        assert!(cache.get("a").is_some());
+        cache.insert("c".to_string(), response("c"));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.clear(), 2);
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_cache() {
+        let cache = ResultCache::new(0, Duration::from_secs(60));
+        cache.insert("a".to_string(), response("a"));
+        assert!(cache.get("a").is_none());
+    }
+
+    #[test]
+    fn test_looks_deterministic() {
+        assert!(looks_deterministic(&code("print(sum(range(10)))")));
+        assert!(!looks_deterministic(&code(
+            "import random\nprint(random.random())"
+        )));
+        assert!(!looks_deterministic(&code(
+            "import time\nprint(time.time())"
+        )));
+    }
+
+    #[test]
+    fn test_files_key_ignores_order() {
+        use crate::program::SourceFile;
+
+        let file = |path: &str, content: &str| SourceFile {
+            path: path.to_string(),
+            content: content.to_string(),
+        };
+        let program = |files| Program::Files {
+            files,
+            entrypoint: "main.py".to_string(),
+        };
+        let a = program(vec![file("main.py", "import u"), file("u.py", "")]);
+        let b = program(vec![file("u.py", ""), file("main.py", "import u")]);
+        assert_eq!(key(a.clone()), key(b));
+        assert_ne!(key(a), key(code("import u")));
+    }
+
+    #[test]
+    fn test_eval_key_differs_from_script() {
+        // An evaluation returns a value the script run doesn't
+        assert_ne!(key(code("1 + 1")), key(Program::Eval("1 + 1".to_string())));
+    }
+}
@@ -0,0 +1,257 @@
+use crate::config::ConfigError;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Time an execution runs before a mid-execute fault strikes, unless configured otherwise
+pub const DEFAULT_MID_EXECUTE_DELAY: Duration = Duration::from_millis(100);
+
+/// Place in a VM's life where the runner consults the fault injector
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultPoint {
+    /// The guest agent answered, before the VM is handed out or pooled
+    AfterBoot,
+    /// The VM was acquired and the execution not sent yet
+    BeforeExecute,
+    /// The execution request is in flight
+    MidExecute,
+    /// The VM is being shut down
+    Shutdown,
+}
+
+impl std::str::FromStr for FaultPoint {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "after_boot" => Ok(FaultPoint::AfterBoot),
+            "before_execute" => Ok(FaultPoint::BeforeExecute),
+            "mid_execute" => Ok(FaultPoint::MidExecute),
+            "shutdown" => Ok(FaultPoint::Shutdown),
+            other => Err(ConfigError::Invalid(format!(
+                "unknown fault point {other:?} (expected after_boot, before_execute, mid_execute or shutdown)"
+            ))),
+        }
+    }
+}
+
+/// What is done to the VM at a fault point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Kill the Firecracker process
+    Kill,
+    /// Delete the VM's TAP interface, cutting the guest off from the host
+    DropTap,
+    /// Fail the step without touching the VM
+    Error,
+}
+
+impl std::str::FromStr for Fault {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "kill" => Ok(Fault::Kill),
+            "drop_tap" => Ok(Fault::DropTap),
+            "error" => Ok(Fault::Error),
+            other => Err(ConfigError::Invalid(format!(
+                "unknown fault {other:?} (expected kill, drop_tap or error)"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Plan {
+    /// Faults still to inject, in the order their point is reached
+    Script(HashMap<FaultPoint, VecDeque<Fault>>),
+    /// Probability of each fault whenever its point is reached
+    Random {
+        odds: Vec<(FaultPoint, Fault, f64)>,
+        state: u64,
+    },
+}
+
+/// Decides which faults the runner injects into its VMs, for resilience testing. Only built
+/// with the `chaos` feature; without it the runner has no fault points at all.
+#[derive(Debug)]
+pub struct FaultInjector {
+    plan: Mutex<Plan>,
+    /// Time an execution runs before a mid-execute fault strikes
+    pub mid_execute_delay: Duration,
+}
+
+impl FaultInjector {
+    /// Inject `faults` one by one, each the next time its point is reached
+    pub fn script(faults: impl IntoIterator<Item = (FaultPoint, Fault)>) -> Self {
+        let mut script: HashMap<FaultPoint, VecDeque<Fault>> = HashMap::new();
+        for (point, fault) in faults {
+            script.entry(point).or_default().push_back(fault);
+        }
+        Self::with_plan(Plan::Script(script))
+    }
+
+    /// Inject each fault with its probability whenever its point is reached; the same `seed`
+    /// gives the same sequence of faults
+    pub fn random(odds: impl IntoIterator<Item = (FaultPoint, Fault, f64)>, seed: u64) -> Self {
+        Self::with_plan(Plan::Random {
+            odds: odds.into_iter().collect(),
+            state: seed,
+        })
+    }
+
+    fn with_plan(plan: Plan) -> Self {
+        Self {
+            plan: Mutex::new(plan),
+            mid_execute_delay: DEFAULT_MID_EXECUTE_DELAY,
+        }
+    }
+
+    pub fn with_mid_execute_delay(mut self, delay: Duration) -> Self {
+        self.mid_execute_delay = delay;
+        self
+    }
+
+    /// The fault to inject now that `point` is reached, if any
+    pub fn next(&self, point: FaultPoint) -> Option<Fault> {
+        let mut plan = self.plan.lock().unwrap_or_else(|e| e.into_inner());
+        match &mut *plan {
+            Plan::Script(script) => script.get_mut(&point)?.pop_front(),
+            Plan::Random { odds, state } => odds
+                .iter()
+                .filter(|(at, _, _)| *at == point)
+                .find(|(_, _, probability)| unit(state) < *probability)
+                .map(|(_, fault, _)| *fault),
+        }
+    }
+
+    /// Scripted faults not injected yet; always 0 for random faults
+    pub fn remaining(&self) -> usize {
+        match &*self.plan.lock().unwrap_or_else(|e| e.into_inner()) {
+            Plan::Script(script) => script.values().map(VecDeque::len).sum(),
+            Plan::Random { .. } => 0,
+        }
+    }
+
+    /// Faults from `FC_CHAOS_SCRIPT` (`point=fault,...`) or else `FC_CHAOS`
+    /// (`point=fault:probability,...`, seeded by `FC_CHAOS_SEED`); `None` when neither is set
+    pub fn from_env() -> Result<Option<Self>, ConfigError> {
+        let injector = if let Ok(raw) = std::env::var("FC_CHAOS_SCRIPT") {
+            Self::script(parse_script(&raw)?)
+        } else if let Ok(raw) = std::env::var("FC_CHAOS") {
+            let seed = crate::config::env_parse("FC_CHAOS_SEED").unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_nanos() as u64)
+                    .unwrap_or_default()
+            });
+            Self::random(parse_odds(&raw)?, seed)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(
+            match crate::config::env_parse("FC_CHAOS_MID_EXECUTE_DELAY_MS") {
+                Some(ms) => injector.with_mid_execute_delay(Duration::from_millis(ms)),
+                None => injector,
+            },
+        ))
+    }
+}
+
+/// Next number in [0, 1) from a SplitMix64 generator; good enough to pick faults
+fn unit(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn entries(raw: &str) -> impl Iterator<Item = Result<(FaultPoint, &str), ConfigError>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (point, rest) = entry
+                .split_once('=')
+                .ok_or_else(|| ConfigError::Invalid(format!("fault {entry:?}")))?;
+            Ok((point.parse()?, rest))
+        })
+}
+
+/// Parse a fault script, `point=fault,...`
+pub fn parse_script(raw: &str) -> Result<Vec<(FaultPoint, Fault)>, ConfigError> {
+    entries(raw)
+        .map(|entry| {
+            let (point, fault) = entry?;
+            Ok((point, fault.parse()?))
+        })
+        .collect()
+}
+
+/// Parse fault probabilities, `point=fault:probability,...`
+pub fn parse_odds(raw: &str) -> Result<Vec<(FaultPoint, Fault, f64)>, ConfigError> {
+    entries(raw)
+        .map(|entry| {
+            let (point, rest) = entry?;
+            let (fault, probability) = rest
+                .split_once(':')
+                .ok_or_else(|| ConfigError::Invalid(format!("fault probability {rest:?}")))?;
+            let probability: f64 = probability
+                .trim()
+                .parse()
+                .ok()
+                .filter(|p| (0.0..=1.0).contains(p))
+                .ok_or_else(|| ConfigError::Invalid(format!("fault probability {rest:?}")))?;
+            Ok((point, fault.parse()?, probability))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_injects_in_order_per_point() {
+        let injector = FaultInjector::script(
+            parse_script("after_boot=kill, before_execute=error, after_boot=drop_tap").unwrap(),
+        );
+        assert_eq!(injector.remaining(), 3);
+        assert_eq!(injector.next(FaultPoint::Shutdown), None);
+        assert_eq!(injector.next(FaultPoint::AfterBoot), Some(Fault::Kill));
+        assert_eq!(injector.next(FaultPoint::AfterBoot), Some(Fault::DropTap));
+        assert_eq!(injector.next(FaultPoint::AfterBoot), None);
+        assert_eq!(injector.next(FaultPoint::BeforeExecute), Some(Fault::Error));
+        assert_eq!(injector.remaining(), 0);
+
+        assert!(parse_script("after_boot").is_err());
+        assert!(parse_script("during_boot=kill").is_err());
+        assert!(parse_script("after_boot=explode").is_err());
+    }
+
+    #[test]
+    fn test_random_faults_follow_their_odds() {
+        let odds = parse_odds("before_execute=kill:0.25, shutdown=error:1").unwrap();
+        let injector = FaultInjector::random(odds.clone(), 7);
+        let kills = (0..1000)
+            .filter(|_| injector.next(FaultPoint::BeforeExecute).is_some())
+            .count();
+        assert!((150..350).contains(&kills), "{kills} kills");
+        assert_eq!(injector.next(FaultPoint::Shutdown), Some(Fault::Error));
+        assert_eq!(injector.next(FaultPoint::AfterBoot), None);
+
+        // The seed makes the sequence repeatable
+        let sequence = |seed| {
+            let injector = FaultInjector::random(odds.clone(), seed);
+            (0..20)
+                .map(|_| injector.next(FaultPoint::BeforeExecute))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(42), sequence(42));
+
+        assert!(parse_odds("shutdown=error").is_err());
+        assert!(parse_odds("shutdown=error:1.5").is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{ErrorResponse, ExecuteRequest, ExecuteResponse, HealthResponse};
+use thiserror::Error;
+
+/// Why a client call failed
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// The server could not be reached, or its response could not be read
+    #[error("Transport error: {0}")]
+    Transport(#[from] reqwest::Error),
+    /// The server answered with an error status
+    #[error("Server returned {status}: {message}")]
+    Server {
+        status: u16,
+        /// Machine-readable code, e.g. `unauthorized` or `rate_limited`; `/execute` rejections
+        /// carry none
+        code: Option<String>,
+        message: String,
+        /// Seconds from `Retry-After`, sent with `429` and `503`
+        retry_after: Option<u64>,
+    },
+}
+
+/// Typed client for the HTTP API, sharing its request and response types with the server
+#[derive(Debug, Clone)]
+pub struct FirecrackerPocClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl FirecrackerPocClient {
+    /// Client for the server at `base_url`, e.g. `http://127.0.0.1:3000`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    /// Send `key` as a bearer token on every request
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Run a program, returning its result even when the code itself failed. The result's
+    /// `vm_id` comes from the `x-vm-id` header.
+    pub async fn execute(&self, request: &ExecuteRequest) -> Result<ExecuteResponse, ClientError> {
+        let response = self
+            .request(reqwest::Method::POST, "/execute")
+            .json(request)
+            .send()
+            .await?;
+        let vm_id = response
+            .headers()
+            .get("x-vm-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let mut response: ExecuteResponse = Self::parse(response).await?;
+        response.vm_id = vm_id;
+        Ok(response)
+    }
+
+    pub async fn health(&self) -> Result<HealthResponse, ClientError> {
+        let response = self.request(reqwest::Method::GET, "/health").send().await?;
+        Self::parse(response).await
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let builder = self
+            .http
+            .request(method, format!("{}{path}", self.base_url));
+        match &self.api_key {
+            Some(key) => builder.bearer_auth(key),
+            None => builder,
+        }
+    }
+
+    async fn parse<T: serde::de::DeserializeOwned>(
+        response: reqwest::Response,
+    ) -> Result<T, ClientError> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let body = response.text().await?;
+        Err(server_error(status.as_u16(), &body, retry_after))
+    }
+}
+
+/// Interpret an error body: the `{error, code}` envelope, or an `/execute` response whose
+/// `stderr` holds the message
+fn server_error(status: u16, body: &str, retry_after: Option<u64>) -> ClientError {
+    let (code, message) = if let Ok(error) = serde_json::from_str::<ErrorResponse>(body) {
+        (Some(error.code), error.error)
+    } else if let Ok(response) = serde_json::from_str::<ExecuteResponse>(body) {
+        (None, response.stderr)
+    } else {
+        (None, body.to_string())
+    };
+    ClientError::Server {
+        status,
+        code,
+        message,
+        retry_after,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_error_bodies() {
+        let envelope = r#"{"error":"Invalid API key","code":"unauthorized"}"#;
+        let ClientError::Server { code, message, .. } = server_error(401, envelope, None) else {
+            panic!("expected a server error");
+        };
+        assert_eq!(code.as_deref(), Some("unauthorized"));
+        assert_eq!(message, "Invalid API key");
+
+        let execute = serde_json::to_string(&crate::create_error_response(
+            "Empty code provided".to_string(),
+        ))
+        .unwrap();
+        let ClientError::Server { code, message, .. } = server_error(400, &execute, None) else {
+            panic!("expected a server error");
+        };
+        assert_eq!(code, None);
+        assert_eq!(message, "Empty code provided");
+
+        let ClientError::Server { message, .. } = server_error(502, "Bad Gateway", Some(3)) else {
+            panic!("expected a server error");
+        };
+        assert_eq!(message, "Bad Gateway");
+    }
+}
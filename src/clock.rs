@@ -0,0 +1,253 @@
+use crate::ExecutionError;
+use crate::history::now_millis;
+use crate::telemetry;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+/// Pushing the host's wall-clock time into guests, whose clocks drift while they sit in the pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClockSyncConfig {
+    /// Set the guest clock on reuse after a long idle spell
+    pub enabled: bool,
+    /// Idle time in the pool after which a VM's clock is set again before it runs code
+    pub resync_after_idle: Duration,
+    /// Time allowed to get the guest clock right before the VM is given up on
+    pub deadline: Duration,
+    /// Offset from the host, in milliseconds, still counted as in sync
+    pub tolerance_ms: u64,
+}
+
+impl Default for ClockSyncConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            resync_after_idle: Duration::from_secs(60),
+            deadline: Duration::from_secs(2),
+            tolerance_ms: 100,
+        }
+    }
+}
+
+/// Body of the agent's `POST /set-time`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SetTime {
+    /// Host wall-clock time, in milliseconds since the Unix epoch
+    pub epoch_ms: u64,
+}
+
+/// The agent's reply to `POST /set-time`: its clock just before and just after setting it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct GuestTime {
+    pub previous_ms: i64,
+    pub now_ms: i64,
+}
+
+/// Offset of the guest clock from the host's before and after a sync, in milliseconds; positive
+/// when the guest is ahead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockDrift {
+    pub before_ms: i64,
+    pub after_ms: i64,
+}
+
+/// A guest whose clock can be set, injectable for tests
+pub trait GuestClock {
+    /// Whether the guest agent has `POST /set-time`; older agents are left as they are
+    fn can_set_time(&self) -> bool {
+        true
+    }
+
+    fn set_guest_time(
+        &self,
+        epoch_ms: u64,
+    ) -> impl Future<Output = Result<GuestTime, ExecutionError>> + Send;
+}
+
+/// Set the guest clock to the host's, retrying until it is within the tolerance or the
+/// deadline passes. The drift before and after is exported as the `fc_guest_clock_drift_ms`
+/// histogram.
+pub async fn sync<C: GuestClock>(
+    vm: &C,
+    config: &ClockSyncConfig,
+) -> Result<ClockDrift, ExecutionError> {
+    let mut last_problem = String::from("no reply from the guest agent");
+    let attempts = async {
+        loop {
+            let sent = now_millis();
+            match vm.set_guest_time(sent).await {
+                Ok(guest) => {
+                    // The agent set its clock somewhere between sending and receiving
+                    let host = (sent + now_millis()) / 2;
+                    let drift = ClockDrift {
+                        before_ms: guest.previous_ms - sent as i64,
+                        after_ms: guest.now_ms - host as i64,
+                    };
+                    if drift.after_ms.unsigned_abs() <= config.tolerance_ms {
+                        return drift;
+                    }
+                    last_problem = format!("guest clock still {}ms off", drift.after_ms);
+                }
+                Err(e) => last_problem = e.to_string(),
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    };
+    let drift = tokio::time::timeout(config.deadline, attempts)
+        .await
+        .map_err(|_| {
+            ExecutionError::ApiCommunicationError(format!(
+                "Guest clock not synchronized within {}ms: {last_problem}",
+                config.deadline.as_millis()
+            ))
+        })?;
+    for (phase, value) in [("before", drift.before_ms), ("after", drift.after_ms)] {
+        telemetry::observe_histogram(
+            "fc_guest_clock_drift_ms",
+            &[("phase", phase)],
+            value.unsigned_abs() as f64,
+        );
+    }
+    if drift.before_ms.unsigned_abs() > config.tolerance_ms {
+        tracing::info!("Corrected guest clock drift of {}ms", drift.before_ms);
+    }
+    Ok(drift)
+}
+
+/// Set the clock of a VM taken from the pool if it sat there long enough to drift. A freshly
+/// booted guest reads the host's time from its RTC, so boots don't sync.
+pub async fn on_acquire<C: GuestClock>(
+    vm: &C,
+    idle_for: Duration,
+    config: &ClockSyncConfig,
+) -> Result<(), ExecutionError> {
+    if !config.enabled || !vm.can_set_time() || idle_for < config.resync_after_idle {
+        return Ok(());
+    }
+    sync(vm, config).await.map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Guest whose clock is `offset_ms` off the host's until set, unless it ignores the call
+    #[derive(Default)]
+    struct FakeGuest {
+        offset_ms: i64,
+        ignores_set: bool,
+        /// An agent from before `POST /set-time`
+        legacy: bool,
+        /// Calls failing before the agent answers
+        failures: Mutex<u32>,
+        calls: Mutex<Vec<u64>>,
+    }
+
+    impl GuestClock for FakeGuest {
+        fn can_set_time(&self) -> bool {
+            !self.legacy
+        }
+
+        async fn set_guest_time(&self, epoch_ms: u64) -> Result<GuestTime, ExecutionError> {
+            self.calls.lock().unwrap().push(epoch_ms);
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(ExecutionError::ApiCommunicationError(
+                    "connection refused".to_string(),
+                ));
+            }
+            let previous_ms = epoch_ms as i64 + self.offset_ms;
+            let now_ms = if self.ignores_set {
+                previous_ms
+            } else {
+                epoch_ms as i64
+            };
+            Ok(GuestTime {
+                previous_ms,
+                now_ms,
+            })
+        }
+    }
+
+    fn short_deadline() -> ClockSyncConfig {
+        ClockSyncConfig {
+            deadline: Duration::from_millis(300),
+            ..ClockSyncConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_reports_the_corrected_drift() {
+        let guest = FakeGuest {
+            offset_ms: -3_600_000,
+            failures: Mutex::new(2),
+            ..FakeGuest::default()
+        };
+        let drift = sync(&guest, &short_deadline()).await.unwrap();
+        assert!((drift.before_ms + 3_600_000).abs() < 100, "{drift:?}");
+        assert!(drift.after_ms.abs() < 100, "{drift:?}");
+        // Two refused calls, then the one that set the clock
+        assert_eq!(guest.calls.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sync_fails_once_the_deadline_passes() {
+        let guest = FakeGuest {
+            offset_ms: 90_000,
+            ignores_set: true,
+            ..FakeGuest::default()
+        };
+        let err = sync(&guest, &short_deadline()).await.unwrap_err();
+        assert!(err.to_string().contains("within 300ms"), "{err}");
+        assert!(err.to_string().contains("still 90000ms off"), "{err}");
+        assert!(guest.calls.lock().unwrap().len() > 1);
+
+        let unreachable = FakeGuest {
+            failures: Mutex::new(u32::MAX),
+            ..FakeGuest::default()
+        };
+        let err = on_acquire(&unreachable, Duration::from_secs(600), &short_deadline())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("connection refused"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_only_long_idle_vms_are_resynced() {
+        let config = ClockSyncConfig::default();
+        let guest = FakeGuest::default();
+        on_acquire(&guest, Duration::from_secs(5), &config)
+            .await
+            .unwrap();
+        assert!(guest.calls.lock().unwrap().is_empty());
+        on_acquire(&guest, Duration::from_secs(600), &config)
+            .await
+            .unwrap();
+        assert_eq!(guest.calls.lock().unwrap().len(), 1);
+
+        let disabled = ClockSyncConfig {
+            enabled: false,
+            ..config
+        };
+        on_acquire(&guest, Duration::from_secs(600), &disabled)
+            .await
+            .unwrap();
+        assert_eq!(guest.calls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_agents_without_set_time_are_left_alone() {
+        let config = ClockSyncConfig::default();
+        let legacy = FakeGuest {
+            legacy: true,
+            failures: Mutex::new(u32::MAX),
+            ..FakeGuest::default()
+        };
+        on_acquire(&legacy, Duration::from_secs(600), &config)
+            .await
+            .unwrap();
+        assert!(legacy.calls.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1199 @@
+use crate::arch::ArchArtifacts;
+use crate::artifacts::{ArtifactBackend, ArtifactConfig};
+use crate::audit::AuditConfig;
+use crate::autoscale::AutoscaleConfig;
+use crate::backend::BackendKind;
+use crate::balloon::BalloonConfig;
+use crate::boot_report::BootDiagnosticsConfig;
+use crate::breaker::BreakerConfig;
+use crate::cache::CacheConfig;
+use crate::clock::ClockSyncConfig;
+use crate::console::ConsoleCaptureConfig;
+use crate::cors::CorsOrigins;
+use crate::debug_events::DebugEventsConfig;
+use crate::fd_limit::FdLimitConfig;
+use crate::guest_client::GuestTransportConfig;
+use crate::guest_network::{self, GuestNetworkConfig};
+use crate::idempotency::IdempotencyConfig;
+use crate::inputs::InputsConfig;
+use crate::integrity::IntegrityConfig;
+use crate::jailer::JailerConfig;
+use crate::listen::{ListenAddr, ListenConfig, SocketOwner};
+use crate::machine::{self, MachineOptions};
+use crate::port_forward::ExposeConfig;
+use crate::probe::HealthProbeConfig;
+use crate::process_usage::ProcessUsageConfig;
+use crate::quota::QuotaConfig;
+use crate::rate_limit::{RateLimit, parse_rate_limit};
+use crate::readiness::ReadinessConfig;
+use crate::runtime_gc::RuntimeGcConfig;
+use crate::sandbox::SandboxConfig;
+use crate::screening::ScreeningConfig;
+use crate::selftest::SelfTestConfig;
+use crate::shaping::ShapingConfig;
+use crate::sub_pool::{self, SubPoolConfig};
+use crate::telemetry::{MetricsConfig, MetricsExporter};
+use crate::warmup::WarmupConfig;
+use crate::webhook::WebhookConfig;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Error raised while loading configuration
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    /// A referenced configuration file could not be read
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// A configuration value is present but unusable
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Default cap on request body size in bytes
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Default cap on submitted code length
+pub const DEFAULT_MAX_CODE_LENGTH: usize = 10_000;
+
+/// Default cap on each output stream of a persisted job result
+pub const DEFAULT_DB_MAX_OUTPUT_BYTES: usize = 256 * 1024;
+
+/// Default port of the gRPC service
+pub const DEFAULT_GRPC_PORT: u16 = 50051;
+
+/// Default deadline of a `POST /execute` request, a margin above the runner's own timeouts
+pub const DEFAULT_EXECUTE_DEADLINE: std::time::Duration =
+    std::time::Duration::from_secs(crate::runner::EXECUTION_BUDGET.as_secs() + 30);
+
+/// Default timeout of a `POST /execute/quick` request
+pub const DEFAULT_QUICK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// HTTP server configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// API keys accepted by the auth middleware; empty disables authentication
+    pub api_keys: Vec<String>,
+    /// Default per-client limit on `/execute` at boot; `None` disables rate limiting. Like
+    /// the other tunables of `reload::Tunables`, a reload can change it.
+    pub rate_limit: Option<RateLimit>,
+    /// Per-client limits keyed by API key identifier (see `ApiKeyId`), at boot
+    pub rate_limit_overrides: HashMap<String, RateLimit>,
+    /// `KEY=VALUE` file of tunables a reload reads over the environment
+    pub tunables_file: Option<PathBuf>,
+    /// Execution quotas per API key identifier; empty leaves every tenant unlimited
+    pub quotas: QuotaConfig,
+    /// Maximum request body size accepted by the server, after decompression
+    pub max_body_bytes: usize,
+    /// Compress responses with gzip or brotli when the client's `Accept-Encoding` allows it
+    pub response_compression: bool,
+    /// Accept request bodies sent with `Content-Encoding: gzip`
+    pub request_decompression: bool,
+    /// Maximum length of submitted code
+    pub max_code_length: usize,
+    /// Static screening of submitted code; disabled by default
+    pub screening: ScreeningConfig,
+    /// Origins allowed by CORS; `None` emits no CORS headers
+    pub cors_origins: Option<CorsOrigins>,
+    /// Result cache for repeated identical executions
+    pub cache: CacheConfig,
+    /// Responses kept for replay to retries carrying the same `Idempotency-Key`
+    pub idempotency: IdempotencyConfig,
+    /// Thresholds of the readiness score below which `/ready` answers `503`
+    pub readiness: ReadinessConfig,
+    /// Canary run once the pool is warm, before `/ready` answers `200`
+    pub selftest: SelfTestConfig,
+    /// Whether guests may reach the network, e.g. to install `requirements`
+    pub allow_network: bool,
+    /// Accept requirements that are URLs or local paths rather than package names
+    pub allow_unsafe_requirements: bool,
+    /// Port of the gRPC `Executor` service, served when built with the `grpc` feature
+    pub grpc_port: u16,
+    /// Serve a Swagger UI for the OpenAPI document at `/docs`
+    pub swagger_ui: bool,
+    /// How long finished jobs stay queryable at `/jobs/{id}`
+    pub job_ttl: std::time::Duration,
+    /// Job completion callbacks
+    pub webhook: WebhookConfig,
+    /// SQLite database keeping jobs and execution history across restarts; `None` keeps them
+    /// in memory
+    pub db_path: Option<PathBuf>,
+    /// Cap on each output stream of a result stored in the database
+    pub db_max_output_bytes: usize,
+    /// Storage of output streams too large to return inline
+    pub artifacts: ArtifactConfig,
+    /// Redis server holding the job queue and results shared by several instances; `None`
+    /// keeps jobs local to this instance
+    pub redis_url: Option<String>,
+    /// Jobs this instance runs concurrently; 0 only accepts jobs for other instances
+    pub job_workers: usize,
+    /// How long a claimed job may go unfinished before another worker takes it over
+    pub job_visibility_timeout: std::time::Duration,
+    /// Where metrics are exported (`FC_METRICS_EXPORTER`)
+    pub metrics: MetricsConfig,
+    /// Longest a `POST /execute` request waits for its result before answering `504`
+    pub execute_deadline: std::time::Duration,
+    /// Longest a `POST /execute/quick` request runs, waiting for a permit included
+    pub quick_timeout: std::time::Duration,
+    /// Append-only JSON lines log of `/execute` requests
+    pub audit: AuditConfig,
+    /// Expected hashes of the guest kernel and rootfs, checked at startup
+    pub integrity: IntegrityConfig,
+    /// Transport and address of the HTTP API
+    pub listen: ListenConfig,
+    /// Who may ask for an execution's event log with `debug`; disabled by default
+    pub debug_events: DebugEventsConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_keys: Vec::new(),
+            rate_limit: None,
+            rate_limit_overrides: HashMap::new(),
+            tunables_file: None,
+            quotas: QuotaConfig::default(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            response_compression: true,
+            request_decompression: true,
+            max_code_length: DEFAULT_MAX_CODE_LENGTH,
+            screening: ScreeningConfig::default(),
+            cors_origins: None,
+            cache: CacheConfig::default(),
+            idempotency: IdempotencyConfig::default(),
+            readiness: ReadinessConfig::default(),
+            selftest: SelfTestConfig::default(),
+            allow_network: false,
+            allow_unsafe_requirements: false,
+            grpc_port: DEFAULT_GRPC_PORT,
+            swagger_ui: false,
+            job_ttl: crate::jobs::DEFAULT_JOB_TTL,
+            webhook: WebhookConfig::default(),
+            db_path: None,
+            db_max_output_bytes: DEFAULT_DB_MAX_OUTPUT_BYTES,
+            artifacts: ArtifactConfig::default(),
+            redis_url: None,
+            job_workers: crate::jobs::DEFAULT_JOB_WORKERS,
+            job_visibility_timeout: crate::jobs::DEFAULT_JOB_VISIBILITY_TIMEOUT,
+            metrics: MetricsConfig::default(),
+            execute_deadline: DEFAULT_EXECUTE_DEADLINE,
+            quick_timeout: DEFAULT_QUICK_TIMEOUT,
+            audit: AuditConfig::default(),
+            integrity: IntegrityConfig::default(),
+            listen: ListenConfig::default(),
+            debug_events: DebugEventsConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the server configuration from `FC_*` environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let mut api_keys = std::env::var("FC_API_KEYS")
+            .map(|keys| parse_key_list(&keys, ','))
+            .unwrap_or_default();
+        if let Ok(path) = std::env::var("FC_API_KEYS_FILE") {
+            let contents = std::fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            api_keys.extend(parse_key_list(&contents, '\n'));
+        }
+        let rate_limit = match std::env::var("FC_RATE_LIMIT") {
+            Ok(raw) => Some(parse_rate_limit(&raw).ok_or_else(|| {
+                ConfigError::Invalid(format!("FC_RATE_LIMIT must be RATE[:BURST], got {raw:?}"))
+            })?),
+            Err(_) => None,
+        };
+        let rate_limit_overrides = match std::env::var("FC_RATE_LIMIT_OVERRIDES") {
+            Ok(raw) => parse_rate_limit_overrides(&raw)?,
+            Err(_) => HashMap::new(),
+        };
+
+        let cors_origins = match std::env::var("FC_CORS_ORIGINS") {
+            Ok(raw) => Some(CorsOrigins::parse(&raw).ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "FC_CORS_ORIGINS must be \"any\" or a list of origins, got {raw:?}"
+                ))
+            })?),
+            Err(_) => None,
+        };
+
+        let db_path = std::env::var_os("FC_DB_PATH").map(PathBuf::from);
+        if db_path.is_some() && !cfg!(feature = "persistence") {
+            return Err(ConfigError::Invalid(
+                "FC_DB_PATH requires building with the persistence feature".to_string(),
+            ));
+        }
+
+        let redis_url = std::env::var("FC_REDIS_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+        if redis_url.is_some() {
+            if !cfg!(feature = "distributed") {
+                return Err(ConfigError::Invalid(
+                    "FC_REDIS_URL requires building with the distributed feature".to_string(),
+                ));
+            }
+            if db_path.is_some() {
+                return Err(ConfigError::Invalid(
+                    "Set only one of FC_DB_PATH and FC_REDIS_URL".to_string(),
+                ));
+            }
+        }
+
+        let default = Self::default();
+        Ok(Self {
+            api_keys,
+            rate_limit,
+            rate_limit_overrides,
+            tunables_file: std::env::var_os("FC_TUNABLES_FILE").map(PathBuf::from),
+            quotas: quotas_from_env()?,
+            max_body_bytes: env_parse("FC_MAX_BODY_BYTES").unwrap_or(default.max_body_bytes),
+            response_compression: env_flag("FC_RESPONSE_COMPRESSION")
+                .unwrap_or(default.response_compression),
+            request_decompression: env_flag("FC_REQUEST_DECOMPRESSION")
+                .unwrap_or(default.request_decompression),
+            max_code_length: env_parse("FC_MAX_CODE_LENGTH").unwrap_or(default.max_code_length),
+            screening: screening_from_env()?,
+            cors_origins,
+            cache: CacheConfig {
+                enabled: env_flag("FC_CACHE").unwrap_or(default.cache.enabled),
+                capacity: env_parse("FC_CACHE_CAPACITY").unwrap_or(default.cache.capacity),
+                ttl: env_parse("FC_CACHE_TTL_SECS")
+                    .map(std::time::Duration::from_secs)
+                    .unwrap_or(default.cache.ttl),
+            },
+            idempotency: IdempotencyConfig {
+                capacity: env_parse("FC_IDEMPOTENCY_CAPACITY")
+                    .unwrap_or(default.idempotency.capacity),
+                ttl: env_parse("FC_IDEMPOTENCY_TTL_SECS")
+                    .map(std::time::Duration::from_secs)
+                    .unwrap_or(default.idempotency.ttl),
+            },
+            readiness: ReadinessConfig {
+                min_score: env_parse("FC_READY_MIN_SCORE").unwrap_or(default.readiness.min_score),
+                require_capacity: env_flag("FC_READY_REQUIRE_CAPACITY")
+                    .unwrap_or(default.readiness.require_capacity),
+            },
+            // On by default where code really runs, off for the mock and local backends
+            selftest: SelfTestConfig {
+                enabled: env_flag("FC_SELFTEST")
+                    .unwrap_or_else(|| runner_config().backend.is_firecracker()),
+                timeout: env_parse("FC_SELFTEST_TIMEOUT_MS")
+                    .map(std::time::Duration::from_millis)
+                    .unwrap_or(default.selftest.timeout),
+                retry_backoff: env_parse("FC_SELFTEST_RETRY_MS")
+                    .map(std::time::Duration::from_millis)
+                    .unwrap_or(default.selftest.retry_backoff),
+                max_retry_backoff: default.selftest.max_retry_backoff,
+            },
+            allow_network: env_flag("FC_ALLOW_NETWORK").unwrap_or(default.allow_network),
+            allow_unsafe_requirements: env_flag("FC_ALLOW_UNSAFE_REQUIREMENTS")
+                .unwrap_or(default.allow_unsafe_requirements),
+            grpc_port: env_parse("FC_GRPC_PORT").unwrap_or(default.grpc_port),
+            swagger_ui: env_flag("FC_SWAGGER_UI").unwrap_or(default.swagger_ui),
+            job_ttl: env_parse("FC_JOB_TTL_SECS")
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(default.job_ttl),
+            webhook: WebhookConfig {
+                allowed_hosts: std::env::var("FC_CALLBACK_ALLOWED_HOSTS")
+                    .map(|hosts| parse_key_list(&hosts, ','))
+                    .unwrap_or_default(),
+                secret: std::env::var("FC_CALLBACK_SECRET")
+                    .ok()
+                    .filter(|secret| !secret.is_empty()),
+                retry_backoff: default.webhook.retry_backoff,
+            },
+            db_path,
+            db_max_output_bytes: env_parse("FC_DB_MAX_OUTPUT_BYTES")
+                .unwrap_or(default.db_max_output_bytes),
+            artifacts: ArtifactConfig {
+                backend: artifact_backend_from_env()?,
+                threshold_bytes: env_parse("FC_ARTIFACT_THRESHOLD_BYTES")
+                    .unwrap_or(default.artifacts.threshold_bytes),
+            },
+            redis_url,
+            job_workers: env_parse("FC_JOB_WORKERS").unwrap_or(default.job_workers),
+            job_visibility_timeout: env_parse("FC_JOB_VISIBILITY_SECS")
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(default.job_visibility_timeout),
+            metrics: MetricsConfig {
+                exporter: metrics_exporter_from_env()?,
+                push_interval: env_parse("FC_METRICS_PUSH_INTERVAL_SECS")
+                    .map(std::time::Duration::from_secs)
+                    .unwrap_or(default.metrics.push_interval),
+            },
+            execute_deadline: env_parse("FC_EXECUTE_DEADLINE_SECS")
+                .map(std::time::Duration::from_secs)
+                .unwrap_or(default.execute_deadline),
+            quick_timeout: env_parse("FC_QUICK_TIMEOUT_MS")
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(default.quick_timeout),
+            audit: AuditConfig {
+                path: std::env::var_os("FC_AUDIT_LOG").map(PathBuf::from),
+                max_bytes: env_parse("FC_AUDIT_LOG_MAX_BYTES").unwrap_or(default.audit.max_bytes),
+                max_files: env_parse("FC_AUDIT_LOG_MAX_FILES").unwrap_or(default.audit.max_files),
+            },
+            integrity: IntegrityConfig {
+                kernel_sha256: std::env::var("FC_KERNEL_SHA256").ok(),
+                rootfs_sha256: std::env::var("FC_ROOTFS_SHA256").ok(),
+                check_interval: env_parse("FC_INTEGRITY_CHECK_INTERVAL_SECS")
+                    .filter(|secs| *secs > 0)
+                    .map(std::time::Duration::from_secs),
+            },
+            listen: listen_from_env()?,
+            debug_events: DebugEventsConfig {
+                enabled: env_flag("FC_DEBUG_EVENTS").unwrap_or(default.debug_events.enabled),
+                denied_key_ids: std::env::var("FC_DEBUG_EVENTS_DENY_KEYS")
+                    .map(|keys| parse_key_list(&keys, ','))
+                    .unwrap_or_default(),
+            },
+        })
+    }
+}
+
+/// `FC_LISTEN` with the socket and TLS settings of its transport
+fn listen_from_env() -> Result<ListenConfig, ConfigError> {
+    let invalid = |e: crate::listen::ListenError| ConfigError::Invalid(e.to_string());
+    let addr: ListenAddr = match std::env::var("FC_LISTEN") {
+        Ok(raw) => raw.parse().map_err(invalid)?,
+        Err(_) => ListenConfig::default().addr,
+    };
+    let socket_mode = match std::env::var("FC_LISTEN_SOCKET_MODE") {
+        Ok(raw) => Some(crate::listen::parse_mode(&raw).ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "FC_LISTEN_SOCKET_MODE must be an octal mode such as 660, got {raw:?}"
+            ))
+        })?),
+        Err(_) => None,
+    };
+    let socket_owner = match std::env::var("FC_LISTEN_SOCKET_OWNER") {
+        Ok(raw) => Some(SocketOwner::parse(&raw).map_err(invalid)?),
+        Err(_) => None,
+    };
+    let tls_cert = std::env::var_os("FC_TLS_CERT").map(PathBuf::from);
+    let tls_key = std::env::var_os("FC_TLS_KEY").map(PathBuf::from);
+    if matches!(addr, ListenAddr::Tls(_)) && (tls_cert.is_none() || tls_key.is_none()) {
+        return Err(ConfigError::Invalid(format!(
+            "FC_LISTEN={addr} needs FC_TLS_CERT and FC_TLS_KEY"
+        )));
+    }
+    Ok(ListenConfig {
+        addr,
+        socket_mode,
+        socket_owner,
+        tls_cert,
+        tls_key,
+    })
+}
+
+/// `FC_METRICS_EXPORTER` (`prometheus`, `statsd` or `otlp`) with its exporter's settings
+fn metrics_exporter_from_env() -> Result<MetricsExporter, ConfigError> {
+    match std::env::var("FC_METRICS_EXPORTER").as_deref() {
+        Err(_) | Ok("") | Ok("prometheus") => Ok(MetricsExporter::Prometheus),
+        Ok("statsd") => Ok(MetricsExporter::Statsd {
+            addr: std::env::var("FC_STATSD_ADDR")
+                .unwrap_or_else(|_| crate::statsd::DEFAULT_STATSD_ADDR.to_string()),
+            prefix: std::env::var("FC_STATSD_PREFIX")
+                .ok()
+                .filter(|prefix| !prefix.is_empty()),
+        }),
+        Ok("otlp") if !cfg!(feature = "otlp") => Err(ConfigError::Invalid(
+            "FC_METRICS_EXPORTER=otlp requires building with the otlp feature".to_string(),
+        )),
+        Ok("otlp") => Ok(MetricsExporter::Otlp {
+            endpoint: std::env::var("FC_OTLP_ENDPOINT")
+                .unwrap_or_else(|_| crate::telemetry::DEFAULT_OTLP_ENDPOINT.to_string()),
+        }),
+        Ok(other) => Err(ConfigError::Invalid(format!(
+            "FC_METRICS_EXPORTER must be prometheus, statsd or otlp, got {other:?}"
+        ))),
+    }
+}
+
+/// `FC_ARTIFACT_DIR` or `FC_ARTIFACT_S3_BUCKET` (with `FC_ARTIFACT_S3_PREFIX` and
+/// `FC_ARTIFACT_S3_ENDPOINT`); at most one may be set
+fn artifact_backend_from_env() -> Result<Option<ArtifactBackend>, ConfigError> {
+    let dir = std::env::var_os("FC_ARTIFACT_DIR").map(PathBuf::from);
+    let bucket = std::env::var("FC_ARTIFACT_S3_BUCKET")
+        .ok()
+        .filter(|bucket| !bucket.is_empty());
+    match (dir, bucket) {
+        (Some(_), Some(_)) => Err(ConfigError::Invalid(
+            "Set only one of FC_ARTIFACT_DIR and FC_ARTIFACT_S3_BUCKET".to_string(),
+        )),
+        (Some(dir), None) => Ok(Some(ArtifactBackend::Local(dir))),
+        (None, Some(_)) if !cfg!(feature = "s3") => Err(ConfigError::Invalid(
+            "FC_ARTIFACT_S3_BUCKET requires building with the s3 feature".to_string(),
+        )),
+        (None, Some(bucket)) => Ok(Some(ArtifactBackend::S3 {
+            bucket,
+            prefix: std::env::var("FC_ARTIFACT_S3_PREFIX").unwrap_or_default(),
+            endpoint: std::env::var("FC_ARTIFACT_S3_ENDPOINT").ok(),
+        })),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Load screening rules from `FC_SCREENING_RULES_FILE`, with `FC_SCREENING*` overrides
+fn screening_from_env() -> Result<ScreeningConfig, ConfigError> {
+    let mut screening = match std::env::var("FC_SCREENING_RULES_FILE") {
+        Ok(path) => {
+            let contents = std::fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            serde_json::from_str(&contents)
+                .map_err(|e| ConfigError::Invalid(format!("{path}: {e}")))?
+        }
+        Err(_) => ScreeningConfig::default(),
+    };
+    if let Some(enabled) = env_flag("FC_SCREENING") {
+        screening.enabled = enabled;
+    }
+    if let Some(defaults) = env_flag("FC_SCREENING_DEFAULTS") {
+        screening.use_default_rules = defaults;
+    }
+    if let Ok(keys) = std::env::var("FC_SCREENING_BYPASS_KEYS") {
+        screening.bypass_key_ids.extend(parse_key_list(&keys, ','));
+    }
+    // Surface invalid patterns at startup rather than on the first request
+    crate::screening::Screener::new(&screening)?;
+    Ok(screening)
+}
+
+/// Load tenant quotas from the JSON file at `FC_QUOTAS_FILE`
+fn quotas_from_env() -> Result<QuotaConfig, ConfigError> {
+    let Ok(path) = std::env::var("FC_QUOTAS_FILE") else {
+        return Ok(QuotaConfig::default());
+    };
+    let contents = std::fs::read_to_string(&path).map_err(|source| ConfigError::Io {
+        path: path.clone(),
+        source,
+    })?;
+    serde_json::from_str(&contents).map_err(|e| ConfigError::Invalid(format!("{path}: {e}")))
+}
+
+/// Parse `KEY_ID=RATE[:BURST]` pairs separated by commas
+pub(crate) fn parse_rate_limit_overrides(
+    raw: &str,
+) -> Result<HashMap<String, RateLimit>, ConfigError> {
+    parse_key_list(raw, ',')
+        .into_iter()
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .and_then(|(key, limit)| Some((key.trim().to_string(), parse_rate_limit(limit)?)))
+                .ok_or_else(|| {
+                    ConfigError::Invalid(format!(
+                        "FC_RATE_LIMIT_OVERRIDES entries must be KEY_ID=RATE[:BURST], got {entry:?}"
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// Split a list of keys, dropping blanks and `#` comments
+fn parse_key_list(raw: &str, separator: char) -> Vec<String> {
+    raw.split(separator)
+        .map(str::trim)
+        .filter(|key| !key.is_empty() && !key.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Runner configuration shared by every VM the runner creates
+#[derive(Debug, Clone)]
+pub struct RunnerConfig {
+    /// Directory holding per-VM sockets, logs and metrics files
+    pub runtime_dir: PathBuf,
+    /// Level passed to Firecracker's `PUT /logger` (Error, Warning, Info, Debug, Trace, Off)
+    pub firecracker_log_level: String,
+    /// Whether Firecracker should be configured with a per-VM metrics file
+    pub firecracker_metrics: bool,
+    /// Number of executions kept in the in-memory history
+    pub history_capacity: usize,
+    /// Run Firecracker under the jailer; `None` execs it directly (the development default)
+    pub jailer: Option<JailerConfig>,
+    /// Memory of one VM in MiB; keep in sync with `fixtures/machine.json`
+    pub vm_memory_mib: u64,
+    /// Maximum number of live VMs; `None` is unlimited
+    pub max_vms: Option<usize>,
+    /// Maximum memory committed to live VMs in MiB; `None` is unlimited
+    pub max_vm_memory_mib: Option<u64>,
+    /// Host memory that must stay available after starting another VM, in MiB
+    pub min_host_available_mib: u64,
+    /// Executions run at once, the rest waiting by priority; `None` is unlimited
+    pub max_concurrent_executions: Option<usize>,
+    /// Executions that may wait for a permit before the least urgent are refused with `429`
+    pub max_queued_executions: usize,
+    /// Longest an execution waits for a permit before it fails with `pool_exhausted`; `None`
+    /// waits indefinitely
+    pub vm_wait_budget: Option<std::time::Duration>,
+    /// Cap on each of stdout and stderr returned from a VM, in bytes
+    pub max_output_bytes: usize,
+    /// Warm VMs kept in the pool when it isn't autoscaled
+    pub pool_size: usize,
+    /// Warm VMs kept per image next to the default pool, keyed by image name
+    pub sub_pools: BTreeMap<String, SubPoolConfig>,
+    /// Read-only dependency images selectable per request, keyed by profile name
+    pub deps_profiles: BTreeMap<String, PathBuf>,
+    /// Read-only ext4 or squashfs images attached to every VM, keyed by the name the guest
+    /// mounts them under
+    pub reference_data: BTreeMap<String, PathBuf>,
+    /// Directory of the rootfs images selectable per request, as `<name>.ext4`
+    pub image_dir: PathBuf,
+    /// CPU template, SMT and dirty-page tracking sent in `PUT /machine-config`
+    pub machine: MachineOptions,
+    /// Machine settings overriding `machine` for VMs with a given deps profile
+    pub machine_overrides: BTreeMap<String, MachineOptions>,
+    /// Attach a virtio-rng entropy device to guests (Firecracker 1.4+)
+    pub entropy_device: bool,
+    /// Memory ballooning of idle pooled VMs
+    pub balloon: BalloonConfig,
+    /// Setting guest clocks to the host's after long idle spells
+    pub clock_sync: ClockSyncConfig,
+    /// Code run on new VMs before they are pooled or serve a request
+    pub warmup: WarmupConfig,
+    /// Demand-driven sizing of the warm pool
+    pub autoscale: AutoscaleConfig,
+    /// Background health checks of pooled VMs
+    pub health_probe: HealthProbeConfig,
+    /// Age at which a pooled VM is retired instead of reused; `None` keeps VMs indefinitely
+    pub vm_max_age: Option<std::time::Duration>,
+    /// Executions after which a VM is retired instead of reused; `None` is unlimited
+    pub vm_max_reuse: Option<u64>,
+    /// Sampling Firecracker processes' host memory, CPU and fds, and the RSS they may reach
+    pub process_usage: ProcessUsageConfig,
+    /// Open file limit raised to at startup, and the headroom the pool keeps under it
+    pub fd_limit: FdLimitConfig,
+    /// DNS and proxy settings passed to guests when they may reach the network
+    pub guest_network: GuestNetworkConfig,
+    /// How the host reaches the guest agent, per image
+    pub guest_transport: GuestTransportConfig,
+    /// `tc` shaping of VM TAP devices, per image or per request for trusted keys
+    pub shaping: ShapingConfig,
+    /// Forwarding host ports to guest ports asked for with `expose_port`
+    pub expose: ExposeConfig,
+    /// Keeping fresh VMs as sandboxes asked for with `keep_alive_seconds`
+    pub sandbox: SandboxConfig,
+    /// Where the guest may download `inputs` from, and how much
+    pub inputs: InputsConfig,
+    /// When repeated boot failures stop cold starts
+    pub vm_creation_breaker: BreakerConfig,
+    /// Keeping the logs of VMs that fail to boot
+    pub boot_diagnostics: BootDiagnosticsConfig,
+    /// Returning the console output of executions that ask for it
+    pub console_capture: ConsoleCaptureConfig,
+    /// Sweeping runtime files left behind by VMs that are gone
+    pub runtime_gc: RuntimeGcConfig,
+    /// Boot VMs from a `--config-file` instead of configuring them over the API socket
+    pub boot_from_config_file: bool,
+    /// Guest kernel and rootfs per host architecture
+    pub artifacts: ArchArtifacts,
+    /// What runs the VMs: Firecracker, host processes (`local-backend` feature) or the mock
+    pub backend: BackendKind,
+    /// Time the mock backend takes to answer each execution
+    pub mock_latency: std::time::Duration,
+    /// Time the mock backend takes to boot each VM
+    pub mock_boot_latency: std::time::Duration,
+    /// Directory each VM's Firecracker API and agent health exchanges are recorded to, as
+    /// `api-<vm_id>.jsonl` sessions the replay server can serve
+    pub api_record_dir: Option<PathBuf>,
+    /// Faults injected into VMs; `None` runs them undisturbed
+    #[cfg(feature = "chaos")]
+    pub faults: Option<Arc<crate::chaos::FaultInjector>>,
+}
+
+impl Default for RunnerConfig {
+    fn default() -> Self {
+        Self {
+            runtime_dir: PathBuf::from("/tmp"),
+            firecracker_log_level: "Warning".to_string(),
+            firecracker_metrics: true,
+            history_capacity: 200,
+            jailer: None,
+            vm_memory_mib: 128,
+            max_vms: None,
+            max_vm_memory_mib: None,
+            min_host_available_mib: 0,
+            max_concurrent_executions: None,
+            max_queued_executions: crate::dispatch::DEFAULT_MAX_QUEUED_EXECUTIONS,
+            vm_wait_budget: Some(crate::dispatch::DEFAULT_VM_WAIT_BUDGET),
+            max_output_bytes: crate::output::DEFAULT_MAX_OUTPUT_BYTES,
+            pool_size: crate::executor::VM_PREWARM_COUNT,
+            sub_pools: BTreeMap::new(),
+            deps_profiles: BTreeMap::new(),
+            reference_data: BTreeMap::new(),
+            image_dir: PathBuf::from(crate::images::DEFAULT_IMAGE_DIR),
+            machine: MachineOptions::default(),
+            machine_overrides: BTreeMap::new(),
+            entropy_device: true,
+            balloon: BalloonConfig::default(),
+            clock_sync: ClockSyncConfig::default(),
+            warmup: WarmupConfig::default(),
+            autoscale: AutoscaleConfig::default(),
+            health_probe: HealthProbeConfig::default(),
+            vm_max_age: Some(crate::executor::DEFAULT_VM_MAX_AGE),
+            vm_max_reuse: None,
+            process_usage: ProcessUsageConfig::default(),
+            fd_limit: FdLimitConfig::default(),
+            guest_network: GuestNetworkConfig::default(),
+            guest_transport: GuestTransportConfig::default(),
+            shaping: ShapingConfig::default(),
+            expose: ExposeConfig::default(),
+            sandbox: SandboxConfig::default(),
+            inputs: InputsConfig::default(),
+            vm_creation_breaker: BreakerConfig::default(),
+            boot_diagnostics: BootDiagnosticsConfig::default(),
+            console_capture: ConsoleCaptureConfig::default(),
+            runtime_gc: RuntimeGcConfig::default(),
+            boot_from_config_file: false,
+            artifacts: ArchArtifacts::default(),
+            backend: BackendKind::default(),
+            mock_latency: std::time::Duration::ZERO,
+            mock_boot_latency: std::time::Duration::ZERO,
+            api_record_dir: None,
+            #[cfg(feature = "chaos")]
+            faults: None,
+        }
+    }
+}
+
+impl RunnerConfig {
+    /// Load the runner configuration from `FC_*` environment variables
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            runtime_dir: std::env::var("FC_RUNTIME_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(default.runtime_dir),
+            firecracker_log_level: std::env::var("FC_FIRECRACKER_LOG_LEVEL")
+                .unwrap_or(default.firecracker_log_level),
+            firecracker_metrics: env_flag("FC_FIRECRACKER_METRICS")
+                .unwrap_or(default.firecracker_metrics),
+            history_capacity: env_parse("FC_HISTORY_CAPACITY").unwrap_or(default.history_capacity),
+            jailer: env_flag("FC_JAILER").unwrap_or(false).then(jailer_from_env),
+            vm_memory_mib: env_parse("FC_VM_MEMORY_MIB").unwrap_or(default.vm_memory_mib),
+            max_vms: env_parse("FC_MAX_VMS").or(default.max_vms),
+            max_vm_memory_mib: env_parse("FC_MAX_VM_MEMORY_MIB").or(default.max_vm_memory_mib),
+            min_host_available_mib: env_parse("FC_MIN_HOST_AVAILABLE_MIB")
+                .unwrap_or(default.min_host_available_mib),
+            max_concurrent_executions: env_parse("FC_MAX_CONCURRENT_EXECUTIONS")
+                .or(default.max_concurrent_executions),
+            max_queued_executions: env_parse("FC_MAX_QUEUED_EXECUTIONS")
+                .unwrap_or(default.max_queued_executions),
+            vm_wait_budget: match env_parse::<u64>("FC_VM_WAIT_BUDGET_MS") {
+                Some(0) => None,
+                Some(ms) => Some(std::time::Duration::from_millis(ms)),
+                None => default.vm_wait_budget,
+            },
+            max_output_bytes: env_parse("FC_MAX_OUTPUT_BYTES").unwrap_or(default.max_output_bytes),
+            pool_size: env_parse("FC_POOL_SIZE").unwrap_or(default.pool_size),
+            sub_pools: std::env::var("FC_SUB_POOLS")
+                .ok()
+                .and_then(|raw| {
+                    sub_pool::parse_sub_pools(&raw)
+                        .inspect_err(|e| tracing::warn!("Ignoring FC_SUB_POOLS: {}", e))
+                        .ok()
+                })
+                .unwrap_or(default.sub_pools),
+            deps_profiles: std::env::var("FC_DEPS_PROFILES")
+                .map(|raw| crate::deps::parse_profiles(&raw))
+                .unwrap_or(default.deps_profiles),
+            reference_data: std::env::var("FC_REFERENCE_DATA")
+                .map(|raw| crate::reference_data::parse(&raw))
+                .unwrap_or(default.reference_data),
+            image_dir: std::env::var("FC_IMAGE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(default.image_dir),
+            machine: machine_from_env(),
+            machine_overrides: std::env::var("FC_MACHINE_OVERRIDES")
+                .ok()
+                .and_then(|raw| {
+                    machine::parse_profile_options(&raw)
+                        .inspect_err(|e| tracing::warn!("Ignoring FC_MACHINE_OVERRIDES: {}", e))
+                        .ok()
+                })
+                .unwrap_or(default.machine_overrides),
+            entropy_device: env_flag("FC_ENTROPY_DEVICE").unwrap_or(default.entropy_device),
+            balloon: balloon_from_env(),
+            clock_sync: clock_sync_from_env(),
+            warmup: warmup_from_env(),
+            autoscale: autoscale_from_env(),
+            health_probe: health_probe_from_env(),
+            // 0 keeps VMs however old they get
+            vm_max_age: match env_parse("FC_VM_MAX_AGE_SECS") {
+                Some(0) => None,
+                Some(secs) => Some(std::time::Duration::from_secs(secs)),
+                None => default.vm_max_age,
+            },
+            vm_max_reuse: env_parse("FC_VM_MAX_REUSE").or(default.vm_max_reuse),
+            process_usage: process_usage_from_env(),
+            fd_limit: fd_limit_from_env(),
+            guest_network: guest_network_from_env(),
+            guest_transport: guest_transport_from_env(),
+            shaping: shaping_from_env(),
+            expose: expose_from_env(),
+            sandbox: sandbox_from_env(),
+            inputs: inputs_from_env(),
+            vm_creation_breaker: breaker_from_env(),
+            boot_diagnostics: boot_diagnostics_from_env(),
+            console_capture: console_capture_from_env(),
+            runtime_gc: runtime_gc_from_env(),
+            boot_from_config_file: env_flag("FC_BOOT_CONFIG_FILE")
+                .unwrap_or(default.boot_from_config_file),
+            artifacts: ArchArtifacts::from_env(),
+            backend: std::env::var("FC_BACKEND")
+                .ok()
+                .and_then(|raw| {
+                    raw.parse()
+                        .inspect_err(|e| tracing::warn!("Ignoring FC_BACKEND: {}", e))
+                        .ok()
+                })
+                .unwrap_or(default.backend),
+            mock_latency: env_parse("FC_MOCK_LATENCY_MS")
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(default.mock_latency),
+            mock_boot_latency: env_parse("FC_MOCK_BOOT_LATENCY_MS")
+                .map(std::time::Duration::from_millis)
+                .unwrap_or(default.mock_boot_latency),
+            api_record_dir: std::env::var_os("FC_API_RECORD_DIR").map(PathBuf::from),
+            #[cfg(feature = "chaos")]
+            faults: crate::chaos::FaultInjector::from_env()
+                .inspect_err(|e| tracing::warn!("Ignoring fault injection settings: {}", e))
+                .ok()
+                .flatten()
+                .map(Arc::new),
+        }
+    }
+
+    /// Machine settings for a VM, with the deps profile's overrides applied
+    pub fn machine_options(&self, deps_profile: Option<&str>) -> MachineOptions {
+        match deps_profile.and_then(|profile| self.machine_overrides.get(profile)) {
+            Some(overrides) => self.machine.overlay(overrides),
+            None => self.machine.clone(),
+        }
+    }
+
+    /// Rootfs of image `name`, if it is in the image directory. Images are looked up per
+    /// request, so ones imported while the server runs are usable right away.
+    pub fn image_path(&self, name: &str) -> Option<PathBuf> {
+        let path = crate::images::image_path(&self.image_dir, name);
+        (crate::images::is_valid_name(name) && path.is_file()).then_some(path)
+    }
+
+    /// Build the path of a runtime file inside the runtime directory
+    pub fn runtime_path(&self, file_name: &str) -> String {
+        self.runtime_dir
+            .join(file_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Machine settings from `FC_CPU_TEMPLATE`, `FC_SMT` and `FC_TRACK_DIRTY_PAGES`
+fn machine_from_env() -> MachineOptions {
+    MachineOptions {
+        cpu_template: std::env::var("FC_CPU_TEMPLATE").ok().and_then(|raw| {
+            machine::parse_cpu_template(raw.trim())
+                .inspect_err(|e| tracing::warn!("Ignoring FC_CPU_TEMPLATE: {}", e))
+                .ok()
+        }),
+        smt: env_flag("FC_SMT"),
+        track_dirty_pages: env_flag("FC_TRACK_DIRTY_PAGES"),
+    }
+}
+
+/// Balloon settings from `FC_BALLOON*` environment variables
+fn balloon_from_env() -> BalloonConfig {
+    let default = BalloonConfig::default();
+    BalloonConfig {
+        enabled: env_flag("FC_BALLOON").unwrap_or(default.enabled),
+        idle_target_mib: env_parse("FC_BALLOON_IDLE_MIB").unwrap_or(default.idle_target_mib),
+        stats_interval_secs: env_parse("FC_BALLOON_STATS_INTERVAL_SECS")
+            .unwrap_or(default.stats_interval_secs),
+    }
+}
+
+/// VM creation circuit breaker settings from `FC_BREAKER_*` environment variables
+fn breaker_from_env() -> BreakerConfig {
+    let default = BreakerConfig::default();
+    let secs = |name| env_parse(name).map(std::time::Duration::from_secs);
+    BreakerConfig {
+        failure_threshold: env_parse("FC_BREAKER_FAILURES").unwrap_or(default.failure_threshold),
+        window: secs("FC_BREAKER_WINDOW_SECS").unwrap_or(default.window),
+        open_for: secs("FC_BREAKER_OPEN_SECS").unwrap_or(default.open_for),
+    }
+}
+
+/// Guest clock settings from `FC_CLOCK_*` environment variables
+fn clock_sync_from_env() -> ClockSyncConfig {
+    let default = ClockSyncConfig::default();
+    ClockSyncConfig {
+        enabled: env_flag("FC_CLOCK_SYNC").unwrap_or(default.enabled),
+        resync_after_idle: env_parse("FC_CLOCK_RESYNC_IDLE_SECS")
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(default.resync_after_idle),
+        deadline: env_parse("FC_CLOCK_SYNC_DEADLINE_MS")
+            .map(std::time::Duration::from_millis)
+            .unwrap_or(default.deadline),
+        tolerance_ms: env_parse("FC_CLOCK_SYNC_TOLERANCE_MS").unwrap_or(default.tolerance_ms),
+    }
+}
+
+/// Warm-up settings from `FC_WARMUP*` environment variables
+fn warmup_from_env() -> WarmupConfig {
+    let default = WarmupConfig::default();
+    WarmupConfig {
+        code: std::env::var("FC_WARMUP_CODE")
+            .ok()
+            .filter(|code| !code.trim().is_empty())
+            .or(default.code),
+        profiles: std::env::var("FC_WARMUP_PROFILES")
+            .ok()
+            .and_then(|raw| {
+                crate::warmup::load_profiles(&raw)
+                    .inspect_err(|e| tracing::warn!("Ignoring FC_WARMUP_PROFILES: {}", e))
+                    .ok()
+            })
+            .unwrap_or(default.profiles),
+        timeout: env_parse("FC_WARMUP_TIMEOUT_MS")
+            .map(std::time::Duration::from_millis)
+            .unwrap_or(default.timeout),
+        fatal: env_flag("FC_WARMUP_FATAL").unwrap_or(default.fatal),
+    }
+}
+
+/// Firecracker process sampling settings from `FC_VM_USAGE_*` and `FC_VM_MAX_HOST_RSS_MIB`
+fn process_usage_from_env() -> ProcessUsageConfig {
+    let default = ProcessUsageConfig::default();
+    ProcessUsageConfig {
+        // 0 only samples VMs when asked about them
+        sample_interval: match env_parse("FC_VM_USAGE_SAMPLE_SECS") {
+            Some(0) => None,
+            Some(secs) => Some(std::time::Duration::from_secs(secs)),
+            None => default.sample_interval,
+        },
+        max_rss_mib: env_parse("FC_VM_MAX_HOST_RSS_MIB").or(default.max_rss_mib),
+    }
+}
+
+/// Open file limit settings from `FC_NOFILE_LIMIT` and `FC_MIN_FD_HEADROOM`
+fn fd_limit_from_env() -> FdLimitConfig {
+    let default = FdLimitConfig::default();
+    FdLimitConfig {
+        // 0 keeps the limit the server was started with
+        nofile: match env_parse("FC_NOFILE_LIMIT") {
+            Some(0) => None,
+            Some(limit) => Some(limit),
+            None => default.nofile,
+        },
+        min_headroom: env_parse("FC_MIN_FD_HEADROOM").unwrap_or(default.min_headroom),
+    }
+}
+
+/// Guest agent transport from `FC_GUEST_TRANSPORT` (`tcp` or `vsock`) and per-image overrides
+/// from `FC_GUEST_TRANSPORT_IMAGES`, e.g. `offline=vsock`
+fn guest_transport_from_env() -> GuestTransportConfig {
+    let default = GuestTransportConfig::default();
+    GuestTransportConfig {
+        default: std::env::var("FC_GUEST_TRANSPORT")
+            .ok()
+            .and_then(|raw| {
+                raw.parse()
+                    .inspect_err(|e| tracing::warn!("Ignoring FC_GUEST_TRANSPORT: {}", e))
+                    .ok()
+            })
+            .unwrap_or(default.default),
+        images: std::env::var("FC_GUEST_TRANSPORT_IMAGES")
+            .ok()
+            .and_then(|raw| {
+                GuestTransportConfig::parse_images(&raw)
+                    .inspect_err(|e| tracing::warn!("Ignoring FC_GUEST_TRANSPORT_IMAGES: {}", e))
+                    .ok()
+            })
+            .unwrap_or(default.images),
+    }
+}
+
+/// Guest DNS and proxy settings from `FC_ALLOW_NETWORK`, `FC_GUEST_DNS` and
+/// `FC_GUEST_*_PROXY`. Without `FC_GUEST_DNS`, guests use the host's reachable name servers.
+fn guest_network_from_env() -> GuestNetworkConfig {
+    let default = GuestNetworkConfig::default();
+    let dns = match std::env::var("FC_GUEST_DNS") {
+        Ok(raw) => {
+            let servers: Vec<String> = raw
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+            guest_network::validate_dns(&servers)
+                .inspect_err(|e| tracing::warn!("Ignoring FC_GUEST_DNS: {}", e))
+                .ok()
+                .map(|()| servers)
+        }
+        Err(_) => std::fs::read_to_string("/etc/resolv.conf")
+            .ok()
+            .map(|conf| guest_network::resolvers_from_resolv_conf(&conf))
+            .filter(|servers| !servers.is_empty()),
+    };
+    let proxy = |name: &str| {
+        std::env::var(name).ok().filter(|value| {
+            let safe = guest_network::is_boot_arg_safe(value);
+            if !safe {
+                tracing::warn!("Ignoring {}: not passable as a boot argument", name);
+            }
+            safe
+        })
+    };
+    GuestNetworkConfig {
+        enabled: env_flag("FC_ALLOW_NETWORK").unwrap_or(default.enabled),
+        dns: dns.unwrap_or(default.dns),
+        http_proxy: proxy("FC_GUEST_HTTP_PROXY"),
+        https_proxy: proxy("FC_GUEST_HTTPS_PROXY"),
+        no_proxy: proxy("FC_GUEST_NO_PROXY"),
+    }
+}
+
+/// TAP traffic shaping from the JSON file at `FC_TRAFFIC_SHAPING_FILE`, with `FC_SHAPING_FATAL`
+/// overriding its `fatal`. An unusable file shapes nothing.
+fn shaping_from_env() -> ShapingConfig {
+    let mut shaping = match std::env::var("FC_TRAFFIC_SHAPING_FILE") {
+        Ok(path) => std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                let shaping: ShapingConfig =
+                    serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+                shaping.validate().map(|()| shaping)
+            })
+            .inspect_err(|e| tracing::warn!("Ignoring FC_TRAFFIC_SHAPING_FILE {}: {}", path, e))
+            .unwrap_or_default(),
+        Err(_) => ShapingConfig::default(),
+    };
+    if let Some(fatal) = env_flag("FC_SHAPING_FATAL") {
+        shaping.fatal = fatal;
+    }
+    shaping
+}
+
+/// Guest port forwarding settings from `FC_MAX_EXPOSED_PORTS`, `FC_EXPOSE_SECS` and
+/// `FC_EXPOSE_BIND_ADDR`
+fn expose_from_env() -> ExposeConfig {
+    let default = ExposeConfig::default();
+    ExposeConfig {
+        max_exposed: env_parse("FC_MAX_EXPOSED_PORTS").unwrap_or(default.max_exposed),
+        ttl: env_parse("FC_EXPOSE_SECS")
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(default.ttl),
+        bind_addr: env_parse("FC_EXPOSE_BIND_ADDR").unwrap_or(default.bind_addr),
+    }
+}
+
+/// Sandbox settings from `FC_MAX_SANDBOXES` and `FC_SANDBOX_MAX_KEEP_ALIVE_SECS`
+fn sandbox_from_env() -> SandboxConfig {
+    let default = SandboxConfig::default();
+    SandboxConfig {
+        max_sandboxes: env_parse("FC_MAX_SANDBOXES").unwrap_or(default.max_sandboxes),
+        max_keep_alive: env_parse("FC_SANDBOX_MAX_KEEP_ALIVE_SECS")
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(default.max_keep_alive),
+    }
+}
+
+/// Remote input settings from `FC_INPUT_ALLOWED_HOSTS`, `FC_INPUT_DENIED_HOSTS`,
+/// `FC_MAX_INPUTS` and `FC_MAX_INPUT_BYTES`
+fn inputs_from_env() -> InputsConfig {
+    let default = InputsConfig::default();
+    let hosts = |name| {
+        std::env::var(name)
+            .map(|hosts| parse_key_list(&hosts, ','))
+            .unwrap_or_default()
+    };
+    InputsConfig {
+        allowed_hosts: hosts("FC_INPUT_ALLOWED_HOSTS"),
+        denied_hosts: hosts("FC_INPUT_DENIED_HOSTS"),
+        max_inputs: env_parse("FC_MAX_INPUTS").unwrap_or(default.max_inputs),
+        max_bytes: env_parse("FC_MAX_INPUT_BYTES").unwrap_or(default.max_bytes),
+    }
+}
+
+/// Pooled VM health probe settings from `FC_HEALTH_PROBE*` environment variables
+fn health_probe_from_env() -> HealthProbeConfig {
+    let default = HealthProbeConfig::default();
+    HealthProbeConfig {
+        // 0 turns probing off
+        interval: match env_parse("FC_HEALTH_PROBE_INTERVAL_SECS") {
+            Some(0) => None,
+            Some(secs) => Some(std::time::Duration::from_secs(secs)),
+            None => default.interval,
+        },
+        timeout: env_parse("FC_HEALTH_PROBE_TIMEOUT_MS")
+            .map(std::time::Duration::from_millis)
+            .unwrap_or(default.timeout),
+        failures_before_eviction: env_parse("FC_HEALTH_PROBE_FAILURES")
+            .filter(|&failures| failures > 0)
+            .unwrap_or(default.failures_before_eviction),
+    }
+}
+
+/// Pool autoscaling settings from `FC_AUTOSCALE*` environment variables
+fn autoscale_from_env() -> AutoscaleConfig {
+    let default = AutoscaleConfig::default();
+    let secs = |name| env_parse(name).map(std::time::Duration::from_secs);
+    AutoscaleConfig {
+        enabled: env_flag("FC_AUTOSCALE").unwrap_or(default.enabled),
+        min: env_parse("FC_AUTOSCALE_MIN").unwrap_or(default.min),
+        max: env_parse("FC_AUTOSCALE_MAX").unwrap_or(default.max),
+        window: secs("FC_AUTOSCALE_WINDOW_SECS").unwrap_or(default.window),
+        cooldown: secs("FC_AUTOSCALE_COOLDOWN_SECS").unwrap_or(default.cooldown),
+        idle_ttl: secs("FC_POOL_IDLE_TTL_SECS").unwrap_or(default.idle_ttl),
+    }
+}
+
+/// Boot diagnostics settings from `FC_BOOT_DIAGNOSTICS*` environment variables
+fn boot_diagnostics_from_env() -> BootDiagnosticsConfig {
+    let default = BootDiagnosticsConfig::default();
+    BootDiagnosticsConfig {
+        enabled: env_flag("FC_BOOT_DIAGNOSTICS").unwrap_or(default.enabled),
+        console_lines: env_parse("FC_BOOT_DIAGNOSTICS_LINES").unwrap_or(default.console_lines),
+        retention: env_parse("FC_BOOT_DIAGNOSTICS_RETENTION_SECS")
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(default.retention),
+    }
+}
+
+/// Console capture settings from `FC_CONSOLE_CAPTURE` and `FC_CONSOLE_CAPTURE_MAX_BYTES`
+fn console_capture_from_env() -> ConsoleCaptureConfig {
+    let default = ConsoleCaptureConfig::default();
+    ConsoleCaptureConfig {
+        enabled: env_flag("FC_CONSOLE_CAPTURE").unwrap_or(default.enabled),
+        max_bytes: env_parse("FC_CONSOLE_CAPTURE_MAX_BYTES").unwrap_or(default.max_bytes),
+    }
+}
+
+/// Runtime file sweeps from `FC_RUNTIME_GC_INTERVAL_SECS` and `FC_RUNTIME_GC_MIN_AGE_SECS`
+fn runtime_gc_from_env() -> RuntimeGcConfig {
+    let default = RuntimeGcConfig::default();
+    RuntimeGcConfig {
+        // 0 never sweeps
+        interval: match env_parse("FC_RUNTIME_GC_INTERVAL_SECS") {
+            Some(0) => None,
+            Some(secs) => Some(std::time::Duration::from_secs(secs)),
+            None => default.interval,
+        },
+        min_age: env_parse("FC_RUNTIME_GC_MIN_AGE_SECS")
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(default.min_age),
+    }
+}
+
+/// Jailer settings from `FC_JAILER_*` environment variables
+fn jailer_from_env() -> JailerConfig {
+    let default = JailerConfig::default();
+    JailerConfig {
+        jailer_bin: std::env::var("FC_JAILER_BIN")
+            .map(PathBuf::from)
+            .unwrap_or(default.jailer_bin),
+        exec_file: std::env::var("FC_JAILER_EXEC_FILE")
+            .map(PathBuf::from)
+            .unwrap_or(default.exec_file),
+        uid: env_parse("FC_JAILER_UID").unwrap_or(default.uid),
+        gid: env_parse("FC_JAILER_GID").unwrap_or(default.gid),
+        chroot_base_dir: std::env::var("FC_JAILER_CHROOT_BASE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or(default.chroot_base_dir),
+    }
+}
+
+/// Parse a boolean environment variable ("1", "true", "yes", "on" and their negations)
+pub fn env_flag(name: &str) -> Option<bool> {
+    let value = std::env::var(name).ok()?;
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse an environment variable with `FromStr`, ignoring unset or malformed values
+pub fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    std::env::var(name).ok()?.trim().parse().ok()
+}
+
+/// Global runner configuration, loaded from the environment on first use
+static RUNNER_CONFIG: once_cell::sync::OnceCell<Arc<RunnerConfig>> =
+    once_cell::sync::OnceCell::new();
+
+/// Get the runner configuration
+pub fn runner_config() -> &'static RunnerConfig {
+    shared()
+}
+
+/// The global runner configuration as a handle an `ExecutorService` can own
+pub fn shared_runner_config() -> Arc<RunnerConfig> {
+    shared().clone()
+}
+
+fn shared() -> &'static Arc<RunnerConfig> {
+    RUNNER_CONFIG.get_or_init(|| Arc::new(RunnerConfig::from_env()))
+}
+
+/// Install the runner configuration; returns false if it was already initialized
+pub fn init_runner_config(config: RunnerConfig) -> bool {
+    RUNNER_CONFIG.set(Arc::new(config)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runtime_path() {
+        let config = RunnerConfig {
+            runtime_dir: PathBuf::from("/run/fc"),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.runtime_path("fc-log-abc.log"),
+            "/run/fc/fc-log-abc.log"
+        );
+    }
+
+    #[test]
+    fn test_parse_key_list() {
+        assert_eq!(parse_key_list(" a, b ,,c", ','), vec!["a", "b", "c"]);
+        assert_eq!(
+            parse_key_list("# team keys\nkey-1\n\n  key-2  \n", '\n'),
+            vec!["key-1", "key-2"]
+        );
+    }
+
+    #[test]
+    fn test_parse_rate_limit_overrides() {
+        let overrides = parse_rate_limit_overrides("ab12cd34=120:20, ff00ff00=5").unwrap();
+        assert_eq!(
+            overrides["ab12cd34"],
+            RateLimit {
+                per_minute: 120,
+                burst: 20
+            }
+        );
+        assert_eq!(overrides["ff00ff00"].burst, 5);
+        assert!(parse_rate_limit_overrides("ab12cd34").is_err());
+    }
+
+    #[test]
+    fn test_default_config() {
+        let config = RunnerConfig::default();
+        assert_eq!(config.runtime_dir, PathBuf::from("/tmp"));
+        assert!(config.firecracker_metrics);
+        assert!(config.jailer.is_none());
+    }
+}
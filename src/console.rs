@@ -0,0 +1,179 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Default most console output returned with one execution
+pub const DEFAULT_CONSOLE_CAPTURE_BYTES: u64 = 64 * 1024;
+
+/// Returning the VM console written during an execution to requests that set
+/// `capture_console`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsoleCaptureConfig {
+    /// Whether requests may ask for it; they then always run on a VM booted for them alone, as
+    /// a pooled VM's console may hold other requests' output
+    pub enabled: bool,
+    /// Most console output returned; the end of it is kept, where crashes print
+    pub max_bytes: u64,
+}
+
+impl Default for ConsoleCaptureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: DEFAULT_CONSOLE_CAPTURE_BYTES,
+        }
+    }
+}
+
+/// Where the console log ended when an execution started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleMark {
+    /// Inode of the log, to notice it was replaced since; `None` if there was no log yet
+    inode: Option<u64>,
+    len: u64,
+}
+
+/// Mark the end of the console log at `path`, to read what is written after it
+pub fn mark(path: &Path) -> ConsoleMark {
+    match std::fs::metadata(path) {
+        Ok(meta) => ConsoleMark {
+            inode: Some(meta.ino()),
+            len: meta.len(),
+        },
+        Err(_) => ConsoleMark {
+            inode: None,
+            len: 0,
+        },
+    }
+}
+
+/// Console output written during an execution
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedConsole {
+    pub text: String,
+    /// Whether output is missing: cut at the cap, or lost when the log was rotated or
+    /// truncated since the mark
+    pub truncated: bool,
+}
+
+/// Console output at `path` written since `mark`, at most the last `max_bytes` of it. A log
+/// replaced or truncated since the mark is read from its start, as what was appended to the
+/// old one is gone.
+pub fn read_since(path: &Path, mark: ConsoleMark, max_bytes: u64) -> CapturedConsole {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return CapturedConsole::default();
+    };
+    let Ok(meta) = file.metadata() else {
+        return CapturedConsole::default();
+    };
+    let len = meta.len();
+    let replaced = mark.inode.is_some_and(|inode| inode != meta.ino()) || len < mark.len;
+    let from = if replaced { 0 } else { mark.len };
+    let start = from.max(len.saturating_sub(max_bytes));
+    let mut bytes = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err()
+        || file.take(len - start).read_to_end(&mut bytes).is_err()
+    {
+        return CapturedConsole::default();
+    }
+    let cut = start > from;
+    if cut {
+        // Don't start in the middle of a character
+        let continuation = bytes
+            .iter()
+            .take(3)
+            .take_while(|byte| (**byte & 0xC0) == 0x80)
+            .count();
+        bytes.drain(..continuation);
+    }
+    CapturedConsole {
+        text: String::from_utf8_lossy(&bytes).into_owned(),
+        truncated: cut || replaced,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("fc-console-{name}-{}", std::process::id()))
+    }
+
+    fn append(path: &Path, text: &str) {
+        use std::io::Write;
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reads_only_what_was_written_since_the_mark() {
+        let path = temp_path("since");
+        let _ = std::fs::remove_file(&path);
+        // No log yet: everything it gets counts
+        let before = mark(&path);
+        append(&path, "[ 0.5] boot\n");
+        let after_boot = mark(&path);
+        append(&path, "[ 1.0] python3[201]: segfault at 0\n");
+
+        let captured = read_since(&path, after_boot, 1024);
+        assert_eq!(captured.text, "[ 1.0] python3[201]: segfault at 0\n");
+        assert!(!captured.truncated);
+        assert!(
+            read_since(&path, before, 1024)
+                .text
+                .starts_with("[ 0.5] boot")
+        );
+
+        // Only the end is kept past the cap
+        let captured = read_since(&path, after_boot, 10);
+        assert_eq!(captured.text, "ault at 0\n");
+        assert!(captured.truncated);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            read_since(&path, after_boot, 1024),
+            CapturedConsole::default()
+        );
+    }
+
+    #[test]
+    fn test_rotated_or_truncated_logs_are_read_from_their_start() {
+        let path = temp_path("rotated");
+        std::fs::write(&path, "old output from before the execution\n").unwrap();
+        let start = mark(&path);
+        // Truncated in place, then written again
+        std::fs::write(&path, "new\n").unwrap();
+        let captured = read_since(&path, start, 1024);
+        assert_eq!(captured.text, "new\n");
+        assert!(captured.truncated);
+
+        // Renamed away and recreated, now longer than it was
+        let start = mark(&path);
+        let rotated = temp_path("rotated.1");
+        std::fs::rename(&path, &rotated).unwrap();
+        append(&path, "after rotation, longer than the old log\n");
+        let captured = read_since(&path, start, 1024);
+        assert_eq!(captured.text, "after rotation, longer than the old log\n");
+        assert!(captured.truncated);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+
+    #[test]
+    fn test_cap_never_splits_a_character() {
+        let path = temp_path("utf8");
+        let _ = std::fs::remove_file(&path);
+        let start = mark(&path);
+        append(&path, "ééé");
+        // The cap lands on the second byte of the second `é`
+        let captured = read_since(&path, start, 3);
+        assert_eq!(captured.text, "é");
+        assert!(captured.truncated);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, Method, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tower::{Layer, ServiceExt};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Origins allowed to call the API from a browser
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorsOrigins {
+    /// Any origin; intended for development only
+    Any,
+    /// Exact origins such as `https://ui.example.com`
+    List(Vec<String>),
+}
+
+impl CorsOrigins {
+    /// Parse `any`/`*` or a comma-separated list of origins
+    pub fn parse(raw: &str) -> Option<Self> {
+        let raw = raw.trim();
+        if raw == "*" || raw.eq_ignore_ascii_case("any") {
+            return Some(CorsOrigins::Any);
+        }
+        let origins: Vec<String> = raw
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(str::to_string)
+            .collect();
+        (!origins.is_empty()).then_some(CorsOrigins::List(origins))
+    }
+}
+
+/// Build the CORS layer for the configured origins
+pub fn cors_layer(origins: &CorsOrigins) -> CorsLayer {
+    let allow_origin = match origins {
+        CorsOrigins::Any => AllowOrigin::from(Any),
+        CorsOrigins::List(origins) => AllowOrigin::list(
+            origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        ),
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            HeaderName::from_static("x-request-id"),
+        ])
+        .expose_headers([
+            HeaderName::from_static("x-vm-id"),
+            HeaderName::from_static("x-request-id"),
+        ])
+}
+
+/// Middleware applying `cors` to every request except plain `OPTIONS` requests.
+///
+/// `CorsLayer` answers every `OPTIONS` request itself; passing the ones without
+/// `Access-Control-Request-Method` through keeps `OPTIONS /execute` limit discovery working.
+pub async fn apply_cors(State(cors): State<CorsLayer>, request: Request, next: Next) -> Response {
+    let is_plain_options = request.method() == Method::OPTIONS
+        && !request
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    if is_plain_options {
+        return next.run(request).await;
+    }
+    match cors.layer(next).oneshot(request).await {
+        Ok(response) => response.into_response(),
+        Err(infallible) => match infallible {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_origins() {
+        assert_eq!(CorsOrigins::parse("*"), Some(CorsOrigins::Any));
+        assert_eq!(CorsOrigins::parse("ANY"), Some(CorsOrigins::Any));
+        assert_eq!(
+            CorsOrigins::parse("https://a.example, https://b.example"),
+            Some(CorsOrigins::List(vec![
+                "https://a.example".to_string(),
+                "https://b.example".to_string()
+            ]))
+        );
+        assert_eq!(CorsOrigins::parse(" , "), None);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::ExecutionError;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Part of a request that takes time out of its deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Booting a VM for the request and waiting for its guest agent
+    Boot,
+    /// Running the code on a ready VM
+    Execute,
+}
+
+impl Phase {
+    /// How errors name the phase
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Boot => "boot",
+            Phase::Execute => "execute",
+        }
+    }
+
+    /// Least time worth starting the phase with; with less it would only fail later
+    pub fn min_budget(self) -> Duration {
+        match self {
+            Phase::Boot => Duration::from_millis(500),
+            Phase::Execute => Duration::from_millis(100),
+        }
+    }
+}
+
+/// When a request must have finished, from booting its VM to running its code. Each phase
+/// takes its time limit from what is left rather than from a fixed timeout of its own, so
+/// together they never outlast the request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    at: Instant,
+    budget: Duration,
+}
+
+impl Deadline {
+    /// A deadline `budget` from now
+    pub fn after(budget: Duration) -> Self {
+        Self {
+            at: Instant::now() + budget,
+            budget,
+        }
+    }
+
+    /// Time left until the deadline, zero once it has passed
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    /// `limit`, or less if the deadline comes first
+    pub fn cap(&self, limit: Duration) -> Duration {
+        limit.min(self.remaining())
+    }
+
+    /// Time left for `phase`, failing with `DeadlineExceeded` when it is too little to start it
+    pub fn budget_for(&self, phase: Phase) -> Result<Duration, ExecutionError> {
+        let remaining = self.remaining();
+        if remaining < phase.min_budget() {
+            return Err(self.exceeded(phase));
+        }
+        Ok(remaining)
+    }
+
+    /// The error of `phase` running out of time
+    pub fn exceeded(&self, phase: Phase) -> ExecutionError {
+        ExecutionError::DeadlineExceeded {
+            phase: phase.name().to_string(),
+            budget_ms: self.budget.as_millis() as u64,
+        }
+    }
+
+    /// Run `phase`, failing with `DeadlineExceeded` if it can't start or finish in time
+    pub async fn run<T>(
+        &self,
+        phase: Phase,
+        future: impl Future<Output = Result<T, ExecutionError>>,
+    ) -> Result<T, ExecutionError> {
+        let budget = self.budget_for(phase)?;
+        tokio::time::timeout(budget, future)
+            .await
+            .unwrap_or_else(|_| Err(self.exceeded(phase)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deadline of `budget` with only `remaining` of it left
+    fn partly_spent(budget: Duration, remaining: Duration) -> Deadline {
+        Deadline {
+            at: Instant::now() + remaining,
+            budget,
+        }
+    }
+
+    #[test]
+    fn test_budget_arithmetic() {
+        let deadline = Deadline::after(Duration::from_secs(10));
+        assert!(deadline.remaining() > Duration::from_secs(9));
+        assert!(deadline.cap(Duration::from_secs(15)) <= Duration::from_secs(10));
+        assert_eq!(deadline.cap(Duration::from_secs(2)), Duration::from_secs(2));
+
+        let deadline = partly_spent(Duration::from_secs(10), Duration::from_millis(300));
+        assert!(deadline.cap(Duration::from_secs(2)) <= Duration::from_millis(300));
+        // Too little to boot, enough to run code on a ready VM
+        let err = deadline.budget_for(Phase::Boot).unwrap_err();
+        assert_eq!(err.code(), "timeout");
+        assert_eq!(
+            err.to_string(),
+            "Not enough of the request's 10000ms deadline was left to boot"
+        );
+        assert!(deadline.budget_for(Phase::Execute).unwrap() > Phase::Execute.min_budget());
+
+        let passed = partly_spent(Duration::from_secs(10), Duration::ZERO);
+        assert_eq!(passed.remaining(), Duration::ZERO);
+        assert_eq!(passed.cap(Duration::from_secs(2)), Duration::ZERO);
+        assert!(passed.budget_for(Phase::Execute).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_stops_a_phase_at_the_deadline() {
+        let deadline = Deadline::after(Duration::from_millis(300));
+        let quick = deadline.run(Phase::Execute, async { Ok(1) }).await;
+        assert_eq!(quick.unwrap(), 1);
+
+        let slow = deadline.run(Phase::Execute, async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        });
+        let err = slow.await.unwrap_err();
+        assert!(
+            matches!(&err, ExecutionError::DeadlineExceeded { phase, budget_ms: 300 } if phase == "execute"),
+            "{err}"
+        );
+        // The phase's own errors pass through
+        let failed = Deadline::after(Duration::from_secs(1))
+            .run(Phase::Boot, async {
+                Err::<(), _>(ExecutionError::ShuttingDown)
+            })
+            .await;
+        assert!(matches!(failed, Err(ExecutionError::ShuttingDown)));
+    }
+}
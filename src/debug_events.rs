@@ -0,0 +1,239 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Most events returned with one execution; later ones are only counted
+pub const MAX_DEBUG_EVENTS: usize = 64;
+
+/// Who may ask for an execution's event log with `debug`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugEventsConfig {
+    /// Whether requests may ask for it at all
+    pub enabled: bool,
+    /// API key identifiers refused it, for tenants that shouldn't see the infrastructure
+    pub denied_key_ids: Vec<String>,
+}
+
+impl DebugEventsConfig {
+    /// Whether the caller with API key `key_id` may ask for the event log
+    pub fn allows(&self, key_id: Option<&str>) -> bool {
+        self.enabled
+            && !key_id.is_some_and(|id| self.denied_key_ids.iter().any(|denied| denied == id))
+    }
+}
+
+/// What happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugEventKind {
+    /// The execution got its permit; `duration_ms` is how long it queued for it
+    PermitGranted,
+    /// A VM was taken from the pool (`hit`) or booted for the execution (`miss`, `dedicated`)
+    PoolAcquire,
+    /// A phase of booting the VM, named by `detail`, took `duration_ms`
+    BootPhase,
+    /// The guest agent answered a health check, with `detail` its verdict
+    HealthProbe,
+    /// The execution was sent to the guest agent
+    GuestRequestSent,
+    /// The first byte of the guest agent's response arrived
+    FirstByte,
+    /// The stream named by `detail` was cut at `bytes`
+    Truncated,
+    /// What became of the VM: `returned` to the pool, or the reason it was discarded
+    PoolRelease,
+    /// `count` more events happened than are kept
+    Dropped,
+}
+
+impl DebugEventKind {
+    /// Name of the kind as serialized
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DebugEventKind::PermitGranted => "permit_granted",
+            DebugEventKind::PoolAcquire => "pool_acquire",
+            DebugEventKind::BootPhase => "boot_phase",
+            DebugEventKind::HealthProbe => "health_probe",
+            DebugEventKind::GuestRequestSent => "guest_request_sent",
+            DebugEventKind::FirstByte => "first_byte",
+            DebugEventKind::Truncated => "truncated",
+            DebugEventKind::PoolRelease => "pool_release",
+            DebugEventKind::Dropped => "dropped",
+        }
+    }
+}
+
+/// One timestamped step of an execution. Only names, IDs and numbers the host chose are
+/// recorded, never code, output or anything a request sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct DebugEvent {
+    /// Milliseconds since the execution got its permit
+    pub at_ms: u64,
+    pub event: DebugEventKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vm_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+}
+
+impl DebugEvent {
+    pub fn new(event: DebugEventKind) -> Self {
+        Self {
+            at_ms: 0,
+            event,
+            vm_id: None,
+            detail: None,
+            duration_ms: None,
+            bytes: None,
+            count: None,
+        }
+    }
+
+    pub fn vm(mut self, vm_id: &str) -> Self {
+        self.vm_id = Some(vm_id.to_string());
+        self
+    }
+
+    /// Only static text, so nothing a request sent can end up here
+    pub fn detail(mut self, detail: &'static str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = Some(duration.as_millis() as u64);
+        self
+    }
+
+    pub fn bytes(mut self, bytes: usize) -> Self {
+        self.bytes = Some(bytes as u64);
+        self
+    }
+}
+
+/// Events of the execution being collected, bounded to `MAX_DEBUG_EVENTS`
+#[derive(Debug)]
+struct EventLog {
+    started: Instant,
+    events: Vec<DebugEvent>,
+    dropped: u64,
+}
+
+impl EventLog {
+    fn push(&mut self, mut event: DebugEvent) {
+        // The last slot is kept for the count of what didn't fit
+        if self.events.len() + 1 >= MAX_DEBUG_EVENTS {
+            self.dropped += 1;
+            return;
+        }
+        event.at_ms = self.started.elapsed().as_millis() as u64;
+        self.events.push(event);
+    }
+
+    fn finish(&mut self) -> Vec<DebugEvent> {
+        if self.dropped > 0 {
+            let mut dropped = DebugEvent::new(DebugEventKind::Dropped);
+            dropped.at_ms = self.started.elapsed().as_millis() as u64;
+            dropped.count = Some(self.dropped);
+            self.events.push(dropped);
+        }
+        std::mem::take(&mut self.events)
+    }
+}
+
+tokio::task_local! {
+    static EVENT_LOG: Arc<Mutex<EventLog>>;
+}
+
+/// Run `future`, collecting the events it records along with its output
+pub async fn collect<T>(future: impl Future<Output = T>) -> (T, Vec<DebugEvent>) {
+    let log = Arc::new(Mutex::new(EventLog {
+        started: Instant::now(),
+        events: Vec::new(),
+        dropped: 0,
+    }));
+    let output = EVENT_LOG.scope(log.clone(), future).await;
+    let events = log.lock().unwrap_or_else(|e| e.into_inner()).finish();
+    (output, events)
+}
+
+/// Record `event` for the execution being collected; does nothing for other executions
+pub fn record(event: DebugEvent) {
+    let _ = EVENT_LOG.try_with(|log| log.lock().unwrap_or_else(|e| e.into_inner()).push(event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialized_form() {
+        let event = DebugEvent {
+            at_ms: 12,
+            ..DebugEvent::new(DebugEventKind::BootPhase)
+                .vm("vm-1")
+                .detail("networking")
+                .duration(Duration::from_millis(40))
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "at_ms": 12,
+                "event": "boot_phase",
+                "vm_id": "vm-1",
+                "detail": "networking",
+                "duration_ms": 40
+            })
+        );
+        assert_eq!(json["event"], event.event.as_str());
+        assert_eq!(serde_json::from_value::<DebugEvent>(json).unwrap(), event);
+        let bare: DebugEvent =
+            serde_json::from_str(r#"{"at_ms": 0, "event": "first_byte"}"#).unwrap();
+        assert_eq!(bare, DebugEvent::new(DebugEventKind::FirstByte));
+    }
+
+    #[tokio::test]
+    async fn test_collected_events_are_bounded_and_scoped() {
+        // Outside a collection nothing is kept
+        record(DebugEvent::new(DebugEventKind::FirstByte));
+        let ((), events) = collect(async {
+            record(DebugEvent::new(DebugEventKind::PoolAcquire).detail("hit"));
+            for _ in 0..100 {
+                record(DebugEvent::new(DebugEventKind::HealthProbe));
+            }
+        })
+        .await;
+        assert_eq!(events.len(), MAX_DEBUG_EVENTS);
+        assert_eq!(events[0].event, DebugEventKind::PoolAcquire);
+        let last = events.last().unwrap();
+        assert_eq!(last.event, DebugEventKind::Dropped);
+        assert_eq!(last.count, Some(101 - (MAX_DEBUG_EVENTS as u64 - 1)));
+        let json = serde_json::to_string(&events).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<DebugEvent>>(&json).unwrap(),
+            events
+        );
+
+        let ((), events) = collect(async {}).await;
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_keys_can_be_denied() {
+        let config = DebugEventsConfig {
+            enabled: true,
+            denied_key_ids: vec!["untrusted".to_string()],
+        };
+        assert!(config.allows(None));
+        assert!(config.allows(Some("ops")));
+        assert!(!config.allows(Some("untrusted")));
+        assert!(!DebugEventsConfig::default().allows(Some("ops")));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::breaker::BreakerStatus;
+use crate::config::{Config, RunnerConfig};
+use crate::events::RecordedEvent;
+use crate::executor::{ExecutorStats, PooledVm};
+use crate::fd_limit::{FdUsage, VmFds};
+use crate::history::now_millis;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use utoipa::ToSchema;
+
+/// Most live VMs and running executions listed in a state document
+pub const MAX_LISTED: usize = 256;
+
+/// Recent errors kept for the state document
+pub const RECENT_ERROR_CAPACITY: usize = 50;
+
+/// Longest error message kept, in bytes
+const MAX_ERROR_MESSAGE_BYTES: usize = 512;
+
+/// Stands in for secrets and host paths in the configuration summary
+const REDACTED: &str = "<redacted>";
+
+/// An execution or VM boot that failed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RecentError {
+    /// When it failed, in milliseconds since the Unix epoch
+    pub at: u64,
+    /// Request that failed; absent for VMs booted to fill the pool
+    pub request_id: Option<String>,
+    pub code: String,
+    pub message: String,
+}
+
+/// The latest failures, oldest first
+static RECENT_ERRORS: Lazy<Mutex<VecDeque<RecentError>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_ERROR_CAPACITY)));
+
+/// Keep a failure for the state document, dropping the oldest beyond
+/// `RECENT_ERROR_CAPACITY`
+pub fn record_error(request_id: Option<&str>, code: &str, message: &str) {
+    let mut message = message.to_string();
+    if message.len() > MAX_ERROR_MESSAGE_BYTES {
+        let mut end = MAX_ERROR_MESSAGE_BYTES;
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        message.truncate(end);
+    }
+    let mut errors = RECENT_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    if errors.len() >= RECENT_ERROR_CAPACITY {
+        errors.pop_front();
+    }
+    errors.push_back(RecentError {
+        at: now_millis(),
+        request_id: request_id.map(str::to_string),
+        code: code.to_string(),
+        message,
+    });
+}
+
+/// The latest failures, oldest first
+pub fn recent_errors() -> Vec<RecentError> {
+    RECENT_ERRORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// When each background task last ran, in milliseconds since the Unix epoch
+static HEARTBEATS: Lazy<Mutex<BTreeMap<&'static str, u64>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Note that background task `task` just ran a round
+pub fn heartbeat(task: &'static str) {
+    HEARTBEATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(task, now_millis());
+}
+
+/// Last round of every background task that has run one
+pub fn heartbeats() -> BTreeMap<String, u64> {
+    HEARTBEATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(task, at)| (task.to_string(), *at))
+        .collect()
+}
+
+/// Settings that explain the server's behaviour, without keys, secrets or host paths
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ConfigSummary {
+    pub backend: String,
+    /// Number of API keys accepted; the keys themselves are never shown
+    pub api_keys: usize,
+    pub allow_network: bool,
+    pub max_code_length: usize,
+    pub max_body_bytes: usize,
+    pub execute_deadline_ms: u64,
+    pub quick_timeout_ms: u64,
+    pub cache_enabled: bool,
+    /// `<redacted>` when jobs are kept in a SQLite database
+    pub db_path: Option<String>,
+    /// `<redacted>` when jobs are queued in Redis
+    pub redis_url: Option<String>,
+    pub job_workers: usize,
+    /// Whether job callbacks are signed
+    pub callback_signing: bool,
+    pub jailer: bool,
+    /// Names of the reference data drives attached to every VM; their paths are never shown
+    pub reference_data: Vec<String>,
+    pub pool_size: usize,
+    pub vm_memory_mib: u64,
+    pub max_vms: Option<usize>,
+    pub max_concurrent_executions: Option<usize>,
+    pub max_queued_executions: usize,
+    pub vm_wait_budget_ms: Option<u64>,
+    pub vm_max_age_secs: Option<u64>,
+    pub vm_max_reuse: Option<u64>,
+    pub health_probe_interval_ms: Option<u64>,
+    pub boot_diagnostics: bool,
+    pub console_capture: bool,
+}
+
+impl ConfigSummary {
+    pub fn new(config: &Config, runner: &RunnerConfig) -> Self {
+        let redacted = |set: bool| set.then(|| REDACTED.to_string());
+        Self {
+            backend: runner.backend.as_str().to_string(),
+            api_keys: config.api_keys.len(),
+            allow_network: config.allow_network,
+            max_code_length: config.max_code_length,
+            max_body_bytes: config.max_body_bytes,
+            execute_deadline_ms: config.execute_deadline.as_millis() as u64,
+            quick_timeout_ms: config.quick_timeout.as_millis() as u64,
+            cache_enabled: config.cache.enabled,
+            db_path: redacted(config.db_path.is_some()),
+            redis_url: redacted(config.redis_url.is_some()),
+            job_workers: config.job_workers,
+            callback_signing: config.webhook.secret.is_some(),
+            jailer: runner.jailer.is_some(),
+            reference_data: runner.reference_data.keys().cloned().collect(),
+            pool_size: runner.pool_size,
+            vm_memory_mib: runner.vm_memory_mib,
+            max_vms: runner.max_vms,
+            max_concurrent_executions: runner.max_concurrent_executions,
+            max_queued_executions: runner.max_queued_executions,
+            vm_wait_budget_ms: runner.vm_wait_budget.map(|d| d.as_millis() as u64),
+            vm_max_age_secs: runner.vm_max_age.map(|d| d.as_secs()),
+            vm_max_reuse: runner.vm_max_reuse,
+            health_probe_interval_ms: runner.health_probe.interval.map(|d| d.as_millis() as u64),
+            boot_diagnostics: runner.boot_diagnostics.enabled,
+            console_capture: runner.console_capture.enabled,
+        }
+    }
+}
+
+/// A VM in the registry of live VMs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct LiveVmState {
+    pub vm_id: String,
+    pub tap_interface: String,
+    pub pid: Option<u32>,
+    /// Waiting in the pool rather than running an execution or being set up
+    pub pooled: bool,
+    /// Host file descriptors it is expected to take
+    pub fds: VmFds,
+}
+
+/// An execution holding a permit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RunningExecution {
+    pub request_id: String,
+    /// When it got its permit, in milliseconds since the Unix epoch
+    pub started_at: u64,
+    pub elapsed_ms: u64,
+}
+
+/// Pool sizing by the autoscaler
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct AutoscaleState {
+    pub enabled: bool,
+    pub min: usize,
+    pub max: usize,
+    pub pool_target: usize,
+}
+
+/// The server's file descriptors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FdState {
+    /// Open descriptors against the limit; absent where `/proc` can't tell
+    pub usage: Option<FdUsage>,
+    /// Descriptors all live VMs are expected to take
+    pub expected_by_vms: u64,
+    /// Headroom below which the pool stops booting VMs
+    pub min_headroom: u64,
+}
+
+/// The executor as of one moment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ExecutorState {
+    pub stats: ExecutorStats,
+    /// Pooled VMs, oldest pooled first
+    pub pool: Vec<PooledVm>,
+    /// Up to `MAX_LISTED` live VMs, by ID
+    pub live_vms: Vec<LiveVmState>,
+    pub live_vms_total: usize,
+    /// Up to `MAX_LISTED` running executions, longest running first
+    pub in_flight: Vec<RunningExecution>,
+    pub vm_creation: BreakerStatus,
+    pub autoscale: AutoscaleState,
+    pub fds: FdState,
+}
+
+/// Everything `GET /admin/state` reports, bounded in size and free of secrets
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DebugState {
+    /// When the snapshot was taken, in milliseconds since the Unix epoch
+    pub taken_at: u64,
+    pub version: String,
+    pub config: ConfigSummary,
+    pub executor: ExecutorState,
+    /// Latest execution and VM boot failures, oldest first
+    pub recent_errors: Vec<RecentError>,
+    /// Latest VM lifecycle events, oldest first
+    pub recent_events: Vec<RecordedEvent>,
+    /// When each background task last ran a round, in milliseconds since the Unix epoch
+    pub tasks: BTreeMap<String, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_config_summary_redacts_keys_and_paths() {
+        let config = Config {
+            api_keys: vec!["sk-live-0123456789".to_string()],
+            db_path: Some(PathBuf::from("/srv/private/jobs.db")),
+            redis_url: Some("redis://:hunter2@cache.internal:6379".to_string()),
+            webhook: crate::webhook::WebhookConfig {
+                secret: Some("callback-secret".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let runner = RunnerConfig {
+            reference_data: BTreeMap::from([(
+                "geo".to_string(),
+                PathBuf::from("/srv/reference/geo.squashfs"),
+            )]),
+            ..Default::default()
+        };
+        let summary = ConfigSummary::new(&config, &runner);
+        let json = serde_json::to_string(&summary).unwrap();
+        for secret in [
+            "sk-live-0123456789",
+            "/srv/private",
+            "hunter2",
+            "cache.internal",
+            "callback-secret",
+            "/srv/reference",
+        ] {
+            assert!(!json.contains(secret), "{secret} leaked into {json}");
+        }
+        assert_eq!(summary.api_keys, 1);
+        assert_eq!(summary.db_path.as_deref(), Some(REDACTED));
+        assert!(summary.callback_signing);
+        assert_eq!(summary.reference_data, vec!["geo"]);
+    }
+
+    #[test]
+    fn test_recent_errors_are_bounded() {
+        for i in 0..RECENT_ERROR_CAPACITY + 5 {
+            record_error(Some(&format!("req-{i}")), "timeout", &"x".repeat(2000));
+        }
+        let errors = recent_errors();
+        assert_eq!(errors.len(), RECENT_ERROR_CAPACITY);
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.message.len() <= MAX_ERROR_MESSAGE_BYTES)
+        );
+        let last = format!("req-{}", RECENT_ERROR_CAPACITY + 4);
+        // Other tests record errors too, so only check ours made it in
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.request_id.as_deref() == Some(last.as_str()))
+        );
+    }
+}
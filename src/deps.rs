@@ -0,0 +1,97 @@
+use crate::config::ConfigError;
+use crate::vm_config::Drive;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Firecracker drive ID of the dependencies drive
+pub const DEPS_DRIVE_ID: &str = "deps";
+
+/// Guest device of the dependencies drive; it is always attached right after the rootfs
+pub const DEPS_GUEST_DEVICE: &str = "/dev/vdb";
+
+/// Kernel command-line parameter telling the guest agent which device to mount at `/opt/deps`
+pub const DEPS_BOOT_ARG: &str = "fc_deps";
+
+/// Parse `NAME=PATH,...` into deps profiles; malformed entries are skipped with a warning
+pub fn parse_profiles(raw: &str) -> BTreeMap<String, PathBuf> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                Some((name.trim().to_string(), PathBuf::from(path.trim())))
+            }
+            _ => {
+                tracing::warn!("Ignoring malformed deps profile {:?}", entry);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Check every configured deps image exists, so a typo fails at startup rather than at boot
+pub fn preflight(profiles: &BTreeMap<String, PathBuf>) -> Result<(), ConfigError> {
+    for (name, path) in profiles {
+        if !path.is_file() {
+            return Err(ConfigError::Invalid(format!(
+                "deps profile '{name}': {} is not a file",
+                path.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The dependencies drive; it is shared between VMs, so it must stay read-only
+pub fn drive_config(path_on_host: &str) -> Drive {
+    Drive {
+        drive_id: DEPS_DRIVE_ID.to_string(),
+        path_on_host: path_on_host.to_string(),
+        is_root_device: false,
+        is_read_only: true,
+    }
+}
+
+/// Kernel boot argument announcing the dependencies drive to the guest
+pub fn boot_arg() -> String {
+    format!("{DEPS_BOOT_ARG}={DEPS_GUEST_DEVICE}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles =
+            parse_profiles("numpy=/srv/deps/numpy.ext4, ml = /srv/deps/ml.ext4,broken,=x");
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles["ml"], PathBuf::from("/srv/deps/ml.ext4"));
+        assert!(parse_profiles("").is_empty());
+    }
+
+    #[test]
+    fn test_preflight_requires_existing_images() {
+        let dir = std::env::temp_dir().join(format!("deps-preflight-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("numpy.ext4");
+        std::fs::write(&image, b"").unwrap();
+
+        let mut profiles = BTreeMap::from([("numpy".to_string(), image)]);
+        assert!(preflight(&profiles).is_ok());
+        profiles.insert("missing".to_string(), dir.join("missing.ext4"));
+        let err = preflight(&profiles).unwrap_err();
+        assert!(err.to_string().contains("'missing'"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_drive_config_is_read_only() {
+        let drive = drive_config("/srv/deps/numpy.ext4");
+        assert_eq!(drive.drive_id, "deps");
+        assert!(drive.is_read_only);
+        assert!(!drive.is_root_device);
+        assert_eq!(boot_arg(), "fc_deps=/dev/vdb");
+    }
+}
@@ -0,0 +1,59 @@
+/// `PYTHONHASHSEED` used for deterministic runs
+pub const DETERMINISTIC_HASH_SEED: u32 = 0;
+
+/// Time zone the guest runs deterministic code in
+pub const DETERMINISTIC_TZ: &str = "UTC";
+
+/// Locale the guest runs deterministic code in
+pub const DETERMINISTIC_LOCALE: &str = "C.UTF-8";
+
+/// Settings the guest applies before running deterministic code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeterministicSettings {
+    pub hash_seed: u32,
+    /// Wall clock the guest is set to, as a Unix timestamp
+    pub fake_time: Option<i64>,
+}
+
+impl DeterministicSettings {
+    pub fn new(fake_time: Option<i64>) -> Self {
+        Self {
+            hash_seed: DETERMINISTIC_HASH_SEED,
+            fake_time,
+        }
+    }
+
+    /// Kernel boot arguments the guest agent reads at startup
+    pub fn boot_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "fc_deterministic=1".to_string(),
+            format!("fc_hash_seed={}", self.hash_seed),
+            format!("fc_tz={DETERMINISTIC_TZ}"),
+            format!("fc_locale={DETERMINISTIC_LOCALE}"),
+        ];
+        if let Some(fake_time) = self.fake_time {
+            args.push(format!("fc_fake_time={fake_time}"));
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_args() {
+        assert_eq!(
+            DeterministicSettings::new(None).boot_args(),
+            vec![
+                "fc_deterministic=1",
+                "fc_hash_seed=0",
+                "fc_tz=UTC",
+                "fc_locale=C.UTF-8"
+            ]
+        );
+        let args = DeterministicSettings::new(Some(1_700_000_000)).boot_args();
+        assert_eq!(args.last().unwrap(), "fc_fake_time=1700000000");
+    }
+}
@@ -0,0 +1,456 @@
+use crate::ExecutionError;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, watch};
+
+/// Default cap on executions waiting for a permit
+pub const DEFAULT_MAX_QUEUED_EXECUTIONS: usize = 100;
+
+/// Default longest an execution waits for a permit
+pub const DEFAULT_VM_WAIT_BUDGET: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Higher-priority grants a waiting request lets pass before it goes next regardless
+pub const AGING_LIMIT: u32 = 4;
+
+/// How urgently an execution wants a VM
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    /// Index of the priority's queue; the most urgent comes first
+    fn queue(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+/// Where an execution waiting for a permit stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuePosition {
+    /// 1 for the next execution to be granted a permit; 0 once this one has it
+    pub position: usize,
+    /// Estimated wait for the permit in milliseconds, from recent execution durations
+    pub eta_ms: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    /// Grants that went to a more urgent request while this one was first in its queue
+    passed_over: u32,
+    grant: oneshot::Sender<()>,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Permits not held by any execution
+    available: usize,
+    max_queued: usize,
+    /// Waiting executions per priority, oldest first
+    queues: [VecDeque<Waiter>; 3],
+    next_id: u64,
+    /// Signalled whenever a waiter joins or leaves a queue
+    changes: watch::Sender<()>,
+}
+
+impl State {
+    fn queued(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Take the next waiter to grant a permit to: the oldest of the most urgent priority,
+    /// unless a less urgent one has been passed over `AGING_LIMIT` times
+    fn next_waiter(&mut self) -> Option<Waiter> {
+        let index = self
+            .queues
+            .iter()
+            .position(|queue| {
+                queue
+                    .front()
+                    .is_some_and(|waiter| waiter.passed_over >= AGING_LIMIT)
+            })
+            .or_else(|| self.queues.iter().position(|queue| !queue.is_empty()))?;
+        for queue in &mut self.queues[index + 1..] {
+            if let Some(waiter) = queue.front_mut() {
+                waiter.passed_over += 1;
+            }
+        }
+        self.changes.send_replace(());
+        self.queues[index].pop_front()
+    }
+
+    /// 1-based place of waiter `id` at `priority`, counting every more urgent waiter as ahead
+    /// of it; aging can still let it pass some of them
+    fn position(&self, id: u64, priority: Priority) -> Option<usize> {
+        let own = self.queues[priority.queue()]
+            .iter()
+            .position(|waiter| waiter.id == id)?;
+        let ahead: usize = self.queues[..priority.queue()]
+            .iter()
+            .map(VecDeque::len)
+            .sum();
+        Some(ahead + own + 1)
+    }
+
+    /// Hand a freed permit to the next waiter still listening, or keep it
+    fn release(&mut self) {
+        while let Some(waiter) = self.next_waiter() {
+            if waiter.grant.send(()).is_ok() {
+                return;
+            }
+        }
+        self.available += 1;
+    }
+}
+
+/// Grants a bounded number of execution permits, most urgent requests first. Waiting requests
+/// park in per-priority queues; when those are full, the newest of a less urgent priority is
+/// shed to make room.
+#[derive(Debug, Clone)]
+pub struct Dispatcher {
+    /// `None` when executions are not limited
+    state: Option<Arc<Mutex<State>>>,
+}
+
+impl Dispatcher {
+    /// A dispatcher granting `permits` concurrent executions with up to `max_queued` waiting,
+    /// or any number of either when `permits` is `None`
+    pub fn new(permits: Option<usize>, max_queued: usize) -> Self {
+        Self {
+            state: permits.map(|permits| {
+                Arc::new(Mutex::new(State {
+                    available: permits,
+                    max_queued,
+                    queues: Default::default(),
+                    next_id: 0,
+                    changes: watch::Sender::new(()),
+                }))
+            }),
+        }
+    }
+
+    /// Queue for a permit at `priority`. Fails at once when the queue is full of requests at
+    /// least as urgent.
+    pub fn enqueue(&self, priority: Priority) -> Result<Ticket, ExecutionError> {
+        let Some(state) = &self.state else {
+            return Ok(Ticket::ready(None));
+        };
+        let mut guard = state.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.available > 0 && guard.queued() == 0 {
+            guard.available -= 1;
+            return Ok(Ticket::ready(Some(state.clone())));
+        }
+        if guard.queued() >= guard.max_queued {
+            // Dropping the shed waiter's sender fails its ticket
+            let shed = guard.queues[priority.queue() + 1..]
+                .iter_mut()
+                .rev()
+                .find_map(VecDeque::pop_back);
+            if shed.is_none() {
+                return Err(overloaded(guard.max_queued));
+            }
+            crate::telemetry::increment_counter("fc_executions_shed_total", &[], 1);
+        }
+        let (grant, granted) = oneshot::channel();
+        let id = guard.next_id;
+        guard.next_id += 1;
+        guard.queues[priority.queue()].push_back(Waiter {
+            id,
+            passed_over: 0,
+            grant,
+        });
+        guard.changes.send_replace(());
+        Ok(Ticket {
+            state: Some(state.clone()),
+            wait: Some((id, priority, granted)),
+            max_queued: guard.max_queued,
+        })
+    }
+
+    /// Executions waiting for a permit
+    pub fn queued(&self) -> usize {
+        self.state.as_ref().map_or(0, |state| {
+            state.lock().unwrap_or_else(|e| e.into_inner()).queued()
+        })
+    }
+}
+
+fn overloaded(max_queued: usize) -> ExecutionError {
+    ExecutionError::Overloaded(format!(
+        "{max_queued} executions are already waiting for a VM"
+    ))
+}
+
+/// A place in a dispatcher's queue. Dropping it before the permit is granted leaves the queue;
+/// dropping it after hands the permit on.
+#[derive(Debug)]
+pub struct Ticket {
+    state: Option<Arc<Mutex<State>>>,
+    /// Waiter ID, queue and grant signal while still waiting
+    wait: Option<(u64, Priority, oneshot::Receiver<()>)>,
+    max_queued: usize,
+}
+
+impl Ticket {
+    fn ready(state: Option<Arc<Mutex<State>>>) -> Self {
+        Self {
+            state,
+            wait: None,
+            max_queued: 0,
+        }
+    }
+
+    /// Wait for the permit; fails if the ticket was shed to make room for a more urgent one
+    pub async fn granted(self) -> Result<Permit, ExecutionError> {
+        self.granted_reporting(|_| {}).await
+    }
+
+    /// Wait for the permit like `granted`, calling `report` with the ticket's place in the
+    /// queue on entering it and whenever the place changes
+    pub async fn granted_reporting(
+        mut self,
+        mut report: impl FnMut(usize),
+    ) -> Result<Permit, ExecutionError> {
+        if let Some(state) = self.state.clone()
+            && self.wait.is_some()
+        {
+            let mut changes = state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .changes
+                .subscribe();
+            let mut reported = None;
+            loop {
+                changes.borrow_and_update();
+                if let Some(position) = self.position().filter(|p| Some(*p) != reported) {
+                    reported = Some(position);
+                    report(position);
+                }
+                let Some((_, _, granted)) = &mut self.wait else {
+                    break;
+                };
+                tokio::select! {
+                    result = granted => {
+                        self.wait = None;
+                        if result.is_err() {
+                            self.state = None;
+                            return Err(overloaded(self.max_queued));
+                        }
+                    }
+                    _ = changes.changed() => {}
+                }
+            }
+        }
+        Ok(Permit {
+            state: self.state.take(),
+        })
+    }
+
+    /// Place in the queue while waiting for the permit
+    pub fn position(&self) -> Option<usize> {
+        let (state, (id, priority, _)) = (self.state.as_ref()?, self.wait.as_ref()?);
+        state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .position(*id, *priority)
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        match self.wait.take() {
+            Some((id, priority, mut granted)) => {
+                if granted.try_recv().is_ok() {
+                    // Granted just as the wait was abandoned
+                    state.release();
+                } else {
+                    state.queues[priority.queue()].retain(|waiter| waiter.id != id);
+                    state.changes.send_replace(());
+                }
+            }
+            None => state.release(),
+        }
+    }
+}
+
+/// Permission to run one execution, handed on when dropped
+#[derive(Debug)]
+pub struct Permit {
+    state: Option<Arc<Mutex<State>>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(state) = &self.state {
+            state.lock().unwrap_or_else(|e| e.into_inner()).release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether `ticket` has its permit, without giving up its place
+    fn granted(ticket: &mut Ticket) -> bool {
+        match &mut ticket.wait {
+            Some((_, _, granted)) => match granted.try_recv() {
+                Ok(()) => {
+                    ticket.wait = None;
+                    true
+                }
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+
+    #[test]
+    fn test_permits_go_to_the_most_urgent_first() {
+        let dispatcher = Dispatcher::new(Some(1), 10);
+        let running = dispatcher.enqueue(Priority::Low).unwrap();
+        assert!(running.wait.is_none());
+
+        let mut low = dispatcher.enqueue(Priority::Low).unwrap();
+        let mut normal = dispatcher.enqueue(Priority::Normal).unwrap();
+        let mut high = dispatcher.enqueue(Priority::High).unwrap();
+        assert_eq!(dispatcher.queued(), 3);
+
+        drop(running);
+        assert!(granted(&mut high));
+        assert!(!granted(&mut normal) && !granted(&mut low));
+        drop(high);
+        assert!(granted(&mut normal));
+        drop(normal);
+        assert!(granted(&mut low));
+        drop(low);
+        assert_eq!(dispatcher.queued(), 0);
+
+        // The permit came back, so the next request runs at once
+        assert!(dispatcher.enqueue(Priority::Low).unwrap().wait.is_none());
+    }
+
+    #[test]
+    fn test_low_priority_ages_past_a_stream_of_high() {
+        let dispatcher = Dispatcher::new(Some(1), 10);
+        let mut running = dispatcher.enqueue(Priority::High).unwrap();
+        let mut low = dispatcher.enqueue(Priority::Low).unwrap();
+
+        let mut passed_over = 0;
+        loop {
+            // Always a fresh high-priority request waiting when the permit frees up
+            let mut high = dispatcher.enqueue(Priority::High).unwrap();
+            drop(running);
+            if granted(&mut low) {
+                break;
+            }
+            assert!(granted(&mut high));
+            passed_over += 1;
+            running = high;
+        }
+        assert_eq!(passed_over, AGING_LIMIT);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_sheds_the_least_urgent() {
+        let dispatcher = Dispatcher::new(Some(1), 2);
+        let _running = dispatcher.enqueue(Priority::Normal).unwrap();
+        let older_low = dispatcher.enqueue(Priority::Low).unwrap();
+        let newer_low = dispatcher.enqueue(Priority::Low).unwrap();
+
+        // The newest low-priority request makes room for a high-priority one
+        let _high = dispatcher.enqueue(Priority::High).unwrap();
+        let err = newer_low.granted().await.unwrap_err();
+        assert_eq!(err.code(), "overloaded");
+        assert_eq!(dispatcher.queued(), 2);
+
+        // Nothing less urgent is left to shed for another low-priority request
+        let err = dispatcher.enqueue(Priority::Low).unwrap_err();
+        assert!(matches!(err, ExecutionError::Overloaded(_)), "{err}");
+        drop(older_low);
+        assert_eq!(dispatcher.queued(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_waiters_hear_their_place_as_the_queue_moves() {
+        let dispatcher = Dispatcher::new(Some(1), 10);
+        let running = dispatcher.enqueue(Priority::Normal).unwrap();
+        let normal = dispatcher.enqueue(Priority::Normal).unwrap();
+        let low = dispatcher.enqueue(Priority::Low).unwrap();
+        assert_eq!((normal.position(), low.position()), (Some(1), Some(2)));
+
+        let places = Arc::new(Mutex::new(Vec::new()));
+        let waiting = tokio::spawn({
+            let places = places.clone();
+            async move {
+                low.granted_reporting(|place| places.lock().unwrap().push(place))
+                    .await
+            }
+        });
+        let heard = |count: usize| {
+            let places = places.clone();
+            async move {
+                while places.lock().unwrap().len() < count {
+                    tokio::task::yield_now().await;
+                }
+            }
+        };
+        heard(1).await;
+        // A more urgent arrival goes ahead of it
+        let high = dispatcher.enqueue(Priority::High).unwrap();
+        heard(2).await;
+        drop(running);
+        let high = high.granted().await.unwrap();
+        heard(3).await;
+        drop(normal);
+        heard(4).await;
+        drop(high);
+        let _permit = waiting.await.unwrap().unwrap();
+        assert_eq!(*places.lock().unwrap(), [2, 3, 2, 1]);
+    }
+
+    #[test]
+    fn test_unlimited_dispatcher_never_queues() {
+        let dispatcher = Dispatcher::new(None, 0);
+        let tickets: Vec<_> = (0..10)
+            .map(|_| dispatcher.enqueue(Priority::Low).unwrap())
+            .collect();
+        assert!(tickets.iter().all(|ticket| ticket.wait.is_none()));
+        assert_eq!(dispatcher.queued(), 0);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::history::{ExecutionRecord, now_millis};
+use crate::jobs::{Job, JobQueue, JobStore, QueuedJob, StoreError};
+use once_cell::sync::Lazy;
+use redis::aio::MultiplexedConnection;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Prepended to every key, so the server can be shared with other applications
+pub const KEY_PREFIX: &str = "fc:";
+
+/// Execution records kept in Redis; older ones are trimmed on insert
+const MAX_EXECUTIONS: isize = 10_000;
+
+/// Time allowed for each command on the store's connection
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requeues the claims older than ARGV[2] (ms) from the processing list KEYS[1] to the head
+/// of the queue KEYS[3]. A job moved to processing by a worker that died before recording its
+/// claim in KEYS[2] gets a claim dated now, so it is requeued one visibility timeout later.
+static RECLAIM: Lazy<redis::Script> = Lazy::new(|| {
+    redis::Script::new(
+        r"
+        local requeued = 0
+        for _, id in ipairs(redis.call('LRANGE', KEYS[1], 0, -1)) do
+            local claimed = redis.call('ZSCORE', KEYS[2], id)
+            if not claimed then
+                redis.call('ZADD', KEYS[2], ARGV[1], id)
+            elseif tonumber(claimed) < tonumber(ARGV[2]) then
+                redis.call('LREM', KEYS[1], 1, id)
+                redis.call('ZREM', KEYS[2], id)
+                redis.call('LPUSH', KEYS[3], id)
+                requeued = requeued + 1
+            end
+        end
+        return requeued
+        ",
+    )
+});
+
+fn backend(e: impl std::fmt::Display) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
+
+fn client(url: &str) -> Result<redis::Client, StoreError> {
+    redis::Client::open(url).map_err(backend)
+}
+
+/// Jobs as hashes `{prefix}job:{id}` that expire `ttl` after they finish, and the execution
+/// history as a capped list, so any instance can answer `GET /jobs/{id}`
+pub struct RedisJobStore {
+    connection: Mutex<redis::Connection>,
+    prefix: String,
+    ttl: Duration,
+}
+
+impl std::fmt::Debug for RedisJobStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisJobStore")
+            .field("prefix", &self.prefix)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisJobStore {
+    pub fn connect(url: &str, prefix: &str, ttl: Duration) -> Result<Self, StoreError> {
+        let connection = client(url)?
+            .get_connection_with_timeout(COMMAND_TIMEOUT)
+            .map_err(backend)?;
+        connection
+            .set_read_timeout(Some(COMMAND_TIMEOUT))
+            .map_err(backend)?;
+        connection
+            .set_write_timeout(Some(COMMAND_TIMEOUT))
+            .map_err(backend)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            prefix: prefix.to_string(),
+            ttl,
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, redis::Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn job_key(&self, id: &str) -> String {
+        format!("{}job:{id}", self.prefix)
+    }
+
+    /// Sorted set of finished job IDs by finish time, for `remove_expired`
+    fn finished_key(&self) -> String {
+        format!("{}finished", self.prefix)
+    }
+
+    fn executions_key(&self) -> String {
+        format!("{}executions", self.prefix)
+    }
+}
+
+impl JobStore for RedisJobStore {
+    fn put(&self, job: &Job) -> Result<(), StoreError> {
+        let key = self.job_key(&job.id);
+        let document = serde_json::to_string(job).map_err(backend)?;
+        let mut pipe = redis::pipe();
+        pipe.atomic()
+            .cmd("HSET")
+            .arg(&key)
+            .arg("status")
+            .arg(job.status.as_str())
+            .arg("document")
+            .arg(document)
+            .ignore();
+        if let Some(finished_at) = job.finished_at {
+            pipe.cmd("EXPIRE")
+                .arg(&key)
+                .arg(self.ttl.as_secs().max(1))
+                .ignore()
+                .cmd("ZADD")
+                .arg(self.finished_key())
+                .arg(finished_at)
+                .arg(&job.id)
+                .ignore();
+        }
+        pipe.query::<()>(&mut *self.connection()).map_err(backend)
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Job>, StoreError> {
+        let document: Option<String> = redis::cmd("HGET")
+            .arg(self.job_key(id))
+            .arg("document")
+            .query(&mut *self.connection())
+            .map_err(backend)?;
+        document
+            .map(|document| serde_json::from_str(&document).map_err(backend))
+            .transpose()
+    }
+
+    /// Nothing to do: jobs of a stopped instance are requeued by the other instances'
+    /// `JobQueue::reclaim` and run again rather than failed
+    fn fail_interrupted(&self) -> Result<usize, StoreError> {
+        Ok(0)
+    }
+
+    /// Finished jobs also expire on their own `ttl` after being stored; this removes them on
+    /// the caller's schedule, along with execution records older than `cutoff`
+    fn remove_expired(&self, cutoff: u64) -> Result<usize, StoreError> {
+        let mut connection = self.connection();
+        let expired: Vec<String> = redis::cmd("ZRANGE")
+            .arg(self.finished_key())
+            .arg("-inf")
+            .arg(format!("({cutoff}"))
+            .arg("BYSCORE")
+            .query(&mut *connection)
+            .map_err(backend)?;
+        let mut removed = 0;
+        for id in &expired {
+            let (deleted,): (usize,) = redis::pipe()
+                .atomic()
+                .cmd("DEL")
+                .arg(self.job_key(id))
+                .cmd("ZREM")
+                .arg(self.finished_key())
+                .arg(id)
+                .ignore()
+                .query(&mut *connection)
+                .map_err(backend)?;
+            removed += deleted;
+        }
+
+        let key = self.executions_key();
+        loop {
+            let oldest: Option<String> = redis::cmd("LINDEX")
+                .arg(&key)
+                .arg(-1)
+                .query(&mut *connection)
+                .map_err(backend)?;
+            let Some(oldest) = oldest else { break };
+            let started_at = serde_json::from_str::<ExecutionRecord>(&oldest)
+                .map(|record| record.started_at)
+                .unwrap_or_default();
+            if started_at >= cutoff {
+                break;
+            }
+            redis::cmd("RPOP")
+                .arg(&key)
+                .query::<()>(&mut *connection)
+                .map_err(backend)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<(), StoreError> {
+        let key = self.executions_key();
+        redis::pipe()
+            .atomic()
+            .cmd("LPUSH")
+            .arg(&key)
+            .arg(serde_json::to_string(record).map_err(backend)?)
+            .ignore()
+            .cmd("LTRIM")
+            .arg(&key)
+            .arg(0)
+            .arg(MAX_EXECUTIONS - 1)
+            .ignore()
+            .query::<()>(&mut *self.connection())
+            .map_err(backend)
+    }
+
+    fn recent_executions(&self, limit: usize) -> Result<Vec<ExecutionRecord>, StoreError> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let records: Vec<String> = redis::cmd("LRANGE")
+            .arg(self.executions_key())
+            .arg(0)
+            .arg(limit.min(MAX_EXECUTIONS as usize) as isize - 1)
+            .query(&mut *self.connection())
+            .map_err(backend)?;
+        records
+            .iter()
+            .map(|record| serde_json::from_str(record).map_err(backend))
+            .collect()
+    }
+}
+
+/// Reliable queue on Redis lists: `pop` atomically moves an ID from `{prefix}queue` to
+/// `{prefix}processing` and records the claim time in `{prefix}claims`; the request itself
+/// waits in `{prefix}queued:{id}` until the job is acked
+#[derive(Debug)]
+pub struct RedisJobQueue {
+    client: redis::Client,
+    /// Shared by the non-blocking commands
+    connection: MultiplexedConnection,
+    /// One connection per worker, since `BLMOVE` blocks the connection it runs on
+    workers: Mutex<HashMap<String, MultiplexedConnection>>,
+    prefix: String,
+}
+
+impl RedisJobQueue {
+    pub async fn connect(url: &str, prefix: &str) -> Result<Self, StoreError> {
+        let client = client(url)?;
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(backend)?;
+        Ok(Self {
+            client,
+            connection,
+            workers: Mutex::new(HashMap::new()),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    fn queued_key(&self, id: &str) -> String {
+        format!("{}queued:{id}", self.prefix)
+    }
+
+    async fn worker_connection(&self, worker: &str) -> Result<MultiplexedConnection, StoreError> {
+        let cached = self
+            .workers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(worker);
+        match cached {
+            Some(connection) => Ok(connection),
+            None => self
+                .client
+                .get_multiplexed_async_connection()
+                .await
+                .map_err(backend),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueue for RedisJobQueue {
+    async fn push(&self, job: QueuedJob) -> Result<(), StoreError> {
+        let request = serde_json::to_string(&job).map_err(backend)?;
+        redis::pipe()
+            .atomic()
+            .cmd("HSET")
+            .arg(self.queued_key(&job.id))
+            .arg("job")
+            .arg(request)
+            .arg("attempts")
+            .arg(0)
+            .ignore()
+            .cmd("RPUSH")
+            .arg(self.key("queue"))
+            .arg(&job.id)
+            .ignore()
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(backend)
+    }
+
+    async fn pop(&self, worker: &str, wait: Duration) -> Result<Option<QueuedJob>, StoreError> {
+        let mut connection = self.worker_connection(worker).await?;
+        connection.set_response_timeout(wait + COMMAND_TIMEOUT);
+        let id: Option<String> = redis::cmd("BLMOVE")
+            .arg(self.key("queue"))
+            .arg(self.key("processing"))
+            .arg("LEFT")
+            .arg("RIGHT")
+            .arg(wait.as_secs_f64())
+            .query_async(&mut connection)
+            .await
+            .map_err(backend)?;
+        // A connection that failed is dropped rather than reused
+        self.workers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(worker.to_string(), connection);
+        let Some(id) = id else {
+            return Ok(None);
+        };
+
+        let queued_key = self.queued_key(&id);
+        let (attempts, request): (u32, Option<String>) = redis::pipe()
+            .atomic()
+            .cmd("ZADD")
+            .arg(self.key("claims"))
+            .arg(now_millis())
+            .arg(&id)
+            .ignore()
+            .cmd("HINCRBY")
+            .arg(&queued_key)
+            .arg("attempts")
+            .arg(1)
+            .cmd("HGET")
+            .arg(&queued_key)
+            .arg("job")
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(backend)?;
+        let Some(request) = request else {
+            // Acked by a worker whose claim had been reclaimed in the meantime
+            self.ack(&id).await?;
+            return Ok(None);
+        };
+        let mut job: QueuedJob = serde_json::from_str(&request).map_err(backend)?;
+        job.attempts = attempts;
+        Ok(Some(job))
+    }
+
+    async fn ack(&self, id: &str) -> Result<(), StoreError> {
+        redis::pipe()
+            .atomic()
+            .cmd("LREM")
+            .arg(self.key("processing"))
+            .arg(0)
+            .arg(id)
+            .ignore()
+            .cmd("ZREM")
+            .arg(self.key("claims"))
+            .arg(id)
+            .ignore()
+            .cmd("DEL")
+            .arg(self.queued_key(id))
+            .ignore()
+            .query_async::<()>(&mut self.connection.clone())
+            .await
+            .map_err(backend)
+    }
+
+    async fn reclaim(&self, visibility: Duration) -> Result<usize, StoreError> {
+        let now = now_millis();
+        RECLAIM
+            .key(self.key("processing"))
+            .key(self.key("claims"))
+            .key(self.key("queue"))
+            .arg(now)
+            .arg(now.saturating_sub(visibility.as_millis() as u64))
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(backend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::tests::{check_queue_contract, check_store_contract};
+
+    /// Server the tests run against, e.g. `redis://127.0.0.1/`; they are skipped without one
+    fn test_url() -> Option<String> {
+        let url = std::env::var("FC_TEST_REDIS_URL").ok();
+        if url.is_none() {
+            eprintln!("FC_TEST_REDIS_URL is not set; skipping");
+        }
+        url
+    }
+
+    /// A key prefix no other test run uses
+    fn test_prefix() -> String {
+        format!("fc-test:{}:", crate::generate_request_id())
+    }
+
+    #[test]
+    fn test_redis_store_contract() {
+        let Some(url) = test_url() else { return };
+        let store = RedisJobStore::connect(&url, &test_prefix(), Duration::from_secs(60)).unwrap();
+        check_store_contract(&store);
+    }
+
+    #[tokio::test]
+    async fn test_redis_queue_contract() {
+        let Some(url) = test_url() else { return };
+        let queue = RedisJobQueue::connect(&url, &test_prefix()).await.unwrap();
+        check_queue_contract(&queue).await;
+    }
+}
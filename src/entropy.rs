@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+/// Body of Firecracker's `PUT /entropy`; the device is fed from the host's `/dev/urandom`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EntropyDevice {
+    /// Cap on the bytes per second handed to the guest; unlimited when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entropy_body() {
+        assert_eq!(
+            serde_json::to_string(&EntropyDevice::default()).unwrap(),
+            "{}"
+        );
+    }
+}
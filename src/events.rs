@@ -0,0 +1,249 @@
+use crate::history::now_millis;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use utoipa::ToSchema;
+
+/// Number of events buffered per subscriber before it starts lagging
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Latest events kept for the state document
+pub const RECENT_EVENT_CAPACITY: usize = 256;
+
+/// Why a VM was shut down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscardReason {
+    /// The pool was already full when the VM booted or came back from an execution
+    PoolFull,
+    /// The pool was reloaded with a smaller size
+    PoolResized,
+    /// Older than `FC_VM_MAX_AGE_SECS`
+    MaxAge,
+    /// Ran `FC_VM_MAX_REUSE` executions
+    MaxReuse,
+    /// Firecracker's resident memory passed `FC_VM_MAX_HOST_RSS_MIB`
+    HostRss,
+    /// Idle in the pool past the idle TTL
+    IdleTtl,
+    /// The Firecracker process exited on its own
+    Exited,
+    /// Failed too many health probes in a row
+    Unhealthy,
+    /// Booted for one request alone, which has finished
+    Dedicated,
+    /// The execution failed, leaving the guest in an unknown state
+    ExecutionError,
+    /// The guest ran out of memory
+    OutOfMemory,
+    /// Its guest port couldn't be forwarded
+    ExposeFailed,
+    /// Its exposed port's time ran out
+    ExposeExpired,
+    /// The guest couldn't get its memory back before an execution
+    BalloonDeflateFailed,
+    /// The guest clock couldn't be synced before an execution
+    ClockSyncFailed,
+    /// The request was cancelled mid-execution
+    Cancelled,
+    /// The guest agent failed to delete an execution's workspace
+    WorkspaceLeftover,
+    /// The warm-up snippet failed and warm-up failures are fatal
+    WarmupFailed,
+    /// The VM never became ready
+    BootFailed,
+    /// Its sandbox's time ran out
+    SandboxExpired,
+    /// Its sandbox was deleted
+    SandboxDeleted,
+    /// The executor is shutting down
+    Shutdown,
+}
+
+impl DiscardReason {
+    /// Label of the reason in logs and metrics
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiscardReason::PoolFull => "pool_full",
+            DiscardReason::PoolResized => "pool_resized",
+            DiscardReason::MaxAge => "max_age",
+            DiscardReason::MaxReuse => "max_reuse",
+            DiscardReason::HostRss => "host_rss",
+            DiscardReason::IdleTtl => "idle_ttl",
+            DiscardReason::Exited => "exited",
+            DiscardReason::Unhealthy => "unhealthy",
+            DiscardReason::Dedicated => "dedicated",
+            DiscardReason::ExecutionError => "execution_error",
+            DiscardReason::OutOfMemory => "out_of_memory",
+            DiscardReason::ExposeFailed => "expose_failed",
+            DiscardReason::ExposeExpired => "expose_expired",
+            DiscardReason::BalloonDeflateFailed => "balloon_deflate_failed",
+            DiscardReason::ClockSyncFailed => "clock_sync_failed",
+            DiscardReason::Cancelled => "cancelled",
+            DiscardReason::WorkspaceLeftover => "workspace_leftover",
+            DiscardReason::WarmupFailed => "warmup_failed",
+            DiscardReason::BootFailed => "boot_failed",
+            DiscardReason::SandboxExpired => "sandbox_expired",
+            DiscardReason::SandboxDeleted => "sandbox_deleted",
+            DiscardReason::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl std::fmt::Display for DiscardReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// VM lifecycle event published by the runner
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VmEvent {
+    /// A VM manager was created and is about to boot
+    Created { vm_id: String },
+    /// The guest API server answered its first health check
+    BootReady { vm_id: String, boot_ms: u64 },
+    /// A VM was handed to a request
+    Acquired { vm_id: String, request_id: String },
+    /// A VM finished a request and went back to the pool
+    Released { vm_id: String },
+    /// A VM is being shut down and cleaned up
+    Discarded {
+        vm_id: String,
+        reason: DiscardReason,
+    },
+    /// The Firecracker process exited on its own
+    Crashed {
+        vm_id: String,
+        exit_code: Option<i32>,
+    },
+}
+
+impl VmEvent {
+    /// Event name used for the SSE `event:` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            VmEvent::Created { .. } => "created",
+            VmEvent::BootReady { .. } => "boot_ready",
+            VmEvent::Acquired { .. } => "acquired",
+            VmEvent::Released { .. } => "released",
+            VmEvent::Discarded { .. } => "discarded",
+            VmEvent::Crashed { .. } => "crashed",
+        }
+    }
+
+    /// VM the event refers to
+    pub fn vm_id(&self) -> &str {
+        match self {
+            VmEvent::Created { vm_id }
+            | VmEvent::BootReady { vm_id, .. }
+            | VmEvent::Acquired { vm_id, .. }
+            | VmEvent::Released { vm_id }
+            | VmEvent::Discarded { vm_id, .. }
+            | VmEvent::Crashed { vm_id, .. } => vm_id,
+        }
+    }
+}
+
+/// Global lifecycle event channel. Slow subscribers lag instead of blocking publishers.
+pub static VM_EVENTS: once_cell::sync::Lazy<broadcast::Sender<VmEvent>> =
+    once_cell::sync::Lazy::new(|| broadcast::channel(EVENT_CHANNEL_CAPACITY).0);
+
+/// An event kept in the recent-events ring
+#[derive(Debug, Clone, Serialize, PartialEq, ToSchema)]
+pub struct RecordedEvent {
+    /// When it was published, in milliseconds since the Unix epoch
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: VmEvent,
+}
+
+/// The latest events, oldest first
+static RECENT_EVENTS: Lazy<Mutex<VecDeque<RecordedEvent>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_EVENT_CAPACITY)));
+
+/// Publish an event and keep it among the recent ones; subscribers only hear it if there are
+/// any
+pub fn publish(event: VmEvent) {
+    {
+        let mut recent = RECENT_EVENTS.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() >= RECENT_EVENT_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(RecordedEvent {
+            at: now_millis(),
+            event: event.clone(),
+        });
+    }
+    let _ = VM_EVENTS.send(event);
+}
+
+/// The latest events, oldest first
+pub fn recent_events() -> Vec<RecordedEvent> {
+    RECENT_EVENTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Subscribe to lifecycle events published from now on
+pub fn subscribe() -> broadcast::Receiver<VmEvent> {
+    VM_EVENTS.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        let event = VmEvent::BootReady {
+            vm_id: "vm-1".to_string(),
+            boot_ms: 120,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "type": "boot_ready", "vm_id": "vm-1", "boot_ms": 120 })
+        );
+        assert_eq!(event.name(), "boot_ready");
+    }
+
+    #[test]
+    fn test_discards_are_kept_among_recent_events() {
+        let vm_id = format!("vm-{}", uuid::Uuid::new_v4());
+        publish(VmEvent::Discarded {
+            vm_id: vm_id.clone(),
+            reason: DiscardReason::MaxReuse,
+        });
+        let recent = recent_events();
+        assert!(recent.len() <= RECENT_EVENT_CAPACITY);
+        let kept = recent.iter().find(|e| e.event.vm_id() == vm_id).unwrap();
+        assert_eq!(
+            serde_json::to_value(kept).unwrap()["reason"],
+            serde_json::json!("max_reuse")
+        );
+    }
+
+    #[test]
+    fn test_lagging_subscriber_does_not_block() {
+        let (sender, mut receiver) = broadcast::channel(2);
+        for i in 0..5 {
+            sender
+                .send(VmEvent::Released {
+                    vm_id: format!("vm-{i}"),
+                })
+                .unwrap();
+        }
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(3))
+        ));
+        assert_eq!(receiver.try_recv().unwrap().vm_id(), "vm-3");
+    }
+}
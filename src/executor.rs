@@ -0,0 +1,3070 @@
+use crate::admission::{ResourceProbe, ResourceUsage};
+use crate::breaker::{Attempt, BreakerStatus, CircuitBreaker};
+#[cfg(feature = "chaos")]
+use crate::chaos::FaultPoint;
+use crate::clock;
+use crate::config::{RunnerConfig, shared_runner_config};
+use crate::console;
+use crate::deadline::Deadline;
+use crate::debug_events::{self, DebugEvent, DebugEventKind};
+use crate::debug_state::{
+    self, AutoscaleState, ExecutorState, FdState, LiveVmState, RunningExecution,
+};
+use crate::dispatch::{Dispatcher, Permit, Priority, QueuePosition};
+use crate::events::{self, DiscardReason, VmEvent};
+use crate::fd_limit;
+use crate::history::{EXECUTION_HISTORY, ExecutionRecord, now_millis};
+use crate::port_forward::{ExposedPorts, PortForward};
+use crate::probe;
+use crate::quota::QuotaGuard;
+use crate::readiness::RunnerSnapshot;
+use crate::runner::{self, ExecutionSpec, VMManager};
+use crate::sandbox::Sandboxes;
+use crate::sub_pool::{DEFAULT_SUB_POOL, PoolKey, SubPoolState};
+use crate::telemetry;
+use crate::warmup::WarmupReport;
+use crate::warning::{Warning, WarningCode};
+use crate::workload::{Outcome, WorkloadLabels, WorkloadRecorder, WorkloadStats};
+use crate::{ExecuteResponse, ExecutionError, output};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, RwLock, watch};
+use tokio::task::JoinSet;
+use tracing::Instrument;
+
+/// Maximum number of idle VMs kept in an executor's pool, unless autoscaling sets its own
+pub const VM_POOL_SIZE: usize = 3;
+
+/// VMs the bundled server boots into the pool at startup
+pub const VM_PREWARM_COUNT: usize = 2;
+
+/// Affinity keys an executor remembers the VM of; the least recently used are forgotten first
+pub const AFFINITY_CAPACITY: usize = 1024;
+
+/// Age at which pooled VMs are retired by default, before guest clock drift, stale DNS and
+/// memory fragmentation build up
+pub const DEFAULT_VM_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Longest the recycler waits between checks for pooled VMs past their maximum age
+pub const RECYCLE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often a VM kept for an exposed port is checked for having exited
+const EXPOSED_VM_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often sandboxes past their deadline are reclaimed
+pub const SANDBOX_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Weight of the latest execution in the moving average of execution durations
+const DURATION_EWMA_WEIGHT: f64 = 0.2;
+
+/// Pool settings a reload can change while the executor runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolTunables {
+    /// Warm VMs kept in the pool when it isn't autoscaled
+    pub pool_size: usize,
+    /// Idle time after which VMs above the pool target are shut down
+    pub idle_ttl: std::time::Duration,
+    /// Cap on each of stdout and stderr returned from a VM, in bytes
+    pub max_output_bytes: usize,
+}
+
+impl PoolTunables {
+    /// The values `config` starts with
+    pub fn from_config(config: &RunnerConfig) -> Self {
+        Self {
+            pool_size: config.pool_size,
+            idle_ttl: config.autoscale.idle_ttl,
+            max_output_bytes: config.max_output_bytes,
+        }
+    }
+}
+
+/// A VM waiting in the pool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct PooledVm {
+    pub vm_id: String,
+    /// Seconds since the VM was created
+    pub age_secs: u64,
+    /// Seconds since it went back into the pool
+    pub idle_secs: u64,
+    /// Executions it has run
+    pub executions: u64,
+    /// How its warm-up code went, if any ran
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<WarmupReport>,
+    /// When the health prober last checked it, in milliseconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_probe_at: Option<u64>,
+}
+
+/// Point-in-time counters of an `ExecutorService`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct ExecutorStats {
+    /// Booted VMs waiting in the pool
+    pub idle_vms: usize,
+    pub pool_capacity: usize,
+    /// Warm VMs the pool is kept at; moved with demand when autoscaling
+    pub pool_target: usize,
+    /// Executions served by a VM from the pool
+    pub pool_hits: u64,
+    /// Executions that found no matching VM in the pool and booted one
+    pub pool_misses: u64,
+    /// Executions currently running
+    pub in_flight: usize,
+    /// Executions waiting for a permit under `max_concurrent_executions`
+    pub queued: usize,
+    /// VMs booted by this executor since it was created
+    pub vms_created: u64,
+    /// VMs that ran the warm-up code after booting, successfully or not
+    pub vms_warmed_up: u64,
+    /// Executions finished, successfully or not
+    pub executions: u64,
+    /// Executions whose caller gave up on them while they held a VM, which was discarded
+    pub cancelled: u64,
+    /// Executions with an affinity key that ran on the VM last used for it
+    pub affinity_hits: u64,
+    /// Executions with an affinity key that ran on another VM
+    pub affinity_misses: u64,
+    /// Moving average of how long executions hold their permit, in milliseconds; absent
+    /// before the first
+    pub avg_execution_ms: Option<u64>,
+    pub shutting_down: bool,
+}
+
+/// Runs programs on Firecracker VMs, owning the pool they come from and the background tasks
+/// that shut down discarded VMs. Clones share one executor.
+///
+/// Shutting it down, or dropping the last clone, cleans up every VM it created.
+#[derive(Clone)]
+pub struct ExecutorService {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: Arc<RunnerConfig>,
+    pool: Mutex<VecDeque<VMManager>>,
+    /// Held shared by every execution and exclusively by `shutdown`, which so waits for
+    /// in-flight executions to finish
+    running: RwLock<()>,
+    closed: AtomicBool,
+    /// Shutdowns of discarded VMs, awaited by `shutdown`
+    tasks: std::sync::Mutex<JoinSet<()>>,
+    in_flight: AtomicUsize,
+    /// Request ID and start of every execution holding a permit, by entry number
+    running_executions: std::sync::Mutex<HashMap<u64, (String, u64, std::time::Instant)>>,
+    next_execution: AtomicU64,
+    vms_created: AtomicU64,
+    vms_warmed_up: AtomicU64,
+    executions: AtomicU64,
+    cancelled: AtomicU64,
+    /// Exponentially weighted moving average of execution durations in milliseconds
+    avg_execution_ms: std::sync::Mutex<Option<f64>>,
+    affinity: std::sync::Mutex<Affinity>,
+    /// Execution permits, granted by priority
+    dispatcher: Dispatcher,
+    pool_target: AtomicUsize,
+    pool_hits: AtomicU64,
+    pool_misses: AtomicU64,
+    affinity_hits: AtomicU64,
+    affinity_misses: AtomicU64,
+    /// Stops cold starts while VM creation keeps failing
+    breaker: CircuitBreaker,
+    /// Settings of `config` as last reloaded
+    tunables: std::sync::Mutex<PoolTunables>,
+    /// Slots of the guest ports exposed at once
+    exposed_ports: ExposedPorts,
+    /// Set by `shutdown` to end every port forward early
+    closing_exposures: watch::Sender<bool>,
+    /// VMs kept after their execution for follow-ups, asked for with `keep_alive_seconds`
+    sandboxes: Sandboxes,
+    /// Code size, duration and outcome of executions by language, image and deps profile
+    workload: WorkloadRecorder,
+}
+
+/// VM that last served each affinity key, bounded to `AFFINITY_CAPACITY` keys
+#[derive(Debug, Default)]
+struct Affinity {
+    /// VM ID and last use of each key
+    vms: HashMap<String, (String, u64)>,
+    clock: u64,
+}
+
+impl Affinity {
+    fn vm_for(&self, key: &str) -> Option<&str> {
+        self.vms.get(key).map(|(vm_id, _)| vm_id.as_str())
+    }
+
+    fn remember(&mut self, key: &str, vm_id: &str) {
+        self.clock += 1;
+        if self.vms.len() >= AFFINITY_CAPACITY && !self.vms.contains_key(key) {
+            let oldest = self
+                .vms
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.vms.remove(&oldest);
+            }
+        }
+        self.vms
+            .insert(key.to_string(), (vm_id.to_string(), self.clock));
+    }
+
+    /// Drop the keys of a VM that is gone
+    fn forget_vm(&mut self, vm_id: &str) {
+        self.vms.retain(|_, (id, _)| id != vm_id);
+    }
+}
+
+impl ExecutorService {
+    pub fn new(config: Arc<RunnerConfig>) -> Self {
+        let dispatcher = Dispatcher::new(
+            config.max_concurrent_executions,
+            config.max_queued_executions,
+        );
+        let pool_target = if config.autoscale.enabled {
+            config.autoscale.clamp(config.pool_size)
+        } else {
+            config.pool_size
+        };
+        let breaker = CircuitBreaker::new(config.vm_creation_breaker.clone());
+        let tunables = std::sync::Mutex::new(PoolTunables::from_config(&config));
+        let exposed_ports = ExposedPorts::new(config.expose.max_exposed);
+        let sandboxes = Sandboxes::new(config.sandbox.max_sandboxes);
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                pool: Mutex::new(VecDeque::new()),
+                running: RwLock::new(()),
+                closed: AtomicBool::new(false),
+                tasks: std::sync::Mutex::new(JoinSet::new()),
+                in_flight: AtomicUsize::new(0),
+                running_executions: std::sync::Mutex::new(HashMap::new()),
+                next_execution: AtomicU64::new(0),
+                vms_created: AtomicU64::new(0),
+                vms_warmed_up: AtomicU64::new(0),
+                executions: AtomicU64::new(0),
+                cancelled: AtomicU64::new(0),
+                avg_execution_ms: std::sync::Mutex::new(None),
+                affinity: std::sync::Mutex::new(Affinity::default()),
+                dispatcher,
+                pool_target: AtomicUsize::new(pool_target),
+                pool_hits: AtomicU64::new(0),
+                pool_misses: AtomicU64::new(0),
+                affinity_hits: AtomicU64::new(0),
+                affinity_misses: AtomicU64::new(0),
+                breaker,
+                tunables,
+                exposed_ports,
+                closing_exposures: watch::Sender::new(false),
+                sandboxes,
+                workload: WorkloadRecorder::default(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &RunnerConfig {
+        &self.inner.config
+    }
+
+    /// Wait for an execution permit, giving up after the wait budget. A request that gave up
+    /// leaves the queue, so the next permit goes to one still waiting. A request that had to
+    /// queue has its place sent to `updates` as it changes, then a place of 0 once granted.
+    async fn permit(
+        &self,
+        priority: Priority,
+        updates: Option<&tokio::sync::mpsc::UnboundedSender<QueuePosition>>,
+    ) -> Result<Permit, ExecutionError> {
+        let ticket = self.inner.dispatcher.enqueue(priority)?;
+        let mut queued = false;
+        let granted = ticket.granted_reporting(|position| {
+            queued = true;
+            if let Some(updates) = updates {
+                let _ = updates.send(QueuePosition {
+                    position,
+                    eta_ms: self.estimated_wait_ms(position),
+                });
+            }
+        });
+        let permit = match self.inner.config.vm_wait_budget {
+            None => granted.await,
+            Some(budget) => self.within_wait_budget(budget, priority, granted).await,
+        };
+        if let (Ok(_), true, Some(updates)) = (&permit, queued, updates) {
+            let _ = updates.send(QueuePosition {
+                position: 0,
+                eta_ms: Some(0),
+            });
+        }
+        permit
+    }
+
+    /// Wait at most `budget` for `granted`
+    async fn within_wait_budget(
+        &self,
+        budget: std::time::Duration,
+        priority: Priority,
+        granted: impl Future<Output = Result<Permit, ExecutionError>>,
+    ) -> Result<Permit, ExecutionError> {
+        let waiting = std::time::Instant::now();
+        match tokio::time::timeout(budget, granted).await {
+            Ok(permit) => permit,
+            Err(_) => {
+                telemetry::increment_counter(
+                    "fc_pool_exhausted_total",
+                    &[("priority", priority.as_str())],
+                    1,
+                );
+                Err(ExecutionError::PoolExhausted {
+                    waited_ms: waiting.elapsed().as_millis() as u64,
+                    queue_depth: self.inner.dispatcher.queued(),
+                })
+            }
+        }
+    }
+
+    /// Expected wait in milliseconds of the execution at `position` in the queue: a round of
+    /// average executions for every `max_concurrent_executions` waiters up to and including it
+    fn estimated_wait_ms(&self, position: usize) -> Option<u64> {
+        let average = (*self
+            .inner
+            .avg_execution_ms
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()))?;
+        let permits = self.inner.config.max_concurrent_executions?.max(1);
+        Some((average * position.div_ceil(permits) as f64).round() as u64)
+    }
+
+    /// Fold an execution that took `duration` into the average
+    fn record_duration(&self, duration: std::time::Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        let mut average = self
+            .inner
+            .avg_execution_ms
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *average = Some(match *average {
+            Some(average) => average + DURATION_EWMA_WEIGHT * (ms - average),
+            None => ms,
+        });
+    }
+
+    /// Run `spec` on a pooled (or freshly booted) VM, recording the outcome in the execution
+    /// history
+    pub async fn execute(&self, spec: ExecutionSpec) -> Result<ExecuteResponse, ExecutionError> {
+        let _running = self.inner.running.read().await;
+        if self.inner.closed.load(Ordering::SeqCst) {
+            return Err(ExecutionError::ShuttingDown);
+        }
+        // Waiting for a permit, booting and running all come out of the request's time
+        let deadline = Deadline::after(spec.timeout.unwrap_or(runner::EXECUTION_BUDGET));
+        let Some(quick) = spec.quick else {
+            return self.run(spec, deadline).await;
+        };
+        // Refused before it queues for a permit it would only give up on a boot
+        if !self.has_warm_vm(&spec).await {
+            telemetry::increment_counter(
+                "fc_quick_rejections_total",
+                &[("reason", "no_warm_vm")],
+                1,
+            );
+            return Err(ExecutionError::NoWarmVm);
+        }
+        let request_id = spec.request_id.clone();
+        match tokio::time::timeout(quick.timeout, self.run(spec, deadline)).await {
+            Ok(result) => result,
+            // Dropping the execution discards its VM, which may still be running the code
+            Err(_) => {
+                telemetry::increment_counter(
+                    "fc_quick_rejections_total",
+                    &[("reason", "timeout")],
+                    1,
+                );
+                let e = ExecutionError::QuickTimeout {
+                    timeout_ms: quick.timeout.as_millis() as u64,
+                };
+                debug_state::record_error(Some(&request_id), e.code(), &e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Run `spec` once the executor has taken it on
+    async fn run(
+        &self,
+        spec: ExecutionSpec,
+        deadline: Deadline,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        // Held until the VM is back in the pool or discarded
+        let queued_at = std::time::Instant::now();
+        let _permit = self
+            .permit(spec.priority, spec.queue_updates.as_ref())
+            .await?;
+        let queued_for = queued_at.elapsed();
+        let in_flight = InFlight::enter(&self.inner, &spec.request_id);
+        let started_at = now_millis();
+        let start = std::time::Instant::now();
+        let mut vm_id = None;
+        let max_output_bytes =
+            output::effective_limit(self.tunables().max_output_bytes, spec.max_output_bytes);
+
+        let span = tracing::info_span!(
+            "run_in_vm",
+            request_id = %spec.request_id,
+            vm_id = tracing::field::Empty,
+            pool_hit = tracing::field::Empty,
+        );
+        let execution = self
+            .execute_in_pooled_vm(&spec, deadline, max_output_bytes, &mut vm_id)
+            .instrument(span);
+        let result = if spec.debug {
+            let (result, events) = debug_events::collect(async {
+                debug_events::record(
+                    DebugEvent::new(DebugEventKind::PermitGranted).duration(queued_for),
+                );
+                execution.await
+            })
+            .await;
+            result.map(|mut response| {
+                response.events = Some(events);
+                response
+            })
+        } else {
+            execution.await
+        };
+        drop(in_flight);
+        self.record_run(&spec, vm_id, started_at, start.elapsed(), &result);
+        result
+    }
+
+    /// Count a finished execution of `spec` on VM `vm_id` and add it to the history
+    fn record_run(
+        &self,
+        spec: &ExecutionSpec,
+        vm_id: Option<String>,
+        started_at: u64,
+        elapsed: std::time::Duration,
+        result: &Result<ExecuteResponse, ExecutionError>,
+    ) {
+        self.inner.executions.fetch_add(1, Ordering::Relaxed);
+        self.record_duration(elapsed);
+        self.inner.workload.record(
+            &WorkloadLabels::of(spec, &self.inner.config),
+            Outcome::of(result),
+            spec.program.source_len(),
+            elapsed,
+        );
+
+        let (success, error_code, stdout_len, stderr_len) = match result {
+            Ok(response) => (
+                response.success,
+                None,
+                response.stdout.len(),
+                response.stderr.len(),
+            ),
+            Err(e) => {
+                debug_state::record_error(Some(&spec.request_id), e.code(), &e.to_string());
+                (false, Some(e.code().to_string()), 0, 0)
+            }
+        };
+        EXECUTION_HISTORY.push(ExecutionRecord {
+            request_id: spec.request_id.clone(),
+            vm_id,
+            started_at,
+            duration_ms: elapsed.as_millis() as u64,
+            success,
+            error_code,
+            code_sha256: spec.program.sha256(),
+            stdout_len,
+            stderr_len,
+        });
+    }
+
+    /// Run `spec` on the VM of sandbox `sandbox_id`, which must belong to the tenant `owner`,
+    /// once it gets a permit like any other execution. The sandbox keeps its deadline; a failed
+    /// execution discards it along with its VM.
+    pub async fn execute_in_sandbox(
+        &self,
+        sandbox_id: &str,
+        owner: Option<&str>,
+        spec: ExecutionSpec,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        let _running = self.inner.running.read().await;
+        if self.inner.closed.load(Ordering::SeqCst) {
+            return Err(ExecutionError::ShuttingDown);
+        }
+        let deadline = Deadline::after(spec.timeout.unwrap_or(runner::EXECUTION_BUDGET));
+        let _permit = self
+            .permit(spec.priority, spec.queue_updates.as_ref())
+            .await?;
+        let max_output_bytes =
+            output::effective_limit(self.tunables().max_output_bytes, spec.max_output_bytes);
+        let (vm, expires_at_ms) = self.inner.sandboxes.take(sandbox_id, owner)?;
+        let in_flight = InFlight::enter(&self.inner, &spec.request_id);
+        let started_at = now_millis();
+        let start = std::time::Instant::now();
+        // Discarded if the caller goes away mid-execution
+        let mut vm = VmLease::new(self, vm);
+        let span = tracing::info_span!(
+            "execute",
+            request_id = %spec.request_id,
+            vm_id = %vm.vm_id(),
+            sandbox_id,
+        );
+        let result = vm
+            .execute_code_via_api(
+                &spec.program,
+                &spec.requirements,
+                &spec.inputs,
+                max_output_bytes,
+                deadline,
+                spec.output.as_ref(),
+            )
+            .instrument(span)
+            .await;
+        drop(in_flight);
+        self.record_run(
+            &spec,
+            Some(vm.vm_id().to_string()),
+            started_at,
+            start.elapsed(),
+            &result,
+        );
+        match result {
+            Ok(mut response) => {
+                vm.record_execution();
+                response.vm_id = Some(vm.vm_id().to_string());
+                response.sandbox_id = Some(sandbox_id.to_string());
+                response.sandbox_expires_at_ms = Some(expires_at_ms);
+                if let Some((vm, reason)) = self.inner.sandboxes.put_back(sandbox_id, vm.release())
+                {
+                    self.discard_vm(vm, reason);
+                }
+                Ok(response)
+            }
+            Err(e) => {
+                let _ = self.inner.sandboxes.remove(sandbox_id, owner);
+                let reason = match e {
+                    ExecutionError::GuestOutOfMemory { .. } => DiscardReason::OutOfMemory,
+                    _ => DiscardReason::ExecutionError,
+                };
+                self.discard_vm(vm.release(), reason);
+                Err(e)
+            }
+        }
+    }
+
+    /// Delete sandbox `sandbox_id` of the tenant `owner`, discarding its VM
+    pub fn delete_sandbox(
+        &self,
+        sandbox_id: &str,
+        owner: Option<&str>,
+    ) -> Result<(), ExecutionError> {
+        // One running an execution is discarded once the execution is done
+        if let Some(vm) = self.inner.sandboxes.remove(sandbox_id, owner)? {
+            self.discard_vm(vm, DiscardReason::SandboxDeleted);
+        }
+        Ok(())
+    }
+
+    /// Give sandbox `sandbox_id`, just kept for an execution, to the tenant `owner` along with
+    /// its concurrency slot `quota`
+    pub fn assign_sandbox(&self, sandbox_id: &str, owner: Option<&str>, quota: QuotaGuard) {
+        self.inner.sandboxes.assign(sandbox_id, owner, quota);
+    }
+
+    /// Discard the VMs of sandboxes past their deadline; returns how many sandboxes held one
+    pub fn reclaim_expired_sandboxes(&self) -> usize {
+        let expired = self.inner.sandboxes.remove_expired();
+        let reclaimed = expired.len();
+        for vm in expired {
+            self.discard_vm(vm, DiscardReason::SandboxExpired);
+        }
+        reclaimed
+    }
+
+    /// Reclaim sandboxes as they pass their deadline; does nothing with sandboxes off
+    pub fn spawn_sandbox_sweeper(&self) -> Option<tokio::task::JoinHandle<()>> {
+        if self.inner.config.sandbox.max_sandboxes == 0 {
+            return None;
+        }
+        let executor = self.clone();
+        Some(tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(SANDBOX_SWEEP_INTERVAL);
+            loop {
+                ticks.tick().await;
+                if executor.inner.closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                executor.reclaim_expired_sandboxes();
+            }
+        }))
+    }
+
+    /// Boot up to `count` VMs into the default pool, stopping once it is full; returns how
+    /// many were added. Failed boots are logged and skipped.
+    pub async fn warm(&self, count: usize) -> usize {
+        self.warm_with(&runner::VmOptions::default(), count).await
+    }
+
+    /// Boot VMs into every sub-pool below its minimum; returns how many were added
+    pub async fn warm_sub_pools(&self) -> usize {
+        let mut added = 0;
+        for (image, sub_pool) in &self.inner.config.sub_pools {
+            let idle = self.pooled_count(PoolKey::Sub(image, *sub_pool)).await;
+            if idle < sub_pool.min_warm {
+                let options = runner::VmOptions {
+                    image: Some(image.clone()),
+                    ..Default::default()
+                };
+                added += self.warm_with(&options, sub_pool.min_warm - idle).await;
+            }
+        }
+        added
+    }
+
+    /// Boot up to `count` VMs set up with `options` into their pool, stopping once it is full
+    async fn warm_with(&self, options: &runner::VmOptions, count: usize) -> usize {
+        let mut added = 0;
+        for i in 1..=count {
+            if self.inner.closed.load(Ordering::SeqCst) {
+                break;
+            }
+            if let Some(usage) = self.fd_shortage() {
+                tracing::warn!(
+                    "Not growing the pool: {} of {} file descriptors open, below the headroom of {}",
+                    usage.open,
+                    usage.limit,
+                    self.inner.config.fd_limit.min_headroom
+                );
+                break;
+            }
+            // No request waits on a pool boot, so it only has the runner's own limits
+            let deadline = Deadline::after(runner::EXECUTION_BUDGET);
+            match self.create_vm(options, deadline).await {
+                Ok(mut vm) => {
+                    vm.inflate_balloon().await;
+                    let mut pool = self.inner.pool.lock().await;
+                    if !self.has_room(&pool, &vm) {
+                        self.discard_vm(vm, DiscardReason::PoolFull);
+                        break;
+                    }
+                    vm.mark_idle();
+                    pool.push_back(vm);
+                    added += 1;
+                    tracing::debug!("Pre-warmed VM {} added to pool", i);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to pre-warm VM {}: {}", i, e);
+                    debug_state::record_error(None, e.code(), &e.to_string());
+                }
+            }
+        }
+        added
+    }
+
+    /// The server's descriptor usage when fewer descriptors than the configured headroom are
+    /// left
+    fn fd_shortage(&self) -> Option<fd_limit::FdUsage> {
+        let min_headroom = self.inner.config.fd_limit.min_headroom;
+        if min_headroom == 0 {
+            return None;
+        }
+        fd_limit::usage().filter(|usage| usage.headroom < min_headroom)
+    }
+
+    /// Executions so far by language, image and deps profile
+    pub fn workload_stats(&self) -> Vec<WorkloadStats> {
+        self.inner.workload.snapshot()
+    }
+
+    pub async fn stats(&self) -> ExecutorStats {
+        let idle_vms = self.inner.pool.lock().await.len();
+        self.stats_with_idle(idle_vms)
+    }
+
+    fn stats_with_idle(&self, idle_vms: usize) -> ExecutorStats {
+        ExecutorStats {
+            idle_vms,
+            pool_capacity: self.pool_capacity(),
+            pool_target: self.pool_target(),
+            pool_hits: self.inner.pool_hits.load(Ordering::Relaxed),
+            pool_misses: self.inner.pool_misses.load(Ordering::Relaxed),
+            in_flight: self.inner.in_flight.load(Ordering::SeqCst),
+            queued: self.inner.dispatcher.queued(),
+            vms_created: self.inner.vms_created.load(Ordering::Relaxed),
+            vms_warmed_up: self.inner.vms_warmed_up.load(Ordering::Relaxed),
+            executions: self.inner.executions.load(Ordering::Relaxed),
+            cancelled: self.inner.cancelled.load(Ordering::Relaxed),
+            affinity_hits: self.inner.affinity_hits.load(Ordering::Relaxed),
+            affinity_misses: self.inner.affinity_misses.load(Ordering::Relaxed),
+            avg_execution_ms: self
+                .inner
+                .avg_execution_ms
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .map(|ms| ms.round() as u64),
+            shutting_down: self.inner.closed.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Most idle VMs the pool holds
+    pub fn pool_capacity(&self) -> usize {
+        let autoscale = &self.inner.config.autoscale;
+        if autoscale.enabled {
+            autoscale.max.max(autoscale.min)
+        } else {
+            VM_POOL_SIZE.max(self.tunables().pool_size)
+        }
+    }
+
+    /// Pool `vm` goes back to after an execution
+    fn pool_key<'a>(&self, vm: &'a VMManager) -> PoolKey<'a> {
+        PoolKey::of(&self.inner.config.sub_pools, vm.image(), vm.deps_profile())
+    }
+
+    /// Whether `pool` can take `vm` without its sub-pool, or the default pool, going past its
+    /// maximum
+    fn has_room(&self, pool: &VecDeque<VMManager>, vm: &VMManager) -> bool {
+        let key = self.pool_key(vm);
+        let max = match key {
+            PoolKey::Default => self.pool_capacity(),
+            PoolKey::Sub(_, sub_pool) => sub_pool.max,
+            PoolKey::OnDemand => return false,
+        };
+        pool.iter().filter(|vm| self.pool_key(vm) == key).count() < max
+    }
+
+    /// Idle VMs of the pool `key`
+    async fn pooled_count(&self, key: PoolKey<'_>) -> usize {
+        let pool = self.inner.pool.lock().await;
+        pool.iter().filter(|vm| self.pool_key(vm) == key).count()
+    }
+
+    /// Warm VMs the pool `key` is kept at
+    fn target_of(&self, key: PoolKey<'_>) -> usize {
+        match key {
+            PoolKey::Default => self.pool_target(),
+            PoolKey::Sub(_, sub_pool) => sub_pool.min_warm,
+            PoolKey::OnDemand => 0,
+        }
+    }
+
+    /// The default pool and every sub-pool with their VMs, oldest pooled first
+    pub async fn sub_pools(&self) -> Vec<SubPoolState> {
+        let pool = self.inner.pool.lock().await;
+        let vms_of = |key: PoolKey<'_>| {
+            pool.iter()
+                .filter(|vm| self.pool_key(vm) == key)
+                .map(pooled_vm)
+                .collect()
+        };
+        let mut sub_pools = vec![SubPoolState {
+            name: DEFAULT_SUB_POOL.to_string(),
+            min_warm: self.pool_target(),
+            max: self.pool_capacity(),
+            vms: vms_of(PoolKey::Default),
+        }];
+        for (image, sub_pool) in &self.inner.config.sub_pools {
+            sub_pools.push(SubPoolState {
+                name: image.clone(),
+                min_warm: sub_pool.min_warm,
+                max: sub_pool.max,
+                vms: vms_of(PoolKey::Sub(image, *sub_pool)),
+            });
+        }
+        sub_pools
+    }
+
+    /// Pool settings in effect, as last reloaded
+    pub fn tunables(&self) -> PoolTunables {
+        *self
+            .inner
+            .tunables
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Switch to the reloaded `tunables` and boot VMs up to a larger pool size. A pool that
+    /// is autoscaled keeps the size the autoscaler gives it; the other settings apply from
+    /// the next execution or autoscaler tick.
+    pub async fn apply_tunables(&self, tunables: PoolTunables) {
+        self.set_tunables(tunables).await;
+        let target = self.pool_target();
+        let idle = self.pooled_count(PoolKey::Default).await;
+        if idle < target {
+            self.warm(target - idle).await;
+        }
+    }
+
+    /// Switch to `tunables`, shutting down idle VMs beyond a smaller pool size but booting
+    /// none
+    pub async fn set_tunables(&self, tunables: PoolTunables) {
+        let previous = std::mem::replace(
+            &mut *self
+                .inner
+                .tunables
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()),
+            tunables,
+        );
+        if self.inner.config.autoscale.enabled {
+            return;
+        }
+        self.set_pool_target(tunables.pool_size);
+        if tunables.pool_size >= previous.pool_size {
+            return;
+        }
+        let surplus: Vec<_> = {
+            let mut pool = self.inner.pool.lock().await;
+            let (default, sub_pools): (VecDeque<_>, VecDeque<_>) = pool
+                .drain(..)
+                .partition(|vm| self.pool_key(vm) == PoolKey::Default);
+            let mut default = default;
+            let keep = default.len().min(tunables.pool_size);
+            // VMs idle the longest go first
+            let surplus = default.len() - keep;
+            let surplus = default.drain(..surplus).collect();
+            pool.extend(default);
+            pool.extend(sub_pools);
+            surplus
+        };
+        for vm in surplus {
+            self.discard_vm(vm, DiscardReason::PoolResized);
+        }
+    }
+
+    /// Warm VMs the pool is kept at
+    pub fn pool_target(&self) -> usize {
+        self.inner.pool_target.load(Ordering::Relaxed)
+    }
+
+    /// Keep the pool at `target` warm VMs from now on, at most its capacity
+    pub fn set_pool_target(&self, target: usize) {
+        let target = target.min(self.pool_capacity());
+        self.inner.pool_target.store(target, Ordering::Relaxed);
+        telemetry::set_gauge("fc_pool_target", &[], target as f64);
+    }
+
+    /// The VMs in the pool, oldest pooled first
+    pub async fn pooled_vms(&self) -> Vec<PooledVm> {
+        self.inner.pool.lock().await.iter().map(pooled_vm).collect()
+    }
+
+    /// Pool, live VMs and running executions as of one moment, read while holding the pool
+    /// lock, the VM registry lock and the running executions' lock together
+    pub async fn state(&self) -> ExecutorState {
+        let pool = self.inner.pool.lock().await;
+        let registry = runner::VM_REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let running = self
+            .inner
+            .running_executions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let mut live_vms: Vec<LiveVmState> = registry
+            .values()
+            .map(|vm| LiveVmState {
+                vm_id: vm.vm_id.clone(),
+                tap_interface: vm.tap_interface.clone(),
+                pid: vm.pid,
+                pooled: pool.iter().any(|pooled| pooled.vm_id() == vm.vm_id),
+                fds: vm.fds,
+            })
+            .collect();
+        let expected_by_vms = live_vms.iter().map(|vm| vm.fds.total()).sum();
+        live_vms.sort_by(|a, b| a.vm_id.cmp(&b.vm_id));
+        let live_vms_total = live_vms.len();
+        live_vms.truncate(debug_state::MAX_LISTED);
+        let mut in_flight: Vec<RunningExecution> = running
+            .values()
+            .map(|(request_id, started_at, start)| RunningExecution {
+                request_id: request_id.clone(),
+                started_at: *started_at,
+                elapsed_ms: start.elapsed().as_millis() as u64,
+            })
+            .collect();
+        in_flight.sort_by_key(|execution| std::cmp::Reverse(execution.elapsed_ms));
+        in_flight.truncate(debug_state::MAX_LISTED);
+        let autoscale = &self.inner.config.autoscale;
+        ExecutorState {
+            stats: self.stats_with_idle(pool.len()),
+            pool: pool.iter().map(pooled_vm).collect(),
+            live_vms,
+            live_vms_total,
+            in_flight,
+            vm_creation: self.inner.breaker.status(),
+            autoscale: AutoscaleState {
+                enabled: autoscale.enabled,
+                min: autoscale.min,
+                max: autoscale.max,
+                pool_target: self.pool_target(),
+            },
+            fds: FdState {
+                usage: fd_limit::usage(),
+                expected_by_vms,
+                min_headroom: self.inner.config.fd_limit.min_headroom,
+            },
+        }
+    }
+
+    /// Why `vm` is worn out and must not be reused, if it is
+    fn retirement(&self, vm: &VMManager) -> Option<DiscardReason> {
+        let config = &self.inner.config;
+        if config
+            .vm_max_reuse
+            .is_some_and(|max| vm.executions() >= max)
+        {
+            return Some(DiscardReason::MaxReuse);
+        }
+        if config.vm_max_age.is_some_and(|max| vm.age() >= max) {
+            return Some(DiscardReason::MaxAge);
+        }
+        if config.process_usage.max_rss_mib.is_some()
+            && vm
+                .process_usage()
+                .is_some_and(|usage| config.process_usage.exceeded_by(&usage))
+        {
+            return Some(DiscardReason::HostRss);
+        }
+        None
+    }
+
+    /// Shut down the pooled VMs past their maximum age, oldest first; returns how many. VMs
+    /// running an execution aren't in the pool, so they are retired once they come back.
+    pub async fn retire_aged(&self) -> usize {
+        let Some(max_age) = self.inner.config.vm_max_age else {
+            return 0;
+        };
+        let mut pool = self.inner.pool.lock().await;
+        let (mut aged, kept): (Vec<_>, Vec<_>) = pool.drain(..).partition(|vm| vm.age() >= max_age);
+        pool.extend(kept);
+        drop(pool);
+        aged.sort_by_key(VMManager::created_at);
+        let retired = aged.len();
+        for vm in aged {
+            self.discard_vm(vm, DiscardReason::MaxAge);
+        }
+        retired
+    }
+
+    /// Retire aged pooled VMs as they pass the maximum age and boot replacements up to the
+    /// pool target; does nothing without a maximum age
+    pub fn spawn_recycler(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let max_age = self.inner.config.vm_max_age?;
+        let executor = self.clone();
+        let period = max_age
+            .min(RECYCLE_INTERVAL)
+            .max(std::time::Duration::from_millis(10));
+        Some(tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            loop {
+                ticks.tick().await;
+                if executor.inner.closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                debug_state::heartbeat("recycler");
+                if executor.retire_aged().await > 0 {
+                    executor.refill().await;
+                }
+            }
+        }))
+    }
+
+    /// Boot VMs until the default pool is back at its target and every sub-pool at its
+    /// minimum
+    async fn refill(&self) {
+        let target = self.pool_target();
+        let idle = self.pooled_count(PoolKey::Default).await;
+        if idle < target {
+            self.warm(target - idle).await;
+        }
+        self.warm_sub_pools().await;
+    }
+
+    /// Ask the agent of pooled VM `vm_id` whether it is up, discarding the VM once it has
+    /// failed `failures_before_eviction` probes in a row or its process is gone; returns
+    /// whether it was discarded. The VM leaves the pool while it is probed, so it can't be
+    /// handed out mid-probe, and VMs already handed out aren't probed at all.
+    pub async fn probe_vm(&self, vm_id: &str) -> bool {
+        let probe = self.inner.config.health_probe;
+        let (index, mut vm) = {
+            let mut pool = self.inner.pool.lock().await;
+            let Some(index) = pool.iter().position(|vm| vm.vm_id() == vm_id) else {
+                return false;
+            };
+            let Some(vm) = pool.remove(index) else {
+                return false;
+            };
+            (index, vm)
+        };
+        if let Some(exit_code) = vm.exited() {
+            tracing::warn!("Pooled VM {} exited while idle", vm.vm_id());
+            events::publish(VmEvent::Crashed {
+                vm_id: vm.vm_id().to_string(),
+                exit_code,
+            });
+            telemetry::increment_counter("fc_pool_unhealthy_evictions_total", &[], 1);
+            self.discard_vm(vm, DiscardReason::Exited);
+            return true;
+        }
+        let health = vm.check_health(probe.timeout).await;
+        if let Err(e) = &health {
+            tracing::warn!("Health probe of pooled VM {} failed: {}", vm.vm_id(), e);
+        }
+        if vm.record_probe(health.is_ok()) >= probe.failures_before_eviction {
+            telemetry::increment_counter("fc_pool_unhealthy_evictions_total", &[], 1);
+            self.discard_vm(vm, DiscardReason::Unhealthy);
+            return true;
+        }
+        let mut pool = self.inner.pool.lock().await;
+        if self.inner.closed.load(Ordering::SeqCst) {
+            self.discard_vm(vm, DiscardReason::Shutdown);
+        } else if !self.has_room(&pool, &vm) {
+            self.discard_vm(vm, DiscardReason::PoolFull);
+        } else {
+            // Back where it was, so probing doesn't change which VM is handed out next
+            let index = index.min(pool.len());
+            pool.insert(index, vm);
+        }
+        false
+    }
+
+    /// Probe every pooled VM once, waiting `pause` before each, then boot replacements for
+    /// the discarded ones; returns how many were discarded
+    pub async fn probe_pool(&self, pause: std::time::Duration) -> usize {
+        let vm_ids: Vec<String> = self
+            .inner
+            .pool
+            .lock()
+            .await
+            .iter()
+            .map(|vm| vm.vm_id().to_string())
+            .collect();
+        let mut evicted = 0;
+        for vm_id in vm_ids {
+            if !pause.is_zero() {
+                tokio::time::sleep(pause).await;
+            }
+            if self.inner.closed.load(Ordering::SeqCst) {
+                return evicted;
+            }
+            if self.probe_vm(&vm_id).await {
+                evicted += 1;
+            }
+        }
+        if evicted > 0 {
+            self.refill().await;
+        }
+        evicted
+    }
+
+    /// Probe the pooled VMs' health in rounds of the configured interval, spreading each
+    /// round's probes over it; does nothing with probing off
+    pub fn spawn_health_prober(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = self.inner.config.health_probe.interval?;
+        let executor = self.clone();
+        Some(tokio::spawn(async move {
+            loop {
+                if executor.inner.closed.load(Ordering::SeqCst) {
+                    return;
+                }
+                debug_state::heartbeat("health_prober");
+                let pooled = executor.inner.pool.lock().await.len();
+                if pooled == 0 {
+                    tokio::time::sleep(interval).await;
+                    continue;
+                }
+                executor.probe_pool(probe::stagger(interval, pooled)).await;
+            }
+        }))
+    }
+
+    /// Shut down idle VMs above their pool's target, or sub-pool's minimum, that have been
+    /// pooled for at least `ttl`, oldest first; returns how many
+    pub async fn evict_idle(&self, ttl: std::time::Duration) -> usize {
+        let mut pool = self.inner.pool.lock().await;
+        let mut evicted = 0;
+        loop {
+            let above_target = |key: PoolKey<'_>| {
+                pool.iter().filter(|vm| self.pool_key(vm) == key).count() > self.target_of(key)
+            };
+            let Some(index) = pool
+                .iter()
+                .enumerate()
+                .filter(|(_, vm)| vm.idle_for() >= ttl && above_target(self.pool_key(vm)))
+                .max_by_key(|(_, vm)| vm.idle_for())
+                .map(|(index, _)| index)
+            else {
+                break;
+            };
+            if let Some(vm) = pool.remove(index) {
+                self.discard_vm(vm, DiscardReason::IdleTtl);
+                evicted += 1;
+            }
+        }
+        evicted
+    }
+
+    /// Refuse new executions, wait for in-flight ones, then shut down and clean up every VM
+    /// this executor created. Calling it again is a no-op.
+    pub async fn shutdown(&self) {
+        self.inner.closed.store(true, Ordering::SeqCst);
+        let _exclusive = self.inner.running.write().await;
+        let idle: Vec<_> = self.inner.pool.lock().await.drain(..).collect();
+        for vm in idle.into_iter().chain(self.inner.sandboxes.drain()) {
+            self.discard_vm(vm, DiscardReason::Shutdown);
+        }
+        self.inner.closing_exposures.send_replace(true);
+        let mut tasks = std::mem::take(&mut *self.tasks());
+        while tasks.join_next().await.is_some() {}
+    }
+
+    /// Run code on a pooled (or freshly created) VM, reporting the VM used through `vm_id`
+    async fn execute_in_pooled_vm(
+        &self,
+        request: &ExecutionSpec,
+        deadline: Deadline,
+        max_output_bytes: usize,
+        vm_id: &mut Option<String>,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        let dedicated = request.needs_dedicated_vm();
+        // Refused before a VM is spent on it
+        let exposure_slot = match request.expose_port {
+            Some(_) => Some(self.inner.exposed_ports.try_reserve().ok_or_else(|| {
+                ExecutionError::ResourceExhausted(format!(
+                    "{} guest ports are already exposed",
+                    self.inner.config.expose.max_exposed
+                ))
+            })?),
+            None => None,
+        };
+        let sandbox_slot = match request.keep_alive {
+            Some(_) => Some(self.inner.sandboxes.try_reserve().ok_or_else(|| {
+                ExecutionError::ResourceExhausted(format!(
+                    "{} sandboxes are already kept",
+                    self.inner.config.sandbox.max_sandboxes
+                ))
+            })?),
+            None => None,
+        };
+        let (mut vm_manager, pool_hit, replaced) = self.acquire_vm(request, deadline).await?;
+        let mut warnings: Vec<_> = replaced
+            .iter()
+            .map(|exited| {
+                Warning::new(
+                    WarningCode::RetriedOnFreshVm,
+                    format!("VM {exited} had exited before the code was sent"),
+                )
+            })
+            .collect();
+        if !pool_hit && !dedicated {
+            warnings.push(Warning::new(
+                WarningCode::ColdStart,
+                "no warm VM matched the request, so one was booted for it",
+            ));
+        }
+        if !dedicated {
+            let counter = if pool_hit {
+                &self.inner.pool_hits
+            } else {
+                &self.inner.pool_misses
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(key) = request.affinity_key.as_deref()
+            && !dedicated
+        {
+            let hit = self.affinity().vm_for(key) == Some(vm_manager.vm_id());
+            let (counter, metric) = if hit {
+                (&self.inner.affinity_hits, "fc_affinity_hits_total")
+            } else {
+                (&self.inner.affinity_misses, "fc_affinity_misses_total")
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            telemetry::increment_counter(metric, &[], 1);
+        }
+        debug_events::record(
+            DebugEvent::new(DebugEventKind::PoolAcquire)
+                .vm(vm_manager.vm_id())
+                .detail(match (dedicated, pool_hit) {
+                    (true, _) => "dedicated",
+                    (false, true) => "hit",
+                    (false, false) => "miss",
+                }),
+        );
+        let span = tracing::Span::current();
+        span.record("pool_hit", pool_hit);
+        *vm_id = Some(vm_manager.vm_id().to_string());
+        span.record("vm_id", vm_manager.vm_id());
+        events::publish(VmEvent::Acquired {
+            vm_id: vm_manager.vm_id().to_string(),
+            request_id: request.request_id.clone(),
+        });
+
+        // Only what the console prints from here on belongs to this execution
+        let console_start = request
+            .capture_console
+            .then(|| console::mark(vm_manager.console_log_path()));
+
+        // Execute code via HTTP API
+        debug_events::record(
+            DebugEvent::new(DebugEventKind::GuestRequestSent).vm(vm_manager.vm_id()),
+        );
+        #[cfg(feature = "chaos")]
+        let execution = vm_manager.execute_code_injecting_faults(
+            &request.program,
+            &request.requirements,
+            &request.inputs,
+            max_output_bytes,
+            deadline,
+            request.output.as_ref(),
+        );
+        #[cfg(not(feature = "chaos"))]
+        let execution = vm_manager.execute_code_via_api(
+            &request.program,
+            &request.requirements,
+            &request.inputs,
+            max_output_bytes,
+            deadline,
+            request.output.as_ref(),
+        );
+        let result = execution
+            .instrument(tracing::info_span!("execute"))
+            .await
+            .map(|mut response| {
+                for (stream, truncated) in [
+                    ("stdout", response.stdout_truncated),
+                    ("stderr", response.stderr_truncated),
+                ] {
+                    if truncated {
+                        debug_events::record(
+                            DebugEvent::new(DebugEventKind::Truncated)
+                                .detail(stream)
+                                .bytes(max_output_bytes),
+                        );
+                    }
+                }
+                // Echo the settings so a caller can reproduce the run
+                if let Some(settings) = &request.deterministic {
+                    response.deterministic = true;
+                    response.hash_seed = Some(settings.hash_seed);
+                    response.fake_time = settings.fake_time;
+                }
+                if let Some(start) = console_start {
+                    let captured = console::read_since(
+                        vm_manager.console_log_path(),
+                        start,
+                        self.config().console_capture.max_bytes,
+                    );
+                    response.console = Some(captured.text);
+                    response.console_truncated = captured.truncated;
+                }
+                response.vm_id = Some(vm_manager.vm_id().to_string());
+                warnings.append(&mut response.warnings);
+                response.warnings = warnings;
+                response
+            });
+
+        match result {
+            Ok(mut response) if dedicated => {
+                if let (Some(ttl), Some(slot)) = (request.keep_alive, sandbox_slot) {
+                    let forward = match (request.expose_port, exposure_slot) {
+                        (Some(port), Some(exposure_slot)) => {
+                            match self.forward(&vm_manager, port).await {
+                                Ok(forward) => Some((forward, exposure_slot)),
+                                Err(e) => {
+                                    self.discard_vm(
+                                        vm_manager.release(),
+                                        DiscardReason::ExposeFailed,
+                                    );
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        _ => None,
+                    };
+                    if let Some((forward, _)) = &forward {
+                        response.exposed_port = Some(forward.host_port());
+                    }
+                    let (sandbox_id, expires_at_ms) =
+                        self.inner
+                            .sandboxes
+                            .park(vm_manager.release(), ttl, slot, forward);
+                    if response.exposed_port.is_some() {
+                        response.exposed_until_ms = Some(expires_at_ms);
+                    }
+                    tracing::info!(sandbox_id, ttl_secs = ttl.as_secs(), "Keeping sandbox");
+                    response.sandbox_id = Some(sandbox_id);
+                    response.sandbox_expires_at_ms = Some(expires_at_ms);
+                } else if let (Some(port), Some(slot)) = (request.expose_port, exposure_slot) {
+                    self.expose(vm_manager.release(), port, slot, &mut response)
+                        .await?;
+                } else {
+                    self.discard_vm(vm_manager.release(), DiscardReason::Dedicated);
+                }
+                Ok(response)
+            }
+            Ok(response) => {
+                vm_manager.record_execution();
+                if let Some(reason) = self.retirement(&vm_manager) {
+                    self.discard_vm(vm_manager.release(), reason);
+                    return Ok(response);
+                }
+                // Only the default rootfs and images with a sub-pool are kept warm
+                if self.pool_key(&vm_manager) == PoolKey::OnDemand {
+                    self.discard_vm(vm_manager.release(), DiscardReason::Dedicated);
+                    return Ok(response);
+                }
+                // Files one execution left behind must never be visible to the next
+                if let Err(e) = vm_manager.verify_workspaces_removed().await {
+                    tracing::warn!(vm_id = %vm_manager.vm_id(), "Discarding VM: {e}");
+                    self.discard_vm(vm_manager.release(), DiscardReason::WorkspaceLeftover);
+                    return Ok(response);
+                }
+                // VM is still healthy, return it to pool
+                vm_manager.inflate_balloon().await;
+                {
+                    let mut pool = self.inner.pool.lock().await;
+                    if self.has_room(&pool, &vm_manager) {
+                        events::publish(VmEvent::Released {
+                            vm_id: vm_manager.vm_id().to_string(),
+                        });
+                        if let Some(key) = &request.affinity_key {
+                            self.affinity().remember(key, vm_manager.vm_id());
+                        }
+                        debug_events::record(
+                            DebugEvent::new(DebugEventKind::PoolRelease)
+                                .vm(vm_manager.vm_id())
+                                .detail("returned"),
+                        );
+                        vm_manager.mark_idle();
+                        pool.push_back(vm_manager.release());
+                        tracing::debug!("Returned VM to pool (pool size: {})", pool.len());
+                    } else {
+                        // Pool is full, shutdown this VM
+                        self.discard_vm(vm_manager.release(), DiscardReason::PoolFull);
+                    }
+                }
+                Ok(response)
+            }
+            Err(e) => {
+                // VM failed, shutdown and cleanup
+                let mut vm = vm_manager.release();
+                if let Some(exit_code) = vm.exited() {
+                    events::publish(VmEvent::Crashed {
+                        vm_id: vm.vm_id().to_string(),
+                        exit_code,
+                    });
+                }
+                let reason = match e {
+                    ExecutionError::GuestOutOfMemory { .. } => DiscardReason::OutOfMemory,
+                    _ => DiscardReason::ExecutionError,
+                };
+                self.discard_vm(vm, reason);
+                Err(e)
+            }
+        }
+    }
+
+    /// Forward a host port to `port` on `vm`, noting it in `response`, and keep the VM until the
+    /// forward ends: when its time is up, the VM exits or the executor shuts down
+    async fn expose(
+        &self,
+        vm: VMManager,
+        port: u16,
+        slot: OwnedSemaphorePermit,
+        response: &mut ExecuteResponse,
+    ) -> Result<(), ExecutionError> {
+        let config = &self.inner.config.expose;
+        let forward = match self.forward(&vm, port).await {
+            Ok(forward) => forward,
+            Err(e) => {
+                self.discard_vm(vm, DiscardReason::ExposeFailed);
+                return Err(e);
+            }
+        };
+        response.exposed_port = Some(forward.host_port());
+        response.exposed_until_ms = Some(now_millis() + config.ttl.as_millis() as u64);
+        tracing::info!(
+            vm_id = vm.vm_id(),
+            port,
+            host_port = forward.host_port(),
+            "Exposing guest port"
+        );
+        let mut tasks = self.tasks();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(self.clone().keep_exposed(vm, forward, slot, config.ttl));
+        Ok(())
+    }
+
+    /// Forward a host port to `port` on `vm`
+    async fn forward(&self, vm: &VMManager, port: u16) -> Result<PortForward, ExecutionError> {
+        let bind_addr = self.inner.config.expose.bind_addr;
+        let forward = match vm.vm_ip().parse() {
+            Ok(ip) => PortForward::start(bind_addr, std::net::SocketAddr::new(ip, port))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(format!("bad VM address {}: {e}", vm.vm_ip())),
+        };
+        forward.map_err(|e| {
+            ExecutionError::ResourceError(format!("Failed to expose guest port {port}: {e}"))
+        })
+    }
+
+    /// Hold `vm` and its forward, with the slot they take, until the forward ends
+    async fn keep_exposed(
+        self,
+        mut vm: VMManager,
+        forward: PortForward,
+        slot: OwnedSemaphorePermit,
+        ttl: std::time::Duration,
+    ) {
+        let mut closing = self.inner.closing_exposures.subscribe();
+        let mut check = tokio::time::interval(EXPOSED_VM_CHECK_INTERVAL);
+        let expired = tokio::time::sleep(ttl);
+        tokio::pin!(expired);
+        let reason = loop {
+            tokio::select! {
+                () = &mut expired => break DiscardReason::ExposeExpired,
+                _ = closing.wait_for(|closing| *closing) => break DiscardReason::Shutdown,
+                _ = check.tick() => {
+                    if vm.exited().is_some() {
+                        break DiscardReason::Exited;
+                    }
+                }
+            }
+        };
+        forward.close().await;
+        drop(slot);
+        // Awaited here rather than spawned, as `shutdown` may already be waiting on this task
+        self.log_discard(&vm, reason);
+        shutdown_and_clean_up(vm).await;
+    }
+
+    /// A VM for `request`, whether it came from the pool and the VMs it replaced. A VM found
+    /// dead before the execution is sent is replaced, as nothing ran on it; one that dies right
+    /// after booting is an error instead of another boot.
+    async fn acquire_vm(
+        &self,
+        request: &ExecutionSpec,
+        deadline: Deadline,
+    ) -> Result<(VmLease, bool, Vec<String>), ExecutionError> {
+        let mut replaced = Vec::new();
+        loop {
+            let pooled = if request.needs_dedicated_vm() {
+                None
+            } else {
+                self.take_from_pool(request).await
+            };
+            let pool_hit = pooled.is_some();
+            let mut vm = match pooled {
+                Some(vm) => vm,
+                None if request.quick.is_some() => return Err(ExecutionError::NoWarmVm),
+                None => {
+                    tracing::debug!(
+                        "Creating new VM for request (dedicated: {})",
+                        request.needs_dedicated_vm()
+                    );
+                    VmLease::new(self, self.create_vm(&request.vm_options(), deadline).await?)
+                }
+            };
+            #[cfg(feature = "chaos")]
+            if let Err(e) = vm.inject_fault(FaultPoint::BeforeExecute).await {
+                self.discard_vm(vm.release(), DiscardReason::ExecutionError);
+                return Err(e);
+            }
+            let Some(exit_code) = vm.exited() else {
+                return Ok((vm, pool_hit, replaced));
+            };
+            tracing::warn!("VM {} exited before the execution was sent", vm.vm_id());
+            events::publish(VmEvent::Crashed {
+                vm_id: vm.vm_id().to_string(),
+                exit_code,
+            });
+            replaced.push(vm.vm_id().to_string());
+            self.discard_vm(vm.release(), DiscardReason::Exited);
+            if !pool_hit {
+                return Err(ExecutionError::ApiCommunicationError(
+                    "VM exited right after booting".to_string(),
+                ));
+            }
+        }
+    }
+
+    /// Whether the pool holds a VM `request` could run on
+    async fn has_warm_vm(&self, request: &ExecutionSpec) -> bool {
+        let pool = self.inner.pool.lock().await;
+        pool.iter().any(|vm| {
+            pooled_vm_matches(
+                vm,
+                request.deps_profile.as_deref(),
+                request.image.as_deref(),
+            )
+        })
+    }
+
+    /// The pooled VM last used for `request`'s affinity key, or else the oldest one matching
+    /// `request`, deflated and ready to run it
+    async fn take_from_pool(&self, request: &ExecutionSpec) -> Option<VmLease> {
+        let preferred = request
+            .affinity_key
+            .as_deref()
+            .and_then(|key| self.affinity().vm_for(key).map(str::to_string));
+        let mut vm = {
+            let mut pool = self.inner.pool.lock().await;
+            let vm = take_pooled_vm(
+                &mut pool,
+                request.deps_profile.as_deref(),
+                request.image.as_deref(),
+                preferred.as_deref(),
+            )?;
+            tracing::debug!("Reusing VM from pool (pool size: {})", pool.len());
+            VmLease::new(self, vm)
+        };
+        if let Err(e) = vm.deflate_balloon().await {
+            // A guest still short of memory would fail in confusing ways
+            tracing::warn!("Failed to deflate balloon of VM {}: {}", vm.vm_id(), e);
+            self.discard_vm(vm.release(), DiscardReason::BalloonDeflateFailed);
+            return None;
+        }
+        if let Err(e) = clock::on_acquire(&*vm, vm.idle_for(), &self.inner.config.clock_sync).await
+        {
+            tracing::warn!("Failed to set the clock of VM {}: {}", vm.vm_id(), e);
+            self.discard_vm(vm.release(), DiscardReason::ClockSyncFailed);
+            return None;
+        }
+        Some(vm)
+    }
+
+    /// Boot a VM by `deadline`, unless the circuit breaker has seen creation fail too often of
+    /// late
+    async fn create_vm(
+        &self,
+        options: &runner::VmOptions,
+        deadline: Deadline,
+    ) -> Result<VMManager, ExecutionError> {
+        let breaker = &self.inner.breaker;
+        let attempt = breaker.try_acquire().map_err(|open| {
+            telemetry::increment_counter("fc_vm_creation_rejected_total", &[], 1);
+            ExecutionError::VmCreationUnavailable(format!(
+                "the last {} VM boots failed (last error: {}); retrying in {}s",
+                breaker.status().consecutive_failures,
+                open.last_error.as_deref().unwrap_or("unknown"),
+                open.retry_in.as_secs().max(1)
+            ))
+        })?;
+        if attempt == Attempt::Probe {
+            tracing::info!("Probing whether VMs can be created again");
+        }
+        match runner::create_new_vm_with(&self.inner.config, options, deadline).await {
+            Ok(vm) => {
+                breaker.record_success();
+                self.inner.vms_created.fetch_add(1, Ordering::Relaxed);
+                if vm.warmup().is_some() {
+                    self.inner.vms_warmed_up.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(vm)
+            }
+            // Running out of capacity or time, or shutting down, says nothing about the host's
+            // setup; missing privileges do, but are known without a boot and reported as such
+            Err(
+                e @ (ExecutionError::ResourceExhausted(_)
+                | ExecutionError::ShuttingDown
+                | ExecutionError::DeadlineExceeded { .. }
+                | ExecutionError::PrivilegeError(_)),
+            ) => Err(e),
+            Err(e) => {
+                breaker.record_failure(&e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// State of the circuit breaker around VM creation
+    pub fn vm_creation_status(&self) -> BreakerStatus {
+        self.inner.breaker.status()
+    }
+
+    /// Pool depth, VM creation health and host memory, as readiness is scored on
+    pub async fn readiness_snapshot(&self, probe: &impl ResourceProbe) -> RunnerSnapshot {
+        let config = &self.inner.config;
+        RunnerSnapshot::assemble(
+            &self.stats().await,
+            self.inner.breaker.status().state,
+            self.inner.breaker.recent_failure_rate(),
+            &ResourceUsage::sample(config, probe),
+            config.vm_memory_mib,
+        )
+    }
+
+    /// Shut down and clean up a VM in the background
+    fn discard_vm(&self, vm: VMManager, reason: DiscardReason) {
+        self.log_discard(&vm, reason);
+        let mut tasks = self.tasks();
+        // Reap finished shutdowns so the set doesn't grow with every discarded VM
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(shutdown_and_clean_up(vm));
+    }
+
+    /// Log, count and announce that `vm` is being discarded
+    fn log_discard(&self, vm: &VMManager, reason: DiscardReason) {
+        tracing::info!(
+            vm_id = vm.vm_id(),
+            reason = reason.as_str(),
+            age_secs = vm.age().as_secs(),
+            executions = vm.executions(),
+            "Discarding VM"
+        );
+        telemetry::increment_counter("fc_vms_discarded_total", &[("reason", reason.as_str())], 1);
+        debug_events::record(
+            DebugEvent::new(DebugEventKind::PoolRelease)
+                .vm(vm.vm_id())
+                .detail(reason.as_str()),
+        );
+        self.affinity().forget_vm(vm.vm_id());
+        events::publish(VmEvent::Discarded {
+            vm_id: vm.vm_id().to_string(),
+            reason,
+        });
+    }
+
+    fn tasks(&self) -> std::sync::MutexGuard<'_, JoinSet<()>> {
+        self.inner.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn affinity(&self) -> std::sync::MutexGuard<'_, Affinity> {
+        self.inner
+            .affinity
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Dropping a JoinSet aborts its tasks; let pending shutdowns run to completion
+        self.tasks
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .detach_all();
+        let idle: Vec<_> = self.pool.get_mut().drain(..).collect();
+        if idle.is_empty() {
+            return;
+        }
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                for vm in idle {
+                    events::publish(VmEvent::Discarded {
+                        vm_id: vm.vm_id().to_string(),
+                        reason: DiscardReason::Shutdown,
+                    });
+                    handle.spawn(shutdown_and_clean_up(vm));
+                }
+            }
+            // Without a runtime only the Firecracker processes can still be stopped
+            Err(_) => idle.into_iter().for_each(VMManager::kill_now),
+        }
+    }
+}
+
+/// A VM out of the pool on behalf of one execution. Dropped before [`VmLease::release`], as
+/// when the caller's future is cancelled mid-execution, it discards the VM in the background
+/// instead of leaking its process, tap device and files.
+struct VmLease {
+    executor: ExecutorService,
+    vm: Option<VMManager>,
+}
+
+impl VmLease {
+    fn new(executor: &ExecutorService, vm: VMManager) -> Self {
+        Self {
+            executor: executor.clone(),
+            vm: Some(vm),
+        }
+    }
+
+    /// The VM, to be pooled or discarded by the caller
+    fn release(mut self) -> VMManager {
+        self.vm.take().expect("a lease holds its VM until released")
+    }
+}
+
+impl std::ops::Deref for VmLease {
+    type Target = VMManager;
+
+    fn deref(&self) -> &VMManager {
+        self.vm
+            .as_ref()
+            .expect("a lease holds its VM until released")
+    }
+}
+
+impl std::ops::DerefMut for VmLease {
+    fn deref_mut(&mut self) -> &mut VMManager {
+        self.vm
+            .as_mut()
+            .expect("a lease holds its VM until released")
+    }
+}
+
+impl Drop for VmLease {
+    fn drop(&mut self) {
+        let Some(vm) = self.vm.take() else {
+            return;
+        };
+        tracing::warn!("Execution on VM {} was cancelled", vm.vm_id());
+        self.executor
+            .inner
+            .cancelled
+            .fetch_add(1, Ordering::Relaxed);
+        telemetry::increment_counter("fc_executions_cancelled_total", &[], 1);
+        if tokio::runtime::Handle::try_current().is_ok() {
+            self.executor.discard_vm(vm, DiscardReason::Cancelled);
+        } else {
+            vm.kill_now();
+        }
+    }
+}
+
+/// Counts an execution in `in_flight` and lists it among the running executions until
+/// dropped, however the execution ends
+struct InFlight<'a> {
+    inner: &'a Inner,
+    entry: u64,
+}
+
+impl<'a> InFlight<'a> {
+    fn enter(inner: &'a Inner, request_id: &str) -> Self {
+        inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        let entry = inner.next_execution.fetch_add(1, Ordering::Relaxed);
+        inner
+            .running_executions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(
+                entry,
+                (
+                    request_id.to_string(),
+                    now_millis(),
+                    std::time::Instant::now(),
+                ),
+            );
+        Self { inner, entry }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.inner
+            .running_executions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.entry);
+        self.inner.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn pooled_vm(vm: &VMManager) -> PooledVm {
+    PooledVm {
+        vm_id: vm.vm_id().to_string(),
+        age_secs: vm.age().as_secs(),
+        idle_secs: vm.idle_for().as_secs(),
+        executions: vm.executions(),
+        warmup: vm.warmup(),
+        last_probe_at: vm.last_probe_at(),
+    }
+}
+
+async fn shutdown_and_clean_up(mut vm: VMManager) {
+    let _ = vm.shutdown_vm().await;
+    let _ = vm.cleanup().await;
+}
+
+/// Remove and return the pooled VM with ID `preferred`, or else the oldest one, with exactly
+/// the given deps profile and image. Deterministic VMs are never pooled, so they can't be
+/// handed out here.
+pub(crate) fn take_pooled_vm(
+    pool: &mut VecDeque<VMManager>,
+    deps_profile: Option<&str>,
+    image: Option<&str>,
+    preferred: Option<&str>,
+) -> Option<VMManager> {
+    let matches = |vm: &VMManager| pooled_vm_matches(vm, deps_profile, image);
+    let index = preferred
+        .and_then(|vm_id| {
+            pool.iter()
+                .position(|vm| vm.vm_id() == vm_id && matches(vm))
+        })
+        .or_else(|| pool.iter().position(matches))?;
+    pool.remove(index)
+}
+
+/// Whether pooled VM `vm` can run a request for `deps_profile` and `image`
+fn pooled_vm_matches(vm: &VMManager, deps_profile: Option<&str>, image: Option<&str>) -> bool {
+    !vm.is_deterministic() && vm.deps_profile() == deps_profile && vm.image() == image
+}
+
+/// Executor behind `run_in_vm`, built from the global runner configuration
+pub fn default_executor() -> &'static ExecutorService {
+    static DEFAULT_EXECUTOR: once_cell::sync::Lazy<ExecutorService> =
+        once_cell::sync::Lazy::new(|| ExecutorService::new(shared_runner_config()));
+    &DEFAULT_EXECUTOR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breaker::BreakerConfig;
+    use crate::runner::live_vm;
+    use crate::warmup::WarmupConfig;
+
+    fn executor() -> ExecutorService {
+        ExecutorService::new(Arc::new(RunnerConfig::default()))
+    }
+
+    /// Run `code` under `request_id`, returning the ID of the VM it ran on
+    async fn run(executor: &ExecutorService, request_id: &str) -> String {
+        let mut events = events::subscribe();
+        let response = executor
+            .execute(ExecutionSpec {
+                request_id: request_id.to_string(),
+                ..ExecutionSpec::code("print('embedded')")
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, "Mock execution of: print('embedded')\n");
+        std::iter::from_fn(|| events.try_recv().ok())
+            .find_map(|event| match event {
+                VmEvent::Acquired {
+                    vm_id,
+                    request_id: id,
+                } if id == request_id => Some(vm_id),
+                _ => None,
+            })
+            .expect("acquired event should be published")
+    }
+
+    #[tokio::test]
+    async fn test_warm_execute_and_stats() {
+        let executor = executor();
+        assert_eq!(executor.warm(2).await, 2);
+        let stats = executor.stats().await;
+        assert_eq!((stats.idle_vms, stats.vms_created), (2, 2));
+
+        run(&executor, "executor-stats-request").await;
+        let stats = executor.stats().await;
+        // The pooled VM was reused and returned
+        assert_eq!((stats.idle_vms, stats.vms_created), (2, 2));
+        assert_eq!((stats.executions, stats.in_flight), (1, 0));
+
+        // Warming stops at the pool capacity
+        assert_eq!(executor.warm(5).await, VM_POOL_SIZE - 2);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_cold_start_is_warned_exactly_on_a_pool_miss() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            ..Default::default()
+        }));
+        let codes = |response: &ExecuteResponse| -> Vec<WarningCode> {
+            response
+                .warnings
+                .iter()
+                .map(|warning| warning.code)
+                .collect()
+        };
+        let mut misses = 0;
+        for (request, pooled) in [("cold", false), ("warm", true), ("warm-again", true)] {
+            let response = executor
+                .execute(ExecutionSpec {
+                    request_id: format!("executor-warnings-{request}"),
+                    ..ExecutionSpec::code("print(1)")
+                })
+                .await
+                .unwrap();
+            misses += u64::from(!pooled);
+            assert_eq!(executor.stats().await.pool_misses, misses);
+            let expected = if pooled {
+                vec![]
+            } else {
+                vec![WarningCode::ColdStart]
+            };
+            assert_eq!(codes(&response), expected, "{request}");
+        }
+
+        // A VM booted for one request alone isn't a pool miss
+        let response = executor
+            .execute(ExecutionSpec {
+                debug_boot: true,
+                ..ExecutionSpec::code("print(1)")
+            })
+            .await
+            .unwrap();
+        assert!(response.warnings.is_empty());
+        assert_eq!(executor.stats().await.pool_misses, misses);
+
+        let response = executor
+            .execute(ExecutionSpec {
+                max_output_bytes: Some(4),
+                ..ExecutionSpec::code("print(1)")
+            })
+            .await
+            .unwrap();
+        assert!(response.stdout_truncated);
+        assert_eq!(codes(&response), [WarningCode::OutputTruncated]);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_autoscaled_pool_evicts_idle_vms_above_its_target() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            autoscale: crate::autoscale::AutoscaleConfig {
+                enabled: true,
+                min: 1,
+                max: 5,
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        assert_eq!(executor.pool_target(), VM_PREWARM_COUNT);
+        assert_eq!(executor.warm(10).await, 5);
+        let stats = executor.stats().await;
+        assert_eq!((stats.idle_vms, stats.pool_capacity), (5, 5));
+
+        run(&executor, "executor-autoscale-request").await;
+        let stats = executor.stats().await;
+        assert_eq!((stats.pool_hits, stats.pool_misses), (1, 0));
+
+        executor.set_pool_target(2);
+        // Nothing has been idle long enough yet
+        assert_eq!(
+            executor
+                .evict_idle(std::time::Duration::from_secs(60))
+                .await,
+            0
+        );
+        assert_eq!(executor.evict_idle(std::time::Duration::ZERO).await, 3);
+        assert_eq!(executor.stats().await.idle_vms, 2);
+        executor.shutdown().await;
+    }
+
+    /// Reasons VMs among `vm_ids` were discarded for, in the order they were
+    fn discards(
+        events: &mut tokio::sync::broadcast::Receiver<VmEvent>,
+        vm_ids: &[String],
+    ) -> Vec<(String, DiscardReason)> {
+        std::iter::from_fn(|| events.try_recv().ok())
+            .filter_map(|event| match event {
+                VmEvent::Discarded { vm_id, reason } if vm_ids.contains(&vm_id) => {
+                    Some((vm_id, reason))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_each_discard_path_reports_its_reason() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(100),
+            autoscale: crate::autoscale::AutoscaleConfig {
+                enabled: true,
+                min: 1,
+                max: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let discarded = |reason: DiscardReason| {
+            telemetry::METRICS
+                .counter_value("fc_vms_discarded_total", &[("reason", reason.as_str())])
+                .unwrap_or(0)
+        };
+        let dedicated_before = discarded(DiscardReason::Dedicated);
+        let mut events = events::subscribe();
+
+        // A VM for one request alone goes once it has run
+        let dedicated = ExecutionSpec {
+            debug_boot: true,
+            ..ExecutionSpec::code("print('dedicated')")
+        };
+        let vm_id = executor.execute(dedicated).await.unwrap().vm_id.unwrap();
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&vm_id)),
+            [(vm_id, DiscardReason::Dedicated)]
+        );
+        assert!(discarded(DiscardReason::Dedicated) > dedicated_before);
+
+        // Two executions at once against a pool of one: only one VM fits back in
+        assert_eq!(executor.warm(1).await, 1);
+        let (first, second) = tokio::join!(
+            executor.execute(ExecutionSpec::code("print(1)")),
+            executor.execute(ExecutionSpec::code("print(2)")),
+        );
+        let vm_ids = [
+            first.unwrap().vm_id.unwrap(),
+            second.unwrap().vm_id.unwrap(),
+        ];
+        assert_ne!(vm_ids[0], vm_ids[1]);
+        let pooled = executor.pooled_vms().await[0].vm_id.clone();
+        let overflow = vm_ids.iter().find(|id| **id != pooled).unwrap().clone();
+        assert_eq!(
+            discards(&mut events, &vm_ids),
+            [(overflow, DiscardReason::PoolFull)]
+        );
+
+        // Idle VMs above a lowered target are evicted
+        executor.set_pool_target(0);
+        assert_eq!(executor.evict_idle(std::time::Duration::ZERO).await, 1);
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&pooled)),
+            [(pooled, DiscardReason::IdleTtl)]
+        );
+
+        // Whatever is left in the pool goes with the executor
+        assert_eq!(executor.warm(1).await, 1);
+        let last = executor.pooled_vms().await[0].vm_id.clone();
+        executor.shutdown().await;
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&last)),
+            [(last, DiscardReason::Shutdown)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vm_is_retired_after_its_maximum_reuse() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            vm_max_reuse: Some(2),
+            vm_max_age: None,
+            ..Default::default()
+        }));
+        assert_eq!(executor.warm(1).await, 1);
+        let vm_id = executor.pooled_vms().await[0].vm_id.clone();
+        let mut events = events::subscribe();
+
+        assert_eq!(run(&executor, "executor-reuse-request-1").await, vm_id);
+        assert_eq!(executor.pooled_vms().await[0].executions, 1);
+        assert_eq!(run(&executor, "executor-reuse-request-2").await, vm_id);
+        assert!(executor.pooled_vms().await.is_empty());
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&vm_id)),
+            [(vm_id, DiscardReason::MaxReuse)]
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_sub_pools_keep_their_own_warm_vms() {
+        let image_dir = std::env::temp_dir().join(format!("fc-sub-pools-{}", std::process::id()));
+        std::fs::create_dir_all(&image_dir).unwrap();
+        for image in ["sci", "bash", "ruby"] {
+            std::fs::write(crate::images::image_path(&image_dir, image), "").unwrap();
+        }
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(200),
+            pool_size: 1,
+            image_dir: image_dir.clone(),
+            sub_pools: crate::sub_pool::parse_sub_pools("sci=1:1,bash=1:2").unwrap(),
+            ..Default::default()
+        }));
+        assert_eq!(executor.warm(1).await, 1);
+        assert_eq!(executor.warm_sub_pools().await, 2);
+        // Sub-pools at their minimum aren't warmed again
+        assert_eq!(executor.warm_sub_pools().await, 0);
+        let pooled = |sub_pools: &[SubPoolState]| -> Vec<(String, Vec<String>)> {
+            sub_pools
+                .iter()
+                .map(|sub_pool| {
+                    let vm_ids = sub_pool.vms.iter().map(|vm| vm.vm_id.clone()).collect();
+                    (sub_pool.name.clone(), vm_ids)
+                })
+                .collect()
+        };
+        let before = pooled(&executor.sub_pools().await);
+        let names: Vec<_> = before.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["default", "bash", "sci"]);
+        assert!(before.iter().all(|(_, vm_ids)| vm_ids.len() == 1));
+        let sci_vm = before[2].1[0].clone();
+
+        let on = |image: &str| ExecutionSpec {
+            image: Some(image.to_string()),
+            ..ExecutionSpec::code("print(1)")
+        };
+        let response = executor.execute(on("sci")).await.unwrap();
+        assert_eq!(response.vm_id.as_deref(), Some(sci_vm.as_str()));
+        assert_eq!(pooled(&executor.sub_pools().await), before);
+
+        // Two at once exhaust the sci sub-pool: the second boots rather than taking the
+        // default or bash VM, and only one comes back under its maximum
+        let mut events = events::subscribe();
+        let (first, second) =
+            tokio::join!(executor.execute(on("sci")), executor.execute(on("sci")));
+        let ran_on = [
+            first.unwrap().vm_id.unwrap(),
+            second.unwrap().vm_id.unwrap(),
+        ];
+        assert!(ran_on.contains(&sci_vm));
+        let fresh = ran_on
+            .iter()
+            .find(|vm_id| **vm_id != sci_vm)
+            .unwrap()
+            .clone();
+        assert!(before.iter().all(|(_, vm_ids)| !vm_ids.contains(&fresh)));
+        let after = pooled(&executor.sub_pools().await);
+        assert_eq!(after[..2], before[..2]);
+        assert_eq!(after[2].1.len(), 1);
+        let overflow = ran_on
+            .iter()
+            .find(|vm_id| !after[2].1.contains(vm_id))
+            .unwrap()
+            .clone();
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&overflow)),
+            [(overflow, DiscardReason::PoolFull)]
+        );
+
+        // An image without a sub-pool gets a fresh VM, discarded after its execution
+        let misses = executor.stats().await.pool_misses;
+        let response = executor.execute(on("ruby")).await.unwrap();
+        let ruby_vm = response.vm_id.unwrap();
+        assert_eq!(executor.stats().await.pool_misses, misses + 1);
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&ruby_vm)),
+            [(ruby_vm, DiscardReason::Dedicated)]
+        );
+        assert_eq!(pooled(&executor.sub_pools().await), after);
+
+        executor.shutdown().await;
+        let _ = std::fs::remove_dir_all(image_dir);
+    }
+
+    #[tokio::test]
+    async fn test_pool_stops_growing_without_fd_headroom() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            fd_limit: crate::fd_limit::FdLimitConfig {
+                nofile: None,
+                min_headroom: u64::MAX,
+            },
+            ..Default::default()
+        }));
+        assert_eq!(executor.warm(2).await, 0);
+        let state = executor.state().await;
+        assert_eq!(state.fds.min_headroom, u64::MAX);
+        assert!(state.fds.usage.is_some());
+        executor.shutdown().await;
+    }
+
+    /// A mock executor warming VMs up with `warmup`, which the mock runs for `latency`
+    fn warming_executor(warmup: WarmupConfig, latency: std::time::Duration) -> ExecutorService {
+        ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: latency,
+            warmup,
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_warmup_runs_once_per_new_vm_and_never_on_reuse() {
+        let executor = warming_executor(
+            WarmupConfig {
+                code: Some("import numpy".to_string()),
+                ..WarmupConfig::default()
+            },
+            std::time::Duration::ZERO,
+        );
+        assert_eq!(executor.warm(2).await, 2);
+        let pooled = executor.pooled_vms().await;
+        assert!(
+            pooled
+                .iter()
+                .all(|vm| vm.warmup.is_some_and(|report| report.success)),
+            "{pooled:?}"
+        );
+        // The warm-up isn't one of the VM's executions
+        assert!(pooled.iter().all(|vm| vm.executions == 0));
+
+        for i in 0..3 {
+            run(&executor, &format!("executor-warmup-request-{i}")).await;
+        }
+        let stats = executor.stats().await;
+        assert_eq!((stats.vms_created, stats.vms_warmed_up), (2, 2));
+        assert_eq!(stats.pool_hits, 3);
+        let reused = executor.pooled_vms().await;
+        for vm in &reused {
+            let before = pooled.iter().find(|p| p.vm_id == vm.vm_id).unwrap();
+            assert_eq!(vm.warmup, before.warmup);
+        }
+
+        // A VM of its own is warmed up too
+        let dedicated = ExecutionSpec {
+            debug_boot: true,
+            ..ExecutionSpec::code("print('cold')")
+        };
+        executor.execute(dedicated).await.unwrap();
+        assert_eq!(executor.stats().await.vms_warmed_up, 3);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_warmup_keeps_or_discards_the_vm_as_configured() {
+        let slow = std::time::Duration::from_millis(200);
+        let warmup = WarmupConfig {
+            code: Some("import torch".to_string()),
+            timeout: std::time::Duration::from_millis(20),
+            ..WarmupConfig::default()
+        };
+        let lenient = warming_executor(warmup.clone(), slow);
+        assert_eq!(lenient.warm(1).await, 1);
+        let report = lenient.pooled_vms().await[0].warmup.unwrap();
+        assert!(!report.success);
+        lenient.shutdown().await;
+
+        let strict = warming_executor(
+            WarmupConfig {
+                fatal: true,
+                ..warmup
+            },
+            slow,
+        );
+        assert_eq!(strict.warm(1).await, 0);
+        let dedicated = ExecutionSpec {
+            debug_boot: true,
+            ..ExecutionSpec::code("print('cold')")
+        };
+        let err = strict.execute(dedicated).await.unwrap_err();
+        assert!(err.to_string().contains("within 20ms"), "{err}");
+        strict.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_fails_cold_starts_but_serves_pooled_vms() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            vm_creation_breaker: BreakerConfig {
+                failure_threshold: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        assert_eq!(executor.warm(1).await, 1);
+        for _ in 0..2 {
+            executor.inner.breaker.record_failure("kernel not found");
+        }
+
+        let vm_id = executor.pooled_vms().await[0].vm_id.clone();
+        assert_eq!(run(&executor, "executor-breaker-request").await, vm_id);
+        // A VM of its own means a cold start
+        let dedicated = ExecutionSpec {
+            debug_boot: true,
+            ..ExecutionSpec::code("print('cold')")
+        };
+        let started = std::time::Instant::now();
+        let err = executor.execute(dedicated).await.unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+        assert_eq!(err.code(), "vm_creation_unavailable");
+        assert!(err.to_string().contains("kernel not found"), "{err}");
+        assert_eq!(executor.warm(1).await, 0);
+        let status = executor.vm_creation_status();
+        assert_eq!(status.state, crate::breaker::BreakerState::Open);
+        assert_eq!(status.consecutive_failures, 2);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_aged_vms_are_retired_oldest_first_without_interrupting_executions() {
+        let max_age = std::time::Duration::from_millis(100);
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            vm_max_age: Some(max_age),
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(300),
+            ..Default::default()
+        }));
+        assert_eq!(executor.warm(3).await, 3);
+        let vm_ids: Vec<String> = executor
+            .pooled_vms()
+            .await
+            .into_iter()
+            .map(|vm| vm.vm_id)
+            .collect();
+        // Nothing is old enough yet
+        assert_eq!(executor.retire_aged().await, 0);
+        let mut events = events::subscribe();
+
+        // The oldest pooled VM is busy while the others age out
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move {
+                executor
+                    .execute(ExecutionSpec::code("print('aging')"))
+                    .await
+            }
+        });
+        tokio::time::sleep(max_age + std::time::Duration::from_millis(50)).await;
+        assert_eq!(executor.retire_aged().await, 2);
+        assert!(executor.pooled_vms().await.is_empty());
+
+        let response = running.await.unwrap().unwrap();
+        assert_eq!(response.stdout, "Mock execution of: print('aging')\n");
+        assert_eq!(response.vm_id.as_deref(), Some(vm_ids[0].as_str()));
+        // It came back past its age and was retired rather than pooled
+        let reason = |vm_id: &String| (vm_id.clone(), DiscardReason::MaxAge);
+        assert_eq!(
+            discards(&mut events, &vm_ids),
+            [reason(&vm_ids[1]), reason(&vm_ids[2]), reason(&vm_ids[0])]
+        );
+        assert!(executor.pooled_vms().await.is_empty());
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_vms_failing_health_probes_twice_are_replaced() {
+        let executor = executor();
+        assert_eq!(executor.warm(2).await, 2);
+        let vm_ids: Vec<_> = executor
+            .pooled_vms()
+            .await
+            .into_iter()
+            .map(|vm| vm.vm_id)
+            .collect();
+        assert!(executor.pooled_vms().await[0].last_probe_at.is_none());
+        // Nothing listens on the discard port, so the first VM's agent looks dead
+        executor.inner.pool.lock().await[0]
+            .use_api_endpoints("/nonexistent.socket", "http://127.0.0.1:9");
+        let mut events = events::subscribe();
+
+        assert_eq!(executor.probe_pool(std::time::Duration::ZERO).await, 0);
+        let pooled = executor.pooled_vms().await;
+        // Probing keeps the pool's order
+        assert_eq!(pooled[0].vm_id, vm_ids[0]);
+        assert!(pooled.iter().all(|vm| vm.last_probe_at.is_some()));
+
+        assert_eq!(executor.probe_pool(std::time::Duration::ZERO).await, 1);
+        assert_eq!(
+            discards(&mut events, &vm_ids),
+            [(vm_ids[0].clone(), DiscardReason::Unhealthy)]
+        );
+        // Replaced up to the pool target, the healthy VM untouched
+        let pooled = executor.pooled_vms().await;
+        assert_eq!(pooled.len(), 2);
+        assert_eq!(pooled[0].vm_id, vm_ids[1]);
+        assert_eq!(executor.stats().await.vms_created, 3);
+        assert!(!executor.probe_vm("no-such-vm").await);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_vm_leaving_a_workspace_behind_is_discarded() {
+        use axum::routing::{get, post};
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // An agent that deletes workspaces until told otherwise
+        let leftover = Arc::new(AtomicU64::new(0));
+        let reported = leftover.clone();
+        let app = axum::Router::new()
+            .route(
+                "/health",
+                get(move || async move {
+                    axum::Json(serde_json::json!({
+                        "status": "healthy",
+                        "protocol_version": crate::guest_protocol::HOST_PROTOCOL_VERSION,
+                        "leftover_workspaces": reported.load(Ordering::Relaxed),
+                    }))
+                }),
+            )
+            .route(
+                "/execute",
+                post(|| async {
+                    axum::Json(serde_json::json!({ "stdout": "", "stderr": "", "success": true }))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let executor = executor();
+        assert_eq!(executor.warm(1).await, 1);
+        let vm_id = executor.pooled_vms().await[0].vm_id.clone();
+        executor.inner.pool.lock().await[0]
+            .use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        let mut events = events::subscribe();
+
+        // A clean guest goes back to the pool
+        let response = executor.execute(ExecutionSpec::code("1")).await.unwrap();
+        assert_eq!(response.vm_id.as_deref(), Some(vm_id.as_str()));
+        assert!(response.exec_id.is_some());
+        assert_eq!(executor.pooled_vms().await[0].vm_id, vm_id);
+
+        // One that kept a workspace is discarded, though the execution itself succeeded
+        leftover.store(1, Ordering::Relaxed);
+        let response = executor.execute(ExecutionSpec::code("1")).await.unwrap();
+        assert!(response.success);
+        assert_eq!(
+            discards(&mut events, std::slice::from_ref(&vm_id)),
+            [(vm_id.clone(), DiscardReason::WorkspaceLeftover)]
+        );
+        assert!(
+            executor
+                .pooled_vms()
+                .await
+                .iter()
+                .all(|vm| vm.vm_id != vm_id)
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_affinity_key_prefers_its_last_vm() {
+        let executor = executor();
+        assert_eq!(executor.warm(2).await, 2);
+        let with_key = |request_id: &str, key: Option<&str>| {
+            executor.execute(ExecutionSpec {
+                request_id: request_id.to_string(),
+                affinity_key: key.map(str::to_string),
+                ..ExecutionSpec::code("print('affinity')")
+            })
+        };
+        let vm_of = |events: &mut tokio::sync::broadcast::Receiver<VmEvent>, request_id: &str| {
+            std::iter::from_fn(|| events.try_recv().ok())
+                .find_map(|event| match event {
+                    VmEvent::Acquired {
+                        vm_id,
+                        request_id: id,
+                    } if id == request_id => Some(vm_id),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let mut events = events::subscribe();
+
+        // The first run takes the oldest VM and puts it back behind the other one
+        with_key("affinity-1", Some("notebook")).await.unwrap();
+        let first = vm_of(&mut events, "affinity-1");
+        // Unkeyed requests still take the oldest VM, so the other one serves this
+        with_key("affinity-2", None).await.unwrap();
+        assert_ne!(vm_of(&mut events, "affinity-2"), first);
+        // Now the keyed VM is the oldest again; make it the newest
+        with_key("affinity-3", Some("other")).await.unwrap();
+        assert_eq!(vm_of(&mut events, "affinity-3"), first);
+
+        // "notebook" and "other" both point at `first`, which is now behind the other VM
+        with_key("affinity-4", Some("notebook")).await.unwrap();
+        assert_eq!(vm_of(&mut events, "affinity-4"), first);
+        let stats = executor.stats().await;
+        assert_eq!((stats.affinity_hits, stats.affinity_misses), (1, 2));
+
+        // A discarded VM's keys are forgotten
+        let vm = executor.inner.pool.lock().await.pop_back().unwrap();
+        assert_eq!(vm.vm_id(), first);
+        executor.discard_vm(vm, DiscardReason::Shutdown);
+        assert_eq!(executor.affinity().vms.len(), 0);
+        with_key("affinity-5", Some("notebook")).await.unwrap();
+        assert_eq!(executor.stats().await.affinity_misses, 3);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_one_permit_runs_queued_requests_by_priority() {
+        use crate::dispatch::Priority;
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(30),
+            max_concurrent_executions: Some(1),
+            ..Default::default()
+        }));
+        let finished = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let submit = |name: &'static str, priority| {
+            let (executor, finished) = (executor.clone(), finished.clone());
+            tokio::spawn(async move {
+                let spec = ExecutionSpec {
+                    priority,
+                    ..ExecutionSpec::code(name)
+                };
+                executor.execute(spec).await.unwrap();
+                finished.lock().unwrap().push(name);
+            })
+        };
+        // Each request is queued before the next is submitted
+        let mut tasks = vec![submit("first", Priority::Low)];
+        let queue = [
+            ("low-1", Priority::Low),
+            ("normal", Priority::Normal),
+            ("high-1", Priority::High),
+            ("low-2", Priority::Low),
+            ("high-2", Priority::High),
+        ];
+        while executor.stats().await.in_flight == 0 {
+            tokio::task::yield_now().await;
+        }
+        for (queued, (name, priority)) in queue.into_iter().enumerate() {
+            tasks.push(submit(name, priority));
+            while executor.stats().await.queued == queued {
+                tokio::task::yield_now().await;
+            }
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(
+            *finished.lock().unwrap(),
+            ["first", "high-1", "high-2", "normal", "low-1", "low-2"]
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_queued_requests_hear_their_place_shrink() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(30),
+            max_concurrent_executions: Some(1),
+            ..Default::default()
+        }));
+        let submit = |name: &str| {
+            let executor = executor.clone();
+            let (queue_updates, places) = tokio::sync::mpsc::unbounded_channel();
+            let spec = ExecutionSpec {
+                queue_updates: Some(queue_updates),
+                ..ExecutionSpec::code(name)
+            };
+            (
+                tokio::spawn(async move { executor.execute(spec).await }),
+                places,
+            )
+        };
+        let (first, mut first_places) = submit("first");
+        while executor.stats().await.in_flight == 0 {
+            tokio::task::yield_now().await;
+        }
+        let mut waiting = Vec::new();
+        for (queued, name) in ["second", "third", "fourth"].into_iter().enumerate() {
+            waiting.push(submit(name));
+            while executor.stats().await.queued == queued {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        first.await.unwrap().unwrap();
+        // Requests that never queued hear nothing
+        assert!(first_places.recv().await.is_none());
+        for (queued_at, (task, mut places)) in waiting.into_iter().enumerate() {
+            task.await.unwrap().unwrap();
+            let mut positions = Vec::new();
+            while let Some(place) = places.recv().await {
+                positions.push(place.position);
+                if place.position > 0 {
+                    // Executions had finished by the time the queue moved, so there is an average
+                    assert!(place.eta_ms.is_some() || positions.len() == 1, "{place:?}");
+                }
+            }
+            assert_eq!(positions, (0..=queued_at + 1).rev().collect::<Vec<_>>());
+        }
+        let stats = executor.stats().await;
+        assert!(
+            stats.avg_execution_ms.is_some_and(|ms| ms >= 30),
+            "{stats:?}"
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_console_printed_during_the_execution_is_returned() {
+        let runtime_dir = std::env::temp_dir().join(format!("fc-console-{}", std::process::id()));
+        std::fs::create_dir_all(&runtime_dir).unwrap();
+        let config = Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(200),
+            runtime_dir: runtime_dir.clone(),
+            console_capture: console::ConsoleCaptureConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let executor = ExecutorService::new(config.clone());
+        let mut events = events::subscribe();
+        let execution = tokio::spawn({
+            let executor = executor.clone();
+            async move {
+                let spec = ExecutionSpec {
+                    request_id: "console-capture".to_string(),
+                    capture_console: true,
+                    ..ExecutionSpec::code("print(1)")
+                };
+                executor.execute(spec).await
+            }
+        });
+
+        // The kernel prints while the code runs
+        let append = |vm_id: &str, line: &str| {
+            use std::io::Write;
+            std::fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(config.runtime_path(&format!("fc-stdout-{vm_id}.log")))
+                .unwrap()
+                .write_all(line.as_bytes())
+                .unwrap();
+        };
+        loop {
+            match tokio::time::timeout(std::time::Duration::from_secs(5), events.recv()).await {
+                Ok(Ok(VmEvent::Acquired { vm_id, request_id }))
+                    if request_id == "console-capture" =>
+                {
+                    append(&vm_id, "[ 1.2] python3[201]: segfault at 0\n");
+                    break;
+                }
+                Ok(_) => continue,
+                Err(_) => panic!("no event within 5s"),
+            }
+        }
+
+        let response = execution.await.unwrap().unwrap();
+        assert_eq!(
+            response.console.as_deref(),
+            Some("[ 1.2] python3[201]: segfault at 0\n")
+        );
+        assert!(!response.console_truncated);
+        // Console output of a VM that ran other requests is never shown
+        assert_eq!(executor.stats().await.idle_vms, 0);
+        executor.shutdown().await;
+        let _ = std::fs::remove_dir_all(&runtime_dir);
+    }
+
+    #[tokio::test]
+    async fn test_slow_boot_leaves_less_of_the_deadline_to_run() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_boot_latency: std::time::Duration::from_millis(500),
+            mock_latency: std::time::Duration::from_millis(300),
+            ..Default::default()
+        }));
+        let within = |ms| ExecutionSpec {
+            timeout: Some(std::time::Duration::from_millis(ms)),
+            ..ExecutionSpec::code("print(1)")
+        };
+
+        // The boot takes most of the budget, so the execution fails at once instead of
+        // running past the deadline
+        let started = std::time::Instant::now();
+        let err = executor.execute(within(550)).await.unwrap_err();
+        assert!(
+            matches!(&err, ExecutionError::DeadlineExceeded { phase, budget_ms: 550 } if phase == "execute"),
+            "{err}"
+        );
+        assert!(started.elapsed() < std::time::Duration::from_millis(700));
+
+        // Or too little is left to attempt a boot at all, which says nothing about the host
+        let started = std::time::Instant::now();
+        let err = executor.execute(within(300)).await.unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_millis(100));
+        assert!(
+            matches!(&err, ExecutionError::DeadlineExceeded { phase, .. } if phase == "boot"),
+            "{err}"
+        );
+        assert_eq!(err.code(), "timeout");
+        assert_eq!(executor.vm_creation_status().consecutive_failures, 0);
+
+        // Room for both
+        assert!(executor.execute(within(2_000)).await.unwrap().success);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_workloads_are_counted_by_configured_image_and_profile() {
+        let image_dir = std::env::temp_dir().join(format!("fc-workload-{}", std::process::id()));
+        std::fs::create_dir_all(&image_dir).unwrap();
+        std::fs::write(crate::images::image_path(&image_dir, "stats-slim"), "").unwrap();
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(700),
+            image_dir: image_dir.clone(),
+            deps_profiles: [("stats-numpy".to_string(), "/srv/deps/numpy.ext4".into())].into(),
+            ..Default::default()
+        }));
+        let spec = |code: &str, image: &str, profile: Option<&str>, timeout_ms| ExecutionSpec {
+            image: Some(image.to_string()),
+            deps_profile: profile.map(str::to_string),
+            timeout: Some(std::time::Duration::from_millis(timeout_ms)),
+            ..ExecutionSpec::code(code)
+        };
+
+        assert!(
+            executor
+                .execute(spec("print(1)", "stats-slim", None, 5_000))
+                .await
+                .is_ok()
+        );
+        let long = "x = 1\n".repeat(100);
+        let with_numpy = spec(&long, "stats-slim", Some("stats-numpy"), 5_000);
+        assert!(executor.execute(with_numpy).await.is_ok());
+        // Runs past its deadline
+        let slow = spec("print(2)", "stats-slim", Some("stats-numpy"), 600);
+        assert!(executor.execute(slow).await.is_err());
+        // Names the server doesn't know are never labels of their own
+        let unknown = spec("print(3)", "stats-slim", Some("scipy"), 5_000);
+        assert!(executor.execute(unknown).await.is_err());
+        let unknown = spec("print(4)", "made-up", Some("stats-numpy"), 5_000);
+        assert!(executor.execute(unknown).await.is_err());
+
+        let counted = |image, profile, outcome| {
+            telemetry::METRICS
+                .counter_value(
+                    "fc_executions_total",
+                    &[
+                        ("language", "python"),
+                        ("image", image),
+                        ("profile", profile),
+                        ("outcome", outcome),
+                    ],
+                )
+                .unwrap_or(0)
+        };
+        assert_eq!(counted("stats-slim", "default", "success"), 1);
+        assert_eq!(counted("stats-slim", "stats-numpy", "success"), 1);
+        assert_eq!(counted("stats-slim", "stats-numpy", "timeout"), 1);
+        assert_eq!(counted("stats-slim", "other", "error"), 1);
+        assert_eq!(counted("other", "stats-numpy", "error"), 1);
+        let metrics = telemetry::METRICS.render();
+        assert!(metrics.contains(
+            "fc_execution_code_bytes_sum{image=\"stats-slim\",language=\"python\",profile=\"stats-numpy\"} 608"
+        ), "{metrics}");
+        assert!(!metrics.contains("scipy") && !metrics.contains("made-up"));
+
+        let stats = executor.workload_stats();
+        let rows: Vec<_> = stats
+            .iter()
+            .map(|s| (s.image.as_str(), s.profile.as_str(), s.executions))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("other", "stats-numpy", 1),
+                ("stats-slim", "default", 1),
+                ("stats-slim", "other", 1),
+                ("stats-slim", "stats-numpy", 2),
+            ]
+        );
+        let numpy = &stats[3];
+        assert_eq!((numpy.succeeded, numpy.timed_out, numpy.errors), (1, 1, 0));
+        assert_eq!((numpy.code_bytes_total, numpy.code_bytes_max), (608, 600));
+        assert!(numpy.duration_ms_max >= 600 && numpy.duration_ms_total >= 1_300);
+        assert_eq!(stats[0].errors, 1);
+        executor.shutdown().await;
+        std::fs::remove_dir_all(&image_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_debug_executions_return_their_events() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            ..Default::default()
+        }));
+        let secret = "API_TOKEN = 'hunter2'; print(API_TOKEN)";
+        let spec = || ExecutionSpec {
+            debug: true,
+            max_output_bytes: Some(16),
+            ..ExecutionSpec::code(secret)
+        };
+
+        let cold = executor.execute(spec()).await.unwrap();
+        let events = cold.events.unwrap();
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| (e.event.as_str(), e.detail.as_deref().unwrap_or("")))
+            .collect();
+        assert_eq!(kinds[0], ("permit_granted", ""));
+        assert!(kinds.contains(&("boot_phase", "boot")), "{kinds:?}");
+        let acquired = kinds.iter().position(|k| *k == ("pool_acquire", "miss"));
+        let sent = kinds.iter().position(|k| k.0 == "guest_request_sent");
+        assert!(acquired.unwrap() < sent.unwrap(), "{kinds:?}");
+        assert!(kinds.contains(&("truncated", "stdout")), "{kinds:?}");
+        assert_eq!(kinds.last(), Some(&("pool_release", "returned")));
+        let truncated = events.iter().find(|e| e.event == DebugEventKind::Truncated);
+        assert_eq!(truncated.unwrap().bytes, Some(16));
+        assert!(events.windows(2).all(|w| w[0].at_ms <= w[1].at_ms));
+        let json = serde_json::to_string(&events).unwrap();
+        assert!(
+            !json.contains("hunter2") && !json.contains("API_TOKEN"),
+            "{json}"
+        );
+
+        // A pooled VM boots nothing
+        let warm = executor.execute(spec()).await.unwrap().events.unwrap();
+        assert!(warm.iter().all(|e| e.event != DebugEventKind::BootPhase));
+        assert!(warm.iter().any(|e| e.detail.as_deref() == Some("hit")));
+        let plain = executor.execute(ExecutionSpec::code("print(1)")).await;
+        assert_eq!(plain.unwrap().events, None);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_serves_its_owner_until_deleted_or_expired() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            ..Default::default()
+        }));
+        let quotas = crate::quota::QuotaTracker::new(Default::default());
+        let keep = || ExecutionSpec {
+            keep_alive: Some(std::time::Duration::from_secs(1)),
+            ..ExecutionSpec::code("open('state', 'w').write('1')")
+        };
+
+        let first = executor.execute(keep()).await.unwrap();
+        let sandbox_id = first.sandbox_id.unwrap();
+        assert!(first.sandbox_expires_at_ms.is_some());
+        executor.assign_sandbox(
+            &sandbox_id,
+            Some("alice"),
+            quotas.acquire(Some("alice")).unwrap(),
+        );
+        let follow_up = executor
+            .execute_in_sandbox(&sandbox_id, Some("alice"), ExecutionSpec::code("print(1)"))
+            .await
+            .unwrap();
+        assert_eq!(follow_up.vm_id, first.vm_id);
+        assert_eq!(follow_up.stdout, "Mock execution of: print(1)\n");
+        let other = executor
+            .execute_in_sandbox(&sandbox_id, Some("bob"), ExecutionSpec::code("print(1)"))
+            .await;
+        assert!(matches!(other, Err(ExecutionError::SandboxNotFound(_))));
+
+        // Deleted early
+        executor.delete_sandbox(&sandbox_id, Some("alice")).unwrap();
+        let gone = executor
+            .execute_in_sandbox(&sandbox_id, Some("alice"), ExecutionSpec::code("print(1)"))
+            .await;
+        assert!(matches!(gone, Err(ExecutionError::SandboxExpired(_))));
+        let response = axum::response::IntoResponse::into_response(gone.unwrap_err());
+        assert_eq!(response.status(), axum::http::StatusCode::GONE);
+
+        // Reclaimed past its deadline
+        let second = executor.execute(keep()).await.unwrap();
+        let sandbox_id = second.sandbox_id.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(executor.reclaim_expired_sandboxes(), 1);
+        let expired = executor
+            .execute_in_sandbox(&sandbox_id, None, ExecutionSpec::code("print(1)"))
+            .await;
+        assert!(matches!(expired, Err(ExecutionError::SandboxExpired(_))));
+        let unknown = executor.delete_sandbox("sbx-unknown", None);
+        assert!(matches!(unknown, Err(ExecutionError::SandboxNotFound(_))));
+        assert_eq!(executor.inner.sandboxes.len(), 0);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_runs_wait_for_a_permit_and_are_recorded() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(200),
+            max_concurrent_executions: Some(1),
+            ..Default::default()
+        }));
+        let sandbox_id = executor
+            .execute(ExecutionSpec {
+                keep_alive: Some(std::time::Duration::from_secs(5)),
+                ..ExecutionSpec::code("x = 1")
+            })
+            .await
+            .unwrap()
+            .sandbox_id
+            .unwrap();
+
+        let busy = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.execute(ExecutionSpec::code("print(1)")).await }
+        });
+        while executor.stats().await.in_flight == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+        let in_sandbox = tokio::spawn({
+            let executor = executor.clone();
+            let sandbox_id = sandbox_id.clone();
+            async move {
+                let spec = ExecutionSpec {
+                    request_id: "sandbox-permit-request".to_string(),
+                    ..ExecutionSpec::code("print(x)")
+                };
+                executor.execute_in_sandbox(&sandbox_id, None, spec).await
+            }
+        });
+        // Queued behind the plain execution rather than running alongside it
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while executor.stats().await.queued == 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the sandbox run should queue for a permit");
+        busy.await.unwrap().unwrap();
+        let response = in_sandbox.await.unwrap().unwrap();
+        assert_eq!(response.sandbox_id.as_deref(), Some(sandbox_id.as_str()));
+
+        let record = EXECUTION_HISTORY
+            .recent(usize::MAX)
+            .into_iter()
+            .find(|record| record.request_id == "sandbox-permit-request")
+            .expect("the sandbox run is in the history");
+        assert_eq!(record.vm_id, response.vm_id);
+        assert!(record.success);
+        let stats = executor.stats().await;
+        assert_eq!((stats.executions, stats.in_flight), (3, 0));
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_execution_discards_its_vm() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            mock_latency: std::time::Duration::from_secs(30),
+            ..Default::default()
+        }));
+        let mut events = events::subscribe();
+        let execution = tokio::spawn({
+            let executor = executor.clone();
+            async move {
+                let spec = ExecutionSpec {
+                    request_id: "cancelled-execution".to_string(),
+                    ..ExecutionSpec::code("print(1)")
+                };
+                executor.execute(spec).await
+            }
+        });
+        // Events of other tests go by on the same channel
+        let mut next_event = async || loop {
+            match tokio::time::timeout(std::time::Duration::from_secs(5), events.recv()).await {
+                Ok(Ok(event)) => return event,
+                Ok(Err(_)) => continue,
+                Err(_) => panic!("no event within 5s"),
+            }
+        };
+        let vm_id = loop {
+            if let VmEvent::Acquired { vm_id, request_id } = next_event().await
+                && request_id == "cancelled-execution"
+            {
+                break vm_id;
+            }
+        };
+
+        execution.abort();
+        assert!(execution.await.unwrap_err().is_cancelled());
+        loop {
+            if let VmEvent::Discarded { vm_id: id, reason } = next_event().await
+                && id == vm_id
+            {
+                assert_eq!(reason, DiscardReason::Cancelled);
+                break;
+            }
+        }
+        let stats = executor.stats().await;
+        assert_eq!((stats.in_flight, stats.cancelled), (0, 1));
+        assert_eq!((stats.idle_vms, stats.executions), (0, 0));
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_exposed_port_outlives_the_execution_until_it_expires() {
+        let executor = ExecutorService::new(Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            expose: crate::port_forward::ExposeConfig {
+                max_exposed: 1,
+                ttl: std::time::Duration::from_millis(300),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let mut events = events::subscribe();
+        let spec = ExecutionSpec {
+            expose_port: Some(8000),
+            ..ExecutionSpec::code("print(1)")
+        };
+        let response = executor.execute(spec.clone()).await.unwrap();
+        let host_port = response.exposed_port.unwrap();
+        assert!(response.exposed_until_ms.unwrap() > now_millis());
+        let vm_id = response.vm_id.unwrap();
+        let host = std::net::SocketAddr::from(([127, 0, 0, 1], host_port));
+        assert!(tokio::net::TcpStream::connect(host).await.is_ok());
+
+        // The only slot is taken until the port closes
+        assert!(matches!(
+            executor.execute(spec.clone()).await,
+            Err(ExecutionError::ResourceExhausted(_))
+        ));
+        loop {
+            match tokio::time::timeout(std::time::Duration::from_secs(5), events.recv()).await {
+                Ok(Ok(VmEvent::Discarded { vm_id: id, reason })) if id == vm_id => {
+                    assert_eq!(reason, DiscardReason::ExposeExpired);
+                    break;
+                }
+                Ok(_) => continue,
+                Err(_) => panic!("the exposed VM wasn't discarded"),
+            }
+        }
+        assert!(tokio::net::TcpStream::connect(host).await.is_err());
+        assert!(executor.execute(spec).await.unwrap().exposed_port.is_some());
+        // Shutting down ends the remaining forward early
+        tokio::time::timeout(std::time::Duration::from_secs(2), executor.shutdown())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_affinity_forgets_least_recently_used_keys() {
+        let mut affinity = Affinity::default();
+        for i in 0..AFFINITY_CAPACITY {
+            affinity.remember(&format!("key-{i}"), "vm-a");
+        }
+        // Refreshing a key keeps it from being the next to go
+        affinity.remember("key-0", "vm-b");
+        affinity.remember("new", "vm-c");
+        assert_eq!(affinity.vms.len(), AFFINITY_CAPACITY);
+        assert_eq!(affinity.vm_for("key-0"), Some("vm-b"));
+        assert_eq!(affinity.vm_for("key-1"), None);
+        assert_eq!(affinity.vm_for("new"), Some("vm-c"));
+
+        affinity.forget_vm("vm-a");
+        assert_eq!(affinity.vms.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cleans_up_every_vm() {
+        let executor = executor();
+        let vm_id = run(&executor, "executor-shutdown-request").await;
+        assert!(live_vm(&vm_id).is_some());
+
+        executor.shutdown().await;
+        assert!(live_vm(&vm_id).is_none());
+        let stats = executor.stats().await;
+        assert_eq!(stats.idle_vms, 0);
+        assert!(stats.shutting_down);
+
+        let err = executor
+            .execute(ExecutionSpec::code("print(1)"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ExecutionError::ShuttingDown));
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_drop_cleans_up_pooled_vms() {
+        let executor = executor();
+        let vm_id = run(&executor, "executor-drop-request").await;
+        drop(executor);
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while live_vm(&vm_id).is_some() {
+            assert!(std::time::Instant::now() < deadline, "VM {vm_id} leaked");
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+}
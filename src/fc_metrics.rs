@@ -0,0 +1,113 @@
+use serde_json::Value;
+
+/// Key counters extracted from Firecracker's metrics output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSummary {
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+    pub block_read_bytes: u64,
+    pub block_write_bytes: u64,
+    pub seccomp_faults: u64,
+}
+
+impl MetricsSummary {
+    /// Extract the key counters from one metrics JSON object, treating missing fields as zero
+    pub fn from_value(value: &Value) -> Self {
+        let field = |pointer: &str| value.pointer(pointer).and_then(Value::as_u64).unwrap_or(0);
+        Self {
+            net_rx_bytes: field("/net/rx_bytes_count"),
+            net_tx_bytes: field("/net/tx_bytes_count"),
+            block_read_bytes: field("/block/read_bytes"),
+            block_write_bytes: field("/block/write_bytes"),
+            seccomp_faults: field("/seccomp/num_faults"),
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.net_rx_bytes += other.net_rx_bytes;
+        self.net_tx_bytes += other.net_tx_bytes;
+        self.block_read_bytes += other.block_read_bytes;
+        self.block_write_bytes += other.block_write_bytes;
+        self.seccomp_faults += other.seccomp_faults;
+    }
+
+    /// Add these counters to the global Prometheus registry
+    pub fn record(&self) {
+        let counters = [
+            ("firecracker_net_rx_bytes_total", self.net_rx_bytes),
+            ("firecracker_net_tx_bytes_total", self.net_tx_bytes),
+            ("firecracker_block_read_bytes_total", self.block_read_bytes),
+            (
+                "firecracker_block_write_bytes_total",
+                self.block_write_bytes,
+            ),
+            ("firecracker_seccomp_faults_total", self.seccomp_faults),
+        ];
+        for (name, value) in counters {
+            crate::telemetry::increment_counter(name, &[], value);
+        }
+    }
+}
+
+/// Iterate over every complete metrics line in the file contents.
+///
+/// Firecracker writes one JSON object per flush; a trailing partially written line is skipped.
+fn metrics_lines(contents: &str) -> impl Iterator<Item = Value> + '_ {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(Value::is_object)
+}
+
+/// Parse the most recent metrics object from a Firecracker metrics file
+pub fn parse_latest(contents: &str) -> Option<Value> {
+    metrics_lines(contents).last()
+}
+
+/// Sum the key counters over every flush in a metrics file.
+///
+/// Firecracker resets its counters after each flush, so each line holds deltas.
+pub fn summarize(contents: &str) -> MetricsSummary {
+    metrics_lines(contents).fold(MetricsSummary::default(), |mut acc, value| {
+        acc.add(&MetricsSummary::from_value(&value));
+        acc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS_FIXTURE: &str = r#"{"utc_timestamp_ms":1,"net":{"rx_bytes_count":100,"tx_bytes_count":50},"block":{"read_bytes":4096,"write_bytes":0},"seccomp":{"num_faults":0}}
+{"utc_timestamp_ms":2,"net":{"rx_bytes_count":10,"tx_bytes_count":5,"some_new_counter":7},"block":{"read_bytes":0,"write_bytes":512},"brand_new_section":{"x":1}}
+{"utc_timestamp_ms":3,"net":{"rx_by"#;
+
+    #[test]
+    fn test_parse_latest_skips_partial_line() {
+        let latest = parse_latest(METRICS_FIXTURE).unwrap();
+        assert_eq!(latest["utc_timestamp_ms"], 2);
+        assert_eq!(latest["brand_new_section"]["x"], 1);
+    }
+
+    #[test]
+    fn test_parse_latest_empty() {
+        assert!(parse_latest("").is_none());
+        assert!(parse_latest("\n\n").is_none());
+    }
+
+    #[test]
+    fn test_summarize_tolerates_missing_and_unknown_fields() {
+        let summary = summarize(METRICS_FIXTURE);
+        assert_eq!(
+            summary,
+            MetricsSummary {
+                net_rx_bytes: 110,
+                net_tx_bytes: 55,
+                block_read_bytes: 4096,
+                block_write_bytes: 512,
+                seccomp_faults: 0,
+            }
+        );
+    }
+}
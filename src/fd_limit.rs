@@ -0,0 +1,199 @@
+use crate::ExecutionError;
+use serde::Serialize;
+use std::path::Path;
+
+/// File descriptor limits of the server process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdLimitConfig {
+    /// Soft `RLIMIT_NOFILE` raised to at startup, capped at the hard limit; `None` keeps the
+    /// inherited one
+    pub nofile: Option<u64>,
+    /// Free descriptors below which the pool stops booting VMs; 0 never stops it
+    pub min_headroom: u64,
+}
+
+impl Default for FdLimitConfig {
+    fn default() -> Self {
+        Self {
+            nofile: Some(65536),
+            min_headroom: 128,
+        }
+    }
+}
+
+/// Host descriptors a VM is expected to take: the server's own, and the ones it hands to
+/// the Firecracker process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct VmFds {
+    /// Connection to the Firecracker API socket
+    pub api_socket: u64,
+    /// stdout and stderr logs
+    pub log_files: u64,
+    /// Handle on the Firecracker (or jailer) process, once started
+    pub process: u64,
+    /// Connection to the guest agent while a request is sent
+    pub guest_connections: u64,
+}
+
+impl VmFds {
+    pub fn expected(process_started: bool) -> Self {
+        Self {
+            api_socket: 1,
+            log_files: 2,
+            process: u64::from(process_started),
+            guest_connections: 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.api_socket + self.log_files + self.process + self.guest_connections
+    }
+}
+
+/// Open descriptors of the server against its limit, as `/admin/state` shows them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct FdUsage {
+    /// Descriptors open now
+    pub open: u64,
+    /// Soft `RLIMIT_NOFILE`
+    pub limit: u64,
+    /// Descriptors left before opening one fails with `EMFILE`
+    pub headroom: u64,
+}
+
+impl FdUsage {
+    pub fn new(open: u64, limit: u64) -> Self {
+        Self {
+            open,
+            limit,
+            headroom: headroom(open, limit),
+        }
+    }
+}
+
+/// Descriptors that can still be opened with `open` of `limit` in use
+pub fn headroom(open: u64, limit: u64) -> u64 {
+    limit.saturating_sub(open)
+}
+
+/// Soft and hard `RLIMIT_NOFILE` of this process
+pub fn nofile_limits() -> std::io::Result<(u64, u64)> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid, writable rlimit
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((limit.rlim_cur, limit.rlim_max))
+}
+
+/// Raise the soft `RLIMIT_NOFILE` to `target`, capped at the hard limit; never lowers it.
+/// Returns the old and new soft limits.
+pub fn raise_nofile(target: u64) -> std::io::Result<(u64, u64)> {
+    let (soft, hard) = nofile_limits()?;
+    let new = target.min(hard);
+    if new <= soft {
+        return Ok((soft, soft));
+    }
+    let limit = libc::rlimit {
+        rlim_cur: new,
+        rlim_max: hard,
+    };
+    // SAFETY: `limit` is a valid rlimit
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((soft, new))
+}
+
+/// Raise the soft limit as `config` asks at startup, logging the old and new limits
+pub fn apply(config: &FdLimitConfig) {
+    let Some(target) = config.nofile else {
+        return;
+    };
+    match raise_nofile(target) {
+        Ok((old, new)) if old == new => tracing::info!("Open file limit: {}", old),
+        Ok((old, new)) => tracing::info!("Raised the open file limit from {} to {}", old, new),
+        Err(e) => tracing::warn!("Cannot raise the open file limit to {}: {}", target, e),
+    }
+}
+
+/// Descriptors open in the process whose `fd` directory is `fd_dir`, e.g. `/proc/self/fd`
+pub fn count_open(fd_dir: &Path) -> Option<u64> {
+    Some(std::fs::read_dir(fd_dir).ok()?.count() as u64)
+}
+
+/// This process's descriptors against its soft limit; `None` where `/proc` can't tell
+pub fn usage() -> Option<FdUsage> {
+    let open = count_open(Path::new("/proc/self/fd"))?;
+    let (limit, _) = nofile_limits().ok()?;
+    Some(FdUsage::new(open, limit))
+}
+
+/// Whether `e` means the process or the system ran out of file descriptors
+pub fn is_exhausted(e: &std::io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+/// The `ResourceError` for a failure to `what`; running out of descriptors says so, with
+/// where to look, instead of the bare OS error
+pub fn open_error(what: &str, e: std::io::Error) -> ExecutionError {
+    if is_exhausted(&e) {
+        let limit = nofile_limits()
+            .map(|(soft, _)| soft.to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        return ExecutionError::ResourceError(format!(
+            "{what}: out of file descriptors (open file limit {limit}); raise FC_NOFILE_LIMIT \
+             or the service's LimitNOFILE, and check /admin/state for leaked VMs"
+        ));
+    }
+    ExecutionError::ResourceError(format!("{what}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headroom_never_goes_negative() {
+        assert_eq!(headroom(100, 1024), 924);
+        assert_eq!(headroom(1024, 1024), 0);
+        // Descriptors inherited past a lowered limit
+        assert_eq!(headroom(2000, 1024), 0);
+        assert_eq!(FdUsage::new(1000, 1024).headroom, 24);
+    }
+
+    #[test]
+    fn test_exhausted_descriptors_get_an_actionable_error() {
+        let e = open_error(
+            "cannot create stdout log",
+            std::io::Error::from_raw_os_error(libc::EMFILE),
+        );
+        let ExecutionError::ResourceError(message) = e else {
+            panic!("expected a ResourceError, got {e:?}");
+        };
+        assert!(message.starts_with("cannot create stdout log: out of file descriptors"));
+        assert!(message.contains("FC_NOFILE_LIMIT"));
+        assert!(is_exhausted(&std::io::Error::from_raw_os_error(
+            libc::ENFILE
+        )));
+
+        let e = open_error(
+            "cannot create stdout log",
+            std::io::Error::from(std::io::ErrorKind::PermissionDenied),
+        );
+        assert!(!e.to_string().contains("file descriptors"));
+    }
+
+    #[test]
+    fn test_usage_counts_this_process() {
+        let before = usage().unwrap();
+        assert!(before.open > 0 && before.open <= before.limit);
+        let (soft, _) = raise_nofile(0).unwrap();
+        assert_eq!(soft, before.limit, "raising to 0 must not lower the limit");
+        assert_eq!(VmFds::expected(true).total(), 5);
+        assert_eq!(VmFds::expected(false).total(), 4);
+    }
+}
@@ -0,0 +1,440 @@
+// `tonic::Status` is large, but it is the error type every tonic handler returns
+#![allow(clippy::result_large_err)]
+
+use crate::auth::{ApiKeyId, ApiKeys};
+use crate::debug_events::DebugEvent;
+use crate::dispatch::Priority;
+use crate::inputs::{RemoteInput, SetupError, SetupErrorCode};
+use crate::output::OutputEncoding;
+use crate::program::{ExecMode, SourceFile};
+use crate::rate_limit::RateLimiter;
+use crate::runner::{Live, LiveExecution, OutputEvent};
+use crate::service::{ExecutionService, ExecutionUpdates, Rejection};
+use crate::{ExceptionInfo, ExecuteRequest, ExecuteResponse, TracebackFrame, generate_request_id};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+/// Code generated from `proto/executor.proto`
+#[allow(clippy::large_enum_variant)]
+pub mod proto {
+    tonic::include_proto!("firecracker.executor.v1");
+}
+
+use proto::execute_event::Event;
+use proto::executor_server::{Executor, ExecutorServer};
+
+/// Metadata key carrying the caller's request ID, as the `X-Request-Id` header does over HTTP
+const REQUEST_ID_KEY: &str = "x-request-id";
+
+/// The `Executor` gRPC service, backed by the same `ExecutionService` as `/execute`
+#[derive(Clone)]
+pub struct GrpcExecutor {
+    pub service: ExecutionService,
+    pub api_keys: Arc<ApiKeys>,
+    pub rate_limiter: Arc<RateLimiter>,
+}
+
+impl GrpcExecutor {
+    /// Authenticate and rate-limit a call like the HTTP middleware does, returning its request
+    /// ID and the ID of its API key
+    fn admit<T>(&self, request: &Request<T>) -> Result<(String, Option<String>), Status> {
+        let key_id = self.authenticate(request)?;
+        self.enforce_rate_limit(request, key_id.as_ref())?;
+        let request_id = request
+            .metadata()
+            .get(REQUEST_ID_KEY)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(generate_request_id);
+        Ok((request_id, key_id.map(|ApiKeyId(id)| id)))
+    }
+
+    /// Admit a call, then run it
+    async fn run(
+        &self,
+        request: Request<proto::ExecuteRequest>,
+    ) -> Result<ExecuteResponse, Status> {
+        let (request_id, key_id) = self.admit(&request)?;
+        self.service
+            .execute(request.into_inner().into(), request_id, key_id.as_deref())
+            .await
+            .map_err(Status::from)
+    }
+
+    /// Check the `authorization: Bearer <key>` metadata when API keys are configured
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<Option<ApiKeyId>, Status> {
+        if !self.api_keys.is_enabled() {
+            return Ok(None);
+        }
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        let key_id = self
+            .api_keys
+            .verify(presented.trim())
+            .ok_or_else(|| Status::unauthenticated("Invalid API key"))?;
+        tracing::Span::current().record("api_key_id", key_id.0.as_str());
+        Ok(Some(key_id))
+    }
+
+    fn enforce_rate_limit<T>(
+        &self,
+        request: &Request<T>,
+        key_id: Option<&ApiKeyId>,
+    ) -> Result<(), Status> {
+        if !self.rate_limiter.is_enabled() {
+            return Ok(());
+        }
+        let client = match key_id {
+            Some(ApiKeyId(id)) => id.clone(),
+            None => match request.remote_addr() {
+                Some(addr) => format!("ip:{}", addr.ip()),
+                None => "ip:unknown".to_string(),
+            },
+        };
+        self.rate_limiter.check(&client).map_err(|retry_after| {
+            crate::telemetry::increment_counter("fc_rate_limited_total", &[], 1);
+            let retry_after_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            Status::resource_exhausted(format!(
+                "Rate limit exceeded, retry after {retry_after_secs} seconds"
+            ))
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Executor for GrpcExecutor {
+    async fn execute(
+        &self,
+        request: Request<proto::ExecuteRequest>,
+    ) -> Result<Response<proto::ExecuteResponse>, Status> {
+        let response = self.run(request).await?;
+        Ok(Response::new(response.into()))
+    }
+
+    type ExecuteStreamStream =
+        Pin<Box<dyn Stream<Item = Result<proto::ExecuteEvent, Status>> + Send>>;
+
+    /// Output chunks as the code prints them, then the result; a rejected or failed execution
+    /// ends the stream with its status instead
+    async fn execute_stream(
+        &self,
+        request: Request<proto::ExecuteRequest>,
+    ) -> Result<Response<Self::ExecuteStreamStream>, Status> {
+        let (request_id, key_id) = self.admit(&request)?;
+        let service = self.service.clone();
+        let payload = request.into_inner().into();
+        let mut execution = LiveExecution::start(|output| async move {
+            let updates = ExecutionUpdates {
+                queue: None,
+                output: Some(output),
+            };
+            service
+                .execute_streaming(payload, request_id, key_id.as_deref(), updates, None)
+                .await
+        });
+        let (events, stream) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move {
+            // A client that hung up gets no more events; the execution itself runs on
+            while let Some(next) = execution.next().await {
+                let event = match next {
+                    Live::Output(event) => Ok(event.into()),
+                    Live::Finished(Ok(Ok(response))) => Ok(OutputEvent::result(response).into()),
+                    Live::Finished(Ok(Err(rejection))) => Err(rejection.into()),
+                    Live::Finished(Err(e)) => {
+                        Err(Status::internal(format!("Execution failed: {e}")))
+                    }
+                };
+                if events.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(stream))))
+    }
+}
+
+impl From<OutputEvent> for proto::ExecuteEvent {
+    fn from(event: OutputEvent) -> Self {
+        let event = match event {
+            OutputEvent::Stdout { data } => Event::Stdout(data),
+            OutputEvent::Stderr { data } => Event::Stderr(data),
+            OutputEvent::Result(response) => Event::Result((*response).into()),
+        };
+        Self { event: Some(event) }
+    }
+}
+
+/// Serve the `Executor` service on `listener` until `shutdown` resolves, letting in-flight
+/// calls finish
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    executor: GrpcExecutor,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(ExecutorServer::new(executor))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await
+}
+
+impl From<Rejection> for Status {
+    fn from(rejection: Rejection) -> Self {
+        use axum::http::StatusCode;
+
+        let message = rejection.to_string();
+        match rejection.status() {
+            _ if matches!(rejection, Rejection::Screened(_)) => {
+                Status::failed_precondition(message)
+            }
+            StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+            StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(message),
+            StatusCode::GATEWAY_TIMEOUT => Status::deadline_exceeded(message),
+            StatusCode::NOT_FOUND | StatusCode::GONE => Status::not_found(message),
+            StatusCode::CONFLICT => Status::aborted(message),
+            status if status.is_client_error() => Status::invalid_argument(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+impl From<proto::ExecuteRequest> for ExecuteRequest {
+    fn from(request: proto::ExecuteRequest) -> Self {
+        // Repeated fields can't be absent, so no files means none were given
+        let files = (!request.files.is_empty()).then(|| {
+            request
+                .files
+                .into_iter()
+                .map(|file| SourceFile {
+                    path: file.path,
+                    content: file.content,
+                })
+                .collect()
+        });
+        Self {
+            code: request.code,
+            files,
+            entrypoint: request.entrypoint,
+            requirements: request.requirements,
+            inputs: (!request.inputs.is_empty()).then(|| {
+                request
+                    .inputs
+                    .into_iter()
+                    .map(|input| RemoteInput {
+                        url: input.url,
+                        path: input.path,
+                        sha256: input.sha256,
+                    })
+                    .collect()
+            }),
+            mode: request
+                .mode
+                .and_then(|mode| proto::ExecMode::try_from(mode).ok())
+                .map(mode),
+            deps_profile: request.deps_profile,
+            image: request.image,
+            dns: (!request.dns.is_empty()).then_some(request.dns),
+            // Shaping per request is only offered over HTTP
+            traffic_shape: None,
+            expose_port: request
+                .expose_port
+                .map(|port| u16::try_from(port).unwrap_or(0)),
+            deterministic: request.deterministic,
+            fake_time: request.fake_time,
+            cache: request.cache,
+            cache_bypass: request.cache_bypass,
+            max_output_bytes: request
+                .max_output_bytes
+                .map(|bytes| usize::try_from(bytes).unwrap_or(usize::MAX)),
+            affinity_key: request.affinity_key,
+            priority: request
+                .priority
+                .and_then(|priority| proto::Priority::try_from(priority).ok())
+                .map(priority),
+            debug_boot: request.debug_boot,
+            mount_reference_data: request.mount_reference_data,
+            capture_console: request.capture_console,
+            debug: request.debug,
+            // Sandboxes are only offered over HTTP, where their endpoints are
+            keep_alive_seconds: None,
+        }
+    }
+}
+
+impl From<ExecuteResponse> for proto::ExecuteResponse {
+    fn from(response: ExecuteResponse) -> Self {
+        Self {
+            stdout: response.stdout,
+            stderr: response.stderr,
+            success: response.success,
+            stdout_truncated: response.stdout_truncated,
+            stderr_truncated: response.stderr_truncated,
+            stdout_encoding: encoding(response.stdout_encoding).into(),
+            stderr_encoding: encoding(response.stderr_encoding).into(),
+            cached: response.cached,
+            oom_killed: response.oom_killed,
+            exception: response.exception.map(Into::into),
+            usage: response.usage.map(|usage| proto::ExecutionUsage {
+                cpu_time_ms: usage.cpu_time_ms,
+                max_rss_kb: usage.max_rss_kb,
+                wall_ms: usage.wall_ms,
+            }),
+            setup_stdout: response.setup_stdout,
+            setup_stderr: response.setup_stderr,
+            setup_error: response.setup_error.map(Into::into),
+            deterministic: response.deterministic,
+            hash_seed: response.hash_seed,
+            fake_time: response.fake_time,
+            exposed_port: response.exposed_port.map(u32::from),
+            exposed_until_ms: response.exposed_until_ms,
+            value_json: response.value.map(|value| value.to_string()),
+            value_repr: response.value_repr,
+            exec_id: response.exec_id,
+            console: response.console,
+            console_truncated: response.console_truncated,
+            events: response
+                .events
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+impl From<DebugEvent> for proto::DebugEvent {
+    fn from(event: DebugEvent) -> Self {
+        Self {
+            at_ms: event.at_ms,
+            event: event.event.as_str().to_string(),
+            vm_id: event.vm_id,
+            detail: event.detail,
+            duration_ms: event.duration_ms,
+            bytes: event.bytes,
+            count: event.count,
+        }
+    }
+}
+
+impl From<SetupError> for proto::SetupError {
+    fn from(error: SetupError) -> Self {
+        Self {
+            code: match error.code {
+                SetupErrorCode::DownloadFailed => "download_failed",
+                SetupErrorCode::TooLarge => "too_large",
+                SetupErrorCode::ChecksumMismatch => "checksum_mismatch",
+            }
+            .to_string(),
+            url: error.url,
+            path: error.path,
+            message: error.message,
+            expected_sha256: error.expected_sha256,
+            actual_sha256: error.actual_sha256,
+        }
+    }
+}
+
+impl From<ExceptionInfo> for proto::ExceptionInfo {
+    fn from(exception: ExceptionInfo) -> Self {
+        Self {
+            r#type: exception.exception_type,
+            message: exception.message,
+            traceback: exception
+                .traceback
+                .into_iter()
+                .map(|frame: TracebackFrame| proto::TracebackFrame {
+                    filename: frame.filename,
+                    line: frame.line,
+                    function: frame.function,
+                })
+                .collect(),
+            cause: exception.cause.map(|cause| Box::new((*cause).into())),
+        }
+    }
+}
+
+fn priority(priority: proto::Priority) -> Priority {
+    match priority {
+        proto::Priority::Normal => Priority::Normal,
+        proto::Priority::High => Priority::High,
+        proto::Priority::Low => Priority::Low,
+    }
+}
+
+fn mode(mode: proto::ExecMode) -> ExecMode {
+    match mode {
+        proto::ExecMode::Script => ExecMode::Script,
+        proto::ExecMode::Eval => ExecMode::Eval,
+    }
+}
+
+fn encoding(encoding: OutputEncoding) -> proto::OutputEncoding {
+    match encoding {
+        OutputEncoding::Utf8 => proto::OutputEncoding::Utf8,
+        OutputEncoding::Base64 => proto::OutputEncoding::Base64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExecutionError;
+    use crate::validation::ValidationError;
+
+    #[test]
+    fn test_rejection_status_codes() {
+        let code = |rejection| Status::from(rejection).code();
+        assert_eq!(
+            code(Rejection::BadRequest("x".into())),
+            tonic::Code::InvalidArgument
+        );
+        assert_eq!(
+            code(Rejection::Invalid(ValidationError::EmptyCode)),
+            tonic::Code::InvalidArgument
+        );
+        assert_eq!(
+            code(Rejection::Screened("x".into())),
+            tonic::Code::FailedPrecondition
+        );
+        assert_eq!(
+            code(Rejection::Execution(ExecutionError::ShuttingDown)),
+            tonic::Code::Unavailable
+        );
+        assert_eq!(
+            code(Rejection::Execution(ExecutionError::DeadlineExceeded {
+                phase: "boot".into(),
+                budget_ms: 100,
+            })),
+            tonic::Code::DeadlineExceeded
+        );
+        assert_eq!(
+            code(Rejection::Execution(ExecutionError::SandboxBusy(
+                "s".into()
+            ))),
+            tonic::Code::Aborted
+        );
+        assert_eq!(
+            code(Rejection::Execution(ExecutionError::ApiCommunicationError(
+                "x".into()
+            ))),
+            tonic::Code::Internal
+        );
+        assert_eq!(
+            code(Rejection::QuotaExceeded(crate::quota::QuotaExceeded {
+                tenant: "x".into(),
+                limit: crate::quota::QuotaKind::Concurrency,
+                allowed: 1,
+                window_secs: None,
+                resets_at: None,
+            })),
+            tonic::Code::ResourceExhausted
+        );
+    }
+}
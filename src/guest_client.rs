@@ -0,0 +1,535 @@
+use crate::config::ConfigError;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time::Instant;
+
+/// Port the guest agent listens on, over TCP or vsock
+pub const AGENT_PORT: u16 = 8080;
+
+/// Context ID of the guest on its vsock device; every VM has a device of its own
+pub const GUEST_CID: u32 = 3;
+
+/// Longest line Firecracker answers a vsock `CONNECT` with
+const MAX_HANDSHAKE_LINE: usize = 64;
+
+/// How VMs are reached, selected with `FC_GUEST_TRANSPORT`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GuestTransportKind {
+    /// HTTP over the VM's TAP device, which also gives the guest its network
+    #[default]
+    Tcp,
+    /// HTTP over a vsock device; the guest gets no network interface at all
+    Vsock,
+}
+
+impl GuestTransportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GuestTransportKind::Tcp => "tcp",
+            GuestTransportKind::Vsock => "vsock",
+        }
+    }
+}
+
+impl std::str::FromStr for GuestTransportKind {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "tcp" => Ok(GuestTransportKind::Tcp),
+            "vsock" => Ok(GuestTransportKind::Vsock),
+            other => Err(ConfigError::Invalid(format!(
+                "unknown guest transport {other:?} (expected tcp or vsock)"
+            ))),
+        }
+    }
+}
+
+/// Transport of VMs booted from the default rootfs and from each image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GuestTransportConfig {
+    /// Transport of VMs booted from the default rootfs, and of images without their own
+    pub default: GuestTransportKind,
+    /// Transports of VMs booted from a named image
+    pub images: HashMap<String, GuestTransportKind>,
+}
+
+impl GuestTransportConfig {
+    /// Transport of a VM booted from `image`
+    pub fn kind_for(&self, image: Option<&str>) -> GuestTransportKind {
+        image
+            .and_then(|image| self.images.get(image))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Parse `FC_GUEST_TRANSPORT_IMAGES`, e.g. `offline=vsock,legacy=tcp`
+    pub fn parse_images(raw: &str) -> Result<HashMap<String, GuestTransportKind>, ConfigError> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (image, kind) = entry.split_once('=').ok_or_else(|| {
+                    ConfigError::Invalid(format!("expected IMAGE=TRANSPORT, got {entry:?}"))
+                })?;
+                Ok((image.trim().to_string(), kind.parse()?))
+            })
+            .collect()
+    }
+}
+
+/// How the host reaches the guest agent of one VM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuestTransport {
+    /// The agent's TCP port at the VM's address, routed over its TAP device
+    TcpOverTap { ip: String, port: u16 },
+    /// The agent's vsock port, reached through the Unix socket Firecracker serves the VM's
+    /// vsock device on
+    Vsock { uds_path: PathBuf, port: u32 },
+}
+
+impl GuestTransport {
+    /// Transport of `kind` to the agent of a VM at `ip` whose vsock device is served on
+    /// `uds_path`
+    pub fn new(kind: GuestTransportKind, ip: &str, uds_path: &str) -> Self {
+        match kind {
+            GuestTransportKind::Tcp => GuestTransport::TcpOverTap {
+                ip: ip.to_string(),
+                port: AGENT_PORT,
+            },
+            GuestTransportKind::Vsock => GuestTransport::Vsock {
+                uds_path: PathBuf::from(uds_path),
+                port: AGENT_PORT.into(),
+            },
+        }
+    }
+
+    pub fn kind(&self) -> GuestTransportKind {
+        match self {
+            GuestTransport::TcpOverTap { .. } => GuestTransportKind::Tcp,
+            GuestTransport::Vsock { .. } => GuestTransportKind::Vsock,
+        }
+    }
+
+    /// Whether the VM needs a TAP device, and gets a network with it
+    pub fn uses_tap(&self) -> bool {
+        self.kind() == GuestTransportKind::Tcp
+    }
+}
+
+impl std::fmt::Display for GuestTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GuestTransport::TcpOverTap { ip, port } => write!(f, "{ip}:{port}"),
+            GuestTransport::Vsock { uds_path, port } => {
+                write!(f, "vsock port {port} via {}", uds_path.display())
+            }
+        }
+    }
+}
+
+/// Failure to exchange a request with the guest agent
+#[derive(Debug, thiserror::Error)]
+pub enum GuestError {
+    /// The agent couldn't be reached
+    #[error("cannot connect to {target}: {reason}")]
+    Connect { target: String, reason: String },
+    /// No full response came within the request's timeout
+    #[error("no response within {}ms", .0.as_millis())]
+    Timeout(Duration),
+    /// The connection broke, or the response was malformed
+    #[error("{0}")]
+    Http(String),
+}
+
+/// The connection a request is sent over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP connection to `host:port`
+    Tcp(String),
+    /// A connection to `port` over the vsock device served on `uds_path`
+    Vsock { uds_path: PathBuf, port: u32 },
+}
+
+/// Where a request to the guest agent goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestTarget {
+    pub endpoint: Endpoint,
+    /// `Host` header sent with the request
+    pub host: String,
+    /// Path and query the request is sent for
+    pub path: String,
+}
+
+impl RequestTarget {
+    async fn send(&self, request: Request<Full<Bytes>>) -> Result<Response, GuestError> {
+        let connect_error = |reason: String| GuestError::Connect {
+            target: match &self.endpoint {
+                Endpoint::Tcp(address) => address.clone(),
+                Endpoint::Vsock { uds_path, port } => {
+                    format!("vsock port {port} via {}", uds_path.display())
+                }
+            },
+            reason,
+        };
+        match &self.endpoint {
+            Endpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address)
+                    .await
+                    .map_err(|e| connect_error(e.to_string()))?;
+                exchange(stream, request).await
+            }
+            Endpoint::Vsock { uds_path, port } => {
+                let stream = connect_vsock(uds_path, *port)
+                    .await
+                    .map_err(connect_error)?;
+                exchange(stream, request).await
+            }
+        }
+    }
+}
+
+type Response = hyper::Response<Incoming>;
+
+/// Open a connection to the guest's vsock `port` through the Unix socket Firecracker serves
+/// the device on
+async fn connect_vsock(uds_path: &Path, port: u32) -> Result<UnixStream, String> {
+    let mut stream = UnixStream::connect(uds_path)
+        .await
+        .map_err(|e| e.to_string())?;
+    stream
+        .write_all(format!("CONNECT {port}\n").as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    // Firecracker answers `OK <host port>` once the guest accepts, and hangs up when nothing
+    // listens on the port. Read a byte at a time, so nothing of the guest's is consumed.
+    let mut line = Vec::new();
+    loop {
+        let byte = stream
+            .read_u8()
+            .await
+            .map_err(|_| format!("nothing accepted the connection to port {port}"))?;
+        if byte == b'\n' {
+            break;
+        }
+        line.push(byte);
+        if line.len() > MAX_HANDSHAKE_LINE {
+            return Err("malformed vsock handshake".to_string());
+        }
+    }
+    if !line.starts_with(b"OK ") {
+        return Err(format!(
+            "vsock handshake refused: {}",
+            String::from_utf8_lossy(&line)
+        ));
+    }
+    Ok(stream)
+}
+
+/// Send `request` over `stream` as the only request of an HTTP/1.1 connection
+async fn exchange<S>(stream: S, request: Request<Full<Bytes>>) -> Result<Response, GuestError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| GuestError::Http(e.to_string()))?;
+    // Driven until the response body is read, and dropped with it
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::debug!("Guest agent connection ended: {}", e);
+        }
+    });
+    sender
+        .send_request(request)
+        .await
+        .map_err(|e| GuestError::Http(e.to_string()))
+}
+
+/// Client of one VM's guest agent, over the VM's transport
+#[derive(Debug, Clone)]
+pub struct GuestClient {
+    transport: GuestTransport,
+    /// Base URL to use instead of the transport, as when the agent is served by the replay
+    /// server
+    base_url: Option<String>,
+}
+
+impl GuestClient {
+    pub fn new(transport: GuestTransport) -> Self {
+        Self {
+            transport,
+            base_url: None,
+        }
+    }
+
+    /// Send requests to `base_url` over TCP, whatever the transport, when one is given
+    pub fn with_base_url(mut self, base_url: Option<String>) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn transport(&self) -> &GuestTransport {
+        &self.transport
+    }
+
+    /// Where a request for `path` goes
+    pub fn target(&self, path: &str) -> RequestTarget {
+        if let Some(uri) = self
+            .base_url
+            .as_deref()
+            .and_then(|url| url.parse::<Uri>().ok())
+        {
+            let host = uri
+                .authority()
+                .map(|authority| authority.to_string())
+                .unwrap_or_default();
+            return RequestTarget {
+                endpoint: Endpoint::Tcp(host.clone()),
+                host,
+                path: format!("{}{path}", uri.path().trim_end_matches('/')),
+            };
+        }
+        match &self.transport {
+            GuestTransport::TcpOverTap { ip, port } => RequestTarget {
+                endpoint: Endpoint::Tcp(format!("{ip}:{port}")),
+                host: format!("{ip}:{port}"),
+                path: path.to_string(),
+            },
+            GuestTransport::Vsock { uds_path, port } => RequestTarget {
+                endpoint: Endpoint::Vsock {
+                    uds_path: uds_path.clone(),
+                    port: *port,
+                },
+                host: "localhost".to_string(),
+                path: path.to_string(),
+            },
+        }
+    }
+
+    /// `GET path`, with `timeout` to receive the whole response
+    pub async fn get(&self, path: &str, timeout: Duration) -> Result<GuestResponse, GuestError> {
+        self.request(Method::GET, path, None, timeout).await
+    }
+
+    /// `POST path` with `body` as JSON, with `timeout` to receive the whole response
+    pub async fn post_json(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+        timeout: Duration,
+    ) -> Result<GuestResponse, GuestError> {
+        let body = serde_json::to_vec(body).map_err(|e| GuestError::Http(e.to_string()))?;
+        self.request(Method::POST, path, Some(body), timeout).await
+    }
+
+    /// Send `method path` with a JSON `body`, if any, with `timeout` to receive the whole
+    /// response
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+        timeout: Duration,
+    ) -> Result<GuestResponse, GuestError> {
+        let target = self.target(path);
+        let mut request = Request::builder()
+            .method(method)
+            .uri(&target.path)
+            .header(hyper::header::HOST, &target.host);
+        if body.is_some() {
+            request = request.header(hyper::header::CONTENT_TYPE, "application/json");
+        }
+        let request = request
+            .body(Full::new(body.map(Bytes::from).unwrap_or_default()))
+            .map_err(|e| GuestError::Http(e.to_string()))?;
+        let deadline = Instant::now() + timeout;
+        let response = tokio::time::timeout_at(deadline, target.send(request))
+            .await
+            .map_err(|_| GuestError::Timeout(timeout))??;
+        Ok(GuestResponse {
+            response,
+            deadline,
+            timeout,
+        })
+    }
+}
+
+/// Response of the guest agent, whose body is read within what is left of the request's
+/// timeout
+#[derive(Debug)]
+pub struct GuestResponse {
+    response: Response,
+    deadline: Instant,
+    timeout: Duration,
+}
+
+impl GuestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    /// Next chunk of the body, or `None` once it is all read
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, GuestError> {
+        loop {
+            let frame = tokio::time::timeout_at(self.deadline, self.response.body_mut().frame())
+                .await
+                .map_err(|_| GuestError::Timeout(self.timeout))?;
+            match frame {
+                None => return Ok(None),
+                Some(Err(e)) => return Err(GuestError::Http(e.to_string())),
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data() {
+                        return Ok(Some(data));
+                    }
+                }
+            }
+        }
+    }
+
+    /// The body, decoded from JSON
+    pub async fn json<T: DeserializeOwned>(mut self) -> Result<T, GuestError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        serde_json::from_slice(&body).map_err(|e| GuestError::Http(format!("invalid JSON: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::UnixListener;
+
+    fn vsock(uds_path: &Path) -> GuestClient {
+        GuestClient::new(GuestTransport::new(
+            GuestTransportKind::Vsock,
+            "172.16.7.2",
+            uds_path.to_str().unwrap(),
+        ))
+    }
+
+    #[test]
+    fn test_request_targets_follow_the_transport() {
+        let tcp = GuestClient::new(GuestTransport::new(
+            GuestTransportKind::Tcp,
+            "172.16.7.2",
+            "/tmp/fc-vsock-a.sock",
+        ));
+        assert_eq!(
+            tcp.target("/health"),
+            RequestTarget {
+                endpoint: Endpoint::Tcp("172.16.7.2:8080".to_string()),
+                host: "172.16.7.2:8080".to_string(),
+                path: "/health".to_string(),
+            }
+        );
+        assert!(tcp.transport().uses_tap());
+
+        let vsock = vsock(Path::new("/tmp/fc-vsock-a.sock"));
+        assert_eq!(
+            vsock.target("/execute"),
+            RequestTarget {
+                endpoint: Endpoint::Vsock {
+                    uds_path: PathBuf::from("/tmp/fc-vsock-a.sock"),
+                    port: 8080,
+                },
+                host: "localhost".to_string(),
+                path: "/execute".to_string(),
+            }
+        );
+        assert!(!vsock.transport().uses_tap());
+
+        // A replay server stands in for the agent over TCP, under its path
+        let replayed = vsock.with_base_url(Some("http://127.0.0.1:4000/agent".to_string()));
+        let target = replayed.target("/health");
+        assert_eq!(target.endpoint, Endpoint::Tcp("127.0.0.1:4000".to_string()));
+        assert_eq!(target.path, "/agent/health");
+    }
+
+    #[test]
+    fn test_transport_config() {
+        let config = GuestTransportConfig {
+            default: "tcp".parse().unwrap(),
+            images: GuestTransportConfig::parse_images(" offline=vsock, legacy=tcp ,").unwrap(),
+        };
+        assert_eq!(config.kind_for(None), GuestTransportKind::Tcp);
+        assert_eq!(config.kind_for(Some("offline")), GuestTransportKind::Vsock);
+        assert_eq!(config.kind_for(Some("other")), GuestTransportKind::Tcp);
+        assert!(GuestTransportConfig::parse_images("offline").is_err());
+        assert!(GuestTransportConfig::parse_images("offline=serial").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_vsock_requests_connect_to_the_agent_port() {
+        let uds_path =
+            std::env::temp_dir().join(format!("fc-vsock-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&uds_path);
+        let listener = UnixListener::bind(&uds_path).unwrap();
+        // Stands in for Firecracker and an agent listening on port 8080
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                if line != "CONNECT 8080\n" {
+                    continue;
+                }
+                line.clear();
+                stream
+                    .get_mut()
+                    .write_all(b"OK 1073741824\n")
+                    .await
+                    .unwrap();
+                let mut request = String::new();
+                while stream.read_line(&mut line).await.unwrap() > 0 {
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                    line.clear();
+                }
+                let body = format!(r#"{{"request": {:?}}}"#, request.lines().next().unwrap());
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{body}",
+                    body.len()
+                );
+                stream
+                    .get_mut()
+                    .write_all(response.as_bytes())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let client = vsock(&uds_path);
+        let response = client.get("/health", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["request"], "GET /health HTTP/1.1");
+
+        // Nothing listens on another port, so Firecracker hangs up
+        let other = GuestClient::new(GuestTransport::Vsock {
+            uds_path: uds_path.clone(),
+            port: 52,
+        });
+        let err = other
+            .get("/health", Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, GuestError::Connect { .. }), "{err}");
+        let _ = std::fs::remove_file(&uds_path);
+    }
+}
@@ -0,0 +1,153 @@
+use std::net::IpAddr;
+
+/// Resolvers guests use when the host's `resolv.conf` names none they can reach
+pub const PUBLIC_RESOLVERS: [&str; 2] = ["1.1.1.1", "8.8.8.8"];
+
+/// Most name servers a guest's `resolv.conf` takes, as glibc reads no more
+pub const MAX_DNS_SERVERS: usize = 3;
+
+/// DNS and proxy settings handed to guests that may reach the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestNetworkConfig {
+    /// Whether guests may reach the network; without it they get no settings at all
+    pub enabled: bool,
+    /// Name servers written to the guest's `resolv.conf`
+    pub dns: Vec<String>,
+    /// Exported to user code as `HTTP_PROXY` / `http_proxy`
+    pub http_proxy: Option<String>,
+    /// Exported to user code as `HTTPS_PROXY` / `https_proxy`
+    pub https_proxy: Option<String>,
+    /// Exported to user code as `NO_PROXY` / `no_proxy`
+    pub no_proxy: Option<String>,
+}
+
+impl Default for GuestNetworkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dns: PUBLIC_RESOLVERS.map(str::to_string).to_vec(),
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+        }
+    }
+}
+
+impl GuestNetworkConfig {
+    /// Kernel boot arguments the guest agent reads at startup; `dns` replaces the configured
+    /// name servers for one VM
+    pub fn boot_args(&self, dns: Option<&[String]>) -> Vec<String> {
+        if !self.enabled {
+            return Vec::new();
+        }
+        let dns = dns.unwrap_or(&self.dns);
+        let mut args = Vec::new();
+        if !dns.is_empty() {
+            args.push(format!("fc_dns={}", dns.join(",")));
+        }
+        for (name, value) in [
+            ("fc_http_proxy", &self.http_proxy),
+            ("fc_https_proxy", &self.https_proxy),
+            ("fc_no_proxy", &self.no_proxy),
+        ] {
+            if let Some(value) = value {
+                args.push(format!("{name}={value}"));
+            }
+        }
+        args
+    }
+}
+
+/// Name servers of a `resolv.conf` that a guest can reach. Loopback ones, like
+/// systemd-resolved's `127.0.0.53`, only answer on the host.
+pub fn resolvers_from_resolv_conf(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next() == Some("nameserver")).then(|| words.next())?
+        })
+        .filter_map(|server| server.parse::<IpAddr>().ok())
+        .filter(|ip| !ip.is_loopback())
+        .map(|ip| ip.to_string())
+        .take(MAX_DNS_SERVERS)
+        .collect()
+}
+
+/// Check name servers given for one request: one to `MAX_DNS_SERVERS` IP addresses
+pub fn validate_dns(servers: &[String]) -> Result<(), String> {
+    if servers.is_empty() || servers.len() > MAX_DNS_SERVERS {
+        return Err(format!(
+            "dns must list 1 to {MAX_DNS_SERVERS} name servers ({} given)",
+            servers.len()
+        ));
+    }
+    match servers.iter().find(|s| s.parse::<IpAddr>().is_err()) {
+        Some(server) => Err(format!("dns server '{server}' is not an IP address")),
+        None => Ok(()),
+    }
+}
+
+/// Whether `value` can be passed on the kernel command line, which splits at whitespace
+pub fn is_boot_arg_safe(value: &str) -> bool {
+    !value.is_empty() && !value.chars().any(|c| c.is_whitespace() || c == '"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled() -> GuestNetworkConfig {
+        GuestNetworkConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_boot_args() {
+        assert_eq!(enabled().boot_args(None), vec!["fc_dns=1.1.1.1,8.8.8.8"]);
+
+        let config = GuestNetworkConfig {
+            http_proxy: Some("http://10.0.0.1:3128".to_string()),
+            no_proxy: Some("localhost,.internal".to_string()),
+            ..enabled()
+        };
+        let dns = ["10.1.0.2".to_string()];
+        assert_eq!(
+            config.boot_args(Some(&dns)),
+            vec![
+                "fc_dns=10.1.0.2",
+                "fc_http_proxy=http://10.0.0.1:3128",
+                "fc_no_proxy=localhost,.internal",
+            ]
+        );
+
+        // Guests that can't reach the network get nothing to act on
+        let disabled = GuestNetworkConfig {
+            enabled: false,
+            ..config
+        };
+        assert!(disabled.boot_args(None).is_empty());
+        assert!(disabled.boot_args(Some(&dns)).is_empty());
+    }
+
+    #[test]
+    fn test_resolvers_from_resolv_conf() {
+        let conf = "# generated\nnameserver 127.0.0.53\nnameserver 192.168.1.1\n\
+                    search lan\nnameserver 2001:4860:4860::8888\nnameserver bogus\n";
+        assert_eq!(
+            resolvers_from_resolv_conf(conf),
+            vec!["192.168.1.1", "2001:4860:4860::8888"]
+        );
+        assert!(resolvers_from_resolv_conf("nameserver 127.0.0.53\n").is_empty());
+    }
+
+    #[test]
+    fn test_validate_dns() {
+        assert!(validate_dns(&["10.0.0.2".to_string(), "::1".to_string()]).is_ok());
+        assert!(validate_dns(&[]).is_err());
+        assert!(validate_dns(&["dns.example".to_string()]).is_err());
+        assert!(validate_dns(&vec!["1.1.1.1".to_string(); 4]).is_err());
+    }
+}
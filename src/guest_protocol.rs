@@ -0,0 +1,238 @@
+use crate::ExecutionError;
+use crate::inputs::RemoteInput;
+use crate::program::Program;
+
+/// Protocol version of the guest agent this host speaks, sent by agents in `/health`
+pub const HOST_PROTOCOL_VERSION: u32 = 4;
+
+/// Version assumed of agents whose `/health` reports none, which predate the handshake
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+
+/// Something the host may ask of the guest agent, in an execution payload or a call of its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuestFeature {
+    /// `files` and `entrypoint`
+    Files,
+    /// `requirements`
+    Requirements,
+    /// `max_output_bytes`
+    OutputLimit,
+    /// `mode: "eval"`
+    Eval,
+    /// `inputs` and `max_input_bytes`
+    Inputs,
+    /// `exec_id`, naming the execution's workspace, and `leftover_workspaces` in `/health`
+    Workspace,
+    /// `POST /set-time`, setting the guest clock
+    SetTime,
+    /// `stream: true`, answered with output lines as the code prints them before the result
+    LiveOutput,
+}
+
+impl GuestFeature {
+    pub const ALL: [GuestFeature; 8] = [
+        GuestFeature::Files,
+        GuestFeature::Requirements,
+        GuestFeature::OutputLimit,
+        GuestFeature::Eval,
+        GuestFeature::Inputs,
+        GuestFeature::Workspace,
+        GuestFeature::SetTime,
+        GuestFeature::LiveOutput,
+    ];
+
+    /// How errors name the feature
+    pub fn name(self) -> &'static str {
+        match self {
+            GuestFeature::Files => "multi-file programs",
+            GuestFeature::Requirements => "requirements",
+            GuestFeature::OutputLimit => "output limits",
+            GuestFeature::Eval => "eval mode",
+            GuestFeature::Inputs => "remote inputs",
+            GuestFeature::Workspace => "per-execution workspaces",
+            GuestFeature::SetTime => "guest clock sync",
+            GuestFeature::LiveOutput => "live output",
+        }
+    }
+
+    /// First protocol version whose agents support the feature
+    pub fn since(self) -> u32 {
+        match self {
+            GuestFeature::Files | GuestFeature::Requirements => 1,
+            GuestFeature::OutputLimit
+            | GuestFeature::Eval
+            | GuestFeature::Inputs
+            | GuestFeature::SetTime => 2,
+            GuestFeature::Workspace => 3,
+            GuestFeature::LiveOutput => 4,
+        }
+    }
+
+    /// Whether the host can do without the feature on older agents: output is truncated on
+    /// the host as well, older agents run everything in the agent's own directory, their
+    /// clocks are left as they are, and their output is sent once the code exits
+    pub fn optional(self) -> bool {
+        matches!(
+            self,
+            GuestFeature::OutputLimit
+                | GuestFeature::Workspace
+                | GuestFeature::SetTime
+                | GuestFeature::LiveOutput
+        )
+    }
+}
+
+/// Protocol spoken with an agent that reported `reported` in `/health`: the older of its
+/// version and the host's
+pub fn negotiate(reported: Option<u32>) -> u32 {
+    reported
+        .unwrap_or(LEGACY_PROTOCOL_VERSION)
+        .min(HOST_PROTOCOL_VERSION)
+}
+
+/// Whether agents speaking protocol `version` understand `feature`
+pub fn supports(version: u32, feature: GuestFeature) -> bool {
+    version >= feature.since()
+}
+
+/// Features an execution of `program` with `requirements` and `inputs` asks of the agent
+pub fn features_of(
+    program: &Program,
+    requirements: &[String],
+    inputs: &[RemoteInput],
+) -> Vec<GuestFeature> {
+    let mut features = vec![GuestFeature::OutputLimit, GuestFeature::Workspace];
+    match program {
+        Program::Code(_) => {}
+        Program::Eval(_) => features.push(GuestFeature::Eval),
+        Program::Files { .. } => features.push(GuestFeature::Files),
+    }
+    if !requirements.is_empty() {
+        features.push(GuestFeature::Requirements);
+    }
+    if !inputs.is_empty() {
+        features.push(GuestFeature::Inputs);
+    }
+    features
+}
+
+/// Fail with `GuestProtocolError` if an agent speaking `version` lacks any feature of
+/// `features` the execution can't do without
+pub fn check(
+    version: u32,
+    features: impl IntoIterator<Item = GuestFeature>,
+) -> Result<(), ExecutionError> {
+    match features
+        .into_iter()
+        .find(|feature| !feature.optional() && !supports(version, *feature))
+    {
+        Some(feature) => Err(ExecutionError::GuestProtocolError {
+            feature: feature.name().to_string(),
+            required: feature.since(),
+            agent: version,
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate(None), LEGACY_PROTOCOL_VERSION);
+        assert_eq!(negotiate(Some(1)), 1);
+        assert_eq!(
+            negotiate(Some(HOST_PROTOCOL_VERSION)),
+            HOST_PROTOCOL_VERSION
+        );
+        // A newer agent is spoken to in the host's version
+        assert_eq!(
+            negotiate(Some(HOST_PROTOCOL_VERSION + 3)),
+            HOST_PROTOCOL_VERSION
+        );
+    }
+
+    #[test]
+    fn test_support_matrix() {
+        let matrix = [
+            (GuestFeature::Files, [false, true, true, true, true]),
+            (GuestFeature::Requirements, [false, true, true, true, true]),
+            (GuestFeature::OutputLimit, [false, false, true, true, true]),
+            (GuestFeature::Eval, [false, false, true, true, true]),
+            (GuestFeature::Inputs, [false, false, true, true, true]),
+            (GuestFeature::Workspace, [false, false, false, true, true]),
+            (GuestFeature::SetTime, [false, false, true, true, true]),
+            (GuestFeature::LiveOutput, [false, false, false, false, true]),
+        ];
+        assert_eq!(matrix.len(), GuestFeature::ALL.len());
+        for (feature, expected) in matrix {
+            for (version, supported) in expected.into_iter().enumerate() {
+                assert_eq!(
+                    supports(version as u32, feature),
+                    supported,
+                    "{feature:?} on v{version}"
+                );
+            }
+        }
+        // The host speaks every feature it knows of
+        assert!(
+            GuestFeature::ALL
+                .iter()
+                .all(|feature| feature.since() <= HOST_PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            GuestFeature::ALL
+                .iter()
+                .filter(|feature| feature.optional())
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_features_of() {
+        let input = RemoteInput {
+            url: "https://example.com/data.csv".to_string(),
+            path: "data.csv".to_string(),
+            sha256: None,
+        };
+        assert_eq!(
+            features_of(&Program::Code("1".to_string()), &[], &[]),
+            [GuestFeature::OutputLimit, GuestFeature::Workspace]
+        );
+        assert_eq!(
+            features_of(
+                &Program::Eval("1".to_string()),
+                &["numpy".to_string()],
+                &[input]
+            ),
+            [
+                GuestFeature::OutputLimit,
+                GuestFeature::Workspace,
+                GuestFeature::Eval,
+                GuestFeature::Requirements,
+                GuestFeature::Inputs
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_fails_only_on_required_features() {
+        let code = features_of(&Program::Code("1".to_string()), &[], &[]);
+        // A legacy agent gets no output limit, which the host enforces anyway, and no workspace
+        assert!(check(LEGACY_PROTOCOL_VERSION, code.clone()).is_ok());
+        assert!(check(HOST_PROTOCOL_VERSION, code).is_ok());
+
+        let eval = features_of(&Program::Eval("1".to_string()), &[], &[]);
+        let err = check(LEGACY_PROTOCOL_VERSION, eval.clone()).unwrap_err();
+        assert_eq!(err.code(), "guest_protocol_error");
+        assert_eq!(
+            err.to_string(),
+            "The VM's guest agent speaks protocol v1, but eval mode needs v2; rebuild the rootfs with a newer agent"
+        );
+        assert!(check(HOST_PROTOCOL_VERSION, eval).is_ok());
+        assert!(check(0, [GuestFeature::Requirements]).is_err());
+    }
+}
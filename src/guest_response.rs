@@ -0,0 +1,593 @@
+use serde_json::{Map, Value};
+
+/// Most bytes kept of any top-level field of a guest response other than its output
+/// streams, e.g. an eval `value`; larger fields are dropped
+pub const MAX_GUEST_FIELD_BYTES: usize = 8 * 1024 * 1024;
+
+/// Top-level string fields holding captured output, kept up to the stream budget
+const OUTPUT_FIELDS: [&str; 4] = ["stdout", "stderr", "setup_stdout", "setup_stderr"];
+
+/// Characters kept of a stream capped at `max_output_bytes`: base64 carries three bytes in
+/// four characters, and the exact cap is applied once the encoding is known
+pub fn stream_budget(max_output_bytes: usize) -> usize {
+    max_output_bytes.div_ceil(3) * 4
+}
+
+/// Most bytes read of a response to an execution capped at `max_output_bytes` before the
+/// transfer is aborted: every output stream escaped at six bytes a byte, plus the rest
+pub fn body_limit(max_output_bytes: usize) -> u64 {
+    let streams = OUTPUT_FIELDS.len() * 6 * stream_budget(max_output_bytes);
+    (streams + 2 * MAX_GUEST_FIELD_BYTES) as u64
+}
+
+/// Why a guest response couldn't be decoded
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("unexpected byte {0:?} at offset {1}")]
+    Unexpected(char, u64),
+    #[error("response ended before its object did")]
+    Incomplete,
+    #[error("response exceeded {0} bytes")]
+    TooLarge(u64),
+}
+
+enum Escape {
+    None,
+    Backslash,
+    Unicode { value: u32, digits: u8 },
+}
+
+/// A string field, unescaped as it arrives and kept up to a budget
+struct TextField {
+    bytes: Vec<u8>,
+    budget: usize,
+    truncated: bool,
+    escape: Escape,
+    high_surrogate: Option<u32>,
+}
+
+impl TextField {
+    fn new(budget: usize) -> Self {
+        Self {
+            bytes: Vec::new(),
+            budget,
+            truncated: false,
+            escape: Escape::None,
+            high_surrogate: None,
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        let room = self.budget.saturating_sub(self.bytes.len());
+        if bytes.len() > room {
+            self.truncated = true;
+        }
+        self.bytes
+            .extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+
+    fn push_char(&mut self, c: char) {
+        self.push_bytes(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+
+    fn push_code_unit(&mut self, unit: u32) {
+        match (self.high_surrogate.take(), unit) {
+            (None, 0xD800..=0xDBFF) => self.high_surrogate = Some(unit),
+            (Some(high), 0xDC00..=0xDFFF) => {
+                let c = 0x10000 + ((high - 0xD800) << 10) + (unit - 0xDC00);
+                self.push_char(char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            (high, unit) => {
+                if high.is_some() {
+                    self.push_char(char::REPLACEMENT_CHARACTER);
+                }
+                self.push_char(char::from_u32(unit).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+        }
+    }
+
+    /// Take one byte of the string's body; returns whether it closed the string
+    fn feed(&mut self, byte: u8, offset: u64) -> Result<bool, DecodeError> {
+        match self.escape {
+            Escape::Backslash => {
+                self.escape = Escape::None;
+                let c = match byte {
+                    b'"' => '"',
+                    b'\\' => '\\',
+                    b'/' => '/',
+                    b'b' => '\u{8}',
+                    b'f' => '\u{c}',
+                    b'n' => '\n',
+                    b'r' => '\r',
+                    b't' => '\t',
+                    b'u' => {
+                        self.escape = Escape::Unicode {
+                            value: 0,
+                            digits: 0,
+                        };
+                        return Ok(false);
+                    }
+                    _ => return Err(DecodeError::Unexpected(byte as char, offset)),
+                };
+                self.push_code_unit(c as u32);
+            }
+            Escape::Unicode { value, digits } => {
+                let digit = (byte as char)
+                    .to_digit(16)
+                    .ok_or(DecodeError::Unexpected(byte as char, offset))?;
+                let value = value << 4 | digit;
+                if digits == 3 {
+                    self.escape = Escape::None;
+                    self.push_code_unit(value);
+                } else {
+                    self.escape = Escape::Unicode {
+                        value,
+                        digits: digits + 1,
+                    };
+                }
+            }
+            Escape::None => match byte {
+                b'"' => {
+                    if self.high_surrogate.take().is_some() {
+                        self.push_char(char::REPLACEMENT_CHARACTER);
+                    }
+                    return Ok(true);
+                }
+                b'\\' => self.escape = Escape::Backslash,
+                _ => {
+                    if self.high_surrogate.take().is_some() {
+                        self.push_char(char::REPLACEMENT_CHARACTER);
+                    }
+                    self.push_bytes(&[byte]);
+                }
+            },
+        }
+        Ok(false)
+    }
+
+    /// The kept text, less any character the budget cut in half
+    fn into_string(self) -> String {
+        let valid = match std::str::from_utf8(&self.bytes) {
+            Ok(_) => self.bytes.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        let mut bytes = self.bytes;
+        bytes.truncate(valid);
+        String::from_utf8(bytes).unwrap_or_default()
+    }
+}
+
+/// Any other field, kept as raw JSON up to `MAX_GUEST_FIELD_BYTES`
+#[derive(Default)]
+struct RawField {
+    bytes: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    oversized: bool,
+}
+
+impl RawField {
+    fn push(&mut self, byte: u8) {
+        if self.bytes.len() < MAX_GUEST_FIELD_BYTES {
+            self.bytes.push(byte);
+        } else {
+            self.oversized = true;
+        }
+    }
+}
+
+enum State {
+    Start,
+    BeforeKey { first: bool },
+    Key(TextField),
+    Colon,
+    BeforeValue,
+    Text(TextField),
+    Raw(RawField),
+    AfterValue,
+    Done,
+}
+
+/// Decodes the agent's `/execute` response, a flat JSON object, as its bytes arrive, so
+/// output past the cap is dropped instead of buffered and memory stays bounded however much
+/// the guest sends
+pub struct ResponseDecoder {
+    stream_budget: usize,
+    limit: u64,
+    received: u64,
+    state: State,
+    key: String,
+    fields: Map<String, Value>,
+    truncated: Vec<String>,
+}
+
+impl ResponseDecoder {
+    /// Decoder for the response to an execution capped at `max_output_bytes`
+    pub fn new(max_output_bytes: usize) -> Self {
+        Self {
+            stream_budget: stream_budget(max_output_bytes),
+            limit: body_limit(max_output_bytes),
+            received: 0,
+            state: State::Start,
+            key: String::new(),
+            fields: Map::new(),
+            truncated: Vec::new(),
+        }
+    }
+
+    /// Take the next chunk of the body; fails once the body is malformed or over its limit,
+    /// at which point the transfer should be abandoned
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), DecodeError> {
+        for &byte in chunk {
+            self.feed_byte(byte)?;
+            self.received += 1;
+        }
+        if self.received > self.limit {
+            return Err(DecodeError::TooLarge(self.limit));
+        }
+        Ok(())
+    }
+
+    fn feed_byte(&mut self, byte: u8) -> Result<(), DecodeError> {
+        let offset = self.received;
+        let unexpected = || DecodeError::Unexpected(byte as char, offset);
+        let whitespace = byte.is_ascii_whitespace();
+        match &mut self.state {
+            State::Start if whitespace => {}
+            State::Start if byte == b'{' => self.state = State::BeforeKey { first: true },
+            State::BeforeKey { .. } if whitespace => {}
+            State::BeforeKey { .. } if byte == b'"' => {
+                self.state = State::Key(TextField::new(MAX_GUEST_FIELD_BYTES))
+            }
+            State::BeforeKey { first: true } if byte == b'}' => self.state = State::Done,
+            State::Key(key) => {
+                if key.feed(byte, offset)? {
+                    let State::Key(key) = std::mem::replace(&mut self.state, State::Colon) else {
+                        unreachable!()
+                    };
+                    self.key = key.into_string();
+                }
+            }
+            State::Colon if whitespace => {}
+            State::Colon if byte == b':' => self.state = State::BeforeValue,
+            State::BeforeValue if whitespace => {}
+            State::BeforeValue if byte == b'"' => {
+                let budget = if OUTPUT_FIELDS.contains(&self.key.as_str()) {
+                    self.stream_budget
+                } else {
+                    MAX_GUEST_FIELD_BYTES
+                };
+                self.state = State::Text(TextField::new(budget));
+            }
+            State::BeforeValue => {
+                self.state = State::Raw(RawField::default());
+                return self.feed_byte(byte);
+            }
+            State::Text(text) => {
+                if text.feed(byte, offset)? {
+                    let State::Text(text) = std::mem::replace(&mut self.state, State::AfterValue)
+                    else {
+                        unreachable!()
+                    };
+                    let key = std::mem::take(&mut self.key);
+                    if text.truncated {
+                        self.truncated.push(key.clone());
+                    }
+                    self.fields.insert(key, Value::String(text.into_string()));
+                }
+            }
+            State::Raw(raw) => {
+                if raw.in_string {
+                    raw.push(byte);
+                    if raw.escaped {
+                        raw.escaped = false;
+                    } else if byte == b'\\' {
+                        raw.escaped = true;
+                    } else if byte == b'"' {
+                        raw.in_string = false;
+                    }
+                    return Ok(());
+                }
+                let ends_scalar = raw.depth == 0 && (whitespace || matches!(byte, b',' | b'}'));
+                match byte {
+                    _ if ends_scalar => {
+                        self.finish_raw()?;
+                        return self.feed_byte(byte);
+                    }
+                    b'"' => raw.in_string = true,
+                    b'{' | b'[' => raw.depth += 1,
+                    b'}' | b']' => raw.depth -= 1,
+                    _ => {}
+                }
+                raw.push(byte);
+                if raw.depth == 0 && matches!(byte, b'}' | b']') {
+                    self.finish_raw()?;
+                }
+            }
+            State::AfterValue if whitespace => {}
+            State::AfterValue if byte == b',' => self.state = State::BeforeKey { first: false },
+            State::AfterValue if byte == b'}' => self.state = State::Done,
+            State::Done if whitespace => {}
+            _ => return Err(unexpected()),
+        }
+        Ok(())
+    }
+
+    fn finish_raw(&mut self) -> Result<(), DecodeError> {
+        let State::Raw(raw) = std::mem::replace(&mut self.state, State::AfterValue) else {
+            unreachable!()
+        };
+        let key = std::mem::take(&mut self.key);
+        if raw.oversized {
+            tracing::warn!(
+                "Dropping guest response field {:?} over {} bytes",
+                key,
+                MAX_GUEST_FIELD_BYTES
+            );
+            return Ok(());
+        }
+        let value = serde_json::from_slice(&raw.bytes).map_err(|_| DecodeError::Incomplete)?;
+        self.fields.insert(key, value);
+        Ok(())
+    }
+
+    /// Bytes taken so far
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The decoded response, with `<stream>_truncated` set on every stream cut short here
+    pub fn finish(self) -> Result<Value, DecodeError> {
+        if !matches!(self.state, State::Done) {
+            return Err(DecodeError::Incomplete);
+        }
+        let mut fields = self.fields;
+        for key in self.truncated {
+            fields.insert(format!("{key}_truncated"), Value::Bool(true));
+        }
+        Ok(Value::Object(fields))
+    }
+}
+
+/// Longest output line of a live response; the agent sends output in smaller pieces, so a
+/// longer line can only be the result
+pub const MAX_LIVE_LINE_BYTES: usize = 64 * 1024;
+
+/// Output the agent sent while the code ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LiveChunk {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// Decodes the agent's live `/execute` response as its bytes arrive: output lines as the code
+/// prints them, then the result, a last line decoded by `ResponseDecoder`
+pub struct LiveDecoder {
+    max_output_bytes: usize,
+    line: Vec<u8>,
+    result: Option<ResponseDecoder>,
+}
+
+impl LiveDecoder {
+    /// Decoder for the response to an execution capped at `max_output_bytes`
+    pub fn new(max_output_bytes: usize) -> Self {
+        Self {
+            max_output_bytes,
+            line: Vec::new(),
+            result: None,
+        }
+    }
+
+    /// Take the next chunk of the body, returning the output lines it completed; fails like
+    /// `ResponseDecoder::feed` once the result is malformed or over its limit
+    pub fn feed(&mut self, mut chunk: &[u8]) -> Result<Vec<LiveChunk>, DecodeError> {
+        let mut output = Vec::new();
+        while !chunk.is_empty() {
+            if let Some(result) = &mut self.result {
+                result.feed(chunk)?;
+                break;
+            }
+            let Some(end) = chunk.iter().position(|byte| *byte == b'\n') else {
+                self.line.extend_from_slice(chunk);
+                if self.line.len() > MAX_LIVE_LINE_BYTES {
+                    self.start_result()?;
+                }
+                break;
+            };
+            self.line.extend_from_slice(&chunk[..=end]);
+            chunk = &chunk[end + 1..];
+            if self.line.iter().all(u8::is_ascii_whitespace) {
+                self.line.clear();
+                continue;
+            }
+            match output_line(&self.line) {
+                Some(line) => {
+                    output.push(line);
+                    self.line.clear();
+                }
+                None => self.start_result()?,
+            }
+        }
+        Ok(output)
+    }
+
+    /// The result the response ended with
+    pub fn finish(mut self) -> Result<Value, DecodeError> {
+        if self.result.is_none() {
+            self.start_result()?;
+        }
+        self.result
+            .map_or(Err(DecodeError::Incomplete), ResponseDecoder::finish)
+    }
+
+    /// Decode the line read so far, and everything after it, as the result
+    fn start_result(&mut self) -> Result<(), DecodeError> {
+        let mut result = ResponseDecoder::new(self.max_output_bytes);
+        result.feed(&std::mem::take(&mut self.line))?;
+        self.result = Some(result);
+        Ok(())
+    }
+}
+
+/// The output `line` carries, if it is an output line
+fn output_line(line: &[u8]) -> Option<LiveChunk> {
+    let line: Value = serde_json::from_slice(line).ok()?;
+    let data = line["data"].as_str()?.to_string();
+    match line["type"].as_str()? {
+        "stdout" => Some(LiveChunk::Stdout(data)),
+        "stderr" => Some(LiveChunk::Stderr(data)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decode(body: &[u8], max_output_bytes: usize) -> Result<Value, DecodeError> {
+        let mut decoder = ResponseDecoder::new(max_output_bytes);
+        decoder.feed(body)?;
+        decoder.finish()
+    }
+
+    #[test]
+    fn test_decodes_like_serde_at_every_split() {
+        let response = json!({
+            "stdout": "héllo \"world\"\n\t\u{1F600} \\ /",
+            "stderr": "",
+            "stdout_encoding": "utf8",
+            "success": true,
+            "exit_code": -1,
+            "value": {"nested": [1, 2.5, "}]", null, {"a": false}]},
+            "exception": null,
+            "usage": {"cpu_ms": 12},
+        });
+        // Python's json.dumps escapes non-ASCII text, surrogate pairs included
+        let ascii = serde_json::to_string_pretty(&response)
+            .unwrap()
+            .replace('é', "\\u00e9")
+            .replace('\u{1F600}', "\\ud83d\\ude00");
+        for body in [serde_json::to_string(&response).unwrap(), ascii] {
+            for split in 0..body.len() {
+                let mut decoder = ResponseDecoder::new(1024);
+                decoder.feed(&body.as_bytes()[..split]).unwrap();
+                decoder.feed(&body.as_bytes()[split..]).unwrap();
+                assert_eq!(decoder.finish().unwrap(), response, "split at {split}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_past_the_budget_is_dropped() {
+        let stdout = "é".repeat(100);
+        let body = json!({"stdout": stdout, "stderr": "ok", "stdout_truncated": false});
+        let decoded = decode(body.to_string().as_bytes(), 3).unwrap();
+        // Four characters' worth of budget, less the half of the third `é`
+        assert_eq!(decoded["stdout"], "éé");
+        assert_eq!(decoded["stdout_truncated"], true);
+        assert_eq!(decoded["stderr"], "ok");
+        assert!(decoded.get("stderr_truncated").is_none());
+
+        // Other string fields aren't held to the output budget
+        let body = json!({"stdout": "", "value_repr": "x".repeat(100)});
+        assert_eq!(
+            decode(body.to_string().as_bytes(), 3).unwrap()["value_repr"],
+            "x".repeat(100)
+        );
+    }
+
+    #[test]
+    fn test_malformed_or_unfinished_bodies_fail() {
+        assert!(matches!(
+            decode(b"[1]", 1024),
+            Err(DecodeError::Unexpected('[', 0))
+        ));
+        assert!(matches!(
+            decode(br#"{"stdout": "x" "stderr": ""}"#, 1024),
+            Err(DecodeError::Unexpected('"', 15))
+        ));
+        assert_eq!(
+            decode(br#"{"stdout": "x""#, 1024),
+            Err(DecodeError::Incomplete)
+        );
+        assert_eq!(
+            decode(br#"{"stdout": "\x"}"#, 1024)
+                .unwrap_err()
+                .to_string(),
+            "unexpected byte 'x' at offset 13"
+        );
+        assert_eq!(decode(b" {} ", 1024).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn test_body_over_the_limit_fails_as_it_arrives() {
+        let mut decoder = ResponseDecoder::new(3);
+        decoder.feed(br#"{"stdout": ""#).unwrap();
+        let chunk = vec![b'a'; 1024 * 1024];
+        let err = std::iter::repeat_n(&chunk, 64)
+            .map(|chunk| decoder.feed(chunk))
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(err, DecodeError::TooLarge(body_limit(3)));
+        assert!(decoder.received() <= body_limit(3) + chunk.len() as u64);
+    }
+
+    #[test]
+    fn test_live_output_lines_come_before_the_result() {
+        let body = concat!(
+            "{\"type\": \"stdout\", \"data\": \"1\\n\"}\n",
+            "{\"type\": \"stderr\", \"data\": \"oops\"}\n",
+            "{\"type\": \"stdout\", \"data\": \"2\\n\"}\n",
+            "{\"type\": \"result\", \"stdout\": \"1\\n2\\n\", \"stderr\": \"oops\", \"success\": true}\n",
+        );
+        for split in 0..body.len() {
+            let mut decoder = LiveDecoder::new(1024);
+            let mut output = decoder.feed(&body.as_bytes()[..split]).unwrap();
+            output.extend(decoder.feed(&body.as_bytes()[split..]).unwrap());
+            assert_eq!(
+                output,
+                [
+                    LiveChunk::Stdout("1\n".into()),
+                    LiveChunk::Stderr("oops".into()),
+                    LiveChunk::Stdout("2\n".into()),
+                ],
+                "split at {split}"
+            );
+            let result = decoder.finish().unwrap();
+            assert_eq!(
+                (result["stdout"].as_str(), result["success"].as_bool()),
+                (Some("1\n2\n"), Some(true))
+            );
+        }
+    }
+
+    #[test]
+    fn test_live_result_keeps_its_limits() {
+        // A result without output lines, or without a final newline, still decodes
+        let mut decoder = LiveDecoder::new(1024);
+        assert!(
+            decoder
+                .feed(br#"{"stdout": "x", "success": true}"#)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(decoder.finish().unwrap()["stdout"], "x");
+
+        // A long line is the result, and held to the output budget as it arrives
+        let mut decoder = LiveDecoder::new(3);
+        decoder.feed(br#"{"type": "result", "stdout": ""#).unwrap();
+        let chunk = vec![b'a'; 1024 * 1024];
+        let err = std::iter::repeat_n(&chunk, 64)
+            .map(|chunk| decoder.feed(chunk))
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(err, DecodeError::TooLarge(body_limit(3)));
+
+        assert_eq!(
+            LiveDecoder::new(1024).finish(),
+            Err(DecodeError::Incomplete)
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use crate::jobs::{JobStore, StoreError};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Summary of one execution. Only hashes and lengths are kept, never code or output bodies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, utoipa::ToSchema)]
+pub struct ExecutionRecord {
+    pub request_id: String,
+    /// VM that served the request, if one was obtained
+    pub vm_id: Option<String>,
+    /// Start time in milliseconds since the Unix epoch
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub success: bool,
+    /// Stable error code when the execution failed on the host side
+    pub error_code: Option<String>,
+    pub code_sha256: String,
+    pub stdout_len: usize,
+    pub stderr_len: usize,
+}
+
+/// Bounded ring buffer of the most recent executions, optionally written through to a store
+#[derive(Debug)]
+pub struct ExecutionHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<ExecutionRecord>>,
+    store: OnceCell<Arc<dyn JobStore>>,
+}
+
+impl ExecutionHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+            store: OnceCell::new(),
+        }
+    }
+
+    /// Load the most recent records from `store` and write every later one through to it.
+    /// Only the first store set takes effect.
+    pub fn persist_to(&self, store: Arc<dyn JobStore>) -> Result<(), StoreError> {
+        let recent = store.recent_executions(self.capacity)?;
+        if self.store.set(store).is_err() {
+            return Ok(());
+        }
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        for record in recent.into_iter().rev() {
+            if records.len() == self.capacity {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+        Ok(())
+    }
+
+    /// Append a record, evicting the oldest one when the buffer is full
+    pub fn push(&self, record: ExecutionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(store) = self.store.get()
+            && let Err(e) = store.record_execution(&record)
+        {
+            tracing::warn!("Failed to persist execution record: {}", e);
+        }
+        let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Up to `limit` most recent records, newest first
+    pub fn recent(&self, limit: usize) -> Vec<ExecutionRecord> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.iter().rev().take(limit).cloned().collect()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Global execution history, sized from the runner configuration
+pub static EXECUTION_HISTORY: once_cell::sync::Lazy<ExecutionHistory> =
+    once_cell::sync::Lazy::new(|| {
+        ExecutionHistory::new(crate::config::runner_config().history_capacity)
+    });
+
+/// Hex-encoded SHA-256 of the submitted code
+pub fn code_sha256(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(n: u64) -> ExecutionRecord {
+        ExecutionRecord {
+            request_id: format!("req-{n}"),
+            vm_id: None,
+            started_at: n,
+            duration_ms: 1,
+            success: true,
+            error_code: None,
+            code_sha256: code_sha256("print(1)"),
+            stdout_len: 2,
+            stderr_len: 0,
+        }
+    }
+
+    #[test]
+    fn test_history_wraparound() {
+        let history = ExecutionHistory::new(3);
+        for n in 1..=5 {
+            history.push(record(n));
+        }
+
+        let ids: Vec<_> = history
+            .recent(10)
+            .into_iter()
+            .map(|r| r.request_id)
+            .collect();
+        assert_eq!(ids, vec!["req-5", "req-4", "req-3"]);
+    }
+
+    #[test]
+    fn test_history_limit_newest_first() {
+        let history = ExecutionHistory::new(10);
+        for n in 1..=4 {
+            history.push(record(n));
+        }
+
+        let recent = history.recent(2);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].request_id, "req-4");
+        assert_eq!(recent[1].request_id, "req-3");
+    }
+
+    #[test]
+    fn test_history_zero_capacity() {
+        let history = ExecutionHistory::new(0);
+        history.push(record(1));
+        assert!(history.recent(10).is_empty());
+    }
+
+    #[test]
+    fn test_history_persists_to_store() {
+        let store: Arc<dyn JobStore> = Arc::new(crate::jobs::MemoryJobStore::new());
+        for n in 1..=3 {
+            store.record_execution(&record(n)).unwrap();
+        }
+
+        // Records from before the restart are loaded, newer ones written through
+        let history = ExecutionHistory::new(2);
+        history.persist_to(store.clone()).unwrap();
+        let ids: Vec<_> = history
+            .recent(10)
+            .into_iter()
+            .map(|r| r.request_id)
+            .collect();
+        assert_eq!(ids, vec!["req-3", "req-2"]);
+
+        history.push(record(4));
+        assert_eq!(store.recent_executions(1).unwrap(), vec![record(4)]);
+    }
+
+    #[test]
+    fn test_code_sha256() {
+        assert_eq!(
+            code_sha256(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
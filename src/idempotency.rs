@@ -0,0 +1,378 @@
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+/// Request header naming an operation that must run at most once
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Response header marking a stored response served again
+pub const IDEMPOTENCY_REPLAYED_HEADER: &str = "idempotency-replayed";
+/// Longest idempotency key accepted
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Settings of the store of responses to requests carrying an `Idempotency-Key`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotencyConfig {
+    /// Maximum number of stored responses; 0 ignores `Idempotency-Key` entirely
+    pub capacity: usize,
+    /// How long a stored response is replayed
+    pub ttl: Duration,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Hex SHA-256 of a request body, telling a retry from a different request under the same key
+pub fn body_hash(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+/// A finished response, buffered so it can be sent again
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl StoredResponse {
+    /// Buffer `response`, which must have a body held in memory
+    pub async fn capture(response: Response) -> Result<Self, axum::Error> {
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await?;
+        Ok(Self {
+            status: parts.status,
+            headers: parts.headers,
+            body,
+        })
+    }
+
+    /// The response again, marked with `Idempotency-Replayed: true`
+    pub fn replay(self) -> Response {
+        let mut response = self.into_response();
+        response.headers_mut().insert(
+            IDEMPOTENCY_REPLAYED_HEADER,
+            HeaderValue::from_static("true"),
+        );
+        response
+    }
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        (self.status, self.headers, self.body).into_response()
+    }
+}
+
+/// API key identifier and idempotency key; keys of different tenants never collide
+type Key = (String, String);
+
+#[derive(Debug)]
+enum Slot {
+    /// The first request is still running; the receiver yields its response
+    InFlight(watch::Receiver<Option<StoredResponse>>),
+    Done {
+        response: StoredResponse,
+        stored: Instant,
+    },
+}
+
+#[derive(Debug)]
+struct Entry {
+    body_hash: String,
+    slot: Slot,
+}
+
+/// What to do with a request carrying an idempotency key
+#[derive(Debug)]
+pub enum Claim {
+    /// First request with the key: run it, then [`IdempotencyGuard::complete`] the guard
+    Execute(IdempotencyGuard),
+    /// The key was used for the same body before; send its response again
+    Replay(StoredResponse),
+    /// The key was used for a different body
+    Conflict,
+    /// The store is full of requests still in flight, so the key couldn't be kept
+    Full,
+}
+
+/// Responses to requests carrying an `Idempotency-Key`, kept for a while so retries replay
+/// them instead of running the code again
+#[derive(Debug)]
+pub struct IdempotencyStore {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        Self {
+            capacity: config.capacity,
+            ttl: config.ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Claim `key` for a request whose body hashes to `body_hash`. A duplicate of a request
+    /// still in flight waits for its response, and runs itself if the first one gives up.
+    pub async fn claim(self: &Arc<Self>, key_id: &str, key: &str, body_hash: &str) -> Claim {
+        let key = (key_id.to_string(), key.to_string());
+        loop {
+            let mut in_flight = match self.try_claim(&key, body_hash) {
+                Ok(claim) => return claim,
+                Err(receiver) => receiver,
+            };
+            if let Ok(response) = in_flight.wait_for(Option::is_some).await {
+                let response = response.clone().expect("waited for a response");
+                return Claim::Replay(response);
+            }
+            // The first request was abandoned without a response to keep; claim again
+        }
+    }
+
+    /// The claim, or the receiver of the response of the request already in flight
+    fn try_claim(
+        self: &Arc<Self>,
+        key: &Key,
+        body_hash: &str,
+    ) -> Result<Claim, watch::Receiver<Option<StoredResponse>>> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let expired = matches!(
+            entries.get(key),
+            Some(Entry { slot: Slot::Done { stored, .. }, .. })
+                if now.saturating_duration_since(*stored) >= self.ttl
+        );
+        if expired {
+            entries.remove(key);
+        }
+        if let Some(entry) = entries.get(key) {
+            if entry.body_hash != body_hash {
+                return Ok(Claim::Conflict);
+            }
+            return match &entry.slot {
+                Slot::Done { response, .. } => Ok(Claim::Replay(response.clone())),
+                Slot::InFlight(receiver) => Err(receiver.clone()),
+            };
+        }
+        if entries.len() >= self.capacity {
+            self.evict(&mut entries, now);
+            if entries.len() >= self.capacity {
+                return Ok(Claim::Full);
+            }
+        }
+        let (sender, receiver) = watch::channel(None);
+        entries.insert(
+            key.clone(),
+            Entry {
+                body_hash: body_hash.to_string(),
+                slot: Slot::InFlight(receiver),
+            },
+        );
+        Ok(Claim::Execute(IdempotencyGuard {
+            store: self.clone(),
+            key: key.clone(),
+            sender: Some(sender),
+        }))
+    }
+
+    /// Drop expired responses, then the oldest one if still full; requests in flight stay
+    fn evict(&self, entries: &mut HashMap<Key, Entry>, now: Instant) {
+        entries.retain(|_, entry| match entry.slot {
+            Slot::InFlight(_) => true,
+            Slot::Done { stored, .. } => now.saturating_duration_since(stored) < self.ttl,
+        });
+        if entries.len() < self.capacity {
+            return;
+        }
+        let oldest = entries
+            .iter()
+            .filter_map(|(key, entry)| match entry.slot {
+                Slot::Done { stored, .. } => Some((key, stored)),
+                Slot::InFlight(_) => None,
+            })
+            .min_by_key(|(_, stored)| *stored)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            entries.remove(&oldest);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The right to run the first request with an idempotency key. Dropped without
+/// [`IdempotencyGuard::complete`], the key is released and waiting duplicates claim it again.
+#[derive(Debug)]
+pub struct IdempotencyGuard {
+    store: Arc<IdempotencyStore>,
+    key: Key,
+    sender: Option<watch::Sender<Option<StoredResponse>>>,
+}
+
+impl IdempotencyGuard {
+    /// Keep `response` for replay and hand it to the duplicates waiting for it
+    pub fn complete(mut self, response: StoredResponse) {
+        let mut entries = self.store.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = entries.get_mut(&self.key) {
+            entry.slot = Slot::Done {
+                response: response.clone(),
+                stored: Instant::now(),
+            };
+        }
+        if let Some(sender) = self.sender.take() {
+            sender.send_replace(Some(response));
+        }
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        if self.sender.is_none() {
+            return;
+        }
+        // Released before the sender is dropped, so woken duplicates find the key free
+        let mut entries = self.store.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(
+            entries.get(&self.key),
+            Some(Entry {
+                slot: Slot::InFlight(_),
+                ..
+            })
+        ) {
+            entries.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(capacity: usize, ttl: Duration) -> Arc<IdempotencyStore> {
+        Arc::new(IdempotencyStore::new(&IdempotencyConfig { capacity, ttl }))
+    }
+
+    fn stored(body: &'static str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    async fn execute(store: &Arc<IdempotencyStore>, key: &str, hash: &str) -> IdempotencyGuard {
+        match store.claim("tenant", key, hash).await {
+            Claim::Execute(guard) => guard,
+            other => panic!("expected to execute, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_conflict() {
+        let store = store(10, Duration::from_secs(60));
+        execute(&store, "k", "a").await.complete(stored("first"));
+
+        match store.claim("tenant", "k", "a").await {
+            Claim::Replay(response) => assert_eq!(response.body, "first"),
+            other => panic!("expected a replay, got {other:?}"),
+        }
+        assert!(matches!(
+            store.claim("tenant", "k", "b").await,
+            Claim::Conflict
+        ));
+        // Keys are scoped to the API key
+        execute(&store, "other", "a").await;
+        assert!(matches!(
+            store.claim("someone-else", "k", "b").await,
+            Claim::Execute(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_claim_releases_the_key() {
+        let store = store(10, Duration::from_secs(60));
+        drop(execute(&store, "k", "a").await);
+        assert!(store.is_empty());
+        execute(&store, "k", "b").await;
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_waits_for_the_first_request() {
+        let store = store(10, Duration::from_secs(60));
+        let guard = execute(&store, "k", "a").await;
+        let duplicate = tokio::spawn({
+            let store = store.clone();
+            async move { store.claim("tenant", "k", "a").await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!duplicate.is_finished());
+        guard.complete(stored("first"));
+        match duplicate.await.unwrap() {
+            Claim::Replay(response) => assert_eq!(response.body, "first"),
+            other => panic!("expected a replay, got {other:?}"),
+        }
+
+        // A duplicate of an abandoned request runs it itself
+        let guard = execute(&store, "j", "a").await;
+        let duplicate = tokio::spawn({
+            let store = store.clone();
+            async move { store.claim("tenant", "j", "a").await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(guard);
+        assert!(matches!(duplicate.await.unwrap(), Claim::Execute(_)));
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_eviction() {
+        let expiring = store(10, Duration::ZERO);
+        execute(&expiring, "k", "a").await.complete(stored("first"));
+        execute(&expiring, "k", "b").await;
+
+        let small = store(2, Duration::from_secs(60));
+        execute(&small, "a", "x").await.complete(stored("a"));
+        execute(&small, "b", "x").await.complete(stored("b"));
+        execute(&small, "c", "x").await.complete(stored("c"));
+        assert_eq!(small.len(), 2);
+        assert!(matches!(
+            small.claim("tenant", "a", "y").await,
+            Claim::Execute(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_store_full_of_requests_in_flight_refuses_new_keys() {
+        let store = store(2, Duration::from_secs(60));
+        let first = execute(&store, "a", "x").await;
+        let _second = execute(&store, "b", "x").await;
+        assert!(matches!(store.claim("tenant", "c", "x").await, Claim::Full));
+        assert_eq!(store.len(), 2);
+
+        // Once a request finishes, its response can make room
+        first.complete(stored("a"));
+        let _third = execute(&store, "c", "x").await;
+        assert_eq!(store.len(), 2);
+    }
+}
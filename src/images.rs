@@ -0,0 +1,532 @@
+use crate::rootfs::{self, DEFAULT_HEADROOM_MIB, RootfsError, RootfsImage, io};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+/// Directory holding the images selectable with a request's `image` field
+pub const DEFAULT_IMAGE_DIR: &str = "images";
+
+/// Extension of the image files in the image directory
+const IMAGE_EXTENSION: &str = "ext4";
+
+/// Marks a directory whose lower-layer contents are hidden
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Prefix of a file hiding the lower-layer entry it names
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Whether `name` may name an image: it becomes a file name, so no separators or dot files
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// File of image `name` in `dir`, whether or not it exists
+pub fn image_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.{IMAGE_EXTENSION}"))
+}
+
+/// The images in `dir`, by name; a missing directory holds none
+pub fn discover(dir: &Path) -> BTreeMap<String, PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return BTreeMap::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == IMAGE_EXTENSION))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_str()?.to_string();
+            is_valid_name(&name).then_some((name, path))
+        })
+        .collect()
+}
+
+/// What `import-image` converts
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportSpec {
+    /// `docker save` tarball or OCI image layout directory
+    pub source: PathBuf,
+    /// Name requests select the image by
+    pub name: String,
+    /// Directory holding the guest agent sources
+    pub agent_dir: PathBuf,
+    /// Image directory the result is written to
+    pub image_dir: PathBuf,
+    pub headroom_mib: u64,
+}
+
+impl ImportSpec {
+    /// Parse `SOURCE --name NAME [--agent-dir DIR] [--image-dir DIR] [--headroom-mib N]`;
+    /// the image directory defaults to `FC_IMAGE_DIR`, as the server reads it
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, RootfsError> {
+        let mut source = None;
+        let mut name = None;
+        let mut agent_dir = PathBuf::from(".");
+        let mut image_dir = std::env::var("FC_IMAGE_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_IMAGE_DIR));
+        let mut headroom_mib = DEFAULT_HEADROOM_MIB;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| RootfsError::InvalidInput(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "--name" => name = Some(value()?),
+                "--agent-dir" => agent_dir = PathBuf::from(value()?),
+                "--image-dir" => image_dir = PathBuf::from(value()?),
+                "--headroom-mib" => {
+                    let raw = value()?;
+                    headroom_mib = raw.parse().map_err(|_| {
+                        RootfsError::InvalidInput(format!("--headroom-mib: invalid value {raw:?}"))
+                    })?;
+                }
+                other if !other.starts_with("--") && source.is_none() => {
+                    source = Some(PathBuf::from(other));
+                }
+                other => {
+                    return Err(RootfsError::InvalidInput(format!(
+                        "unexpected argument {other:?}; usage: import-image SOURCE --name NAME \
+                         [--agent-dir DIR] [--image-dir DIR] [--headroom-mib N]"
+                    )));
+                }
+            }
+        }
+        let name =
+            name.ok_or_else(|| RootfsError::InvalidInput("--name is required".to_string()))?;
+        if !is_valid_name(&name) {
+            return Err(RootfsError::InvalidInput(format!(
+                "invalid image name {name:?}; use letters, digits, '.', '_' and '-'"
+            )));
+        }
+        Ok(Self {
+            source: source.ok_or_else(|| {
+                RootfsError::InvalidInput("a docker save tarball or OCI layout is required".into())
+            })?,
+            name,
+            agent_dir,
+            image_dir,
+            headroom_mib,
+        })
+    }
+
+    /// Where the image is written
+    pub fn output(&self) -> PathBuf {
+        image_path(&self.image_dir, &self.name)
+    }
+}
+
+/// Convert a container image into a rootfs in the image directory: its layers are applied
+/// in order, honouring whiteouts, and the agent and init are added as for `build-rootfs`.
+/// Entries that would land outside the rootfs reject the whole image.
+pub fn import(spec: &ImportSpec) -> Result<RootfsImage, RootfsError> {
+    fs::create_dir_all(&spec.image_dir).map_err(io(&spec.image_dir))?;
+    let output = spec.output();
+    // A docker save tarball is unpacked next to the output so its layers can be read in order
+    let unpacked = output.with_extension("layout");
+    let layout = if spec.source.is_dir() {
+        spec.source.clone()
+    } else {
+        if unpacked.exists() {
+            fs::remove_dir_all(&unpacked).map_err(io(&unpacked))?;
+        }
+        fs::create_dir_all(&unpacked).map_err(io(&unpacked))?;
+        unpack_archive(&spec.source, &unpacked)?;
+        unpacked.clone()
+    };
+
+    let result = layer_paths(&layout).and_then(|layers| {
+        rootfs::build_with(
+            &spec.source,
+            &spec.agent_dir,
+            &output,
+            spec.headroom_mib,
+            |staging, privileged| {
+                let mut modes = BTreeMap::new();
+                for layer in &layers {
+                    apply_layer(layer, staging, privileged, &mut modes)?;
+                }
+                restore_modes(staging, &modes)
+            },
+        )
+    });
+    if layout == unpacked {
+        let _ = fs::remove_dir_all(&unpacked);
+    }
+    result
+}
+
+/// Unpack the outer `docker save` tarball into `dest`, keeping only files and directories
+fn unpack_archive(source: &Path, dest: &Path) -> Result<(), RootfsError> {
+    let file = fs::File::open(source).map_err(io(source))?;
+    let mut archive = tar::Archive::new(file);
+    for entry in archive.entries().map_err(io(source))? {
+        let mut entry = entry.map_err(io(source))?;
+        let Some(relative) = confined(&entry.path().map_err(io(source))?)? else {
+            continue;
+        };
+        check_parents(dest, &relative)?;
+        let target = dest.join(&relative);
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => fs::create_dir_all(&target).map_err(io(&target))?,
+            tar::EntryType::Regular => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(io(parent))?;
+                }
+                remove_path(&target)?;
+                let mut out = fs::File::create(&target).map_err(io(&target))?;
+                std::io::copy(&mut entry, &mut out).map_err(io(&target))?;
+            }
+            // Newer docker releases link `manifest.json` layer paths to their blobs
+            tar::EntryType::Symlink => {
+                let link = entry.link_name().map_err(io(source))?.unwrap_or_default();
+                if link.is_absolute() {
+                    return Err(RootfsError::UnsafeEntry(relative.display().to_string()));
+                }
+                let resolved = relative
+                    .parent()
+                    .map(|parent| parent.join(&link))
+                    .unwrap_or_else(|| link.to_path_buf());
+                confined(&normalize(&resolved))?;
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(io(parent))?;
+                }
+                std::os::unix::fs::symlink(&link, &target).map_err(io(&target))?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// `path` relative to the rootfs, or `None` for the root itself; `..` is refused rather than
+/// resolved, since no legitimate layer needs it
+fn confined(path: &Path) -> Result<Option<PathBuf>, RootfsError> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(RootfsError::UnsafeEntry(path.display().to_string()));
+            }
+        }
+    }
+    Ok((!relative.as_os_str().is_empty()).then_some(relative))
+}
+
+/// Collapse `..` lexically, keeping a leading one so `confined` can refuse it
+fn normalize(path: &Path) -> PathBuf {
+    let mut parts: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir if matches!(parts.last(), Some(Component::Normal(_))) => {
+                parts.pop();
+            }
+            component => parts.push(component),
+        }
+    }
+    parts.iter().collect()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest {
+    layers: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    #[serde(rename = "mediaType", default)]
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+/// An OCI image index or image manifest
+#[derive(Deserialize)]
+struct OciDocument {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+/// The layer blobs of the image in `layout`, lowest first. A `docker save` tarball lists
+/// them in `manifest.json`; an OCI layout is followed from `index.json`, picking the
+/// manifest for this host's platform.
+fn layer_paths(layout: &Path) -> Result<Vec<PathBuf>, RootfsError> {
+    let docker_manifest = layout.join("manifest.json");
+    if docker_manifest.is_file() {
+        let manifests: Vec<DockerManifest> = read_json(&docker_manifest)?;
+        let manifest = manifests.into_iter().next().ok_or_else(|| {
+            RootfsError::InvalidInput("manifest.json lists no images".to_string())
+        })?;
+        return manifest
+            .layers
+            .iter()
+            .map(|layer| {
+                let relative = confined(Path::new(layer))?
+                    .ok_or_else(|| RootfsError::UnsafeEntry(layer.clone()))?;
+                Ok(layout.join(relative))
+            })
+            .collect();
+    }
+
+    let index = layout.join("index.json");
+    if !index.is_file() {
+        return Err(RootfsError::InvalidInput(format!(
+            "{} is neither a docker save tarball nor an OCI layout",
+            layout.display()
+        )));
+    }
+    let mut document: OciDocument = read_json(&index)?;
+    // Indexes may nest; follow them down to an image manifest
+    for _ in 0..4 {
+        if document.manifests.is_empty() {
+            return document
+                .layers
+                .iter()
+                .map(|layer| {
+                    if layer.media_type.contains("zstd") {
+                        return Err(RootfsError::InvalidInput(format!(
+                            "layer {} is zstd-compressed, which is not supported",
+                            layer.digest
+                        )));
+                    }
+                    blob_path(layout, &layer.digest)
+                })
+                .collect();
+        }
+        let manifest = pick_manifest(&document.manifests);
+        document = read_json(&blob_path(layout, &manifest.digest)?)?;
+    }
+    Err(RootfsError::InvalidInput(
+        "OCI index nests too deeply".to_string(),
+    ))
+}
+
+/// The manifest for this host's platform, or the first one when none names it
+fn pick_manifest(manifests: &[Descriptor]) -> &Descriptor {
+    let architecture = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        other => other,
+    };
+    manifests
+        .iter()
+        .find(|manifest| {
+            manifest.platform.as_ref().is_some_and(|platform| {
+                platform.os == "linux" && platform.architecture == architecture
+            })
+        })
+        .unwrap_or(&manifests[0])
+}
+
+/// Blob file of `digest`, which must be `algorithm:hex` so it can't point elsewhere
+fn blob_path(layout: &Path, digest: &str) -> Result<PathBuf, RootfsError> {
+    match digest.split_once(':') {
+        Some((algorithm, hex))
+            if !algorithm.is_empty()
+                && algorithm.chars().all(|c| c.is_ascii_alphanumeric())
+                && !hex.is_empty()
+                && hex.chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            Ok(layout.join("blobs").join(algorithm).join(hex))
+        }
+        _ => Err(RootfsError::InvalidInput(format!(
+            "invalid digest {digest:?}"
+        ))),
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, RootfsError> {
+    let raw = fs::read(path).map_err(io(path))?;
+    serde_json::from_slice(&raw)
+        .map_err(|e| RootfsError::InvalidInput(format!("{}: {e}", path.display())))
+}
+
+/// Apply one layer tarball, gzipped or not, on top of `staging`. Directory modes are
+/// collected in `modes` and applied once every layer is in, so a read-only directory doesn't
+/// stop an unprivileged build from filling it.
+fn apply_layer(
+    layer: &Path,
+    staging: &Path,
+    privileged: bool,
+    modes: &mut BTreeMap<PathBuf, u32>,
+) -> Result<(), RootfsError> {
+    let mut reader = BufReader::new(fs::File::open(layer).map_err(io(layer))?);
+    let gzipped = reader
+        .fill_buf()
+        .map_err(io(layer))?
+        .starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(flate2::read::GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_ownerships(privileged);
+    for entry in archive.entries().map_err(io(layer))? {
+        let mut entry = entry.map_err(io(layer))?;
+        let path = entry.path().map_err(io(layer))?.into_owned();
+        let Some(relative) = confined(&path)? else {
+            continue;
+        };
+        check_parents(staging, &relative)?;
+        let target = staging.join(&relative);
+        let file_name = relative
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        if file_name == OPAQUE_WHITEOUT {
+            if let Some(parent) = target.parent() {
+                clear_dir(parent)?;
+            }
+            continue;
+        }
+        if let Some(hidden) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+            remove_path(&target.with_file_name(hidden))?;
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            // devtmpfs provides device nodes, and creating them needs root
+            tar::EntryType::Char | tar::EntryType::Block | tar::EntryType::Fifo => continue,
+            tar::EntryType::Directory => {
+                if fs::symlink_metadata(&target).is_ok_and(|m| !m.is_dir()) {
+                    remove_path(&target)?;
+                }
+                fs::create_dir_all(&target).map_err(io(&target))?;
+                modes.insert(relative, entry.header().mode().map_err(io(layer))?);
+                continue;
+            }
+            _ => remove_path(&target)?,
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(io(parent))?;
+        }
+        if entry_type == tar::EntryType::Link {
+            let link = entry.link_name().map_err(io(layer))?.unwrap_or_default();
+            let source = confined(&link)?
+                .ok_or_else(|| RootfsError::UnsafeEntry(link.display().to_string()))?;
+            check_parents(staging, &source)?;
+            fs::hard_link(staging.join(source), &target).map_err(io(&target))?;
+        } else {
+            entry.unpack(&target).map_err(io(&target))?;
+        }
+    }
+    Ok(())
+}
+
+/// Refuse `relative` if a directory on its way is a symlink, which would let a later layer
+/// write through a link planted by an earlier one
+fn check_parents(staging: &Path, relative: &Path) -> Result<(), RootfsError> {
+    let mut current = staging.to_path_buf();
+    for part in relative.parent().into_iter().flat_map(Path::components) {
+        current.push(part);
+        if fs::symlink_metadata(&current).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(RootfsError::UnsafeEntry(relative.display().to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<(), RootfsError> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path).map_err(io(path)),
+        Ok(_) => fs::remove_file(path).map_err(io(path)),
+        Err(_) => Ok(()),
+    }
+}
+
+fn clear_dir(dir: &Path) -> Result<(), RootfsError> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(());
+    };
+    for entry in entries {
+        remove_path(&entry.map_err(io(dir))?.path())?;
+    }
+    Ok(())
+}
+
+/// Apply the collected directory modes, deepest first so parents stay writable meanwhile
+fn restore_modes(staging: &Path, modes: &BTreeMap<PathBuf, u32>) -> Result<(), RootfsError> {
+    for (relative, mode) in modes.iter().rev() {
+        let path = staging.join(relative);
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir()) {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o7777))
+                .map_err(io(&path))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confined_refuses_traversal() {
+        assert_eq!(
+            confined(Path::new("./usr/bin/python3")).unwrap(),
+            Some(PathBuf::from("usr/bin/python3"))
+        );
+        assert_eq!(
+            confined(Path::new("/etc/passwd")).unwrap(),
+            Some(PathBuf::from("etc/passwd"))
+        );
+        assert_eq!(confined(Path::new("./")).unwrap(), None);
+        for unsafe_path in ["../etc/passwd", "usr/../../etc", "/.."] {
+            assert!(
+                matches!(
+                    confined(Path::new(unsafe_path)),
+                    Err(RootfsError::UnsafeEntry(_))
+                ),
+                "{unsafe_path} should be refused"
+            );
+        }
+    }
+
+    #[test]
+    fn test_blob_path_requires_a_digest() {
+        assert_eq!(
+            blob_path(Path::new("/img"), "sha256:ab12").unwrap(),
+            PathBuf::from("/img/blobs/sha256/ab12")
+        );
+        for invalid in ["sha256:../../etc", "ab12", "sha256:", ":ab12"] {
+            assert!(blob_path(Path::new("/img"), invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_image_names() {
+        assert!(is_valid_name("ds-team_py3.12"));
+        for invalid in ["", ".hidden", "a/b", "../x", "name with space"] {
+            assert!(!is_valid_name(invalid), "{invalid:?}");
+        }
+        assert_eq!(
+            image_path(Path::new("images"), "ds"),
+            PathBuf::from("images/ds.ext4")
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use crate::program;
+use crate::webhook;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Names that answer cloud instance metadata requests
+const METADATA_HOSTS: &[&str] = &["metadata.google.internal", "metadata.goog", "instance-data"];
+
+/// Metadata endpoints outside the link-local range: AWS over IPv6 and Alibaba Cloud
+const METADATA_IPS: &[IpAddr] = &[
+    IpAddr::V6(Ipv6Addr::new(0xfd00, 0xec2, 0, 0, 0, 0, 0, 0x254)),
+    IpAddr::V4(Ipv4Addr::new(100, 100, 100, 200)),
+];
+
+/// A file downloaded into the guest before the code runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RemoteInput {
+    /// http(s) URL the guest downloads
+    pub url: String,
+    /// Relative path under `FC_INPUTS_DIR` (`/inputs` in the guest), e.g. `sales/2024.csv`
+    pub path: String,
+    /// Expected hex SHA-256 of the download
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+/// Where inputs may be downloaded from, and how much
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputsConfig {
+    /// Hosts inputs may come from; a leading `.` also admits subdomains. Empty allows any
+    /// host that isn't denied.
+    pub allowed_hosts: Vec<String>,
+    /// Hosts never downloaded from, on top of loopback, link-local, metadata and VM addresses
+    pub denied_hosts: Vec<String>,
+    /// Most inputs per request
+    pub max_inputs: usize,
+    /// Largest download, in bytes; the guest stops and fails past it
+    pub max_bytes: u64,
+}
+
+impl Default for InputsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            denied_hosts: Vec::new(),
+            max_inputs: 8,
+            max_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Why a request's inputs were refused
+#[derive(Debug, Error, PartialEq)]
+pub enum InputError {
+    #[error("Invalid input: {0}")]
+    Invalid(String),
+    #[error("Input host {0} is not allowed")]
+    Forbidden(String),
+}
+
+/// Why the guest couldn't provide an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SetupErrorCode {
+    DownloadFailed,
+    TooLarge,
+    ChecksumMismatch,
+}
+
+/// An input that failed to download; the code didn't run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SetupError {
+    pub code: SetupErrorCode,
+    pub url: String,
+    pub path: String,
+    pub message: String,
+    /// For `checksum_mismatch`, the SHA-256 asked for and the one downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual_sha256: Option<String>,
+}
+
+impl SetupError {
+    pub fn new(code: SetupErrorCode, input: &RemoteInput, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            url: input.url.clone(),
+            path: input.path.clone(),
+            message: message.into(),
+            expected_sha256: None,
+            actual_sha256: None,
+        }
+    }
+
+    /// Parse the agent's `setup_error` field; absent or malformed values yield `None`
+    pub fn from_agent(value: &serde_json::Value) -> Option<Self> {
+        if value.is_null() {
+            return None;
+        }
+        serde_json::from_value(value.clone())
+            .map_err(|e| tracing::debug!("Ignoring malformed setup error from agent: {}", e))
+            .ok()
+    }
+}
+
+/// Check a request's inputs: relative, distinct paths, well-formed checksums, and http(s)
+/// URLs whose host `config` allows. Names are checked as given, since the guest resolves them.
+pub fn validate(inputs: &[RemoteInput], config: &InputsConfig) -> Result<(), InputError> {
+    if inputs.len() > config.max_inputs {
+        return Err(InputError::Invalid(format!(
+            "at most {} inputs are allowed ({} given)",
+            config.max_inputs,
+            inputs.len()
+        )));
+    }
+    let mut paths = HashSet::new();
+    for input in inputs {
+        program::validate_path(&input.path).map_err(|e| InputError::Invalid(e.to_string()))?;
+        if !paths.insert(input.path.as_str()) {
+            return Err(InputError::Invalid(format!(
+                "duplicate path '{}'",
+                input.path
+            )));
+        }
+        if let Some(sha256) = &input.sha256
+            && (sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            return Err(InputError::Invalid(format!(
+                "sha256 of '{}' must be 64 hex digits",
+                input.path
+            )));
+        }
+        validate_url(&input.url, config)?;
+    }
+    Ok(())
+}
+
+fn validate_url(raw: &str, config: &InputsConfig) -> Result<(), InputError> {
+    let url = Url::parse(raw).map_err(|e| InputError::Invalid(format!("{raw}: {e}")))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(InputError::Invalid(format!(
+            "{raw}: unsupported scheme {}",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| InputError::Invalid(format!("{raw}: missing host")))?;
+    let name = host
+        .trim_matches(['[', ']'])
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    let forbidden = || InputError::Forbidden(host.to_string());
+    if webhook::is_local_name(&name)
+        || METADATA_HOSTS.contains(&name.as_str())
+        || config
+            .denied_hosts
+            .iter()
+            .any(|denied| matches_host(denied, &name))
+    {
+        return Err(forbidden());
+    }
+    if let Ok(ip) = name.parse::<IpAddr>()
+        && (webhook::is_forbidden(ip) || METADATA_IPS.contains(&ip) || is_vm_network(ip))
+    {
+        return Err(forbidden());
+    }
+    if !config.allowed_hosts.is_empty()
+        && !config
+            .allowed_hosts
+            .iter()
+            .any(|allowed| matches_host(allowed, &name))
+    {
+        return Err(forbidden());
+    }
+    Ok(())
+}
+
+/// Whether `name` is `pattern`, or one of its subdomains when the pattern starts with `.`
+fn matches_host(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.trim_matches(['[', ']']).to_ascii_lowercase();
+    match pattern.strip_prefix('.') {
+        Some(domain) => name == domain || name.ends_with(&pattern),
+        None => name == pattern,
+    }
+}
+
+/// VM subnets, `172.16.0.0/16`, whose `.1` addresses are the host
+fn is_vm_network(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    };
+    matches!(ip, IpAddr::V4(v4) if v4.octets()[..2] == [172, 16])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(url: &str) -> RemoteInput {
+        RemoteInput {
+            url: url.to_string(),
+            path: "data.csv".to_string(),
+            sha256: None,
+        }
+    }
+
+    fn check(url: &str, config: &InputsConfig) -> Result<(), InputError> {
+        validate(&[input(url)], config)
+    }
+
+    #[test]
+    fn test_url_validation() {
+        let config = InputsConfig::default();
+        assert!(check("https://storage.internal.example/bucket/data.csv", &config).is_ok());
+        assert!(check("http://10.1.2.3:9000/bucket/data.csv", &config).is_ok());
+
+        for url in [
+            "http://169.254.169.254/latest/meta-data",
+            "http://metadata.google.internal/computeMetadata/v1/",
+            "http://[fd00:ec2::254]/latest/meta-data",
+            "http://100.100.100.200/latest/meta-data",
+            "http://127.0.0.1:3000/health",
+            "http://localhost/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[fe80::1]/",
+            // The guest's gateway is the host
+            "http://172.16.0.1:3000/",
+        ] {
+            assert!(
+                matches!(check(url, &config), Err(InputError::Forbidden(_))),
+                "{url}"
+            );
+        }
+        for url in ["file:///etc/passwd", "ftp://example.com/data", "not a url"] {
+            assert!(
+                matches!(check(url, &config), Err(InputError::Invalid(_))),
+                "{url}"
+            );
+        }
+
+        let config = InputsConfig {
+            allowed_hosts: vec![".store.example".to_string(), "10.1.2.3".to_string()],
+            denied_hosts: vec!["private.store.example".to_string()],
+            ..Default::default()
+        };
+        assert!(check("https://eu.store.example/data.csv", &config).is_ok());
+        assert!(check("https://store.example/data.csv", &config).is_ok());
+        assert!(check("http://10.1.2.3/data.csv", &config).is_ok());
+        assert!(check("https://private.store.example/data.csv", &config).is_err());
+        assert!(check("https://elsewhere.example/data.csv", &config).is_err());
+        assert!(check("https://evilstore.example/data.csv", &config).is_err());
+    }
+
+    #[test]
+    fn test_paths_checksums_and_count() {
+        let config = InputsConfig {
+            max_inputs: 2,
+            ..Default::default()
+        };
+        let at = |path: &str| RemoteInput {
+            path: path.to_string(),
+            ..input("https://example.com/data")
+        };
+        assert!(validate(&[at("a.csv"), at("dir/b.csv")], &config).is_ok());
+        assert!(validate(&[at("a.csv"), at("a.csv")], &config).is_err());
+        assert!(validate(&[at("/etc/passwd")], &config).is_err());
+        assert!(validate(&[at("../escape")], &config).is_err());
+        assert!(validate(&[at("a"), at("b"), at("c")], &config).is_err());
+
+        let with_sha = |sha256: &str| RemoteInput {
+            sha256: Some(sha256.to_string()),
+            ..at("a.csv")
+        };
+        assert!(validate(&[with_sha(&"ab".repeat(32))], &config).is_ok());
+        assert!(validate(&[with_sha("abc")], &config).is_err());
+        assert!(validate(&[with_sha(&"zz".repeat(32))], &config).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::arch::Artifacts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Bytes hashed at a time, so a multi-GiB rootfs never sits in memory
+const HASH_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Expected hashes of the guest artifacts and how often to re-check them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityConfig {
+    /// SHA-256 the guest kernel must have, as hex
+    pub kernel_sha256: Option<String>,
+    /// SHA-256 the guest rootfs must have, as hex
+    pub rootfs_sha256: Option<String>,
+    /// Re-hash the artifacts this often, marking the service unhealthy on a mismatch
+    pub check_interval: Option<Duration>,
+}
+
+/// Size and hash of one guest artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ArtifactDigest {
+    /// `kernel` or `rootfs`
+    pub kind: String,
+    pub path: String,
+    pub size: u64,
+    /// SHA-256 of the file, as hex
+    pub sha256: String,
+}
+
+#[derive(Error, Debug)]
+pub enum IntegrityError {
+    #[error("cannot hash guest {kind} {}: {source}", path.display())]
+    Io {
+        kind: &'static str,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("guest {kind} {} has sha256 {actual}, expected {expected}", path.display())]
+    Mismatch {
+        kind: &'static str,
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+/// Size and SHA-256 of the file at `path`, read in chunks
+pub fn sha256_file(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_CHUNK_BYTES];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// Hash `artifacts` and check them against the expected hashes of `config`
+pub fn verify(
+    artifacts: &Artifacts,
+    config: &IntegrityConfig,
+) -> Result<Vec<ArtifactDigest>, IntegrityError> {
+    [
+        ("kernel", &artifacts.kernel, &config.kernel_sha256),
+        ("rootfs", &artifacts.rootfs, &config.rootfs_sha256),
+    ]
+    .into_iter()
+    .map(|(kind, path, expected)| {
+        let (size, sha256) = sha256_file(path).map_err(|source| IntegrityError::Io {
+            kind,
+            path: path.clone(),
+            source,
+        })?;
+        if let Some(expected) = expected
+            && !expected.trim().eq_ignore_ascii_case(&sha256)
+        {
+            return Err(IntegrityError::Mismatch {
+                kind,
+                path: path.clone(),
+                expected: expected.trim().to_ascii_lowercase(),
+                actual: sha256,
+            });
+        }
+        Ok(ArtifactDigest {
+            kind: kind.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            size,
+            sha256,
+        })
+    })
+    .collect()
+}
+
+/// Outcome of the latest artifact check, shared with the health and version endpoints
+#[derive(Debug, Default)]
+pub struct ArtifactIntegrity {
+    state: Mutex<(Vec<ArtifactDigest>, Option<String>)>,
+}
+
+impl ArtifactIntegrity {
+    /// Record the outcome of a check; a failed one keeps the digests of the last good one
+    pub fn record(&self, result: &Result<Vec<ArtifactDigest>, IntegrityError>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Ok(digests) => *state = (digests.clone(), None),
+            Err(e) => state.1 = Some(e.to_string()),
+        }
+    }
+
+    /// Digests of the artifacts as last verified
+    pub fn digests(&self) -> Vec<ArtifactDigest> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .0
+            .clone()
+    }
+
+    /// Why the latest check failed, if it did
+    pub fn problem(&self) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .1
+            .clone()
+    }
+}
+
+/// Re-verify `artifacts` every `interval`, recording each outcome in `integrity`
+pub fn spawn_checks(
+    artifacts: Artifacts,
+    config: IntegrityConfig,
+    interval: Duration,
+    integrity: Arc<ArtifactIntegrity>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        // The first tick is immediate, and startup has just verified them
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let (artifacts, config) = (artifacts.clone(), config.clone());
+            let Ok(result) = tokio::task::spawn_blocking(move || verify(&artifacts, &config)).await
+            else {
+                continue;
+            };
+            if let Err(e) = &result {
+                tracing::error!("Guest artifact check failed: {}", e);
+            }
+            integrity.record(&result);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artifacts(name: &str, rootfs: &[u8]) -> Artifacts {
+        let dir = std::env::temp_dir().join(format!("fc-integrity-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let kernel = dir.join("vmlinux");
+        std::fs::write(&kernel, b"kernel").unwrap();
+        let rootfs_path = dir.join("rootfs.ext4");
+        std::fs::write(&rootfs_path, rootfs).unwrap();
+        Artifacts {
+            kernel,
+            rootfs: rootfs_path,
+        }
+    }
+
+    #[test]
+    fn test_hashes_files_larger_than_a_chunk() {
+        let contents = vec![7u8; HASH_CHUNK_BYTES * 2 + 5];
+        let artifacts = artifacts("chunks", &contents);
+        let (size, sha256) = sha256_file(&artifacts.rootfs).unwrap();
+        assert_eq!(size, contents.len() as u64);
+        assert_eq!(sha256, hex::encode(Sha256::digest(&contents)));
+        let _ = std::fs::remove_dir_all(artifacts.rootfs.parent().unwrap());
+    }
+
+    #[test]
+    fn test_verify_against_expected_hashes() {
+        let artifacts = artifacts("verify", b"rootfs");
+        let digests = verify(&artifacts, &IntegrityConfig::default()).unwrap();
+        assert_eq!(digests[0].kind, "kernel");
+        assert_eq!(digests[1].size, 6);
+        let rootfs_sha256 = digests[1].sha256.clone();
+
+        // Expected hashes match regardless of case
+        let config = IntegrityConfig {
+            rootfs_sha256: Some(rootfs_sha256.to_ascii_uppercase()),
+            ..Default::default()
+        };
+        assert_eq!(verify(&artifacts, &config).unwrap(), digests);
+
+        // A truncated rootfs names the file and both hashes
+        std::fs::write(&artifacts.rootfs, b"root").unwrap();
+        let err = verify(&artifacts, &config).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("guest rootfs"), "{message}");
+        assert!(message.contains("rootfs.ext4"), "{message}");
+        assert!(message.contains(&rootfs_sha256), "{message}");
+        assert!(
+            message.contains(&hex::encode(Sha256::digest(b"root"))),
+            "{message}"
+        );
+
+        let integrity = ArtifactIntegrity::default();
+        integrity.record(&Ok(digests.clone()));
+        integrity.record(&Err(err));
+        assert_eq!(integrity.digests(), digests);
+        assert!(integrity.problem().unwrap().contains("expected"));
+        let _ = std::fs::remove_dir_all(artifacts.rootfs.parent().unwrap());
+    }
+}
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+/// API socket path Firecracker listens on inside the jail
+pub const JAIL_SOCKET_PATH: &str = "/run/firecracker.socket";
+
+/// Settings for running Firecracker under the upstream `jailer` binary
+#[derive(Debug, Clone, PartialEq)]
+pub struct JailerConfig {
+    /// Path of the `jailer` binary
+    pub jailer_bin: PathBuf,
+    /// Absolute path of the Firecracker binary the jailer execs
+    pub exec_file: PathBuf,
+    /// Unprivileged uid Firecracker runs as
+    pub uid: u32,
+    /// Unprivileged gid Firecracker runs as
+    pub gid: u32,
+    /// Directory under which per-VM chroots are created
+    pub chroot_base_dir: PathBuf,
+}
+
+impl Default for JailerConfig {
+    fn default() -> Self {
+        Self {
+            jailer_bin: PathBuf::from("jailer"),
+            exec_file: PathBuf::from("/usr/local/bin/firecracker"),
+            uid: 10000,
+            gid: 10000,
+            chroot_base_dir: PathBuf::from("/srv/jailer"),
+        }
+    }
+}
+
+/// Per-VM chroot laid out the way the jailer creates it:
+/// `<chroot_base_dir>/<exec_file_name>/<vm_id>/root`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jail {
+    vm_id: String,
+    config: JailerConfig,
+}
+
+impl Jail {
+    pub fn new(config: &JailerConfig, vm_id: &str) -> Self {
+        Self {
+            vm_id: vm_id.to_string(),
+            config: config.clone(),
+        }
+    }
+
+    pub fn config(&self) -> &JailerConfig {
+        &self.config
+    }
+
+    /// Per-VM directory removed on cleanup
+    pub fn vm_dir(&self) -> PathBuf {
+        let exec_name = self
+            .config
+            .exec_file
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_else(|| "firecracker".into());
+        self.config
+            .chroot_base_dir
+            .join(exec_name)
+            .join(&self.vm_id)
+    }
+
+    /// Directory Firecracker sees as `/`
+    pub fn root_dir(&self) -> PathBuf {
+        self.vm_dir().join("root")
+    }
+
+    /// Host path of a path as seen from inside the jail
+    pub fn host_path(&self, jail_path: &str) -> PathBuf {
+        self.root_dir().join(jail_path.trim_start_matches('/'))
+    }
+
+    /// Path as seen from inside the jail, or `None` if `host_path` lies outside it
+    pub fn jail_path(&self, host_path: &Path) -> Option<String> {
+        let relative = host_path.strip_prefix(self.root_dir()).ok()?;
+        Some(format!("/{}", relative.to_string_lossy()))
+    }
+
+    /// Make `source` available inside the jail as `/<name>`, hard-linking when possible.
+    /// Returns the in-jail path.
+    pub fn stage(&self, source: &Path, name: &str) -> std::io::Result<String> {
+        let target = self.host_path(name);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if std::fs::hard_link(source, &target).is_err() {
+            // Hard links can't cross filesystems
+            std::fs::copy(source, &target)?;
+        }
+        self.chown(&target)?;
+        Ok(format!("/{}", name.trim_start_matches('/')))
+    }
+
+    /// Create an empty file inside the jail that the jailed Firecracker can write to
+    pub fn create_file(&self, jail_path: &str) -> std::io::Result<PathBuf> {
+        let target = self.host_path(jail_path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::File::create(&target)?;
+        self.chown(&target)?;
+        Ok(target)
+    }
+
+    fn chown(&self, path: &Path) -> std::io::Result<()> {
+        std::os::unix::fs::chown(path, Some(self.config.uid), Some(self.config.gid))
+    }
+
+    /// Arguments for the jailer; everything after `--` is passed on to Firecracker
+    pub fn args(&self) -> Vec<String> {
+        vec![
+            "--id".to_string(),
+            self.vm_id.clone(),
+            "--exec-file".to_string(),
+            self.config.exec_file.to_string_lossy().into_owned(),
+            "--uid".to_string(),
+            self.config.uid.to_string(),
+            "--gid".to_string(),
+            self.config.gid.to_string(),
+            "--chroot-base-dir".to_string(),
+            self.config.chroot_base_dir.to_string_lossy().into_owned(),
+            "--".to_string(),
+            "--api-sock".to_string(),
+            JAIL_SOCKET_PATH.to_string(),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jail() -> Jail {
+        Jail::new(
+            &JailerConfig {
+                chroot_base_dir: PathBuf::from("/srv/jailer"),
+                exec_file: PathBuf::from("/usr/bin/firecracker"),
+                ..Default::default()
+            },
+            "vm-1234",
+        )
+    }
+
+    #[test]
+    fn test_chroot_layout() {
+        let jail = jail();
+        assert_eq!(
+            jail.vm_dir(),
+            PathBuf::from("/srv/jailer/firecracker/vm-1234")
+        );
+        assert_eq!(
+            jail.root_dir(),
+            PathBuf::from("/srv/jailer/firecracker/vm-1234/root")
+        );
+    }
+
+    #[test]
+    fn test_path_translation_round_trip() {
+        let jail = jail();
+        let host = jail.host_path(JAIL_SOCKET_PATH);
+        assert_eq!(
+            host,
+            PathBuf::from("/srv/jailer/firecracker/vm-1234/root/run/firecracker.socket")
+        );
+        assert_eq!(jail.jail_path(&host).unwrap(), JAIL_SOCKET_PATH);
+        assert_eq!(jail.host_path("fc.log"), jail.root_dir().join("fc.log"));
+        assert_eq!(jail.jail_path(Path::new("/tmp/fc.log")), None);
+        // A sibling VM's chroot is not inside this jail
+        assert_eq!(
+            jail.jail_path(Path::new("/srv/jailer/firecracker/vm-12345/root/fc.log")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_jailer_args() {
+        let args = jail().args();
+        assert_eq!(&args[..2], &["--id", "vm-1234"]);
+        assert!(
+            args.windows(2)
+                .any(|w| w == ["--exec-file", "/usr/bin/firecracker"])
+        );
+        assert!(args.windows(2).any(|w| w == ["--uid", "10000"]));
+        let separator = args.iter().position(|a| a == "--").unwrap();
+        assert_eq!(&args[separator + 1..], &["--api-sock", JAIL_SOCKET_PATH]);
+    }
+}
@@ -0,0 +1,705 @@
+use crate::artifacts::Offloader;
+use crate::config::Config;
+use crate::history::{ExecutionRecord, now_millis};
+use crate::service::ExecutionService;
+use crate::webhook::{self, CallbackDelivery, WebhookConfig};
+use crate::{ExecuteRequest, ExecuteResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+/// How long finished jobs stay queryable by default
+pub const DEFAULT_JOB_TTL: Duration = Duration::from_secs(3600);
+
+/// Workers each instance runs by default
+pub const DEFAULT_JOB_WORKERS: usize = 4;
+
+/// How long a job may stay claimed before it is handed to another worker, by default
+pub const DEFAULT_JOB_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Longest pause between two cleanup passes
+pub(crate) const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// The code ran; the result's `success` tells whether it exited cleanly
+    Completed,
+    /// The request was rejected or the host failed to run it
+    Failed,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobStatus::Completed | JobStatus::Failed)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Body of `POST /jobs`: an execute request plus where to report completion
+#[derive(Debug, Clone, Default, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct JobRequest {
+    #[serde(flatten)]
+    pub execute: ExecuteRequest,
+    /// URL the finished job document is POSTed to
+    #[serde(default)]
+    pub callback_url: Option<String>,
+}
+
+/// State of an asynchronous execution, as returned by `GET /jobs/{id}`
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    /// Submission time in milliseconds since the Unix epoch
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub result: Option<ExecuteResponse>,
+    /// Why the job failed without a result
+    pub error: Option<String>,
+    /// Stable code of `error`, e.g. `bad_request` or `interrupted`
+    #[serde(default)]
+    pub error_code: Option<String>,
+    /// While the job waits for an execution permit, its place among the waiting executions
+    #[serde(default)]
+    pub queue_position: Option<usize>,
+    /// Estimated wait for the permit in milliseconds, alongside `queue_position`
+    #[serde(default)]
+    pub queue_eta_ms: Option<u64>,
+    pub callback: Option<CallbackDelivery>,
+}
+
+impl Job {
+    pub fn new(id: impl Into<String>, callback_url: Option<String>) -> Self {
+        Self {
+            id: id.into(),
+            status: JobStatus::Queued,
+            created_at: now_millis(),
+            finished_at: None,
+            result: None,
+            error: None,
+            error_code: None,
+            queue_position: None,
+            queue_eta_ms: None,
+            callback: callback_url.map(CallbackDelivery::pending),
+        }
+    }
+
+    /// Fail a job that was cut short by a restart
+    pub(crate) fn interrupt(&mut self, now: u64) {
+        self.status = JobStatus::Failed;
+        self.finished_at = Some(now);
+        self.error = Some("The server stopped before the job finished".to_string());
+        self.error_code = Some(INTERRUPTED_ERROR_CODE.to_string());
+    }
+}
+
+/// `error_code` of jobs that were queued or running when the server stopped
+pub const INTERRUPTED_ERROR_CODE: &str = "interrupted";
+
+/// Why a job store operation failed
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("Job store error: {0}")]
+    Backend(String),
+}
+
+/// Storage of jobs and the execution history: in memory, in SQLite (`persistence` feature) or
+/// in Redis (`distributed` feature)
+pub trait JobStore: Send + Sync + std::fmt::Debug {
+    /// Insert or replace a job
+    fn put(&self, job: &Job) -> Result<(), StoreError>;
+    fn get(&self, id: &str) -> Result<Option<Job>, StoreError>;
+    /// Fail every job a previous process left queued or running with `INTERRUPTED_ERROR_CODE`,
+    /// returning how many there were
+    fn fail_interrupted(&self) -> Result<usize, StoreError>;
+    /// Delete jobs that finished, and execution records that started, before `cutoff`
+    /// (milliseconds since the Unix epoch), returning how many rows went
+    fn remove_expired(&self, cutoff: u64) -> Result<usize, StoreError>;
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<(), StoreError>;
+    /// Up to `limit` most recent execution records, newest first
+    fn recent_executions(&self, limit: usize) -> Result<Vec<ExecutionRecord>, StoreError>;
+    /// Note that `tenant` started an execution counted against its quota at `at`, dropping
+    /// its uses from before `expired_before`. Stores that don't outlive the process keep none.
+    fn record_quota_use(
+        &self,
+        _tenant: &str,
+        _at: u64,
+        _expired_before: u64,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+    /// Every quota use recorded at or after `since`, oldest first
+    fn quota_uses(&self, _since: u64) -> Result<Vec<(String, u64)>, StoreError> {
+        Ok(Vec::new())
+    }
+}
+
+impl dyn JobStore + '_ {
+    /// Apply `update` to the job, returning its new state
+    pub fn update(
+        &self,
+        id: &str,
+        update: impl FnOnce(&mut Job),
+    ) -> Result<Option<Job>, StoreError> {
+        let Some(mut job) = self.get(id)? else {
+            return Ok(None);
+        };
+        update(&mut job);
+        self.put(&job)?;
+        Ok(Some(job))
+    }
+}
+
+/// Jobs and execution records held in memory, lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    executions: Mutex<VecDeque<ExecutionRecord>>,
+}
+
+impl MemoryJobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl JobStore for MemoryJobStore {
+    fn put(&self, job: &Job) -> Result<(), StoreError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.insert(job.id.clone(), job.clone());
+        Ok(())
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Job>, StoreError> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(jobs.get(id).cloned())
+    }
+
+    fn fail_interrupted(&self) -> Result<usize, StoreError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let now = now_millis();
+        let interrupted = jobs
+            .values_mut()
+            .filter(|job| !job.status.is_finished())
+            .map(|job| job.interrupt(now))
+            .count();
+        Ok(interrupted)
+    }
+
+    fn remove_expired(&self, cutoff: u64) -> Result<usize, StoreError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let before = jobs.len();
+        jobs.retain(|_, job| job.finished_at.is_none_or(|finished| finished >= cutoff));
+        let mut removed = before - jobs.len();
+
+        let mut executions = self.executions.lock().unwrap_or_else(|e| e.into_inner());
+        let before = executions.len();
+        executions.retain(|record| record.started_at >= cutoff);
+        removed += before - executions.len();
+        Ok(removed)
+    }
+
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<(), StoreError> {
+        let mut executions = self.executions.lock().unwrap_or_else(|e| e.into_inner());
+        executions.push_back(record.clone());
+        Ok(())
+    }
+
+    fn recent_executions(&self, limit: usize) -> Result<Vec<ExecutionRecord>, StoreError> {
+        let executions = self.executions.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(executions.iter().rev().take(limit).cloned().collect())
+    }
+}
+
+/// The store selected by the configuration: Redis at `redis_url`, SQLite at `db_path`, else
+/// memory
+pub fn open_store(config: &Config) -> Result<Arc<dyn JobStore>, StoreError> {
+    #[cfg(feature = "distributed")]
+    if let Some(url) = &config.redis_url {
+        return Ok(Arc::new(crate::distributed::RedisJobStore::connect(
+            url,
+            crate::distributed::KEY_PREFIX,
+            config.job_ttl,
+        )?));
+    }
+    match &config.db_path {
+        #[cfg(feature = "persistence")]
+        Some(path) => Ok(Arc::new(crate::persistence::SqliteJobStore::open(
+            path,
+            config.db_max_output_bytes,
+        )?)),
+        #[cfg(not(feature = "persistence"))]
+        Some(_) => Err(StoreError::Backend(
+            "FC_DB_PATH requires the persistence feature".to_string(),
+        )),
+        None => Ok(Arc::new(MemoryJobStore::new())),
+    }
+}
+
+/// Periodically delete jobs and execution records older than `ttl`
+pub fn spawn_cleanup(store: Arc<dyn JobStore>, ttl: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ttl.min(CLEANUP_INTERVAL));
+        loop {
+            interval.tick().await;
+            crate::debug_state::heartbeat("job_cleanup");
+            let cutoff = now_millis().saturating_sub(ttl.as_millis() as u64);
+            match store.remove_expired(cutoff) {
+                Ok(0) => {}
+                Ok(removed) => tracing::debug!("Removed {} expired job store rows", removed),
+                Err(e) => tracing::warn!("Job store cleanup failed: {}", e),
+            }
+        }
+    })
+}
+
+/// A job waiting in a `JobQueue`, with what is needed to run it on any instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedJob {
+    pub id: String,
+    pub request: ExecuteRequest,
+    /// Identifier of the submitting API key, for screening bypasses
+    pub key_id: Option<String>,
+    /// How many times the job has been claimed, counting the current claim
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+/// Queue of jobs waiting for a worker, in memory or in Redis (`distributed` feature). A
+/// claimed job stays with the queue until it is acked, so one whose worker dies goes back
+/// to the queue once `reclaim` finds its claim older than the visibility timeout.
+#[async_trait::async_trait]
+pub trait JobQueue: Send + Sync + std::fmt::Debug {
+    async fn push(&self, job: QueuedJob) -> Result<(), StoreError>;
+    /// Claim the next job for `worker`, waiting up to `wait` for one to arrive
+    async fn pop(&self, worker: &str, wait: Duration) -> Result<Option<QueuedJob>, StoreError>;
+    /// Drop a claimed job once it finished
+    async fn ack(&self, id: &str) -> Result<(), StoreError>;
+    /// Put jobs claimed more than `visibility` ago back at the head of the queue, returning
+    /// how many there were
+    async fn reclaim(&self, visibility: Duration) -> Result<usize, StoreError>;
+}
+
+/// Jobs queued in memory, claimable only by this process's workers
+#[derive(Debug, Default)]
+pub struct MemoryJobQueue {
+    state: Mutex<QueueState>,
+    arrived: tokio::sync::Notify,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    pending: VecDeque<QueuedJob>,
+    claimed: HashMap<String, (QueuedJob, std::time::Instant)>,
+}
+
+impl MemoryJobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn claim_next(&self) -> Option<QueuedJob> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut job = state.pending.pop_front()?;
+        job.attempts += 1;
+        state
+            .claimed
+            .insert(job.id.clone(), (job.clone(), std::time::Instant::now()));
+        Some(job)
+    }
+}
+
+#[async_trait::async_trait]
+impl JobQueue for MemoryJobQueue {
+    async fn push(&self, job: QueuedJob) -> Result<(), StoreError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.pending.push_back(job);
+        self.arrived.notify_one();
+        Ok(())
+    }
+
+    async fn pop(&self, _worker: &str, wait: Duration) -> Result<Option<QueuedJob>, StoreError> {
+        let deadline = tokio::time::Instant::now() + wait;
+        loop {
+            let arrived = self.arrived.notified();
+            if let Some(job) = self.claim_next() {
+                return Ok(Some(job));
+            }
+            if tokio::time::timeout_at(deadline, arrived).await.is_err() {
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn ack(&self, id: &str) -> Result<(), StoreError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.claimed.remove(id);
+        Ok(())
+    }
+
+    async fn reclaim(&self, visibility: Duration) -> Result<usize, StoreError> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let expired: Vec<String> = state
+            .claimed
+            .iter()
+            .filter(|(_, (_, claimed_at))| claimed_at.elapsed() > visibility)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            if let Some((job, _)) = state.claimed.remove(id) {
+                state.pending.push_front(job);
+                self.arrived.notify_one();
+            }
+        }
+        Ok(expired.len())
+    }
+}
+
+/// The queue selected by the configuration: Redis at `redis_url`, else memory
+pub async fn open_queue(config: &Config) -> Result<Arc<dyn JobQueue>, StoreError> {
+    match &config.redis_url {
+        #[cfg(feature = "distributed")]
+        Some(url) => Ok(Arc::new(
+            crate::distributed::RedisJobQueue::connect(url, crate::distributed::KEY_PREFIX).await?,
+        )),
+        #[cfg(not(feature = "distributed"))]
+        Some(_) => Err(StoreError::Backend(
+            "FC_REDIS_URL requires the distributed feature".to_string(),
+        )),
+        None => Ok(Arc::new(MemoryJobQueue::new())),
+    }
+}
+
+/// How long an idle worker blocks on the queue before polling again
+const POP_WAIT: Duration = Duration::from_secs(5);
+
+/// Pause after a failed queue operation before a worker retries
+const QUEUE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Claims after which a job whose workers keep dying is failed rather than retried
+pub const MAX_JOB_ATTEMPTS: u32 = 3;
+
+/// `error_code` of jobs given up after `MAX_JOB_ATTEMPTS` claims
+pub const WORKER_LOST_ERROR_CODE: &str = "worker_lost";
+
+/// What workers need to run jobs popped from `queue`
+#[derive(Clone)]
+pub struct JobWorkers {
+    pub service: ExecutionService,
+    pub store: Arc<dyn JobStore>,
+    pub queue: Arc<dyn JobQueue>,
+    /// Destination of oversized output, when configured
+    pub offloader: Option<Offloader>,
+    pub webhook: WebhookConfig,
+}
+
+impl JobWorkers {
+    /// Start `count` workers on the local VM pool, and a task returning the jobs of dead
+    /// workers (on any instance) to the queue once their claim is older than `visibility`
+    pub fn spawn(self, count: usize, visibility: Duration) {
+        let instance = crate::generate_request_id();
+        for index in 0..count {
+            let workers = self.clone();
+            let name = format!("{instance}-{index}");
+            tokio::spawn(async move { workers.work(&name).await });
+        }
+        let queue = self.queue;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(visibility.min(CLEANUP_INTERVAL));
+            loop {
+                interval.tick().await;
+                match queue.reclaim(visibility).await {
+                    Ok(0) => {}
+                    Ok(reclaimed) => tracing::warn!(
+                        "Requeued {} jobs whose worker stopped responding",
+                        reclaimed
+                    ),
+                    Err(e) => tracing::warn!("Job reclaim failed: {}", e),
+                }
+            }
+        });
+    }
+
+    async fn work(&self, name: &str) {
+        loop {
+            match self.queue.pop(name, POP_WAIT).await {
+                Ok(Some(job)) => {
+                    let id = job.id.clone();
+                    self.run(job).await;
+                    if let Err(e) = self.queue.ack(&id).await {
+                        tracing::error!("Failed to ack job {}: {}", id, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("Worker {} failed to pop a job: {}", name, e);
+                    tokio::time::sleep(QUEUE_RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+
+    fn update(&self, id: &str, update: &dyn Fn(&mut Job)) -> Option<Job> {
+        match self.store.update(id, update) {
+            Ok(job) => job,
+            Err(e) => {
+                tracing::error!("Failed to update job {}: {}", id, e);
+                None
+            }
+        }
+    }
+
+    /// Run a claimed job and record its outcome, then deliver its callback
+    async fn run(&self, queued: QueuedJob) {
+        let id = queued.id;
+        let finished = if queued.attempts > MAX_JOB_ATTEMPTS {
+            tracing::error!("Giving up on job {} after {} claims", id, MAX_JOB_ATTEMPTS);
+            self.update(&id, &|job| {
+                job.status = JobStatus::Failed;
+                job.finished_at = Some(now_millis());
+                job.error = Some(format!(
+                    "The job's worker stopped responding {MAX_JOB_ATTEMPTS} times"
+                ));
+                job.error_code = Some(WORKER_LOST_ERROR_CODE.to_string());
+            })
+        } else {
+            self.update(&id, &|job| job.status = JobStatus::Running);
+            let (queue_updates, mut places) = tokio::sync::mpsc::unbounded_channel();
+            let execution = self.service.execute_with_queue_updates(
+                queued.request,
+                id.clone(),
+                queued.key_id.as_deref(),
+                Some(queue_updates),
+            );
+            // Ends once the execution drops its sender
+            let track_queue = async {
+                while let Some(place) = places.recv().await {
+                    let waiting = place.position > 0;
+                    self.update(&id, &|job| {
+                        job.queue_position = waiting.then_some(place.position);
+                        job.queue_eta_ms = place.eta_ms.filter(|_| waiting);
+                    });
+                }
+            };
+            let (mut outcome, ()) = tokio::join!(execution, track_queue);
+            if let (Some(offloader), Ok(response)) = (&self.offloader, &mut outcome) {
+                offloader.offload(&id, response).await;
+            }
+            let finished_at = now_millis();
+            self.update(&id, &|job| {
+                job.finished_at = Some(finished_at);
+                match &outcome {
+                    Ok(response) => {
+                        job.status = JobStatus::Completed;
+                        job.result = Some(response.clone());
+                    }
+                    Err(rejection) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(rejection.to_string());
+                        job.error_code = Some(rejection.code().to_string());
+                    }
+                }
+            })
+        };
+
+        let Some(job) = finished else {
+            return;
+        };
+        let Some(delivery) = job.callback.clone() else {
+            return;
+        };
+        let body = serde_json::to_vec(&job).expect("jobs serialize");
+        webhook::deliver(&self.webhook, delivery, body, |delivery| {
+            self.update(&id, &|job| job.callback = Some(delivery.clone()));
+        })
+        .await;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn record(request_id: &str, started_at: u64) -> ExecutionRecord {
+        ExecutionRecord {
+            request_id: request_id.to_string(),
+            vm_id: Some("vm-1".to_string()),
+            started_at,
+            duration_ms: 5,
+            success: true,
+            error_code: None,
+            code_sha256: crate::history::code_sha256("print(1)"),
+            stdout_len: 2,
+            stderr_len: 0,
+        }
+    }
+
+    /// Behaviour every `JobStore` implementation must share
+    pub(crate) fn check_store_contract(store: &dyn JobStore) {
+        assert!(store.get("missing").unwrap().is_none());
+
+        let mut job = Job::new("job-1", Some("https://example.com/cb".to_string()));
+        store.put(&job).unwrap();
+        assert_eq!(
+            store.get("job-1").unwrap().unwrap().status,
+            JobStatus::Queued
+        );
+
+        let updated = store
+            .update("job-1", |job| {
+                job.status = JobStatus::Completed;
+                job.finished_at = Some(1_000);
+                job.result = Some(crate::ExecuteResponse {
+                    stdout: "1\n".to_string(),
+                    success: true,
+                    ..Default::default()
+                });
+            })
+            .unwrap()
+            .unwrap();
+        let stored = store.get("job-1").unwrap().unwrap();
+        assert_eq!(stored.status, JobStatus::Completed);
+        assert_eq!(stored.result.unwrap().stdout, "1\n");
+        assert_eq!(stored.callback, updated.callback);
+        assert!(store.update("missing", |_| {}).unwrap().is_none());
+
+        job.id = "job-2".to_string();
+        job.status = JobStatus::Running;
+        store.put(&job).unwrap();
+
+        for (id, started_at) in [("req-1", 500), ("req-2", 1_500), ("req-3", 2_500)] {
+            store.record_execution(&record(id, started_at)).unwrap();
+        }
+        let recent = store.recent_executions(2).unwrap();
+        assert_eq!(recent, vec![record("req-3", 2_500), record("req-2", 1_500)]);
+
+        // Expiry drops old finished jobs and records; job-2 is still running
+        assert_eq!(store.remove_expired(1_200).unwrap(), 2);
+        assert!(store.get("job-1").unwrap().is_none());
+        assert!(store.get("job-2").unwrap().is_some());
+        assert_eq!(store.recent_executions(10).unwrap().len(), 2);
+        let mut queued = Job::new("job-3", None);
+        queued.created_at = 0;
+        store.put(&queued).unwrap();
+        store.remove_expired(u64::MAX).unwrap();
+        assert!(
+            store.get("job-3").unwrap().is_some(),
+            "unfinished jobs never expire"
+        );
+    }
+
+    /// Startup behaviour of the stores owned by a single instance
+    pub(crate) fn check_interrupted_jobs_fail(store: &dyn JobStore) {
+        let mut running = Job::new("running", None);
+        running.status = JobStatus::Running;
+        store.put(&running).unwrap();
+        let mut done = Job::new("done", None);
+        done.status = JobStatus::Completed;
+        done.finished_at = Some(1_000);
+        store.put(&done).unwrap();
+
+        // Unfinished jobs are failed by the startup pass, finished ones are left alone
+        assert_eq!(store.fail_interrupted().unwrap(), 1);
+        let interrupted = store.get("running").unwrap().unwrap();
+        assert_eq!(interrupted.status, JobStatus::Failed);
+        assert!(interrupted.finished_at.is_some());
+        assert_eq!(
+            interrupted.error_code.as_deref(),
+            Some(INTERRUPTED_ERROR_CODE)
+        );
+        assert_eq!(
+            store.get("done").unwrap().unwrap().status,
+            JobStatus::Completed
+        );
+    }
+
+    fn queued(id: &str) -> QueuedJob {
+        QueuedJob {
+            id: id.to_string(),
+            request: ExecuteRequest {
+                code: Some(format!("print('{id}')")),
+                ..Default::default()
+            },
+            key_id: Some("key-1".to_string()),
+            attempts: 0,
+        }
+    }
+
+    /// Behaviour every `JobQueue` implementation must share
+    pub(crate) async fn check_queue_contract(queue: &dyn JobQueue) {
+        let short = Duration::from_millis(50);
+        assert!(queue.pop("worker-1", short).await.unwrap().is_none());
+
+        // Jobs come out in submission order, with their request, once per claim
+        queue.push(queued("job-1")).await.unwrap();
+        queue.push(queued("job-2")).await.unwrap();
+        let first = queue.pop("worker-1", short).await.unwrap().unwrap();
+        assert_eq!(first.id, "job-1");
+        assert_eq!(first.request.code.as_deref(), Some("print('job-1')"));
+        assert_eq!(first.key_id.as_deref(), Some("key-1"));
+        assert_eq!(first.attempts, 1);
+        let second = queue.pop("worker-2", short).await.unwrap().unwrap();
+        assert_eq!(second.id, "job-2");
+        assert!(queue.pop("worker-1", short).await.unwrap().is_none());
+
+        // A fresh claim is kept; an acked job is gone for good
+        assert_eq!(queue.reclaim(Duration::from_secs(60)).await.unwrap(), 0);
+        queue.ack("job-1").await.unwrap();
+
+        // The unacked job goes back to the queue once its claim is older than the timeout
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.reclaim(Duration::ZERO).await.unwrap(), 1);
+        let retried = queue.pop("worker-1", short).await.unwrap().unwrap();
+        assert_eq!(retried.id, "job-2");
+        assert_eq!(retried.attempts, 2);
+        queue.ack("job-2").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.reclaim(Duration::ZERO).await.unwrap(), 0);
+
+        // A waiting worker picks up a job pushed while it waits
+        let (popped, ()) = tokio::join!(queue.pop("worker-1", Duration::from_secs(2)), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            queue.push(queued("job-3")).await.unwrap();
+        });
+        assert_eq!(popped.unwrap().unwrap().id, "job-3");
+        queue.ack("job-3").await.unwrap();
+    }
+
+    #[test]
+    fn test_memory_store_contract() {
+        check_store_contract(&MemoryJobStore::new());
+        check_interrupted_jobs_fail(&MemoryJobStore::new());
+    }
+
+    #[tokio::test]
+    async fn test_memory_queue_contract() {
+        check_queue_contract(&MemoryJobQueue::new()).await;
+    }
+
+    #[test]
+    fn test_job_request_flattens_execute_fields() {
+        let request: JobRequest = serde_json::from_str(
+            r#"{"code": "print(1)", "deterministic": true, "callback_url": "https://example.com/cb"}"#,
+        )
+        .unwrap();
+        assert_eq!(request.execute.code.as_deref(), Some("print(1)"));
+        assert!(request.execute.deterministic);
+        assert_eq!(
+            request.callback_url.as_deref(),
+            Some("https://example.com/cb")
+        );
+    }
+}
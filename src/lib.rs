@@ -1,22 +1,198 @@
 use axum::{http::StatusCode, response::IntoResponse, response::Json};
+use output::OutputEncoding;
+use program::{ExecMode, SourceFile};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::error;
+use utoipa::ToSchema;
 
+pub mod admission;
+pub mod arch;
+pub mod artifacts;
+pub mod audit;
+pub mod auth;
+pub mod autoscale;
+pub mod backend;
+pub mod balloon;
+pub mod boot_report;
+pub mod breaker;
+pub mod cache;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clock;
+pub mod config;
+pub mod console;
+pub mod cors;
+pub mod deadline;
+pub mod debug_events;
+pub mod debug_state;
+pub mod deps;
+pub mod determinism;
+pub mod dispatch;
+#[cfg(feature = "distributed")]
+pub mod distributed;
+pub mod entropy;
+pub mod events;
+pub mod executor;
+pub mod fc_metrics;
+pub mod fd_limit;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod guest_client;
+pub mod guest_network;
+pub mod guest_protocol;
+pub mod guest_response;
+pub mod history;
+pub mod idempotency;
+pub mod images;
+pub mod inputs;
+pub mod integrity;
+pub mod jailer;
+pub mod jobs;
+pub mod listen;
+#[cfg(feature = "client")]
+pub mod loadtest;
+pub mod machine;
+pub mod oom;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod output;
+pub mod payload;
+#[cfg(feature = "persistence")]
+pub mod persistence;
+pub mod port_forward;
+pub mod privilege;
+pub mod probe;
+pub mod process_usage;
+pub mod program;
+pub mod quota;
+pub mod rate_limit;
+pub mod readiness;
+pub mod reference_data;
+pub mod reload;
+pub mod replay;
+pub mod rootfs;
 pub mod runner;
+pub mod runtime_gc;
+pub mod sandbox;
+pub mod screening;
+pub mod selftest;
+pub mod service;
+pub mod shaping;
+pub mod shutdown;
+pub mod statsd;
+pub mod sub_pool;
+pub mod systemd;
+pub mod tap;
+pub mod telemetry;
+pub mod validation;
+pub mod version;
+pub mod vm_config;
+pub mod warmup;
+pub mod warning;
+pub mod webhook;
+pub mod workload;
 
 // Re-export the main function for easy access
 pub use runner::run_in_vm;
 
 /// Request body for code execution
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct ExecuteRequest {
-    /// Python code to execute in the microVM
+    /// Python code to execute in the microVM; the alternative to `files`
+    #[serde(default)]
+    pub code: Option<String>,
+    /// Multi-file program written into the working directory
+    #[serde(default)]
+    pub files: Option<Vec<SourceFile>>,
+    /// Path of the file in `files` to run
+    #[serde(default)]
+    pub entrypoint: Option<String>,
+    /// Packages to `pip install` before running; needs network access
+    #[serde(default)]
+    pub requirements: Vec<String>,
+    /// Files the guest downloads under `FC_INPUTS_DIR` before running the code; needs network
+    /// access and boots a VM for this request alone
+    #[serde(default)]
+    pub inputs: Option<Vec<inputs::RemoteInput>>,
+    /// `eval` also returns the value of the code's final expression; defaults to `script`
+    #[serde(default)]
+    pub mode: Option<ExecMode>,
+    /// Pre-built dependency image to attach, by profile name
+    #[serde(default)]
+    pub deps_profile: Option<String>,
+    /// Rootfs image to boot instead of the default one, by name (see `import-image`)
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Name servers the guest resolves with instead of the server's; needs network access and
+    /// boots a VM for this request alone
+    #[serde(default)]
+    pub dns: Option<Vec<String>>,
+    /// `tc` shaping of the VM's TAP device instead of the image's; only for API keys the
+    /// server allows it, and boots a VM for this request alone
+    #[serde(default)]
+    pub traffic_shape: Option<shaping::TrafficShape>,
+    /// Guest port, 1024 or above, to forward a host port to once the code has run, for a
+    /// server it left running; boots a VM for this request alone, kept until the port closes
+    #[serde(default)]
+    pub expose_port: Option<u16>,
+    /// Run reproducibly on a fresh VM with a fixed hash seed, time zone and locale
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Unix timestamp the guest clock is set to; requires `deterministic`
+    #[serde(default)]
+    pub fake_time: Option<i64>,
+    /// Serve and store the result in the result cache; defaults to the server setting
+    #[serde(default)]
+    pub cache: Option<bool>,
+    /// Skip the cache lookup and execute anyway, refreshing the cached result
+    #[serde(default)]
+    pub cache_bypass: bool,
+    /// Per-stream output cap; may lower but never raise the server limit
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+    /// Prefer the pooled VM that last ran a request with this key, whose caches and imports
+    /// are still warm; best-effort, any VM may serve it
+    #[serde(default)]
+    pub affinity_key: Option<String>,
+    /// Place in the queue for a VM when executions are limited; defaults to `normal`
+    #[serde(default)]
+    pub priority: Option<dispatch::Priority>,
+    /// Boot a fresh VM and keep its logs if the boot fails, served at `/vms/{id}/boot-report`
+    #[serde(default)]
+    pub debug_boot: bool,
+    /// Attach the server's read-only reference data drives; `false` boots a VM for this request
+    /// alone without them. Defaults to `true`
+    #[serde(default)]
+    pub mount_reference_data: Option<bool>,
+    /// Return the kernel console output printed while the code ran, for crashes that leave
+    /// stderr empty; the server must allow it, and a VM is booted for this request alone
+    #[serde(default)]
+    pub capture_console: Option<bool>,
+    /// Return a log of the execution's steps in `events`, for diagnosing where time went; the
+    /// server must allow it for the API key
+    #[serde(default)]
+    pub debug: Option<bool>,
+    /// Keep the VM this long after the execution as a sandbox for follow-up executions at
+    /// `/sandboxes/{id}/execute`, up to the server's limit; boots a VM for this request alone
+    #[serde(default)]
+    pub keep_alive_seconds: Option<u32>,
+}
+
+/// Most code a `/execute/quick` request takes, in bytes
+pub const QUICK_MAX_CODE_BYTES: usize = 1024;
+
+/// Request body of `/execute/quick`: a snippet and nothing else
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct QuickExecuteRequest {
+    /// Python code to execute, at most `QUICK_MAX_CODE_BYTES` bytes
     pub code: String,
 }
 
 /// Response structure for code execution results
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, ToSchema)]
 pub struct ExecuteResponse {
     /// Standard output from the Python code execution
     pub stdout: String,
@@ -24,9 +200,202 @@ pub struct ExecuteResponse {
     pub stderr: String,
     /// Whether the execution was successful
     pub success: bool,
+    /// Whether stdout was cut at the output limit
+    pub stdout_truncated: bool,
+    /// Whether stderr was cut at the output limit
+    pub stderr_truncated: bool,
+    /// `base64` when stdout wasn't valid UTF-8; omitted for plain text
+    #[serde(default, skip_serializing_if = "OutputEncoding::is_utf8")]
+    pub stdout_encoding: OutputEncoding,
+    /// `base64` when stderr wasn't valid UTF-8; omitted for plain text
+    #[serde(default, skip_serializing_if = "OutputEncoding::is_utf8")]
+    pub stderr_encoding: OutputEncoding,
+    /// Value of the final expression in `eval` mode, when it is JSON-serializable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<serde_json::Value>,
+    /// `repr()` of the final expression's value when it isn't JSON-serializable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_repr: Option<String>,
+    /// Whether the response was served from the result cache
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// Whether the guest's OOM killer terminated the code; `stderr` then names the VM's memory
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub oom_killed: bool,
+    /// Exception raised by the code, when the guest agent reports one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exception: Option<ExceptionInfo>,
+    /// Resources the code consumed, when the guest agent reports them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ExecutionUsage>,
+    /// Output of installing `requirements`, present only when some were requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_stdout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_stderr: Option<String>,
+    /// The input that couldn't be downloaded; the code then didn't run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setup_error: Option<inputs::SetupError>,
+    /// Whether the run used deterministic mode; echoed with its settings for auditing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deterministic: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_seed: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fake_time: Option<i64>,
+    /// Output streams stored outside the response because they exceeded the artifact
+    /// threshold; the stream's field is then empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<artifacts::Artifact>,
+    /// Host port forwarding to the guest's `expose_port`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposed_port: Option<u16>,
+    /// When the exposed port closes and its VM is discarded, in milliseconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposed_until_ms: Option<u64>,
+    /// Kernel console output printed while the code ran, present only when `capture_console`
+    /// was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub console: Option<String>,
+    /// Whether console output is missing: cut at the server's cap or lost to log rotation
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub console_truncated: bool,
+    /// ID the host gave the execution, naming its workspace in the guest; absent for cached
+    /// results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec_id: Option<String>,
+    /// Steps of the execution with their timings, present only when `debug` was requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<debug_events::DebugEvent>>,
+    /// Sandbox keeping the VM for follow-up executions, present when `keep_alive_seconds` was
+    /// requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox_id: Option<String>,
+    /// When the sandbox expires and its VM is discarded, in milliseconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox_expires_at_ms: Option<u64>,
+    /// Soft conditions the execution ran into, such as a cold start; always present, empty
+    /// when there were none
+    #[serde(default)]
+    pub warnings: Vec<warning::Warning>,
+    /// VM the code ran on; sent as the `x-vm-id` header rather than in the body
+    #[serde(skip)]
+    pub vm_id: Option<String>,
+}
+
+/// Resource usage of one execution, as measured inside the guest
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+pub struct ExecutionUsage {
+    /// User plus system CPU time
+    pub cpu_time_ms: u64,
+    /// Peak resident set size
+    pub max_rss_kb: u64,
+    pub wall_ms: u64,
+}
+
+impl ExecutionUsage {
+    /// Parse the agent's `usage` field; absent or malformed values yield `None`
+    pub fn from_agent(value: &serde_json::Value) -> Option<Self> {
+        if value.is_null() {
+            return None;
+        }
+        serde_json::from_value(value.clone())
+            .map_err(|e| tracing::debug!("Ignoring malformed usage from agent: {}", e))
+            .ok()
+    }
+
+    /// Add this execution to the usage histograms
+    pub fn record(&self) {
+        telemetry::observe_histogram("fc_execution_cpu_time_ms", &[], self.cpu_time_ms as f64);
+        telemetry::observe_histogram("fc_execution_wall_ms", &[], self.wall_ms as f64);
+        // MiB keeps typical sizes within the default bucket range
+        telemetry::observe_histogram(
+            "fc_execution_max_rss_mib",
+            &[],
+            self.max_rss_kb as f64 / 1024.0,
+        );
+    }
+}
+
+/// Uncaught Python exception reported by the guest agent
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct ExceptionInfo {
+    /// Exception class name, e.g. `ZeroDivisionError`
+    #[serde(rename = "type")]
+    pub exception_type: String,
+    pub message: String,
+    /// Frames from outermost to innermost, as in a printed traceback
+    #[serde(default)]
+    pub traceback: Vec<TracebackFrame>,
+    /// Exception this one was raised from or while handling
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(no_recursion)]
+    pub cause: Option<Box<ExceptionInfo>>,
+}
+
+/// One frame of a Python traceback
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct TracebackFrame {
+    pub filename: String,
+    pub line: u32,
+    pub function: String,
+}
+
+impl ExceptionInfo {
+    /// Parse the agent's `exception` field; absent or malformed values yield `None`
+    pub fn from_agent(value: &serde_json::Value) -> Option<Self> {
+        if value.is_null() {
+            return None;
+        }
+        serde_json::from_value(value.clone())
+            .map_err(|e| tracing::debug!("Ignoring malformed exception from agent: {}", e))
+            .ok()
+    }
+}
+
+/// Structured error envelope returned by every endpoint
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct ErrorResponse {
+    /// Human-readable error message
+    pub error: String,
+    /// Stable machine-readable error code
+    pub code: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: &str, message: impl Into<String>) -> Self {
+        Self {
+            error: message.into(),
+            code: code.to_string(),
+        }
+    }
+}
+
+/// Body of `GET /health`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, ToSchema)]
+pub struct HealthResponse {
+    pub status: String,
+    /// Release of the Firecracker binary VMs run on, when it could be detected
+    #[schema(value_type = Option<String>, example = "1.7.0")]
+    pub firecracker_version: Option<version::FirecrackerVersion>,
+    /// Why the service is unhealthy, such as a guest artifact that changed on disk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Circuit breaker around VM creation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vm_creation: Option<breaker::BreakerStatus>,
+    /// How quickly a request could be served right now, with the inputs it's scored on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub readiness: Option<readiness::Readiness>,
+    /// How VM networking is set up, probed at startup; `unavailable` fails every boot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network_privilege: Option<privilege::NetworkPrivilege>,
+    /// Latest canary run, when the startup self-test is on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selftest: Option<selftest::SelfTestReport>,
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
 pub enum ExecutionError {
     /// Error communicating with Firecracker API
     #[error("API communication error: {0}")]
@@ -45,22 +414,145 @@ pub enum ExecutionError {
     /// Error spawning a process
     #[error("Process spawning error: {0}")]
     ProcessSpawnError(String),
+    /// The host is over its VM or memory budget
+    #[error("Host resources exhausted: {0}")]
+    ResourceExhausted(String),
+    /// The executor is shutting down and takes no new executions
+    #[error("Executor is shutting down")]
+    ShuttingDown,
+    /// Too many executions are waiting for a VM, or this one was shed for a more urgent one
+    #[error("Too many queued executions: {0}")]
+    Overloaded(String),
+    /// VM creation keeps failing, so cold starts are refused until a probe boot succeeds
+    #[error("VM creation unavailable: {0}")]
+    VmCreationUnavailable(String),
+    /// No execution permit came free within the wait budget
+    #[error(
+        "No VM became available within {waited_ms}ms; {queue_depth} executions are still waiting"
+    )]
+    PoolExhausted { waited_ms: u64, queue_depth: usize },
+    /// The guest ran out of memory and the OOM killer took the agent down with the code
+    #[error(
+        "The guest ran out of memory: the OOM killer terminated {process} (the VM has {mem_size_mib} MiB)"
+    )]
+    GuestOutOfMemory { process: String, mem_size_mib: u64 },
+    /// The request needs a feature the VM's guest agent is too old to provide
+    #[error(
+        "The VM's guest agent speaks protocol v{agent}, but {feature} needs v{required}; rebuild the rootfs with a newer agent"
+    )]
+    GuestProtocolError {
+        feature: String,
+        required: u32,
+        agent: u32,
+    },
+    /// A quick execution found no warm VM in the pool, and quick executions never boot one
+    #[error("No warm VM is available for a quick execution")]
+    NoWarmVm,
+    /// A quick execution didn't finish within its timeout
+    #[error("Quick execution did not finish within {timeout_ms}ms")]
+    QuickTimeout { timeout_ms: u64 },
+    /// Too little of the request's deadline was left to start or finish `phase`
+    #[error("Not enough of the request's {budget_ms}ms deadline was left to {phase}")]
+    DeadlineExceeded { phase: String, budget_ms: u64 },
+    /// VM networking can't be set up: neither `CAP_NET_ADMIN` nor passwordless sudo
+    #[error(
+        "VM networking needs privileges this server lacks: {0}. Grant it CAP_NET_ADMIN (e.g. `setcap cap_net_admin+ep` on the binary, or `AmbientCapabilities=CAP_NET_ADMIN` under systemd), or allow its user passwordless sudo for `ip` and `tc`"
+    )]
+    PrivilegeError(String),
+    /// No sandbox with this ID is kept for the caller
+    #[error("No sandbox {0}")]
+    SandboxNotFound(String),
+    /// The sandbox expired or was deleted
+    #[error("Sandbox {0} has expired or was deleted")]
+    SandboxExpired(String),
+    /// Another execution is running in the sandbox
+    #[error("Sandbox {0} is running another execution")]
+    SandboxBusy(String),
 }
 
-impl IntoResponse for ExecutionError {
-    fn into_response(self) -> axum::response::Response {
-        let status = match self {
+impl ExecutionError {
+    /// Stable machine-readable code for this error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExecutionError::ApiCommunicationError(_) => "api_communication_error",
+            ExecutionError::TimeoutError => "timeout",
+            ExecutionError::TimeoutErrorWithLogs(_) => "timeout",
+            ExecutionError::SerializationError(_) => "serialization_error",
+            ExecutionError::ResourceError(_) => "resource_error",
+            ExecutionError::ProcessSpawnError(_) => "process_spawn_error",
+            ExecutionError::ResourceExhausted(_) => "resource_exhausted",
+            ExecutionError::ShuttingDown => "shutting_down",
+            ExecutionError::Overloaded(_) => "overloaded",
+            ExecutionError::VmCreationUnavailable(_) => "vm_creation_unavailable",
+            ExecutionError::PoolExhausted { .. } => "pool_exhausted",
+            ExecutionError::GuestOutOfMemory { .. } => "guest_out_of_memory",
+            ExecutionError::GuestProtocolError { .. } => "guest_protocol_error",
+            ExecutionError::NoWarmVm => "no_warm_vm",
+            ExecutionError::PrivilegeError(_) => "privilege_error",
+            ExecutionError::SandboxNotFound(_) => "sandbox_not_found",
+            ExecutionError::SandboxExpired(_) => "sandbox_expired",
+            ExecutionError::SandboxBusy(_) => "sandbox_busy",
+            ExecutionError::QuickTimeout { .. } | ExecutionError::DeadlineExceeded { .. } => {
+                "timeout"
+            }
+        }
+    }
+
+    /// HTTP status this error is answered with
+    pub fn status(&self) -> StatusCode {
+        match self {
             ExecutionError::ApiCommunicationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ExecutionError::TimeoutError => StatusCode::INTERNAL_SERVER_ERROR,
             ExecutionError::TimeoutErrorWithLogs(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ExecutionError::SerializationError(_) => StatusCode::BAD_REQUEST,
             ExecutionError::ResourceError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ExecutionError::ProcessSpawnError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
-        let body = Json(serde_json::json!({
-            "error": self.to_string(),
-        }));
-        (status, body).into_response()
+            ExecutionError::ResourceExhausted(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ExecutionError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            ExecutionError::Overloaded(_) => StatusCode::TOO_MANY_REQUESTS,
+            ExecutionError::VmCreationUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ExecutionError::PoolExhausted { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ExecutionError::GuestOutOfMemory { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ExecutionError::GuestProtocolError { .. } => StatusCode::BAD_REQUEST,
+            ExecutionError::NoWarmVm => StatusCode::SERVICE_UNAVAILABLE,
+            ExecutionError::PrivilegeError(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ExecutionError::SandboxNotFound(_) => StatusCode::NOT_FOUND,
+            ExecutionError::SandboxExpired(_) => StatusCode::GONE,
+            ExecutionError::SandboxBusy(_) => StatusCode::CONFLICT,
+            ExecutionError::QuickTimeout { .. } | ExecutionError::DeadlineExceeded { .. } => {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+        }
+    }
+
+    /// Seconds after which a retry may succeed, for errors that clear up on their own as
+    /// running executions finish or VMs come back
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        matches!(
+            self,
+            ExecutionError::ResourceExhausted(_)
+                | ExecutionError::ShuttingDown
+                | ExecutionError::Overloaded(_)
+                | ExecutionError::VmCreationUnavailable(_)
+                | ExecutionError::PoolExhausted { .. }
+                | ExecutionError::NoWarmVm
+        )
+        .then_some(admission::ADMISSION_RETRY_AFTER_SECS)
+    }
+}
+
+impl IntoResponse for ExecutionError {
+    fn into_response(self) -> axum::response::Response {
+        let status = self.status();
+        let retry_after = self
+            .retry_after_secs()
+            .map(|secs| [(axum::http::header::RETRY_AFTER, secs.to_string())]);
+        (
+            status,
+            retry_after,
+            Json(ErrorResponse::new(self.code(), self.to_string())),
+        )
+            .into_response()
     }
 }
 
@@ -69,12 +561,18 @@ pub fn generate_vm_id() -> String {
     uuid::Uuid::new_v4().to_string()
 }
 
+/// Generate a unique request identifier
+pub fn generate_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
 /// Create an ExecuteResponse for successful execution
 pub fn create_success_response(stdout: String, stderr: String) -> ExecuteResponse {
     ExecuteResponse {
         stdout,
         stderr,
         success: true,
+        ..Default::default()
     }
 }
 
@@ -84,6 +582,7 @@ pub fn create_error_response(error_message: String) -> ExecuteResponse {
         stdout: String::new(),
         stderr: error_message,
         success: false,
+        ..Default::default()
     }
 }
 
@@ -95,7 +594,157 @@ mod tests {
     fn test_execute_request_deserialization() {
         let json = r#"{"code": "print('Hello, World!')"}"#;
         let request: ExecuteRequest = serde_json::from_str(json).unwrap();
-        assert_eq!(request.code, "print('Hello, World!')");
+        assert_eq!(request.code.as_deref(), Some("print('Hello, World!')"));
+        assert!(request.files.is_none());
+    }
+
+    #[test]
+    fn test_execute_response_always_has_warnings() {
+        let json = serde_json::to_value(ExecuteResponse::default()).unwrap();
+        assert_eq!(json["warnings"], serde_json::json!([]));
+
+        let response = ExecuteResponse {
+            warnings: vec![warning::Warning::new(
+                warning::WarningCode::ColdStart,
+                "booted",
+            )],
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            json["warnings"],
+            serde_json::json!([{"code": "cold_start", "message": "booted"}])
+        );
+        let parsed: ExecuteResponse = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.warnings, response.warnings);
+        // Responses from servers without warnings still parse
+        let parsed: ExecuteResponse =
+            serde_json::from_str(r#"{"stdout": "", "stderr": "", "success": true, "stdout_truncated": false, "stderr_truncated": false}"#)
+                .unwrap();
+        assert!(parsed.warnings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_execution_errors_answer_with_their_status() {
+        let cases = [
+            (
+                ExecutionError::ApiCommunicationError("reset".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                ExecutionError::TimeoutError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                ExecutionError::TimeoutErrorWithLogs(String::new()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                ExecutionError::SerializationError("eof".into()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                ExecutionError::ResourceError("socket".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                ExecutionError::ProcessSpawnError("enoent".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                ExecutionError::ResourceExhausted("memory".into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ExecutionError::ShuttingDown,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ExecutionError::Overloaded("queue".into()),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                ExecutionError::VmCreationUnavailable("open".into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ExecutionError::PoolExhausted {
+                    waited_ms: 5,
+                    queue_depth: 2,
+                },
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ExecutionError::GuestOutOfMemory {
+                    process: "python3".into(),
+                    mem_size_mib: 128,
+                },
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                ExecutionError::GuestProtocolError {
+                    feature: "eval".into(),
+                    required: 2,
+                    agent: 1,
+                },
+                StatusCode::BAD_REQUEST,
+            ),
+            (ExecutionError::NoWarmVm, StatusCode::SERVICE_UNAVAILABLE),
+            (
+                ExecutionError::QuickTimeout { timeout_ms: 500 },
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+            (
+                ExecutionError::DeadlineExceeded {
+                    phase: "boot".into(),
+                    budget_ms: 100,
+                },
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+            (
+                ExecutionError::PrivilegeError("no sudo".into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                ExecutionError::SandboxNotFound("sbx-1".into()),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                ExecutionError::SandboxExpired("sbx-1".into()),
+                StatusCode::GONE,
+            ),
+            (
+                ExecutionError::SandboxBusy("sbx-1".into()),
+                StatusCode::CONFLICT,
+            ),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status(), status, "{error:?}");
+            let code = error.code();
+            let message = error.to_string();
+            let retries = matches!(
+                error,
+                ExecutionError::ResourceExhausted(_)
+                    | ExecutionError::ShuttingDown
+                    | ExecutionError::Overloaded(_)
+                    | ExecutionError::VmCreationUnavailable(_)
+                    | ExecutionError::PoolExhausted { .. }
+                    | ExecutionError::NoWarmVm
+            );
+            let response = error.into_response();
+            assert_eq!(response.status(), status);
+            assert_eq!(
+                response
+                    .headers()
+                    .contains_key(axum::http::header::RETRY_AFTER),
+                retries
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!((body.code.as_str(), body.error), (code, message));
+        }
     }
 
     #[test]
@@ -104,12 +753,145 @@ mod tests {
             stdout: "Hello, World!\n".to_string(),
             stderr: String::new(),
             success: true,
+            ..Default::default()
         };
         let json = serde_json::to_string(&response).unwrap();
         assert!(json.contains("Hello, World!"));
         assert!(json.contains("\"success\":true"));
     }
 
+    #[test]
+    fn test_eval_value_serialization() {
+        let request: ExecuteRequest =
+            serde_json::from_str(r#"{"code": "1 + 1", "mode": "eval"}"#).unwrap();
+        assert_eq!(request.mode, Some(ExecMode::Eval));
+        assert!(serde_json::from_str::<ExecuteRequest>(r#"{"mode": "repl"}"#).is_err());
+
+        // Script runs carry neither field
+        let json = serde_json::to_value(ExecuteResponse::default()).unwrap();
+        assert!(json.get("value").is_none() && json.get("value_repr").is_none());
+
+        let value = serde_json::json!({ "total": 3, "items": [1, 2.5, null, "x"] });
+        let response = ExecuteResponse {
+            value: Some(value.clone()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["value"], value);
+        assert!(json.get("value_repr").is_none());
+        let round_trip: ExecuteResponse = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip.value, Some(value));
+
+        // Values JSON can't carry come back as their repr
+        let response = ExecuteResponse {
+            value_repr: Some("{1, 2}".to_string()),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["value_repr"], "{1, 2}");
+        assert!(json.get("value").is_none());
+        let round_trip: ExecuteResponse = serde_json::from_value(json).unwrap();
+        assert!(round_trip.value.is_none());
+        assert_eq!(round_trip.value_repr.as_deref(), Some("{1, 2}"));
+    }
+
+    #[test]
+    fn test_exception_info_from_agent() {
+        let value = serde_json::json!({
+            "type": "ZeroDivisionError",
+            "message": "division by zero",
+            "traceback": [
+                { "filename": "<string>", "line": 3, "function": "<module>" },
+                { "filename": "<string>", "line": 1, "function": "div" }
+            ]
+        });
+        let info = ExceptionInfo::from_agent(&value).unwrap();
+        assert_eq!(info.exception_type, "ZeroDivisionError");
+        assert_eq!(info.traceback[1].function, "div");
+        assert!(info.cause.is_none());
+        assert_eq!(serde_json::to_value(&info).unwrap(), value);
+
+        // Older agents omit the field; malformed values are ignored
+        assert!(ExceptionInfo::from_agent(&serde_json::Value::Null).is_none());
+        assert!(ExceptionInfo::from_agent(&serde_json::json!({ "type": 1 })).is_none());
+    }
+
+    #[test]
+    fn test_exception_info_deep_traceback_and_chain() {
+        let frames: Vec<_> = (1..=1000)
+            .map(|line| serde_json::json!({ "filename": "main.py", "line": line, "function": "recurse" }))
+            .collect();
+        let mut value = serde_json::json!({ "type": "RecursionError", "message": "too deep", "traceback": frames });
+        for depth in 0..20 {
+            value = serde_json::json!({
+                "type": "RuntimeError",
+                "message": format!("wrapper {depth}"),
+                "traceback": [],
+                "cause": value,
+            });
+        }
+        let mut info = ExceptionInfo::from_agent(&value).unwrap();
+        let mut depth = 0;
+        while let Some(cause) = info.cause {
+            info = *cause;
+            depth += 1;
+        }
+        assert_eq!(depth, 20);
+        assert_eq!(info.exception_type, "RecursionError");
+        assert_eq!(info.traceback.len(), 1000);
+    }
+
+    #[test]
+    fn test_exception_info_unusual_messages() {
+        // The agent escapes undecodable bytes; control and replacement characters survive
+        let raw = r#"{"type":"UnicodeDecodeError","message":"can't decode \\xff: � \u0000 😀","traceback":[]}"#;
+        let info: ExceptionInfo = serde_json::from_str(raw).unwrap();
+        assert_eq!(info.message, "can't decode \\xff: \u{fffd} \0 \u{1f600}");
+        let round_trip: ExceptionInfo =
+            serde_json::from_str(&serde_json::to_string(&info).unwrap()).unwrap();
+        assert_eq!(round_trip, info);
+
+        // A lone surrogate can't be represented in a Rust string; it is rejected, not mangled
+        let lone = r#"{"type":"E","message":"\udcff","traceback":[]}"#;
+        assert!(serde_json::from_str::<ExceptionInfo>(lone).is_err());
+    }
+
+    #[test]
+    fn test_execution_usage_from_agent() {
+        let value = serde_json::json!({ "cpu_time_ms": 120, "max_rss_kb": 20480, "wall_ms": 150 });
+        let usage = ExecutionUsage::from_agent(&value).unwrap();
+        assert_eq!(usage.max_rss_kb, 20480);
+        assert_eq!(serde_json::to_value(usage).unwrap(), value);
+
+        // Older agents omit the field; partial reports are ignored rather than guessed at
+        assert!(ExecutionUsage::from_agent(&serde_json::Value::Null).is_none());
+        assert!(ExecutionUsage::from_agent(&serde_json::json!({ "wall_ms": 5 })).is_none());
+
+        let response = ExecuteResponse::default();
+        assert!(!serde_json::to_string(&response).unwrap().contains("usage"));
+    }
+
+    #[test]
+    fn test_execution_usage_aggregation() {
+        let registry = telemetry::Registry::default();
+        metrics::with_local_recorder(&registry, || {
+            for (cpu, rss_kb) in [(3, 10 * 1024), (40, 20 * 1024), (700, 200 * 1024)] {
+                ExecutionUsage {
+                    cpu_time_ms: cpu,
+                    max_rss_kb: rss_kb,
+                    wall_ms: cpu + 1,
+                }
+                .record();
+            }
+        });
+        let text = registry.render();
+        assert!(text.contains("fc_execution_cpu_time_ms_bucket{le=\"5\"} 1"));
+        assert!(text.contains("fc_execution_cpu_time_ms_bucket{le=\"50\"} 2"));
+        assert!(text.contains("fc_execution_cpu_time_ms_sum 743"));
+        assert!(text.contains("fc_execution_max_rss_mib_bucket{le=\"25\"} 2"));
+        assert!(text.contains("fc_execution_wall_ms_count 3"));
+    }
+
     #[test]
     fn test_vm_id_generation() {
         let id1 = generate_vm_id();
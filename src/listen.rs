@@ -0,0 +1,536 @@
+use axum::Router;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Address the HTTP API listens on unless `FC_LISTEN` says otherwise
+pub const DEFAULT_LISTEN: &str = "tcp://127.0.0.1:3000";
+
+/// Where the HTTP API listens, from `tcp://HOST:PORT`, `unix:///PATH` or `tls://HOST:PORT`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    Tls(SocketAddr),
+}
+
+#[derive(Error, Debug)]
+pub enum ListenError {
+    #[error(
+        "invalid listen address {0:?}; expected tcp://HOST:PORT, unix:///PATH or tls://HOST:PORT"
+    )]
+    InvalidAddr(String),
+    #[error("invalid socket owner {0:?}; expected USER[:GROUP] as names or numeric IDs")]
+    InvalidOwner(String),
+    #[error("{} is in use by a running server", .0.display())]
+    SocketInUse(PathBuf),
+    #[error("{} exists and is not a socket; refusing to replace it", .0.display())]
+    NotASocket(PathBuf),
+    #[error("cannot listen on {addr}: {source}")]
+    Io {
+        addr: String,
+        source: std::io::Error,
+    },
+}
+
+impl FromStr for ListenAddr {
+    type Err = ListenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ListenError::InvalidAddr(s.to_string());
+        let (scheme, rest) = s.trim().split_once("://").ok_or_else(invalid)?;
+        match scheme {
+            "tcp" => rest.parse().map(ListenAddr::Tcp).map_err(|_| invalid()),
+            "tls" => rest.parse().map(ListenAddr::Tls).map_err(|_| invalid()),
+            "unix" if rest.starts_with('/') && rest.len() > 1 => {
+                Ok(ListenAddr::Unix(PathBuf::from(rest)))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "tcp://{addr}"),
+            ListenAddr::Unix(path) => write!(f, "unix://{}", path.display()),
+            ListenAddr::Tls(addr) => write!(f, "tls://{addr}"),
+        }
+    }
+}
+
+/// Owner given to the Unix socket; `None` keeps the server's own user or group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOwner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl SocketOwner {
+    /// Parse `USER[:GROUP]`, looking names up in `/etc/passwd` and `/etc/group`
+    pub fn parse(s: &str) -> Result<Self, ListenError> {
+        let passwd = std::fs::read_to_string("/etc/passwd").unwrap_or_default();
+        let group = std::fs::read_to_string("/etc/group").unwrap_or_default();
+        Self::parse_with(s, &passwd, &group)
+    }
+
+    fn parse_with(s: &str, passwd: &str, group: &str) -> Result<Self, ListenError> {
+        let invalid = || ListenError::InvalidOwner(s.to_string());
+        let (user, group_name) = match s.split_once(':') {
+            Some((user, group_name)) => (user, Some(group_name)),
+            None => (s, None),
+        };
+        let resolve = |name: &str, db: &str| -> Result<Option<u32>, ListenError> {
+            if name.is_empty() {
+                return Ok(None);
+            }
+            name.parse()
+                .ok()
+                .or_else(|| lookup_id(db, name))
+                .map(Some)
+                .ok_or_else(invalid)
+        };
+        let owner = Self {
+            uid: resolve(user, passwd)?,
+            gid: match group_name {
+                Some(name) => resolve(name, group)?,
+                None => None,
+            },
+        };
+        if owner == Self::default() {
+            return Err(invalid());
+        }
+        Ok(owner)
+    }
+}
+
+/// ID of `name` in `passwd` or `group` file contents, whose third field is the ID
+fn lookup_id(db: &str, name: &str) -> Option<u32> {
+    db.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next()? == name).then_some(())?;
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+/// The HTTP listener and its transport settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenConfig {
+    pub addr: ListenAddr,
+    /// Permission bits of the Unix socket, such as `0o660`
+    pub socket_mode: Option<u32>,
+    /// Owner of the Unix socket
+    pub socket_owner: Option<SocketOwner>,
+    /// PEM certificate chain served with `tls://`
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self {
+            addr: DEFAULT_LISTEN
+                .parse()
+                .expect("the default listen address parses"),
+            socket_mode: None,
+            socket_owner: None,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+/// Parse a socket mode given in octal, as `660`, `0660` or `0o660`
+pub fn parse_mode(s: &str) -> Option<u32> {
+    let digits = s.trim().trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
+
+/// A bound listener, ready to serve the API
+pub enum Listener {
+    Tcp(tokio::net::TcpListener),
+    Unix {
+        listener: tokio::net::UnixListener,
+        /// Socket file to remove after shutdown; `None` for one systemd created
+        path: Option<PathBuf>,
+    },
+    Tls {
+        listener: std::net::TcpListener,
+        tls: axum_server::tls_rustls::RustlsConfig,
+    },
+}
+
+impl Listener {
+    /// Bind the address of `config`. A Unix socket left behind by a server that is gone is
+    /// replaced; one a running server still answers on is not.
+    pub async fn bind(config: &ListenConfig) -> Result<Self, ListenError> {
+        let io = |source| ListenError::Io {
+            addr: config.addr.to_string(),
+            source,
+        };
+        match &config.addr {
+            ListenAddr::Tcp(addr) => tokio::net::TcpListener::bind(addr)
+                .await
+                .map(Listener::Tcp)
+                .map_err(io),
+            ListenAddr::Unix(path) => {
+                remove_stale_socket(path).await?;
+                let listener = tokio::net::UnixListener::bind(path).map_err(io)?;
+                if let Some(mode) = config.socket_mode {
+                    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                        .map_err(io)?;
+                }
+                if let Some(owner) = config.socket_owner {
+                    std::os::unix::fs::chown(path, owner.uid, owner.gid).map_err(io)?;
+                }
+                Ok(Listener::Unix {
+                    listener,
+                    path: Some(path.clone()),
+                })
+            }
+            ListenAddr::Tls(addr) => {
+                let tls = load_tls(config).await?;
+                let listener = std::net::TcpListener::bind(addr).map_err(io)?;
+                listener.set_nonblocking(true).map_err(io)?;
+                Ok(Listener::Tls { listener, tls })
+            }
+        }
+    }
+
+    /// Serve on a socket inherited from systemd instead of binding one. Whether it is a TCP
+    /// or a Unix socket decides the transport; TLS still follows `config`. The socket file
+    /// of an inherited Unix socket belongs to systemd and is left in place.
+    pub async fn adopt(config: &ListenConfig, fd: OwnedFd) -> Result<Self, ListenError> {
+        let io = |source| ListenError::Io {
+            addr: "the socket inherited from systemd".to_string(),
+            source,
+        };
+        let tcp = std::net::TcpListener::from(fd);
+        let listener = if tcp.local_addr().is_ok() {
+            tcp.set_nonblocking(true).map_err(io)?;
+            match config.addr {
+                ListenAddr::Tls(_) => Listener::Tls {
+                    listener: tcp,
+                    tls: load_tls(config).await?,
+                },
+                _ => Listener::Tcp(tokio::net::TcpListener::from_std(tcp).map_err(io)?),
+            }
+        } else {
+            let unix = std::os::unix::net::UnixListener::from(OwnedFd::from(tcp));
+            unix.local_addr().map_err(io)?;
+            unix.set_nonblocking(true).map_err(io)?;
+            Listener::Unix {
+                listener: tokio::net::UnixListener::from_std(unix).map_err(io)?,
+                path: None,
+            }
+        };
+        Ok(listener)
+    }
+
+    /// Address actually bound, with the port picked when `0` was asked for
+    pub fn local_addr(&self) -> std::io::Result<ListenAddr> {
+        Ok(match self {
+            Listener::Tcp(listener) => ListenAddr::Tcp(listener.local_addr()?),
+            Listener::Unix { listener, .. } => ListenAddr::Unix(
+                listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+            ),
+            Listener::Tls { listener, .. } => ListenAddr::Tls(listener.local_addr()?),
+        })
+    }
+
+    /// Serve `app` until `shutdown` resolves and in-flight requests have finished, then
+    /// remove the Unix socket. Clients on a Unix socket have no address, so those without an
+    /// API key share one rate limit.
+    pub async fn serve(
+        self,
+        app: Router,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> std::io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown)
+                .await
+            }
+            Listener::Unix { listener, path } => {
+                let served = axum::serve(listener, app.into_make_service())
+                    .with_graceful_shutdown(shutdown)
+                    .await;
+                if let Some(path) = path
+                    && let Err(e) = std::fs::remove_file(&path)
+                {
+                    tracing::warn!("Failed to remove socket {}: {}", path.display(), e);
+                }
+                served
+            }
+            Listener::Tls { listener, tls } => {
+                let handle = axum_server::Handle::new();
+                let draining = handle.clone();
+                tokio::spawn(async move {
+                    shutdown.await;
+                    draining.graceful_shutdown(None);
+                });
+                axum_server::from_tcp_rustls(listener, tls)?
+                    .handle(handle)
+                    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                    .await
+            }
+        }
+    }
+}
+
+/// Certificate and key of a `tls://` address
+async fn load_tls(
+    config: &ListenConfig,
+) -> Result<axum_server::tls_rustls::RustlsConfig, ListenError> {
+    let io = |source| ListenError::Io {
+        addr: config.addr.to_string(),
+        source,
+    };
+    let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+        return Err(io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "tls:// needs both FC_TLS_CERT and FC_TLS_KEY",
+        )));
+    };
+    // Another crypto provider may be compiled in through other dependencies
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    axum_server::tls_rustls::RustlsConfig::from_pem_file(cert, key)
+        .await
+        .map_err(io)
+}
+
+/// Remove a socket at `path` nothing listens on any more
+async fn remove_stale_socket(path: &Path) -> Result<(), ListenError> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(ListenError::NotASocket(path.to_path_buf()));
+    }
+    if tokio::net::UnixStream::connect(path).await.is_ok() {
+        return Err(ListenError::SocketInUse(path.to_path_buf()));
+    }
+    tracing::info!("Removing stale socket {}", path.display());
+    std::fs::remove_file(path).map_err(|source| ListenError::Io {
+        addr: format!("unix://{}", path.display()),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use http_body_util::BodyExt;
+
+    #[test]
+    fn test_parse_listen_addresses() {
+        assert_eq!(
+            "tcp://0.0.0.0:3000".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp("0.0.0.0:3000".parse().unwrap())
+        );
+        assert_eq!(
+            "unix:///run/firecracker-poc.sock"
+                .parse::<ListenAddr>()
+                .unwrap(),
+            ListenAddr::Unix(PathBuf::from("/run/firecracker-poc.sock"))
+        );
+        assert_eq!(
+            "tls://[::1]:3443".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tls("[::1]:3443".parse().unwrap())
+        );
+        for addr in [
+            "tcp://localhost:3000",
+            "0.0.0.0:3000",
+            "http://0.0.0.0:3000",
+            "unix://relative.sock",
+            "unix:///",
+            "tls://0.0.0.0",
+        ] {
+            assert!(addr.parse::<ListenAddr>().is_err(), "{addr}");
+        }
+        for addr in [DEFAULT_LISTEN, "unix:///tmp/a.sock", "tls://127.0.0.1:3443"] {
+            assert_eq!(addr.parse::<ListenAddr>().unwrap().to_string(), addr);
+        }
+    }
+
+    #[test]
+    fn test_parse_mode_and_owner() {
+        assert_eq!(parse_mode("660"), Some(0o660));
+        assert_eq!(parse_mode("0660"), Some(0o660));
+        assert_eq!(parse_mode("0o600"), Some(0o600));
+        assert_eq!(parse_mode("rw"), None);
+        assert_eq!(parse_mode("8"), None);
+
+        let passwd =
+            "root:x:0:0:root:/root:/bin/bash\nsidecar:x:1001:1001::/home/sidecar:/bin/sh\n";
+        let group = "root:x:0:\nproxy:x:13:\n";
+        let parse = |s| SocketOwner::parse_with(s, passwd, group);
+        assert_eq!(
+            parse("sidecar:proxy").unwrap(),
+            SocketOwner {
+                uid: Some(1001),
+                gid: Some(13),
+            }
+        );
+        assert_eq!(
+            parse(":proxy").unwrap(),
+            SocketOwner {
+                uid: None,
+                gid: Some(13),
+            }
+        );
+        assert_eq!(
+            parse("2000").unwrap(),
+            SocketOwner {
+                uid: Some(2000),
+                gid: None,
+            }
+        );
+        assert!(parse("nobody-here").is_err());
+        assert!(parse("").is_err());
+    }
+
+    /// GET `uri` over the Unix socket at `path`
+    async fn get_over_socket(path: &Path, uri: &str) -> (hyper::StatusCode, String) {
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .build::<_, http_body_util::Full<hyper::body::Bytes>>(hyperlocal::UnixConnector);
+        let response = client
+            .get(hyperlocal::Uri::new(path, uri).into())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_serves_over_a_unix_socket_and_removes_it() {
+        let path = std::env::temp_dir().join(format!("fc-listen-{}.sock", std::process::id()));
+        // A server that went away without cleaning up
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let config = ListenConfig {
+            addr: ListenAddr::Unix(path.clone()),
+            socket_mode: Some(0o600),
+            ..Default::default()
+        };
+        let listener = Listener::bind(&config).await.unwrap();
+        assert_eq!(listener.local_addr().unwrap(), config.addr);
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // A second server doesn't take over the live socket
+        assert!(matches!(
+            Listener::bind(&config).await,
+            Err(ListenError::SocketInUse(_))
+        ));
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let server = tokio::spawn(listener.serve(app, async {
+            let _ = stopped.await;
+        }));
+        assert_eq!(
+            get_over_socket(&path, "/health").await,
+            (hyper::StatusCode::OK, "ok".to_string())
+        );
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_adopts_inherited_sockets_by_family() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let adopted = Listener::adopt(&ListenConfig::default(), OwnedFd::from(tcp))
+            .await
+            .unwrap();
+        assert_eq!(adopted.local_addr().unwrap(), ListenAddr::Tcp(addr));
+
+        let path = std::env::temp_dir().join(format!("fc-adopt-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let adopted = Listener::adopt(&ListenConfig::default(), OwnedFd::from(unix))
+            .await
+            .unwrap();
+        assert_eq!(
+            adopted.local_addr().unwrap(),
+            ListenAddr::Unix(path.clone())
+        );
+
+        // systemd owns the socket file, so it outlives the server
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        let server = tokio::spawn(adopted.serve(app, async {}));
+        server.await.unwrap().unwrap();
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_refuses_to_replace_a_regular_file() {
+        let path = std::env::temp_dir().join(format!("fc-listen-{}.file", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+        let config = ListenConfig {
+            addr: ListenAddr::Unix(path.clone()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Listener::bind(&config).await,
+            Err(ListenError::NotASocket(_))
+        ));
+        assert!(path.exists());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_serves_tls() {
+        let config = ListenConfig {
+            addr: "tls://127.0.0.1:0".parse().unwrap(),
+            tls_cert: Some(PathBuf::from("fixtures/tls/cert.pem")),
+            tls_key: Some(PathBuf::from("fixtures/tls/key.pem")),
+            ..Default::default()
+        };
+        let listener = Listener::bind(&config).await.unwrap();
+        let ListenAddr::Tls(addr) = listener.local_addr().unwrap() else {
+            panic!("expected a TLS address");
+        };
+        let app = Router::new().route("/health", get(|| async { "ok" }));
+        tokio::spawn(listener.serve(app, std::future::pending()));
+
+        let cert = std::fs::read("fixtures/tls/cert.pem").unwrap();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+            .resolve("localhost", addr)
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/health", addr.port());
+        let body = client.get(url).send().await.unwrap().text().await.unwrap();
+        assert_eq!(body, "ok");
+
+        // Plain HTTP isn't answered
+        let plain = format!("http://127.0.0.1:{}/health", addr.port());
+        assert!(reqwest::get(plain).await.is_err());
+    }
+}
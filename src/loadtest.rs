@@ -0,0 +1,436 @@
+use crate::ExecuteRequest;
+use crate::client::{ClientError, FirecrackerPocClient};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Server targeted when `--target` isn't given
+pub const DEFAULT_TARGET: &str = "http://127.0.0.1:3000";
+
+/// Snippet sent when no `--code` or `--code-file` is given
+pub const DEFAULT_CODE: &str = "print('hello from the load test')";
+
+const USAGE: &str = "usage: loadtest [--target URL] [--concurrency N] [--duration SECS] \
+     [--ramp-up SECS] [--code CODE]... [--code-file FILE]... [--max-error-rate RATE] \
+     [--api-key KEY] [--mock]";
+
+#[derive(Debug, Error)]
+pub enum LoadTestError {
+    #[error("Invalid load test arguments: {0}")]
+    InvalidInput(String),
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// What to send, how hard and for how long
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadTestSpec {
+    /// Base URL of the server
+    pub target: String,
+    /// Requests in flight once ramp-up is over, one per worker
+    pub concurrency: usize,
+    /// Time requests are sent for, counting the ramp-up
+    pub duration: Duration,
+    /// Time over which workers start, evenly spaced
+    pub ramp_up: Duration,
+    /// Snippets sent in turn
+    pub payloads: Vec<String>,
+    /// Largest share of failed requests that still passes
+    pub max_error_rate: f64,
+    pub api_key: Option<String>,
+    /// The target runs the mock backend: each response must echo its snippet, so a smoke run
+    /// checks the whole request path rather than just status codes
+    pub mock: bool,
+}
+
+impl Default for LoadTestSpec {
+    fn default() -> Self {
+        Self {
+            target: DEFAULT_TARGET.to_string(),
+            concurrency: 10,
+            duration: Duration::from_secs(30),
+            ramp_up: Duration::ZERO,
+            payloads: vec![DEFAULT_CODE.to_string()],
+            max_error_rate: 0.01,
+            api_key: None,
+            mock: false,
+        }
+    }
+}
+
+impl LoadTestSpec {
+    /// Parse the command line; `FC_API_KEY` is used when `--api-key` isn't given
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, LoadTestError> {
+        let mut spec = Self {
+            api_key: std::env::var("FC_API_KEY").ok(),
+            ..Self::default()
+        };
+        let (mut concurrency, mut duration, mut payloads) = (None, None, Vec::new());
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| LoadTestError::InvalidInput(format!("{flag} needs a value")))
+            };
+            match flag.as_str() {
+                "--target" => spec.target = value()?,
+                "--concurrency" => concurrency = Some(parse(&flag, &value()?)?),
+                "--duration" => duration = Some(seconds(&flag, &value()?)?),
+                "--ramp-up" => spec.ramp_up = seconds(&flag, &value()?)?,
+                "--code" => payloads.push(value()?),
+                "--code-file" => {
+                    let path = PathBuf::from(value()?);
+                    let code = std::fs::read_to_string(&path)
+                        .map_err(|source| LoadTestError::Io { path, source })?;
+                    payloads.push(code);
+                }
+                "--max-error-rate" => {
+                    let raw = value()?;
+                    spec.max_error_rate = raw
+                        .parse()
+                        .ok()
+                        .filter(|rate| (0.0..=1.0).contains(rate))
+                        .ok_or_else(|| {
+                            LoadTestError::InvalidInput(format!("{flag}: invalid rate {raw:?}"))
+                        })?;
+                }
+                "--api-key" => spec.api_key = Some(value()?),
+                "--mock" => spec.mock = true,
+                other => {
+                    return Err(LoadTestError::InvalidInput(format!(
+                        "unknown argument {other:?}; {USAGE}"
+                    )));
+                }
+            }
+        }
+        // Smoke runs are short and gentle unless told otherwise
+        if spec.mock {
+            spec.concurrency = 4;
+            spec.duration = Duration::from_secs(5);
+        }
+        spec.concurrency = concurrency.unwrap_or(spec.concurrency);
+        spec.duration = duration.unwrap_or(spec.duration);
+        if spec.concurrency == 0 {
+            return Err(LoadTestError::InvalidInput(
+                "--concurrency must be at least 1".to_string(),
+            ));
+        }
+        if spec.ramp_up > spec.duration {
+            return Err(LoadTestError::InvalidInput(
+                "--ramp-up can't be longer than --duration".to_string(),
+            ));
+        }
+        if !payloads.is_empty() {
+            spec.payloads = payloads;
+        }
+        Ok(spec)
+    }
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, raw: &str) -> Result<T, LoadTestError> {
+    raw.parse()
+        .map_err(|_| LoadTestError::InvalidInput(format!("{flag}: invalid value {raw:?}")))
+}
+
+fn seconds(flag: &str, raw: &str) -> Result<Duration, LoadTestError> {
+    let secs: f64 = parse(flag, raw)?;
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| LoadTestError::InvalidInput(format!("{flag}: invalid duration {raw:?}")))
+}
+
+/// Result of one request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub latency: Duration,
+    /// VM from `x-vm-id` on success, or the error's key in the breakdown
+    pub outcome: Result<Option<String>, String>,
+}
+
+/// Key of a failed request in the error breakdown: the status and error code, or `transport`
+/// when no response came back
+fn error_key(error: &ClientError) -> String {
+    match error {
+        ClientError::Transport(_) => "transport".to_string(),
+        ClientError::Server {
+            status,
+            code: Some(code),
+            ..
+        } => format!("{status} {code}"),
+        ClientError::Server { status, .. } => status.to_string(),
+    }
+}
+
+/// Fire `spec`'s requests and collect what came back
+pub async fn run(spec: &LoadTestSpec) -> Report {
+    let mut client = FirecrackerPocClient::new(&spec.target);
+    if let Some(key) = &spec.api_key {
+        client = client.with_api_key(key);
+    }
+    let start = Instant::now();
+    let deadline = start + spec.duration;
+    let mut workers = tokio::task::JoinSet::new();
+    for worker in 0..spec.concurrency {
+        let client = client.clone();
+        let spec = spec.clone();
+        let delay = spec
+            .ramp_up
+            .mul_f64(worker as f64 / spec.concurrency as f64);
+        workers.spawn(async move {
+            tokio::time::sleep(delay).await;
+            let mut samples = Vec::new();
+            // Workers start on different snippets so every payload is in flight at once
+            let mut next = worker;
+            while Instant::now() < deadline {
+                let code = &spec.payloads[next % spec.payloads.len()];
+                next += 1;
+                samples.push(send(&client, code, spec.mock).await);
+            }
+            samples
+        });
+    }
+    let mut samples = Vec::new();
+    while let Some(finished) = workers.join_next().await {
+        samples.extend(finished.unwrap_or_default());
+    }
+    Report::new(samples, start.elapsed())
+}
+
+async fn send(client: &FirecrackerPocClient, code: &str, mock: bool) -> Sample {
+    let request = ExecuteRequest {
+        code: Some(code.to_string()),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let result = client.execute(&request).await;
+    let latency = started.elapsed();
+    let outcome = match result {
+        Ok(response) if mock && response.stdout != format!("Mock execution of: {code}\n") => {
+            Err("unexpected_output".to_string())
+        }
+        Ok(response) if !response.success => Err("execution_failed".to_string()),
+        Ok(response) => Ok(response.vm_id),
+        Err(e) => Err(error_key(&e)),
+    };
+    Sample { latency, outcome }
+}
+
+/// Summary of a load test run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub requests: usize,
+    pub elapsed: Duration,
+    /// Latency of every request, answered or not, in ascending order
+    latencies: Vec<Duration>,
+    /// Failed requests by status and error code; `execution_failed` and `unexpected_output`
+    /// count answered requests whose code failed or, with `--mock`, whose output was wrong
+    pub errors: BTreeMap<String, usize>,
+    /// Successful requests that named their VM
+    pub with_vm_id: usize,
+    /// Distinct VMs named
+    pub distinct_vms: usize,
+}
+
+impl Report {
+    pub fn new(samples: Vec<Sample>, elapsed: Duration) -> Self {
+        let mut latencies: Vec<Duration> = samples.iter().map(|sample| sample.latency).collect();
+        latencies.sort();
+        let mut errors = BTreeMap::new();
+        let mut vms = HashSet::new();
+        let mut with_vm_id = 0;
+        for sample in &samples {
+            match &sample.outcome {
+                Ok(Some(vm_id)) => {
+                    with_vm_id += 1;
+                    vms.insert(vm_id.clone());
+                }
+                Ok(None) => {}
+                Err(key) => *errors.entry(key.clone()).or_default() += 1,
+            }
+        }
+        Self {
+            requests: samples.len(),
+            elapsed,
+            latencies,
+            errors,
+            with_vm_id,
+            distinct_vms: vms.len(),
+        }
+    }
+
+    /// Requests finished per second
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Nearest-rank `p`th percentile latency, `p` in 0..=100
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies
+            .get(rank.clamp(1, self.latencies.len().max(1)) - 1)
+            .copied()
+    }
+
+    /// Share of requests that failed
+    pub fn error_rate(&self) -> f64 {
+        let failed: usize = self.errors.values().sum();
+        if self.requests == 0 {
+            return 0.0;
+        }
+        failed as f64 / self.requests as f64
+    }
+
+    /// Share of the VM-naming responses that ran on a VM an earlier one already used
+    pub fn vm_reuse_ratio(&self) -> Option<f64> {
+        (self.with_vm_id > 0)
+            .then(|| (self.with_vm_id - self.distinct_vms) as f64 / self.with_vm_id as f64)
+    }
+
+    /// Whether the run stays within `max_error_rate`; a run without a single request fails
+    pub fn passes(&self, max_error_rate: f64) -> bool {
+        self.requests > 0 && self.error_rate() <= max_error_rate
+    }
+
+    /// Human-readable summary
+    pub fn render(&self) -> String {
+        let ms = |latency: Option<Duration>| match latency {
+            Some(latency) => format!("{:.1}ms", latency.as_secs_f64() * 1000.0),
+            None => "-".to_string(),
+        };
+        let mut lines = vec![
+            format!(
+                "requests:    {} in {:.1}s ({:.1}/s)",
+                self.requests,
+                self.elapsed.as_secs_f64(),
+                self.throughput()
+            ),
+            format!(
+                "latency:     p50 {}  p95 {}  p99 {}  max {}",
+                ms(self.percentile(50.0)),
+                ms(self.percentile(95.0)),
+                ms(self.percentile(99.0)),
+                ms(self.latencies.last().copied())
+            ),
+            format!("errors:      {:.2}%", self.error_rate() * 100.0),
+        ];
+        for (key, count) in &self.errors {
+            lines.push(format!("  {key}: {count}"));
+        }
+        lines.push(match self.vm_reuse_ratio() {
+            Some(ratio) => format!(
+                "vm reuse:    {:.1}% ({} responses on {} VMs)",
+                ratio * 100.0,
+                self.with_vm_id,
+                self.distinct_vms
+            ),
+            None => "vm reuse:    - (no x-vm-id headers)".to_string(),
+        });
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn sample(ms: u64, outcome: Result<Option<&str>, &str>) -> Sample {
+        Sample {
+            latency: Duration::from_millis(ms),
+            outcome: outcome
+                .map(|vm_id| vm_id.map(str::to_string))
+                .map_err(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_spec_from_args() {
+        let spec = LoadTestSpec::from_args(args(&[
+            "--target",
+            "http://sandbox:3000",
+            "--concurrency",
+            "32",
+            "--duration",
+            "60",
+            "--ramp-up",
+            "2.5",
+            "--code",
+            "print(1)",
+            "--code",
+            "print(2)",
+            "--max-error-rate",
+            "0.05",
+        ]))
+        .unwrap();
+        assert_eq!(spec.target, "http://sandbox:3000");
+        assert_eq!(spec.concurrency, 32);
+        assert_eq!(spec.duration, Duration::from_secs(60));
+        assert_eq!(spec.ramp_up, Duration::from_millis(2500));
+        assert_eq!(spec.payloads, vec!["print(1)", "print(2)"]);
+        assert_eq!(spec.max_error_rate, 0.05);
+        assert!(!spec.mock);
+
+        let smoke = LoadTestSpec::from_args(args(&["--duration", "1", "--mock"])).unwrap();
+        assert!(smoke.mock);
+        assert_eq!(
+            (smoke.concurrency, smoke.duration),
+            (4, Duration::from_secs(1))
+        );
+        assert_eq!(smoke.payloads, vec![DEFAULT_CODE]);
+
+        for bad in [
+            &["--concurrency", "0"][..],
+            &["--duration"],
+            &["--max-error-rate", "2"],
+            &["--ramp-up", "10", "--duration", "5"],
+            &["--workers", "3"],
+        ] {
+            assert!(LoadTestSpec::from_args(args(bad)).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_report_percentiles_errors_and_reuse() {
+        let mut samples: Vec<_> = (1..=100).map(|ms| sample(ms, Ok(Some("vm-a")))).collect();
+        samples[10].outcome = Ok(Some("vm-b".to_string()));
+        samples[20].outcome = Err("429 rate_limited".to_string());
+        samples[30].outcome = Err("transport".to_string());
+        samples[40].outcome = Err("429 rate_limited".to_string());
+        let report = Report::new(samples, Duration::from_secs(4));
+
+        assert_eq!(report.throughput(), 25.0);
+        assert_eq!(report.percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(report.percentile(95.0), Some(Duration::from_millis(95)));
+        assert_eq!(report.percentile(99.0), Some(Duration::from_millis(99)));
+        assert_eq!(report.error_rate(), 0.03);
+        assert_eq!(
+            report.errors,
+            BTreeMap::from([
+                ("429 rate_limited".to_string(), 2),
+                ("transport".to_string(), 1)
+            ])
+        );
+        // 97 successes on 2 VMs: all but the first use of each were reuses
+        assert_eq!(report.vm_reuse_ratio(), Some(95.0 / 97.0));
+        assert!(report.passes(0.05));
+        assert!(!report.passes(0.01));
+
+        let rendered = report.render();
+        assert!(
+            rendered.contains("p50 50.0ms  p95 95.0ms  p99 99.0ms"),
+            "{rendered}"
+        );
+        assert!(rendered.contains("  429 rate_limited: 2"), "{rendered}");
+
+        let empty = Report::new(Vec::new(), Duration::from_secs(1));
+        assert_eq!(empty.percentile(50.0), None);
+        assert_eq!(empty.vm_reuse_ratio(), None);
+        assert!(!empty.passes(1.0));
+    }
+}
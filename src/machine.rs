@@ -0,0 +1,200 @@
+use crate::config::ConfigError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Static CPU templates Firecracker accepts in `PUT /machine-config`
+pub const CPU_TEMPLATES: &[&str] = &["C3", "T2", "T2S", "T2CL", "T2A", "V1N1", "None"];
+
+/// Body of Firecracker's `PUT /machine-config`; optional fields are sent only when set,
+/// since some Firecracker versions reject fields they don't know
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MachineConfig {
+    pub vcpu_count: u32,
+    pub mem_size_mib: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smt: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub track_dirty_pages: Option<bool>,
+}
+
+impl MachineConfig {
+    /// Apply the operator's options on top of the base config; unset options keep the base value
+    pub fn with_options(mut self, options: &MachineOptions) -> Self {
+        self.cpu_template = options.cpu_template.clone().or(self.cpu_template);
+        self.smt = options.smt.or(self.smt);
+        self.track_dirty_pages = options.track_dirty_pages.or(self.track_dirty_pages);
+        self
+    }
+}
+
+/// Optional machine settings from the runner config or a deps profile override
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineOptions {
+    pub cpu_template: Option<String>,
+    pub smt: Option<bool>,
+    pub track_dirty_pages: Option<bool>,
+}
+
+impl MachineOptions {
+    /// `other` wins wherever it sets a field
+    pub fn overlay(&self, other: &MachineOptions) -> MachineOptions {
+        MachineOptions {
+            cpu_template: other.cpu_template.clone().or(self.cpu_template.clone()),
+            smt: other.smt.or(self.smt),
+            track_dirty_pages: other.track_dirty_pages.or(self.track_dirty_pages),
+        }
+    }
+
+    /// Parse `key=value;key=value`; unknown keys and invalid values are an error
+    pub fn parse(raw: &str) -> Result<MachineOptions, ConfigError> {
+        let mut options = MachineOptions::default();
+        for pair in raw.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| ConfigError::Invalid(format!("machine option {pair:?}")))?;
+            let flag = || match value {
+                "true" => Ok(true),
+                "false" => Ok(false),
+                _ => Err(ConfigError::Invalid(format!("{key} must be true or false"))),
+            };
+            match key {
+                "cpu_template" => options.cpu_template = Some(parse_cpu_template(value)?),
+                "smt" => options.smt = Some(flag()?),
+                "track_dirty_pages" => options.track_dirty_pages = Some(flag()?),
+                _ => {
+                    return Err(ConfigError::Invalid(format!(
+                        "unknown machine option {key:?}"
+                    )));
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Check a CPU template name against the ones Firecracker knows
+pub fn parse_cpu_template(value: &str) -> Result<String, ConfigError> {
+    CPU_TEMPLATES
+        .iter()
+        .find(|template| template.eq_ignore_ascii_case(value))
+        .map(|template| template.to_string())
+        .ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "unknown CPU template {value:?} (expected one of {})",
+                CPU_TEMPLATES.join(", ")
+            ))
+        })
+}
+
+/// Parse per-profile overrides, `NAME:key=value;key=value,...`
+pub fn parse_profile_options(raw: &str) -> Result<BTreeMap<String, MachineOptions>, ConfigError> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, options) = entry
+                .split_once(':')
+                .ok_or_else(|| ConfigError::Invalid(format!("machine override {entry:?}")))?;
+            Ok((name.trim().to_string(), MachineOptions::parse(options)?))
+        })
+        .collect()
+}
+
+/// Whether the host CPU vendor can run a template, when that can be told from `/proc/cpuinfo`
+pub fn template_supported(template: &str, cpuinfo: &str) -> Option<bool> {
+    let vendor = cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "vendor_id").then(|| value.trim())
+    });
+    match (template, vendor) {
+        ("None", _) => Some(true),
+        ("C3" | "T2" | "T2S" | "T2CL", Some(vendor)) => Some(vendor == "GenuineIntel"),
+        ("T2A", Some(vendor)) => Some(vendor == "AuthenticAMD"),
+        // ARM hosts report no vendor_id
+        ("V1N1", vendor) => Some(vendor.is_none()),
+        _ => None,
+    }
+}
+
+/// Warn at startup about CPU templates this host is unlikely to support; Firecracker has the
+/// final word when the VM is configured
+pub fn preflight<'a>(options: impl IntoIterator<Item = &'a MachineOptions>) {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").unwrap_or_default();
+    for template in options
+        .into_iter()
+        .filter_map(|options| options.cpu_template.as_deref())
+    {
+        if template_supported(template, &cpuinfo) == Some(false) {
+            tracing::warn!(
+                "CPU template {} is probably not supported by this host CPU",
+                template
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> MachineConfig {
+        serde_json::from_str(include_str!("../fixtures/machine.json")).unwrap()
+    }
+
+    #[test]
+    fn test_optional_fields_are_omitted_unless_set() {
+        let json = serde_json::to_value(base()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "vcpu_count": 2, "mem_size_mib": 128 })
+        );
+
+        let fields = [
+            ("cpu_template", "cpu_template=T2S", serde_json::json!("T2S")),
+            ("smt", "smt=false", serde_json::json!(false)),
+            (
+                "track_dirty_pages",
+                "track_dirty_pages=true",
+                serde_json::json!(true),
+            ),
+        ];
+        for (field, raw, expected) in fields {
+            let options = MachineOptions::parse(raw).unwrap();
+            let json = serde_json::to_value(base().with_options(&options)).unwrap();
+            assert_eq!(json[field], expected, "{raw}");
+            assert_eq!(json.as_object().unwrap().len(), 3, "{raw}");
+        }
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let global = MachineOptions::parse("cpu_template=t2; smt=false").unwrap();
+        assert_eq!(global.cpu_template.as_deref(), Some("T2"));
+
+        let profiles = parse_profile_options("ml:cpu_template=None,numpy:smt=true").unwrap();
+        let ml = global.overlay(&profiles["ml"]);
+        assert_eq!(ml.cpu_template.as_deref(), Some("None"));
+        assert_eq!(ml.smt, Some(false));
+        assert_eq!(global.overlay(&profiles["numpy"]).smt, Some(true));
+
+        assert!(MachineOptions::parse("cpu_template=T9").is_err());
+        assert!(MachineOptions::parse("smt=maybe").is_err());
+        assert!(MachineOptions::parse("hugepages=true").is_err());
+        assert!(parse_profile_options("ml").is_err());
+    }
+
+    #[test]
+    fn test_template_supported() {
+        let intel = "processor\t: 0\nvendor_id\t: GenuineIntel\n";
+        let amd = "processor\t: 0\nvendor_id\t: AuthenticAMD\n";
+        assert_eq!(template_supported("T2S", intel), Some(true));
+        assert_eq!(template_supported("T2S", amd), Some(false));
+        assert_eq!(template_supported("T2A", amd), Some(true));
+        assert_eq!(template_supported("V1N1", intel), Some(false));
+        assert_eq!(template_supported("None", amd), Some(true));
+        assert_eq!(template_supported("T2", ""), None);
+    }
+}
@@ -1,173 +1,5140 @@
+use axum::middleware;
 use axum::{
-    Router, extract::Json, http::StatusCode, response::Json as ResponseJson, routing::post,
+    Router,
+    body::Body,
+    extract::{DefaultBodyLimit, Extension, FromRequest, MatchedPath, Path, Query, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{
+        Html, IntoResponse, Json as ResponseJson, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{delete, get, post},
 };
-use firecracker_poc::{ExecuteRequest, ExecuteResponse, create_error_response, run_in_vm, runner};
-use std::net::SocketAddr;
+use firecracker_poc::admission::{ADMISSION_RETRY_AFTER_SECS, HostProbe, ResourceUsage};
+use firecracker_poc::arch;
+use firecracker_poc::artifacts::{self, ArtifactError, Offloader};
+use firecracker_poc::audit::{AuditLog, PendingAudit};
+use firecracker_poc::auth::{self, ApiKeyId, ApiKeys};
+use firecracker_poc::backend::BackendKind;
+use firecracker_poc::boot_report::{self, BOOT_REPORTS, BootReport};
+use firecracker_poc::breaker::BreakerState;
+use firecracker_poc::config::{Config, runner_config, shared_runner_config};
+use firecracker_poc::cors;
+use firecracker_poc::debug_state::{self, ConfigSummary, DebugState};
+use firecracker_poc::deps;
+use firecracker_poc::events;
+use firecracker_poc::executor::{ExecutorService, ExecutorStats, PooledVm};
+use firecracker_poc::history::{EXECUTION_HISTORY, ExecutionRecord};
+use firecracker_poc::idempotency::{
+    self, Claim, IDEMPOTENCY_KEY_HEADER, IdempotencyStore, MAX_IDEMPOTENCY_KEY_LEN, StoredResponse,
+};
+use firecracker_poc::images::{self, ImportSpec};
+use firecracker_poc::integrity::{self, ArtifactIntegrity};
+use firecracker_poc::jobs::{
+    self, Job, JobQueue, JobRequest, JobStore, JobWorkers, MemoryJobQueue, MemoryJobStore,
+    QueuedJob,
+};
+use firecracker_poc::listen::Listener;
+use firecracker_poc::machine;
+use firecracker_poc::payload::{Format, Payload, PayloadRejection};
+use firecracker_poc::privilege::{self, NetworkPrivilege};
+use firecracker_poc::process_usage;
+use firecracker_poc::quota::{QuotaErrorResponse, TenantUsage};
+use firecracker_poc::rate_limit::{self, RateLimiter};
+use firecracker_poc::readiness;
+use firecracker_poc::reference_data;
+use firecracker_poc::reload::{self, ReloadOutcome, Reloader, Tunables};
+use firecracker_poc::rootfs::{self, RootfsSpec};
+use firecracker_poc::runner::{Live, LiveExecution, OutputEvent, QuickOptions};
+use firecracker_poc::selftest::{self, SelfTest, SelfTestReport};
+use firecracker_poc::service::{ExecutionService, ExecutionUpdates, Rejection};
+use firecracker_poc::sub_pool::SubPoolState;
+use firecracker_poc::systemd;
+use firecracker_poc::telemetry::MetricsExporter;
+use firecracker_poc::version::{self, FirecrackerVersion};
+use firecracker_poc::webhook;
+use firecracker_poc::workload::WorkloadStats;
+use firecracker_poc::{
+    ErrorResponse, ExecuteRequest, ExecuteResponse, ExecutionError, HealthResponse,
+    QUICK_MAX_CODE_BYTES, QuickExecuteRequest, create_error_response, generate_request_id, runner,
+    telemetry,
+};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
+use tokio_stream::{Stream, StreamExt};
 use tower::ServiceBuilder;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::decompression::RequestDecompressionLayer;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
-use tracing::{debug, error, info};
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+/// Header carrying the request ID assigned by `SetRequestIdLayer`
+const X_REQUEST_ID: &str = "x-request-id";
+/// Response header naming the VM an execution ran on
+const X_VM_ID: &str = "x-vm-id";
+/// Request header with the milliseconds the client waits for a response; shortens the
+/// server's deadline, never extends it
+const X_DEADLINE_MS: &str = "x-deadline-ms";
+
+/// Number of executions returned by `/admin/executions` when no limit is given
+const DEFAULT_EXECUTIONS_LIMIT: usize = 50;
+
+/// Media type of the newline-delimited JSON output stream of `/execute`
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+#[derive(Deserialize, IntoParams)]
+struct ExecuteQuery {
+    /// `ndjson` streams the output as JSON lines, as does `Accept: application/x-ndjson`
+    format: Option<String>,
+}
+
+/// Media types whose whole body `/execute` takes as the code
+const PLAIN_CODE_CONTENT_TYPES: [&str; 2] = ["text/plain", "application/x-python"];
+
+/// Options of a plain-text `/execute` submission, which has no JSON body to carry them
+#[derive(Deserialize, IntoParams)]
+struct PlainCodeQuery {
+    /// Language of the code; only `python` is supported
+    language: Option<String>,
+    /// Rootfs image to boot instead of the default one, by name
+    image: Option<String>,
+    /// Pre-built dependency image to attach, by profile name
+    deps_profile: Option<String>,
+    /// Run reproducibly on a fresh VM
+    #[serde(default)]
+    deterministic: bool,
+}
+
+/// Body of an `/execute` request: an `ExecuteRequest` in a negotiated format, or raw code sent
+/// as `text/plain` or `application/x-python` with its options in the query string
+struct ExecuteBody(Payload<ExecuteRequest>);
+
+impl<S: Send + Sync> FromRequest<S> for ExecuteBody {
+    type Rejection = PayloadRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let essence = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        if !essence.is_some_and(|essence| PLAIN_CODE_CONTENT_TYPES.contains(&essence.as_str())) {
+            return Payload::from_request(request, state).await.map(Self);
+        }
+        let Query(query) = Query::<PlainCodeQuery>::try_from_uri(request.uri())
+            .map_err(|e| PayloadRejection::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+        if let Some(language) = query.language.filter(|l| !l.eq_ignore_ascii_case("python")) {
+            return Err(PayloadRejection::new(
+                StatusCode::BAD_REQUEST,
+                format!("Unsupported language '{language}'; only python is supported"),
+            ));
+        }
+        let bytes = axum::body::Bytes::from_request(request, state)
+            .await
+            .map_err(|rejection| {
+                PayloadRejection::new(rejection.status(), rejection.body_text())
+            })?;
+        let code = String::from_utf8(bytes.into()).map_err(|_| {
+            PayloadRejection::new(StatusCode::BAD_REQUEST, "Code is not valid UTF-8")
+        })?;
+        let request = ExecuteRequest {
+            code: Some(code),
+            image: query.image,
+            deps_profile: query.deps_profile,
+            deterministic: query.deterministic,
+            ..Default::default()
+        };
+        Ok(Self(Payload::new(Format::Json, request)))
+    }
+}
 
 /// Handler for the /execute endpoint
+#[utoipa::path(
+    post,
+    path = "/execute",
+    request_body(
+        content(
+            (ExecuteRequest = "application/json"),
+            (ExecuteRequest = "application/msgpack"),
+            (String = "text/plain"),
+            (String = "application/x-python"),
+        )
+    ),
+    params(ExecuteQuery, PlainCodeQuery),
+    responses(
+        (status = 200, description = "The code ran; `success` tells whether it exited cleanly", body = ExecuteResponse,
+            headers(
+                ("x-vm-id" = String, description = "VM the code ran on; absent for cached results"),
+                ("idempotency-replayed" = String, description = "`true` when this is the stored response to an earlier request with the same `Idempotency-Key`"),
+            )),
+        (status = 200, description = "With `format=ndjson`: `queued` lines with the place in the queue while waiting for a permit, `stdout` and `stderr` lines, then a `result` line carrying the response, also when the deadline passes", content_type = "application/x-ndjson"),
+        (status = 400, description = "Malformed request (`bad_request`)", body = ErrorResponse),
+        (status = 400, description = "Invalid program or options, or an unknown deps profile or image", body = ErrorResponse),
+        (status = 413, description = "Body too large (`payload_too_large`)", body = ErrorResponse),
+        (status = 413, description = "Code too long (`code_too_long`)", body = ErrorResponse),
+        (status = 415, description = "Unsupported `Content-Type`, or a body in the other format (`unsupported_media_type`)", body = ErrorResponse),
+        (status = 422, description = "Rejected by a screening rule (`screened`)", body = ErrorResponse),
+        (status = 422, description = "Empty code (`empty_code`)", body = ErrorResponse),
+        (status = 422, description = "`Idempotency-Key` already used for a different request (`idempotency_key_reused`)", body = ErrorResponse),
+        (status = 429, description = "Rate limited, or too many executions queued ahead of this one (`overloaded`)", body = ErrorResponse),
+        (status = 429, description = "The API key's tenant reached a quota", body = QuotaErrorResponse),
+        (status = 503, description = "No capacity (`resource_exhausted`), or no VM came free in time (`pool_exhausted`); retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 503, description = "VM creation keeps failing and no pooled VM was free (`vm_creation_unavailable`), or VM networking lacks privileges (`privilege_error`)", body = ErrorResponse),
+        (status = 503, description = "The server is draining before it shuts down (`shutting_down`)", body = ErrorResponse),
+        (status = 503, description = "Too many requests with an `Idempotency-Key` in flight (`idempotency_store_full`)", body = ErrorResponse),
+        (status = 500, description = "Running the code failed; `code` names the failure", body = ErrorResponse),
+        (status = 504, description = "No result within `FC_EXECUTE_DEADLINE_SECS` or `X-Deadline-Ms` (`timeout`)", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
 async fn execute_handler(
-    Json(payload): Json<ExecuteRequest>,
-) -> Result<ResponseJson<ExecuteResponse>, (StatusCode, ResponseJson<ExecuteResponse>)> {
-    debug!("Received execute request with code: {}", payload.code);
+    State(state): State<AppState>,
+    key_id: Option<Extension<ApiKeyId>>,
+    Query(query): Query<ExecuteQuery>,
+    headers: HeaderMap,
+    payload: Result<ExecuteBody, PayloadRejection>,
+) -> Result<Response, Response> {
+    // Errors are rendered in the format the caller accepts, like successful responses
+    let format = Format::from_accept(&headers);
+    if let Some(refusal) = state.shutdown.refusal(format) {
+        return Err(refusal);
+    }
+    let ExecuteBody(payload) =
+        payload.map_err(|rejection| body_rejection(&state.config, rejection, format))?;
+    let key_id = key_id.map(|Extension(ApiKeyId(id))| id);
+    let client_deadline = match headers
+        .get(X_DEADLINE_MS)
+        .map(|v| v.to_str().ok()?.trim().parse().ok())
+    {
+        None => None,
+        Some(Some(ms)) => Some(std::time::Duration::from_millis(ms)),
+        Some(None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Payload::new(
+                    format,
+                    ErrorResponse::new(
+                        "invalid_options",
+                        "X-Deadline-Ms must be a whole number of milliseconds",
+                    ),
+                ),
+            )
+                .into_response());
+        }
+    };
+    let Some(idempotency_key) = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .filter(|_| state.idempotency.enabled())
+    else {
+        return execute(
+            state,
+            query,
+            headers,
+            payload.value,
+            key_id,
+            format,
+            client_deadline,
+        )
+        .await;
+    };
+    let invalid = |message: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Payload::new(format, ErrorResponse::new("invalid_options", message)),
+        )
+            .into_response()
+    };
+    let idempotency_key = match idempotency_key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => key.to_string(),
+        _ => {
+            return Err(invalid(&format!(
+                "Idempotency-Key must be 1 to {MAX_IDEMPOTENCY_KEY_LEN} visible ASCII characters"
+            )));
+        }
+    };
+    // A stream is sent as it is produced, so there is no finished response to replay
+    if wants_ndjson(&query, &headers) {
+        return Err(invalid(
+            "Idempotency-Key cannot be combined with streamed NDJSON output",
+        ));
+    }
+    let body = serde_json::to_vec(&payload.value).expect("execute requests serialize");
+    let claim = state
+        .idempotency
+        .claim(
+            key_id.as_deref().unwrap_or_default(),
+            &idempotency_key,
+            &idempotency::body_hash(&body),
+        )
+        .await;
+    let guard = match claim {
+        Claim::Execute(guard) => guard,
+        Claim::Replay(response) => return Ok(response.replay()),
+        Claim::Conflict => {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Payload::new(
+                    format,
+                    ErrorResponse::new(
+                        "idempotency_key_reused",
+                        "Idempotency-Key was already used for a different request",
+                    ),
+                ),
+            )
+                .into_response());
+        }
+        Claim::Full => {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, ADMISSION_RETRY_AFTER_SECS.to_string())],
+                Payload::new(
+                    format,
+                    ErrorResponse::new(
+                        "idempotency_store_full",
+                        "Too many requests with an Idempotency-Key are in flight",
+                    ),
+                ),
+            )
+                .into_response());
+        }
+    };
+    let response = execute(
+        state,
+        query,
+        headers,
+        payload.value,
+        key_id,
+        format,
+        client_deadline,
+    )
+    .await
+    .unwrap_or_else(|response| response);
+    // Refused before anything ran, or failed on our side (a timeout included), so a retry
+    // should get another chance rather than the same failure for the whole TTL
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        return Ok(response);
+    }
+    match StoredResponse::capture(response).await {
+        Ok(response) => {
+            guard.complete(response.clone());
+            Ok(response.into_response())
+        }
+        Err(e) => Err(rejection_response(
+            Rejection::Internal(e.to_string()),
+            format,
+        )),
+    }
+}
+
+/// Run an `/execute` request, streaming its output or answering once it finishes
+async fn execute(
+    state: AppState,
+    query: ExecuteQuery,
+    headers: HeaderMap,
+    payload: ExecuteRequest,
+    key_id: Option<String>,
+    format: Format,
+    client_deadline: Option<std::time::Duration>,
+) -> Result<Response, Response> {
+    let request_id = headers
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    let audit = state.audit.clone().map(|log| {
+        let pending = PendingAudit::new(&payload, &request_id, key_id.as_deref());
+        (log, pending)
+    });
+    let client_deadline = client_deadline.filter(|d| *d < state.config.execute_deadline);
+    if wants_ndjson(&query, &headers) {
+        return Ok(ndjson_response(
+            &state,
+            payload,
+            request_id,
+            key_id,
+            audit,
+            client_deadline,
+        ));
+    }
+    let deadline = client_deadline.unwrap_or(state.config.execute_deadline);
+    // Detached, so a VM that is still busy when the deadline passes is returned to the pool or
+    // cleaned up as usual instead of being dropped mid-execution. The runner spends no more
+    // than the deadline on it either, so a boot never eats the time the code needed to run.
+    let execution = tokio::spawn({
+        let service = state.service.clone();
+        let request_id = request_id.clone();
+        async move {
+            service
+                .execute_within(payload, request_id, key_id.as_deref(), deadline)
+                .await
+        }
+    });
+    let abort = execution.abort_handle();
+    // Failures carry their error code along for the audit log
+    let outcome = match tokio::time::timeout(deadline, execution).await {
+        Ok(Ok(result)) => {
+            result.map_err(|rejection| (rejection.code(), rejection_response(rejection, format)))
+        }
+        Ok(Err(e)) => Err((
+            "internal",
+            rejection_response(Rejection::Internal(e.to_string()), format),
+        )),
+        Err(_) => {
+            // Nobody waits for the result past the client's own deadline; the VM is discarded
+            if client_deadline.is_some() {
+                abort.abort();
+            }
+            let message = deadline_exceeded(&request_id, deadline);
+            Err((
+                "timeout",
+                (
+                    StatusCode::GATEWAY_TIMEOUT,
+                    Payload::new(format, ErrorResponse::new("timeout", message)),
+                )
+                    .into_response(),
+            ))
+        }
+    };
+    if let Some((log, pending)) = audit {
+        log.record(pending.finish(outcome.as_ref().map_err(|(code, _)| *code)));
+    }
+    let mut response = outcome.map_err(|(_, response)| response)?;
+    if let Some(offloader) = &state.artifacts {
+        offloader.offload(&request_id, &mut response).await;
+    }
+    let vm_id = response.vm_id.take();
+    let mut response = Payload::new(format, response).into_response();
+    if let Some(vm_id) = vm_id.and_then(|id| header::HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert(X_VM_ID, vm_id);
+    }
+    Ok(response)
+}
+
+/// Run a snippet on a warm pooled VM, failing fast instead of queueing behind a boot; for
+/// probes and examples where even pool-hit latency matters
+#[utoipa::path(
+    post,
+    path = "/execute/quick",
+    request_body(
+        content(
+            (QuickExecuteRequest = "application/json"),
+            (QuickExecuteRequest = "application/msgpack"),
+        )
+    ),
+    responses(
+        (status = 200, description = "The code ran; `success` tells whether it exited cleanly", body = ExecuteResponse,
+            headers(("x-vm-id" = String, description = "VM the code ran on; absent for cached results"))),
+        (status = 400, description = "Malformed request (`bad_request`)", body = ErrorResponse),
+        (status = 413, description = "Code over 1 KiB (`code_too_long`)", body = ErrorResponse),
+        (status = 422, description = "A field other than `code`, or rejected by a screening rule (`screened`)", body = ErrorResponse),
+        (status = 422, description = "Empty code (`empty_code`)", body = ErrorResponse),
+        (status = 429, description = "Rate limited, or the API key's tenant reached a quota", body = ErrorResponse),
+        (status = 503, description = "No warm VM is available (`no_warm_vm`); retry after `Retry-After` seconds", body = ErrorResponse),
+        (status = 504, description = "No result within `FC_QUICK_TIMEOUT_MS` (`timeout`)", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn execute_quick_handler(
+    State(state): State<AppState>,
+    key_id: Option<Extension<ApiKeyId>>,
+    headers: HeaderMap,
+    payload: Result<Payload<QuickExecuteRequest>, PayloadRejection>,
+) -> Result<Response, Response> {
+    let format = Format::from_accept(&headers);
+    if let Some(refusal) = state.shutdown.refusal(format) {
+        return Err(refusal);
+    }
+    let Payload { value: request, .. } =
+        payload.map_err(|rejection| body_rejection(&state.config, rejection, format))?;
+    if request.code.len() > QUICK_MAX_CODE_BYTES {
+        let message = format!("Quick executions take at most {QUICK_MAX_CODE_BYTES} bytes of code");
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Payload::new(format, ErrorResponse::new("code_too_long", message)),
+        )
+            .into_response());
+    }
+    let key_id = key_id.map(|Extension(ApiKeyId(id))| id);
+    let request_id = headers
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    let audit = state.audit.clone().map(|log| {
+        let payload = ExecuteRequest {
+            code: Some(request.code.clone()),
+            ..Default::default()
+        };
+        (
+            log,
+            PendingAudit::new(&payload, &request_id, key_id.as_deref()),
+        )
+    });
+    let quick = QuickOptions {
+        timeout: state.config.quick_timeout,
+    };
+    // Output is returned inline, never offloaded to artifacts or streamed
+    let outcome = state
+        .service
+        .execute_quick(request.code, request_id, key_id.as_deref(), quick)
+        .await;
+    if let Some((log, pending)) = audit {
+        log.record(pending.finish(outcome.as_ref().map_err(Rejection::code)));
+    }
+    let mut response = outcome.map_err(|rejection| rejection_response(rejection, format))?;
+    let vm_id = response.vm_id.take();
+    let mut response = Payload::new(format, response).into_response();
+    if let Some(vm_id) = vm_id.and_then(|id| header::HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert(X_VM_ID, vm_id);
+    }
+    Ok(response)
+}
+
+/// Run code in a sandbox kept by an execution with `keep_alive_seconds`, on the same VM and
+/// with the files earlier executions left
+#[utoipa::path(
+    post,
+    path = "/sandboxes/{id}/execute",
+    params(("id" = String, Path, description = "`sandbox_id` of the execution that kept it")),
+    request_body(
+        content(
+            (ExecuteRequest = "application/json"),
+            (ExecuteRequest = "application/msgpack"),
+        )
+    ),
+    responses(
+        (status = 200, description = "The code ran; `success` tells whether it exited cleanly", body = ExecuteResponse,
+            headers(("x-vm-id" = String, description = "VM the code ran on"))),
+        (status = 400, description = "Malformed request, or an option that sets up a VM at boot", body = ErrorResponse),
+        (status = 400, description = "Invalid program or options", body = ErrorResponse),
+        (status = 404, description = "Unknown sandbox, or another API key's (`sandbox_not_found`)", body = ErrorResponse),
+        (status = 409, description = "Another execution is running in the sandbox (`sandbox_busy`)", body = ErrorResponse),
+        (status = 410, description = "The sandbox expired or was deleted (`sandbox_expired`)", body = ErrorResponse),
+        (status = 429, description = "Rate limited, or the API key's tenant reached its execution quota", body = ErrorResponse),
+        (status = 503, description = "The server is draining before it shuts down", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn sandbox_execute_handler(
+    State(state): State<AppState>,
+    Path(sandbox_id): Path<String>,
+    key_id: Option<Extension<ApiKeyId>>,
+    headers: HeaderMap,
+    payload: Result<Payload<ExecuteRequest>, PayloadRejection>,
+) -> Result<Response, Response> {
+    let format = Format::from_accept(&headers);
+    if let Some(refusal) = state.shutdown.refusal(format) {
+        return Err(refusal);
+    }
+    let Payload { value: payload, .. } =
+        payload.map_err(|rejection| body_rejection(&state.config, rejection, format))?;
+    let key_id = key_id.map(|Extension(ApiKeyId(id))| id);
+    let request_id = headers
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    let audit = state.audit.clone().map(|log| {
+        let pending = PendingAudit::new(&payload, &request_id, key_id.as_deref());
+        (log, pending)
+    });
+    let outcome = state
+        .service
+        .execute_in_sandbox(&sandbox_id, payload, request_id, key_id.as_deref())
+        .await;
+    if let Some((log, pending)) = audit {
+        log.record(pending.finish(outcome.as_ref().map_err(Rejection::code)));
+    }
+    let mut response = outcome.map_err(|rejection| rejection_response(rejection, format))?;
+    let vm_id = response.vm_id.take();
+    let mut response = Payload::new(format, response).into_response();
+    if let Some(vm_id) = vm_id.and_then(|id| header::HeaderValue::from_str(&id).ok()) {
+        response.headers_mut().insert(X_VM_ID, vm_id);
+    }
+    Ok(response)
+}
+
+/// Dispose of a sandbox before its deadline, discarding its VM; one running an execution is
+/// discarded once the execution is done
+#[utoipa::path(
+    delete,
+    path = "/sandboxes/{id}",
+    params(("id" = String, Path, description = "`sandbox_id` of the execution that kept it")),
+    responses(
+        (status = 204, description = "The sandbox is gone"),
+        (status = 404, description = "Unknown sandbox, or another API key's (`sandbox_not_found`)", body = ErrorResponse),
+        (status = 410, description = "The sandbox already expired or was deleted (`sandbox_expired`)", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn delete_sandbox_handler(
+    State(state): State<AppState>,
+    Path(sandbox_id): Path<String>,
+    key_id: Option<Extension<ApiKeyId>>,
+) -> Result<StatusCode, ExecutionError> {
+    let key_id = key_id.map(|Extension(ApiKeyId(id))| id);
+    state
+        .service
+        .executor
+        .delete_sandbox(&sandbox_id, key_id.as_deref())?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Time an execute endpoint to its response headers, labeled by its route, so the endpoints'
+/// latencies can be compared
+async fn record_execute_latency(
+    endpoint: MatchedPath,
+    request: Request,
+    next: middleware::Next,
+) -> Response {
+    let start = std::time::Instant::now();
+    let response = next.run(request).await;
+    telemetry::observe_histogram(
+        "fc_execute_latency_ms",
+        &[("endpoint", endpoint.as_str())],
+        start.elapsed().as_secs_f64() * 1000.0,
+    );
+    response
+}
+
+/// Response to a request body that could not be extracted
+fn body_rejection(config: &Config, rejection: PayloadRejection, format: Format) -> Response {
+    let message = if rejection.status == StatusCode::PAYLOAD_TOO_LARGE {
+        format!(
+            "Request body exceeds maximum size of {} bytes",
+            config.max_body_bytes
+        )
+    } else {
+        rejection.message.clone()
+    };
+    (
+        rejection.status,
+        Payload::new(format, ErrorResponse::new(rejection.code(), message)),
+    )
+        .into_response()
+}
+
+fn wants_ndjson(query: &ExecuteQuery, headers: &HeaderMap) -> bool {
+    query.format.as_deref() == Some("ndjson")
+        || headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains(NDJSON_CONTENT_TYPE))
+}
+
+/// Note that no result came within `deadline`, returning the message the caller gets
+fn deadline_exceeded(request_id: &str, deadline: std::time::Duration) -> String {
+    tracing::warn!(request_id, "No result within {:?}", deadline);
+    telemetry::increment_counter("fc_execute_deadline_exceeded_total", &[], 1);
+    format!(
+        "Execution did not finish within {} seconds",
+        deadline.as_secs_f64()
+    )
+}
+
+/// Stream the execution's output as JSON lines while the code prints it. The body always ends
+/// with a `result` line, which carries the error when the request is rejected, the execution
+/// fails or the deadline passes, so parsers have a deterministic end marker.
+fn ndjson_response(
+    state: &AppState,
+    payload: ExecuteRequest,
+    request_id: String,
+    key_id: Option<String>,
+    audit: Option<(AuditLog, PendingAudit)>,
+    client_deadline: Option<std::time::Duration>,
+) -> Response {
+    let service = state.service.clone();
+    let artifacts = state.artifacts.clone();
+    let deadline = client_deadline.unwrap_or(state.config.execute_deadline);
+    let (lines, body) = tokio::sync::mpsc::channel::<Result<Vec<u8>, Infallible>>(16);
+    tokio::spawn(async move {
+        // A client that went away gets no more lines, but the execution is still audited
+        let expires = tokio::time::Instant::now() + deadline;
+        let (queue_updates, mut places) = tokio::sync::mpsc::unbounded_channel();
+        let mut execution = LiveExecution::start(|output| {
+            let request_id = request_id.clone();
+            async move {
+                let updates = ExecutionUpdates {
+                    queue: Some(queue_updates),
+                    output: Some(output),
+                };
+                service
+                    .execute_streaming(
+                        payload,
+                        request_id,
+                        key_id.as_deref(),
+                        updates,
+                        Some(deadline),
+                    )
+                    .await
+            }
+        });
+        let outcome = loop {
+            let next = tokio::select! {
+                biased;
+                // A request waiting for a permit hears where it stands until it gets one, so
+                // a long wait doesn't look like a hang
+                Some(place) = places.recv() => {
+                    let line = ndjson_line(&serde_json::json!({
+                        "type": "queued",
+                        "position": place.position,
+                        "eta_ms": place.eta_ms,
+                    }));
+                    let _ = lines.send(line).await;
+                    continue;
+                }
+                next = tokio::time::timeout_at(expires, execution.next()) => next,
+            };
+            match next {
+                Ok(Some(Live::Output(event))) => {
+                    let _ = lines.send(ndjson_line(&event)).await;
+                }
+                Ok(Some(Live::Finished(Ok(result)))) => {
+                    break result.map_err(|rejection| (rejection.code(), rejection.to_string()));
+                }
+                Ok(Some(Live::Finished(Err(e)))) => {
+                    break Err(("internal", format!("Execution failed: {e}")));
+                }
+                Ok(None) => break Err(("internal", "Execution ended without a result".into())),
+                Err(_) => {
+                    // Nobody waits for the result past the client's own deadline; the VM is
+                    // discarded
+                    if client_deadline.is_some() {
+                        execution.abort();
+                    }
+                    break Err(("timeout", deadline_exceeded(&request_id, deadline)));
+                }
+            }
+        };
+        if let Some((log, pending)) = audit {
+            log.record(pending.finish(outcome.as_ref().map_err(|(code, _)| *code)));
+        }
+        let result = match outcome {
+            Ok(mut response) => {
+                if let Some(offloader) = &artifacts {
+                    offloader.offload(&request_id, &mut response).await;
+                }
+                response
+            }
+            Err((_, message)) => {
+                let stderr = OutputEvent::Stderr {
+                    data: message.clone(),
+                };
+                let _ = lines.send(ndjson_line(&stderr)).await;
+                create_error_response(message)
+            }
+        };
+        let _ = lines.send(ndjson_line(&OutputEvent::result(result))).await;
+    });
+    (
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        Body::from_stream(ReceiverStream::new(body)),
+    )
+        .into_response()
+}
+
+/// `value` as one line of an NDJSON body
+fn ndjson_line(value: &impl Serialize) -> Result<Vec<u8>, Infallible> {
+    let mut line = serde_json::to_vec(value).expect("NDJSON lines serialize");
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// HTTP response to a request the execution service turned away, with `Retry-After` when a
+/// retry may succeed
+fn rejection_response(rejection: Rejection, format: Format) -> Response {
+    let status = rejection.status();
+    let retry_after = rejection
+        .retry_after_secs()
+        .map(|secs| [(header::RETRY_AFTER, secs.to_string())]);
+    match rejection {
+        Rejection::QuotaExceeded(quota) => (
+            status,
+            retry_after,
+            Payload::new(format, QuotaErrorResponse::from(quota)),
+        )
+            .into_response(),
+        rejection => (
+            status,
+            retry_after,
+            Payload::new(
+                format,
+                ErrorResponse::new(rejection.code(), rejection.to_string()),
+            ),
+        )
+            .into_response(),
+    }
+}
+
+/// Queue an execution and answer at once; the job is polled at `/jobs/{id}` or reported to
+/// its `callback_url`
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    request_body = JobRequest,
+    responses(
+        (status = 202, description = "Job accepted; `Location` points at it", body = Job),
+        (status = 400, description = "Malformed request or refused `callback_url` (`bad_request`)", body = ErrorResponse),
+        (status = 400, description = "Invalid program or options, or an unknown deps profile or image", body = ErrorResponse),
+        (status = 413, description = "Body too large (`payload_too_large`)", body = ErrorResponse),
+        (status = 413, description = "Code too long (`code_too_long`)", body = ErrorResponse),
+        (status = 422, description = "Rejected by a screening rule (`screened`)", body = ErrorResponse),
+        (status = 422, description = "Empty code (`empty_code`)", body = ErrorResponse),
+        (status = 429, description = "Rate limited", body = ErrorResponse),
+        (status = 429, description = "The API key's tenant used up its execution quota", body = QuotaErrorResponse),
+        (status = 503, description = "The server is draining before it shuts down", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn submit_job_handler(
+    State(state): State<AppState>,
+    key_id: Option<Extension<ApiKeyId>>,
+    headers: HeaderMap,
+    payload: Result<Payload<JobRequest>, PayloadRejection>,
+) -> Result<Response, Response> {
+    let format = Format::from_accept(&headers);
+    if let Some(refusal) = state.shutdown.refusal(format) {
+        return Err(refusal);
+    }
+    let request = payload
+        .map_err(|rejection| body_rejection(&state.config, rejection, format))?
+        .value;
+    let key_id = key_id.map(|Extension(ApiKeyId(id))| id);
+    let reject = |rejection| rejection_response(rejection, format);
+    state
+        .service
+        .check(&request.execute, key_id.as_deref())
+        .map_err(reject)?;
+    if let Some(url) = &request.callback_url {
+        webhook::validate_callback_url(url, &state.config.webhook)
+            .map_err(|e| reject(Rejection::BadRequest(e.to_string())))?;
+    }
+
+    let job = Job::new(generate_request_id(), request.callback_url);
+    state
+        .jobs
+        .put(&job)
+        .map_err(|e| reject(Rejection::Internal(e.to_string())))?;
+    state
+        .queue
+        .push(QueuedJob {
+            id: job.id.clone(),
+            request: request.execute,
+            key_id,
+            attempts: 0,
+        })
+        .await
+        .map_err(|e| reject(Rejection::Internal(e.to_string())))?;
+    Ok((
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/jobs/{}", job.id))],
+        Payload::new(format, job),
+    )
+        .into_response())
+}
+
+/// Current state of a job, including its result and callback delivery once finished
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    params(("id" = String, Path, description = "ID returned by `POST /jobs`")),
+    responses(
+        (status = 200, body = Job),
+        (status = 404, description = "Unknown or expired job", body = ErrorResponse),
+        (status = 500, description = "The job store failed", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let format = Format::from_accept(&headers);
+    match state.jobs.get(&id) {
+        Ok(Some(job)) => Payload::new(format, job).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Payload::new(
+                format,
+                ErrorResponse::new("not_found", format!("Job {id} not found")),
+            ),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Payload::new(format, ErrorResponse::new("store_error", e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Download an output stream stored as an artifact
+#[utoipa::path(
+    get,
+    path = "/artifacts/{job_id}/{name}",
+    params(
+        ("job_id" = String, Path, description = "Job or request ID of the execution"),
+        ("name" = String, Path, description = "`stdout` or `stderr`"),
+    ),
+    responses(
+        (status = 200, description = "Raw bytes of the stream", content_type = "application/octet-stream"),
+        (status = 404, description = "Unknown or expired artifact, or no artifact store", body = ErrorResponse),
+        (status = 500, description = "The artifact store failed", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn artifact_handler(
+    State(state): State<AppState>,
+    Path((job_id, name)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    let format = Format::from_accept(&headers);
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Payload::new(
+                format,
+                ErrorResponse::new("not_found", format!("Artifact {job_id}/{name} not found")),
+            ),
+        )
+            .into_response()
+    };
+    let Some(offloader) = &state.artifacts else {
+        return not_found();
+    };
+    match offloader.store.get(&job_id, &name).await {
+        Ok(bytes) => ([(header::CONTENT_TYPE, "application/octet-stream")], bytes).into_response(),
+        Err(ArtifactError::NotFound(_) | ArtifactError::InvalidName(_)) => not_found(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Payload::new(format, ErrorResponse::new("store_error", e.to_string())),
+        )
+            .into_response(),
+    }
+}
+
+/// Describe the /execute endpoint and its limits so clients can discover them
+#[utoipa::path(
+    options,
+    path = "/execute",
+    responses((status = 200, description = "Request size limits", body = serde_json::Value)),
+    security(("api_key" = []))
+)]
+async fn execute_options_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::ALLOW, "POST, OPTIONS")],
+        ResponseJson(serde_json::json!({
+            "max_body_bytes": state.config.max_body_bytes,
+            "max_code_length": state.config.max_code_length,
+        })),
+    )
+}
+
+/// Body of `/pool`
+#[derive(Serialize, ToSchema)]
+struct PoolResponse {
+    idle_vms: usize,
+    /// Pooled VMs with their age and use, oldest pooled first
+    vms: Vec<PooledVm>,
+    /// The same VMs by sub-pool, the default pool first
+    sub_pools: Vec<SubPoolState>,
+    executor: ExecutorStats,
+    resources: ResourceUsage,
+}
+
+/// Pool occupancy and host resource usage against the admission budgets
+#[utoipa::path(
+    get,
+    path = "/pool",
+    responses((status = 200, body = PoolResponse)),
+    security(("api_key" = []))
+)]
+async fn pool_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let executor = &state.service.executor;
+    let stats = executor.stats().await;
+    let usage = ResourceUsage::sample(executor.config(), &HostProbe);
+    usage.record();
+    ResponseJson(PoolResponse {
+        idle_vms: stats.idle_vms,
+        vms: executor.pooled_vms().await,
+        sub_pools: executor.sub_pools().await,
+        executor: stats,
+        resources: usage,
+    })
+}
+
+/// Health check endpoint
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, body = HealthResponse),
+        (status = 503, description = "A guest artifact no longer matches its hash, or VM networking lacks privileges", body = HealthResponse),
+    )
+)]
+async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let error = state.integrity.problem().or_else(|| {
+        state
+            .network_privilege
+            .and_then(|privilege| privilege.check().err())
+            .map(|e| e.to_string())
+    });
+    let vm_creation = state.service.executor.vm_creation_status();
+    let snapshot = state.service.executor.readiness_snapshot(&HostProbe).await;
+    let status = if error.is_some() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (
+        status,
+        ResponseJson(HealthResponse {
+            status: if error.is_some() {
+                "unhealthy"
+            } else if vm_creation.state != BreakerState::Closed {
+                "degraded"
+            } else {
+                "healthy"
+            }
+            .to_string(),
+            firecracker_version: state.firecracker_version,
+            error,
+            vm_creation: Some(vm_creation),
+            readiness: Some(readiness::assess(snapshot, &state.config.readiness)),
+            network_privilege: state.network_privilege,
+            selftest: None,
+        }),
+    )
+}
+
+/// Readiness to take new work; turns `503` once a shutdown signal arrives, while `/health`
+/// keeps answering `200` until the process exits, until the startup self-test has passed,
+/// and while the readiness score is below the configured thresholds
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, body = HealthResponse),
+        (status = 503, description = "The server is draining before it shuts down, hasn't passed its self-test yet, or can't serve a request quickly", body = HealthResponse),
+    )
+)]
+async fn ready_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let snapshot = state.service.executor.readiness_snapshot(&HostProbe).await;
+    let readiness = readiness::assess(snapshot, &state.config.readiness);
+    let selftest = &state.config.selftest;
+    let (status, label) = if state.shutdown.draining() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if selftest.enabled && !state.selftest.passed() {
+        (StatusCode::SERVICE_UNAVAILABLE, "self_test_pending")
+    } else if !readiness.ready {
+        (StatusCode::SERVICE_UNAVAILABLE, "not_ready")
+    } else {
+        (StatusCode::OK, "ready")
+    };
+    (
+        status,
+        ResponseJson(HealthResponse {
+            status: label.to_string(),
+            firecracker_version: state.firecracker_version,
+            error: None,
+            vm_creation: None,
+            readiness: Some(readiness),
+            network_privilege: None,
+            selftest: selftest.enabled.then(|| state.selftest.last()).flatten(),
+        }),
+    )
+}
+
+/// Versions of this service and of Firecracker
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses((status = 200, description = "Crate version, git SHA, Firecracker release and guest artifact hashes", body = serde_json::Value)),
+    security(("api_key" = []))
+)]
+async fn version_handler(State(state): State<AppState>) -> impl IntoResponse {
+    ResponseJson(serde_json::json!({
+        "version": version::CRATE_VERSION,
+        "git_sha": version::GIT_SHA,
+        "firecracker_version": state.firecracker_version,
+        "artifacts": state.integrity.digests(),
+    }))
+}
+
+/// Prometheus metrics endpoint
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus text exposition", body = String, content_type = "text/plain"),
+        (status = 404, description = "A push exporter is configured instead", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn metrics_handler(State(state): State<AppState>) -> Response {
+    // Push exporters leave the registry empty; an empty page would look like a healthy scrape
+    if state.config.metrics.exporter != MetricsExporter::Prometheus {
+        return not_found("Metrics are pushed by the configured exporter, not served here");
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        telemetry::METRICS.render(),
+    )
+        .into_response()
+}
+
+/// A live VM and the host resources its Firecracker process holds
+#[utoipa::path(
+    get,
+    path = "/vms/{id}",
+    params(("id" = String, Path, description = "ID of a live VM")),
+    responses(
+        (status = 200, description = "The VM", body = runner::VmDetails),
+        (status = 404, description = "Unknown VM", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn vm_handler(Path(vm_id): Path<String>) -> Response {
+    match runner::live_vm(&vm_id) {
+        Some(vm) => ResponseJson(vm.details()).into_response(),
+        None => not_found(format!("VM {vm_id} not found")),
+    }
+}
+
+/// Latest Firecracker metrics reported by a live VM
+#[utoipa::path(
+    get,
+    path = "/vms/{id}/fc-metrics",
+    params(("id" = String, Path, description = "ID of a live VM")),
+    responses(
+        (status = 200, description = "Metrics as written by Firecracker", body = serde_json::Value),
+        (status = 404, description = "Unknown VM or no metrics yet", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn fc_metrics_handler(Path(vm_id): Path<String>) -> Response {
+    let Some(vm) = runner::live_vm(&vm_id) else {
+        return not_found(format!("VM {vm_id} not found"));
+    };
+    match runner::read_firecracker_metrics(&vm).await {
+        Some(metrics) => ResponseJson(metrics).into_response(),
+        None => not_found(format!("No metrics reported yet for VM {vm_id}")),
+    }
+}
+
+/// Diagnostics of a VM that recently failed to boot with boot diagnostics on
+#[utoipa::path(
+    get,
+    path = "/vms/{id}/boot-report",
+    params(("id" = String, Path, description = "ID of a VM that failed to boot")),
+    responses(
+        (status = 200, description = "Console and Firecracker logs and configuration of the boot", body = BootReport),
+        (status = 404, description = "No report kept for the VM", body = ErrorResponse),
+    ),
+    security(("api_key" = []))
+)]
+async fn boot_report_handler(Path(vm_id): Path<String>) -> Response {
+    match BOOT_REPORTS.get(&vm_id) {
+        Some(report) => ResponseJson(report).into_response(),
+        None => not_found(format!("No boot report kept for VM {vm_id}")),
+    }
+}
+
+/// `404` with code `not_found`, for the JSON-only endpoints
+fn not_found(message: impl Into<String>) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        ResponseJson(ErrorResponse::new("not_found", message)),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+struct ExecutionsQuery {
+    /// Maximum number of executions to return
+    limit: Option<usize>,
+}
+
+/// Drop every cached execution result
+#[utoipa::path(
+    delete,
+    path = "/admin/cache",
+    responses((status = 200, description = "Number of results dropped", body = serde_json::Value)),
+    security(("api_key" = []))
+)]
+async fn clear_cache_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let cleared = state.service.cache.clear();
+    info!("Cleared {} cached results", cleared);
+    ResponseJson(serde_json::json!({ "cleared": cleared }))
+}
+
+/// Re-read the tunables, applying them only if every one is valid
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    responses(
+        (status = 200, description = "The new values took effect", body = ReloadOutcome),
+        (status = 422, description = "A value is invalid; the old ones stay", body = ReloadOutcome),
+    ),
+    security(("api_key" = []))
+)]
+async fn reload_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let outcome = state.reloader.reload();
+    let status = if outcome.applied {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    (status, ResponseJson(outcome))
+}
+
+/// Run the canary snippet now through a pooled or fresh VM, reporting how long each step
+/// took; a pass makes the server ready if the startup self-test hasn't passed yet
+#[utoipa::path(
+    post,
+    path = "/admin/selftest",
+    responses(
+        (status = 200, description = "The canary printed what it should", body = SelfTestReport),
+        (status = 503, description = "The canary failed or printed something else", body = SelfTestReport),
+    ),
+    security(("api_key" = []))
+)]
+async fn selftest_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let report = selftest::run(&state.service.executor, state.config.selftest.timeout, 0).await;
+    let status = if report.passed {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, ResponseJson(state.selftest.record(report)))
+}
+
+/// Most recent executions, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/executions",
+    params(ExecutionsQuery),
+    responses((status = 200, body = [ExecutionRecord])),
+    security(("api_key" = []))
+)]
+async fn executions_handler(Query(query): Query<ExecutionsQuery>) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(DEFAULT_EXECUTIONS_LIMIT);
+    ResponseJson(EXECUTION_HISTORY.recent(limit))
+}
+
+/// Pool, VMs, running executions, recent errors and background tasks as one document, for
+/// attaching to bug reports. The executor's parts are read under its locks, so they agree.
+#[utoipa::path(
+    get,
+    path = "/admin/state",
+    responses((status = 200, body = DebugState)),
+    security(("api_key" = []))
+)]
+async fn state_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let executor = &state.service.executor;
+    ResponseJson(DebugState {
+        taken_at: firecracker_poc::history::now_millis(),
+        version: version::CRATE_VERSION.to_string(),
+        config: ConfigSummary::new(&state.config, executor.config()),
+        executor: executor.state().await,
+        recent_errors: debug_state::recent_errors(),
+        recent_events: events::recent_events(),
+        tasks: debug_state::heartbeats(),
+    })
+}
+
+/// Quota usage of every tenant with a quota, ordered by API key identifier
+#[utoipa::path(
+    get,
+    path = "/admin/quotas",
+    responses((status = 200, body = [TenantUsage])),
+    security(("api_key" = []))
+)]
+async fn quotas_handler(State(state): State<AppState>) -> impl IntoResponse {
+    ResponseJson(state.service.quotas.usage())
+}
+
+/// Executions so far by language, rootfs image and deps profile: counts by outcome, code size
+/// and duration. The same figures `/metrics` exports, for deployments without Prometheus.
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    responses((status = 200, body = [WorkloadStats])),
+    security(("api_key" = []))
+)]
+async fn stats_handler(State(state): State<AppState>) -> impl IntoResponse {
+    ResponseJson(state.service.executor.workload_stats())
+}
+
+/// Server-Sent Events stream of VM lifecycle events
+#[utoipa::path(
+    get,
+    path = "/events",
+    responses((status = 200, description = "VM lifecycle events", content_type = "text/event-stream")),
+    security(("api_key" = []))
+)]
+async fn events_handler() -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(events::subscribe()).map(|item| {
+        let event = match item {
+            Ok(vm_event) => Event::default()
+                .event(vm_event.name())
+                .json_data(&vm_event)
+                .unwrap_or_default(),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Event::default()
+                .event("lagged")
+                .data(serde_json::json!({ "type": "lagged", "missed": missed }).to_string()),
+        };
+        Ok(event)
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// OpenAPI description of the HTTP API
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "firecracker-poc"),
+    paths(
+        execute_handler,
+        execute_quick_handler,
+        execute_options_handler,
+        sandbox_execute_handler,
+        delete_sandbox_handler,
+        submit_job_handler,
+        job_handler,
+        artifact_handler,
+        health_handler,
+        ready_handler,
+        version_handler,
+        pool_handler,
+        metrics_handler,
+        vm_handler,
+        fc_metrics_handler,
+        boot_report_handler,
+        executions_handler,
+        state_handler,
+        quotas_handler,
+        stats_handler,
+        clear_cache_handler,
+        reload_handler,
+        selftest_handler,
+        events_handler,
+    ),
+    modifiers(&BearerAuth)
+)]
+struct ApiDoc;
+
+/// Registers the `api_key` bearer scheme referenced by the authenticated routes
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "api_key",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+    }
+}
+
+/// Serve the OpenAPI document generated at startup
+async fn openapi_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        state.openapi.to_string(),
+    )
+}
+
+/// Swagger UI for `/openapi.json`, loaded from a CDN
+async fn docs_handler() -> impl IntoResponse {
+    Html(SWAGGER_UI_HTML)
+}
+
+const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>firecracker-poc API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+/// Shared state handed to every handler and middleware
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    api_keys: Arc<ApiKeys>,
+    rate_limiter: Arc<RateLimiter>,
+    service: ExecutionService,
+    /// Jobs submitted through `/jobs`
+    jobs: Arc<dyn JobStore>,
+    /// Jobs waiting for a worker, possibly on another instance
+    queue: Arc<dyn JobQueue>,
+    /// Destination of oversized output; `None` keeps all output inline
+    artifacts: Option<Offloader>,
+    /// Release of the Firecracker binary VMs run on, detected at startup
+    firecracker_version: Option<FirecrackerVersion>,
+    /// How VM networking is set up, probed at startup; `None` for backends without it
+    network_privilege: Option<NetworkPrivilege>,
+    /// OpenAPI document, rendered once
+    openapi: Arc<str>,
+    /// Writer of the execution audit log, when one is configured
+    audit: Option<AuditLog>,
+    /// Hashes of the guest artifacts and whether they still match
+    integrity: Arc<ArtifactIntegrity>,
+    /// Set once a shutdown signal arrives
+    shutdown: ShutdownState,
+    /// Tunables in effect, re-read on `SIGHUP` and `POST /admin/reload`
+    reloader: Arc<Reloader>,
+    /// Responses to `/execute` requests carrying an `Idempotency-Key`
+    idempotency: Arc<IdempotencyStore>,
+    /// Canary results; `/ready` waits for one to pass when the self-test is on
+    selftest: Arc<SelfTest>,
+}
+
+/// Whether the server is draining: new executions are refused while in-flight ones finish
+#[derive(Clone, Default)]
+struct ShutdownState(Arc<AtomicBool>);
+
+impl ShutdownState {
+    fn begin(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn draining(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Turn new work away with `503` and `Retry-After` once draining, so clients go elsewhere
+    /// instead of racing the pool teardown
+    fn refusal(&self, format: Format) -> Option<Response> {
+        if !self.draining() {
+            return None;
+        }
+        telemetry::increment_counter("fc_requests_rejected_draining_total", &[], 1);
+        Some(
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, ADMISSION_RETRY_AFTER_SECS.to_string())],
+                Payload::new(
+                    format,
+                    ErrorResponse::new("shutting_down", "Server is shutting down"),
+                ),
+            )
+                .into_response(),
+        )
+    }
+}
+
+impl AppState {
+    fn new(config: Config) -> Self {
+        let config = Arc::new(config);
+        let audit = AuditLog::spawn(&config.audit);
+        let runner = shared_runner_config();
+        let reloader = Arc::new(Reloader::new(
+            Tunables::from_config(&config, &runner),
+            runner.clone(),
+            config.tunables_file.clone(),
+        ));
+        Self {
+            api_keys: Arc::new(ApiKeys::new(&config.api_keys)),
+            rate_limiter: Arc::new(RateLimiter::new(
+                config.rate_limit,
+                config.rate_limit_overrides.clone(),
+            )),
+            service: ExecutionService::new(config.clone(), ExecutorService::new(runner)),
+            jobs: Arc::new(MemoryJobStore::new()),
+            queue: Arc::new(MemoryJobQueue::new()),
+            artifacts: None,
+            idempotency: Arc::new(IdempotencyStore::new(&config.idempotency)),
+            config,
+            firecracker_version: None,
+            network_privilege: None,
+            openapi: ApiDoc::openapi()
+                .to_json()
+                .expect("the OpenAPI document serializes")
+                .into(),
+            audit,
+            integrity: Arc::default(),
+            shutdown: ShutdownState::default(),
+            reloader,
+            selftest: Arc::default(),
+        }
+    }
+}
+
+impl AppState {
+    /// Start this instance's job workers
+    fn spawn_job_workers(&self) {
+        JobWorkers {
+            service: self.service.clone(),
+            store: self.jobs.clone(),
+            queue: self.queue.clone(),
+            offloader: self.artifacts.clone(),
+            webhook: self.config.webhook.clone(),
+        }
+        .spawn(self.config.job_workers, self.config.job_visibility_timeout);
+    }
+
+    /// Apply reloaded tunables as they come, and reload on `SIGHUP`
+    fn spawn_reloads(&self) {
+        reload::spawn_apply(
+            self.reloader.subscribe(),
+            self.service.executor.clone(),
+            self.rate_limiter.clone(),
+        );
+        let reloader = self.reloader.clone();
+        tokio::spawn(async move {
+            let mut hangups =
+                match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                    Ok(signal) => signal,
+                    Err(e) => {
+                        tracing::warn!("Cannot listen for SIGHUP: {}", e);
+                        return;
+                    }
+                };
+            while hangups.recv().await.is_some() {
+                info!("SIGHUP received, reloading tunables");
+                reloader.reload();
+            }
+        });
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+/// Create the application router
+fn create_app(state: AppState) -> Router {
+    let router = Router::new()
+        .route(
+            "/execute",
+            post(execute_handler)
+                .layer(middleware::from_fn_with_state(
+                    state.rate_limiter.clone(),
+                    rate_limit::enforce_rate_limit,
+                ))
+                .layer(middleware::from_fn(record_execute_latency))
+                .options(execute_options_handler),
+        )
+        .route(
+            "/execute/quick",
+            post(execute_quick_handler)
+                .layer(middleware::from_fn_with_state(
+                    state.rate_limiter.clone(),
+                    rate_limit::enforce_rate_limit,
+                ))
+                .layer(middleware::from_fn(record_execute_latency)),
+        )
+        .route(
+            "/sandboxes/{id}/execute",
+            post(sandbox_execute_handler).layer(middleware::from_fn_with_state(
+                state.rate_limiter.clone(),
+                rate_limit::enforce_rate_limit,
+            )),
+        )
+        .route("/sandboxes/{id}", delete(delete_sandbox_handler))
+        .route(
+            "/jobs",
+            post(submit_job_handler).layer(middleware::from_fn_with_state(
+                state.rate_limiter.clone(),
+                rate_limit::enforce_rate_limit,
+            )),
+        )
+        .route("/jobs/{id}", get(job_handler))
+        .route("/artifacts/{job_id}/{name}", get(artifact_handler))
+        .route("/health", get(health_handler))
+        .route("/ready", get(ready_handler))
+        .route("/version", get(version_handler))
+        .route("/pool", get(pool_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/vms/{id}", get(vm_handler))
+        .route("/vms/{id}/fc-metrics", get(fc_metrics_handler))
+        .route("/vms/{id}/boot-report", get(boot_report_handler))
+        .route("/admin/executions", get(executions_handler))
+        .route("/admin/state", get(state_handler))
+        .route("/admin/quotas", get(quotas_handler))
+        .route("/admin/stats", get(stats_handler))
+        .route("/admin/cache", delete(clear_cache_handler))
+        .route("/admin/reload", post(reload_handler))
+        .route("/admin/selftest", post(selftest_handler))
+        .route("/events", get(events_handler))
+        .route("/openapi.json", get(openapi_handler));
+    let router = if state.config.swagger_ui {
+        router.route("/docs", get(docs_handler))
+    } else {
+        router
+    };
+    // The limit applies to bodies as decompressed, so a small gzip bomb is cut off too
+    let router = router.layer(DefaultBodyLimit::max(state.config.max_body_bytes));
+    let router = if state.config.request_decompression {
+        router.layer(RequestDecompressionLayer::new())
+    } else {
+        router
+    };
+    let router = router.layer(middleware::from_fn_with_state(
+        state.api_keys.clone(),
+        auth::require_api_key,
+    ));
+
+    // Outside auth so browser preflights, which carry no credentials, are answered
+    let router = match &state.config.cors_origins {
+        Some(origins) => router.layer(middleware::from_fn_with_state(
+            cors::cors_layer(origins),
+            cors::apply_cors,
+        )),
+        None => router,
+    };
+    // Streamed output would sit in the encoder's buffer instead of reaching the client
+    let router = if state.config.response_compression {
+        router.layer(CompressionLayer::new().compress_when(
+            DefaultPredicate::new().and(NotForContentType::const_new(NDJSON_CONTENT_TYPE)),
+        ))
+    } else {
+        router
+    };
+
+    router
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+                .layer(TraceLayer::new_for_http().make_span_with(make_request_span))
+                .layer(PropagateRequestIdLayer::x_request_id()),
+        )
+        .with_state(state)
+}
+
+/// Request span carrying the request ID and, once authenticated, the API key identifier
+fn make_request_span(request: &axum::http::Request<axum::body::Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+        api_key_id = tracing::field::Empty,
+    );
+    #[cfg(feature = "otlp")]
+    firecracker_poc::otlp::set_remote_parent(&span, request.headers());
+    span
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Initialize tracing
+    let subscriber = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer());
+    #[cfg(feature = "otlp")]
+    let subscriber = subscriber.with(firecracker_poc::otlp::trace_layer_from_env()?);
+    subscriber.init();
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("build-rootfs") => {
+            let image = rootfs::build(&RootfsSpec::from_args(args)?)?;
+            println!("Built rootfs {image}");
+            return Ok(());
+        }
+        Some("import-image") => {
+            let spec = ImportSpec::from_args(args)?;
+            let image = images::import(&spec)?;
+            println!("Imported image '{}' as {image}", spec.name);
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let config = Config::from_env()?;
+    telemetry::install(&config.metrics)?;
+    firecracker_poc::fd_limit::apply(&runner_config().fd_limit);
+    let integrity = Arc::new(ArtifactIntegrity::default());
+    match runner_config().backend {
+        BackendKind::Firecracker => {
+            let arch = arch::preflight(&runner_config().artifacts)?;
+            info!("Booting {} guests", arch.as_str());
+            // A truncated rootfs otherwise only shows up as boot timeouts
+            let digests =
+                integrity::verify(&runner_config().artifacts.for_host(), &config.integrity)?;
+            for digest in &digests {
+                info!(
+                    "Guest {} {}: {} bytes, sha256 {}",
+                    digest.kind, digest.path, digest.size, digest.sha256
+                );
+            }
+            integrity.record(&Ok(digests));
+            if let Some(interval) = config.integrity.check_interval {
+                integrity::spawn_checks(
+                    runner_config().artifacts.for_host(),
+                    config.integrity.clone(),
+                    interval,
+                    integrity.clone(),
+                );
+            }
+        }
+        #[cfg(feature = "local-backend")]
+        BackendKind::Local => {
+            tracing::warn!(
+                "!!! FC_BACKEND=local: submitted code runs as plain python3 processes on this host !!!"
+            );
+            tracing::warn!(
+                "!!! There is NO isolation from the host's files, network or processes; use it for development only !!!"
+            );
+        }
+        BackendKind::Mock => {
+            tracing::warn!(
+                "FC_BACKEND=mock: no code is executed, every execution gets a canned response"
+            );
+        }
+    }
+    deps::preflight(&runner_config().deps_profiles)?;
+    reference_data::preflight(&runner_config().reference_data)?;
+    let available_images = images::discover(&runner_config().image_dir);
+    if !available_images.is_empty() {
+        info!(
+            "Images in {}: {}",
+            runner_config().image_dir.display(),
+            available_images.into_keys().collect::<Vec<_>>().join(", ")
+        );
+    }
+    for image in runner_config().sub_pools.keys() {
+        if runner_config().image_path(image).is_none() {
+            tracing::warn!("Sub-pool image '{}' is not in the image directory", image);
+        }
+    }
+    machine::preflight(
+        std::iter::once(&runner_config().machine).chain(runner_config().machine_overrides.values()),
+    );
+    if config.api_keys.is_empty() {
+        tracing::warn!(
+            "No API keys configured (FC_API_KEYS / FC_API_KEYS_FILE); authentication is disabled"
+        );
+    } else {
+        info!(
+            "API key authentication enabled ({} keys)",
+            config.api_keys.len()
+        );
+    }
+    let firecracker_version = version::host_version().cloned();
+    match &firecracker_version {
+        Some(version) => info!("Firecracker {}", version),
+        None if runner_config().backend.is_firecracker() => {
+            tracing::warn!("Could not determine the Firecracker version")
+        }
+        None => {}
+    }
+    let network_privilege = if runner_config().backend.is_firecracker() {
+        let privilege = privilege::network_privilege().await;
+        match privilege.check() {
+            Ok(()) => info!("Setting up VM networking with {:?}", privilege),
+            Err(e) => tracing::error!("{}", e),
+        }
+        Some(privilege)
+    } else {
+        None
+    };
+    let jobs = jobs::open_store(&config)?;
+    let queue = jobs::open_queue(&config).await?;
+    if config.redis_url.is_some() {
+        info!(
+            "Sharing jobs through Redis ({} workers)",
+            config.job_workers
+        );
+        EXECUTION_HISTORY.persist_to(jobs.clone())?;
+    } else if config.db_path.is_some() {
+        let interrupted = jobs.fail_interrupted()?;
+        if interrupted > 0 {
+            tracing::warn!(
+                "Marked {} jobs interrupted by the last shutdown as failed",
+                interrupted
+            );
+        }
+        EXECUTION_HISTORY.persist_to(jobs.clone())?;
+    }
+    jobs::spawn_cleanup(jobs.clone(), config.job_ttl);
+    let artifacts = artifacts::open_store(&config.artifacts, config.job_ttl)
+        .await?
+        .map(|store| {
+            // Artifacts expire with the jobs and records that reference them
+            artifacts::spawn_cleanup(store.clone(), config.job_ttl);
+            Offloader::new(store, config.artifacts.threshold_bytes)
+        });
+    let state = AppState {
+        firecracker_version,
+        network_privilege,
+        jobs,
+        queue,
+        artifacts,
+        integrity,
+        ..AppState::new(config)
+    };
+    if let Some(path) = &state.config.audit.path {
+        info!("Writing the execution audit log to {}", path.display());
+    }
+    if state.service.quotas.is_enabled() {
+        // Without a database every tenant starts a fresh window after a restart
+        if state.config.db_path.is_some() {
+            state.service.quotas.persist_to(state.jobs.clone())?;
+        }
+        info!(
+            "Execution quotas enabled ({} tenants with their own)",
+            state.config.quotas.tenants.len()
+        );
+    }
+    if let Some(path) = &state.config.tunables_file {
+        // The tunables file overrides the environment from the start
+        let outcome = state.reloader.reload();
+        if !outcome.applied {
+            return Err(format!("{}: {}", path.display(), outcome.errors.join("; ")).into());
+        }
+        let tunables = state.reloader.current();
+        state
+            .rate_limiter
+            .set_limits(tunables.rate_limit, tunables.rate_limit_overrides);
+        state.service.executor.set_tunables(tunables.pool).await;
+    }
+    state.spawn_reloads();
+    state.spawn_job_workers();
+    let app = create_app(state.clone());
+
+    // Serve on the socket systemd passed, or bind the TCP, Unix socket or TLS address of
+    // FC_LISTEN
+    let notifier = systemd::Notifier::from_env();
+    let listener = match systemd::listen_fd() {
+        Some(fd) => {
+            info!("Firecracker POC server starting on the socket passed by systemd");
+            Listener::adopt(&state.config.listen, fd).await?
+        }
+        None => {
+            info!(
+                "Firecracker POC server starting on {}",
+                state.config.listen.addr
+            );
+            Listener::bind(&state.config.listen).await?
+        }
+    };
+    info!("Server listening on {}", listener.local_addr()?);
+    info!("Available endpoints:");
+    info!("  POST /execute - Execute Python code in secure microVM");
+    info!("  POST /sandboxes/{{id}}/execute - Run code in a kept sandbox");
+    info!("  DELETE /sandboxes/{{id}} - Dispose of a kept sandbox");
+    info!("  POST /jobs    - Queue an execution, optionally with a callback");
+    info!("  GET  /jobs/{{id}} - Status and result of a queued execution");
+    info!("  GET  /artifacts/{{job_id}}/{{name}} - Output stored outside a response");
+    info!("  GET  /health  - Health check endpoint");
+    info!("  GET  /ready   - Readiness; 503 while draining");
+    info!("  GET  /version - Service and Firecracker versions");
+    info!("  GET  /pool    - VM pool and host resource usage");
+    info!("  GET  /metrics - Prometheus metrics");
+    info!("  GET  /vms/{{id}} - A live VM and its host resource usage");
+    info!("  GET  /vms/{{id}}/fc-metrics - Firecracker metrics of a live VM");
+    info!("  GET  /vms/{{id}}/boot-report - Diagnostics of a VM that failed to boot");
+    info!("  GET  /admin/executions - Recent execution history");
+    info!("  GET  /admin/state - Consistent snapshot of the pool, VMs and background tasks");
+    info!("  GET  /admin/quotas - Quota usage per tenant");
+    info!("  GET  /admin/stats - Execution counts, code sizes and durations by language and image");
+    info!("  DELETE /admin/cache - Clear the result cache");
+    info!("  POST /admin/reload - Re-read the tunables, as SIGHUP does");
+    info!("  POST /admin/selftest - Run the canary snippet and time its steps");
+    info!("  GET  /events  - Server-Sent Events stream of VM lifecycle events");
+
+    // Pre-warm VM pool in background
+    let prewarm = state.service.executor.clone();
+    let ready = notifier.clone();
+    let selftest_config = state.config.selftest.clone();
+    let selftest = state.selftest.clone();
+    tokio::spawn(async move {
+        let count = prewarm.pool_target();
+        info!("Pre-warming VM pool ({} VMs)...", count);
+        let warmed = prewarm.warm(count).await + prewarm.warm_sub_pools().await;
+        info!("VM pool pre-warming completed ({} VMs)", warmed);
+        // Units ordered after this one start once the pool is warm
+        ready.ready(&format!("Serving with {warmed} VMs warm"));
+        if selftest_config.enabled {
+            selftest::spawn(prewarm, selftest_config, selftest);
+        }
+    });
+    if let Some(interval) = systemd::watchdog_interval_from_env() {
+        let integrity = state.integrity.clone();
+        systemd::spawn_watchdog(notifier.clone(), interval, move || {
+            integrity.problem().is_none()
+        });
+    }
+    if state.service.executor.config().autoscale.enabled {
+        firecracker_poc::autoscale::spawn(state.service.executor.clone());
+    }
+    state.service.executor.spawn_recycler();
+    state.service.executor.spawn_health_prober();
+    state.service.executor.spawn_sandbox_sweeper();
+    process_usage::spawn_sampler(state.service.executor.config().process_usage);
+    // Requests can ask for boot diagnostics even when they are off
+    boot_report::spawn_gc();
+    let runner_config = state.service.executor.config();
+    firecracker_poc::runtime_gc::spawn(
+        runner_config.runtime_dir.clone(),
+        runner_config.runtime_gc.clone(),
+    );
+
+    // Both servers drain in-flight requests once a shutdown signal arrives
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let draining = state.shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        notifier.stopping();
+        info!("Shutdown signal received, draining in-flight requests");
+        draining.begin();
+        let _ = shutdown_tx.send(true);
+    });
+    let shutdown = move || {
+        let mut rx = shutdown_rx.clone();
+        async move {
+            let _ = rx.wait_for(|stop| *stop).await;
+        }
+    };
+
+    #[cfg(feature = "grpc")]
+    let grpc = {
+        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], state.config.grpc_port));
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        info!("gRPC Executor service listening on {}", addr);
+        let executor = firecracker_poc::grpc::GrpcExecutor {
+            service: state.service.clone(),
+            api_keys: state.api_keys.clone(),
+            rate_limiter: state.rate_limiter.clone(),
+        };
+        tokio::spawn(firecracker_poc::grpc::serve(listener, executor, shutdown()))
+    };
+
+    // Start server
+    listener.serve(app, shutdown()).await?;
+
+    #[cfg(feature = "grpc")]
+    grpc.await??;
+
+    // Requests have drained; take every VM down with the server
+    state.service.executor.shutdown().await;
+    info!("All VMs shut down");
+    #[cfg(feature = "otlp")]
+    firecracker_poc::otlp::shutdown_traces();
+
+    Ok(())
+}
+
+/// Resolve on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use firecracker_poc::config::DEFAULT_MAX_BODY_BYTES;
+    use firecracker_poc::idempotency::IDEMPOTENCY_REPLAYED_HEADER;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_health_endpoint() {
+        let app = create_app(AppState::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "healthy");
+        assert!(body["firecracker_version"].is_null());
+        assert_eq!(body["vm_creation"]["state"], "closed");
+        let readiness = &body["readiness"];
+        assert!(readiness["score"].as_f64().unwrap() > 0.0);
+        assert_eq!(readiness["inputs"]["vm_creation"], "closed");
+        assert!(readiness["inputs"]["pool_target"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn test_ready_follows_the_readiness_thresholds() {
+        let ready = |min_score| async move {
+            let app = create_app(AppState::new(Config {
+                readiness: firecracker_poc::readiness::ReadinessConfig {
+                    min_score,
+                    ..Default::default()
+                },
+                ..Config::default()
+            }));
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri("/ready")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, body)
+        };
+        let (status, body) = ready(0.0).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["readiness"]["ready"], true);
+
+        // No score reaches above 1
+        let (status, body) = ready(1.5).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "not_ready");
+        assert_eq!(body["readiness"]["ready"], false);
+    }
+
+    #[tokio::test]
+    async fn test_ready_waits_for_the_self_test() {
+        let state = AppState::new(Config {
+            selftest: selftest::SelfTestConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            readiness: firecracker_poc::readiness::ReadinessConfig {
+                min_score: 0.0,
+                ..Default::default()
+            },
+            ..Config::default()
+        });
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
+        let send = |method: &'static str, uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::builder()
+                            .method(method)
+                            .uri(uri)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, body)
+            }
+        };
+        let (status, body) = send("GET", "/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "self_test_pending");
+
+        let (status, report) = send("POST", "/admin/selftest").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["passed"], true);
+        assert_eq!(report["attempt"], 0);
+        assert_eq!(report["baseline_ms"], report["total_ms"]);
+        assert!(
+            report["events"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|event| event["event"] == "pool_acquire"),
+            "{report}"
+        );
+
+        let (status, body) = send("GET", "/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["selftest"]["passed"], true);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_draining_server_refuses_new_work_but_stays_healthy() {
+        let state = AppState::default();
+        let app = create_app(state.clone());
+        let get = |uri: &'static str| {
+            app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+        assert_eq!(get("/ready").await.unwrap().status(), StatusCode::OK);
+
+        state.shutdown.begin();
+        let response = get("/ready").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "draining");
+        assert_eq!(get("/health").await.unwrap().status(), StatusCode::OK);
+
+        for request in [
+            post_json(r#"{"code": "print(1)"}"#),
+            post_job(r#"{"code": "print(1)"}"#),
+        ] {
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(
+                response.headers()[header::RETRY_AFTER],
+                ADMISSION_RETRY_AFTER_SECS.to_string()
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "shutting_down");
+        }
+        // Nothing reached the executor
+        assert_eq!(state.service.executor.stats().await.executions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_openapi_document() {
+        let app = create_app(AppState::default());
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/openapi.json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let schema_ref = &spec["paths"]["/execute"]["post"]["requestBody"]["content"]["application/json"]
+            ["schema"]["$ref"];
+        assert_eq!(schema_ref, "#/components/schemas/ExecuteRequest");
+        let properties = spec["components"]["schemas"]["ExecuteRequest"]["properties"]
+            .as_object()
+            .unwrap();
+        for field in [
+            "code",
+            "files",
+            "entrypoint",
+            "requirements",
+            "deps_profile",
+            "deterministic",
+            "fake_time",
+            "cache",
+            "cache_bypass",
+            "max_output_bytes",
+        ] {
+            assert!(properties.contains_key(field), "missing {field}");
+        }
+
+        // Swagger UI is off by default
+        let response = app
+            .oneshot(Request::builder().uri("/docs").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_swagger_ui_when_enabled() {
+        let state = AppState::new(Config {
+            swagger_ui: true,
+            api_keys: vec!["secret".to_string()],
+            ..Config::default()
+        });
+        let app = create_app(state);
+        assert_eq!(
+            get_with_auth(app.clone(), "/docs", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_with_auth(app, "/openapi.json", None).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_endpoint() {
+        let state = AppState {
+            firecracker_version: FirecrackerVersion::parse("1.7.0"),
+            ..AppState::default()
+        };
+        let response = create_app(state)
+            .oneshot(
+                Request::builder()
+                    .uri("/version")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["firecracker_version"], "1.7.0");
+        assert!(body["git_sha"].is_string());
+        assert_eq!(body["artifacts"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_artifact_mismatch_reports_hashes_and_fails_health() {
+        let dir = std::env::temp_dir().join(format!("fc-integrity-health-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let artifacts = arch::Artifacts {
+            kernel: dir.join("vmlinux"),
+            rootfs: dir.join("rootfs.ext4"),
+        };
+        std::fs::write(&artifacts.kernel, b"kernel").unwrap();
+        std::fs::write(&artifacts.rootfs, b"rootfs").unwrap();
+        let config = integrity::IntegrityConfig::default();
+        let state = AppState::default();
+        state
+            .integrity
+            .record(&integrity::verify(&artifacts, &config));
+
+        let get = |uri: &'static str| {
+            create_app(state.clone())
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+        let body = |response: Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+        let version = body(get("/version").await.unwrap()).await;
+        assert_eq!(version["artifacts"][1]["kind"], "rootfs");
+        assert_eq!(version["artifacts"][1]["size"], 6);
+        let rootfs_sha256 = version["artifacts"][1]["sha256"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(get("/health").await.unwrap().status(), StatusCode::OK);
+
+        // The rootfs changes under the running service
+        std::fs::write(&artifacts.rootfs, b"root").unwrap();
+        let expected = integrity::IntegrityConfig {
+            rootfs_sha256: Some(rootfs_sha256.clone()),
+            ..config
+        };
+        state
+            .integrity
+            .record(&integrity::verify(&artifacts, &expected));
+        let response = get("/health").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let health = body(response).await;
+        assert_eq!(health["status"], "unhealthy");
+        assert!(
+            health["error"].as_str().unwrap().contains(&rootfs_sha256),
+            "{health}"
+        );
+        // The digests of the last good check are still reported
+        let version = body(get("/version").await.unwrap()).await;
+        assert_eq!(version["artifacts"][1]["sha256"], rootfs_sha256.as_str());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_network_privileges_fail_health() {
+        let health = |network_privilege| async move {
+            let response = create_app(AppState {
+                network_privilege,
+                ..AppState::default()
+            })
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        };
+
+        let (status, body) = health(Some(NetworkPrivilege::Unavailable)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["network_privilege"], "unavailable");
+        let error = body["error"].as_str().unwrap();
+        assert!(error.contains("CAP_NET_ADMIN") && error.contains("passwordless sudo"));
+
+        let (status, body) = health(Some(NetworkPrivilege::Sudo)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["network_privilege"], "sudo");
+        // Backends without networking aren't probed
+        let (_, body) = health(None).await;
+        assert!(body.get("network_privilege").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pool_endpoint_reports_resources() {
+        let response = create_app(AppState::default())
+            .oneshot(Request::builder().uri("/pool").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["idle_vms"].is_u64());
+        assert!(body["resources"]["live_vms"].is_u64());
+        assert!(body["resources"]["max_vms"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_fc_metrics_endpoint() {
+        let metrics_path = "/tmp/test-fc-metrics-endpoint.json";
+        tokio::fs::write(
+            metrics_path,
+            "{\"net\":{\"rx_bytes_count\":1}}\n{\"net\":{\"rx_bytes_count\":2}}\n",
+        )
+        .await
+        .unwrap();
+        runner::VM_REGISTRY.lock().unwrap().insert(
+            "metrics-test-vm".to_string(),
+            runner::LiveVm {
+                vm_id: "metrics-test-vm".to_string(),
+                socket_path: "/tmp/test-fc-metrics-endpoint.socket".to_string(),
+                fc_metrics_path: metrics_path.to_string(),
+                tap_interface: "tap-metrics".to_string(),
+                pid: None,
+                traffic_shape: None,
+                agent_protocol: None,
+                fds: firecracker_poc::fd_limit::VmFds::expected(false),
+            },
+        );
+
+        let response = create_app(AppState::default())
+            .oneshot(
+                Request::builder()
+                    .uri("/vms/metrics-test-vm/fc-metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metrics: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(metrics["net"]["rx_bytes_count"], 2);
+
+        let response = create_app(AppState::default())
+            .oneshot(
+                Request::builder()
+                    .uri("/vms/unknown-vm/fc-metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = error_body(response).await;
+        assert_eq!(body.code, "not_found");
+        assert_eq!(body.error, "VM unknown-vm not found");
+
+        runner::VM_REGISTRY
+            .lock()
+            .unwrap()
+            .remove("metrics-test-vm");
+        let _ = tokio::fs::remove_file(metrics_path).await;
+    }
+
+    #[tokio::test]
+    async fn test_vm_endpoint_reports_process_usage() {
+        // This test process stands in for the VM's Firecracker
+        runner::VM_REGISTRY.lock().unwrap().insert(
+            "usage-test-vm".to_string(),
+            runner::LiveVm {
+                vm_id: "usage-test-vm".to_string(),
+                socket_path: "/tmp/test-usage.socket".to_string(),
+                fc_metrics_path: "/tmp/test-usage-metrics.json".to_string(),
+                tap_interface: "tap-usage".to_string(),
+                pid: Some(std::process::id()),
+                traffic_shape: Some(firecracker_poc::shaping::TrafficShape {
+                    qdisc: firecracker_poc::shaping::Qdisc::Htb,
+                    rate_kbit: 1000,
+                    burst_bytes: 16384,
+                    latency_ms: 50,
+                }),
+                agent_protocol: Some(2),
+                fds: firecracker_poc::fd_limit::VmFds::expected(true),
+            },
+        );
+        let get = |uri: &'static str| {
+            create_app(AppState::default())
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+        let response = get("/vms/usage-test-vm").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let vm: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(vm["tap_interface"], "tap-usage");
+        assert_eq!(vm["usage"]["pid"], std::process::id());
+        assert!(vm["usage"]["rss_kib"].as_u64().unwrap() > 0);
+        assert!(vm["usage"]["open_fds"].as_u64().unwrap() > 0);
+        assert_eq!(vm["traffic_shape"]["qdisc"], "htb");
+        assert_eq!(vm["traffic_shape"]["rate_kbit"], 1000);
+        assert_eq!(vm["agent_protocol_version"], 2);
+        runner::VM_REGISTRY.lock().unwrap().remove("usage-test-vm");
+
+        let response = get("/vms/usage-test-vm").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await.code, "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_boot_report_endpoint() {
+        let logs = boot_report::BootLogs {
+            console: "[    0.071450] Kernel panic - not syncing\n".to_string(),
+            firecracker_log: String::new(),
+            firecracker_config: r#"{"drives":[{"path_on_host":"/srv/images/rootfs.ext4"}]}"#
+                .to_string(),
+        };
+        let report = BootReport::assemble("boot-report-test-vm", "boot timed out", "", &logs, 10);
+        BOOT_REPORTS.retain(report, Vec::new(), std::time::Duration::from_secs(60));
+
+        let get = |uri: &'static str| {
+            create_app(AppState::default())
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+        let response = get("/vms/boot-report-test-vm/boot-report").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["error"], "boot timed out");
+        assert_eq!(
+            report["console_tail"][0],
+            "[    0.071450] Kernel panic - not syncing"
+        );
+        assert_eq!(
+            report["firecracker_config"]["drives"][0]["path_on_host"],
+            "<redacted>/rootfs.ext4"
+        );
+
+        let response = get("/vms/unknown-vm/boot-report").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await.code, "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_is_absent_with_a_push_exporter() {
+        let app = create_app(AppState::new(Config {
+            metrics: firecracker_poc::telemetry::MetricsConfig {
+                exporter: MetricsExporter::Statsd {
+                    addr: "127.0.0.1:8125".to_string(),
+                    prefix: None,
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await.code, "not_found");
+    }
+
+    /// The `ErrorResponse` envelope of a JSON error response
+    async fn error_body(response: Response) -> ErrorResponse {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_executions_history_endpoint() {
+        let response = create_app(AppState::default())
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header("x-request-id", "history-test-request")
+                    .body(Body::from(r#"{"code": "print('history')"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers().get("x-request-id").unwrap(),
+            "history-test-request"
+        );
+
+        let response = create_app(AppState::default())
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/executions?limit=500")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let records: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let record = records
+            .iter()
+            .find(|r| r["request_id"] == "history-test-request")
+            .expect("execution should be recorded");
+        assert_eq!(
+            record["code_sha256"],
+            firecracker_poc::history::code_sha256("print('history')")
+        );
+        assert!(record.get("code").is_none());
+        assert!(record.get("stdout").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_events_endpoint_streams_lifecycle() {
+        use http_body_util::BodyExt;
+
+        let response = create_app(AppState::default())
+            .oneshot(
+                Request::builder()
+                    .uri("/events")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+
+        // The subscription exists once the response is returned
+        tokio::spawn(runner::run_in_vm_for_request(runner::ExecutionSpec {
+            request_id: "sse-test-request".to_string(),
+            ..runner::ExecutionSpec::code("print('sse')")
+        }));
+
+        let mut body = response.into_body();
+        let mut text = String::new();
+        let deadline = std::time::Duration::from_secs(5);
+        while !text.contains("sse-test-request") {
+            let frame = tokio::time::timeout(deadline, body.frame())
+                .await
+                .expect("event should arrive")
+                .unwrap()
+                .unwrap();
+            if let Ok(data) = frame.into_data() {
+                text.push_str(&String::from_utf8_lossy(&data));
+            }
+        }
+        assert!(text.contains("event: acquired"));
+        assert!(text.contains("\"type\":\"acquired\""));
+    }
+
+    fn app_with_keys(keys: &[&str]) -> Router {
+        create_app(AppState::new(Config {
+            api_keys: keys.iter().map(|k| k.to_string()).collect(),
+            ..Default::default()
+        }))
+    }
+
+    fn execute_request(auth: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder()
+            .method("POST")
+            .uri("/execute")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(auth) = auth {
+            builder = builder.header(header::AUTHORIZATION, auth);
+        }
+        builder
+            .body(Body::from(r#"{"code": "print('limited')"}"#))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_rejects_past_burst() {
+        let app = create_app(AppState::new(Config {
+            rate_limit: Some(rate_limit::RateLimit {
+                per_minute: 1,
+                burst: 2,
+            }),
+            ..Default::default()
+        }));
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(execute_request(None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(execute_request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after));
+
+        // Other routes are not rate limited
+        assert_eq!(get_with_auth(app, "/health", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_api_key_override() {
+        let keys = ApiKeys::new(&["vip-key".to_string()]);
+        let vip_id = keys.verify("vip-key").unwrap().0;
+        let mut overrides = std::collections::HashMap::new();
+        overrides.insert(
+            vip_id.clone(),
+            rate_limit::RateLimit {
+                per_minute: 60,
+                burst: 3,
+            },
+        );
+        let app = create_app(AppState::new(Config {
+            api_keys: vec!["vip-key".to_string(), "basic-key".to_string()],
+            rate_limit: Some(rate_limit::RateLimit {
+                per_minute: 1,
+                burst: 1,
+            }),
+            rate_limit_overrides: overrides,
+            ..Default::default()
+        }));
+
+        let basic = Some("Bearer basic-key");
+        let vip = Some("Bearer vip-key");
+        assert_eq!(
+            app.clone()
+                .oneshot(execute_request(basic))
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            app.clone()
+                .oneshot(execute_request(basic))
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        for _ in 0..3 {
+            assert_eq!(
+                app.clone()
+                    .oneshot(execute_request(vip))
+                    .await
+                    .unwrap()
+                    .status(),
+                StatusCode::OK
+            );
+        }
+        assert_eq!(
+            app.clone()
+                .oneshot(execute_request(vip))
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        let level = telemetry::METRICS
+            .gauge_value("fc_rate_limit_tokens", &[("client", &vip_id)])
+            .unwrap();
+        assert!(level < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_quota_rejects_with_the_limit_and_reports_usage() {
+        use firecracker_poc::quota::{QuotaConfig, QuotaLimit};
+        let team_id = ApiKeys::new(&["team-key".to_string()])
+            .verify("team-key")
+            .unwrap()
+            .0;
+        let app = create_app(AppState::new(Config {
+            api_keys: vec!["team-key".to_string(), "other-key".to_string()],
+            quotas: QuotaConfig {
+                default: None,
+                tenants: std::collections::HashMap::from([(
+                    team_id.clone(),
+                    QuotaLimit {
+                        executions: Some(1),
+                        window_secs: 3600,
+                        max_concurrent: None,
+                    },
+                )]),
+            },
+            ..Default::default()
+        }));
+
+        let team = Some("Bearer team-key");
+        let response = app.clone().oneshot(execute_request(team)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(execute_request(team)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = response.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((3500..=3600).contains(&retry_after));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "quota_exceeded");
+        assert_eq!(body["quota"]["limit"], "executions");
+        assert_eq!(body["quota"]["allowed"], 1);
+        assert!(body["quota"]["resets_at"].as_u64().is_some());
+
+        // Other tenants have no quota
+        let response = app
+            .clone()
+            .oneshot(execute_request(Some("Bearer other-key")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(
+            get_with_auth(app.clone(), "/admin/quotas", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/quotas")
+                    .header(header::AUTHORIZATION, "Bearer other-key")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let usage: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(usage.as_array().unwrap().len(), 1);
+        assert_eq!(usage[0]["tenant"], team_id.as_str());
+        assert_eq!(usage[0]["executions"], 1);
+        assert_eq!(usage[0]["executions_limit"], 1);
+    }
+
+    async fn get_with_auth(app: Router, uri: &str, auth: Option<&str>) -> StatusCode {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(auth) = auth {
+            builder = builder.header(header::AUTHORIZATION, auth);
+        }
+        app.oneshot(builder.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_auth_allows_valid_key() {
+        let app = app_with_keys(&["secret-1", "secret-2"]);
+        let status = get_with_auth(app, "/admin/executions", Some("Bearer secret-2")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_denies_missing_or_wrong_key() {
+        let app = app_with_keys(&["secret-1"]);
+        assert_eq!(
+            get_with_auth(app.clone(), "/metrics", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get_with_auth(app.clone(), "/metrics", Some("Bearer nope")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get_with_auth(app.clone(), "/metrics", Some("secret-1")).await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"code": "print(1)"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: firecracker_poc::ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn test_admin_state_is_consistent_and_redacted() {
+        let state = AppState::new(Config {
+            api_keys: vec!["sk-admin-7f3a9c".to_string()],
+            ..Default::default()
+        });
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(300),
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            // Set after construction so no job database is opened
+            config: Arc::new(Config {
+                db_path: Some("/srv/private/fc-jobs.db".into()),
+                ..(*state.config).clone()
+            }),
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
+
+        let running = tokio::spawn({
+            let app = app.clone();
+            async move {
+                let mut request = execute_request(Some("Bearer sk-admin-7f3a9c"));
+                request
+                    .headers_mut()
+                    .insert(X_REQUEST_ID, "state-snapshot-req".parse().unwrap());
+                app.oneshot(request).await.unwrap().status()
+            }
+        });
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while executor.stats().await.in_flight == 0 {
+            assert!(std::time::Instant::now() < deadline, "never started");
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/state")
+                    .header(header::AUTHORIZATION, "Bearer sk-admin-7f3a9c")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!text.contains("sk-admin-7f3a9c"), "{text}");
+        assert!(!text.contains("/srv/private"), "{text}");
+        let document: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document["config"]["api_keys"], 1);
+        assert_eq!(document["config"]["db_path"], "<redacted>");
+        let executor_state = &document["executor"];
+        assert_eq!(executor_state["stats"]["in_flight"], 1);
+        assert_eq!(
+            executor_state["in_flight"][0]["request_id"],
+            "state-snapshot-req"
+        );
+        assert_eq!(executor_state["vm_creation"]["state"], "closed");
+        assert!(document["recent_errors"].is_array());
+        assert!(document["tasks"].is_object());
+        assert!(
+            document["recent_events"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|e| e["type"] == "acquired" && e["request_id"] == "state-snapshot-req")
+        );
+
+        assert_eq!(running.await.unwrap(), StatusCode::OK);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_admin_stats_aggregates_executions_by_workload() {
+        let (app, executor) = slow_app(std::time::Duration::ZERO);
+        for code in ["print(1)", "print('twelve')"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/execute")
+                        .header(header::CONTENT_TYPE, "text/plain")
+                        .body(Body::from(code))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/admin/stats")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats.as_array().unwrap().len(), 1, "{stats}");
+        let workload = &stats[0];
+        assert_eq!(
+            (
+                &workload["language"],
+                &workload["image"],
+                &workload["profile"]
+            ),
+            (&"python".into(), &"default".into(), &"default".into())
+        );
+        assert_eq!(
+            (&workload["executions"], &workload["succeeded"]),
+            (&2.into(), &2.into())
+        );
+        assert_eq!(
+            (&workload["code_bytes_total"], &workload["code_bytes_max"]),
+            (&23.into(), &15.into())
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_auth_health_is_public() {
+        let app = app_with_keys(&["secret-1"]);
+        assert_eq!(get_with_auth(app, "/health", None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_unconfigured_is_noop() {
+        let app = app_with_keys(&[]);
+        assert_eq!(
+            get_with_auth(app.clone(), "/admin/executions", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_with_auth(app, "/metrics", Some("Bearer whatever")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_endpoint_empty_code() {
+        let app = create_app(AppState::default());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"code": ""}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            ErrorResponse::new("empty_code", "Empty code provided")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_endpoint_too_long_code() {
+        let app = create_app(AppState::default());
+        let long_code = "a".repeat(10_001);
+
+        let request_body = format!(r#"{{"code": "{long_code}"}}"#);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(request_body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "code_too_long");
+        assert!(
+            body.error.contains("10000 characters (10001 given)"),
+            "{}",
+            body.error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_endpoint_screening() {
+        let keys = ApiKeys::new(&["trusted-key".to_string()]);
+        let trusted_id = keys.verify("trusted-key").unwrap().0;
+        let app = create_app(AppState::new(Config {
+            api_keys: vec!["trusted-key".to_string(), "plain-key".to_string()],
+            screening: firecracker_poc::screening::ScreeningConfig {
+                enabled: true,
+                deny: vec![firecracker_poc::screening::DenyRuleConfig {
+                    name: "limited".to_string(),
+                    pattern: "limited".to_string(),
+                    regex: false,
+                }],
+                bypass_key_ids: vec![trusted_id],
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        let response = app
+            .clone()
+            .oneshot(execute_request(Some("Bearer plain-key")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "screened");
+        assert!(body["error"].as_str().unwrap().contains("'limited'"));
+
+        let response = app
+            .oneshot(execute_request(Some("Bearer trusted-key")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_execute_endpoint_body_limit() {
+        let app = create_app(AppState::new(Config {
+            max_body_bytes: 128,
+            ..Default::default()
+        }));
+        let code = "x".repeat(200);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(format!(r#"{{"code": "{code}"}}"#)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "payload_too_large");
+        assert!(body["error"].as_str().unwrap().contains("128 bytes"));
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn gzipped_execute(body: &[u8]) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/execute")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::from(gzip(body)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gzipped_request_and_response_round_trip() {
+        let code = format!("print('{}')", "compressible ".repeat(20));
+        let request = serde_json::to_vec(&serde_json::json!({ "code": code })).unwrap();
+        let response = create_app(AppState::default())
+            .oneshot(gzipped_execute(&request))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut json = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&body[..]), &mut json)
+            .unwrap();
+        let response: ExecuteResponse = serde_json::from_slice(&json).unwrap();
+        assert_eq!(response.stdout, format!("Mock execution of: {code}\n"));
+
+        // Switched off, bodies are taken and sent as they are
+        let app = create_app(AppState::new(Config {
+            response_compression: false,
+            request_decompression: false,
+            ..Default::default()
+        }));
+        let response = app.oneshot(gzipped_execute(&request)).await.unwrap();
+        assert!(response.status().is_client_error());
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+
+    #[tokio::test]
+    async fn test_decompressed_body_is_held_to_the_body_limit() {
+        let app = create_app(AppState::new(Config {
+            max_body_bytes: 1024,
+            ..Default::default()
+        }));
+        // A few hundred bytes that inflate far past the limit
+        let code = "x".repeat(512 * 1024);
+        let request = serde_json::to_vec(&serde_json::json!({ "code": code })).unwrap();
+        let compressed = gzip(&request);
+        assert!(compressed.len() < 1024);
+
+        let response = app.oneshot(gzipped_execute(&request)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    fn plain_execute(uri: &str, content_type: &str, code: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(code.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_plain_text_code_submission() {
+        let app = create_app(AppState::default());
+        let code = "print(\"no \\\"escaping\\\" needed\")\n";
+        for content_type in ["text/plain; charset=utf-8", "application/x-python"] {
+            let response = app
+                .clone()
+                .oneshot(plain_execute("/execute", content_type, code))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let response: ExecuteResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response.stdout, format!("Mock execution of: {code}\n"));
+        }
+
+        let response = app
+            .clone()
+            .oneshot(plain_execute(
+                "/execute?language=python&deterministic=true",
+                "text/plain",
+                "print(1)",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(plain_execute(
+                "/execute?language=ruby",
+                "text/plain",
+                "puts 1",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_plain_text_code_is_validated_like_json() {
+        let app = create_app(AppState::new(Config {
+            max_body_bytes: 128,
+            ..Default::default()
+        }));
+
+        let response = app
+            .clone()
+            .oneshot(plain_execute("/execute", "text/plain", ""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "empty_code");
+
+        let response = app
+            .oneshot(plain_execute("/execute", "text/plain", &"x".repeat(200)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "payload_too_large");
+        assert!(body["error"].as_str().unwrap().contains("128 bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_options_reports_limits() {
+        let app = create_app(AppState::new(Config {
+            max_code_length: 500,
+            ..Default::default()
+        }));
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/execute")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["max_code_length"], 500);
+        assert_eq!(body["max_body_bytes"], DEFAULT_MAX_BODY_BYTES);
+    }
+
+    fn preflight_request() -> Request<Body> {
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/execute")
+            .header(header::ORIGIN, "https://ui.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type,authorization",
+            )
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_configured() {
+        let app = create_app(AppState::new(Config {
+            api_keys: vec!["secret".to_string()],
+            cors_origins: Some(cors::CorsOrigins::List(vec![
+                "https://ui.example.com".to_string(),
+            ])),
+            ..Default::default()
+        }));
+
+        // Preflights are answered without credentials
+        let response = app.clone().oneshot(preflight_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://ui.example.com"
+        );
+        let allowed_headers = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        for name in ["content-type", "authorization", "x-request-id"] {
+            assert!(allowed_headers.contains(name));
+        }
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+                .to_str()
+                .unwrap()
+                .contains("POST")
+        );
+
+        // Actual requests expose the VM and request ID headers
+        let mut request = execute_request(Some("Bearer secret"));
+        request
+            .headers_mut()
+            .insert(header::ORIGIN, "https://ui.example.com".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let exposed = response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(exposed.contains("x-vm-id") && exposed.contains("x-request-id"));
+
+        // Other origins are not allowed
+        let mut request = preflight_request();
+        request
+            .headers_mut()
+            .insert(header::ORIGIN, "https://evil.example.com".parse().unwrap());
+        let response = app.oneshot(request).await.unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cors_any_origin_keeps_options_discovery() {
+        let app = create_app(AppState::new(Config {
+            cors_origins: Some(cors::CorsOrigins::Any),
+            ..Default::default()
+        }));
+        let response = app.clone().oneshot(preflight_request()).await.unwrap();
+        assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        // A plain OPTIONS request still reaches the limits handler
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("OPTIONS")
+                    .uri("/execute")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["max_code_length"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn test_cors_unconfigured_emits_no_headers() {
+        let response = create_app(AppState::default())
+            .oneshot(preflight_request())
+            .await
+            .unwrap();
+        assert!(
+            response
+                .headers()
+                .keys()
+                .all(|name| !name.as_str().starts_with("access-control-"))
+        );
+    }
+
+    fn post_json(body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/execute")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn post_job(body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/jobs")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn post_execute(app: &Router, body: &str) -> serde_json::Value {
+        let response = app.clone().oneshot(post_json(body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn idempotent_execute(key: &str, body: &str) -> Request<Body> {
+        let mut request = post_json(body);
+        request
+            .headers_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, key.parse().unwrap());
+        request
+    }
+
+    /// Mock backend answering after `latency`, and the executor counting its runs
+    fn slow_app(latency: std::time::Duration) -> (Router, ExecutorService) {
+        let state = AppState::default();
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: latency,
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
+        (app, executor)
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_the_first_response() {
+        let (app, executor) = slow_app(std::time::Duration::ZERO);
+        let body = r#"{"code": "print('once')"}"#;
+
+        let first = app
+            .clone()
+            .oneshot(idempotent_execute("order-1", body))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert!(!first.headers().contains_key(IDEMPOTENCY_REPLAYED_HEADER));
+        let first = axum::body::to_bytes(first.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        let retry = app
+            .clone()
+            .oneshot(idempotent_execute("order-1", body))
+            .await
+            .unwrap();
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert_eq!(retry.headers()[IDEMPOTENCY_REPLAYED_HEADER], "true");
+        assert_eq!(retry.headers()[header::CONTENT_TYPE], "application/json");
+        let retry = axum::body::to_bytes(retry.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(retry, first);
+        assert_eq!(executor.stats().await.executions, 1);
+
+        // Without the header every request runs
+        post_execute(&app, body).await;
+        assert_eq!(executor.stats().await.executions, 2);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_is_released_after_a_deadline() {
+        let (app, executor) = slow_app(std::time::Duration::from_secs(1));
+        let body = r#"{"code": "print('once')"}"#;
+
+        let mut request = idempotent_execute("order-1", body);
+        request
+            .headers_mut()
+            .insert(X_DEADLINE_MS, "600".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        // The timeout isn't replayed: the retry runs the code
+        let retry = app
+            .clone()
+            .oneshot(idempotent_execute("order-1", body))
+            .await
+            .unwrap();
+        assert_eq!(retry.status(), StatusCode::OK);
+        assert!(!retry.headers().contains_key(IDEMPOTENCY_REPLAYED_HEADER));
+
+        let replay = app
+            .oneshot(idempotent_execute("order-1", body))
+            .await
+            .unwrap();
+        assert_eq!(replay.headers()[IDEMPOTENCY_REPLAYED_HEADER], "true");
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_reused_for_another_body_conflicts() {
+        let (app, executor) = slow_app(std::time::Duration::ZERO);
+        let response = app
+            .clone()
+            .oneshot(idempotent_execute("order-1", r#"{"code": "print(1)"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(idempotent_execute("order-1", r#"{"code": "print(2)"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "idempotency_key_reused");
+        assert_eq!(executor.stats().await.executions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_duplicates_wait_for_the_first_request() {
+        let (app, executor) = slow_app(std::time::Duration::from_millis(200));
+        let body = r#"{"code": "print('once')"}"#;
+        let requests: Vec<_> = (0..3)
+            .map(|_| {
+                let app = app.clone();
+                tokio::spawn(async move { app.oneshot(idempotent_execute("order-1", body)).await })
+            })
+            .collect();
+
+        let mut replayed = 0;
+        for request in requests {
+            let response = request.await.unwrap().unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            replayed += usize::from(response.headers().contains_key(IDEMPOTENCY_REPLAYED_HEADER));
+        }
+        assert_eq!(replayed, 2);
+        assert_eq!(executor.stats().await.executions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reload_applies_new_rate_limits_or_rejects_them() {
+        let path = std::env::temp_dir().join(format!("fc-reload-{}.env", std::process::id()));
+        std::fs::write(&path, "FC_RATE_LIMIT=60:1\n").unwrap();
+        let state = AppState::new(Config {
+            tunables_file: Some(path.clone()),
+            ..Default::default()
+        });
+        state.spawn_reloads();
+        let app = create_app(state.clone());
+        let reload = || async {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/admin/reload")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        };
+
+        let (status, outcome) = reload().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(outcome["changed"], serde_json::json!(["FC_RATE_LIMIT"]));
+        tokio::time::timeout(std::time::Duration::from_secs(2), async {
+            while !state.rate_limiter.is_enabled() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+        post_execute(&app, r#"{"code": "print(1)"}"#).await;
+        let limited = app
+            .clone()
+            .oneshot(post_json(r#"{"code": "print(1)"}"#))
+            .await
+            .unwrap();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        std::fs::write(&path, "FC_RATE_LIMIT=off\nFC_API_KEYS=secret\n").unwrap();
+        let (status, outcome) = reload().await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(outcome["applied"], false);
+        assert_eq!(
+            outcome["errors"],
+            serde_json::json!(["line 2: FC_API_KEYS can't be changed without a restart"])
+        );
+        assert!(state.reloader.current().rate_limit.is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn post_execute_as(content_type: &str, accept: &str, body: Vec<u8>) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/execute")
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT, accept)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_execute_msgpack_roundtrip() {
+        let app = create_app(AppState::default());
+        let request = ExecuteRequest {
+            code: Some("print('packed')".to_string()),
+            ..Default::default()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(post_execute_as(
+                "application/msgpack",
+                "application/msgpack",
+                rmp_serde::to_vec_named(&request).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/msgpack"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: ExecuteResponse = rmp_serde::from_slice(&body).unwrap();
+        assert!(response.success);
+        assert_eq!(response.stdout, "Mock execution of: print('packed')\n");
+
+        // JSON in, JSON out, unless MessagePack is asked for
+        let response = app
+            .clone()
+            .oneshot(post_execute_as(
+                "application/json",
+                "application/json",
+                serde_json::to_vec(&request).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: ExecuteResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.stdout, "Mock execution of: print('packed')\n");
+
+        // Errors follow the negotiated format too
+        let empty = ExecuteRequest {
+            code: Some("  ".to_string()),
+            ..Default::default()
+        };
+        let response = app
+            .oneshot(post_execute_as(
+                "application/json",
+                "application/msgpack",
+                serde_json::to_vec(&empty).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: ErrorResponse = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(response.error, "Empty code provided");
+    }
+
+    #[tokio::test]
+    async fn test_execute_ndjson_stream() {
+        let app = create_app(AppState::default());
+        let request = |uri: &str, accept: &str, body: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::ACCEPT, accept)
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let read_lines = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                NDJSON_CONTENT_TYPE
+            );
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.ends_with('\n'), "every line is newline-terminated");
+            body.lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request(
+                "/execute?format=ndjson",
+                "*/*",
+                r#"{"code": "print('streamed')"}"#,
+            ))
+            .await
+            .unwrap();
+        let lines = read_lines(response).await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "stdout");
+        assert_eq!(lines[0]["data"], "Mock execution of: print('streamed')\n");
+        assert_eq!(lines[1]["type"], "result");
+        assert_eq!(lines[1]["success"], true);
+        assert_eq!(lines[1]["stdout"], "");
+
+        // Rejected requests still end with a result line
+        let response = app
+            .oneshot(request("/execute", NDJSON_CONTENT_TYPE, r#"{"code": " "}"#))
+            .await
+            .unwrap();
+        let lines = read_lines(response).await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "stderr");
+        assert_eq!(lines[0]["data"], "Empty code provided");
+        assert_eq!(lines[1]["type"], "result");
+        assert_eq!(lines[1]["success"], false);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_lines_arrive_as_the_code_prints() {
+        use http_body_util::BodyExt;
+
+        let (app, executor) = slow_app(std::time::Duration::from_millis(400));
+        let response = app
+            .oneshot(post_execute_as(
+                "application/json",
+                NDJSON_CONTENT_TYPE,
+                br#"{"code": "print(1)\nprint(2)"}"#.to_vec(),
+            ))
+            .await
+            .unwrap();
+        let mut body = response.into_body();
+        let mut text = String::new();
+        while !text.contains('\n') {
+            let frame = body.frame().await.unwrap().unwrap();
+            text.push_str(&String::from_utf8_lossy(&frame.into_data().unwrap()));
+        }
+        assert_eq!(
+            executor.stats().await.in_flight,
+            1,
+            "the first line came before the code finished"
+        );
+
+        let rest = body.collect().await.unwrap().to_bytes();
+        text.push_str(&String::from_utf8_lossy(&rest));
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "stdout");
+        assert_eq!(lines[0]["data"], "Mock execution of: print(1)\n");
+        assert_eq!(lines[1]["data"], "print(2)\n");
+        assert_eq!(lines[2]["type"], "result");
+        assert_eq!(lines[2]["success"], true);
+        assert_eq!(lines[2]["stdout"], "");
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_stream_ends_with_a_result_at_the_deadline() {
+        let (app, executor) = slow_app(std::time::Duration::from_secs(5));
+        let mut request = post_execute_as(
+            "application/json",
+            NDJSON_CONTENT_TYPE,
+            br#"{"code": "print('slow')"}"#.to_vec(),
+        );
+        request
+            .headers_mut()
+            .insert(X_DEADLINE_MS, "800".parse().unwrap());
+
+        let started = std::time::Instant::now();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        let lines: Vec<serde_json::Value> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let result = lines.last().unwrap();
+        assert_eq!(result["type"], "result");
+        assert_eq!(result["success"], false);
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert_eq!(lines[0]["type"], "stderr");
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_job_callback_delivery() {
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Receiver that fails the first delivery and records the rest
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let received = Arc::new(Mutex::new(Vec::<(Option<String>, axum::body::Bytes)>::new()));
+        let receiver = Router::new().route(
+            "/hook",
+            post({
+                let attempts = attempts.clone();
+                let received = received.clone();
+                move |headers: HeaderMap, body: axum::body::Bytes| async move {
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return StatusCode::INTERNAL_SERVER_ERROR;
+                    }
+                    let signature = headers
+                        .get(webhook::SIGNATURE_HEADER)
+                        .and_then(|v| v.to_str().ok())
+                        .map(str::to_string);
+                    received.lock().unwrap().push((signature, body));
+                    StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let state = AppState::new(Config {
+            webhook: webhook::WebhookConfig {
+                allowed_hosts: vec!["127.0.0.1".to_string()],
+                secret: Some("s3cret".to_string()),
+                retry_backoff: std::time::Duration::from_millis(10),
+            },
+            ..Config::default()
+        });
+        state.spawn_job_workers();
+        let app = create_app(state);
+        let body = format!(
+            r#"{{"code": "print('job')", "callback_url": "http://127.0.0.1:{port}/hook"}}"#
+        );
+        let response = app.clone().oneshot(post_job(&body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let location = response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let job = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let response = app
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .uri(&location)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let job: Job = serde_json::from_slice(&body).unwrap();
+                if job
+                    .callback
+                    .as_ref()
+                    .is_some_and(|callback| callback.status != webhook::CallbackStatus::Pending)
+                {
+                    return job;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(job.status, jobs::JobStatus::Completed);
+        assert_eq!(
+            job.result.unwrap().stdout,
+            "Mock execution of: print('job')\n"
+        );
+        let callback = job.callback.unwrap();
+        assert_eq!(callback.status, webhook::CallbackStatus::Delivered);
+        assert_eq!(callback.attempts, 2);
+
+        let received = received.lock().unwrap();
+        let (signature, body) = &received[0];
+        assert_eq!(
+            signature.as_deref(),
+            Some(webhook::sign("s3cret", body).as_str())
+        );
+        let delivered: Job = serde_json::from_slice(body).unwrap();
+        assert_eq!(delivered.id, location.trim_start_matches("/jobs/"));
+        assert_eq!(delivered.status, jobs::JobStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_job_rejects_unsafe_callback() {
+        let app = create_app(AppState::default());
+        for url in [
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost:3000/hook",
+        ] {
+            let body = format!(r#"{{"code": "print(1)", "callback_url": "{url}"}}"#);
+            let response = app.clone().oneshot(post_job(&body)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{url}");
+        }
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/jobs/unknown")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_execute_offloads_large_output() {
+        let dir = std::env::temp_dir().join(format!("fc-artifacts-main-{}", std::process::id()));
+        let store = Arc::new(artifacts::LocalArtifactStore::new(&dir));
+        let app = create_app(AppState {
+            artifacts: Some(Offloader::new(store, 8)),
+            ..AppState::default()
+        });
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(X_REQUEST_ID, "req-artifacts")
+                    .body(Body::from(r#"{"code": "print('big')"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: ExecuteResponse = serde_json::from_slice(&body).unwrap();
+        assert!(response.stdout.is_empty());
+        assert_eq!(response.artifacts.len(), 1);
+        let artifact = &response.artifacts[0];
+        assert_eq!(artifact.name, "stdout");
+        assert_eq!(artifact.url, "/artifacts/req-artifacts/stdout");
+
+        let download = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(&artifact.url)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(download.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(download.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"Mock execution of: print('big')\n");
+        assert_eq!(bytes.len() as u64, artifact.size);
+
+        // Streamed output is offloaded the same way, and the result line points at it
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/execute")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::ACCEPT, NDJSON_CONTENT_TYPE)
+                    .header(X_REQUEST_ID, "req-artifacts-ndjson")
+                    .body(Body::from(r#"{"code": "print('big')"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let result: serde_json::Value = serde_json::from_str(body.lines().last().unwrap()).unwrap();
+        assert_eq!(result["type"], "result");
+        assert_eq!(
+            result["artifacts"][0]["url"],
+            "/artifacts/req-artifacts-ndjson/stdout"
+        );
+
+        for missing in [
+            "/artifacts/req-artifacts/stderr",
+            "/artifacts/..%2Fetc/passwd",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(missing).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{missing}");
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_execute_msgpack_body_with_json_content_type() {
+        let app = create_app(AppState::default());
+        let request = ExecuteRequest {
+            code: Some("print('packed')".to_string()),
+            ..Default::default()
+        };
+
+        let response = app
+            .oneshot(post_execute_as(
+                "application/json",
+                "application/json",
+                rmp_serde::to_vec_named(&request).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "unsupported_media_type");
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .contains("application/msgpack")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_result_cache() {
+        let app = create_app(AppState::default());
+        let cached_request = r#"{"code": "print('cache me')", "cache": true}"#;
+
+        let miss = post_execute(&app, cached_request).await;
+        assert!(miss.get("cached").is_none());
+        let hit = post_execute(&app, cached_request).await;
+        assert_eq!(hit["cached"], true);
+        assert_eq!(hit["stdout"], miss["stdout"]);
+
+        // Requests that don't opt in never see cached results
+        let uncached = post_execute(&app, r#"{"code": "print('cache me')"}"#).await;
+        assert!(uncached.get("cached").is_none());
+
+        // Bypass executes again
+        let bypassed = post_execute(
+            &app,
+            r#"{"code": "print('cache me')", "cache": true, "cache_bypass": true}"#,
+        )
+        .await;
+        assert!(bypassed.get("cached").is_none());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/admin/cache")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["cleared"], 1);
+        let after_clear = post_execute(&app, cached_request).await;
+        assert!(after_clear.get("cached").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_execute_writes_the_audit_log() {
+        let path = std::env::temp_dir().join(format!("fc-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let app = create_app(AppState::new(Config {
+            api_keys: vec!["audited-key".to_string()],
+            audit: firecracker_poc::audit::AuditConfig {
+                path: Some(path.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let auth = Some("Bearer audited-key");
+        let response = app.clone().oneshot(execute_request(auth)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let vm_id = response.headers()[X_VM_ID].to_str().unwrap().to_string();
+        let mut empty = post_json(r#"{"code": "  "}"#);
+        empty
+            .headers_mut()
+            .insert(header::AUTHORIZATION, "Bearer audited-key".parse().unwrap());
+        let response = app.oneshot(empty).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Records are written in the background
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            lines = std::fs::read_to_string(&path)
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect();
+            if lines.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(lines.len(), 2);
+        let key_id = ApiKeys::new(&["audited-key".to_string()])
+            .verify("audited-key")
+            .unwrap()
+            .0;
+        assert_eq!(lines[0]["api_key_id"], key_id.as_str());
+        assert_eq!(lines[0]["vm_id"], vm_id.as_str());
+        assert_eq!(
+            lines[0]["code_sha256"],
+            firecracker_poc::history::code_sha256("print('limited')")
+        );
+        assert_eq!(lines[0]["success"], true);
+        assert_eq!(lines[1]["success"], false);
+        assert_eq!(lines[1]["error_code"], "empty_code");
+        assert_eq!(lines[1]["vm_id"], serde_json::Value::Null);
+        let _ = std::fs::remove_file(&path);
+    }
 
-    // Validate input
-    if payload.code.trim().is_empty() {
-        let error_response = create_error_response("Empty code provided".to_string());
-        return Err((StatusCode::BAD_REQUEST, ResponseJson(error_response)));
+    #[tokio::test]
+    async fn test_execute_names_the_vm() {
+        let app = create_app(AppState::default());
+        let request = r#"{"code": "print('which vm')", "cache": true}"#;
+        let response = app.clone().oneshot(post_json(request)).await.unwrap();
+        let vm_id = response.headers().get(X_VM_ID).unwrap().to_str().unwrap();
+        assert!(runner::live_vm(vm_id).is_some());
+
+        // A cached result didn't run on any VM
+        let response = app.clone().oneshot(post_json(request)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(X_VM_ID).is_none());
     }
 
-    // Check code length limit (prevent extremely large payloads)
-    if payload.code.len() > 10_000 {
-        let error_response =
-            create_error_response("Code exceeds maximum length of 10,000 characters".to_string());
-        return Err((StatusCode::BAD_REQUEST, ResponseJson(error_response)));
+    #[tokio::test]
+    async fn test_saturated_pool_answers_503_with_retry_after() {
+        let state = AppState::default();
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(500),
+            max_concurrent_executions: Some(1),
+            vm_wait_budget: Some(std::time::Duration::from_millis(50)),
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
+
+        let running = tokio::spawn({
+            let app = app.clone();
+            async move { post_execute(&app, r#"{"code": "print('first')"}"#).await }
+        });
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while executor.stats().await.in_flight == 0 {
+            assert!(std::time::Instant::now() < deadline, "never started");
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        let response = app
+            .clone()
+            .oneshot(post_json(r#"{"code": "print('second')"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.headers()[header::RETRY_AFTER],
+            ADMISSION_RETRY_AFTER_SECS.to_string()
+        );
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["code"], "pool_exhausted");
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .contains("No VM became available within"),
+            "{body}"
+        );
+
+        // The request holding the only permit is unaffected, and leaves nobody queued
+        assert_eq!(running.await.unwrap()["success"], true);
+        assert_eq!(executor.stats().await.queued, 0);
+        executor.shutdown().await;
     }
 
-    // Execute code in VM
-    match run_in_vm(&payload.code).await {
-        Ok(response) => {
-            info!("Code execution completed successfully");
-            Ok(ResponseJson(response))
+    #[tokio::test]
+    async fn test_ndjson_stream_reports_queue_position() {
+        let state = AppState::default();
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(100),
+            max_concurrent_executions: Some(1),
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
+
+        let running = tokio::spawn({
+            let app = app.clone();
+            async move { post_execute(&app, r#"{"code": "print('first')"}"#).await }
+        });
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while executor.stats().await.in_flight == 0 {
+            assert!(std::time::Instant::now() < deadline, "never started");
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
         }
-        Err(e) => {
-            error!("Code execution failed: {}", e);
-            let error_response = create_error_response(format!("Execution failed: {e}"));
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                ResponseJson(error_response),
+
+        let response = app
+            .oneshot(post_execute_as(
+                "application/json",
+                NDJSON_CONTENT_TYPE,
+                br#"{"code": "print('second')"}"#.to_vec(),
             ))
-        }
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let lines: Vec<serde_json::Value> = String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines[0]["type"], "queued");
+        assert_eq!(lines[0]["position"], 1);
+        // Nothing had finished yet to estimate from
+        assert!(lines[0]["eta_ms"].is_null());
+        assert_eq!(lines[1]["type"], "queued");
+        assert_eq!(lines[1]["position"], 0);
+        assert_eq!(lines[2]["type"], "stdout");
+        assert_eq!(lines.last().unwrap()["type"], "result");
+
+        assert_eq!(running.await.unwrap()["success"], true);
+        assert!(executor.stats().await.avg_execution_ms.is_some());
+        executor.shutdown().await;
     }
-}
 
-/// Health check endpoint
-async fn health_handler() -> &'static str {
-    "OK"
-}
+    #[tokio::test]
+    async fn test_execute_deadline_too_short_to_boot_answers_504_at_once() {
+        let state = AppState::new(Config {
+            execute_deadline: std::time::Duration::from_millis(50),
+            ..Default::default()
+        });
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(300),
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
 
-/// Create the application router
-fn create_app() -> Router {
-    Router::new()
-        .route("/execute", post(execute_handler))
-        .route("/health", axum::routing::get(health_handler))
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
-}
+        let started = std::time::Instant::now();
+        let response = app
+            .oneshot(post_json(r#"{"code": "print('slow')"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < std::time::Duration::from_millis(300));
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["code"], "timeout");
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
+        // The runner knew the deadline, so no VM was booted only to be abandoned
+        let stats = executor.stats().await;
+        assert_eq!((stats.in_flight, stats.vms_created), (0, 0));
+        executor.shutdown().await;
+    }
 
-    let app = create_app();
+    fn quick_app(latency: std::time::Duration) -> (Router, ExecutorService) {
+        let state = AppState::new(Config {
+            quick_timeout: std::time::Duration::from_millis(100),
+            ..Default::default()
+        });
+        let executor = ExecutorService::new(Arc::new(firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: latency,
+            ..Default::default()
+        }));
+        let app = create_app(AppState {
+            service: ExecutionService::new(state.config.clone(), executor.clone()),
+            ..state
+        });
+        (app, executor)
+    }
 
-    // Bind to address
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    info!("Firecracker POC server starting on {}", addr);
+    fn post_quick(body: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/execute/quick")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
 
-    // Create listener
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    info!("Server listening on http://{}", addr);
-    info!("Available endpoints:");
-    info!("  POST /execute - Execute Python code in secure microVM");
-    info!("  GET  /health  - Health check endpoint");
+    #[tokio::test]
+    async fn test_quick_execute_rejects_large_code_and_extra_fields() {
+        let (app, executor) = quick_app(std::time::Duration::ZERO);
+        executor.warm(1).await;
 
-    // Pre-warm VM pool in background
-    tokio::spawn(async {
-        info!("Pre-warming VM pool ({} VMs)...", runner::VM_PREWARM_COUNT);
-        for i in 1..=runner::VM_PREWARM_COUNT {
-            match runner::create_new_vm().await {
-                Ok(vm) => {
-                    let mut pool = runner::VM_POOL.lock().await;
-                    pool.push_back(vm);
-                    debug!("Pre-warmed VM {} added to pool", i);
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to pre-warm VM {}: {}", i, e);
-                }
+        let code = "x".repeat(QUICK_MAX_CODE_BYTES + 1);
+        let body = serde_json::json!({ "code": code }).to_string();
+        let response = app.clone().oneshot(post_quick(&body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["code"], "code_too_long");
+
+        // Anything beyond the snippet belongs on `/execute`
+        let response = app
+            .clone()
+            .oneshot(post_quick(r#"{"code": "1", "requirements": ["numpy"]}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = app.oneshot(post_quick(r#"{"code": ""}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Nothing was spent on the rejected requests
+        let stats = executor.stats().await;
+        assert_eq!((stats.idle_vms, stats.vms_created), (1, 1));
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_quick_execute_needs_a_warm_vm_and_never_boots_one() {
+        let (app, executor) = quick_app(std::time::Duration::ZERO);
+        let response = app
+            .clone()
+            .oneshot(post_quick(r#"{"code": "print(1)"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["code"], "no_warm_vm");
+        assert_eq!(executor.stats().await.vms_created, 0);
+
+        executor.warm(1).await;
+        let response = app
+            .clone()
+            .oneshot(post_quick(r#"{"code": "print(1)"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(X_VM_ID));
+        let stats = executor.stats().await;
+        assert_eq!((stats.vms_created, stats.pool_hits), (1, 1));
+        assert!(
+            telemetry::METRICS
+                .render()
+                .contains("fc_execute_latency_ms_count{endpoint=\"/execute/quick\"}")
+        );
+
+        // `/execute` is timed under its own label
+        app.oneshot(post_json(r#"{"code": "print(1)"}"#))
+            .await
+            .unwrap();
+        assert!(
+            telemetry::METRICS
+                .render()
+                .contains("fc_execute_latency_ms_count{endpoint=\"/execute\"}")
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_quick_execute_times_out_and_discards_the_vm() {
+        let (app, executor) = quick_app(std::time::Duration::from_millis(500));
+        executor.warm(1).await;
+
+        let started = std::time::Instant::now();
+        let response = app
+            .oneshot(post_quick(r#"{"code": "print('slow')"}"#))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["code"], "timeout");
+        let stats = executor.stats().await;
+        assert_eq!(
+            (stats.in_flight, stats.idle_vms, stats.cancelled),
+            (0, 0, 1)
+        );
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_client_deadline_header_stops_the_execution() {
+        let (app, executor) = slow_app(std::time::Duration::from_millis(300));
+        executor.warm(1).await;
+        let mut request = post_json(r#"{"code": "print('slow')"}"#);
+        request
+            .headers_mut()
+            .insert(X_DEADLINE_MS, header::HeaderValue::from_static("150"));
+
+        let started = std::time::Instant::now();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(started.elapsed() < std::time::Duration::from_millis(300));
+
+        // Nobody waits for the result, so the VM is discarded instead of finishing the run
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let stats = executor.stats().await;
+            if (stats.in_flight, stats.idle_vms) == (0, 0) {
+                break;
             }
+            assert!(
+                std::time::Instant::now() < deadline,
+                "execution never stopped"
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
         }
-        info!("VM pool pre-warming completed");
-    });
 
-    // Start server
-    axum::serve(listener, app).await?;
+        let mut request = post_json(r#"{"code": "print(1)"}"#);
+        request
+            .headers_mut()
+            .insert(X_DEADLINE_MS, header::HeaderValue::from_static("soon"));
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        executor.shutdown().await;
+    }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_execute_output_limit_per_request() {
+        let app = create_app(AppState::default());
+        let body = post_execute(&app, r#"{"code": "é", "max_output_bytes": 20}"#).await;
+        // "Mock execution of: " is 19 bytes, so a 20-byte cap falls inside the 2-byte "é"
+        assert_eq!(body["stdout"], "Mock execution of: ");
+        assert_eq!(body["stdout_truncated"], true);
+        assert_eq!(body["stderr_truncated"], false);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::body::Body;
-    use axum::http::{Request, StatusCode, header};
-    use tower::ServiceExt;
+        let body = post_execute(
+            &app,
+            r#"{"code": "print(1)", "max_output_bytes": 1000000000}"#,
+        )
+        .await;
+        assert_eq!(body["stdout"], "Mock execution of: print(1)\n");
+        assert_eq!(body["stdout_truncated"], false);
+    }
 
     #[tokio::test]
-    async fn test_health_endpoint() {
-        let app = create_app();
+    async fn test_execute_multi_file_program() {
+        let app = create_app(AppState::default());
+        let body = serde_json::json!({
+            "files": [
+                { "path": "main.py", "content": "from utils import greet\nprint(greet('fc'))" },
+                { "path": "utils.py", "content": "def greet(name):\n    return f'hi {name}'" }
+            ],
+            "entrypoint": "main.py"
+        });
+        let body = post_execute(&app, &body.to_string()).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(
+            body["stdout"],
+            "Mock execution of: main.py with main.py, utils.py\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_requirements() {
+        let body = r#"{"code": "import numpy", "requirements": ["numpy>=1.26"]}"#;
+
+        // Without network access there is nothing to install from
+        let app = create_app(AppState::default());
+        let response = app.oneshot(post_json(body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let app = create_app(AppState::new(Config {
+            allow_network: true,
+            ..Config::default()
+        }));
+        let body = post_execute(&app, body).await;
+        assert_eq!(body["setup_stdout"], "Mock install of: numpy>=1.26\n");
+        assert_eq!(body["stdout"], "Mock execution of: import numpy\n");
+
+        // Plain requests keep the old response shape
+        let body = post_execute(&app, r#"{"code": "print(1)"}"#).await;
+        assert!(body.get("setup_stdout").is_none());
+
+        for bad in [
+            r#"{"code": "1", "requirements": ["https://evil.example/x.whl"]}"#,
+            r#"{"code": "1", "requirements": ["--index-url=https://evil.example"]}"#,
+            r#"{"code": "1", "requirements": ["../pkg"]}"#,
+        ] {
+            let response = app.clone().oneshot(post_json(bad)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{bad}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_inputs() {
+        let app = create_app(AppState::new(Config {
+            allow_network: true,
+            ..Config::default()
+        }));
+        let body = post_execute(
+            &app,
+            r#"{"code": "1", "inputs": [{"url": "https://store.example/a.csv", "path": "a.csv"}]}"#,
+        )
+        .await;
+        assert_eq!(
+            body["setup_stdout"],
+            "Mock download of: https://store.example/a.csv to a.csv\n"
+        );
+
+        for (url, status, code) in [
+            (
+                "http://169.254.169.254/latest/meta-data",
+                StatusCode::FORBIDDEN,
+                "input_host_forbidden",
+            ),
+            (
+                "http://localhost:3000/health",
+                StatusCode::FORBIDDEN,
+                "input_host_forbidden",
+            ),
+            (
+                "file:///etc/passwd",
+                StatusCode::BAD_REQUEST,
+                "invalid_inputs",
+            ),
+        ] {
+            let request = serde_json::json!({
+                "code": "1",
+                "inputs": [{ "url": url, "path": "a.csv" }]
+            });
+            let response = app
+                .clone()
+                .oneshot(post_json(&request.to_string()))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{url}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body.code, code, "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_deterministic() {
+        let app = create_app(AppState::default());
+        let body = post_execute(
+            &app,
+            r#"{"code": "print(1)", "deterministic": true, "fake_time": 1700000000}"#,
+        )
+        .await;
+        assert_eq!(body["deterministic"], true);
+        assert_eq!(body["hash_seed"], 0);
+        assert_eq!(body["fake_time"], 1_700_000_000);
+
+        let body = post_execute(&app, r#"{"code": "print(1)"}"#).await;
+        assert!(body.get("deterministic").is_none());
+        assert!(body.get("hash_seed").is_none());
 
         let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/health")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
+            .oneshot(post_json(r#"{"code": "print(1)", "fake_time": 0}"#))
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 
-        assert_eq!(response.status(), StatusCode::OK);
+    #[tokio::test]
+    async fn test_execute_unknown_deps_profile() {
+        let app = create_app(AppState::default());
+        let response = app
+            .oneshot(post_json(
+                r#"{"code": "import numpy", "deps_profile": "nope"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn test_execute_endpoint_empty_code() {
-        let app = create_app();
+    async fn test_execute_eval_mode_returns_a_value() {
+        let app = create_app(AppState::default());
+        for (code, value, value_repr) in [
+            (
+                "x = 20\n{\"answer\": 42}",
+                Some(serde_json::json!({ "answer": 42 })),
+                None,
+            ),
+            (
+                "import fractions\nfractions.Fraction(1, 3)",
+                None,
+                Some("fractions.Fraction(1, 3)"),
+            ),
+        ] {
+            let request = serde_json::json!({ "code": code, "mode": "eval" }).to_string();
+            let response = app.clone().oneshot(post_json(&request)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let response: ExecuteResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response.stdout, format!("Mock evaluation of: {code}\n"));
+            assert_eq!(response.value, value);
+            assert_eq!(response.value_repr.as_deref(), value_repr);
+        }
+    }
 
+    #[tokio::test]
+    async fn test_traffic_shape_needs_a_trusted_key() {
+        let app = create_app(AppState::default());
         let response = app
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/execute")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(r#"{"code": ""}"#))
-                    .unwrap(),
-            )
+            .oneshot(post_json(
+                r#"{"code": "print(1)", "traffic_shape": {"rate_kbit": 1000, "burst_bytes": 16384}}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
             .await
             .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "traffic_shape_not_allowed");
+    }
 
+    #[tokio::test]
+    async fn test_console_capture_needs_the_server_to_allow_it() {
+        let app = create_app(AppState::default());
+        let response = app
+            .oneshot(post_json(
+                r#"{"code": "print(1)", "capture_console": true}"#,
+            ))
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "console_capture_disabled");
     }
 
     #[tokio::test]
-    async fn test_execute_endpoint_too_long_code() {
-        let app = create_app();
-        let long_code = "a".repeat(10_001);
-
-        let request_body = format!(r#"{{"code": "{long_code}"}}"#);
+    async fn test_debug_events_are_denied_to_untrusted_keys() {
+        let keys = ApiKeys::new(&["tenant-key".to_string()]);
+        let tenant_id = keys.verify("tenant-key").unwrap().0;
+        let app = create_app(AppState::new(Config {
+            api_keys: vec!["ops-key".to_string(), "tenant-key".to_string()],
+            debug_events: firecracker_poc::debug_events::DebugEventsConfig {
+                enabled: true,
+                denied_key_ids: vec![tenant_id],
+            },
+            ..Default::default()
+        }));
+        let debug_request = |key: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/execute")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("Bearer {key}"))
+                .body(Body::from(r#"{"code": "print(1)", "debug": true}"#))
+                .unwrap()
+        };
 
         let response = app
-            .oneshot(
-                Request::builder()
-                    .method("POST")
-                    .uri("/execute")
-                    .header(header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(request_body))
-                    .unwrap(),
-            )
+            .clone()
+            .oneshot(debug_request("tenant-key"))
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "debug_not_allowed");
 
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = app.oneshot(debug_request("ops-key")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let events = body["events"].as_array().unwrap();
+        assert_eq!(events[0]["event"], "permit_granted");
+        assert!(events.iter().any(|e| e["event"] == "guest_request_sent"));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_endpoints_follow_its_lifetime() {
+        let app = create_app(AppState::new(Config::default()));
+        let sandbox_request = |method: &str, uri: String, body: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let keep = |app: Router| async move {
+            let response = app
+                .oneshot(post_json(r#"{"code": "x = 1", "keep_alive_seconds": 1}"#))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: ExecuteResponse = serde_json::from_slice(&body).unwrap();
+            assert!(body.sandbox_expires_at_ms.is_some());
+            body.sandbox_id.unwrap()
+        };
+        let status_of = |response: Response| async move {
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+            (status, body.code)
+        };
+
+        let sandbox_id = keep(app.clone()).await;
+        let follow_up = sandbox_request(
+            "POST",
+            format!("/sandboxes/{sandbox_id}/execute"),
+            r#"{"code": "print(x)"}"#,
+        );
+        let response = app.clone().oneshot(follow_up).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(X_VM_ID));
+        let reboot = sandbox_request(
+            "POST",
+            format!("/sandboxes/{sandbox_id}/execute"),
+            r#"{"code": "print(x)", "image": "python-3.12"}"#,
+        );
+        let response = app.clone().oneshot(reboot).await.unwrap();
+        assert_eq!(
+            status_of(response).await,
+            (StatusCode::BAD_REQUEST, "invalid_options".to_string())
+        );
+        // Options a run in a sandbox can't honor aren't dropped silently either
+        for option in [
+            r#""capture_console": true"#,
+            r#""debug": false"#,
+            r#""priority": "high""#,
+            r#""affinity_key": "k""#,
+            r#""cache": true"#,
+        ] {
+            let request = sandbox_request(
+                "POST",
+                format!("/sandboxes/{sandbox_id}/execute"),
+                &format!(r#"{{"code": "print(x)", {option}}}"#),
+            );
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(
+                status_of(response).await,
+                (StatusCode::BAD_REQUEST, "invalid_options".to_string()),
+                "{option}"
+            );
+        }
+
+        let delete = sandbox_request("DELETE", format!("/sandboxes/{sandbox_id}"), "");
+        let response = app.clone().oneshot(delete).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let after_delete = sandbox_request(
+            "POST",
+            format!("/sandboxes/{sandbox_id}/execute"),
+            r#"{"code": "print(x)"}"#,
+        );
+        let response = app.clone().oneshot(after_delete).await.unwrap();
+        assert_eq!(
+            status_of(response).await,
+            (StatusCode::GONE, "sandbox_expired".to_string())
+        );
+
+        let sandbox_id = keep(app.clone()).await;
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let expired = sandbox_request(
+            "POST",
+            format!("/sandboxes/{sandbox_id}/execute"),
+            r#"{"code": "print(x)"}"#,
+        );
+        let response = app.clone().oneshot(expired).await.unwrap();
+        assert_eq!(
+            status_of(response).await,
+            (StatusCode::GONE, "sandbox_expired".to_string())
+        );
+        let unknown = sandbox_request("DELETE", "/sandboxes/sbx-unknown".to_string(), "");
+        let response = app.clone().oneshot(unknown).await.unwrap();
+        assert_eq!(
+            status_of(response).await,
+            (StatusCode::NOT_FOUND, "sandbox_not_found".to_string())
+        );
+
+        let too_long = post_json(r#"{"code": "x = 1", "keep_alive_seconds": 86400}"#);
+        let response = app.oneshot(too_long).await.unwrap();
+        assert_eq!(
+            status_of(response).await,
+            (StatusCode::BAD_REQUEST, "invalid_options".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execution_failures_keep_their_status_and_envelope() {
+        let cases = || {
+            [
+                (
+                    ExecutionError::SerializationError("truncated reply".into()),
+                    StatusCode::BAD_REQUEST,
+                    "serialization_error",
+                    false,
+                ),
+                (
+                    ExecutionError::GuestOutOfMemory {
+                        process: "python3".into(),
+                        mem_size_mib: 128,
+                    },
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "guest_out_of_memory",
+                    false,
+                ),
+                (
+                    ExecutionError::GuestProtocolError {
+                        feature: "eval".into(),
+                        required: 2,
+                        agent: 1,
+                    },
+                    StatusCode::BAD_REQUEST,
+                    "guest_protocol_error",
+                    false,
+                ),
+                (
+                    ExecutionError::PoolExhausted {
+                        waited_ms: 50,
+                        queue_depth: 3,
+                    },
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "pool_exhausted",
+                    true,
+                ),
+                (
+                    ExecutionError::ResourceExhausted("4 of 4 VMs running".into()),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "resource_exhausted",
+                    true,
+                ),
+                (
+                    ExecutionError::DeadlineExceeded {
+                        phase: "boot".into(),
+                        budget_ms: 50,
+                    },
+                    StatusCode::GATEWAY_TIMEOUT,
+                    "timeout",
+                    false,
+                ),
+                (
+                    ExecutionError::PrivilegeError("no CAP_NET_ADMIN".into()),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "privilege_error",
+                    false,
+                ),
+                (
+                    ExecutionError::ShuttingDown,
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "shutting_down",
+                    true,
+                ),
+            ]
+        };
+        // Errors reach the handler through the service's mapping, and MessagePack callers get
+        // the same status and envelope as JSON ones
+        for format in [Format::Json, Format::MsgPack] {
+            for (error, status, code, retries) in cases() {
+                let message = error.to_string();
+                let response = rejection_response(Rejection::from(error), format);
+                assert_eq!(response.status(), status, "{code}");
+                assert_eq!(
+                    response.headers().contains_key(header::RETRY_AFTER),
+                    retries,
+                    "{code}"
+                );
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: ErrorResponse = match format {
+                    Format::Json => serde_json::from_slice(&body).unwrap(),
+                    Format::MsgPack => rmp_serde::from_slice(&body).unwrap(),
+                };
+                assert_eq!(body.code, code);
+                assert_eq!(body.error, message);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refused_executions_answer_with_their_own_code() {
+        async fn refused(state: AppState, deadline_ms: Option<&str>) -> (StatusCode, String) {
+            let mut request = Request::builder()
+                .method("POST")
+                .uri("/execute")
+                .header("content-type", "application/json");
+            if let Some(deadline_ms) = deadline_ms {
+                request = request.header("x-deadline-ms", deadline_ms);
+            }
+            let request = request.body(Body::from(r#"{"code": "print(1)"}"#)).unwrap();
+            let response = create_app(state).oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, body["code"].as_str().unwrap().to_string())
+        }
+
+        fn serving(executor: ExecutorService) -> AppState {
+            let state = AppState::default();
+            AppState {
+                service: ExecutionService::new(state.config.clone(), executor),
+                ..state
+            }
+        }
+        let mock = || firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Mock,
+            ..Default::default()
+        };
+
+        // Pool exhaustion has its own case in `test_saturated_pool_answers_503_with_retry_after`
+        let executor = ExecutorService::new(Arc::new(mock()));
+        executor.shutdown().await;
+        let state = serving(executor);
+        assert_eq!(
+            refused(state, None).await,
+            (StatusCode::SERVICE_UNAVAILABLE, "shutting_down".to_string())
+        );
+
+        let full = serving(ExecutorService::new(Arc::new(
+            firecracker_poc::config::RunnerConfig {
+                max_vms: Some(0),
+                ..mock()
+            },
+        )));
+        assert_eq!(
+            refused(full, None).await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "resource_exhausted".to_string()
+            )
+        );
+
+        assert_eq!(
+            refused(serving(ExecutorService::new(Arc::new(mock()))), Some("50")).await,
+            (StatusCode::GATEWAY_TIMEOUT, "timeout".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_multi_file_validation() {
+        let app = create_app(AppState::default());
+        let cases = [
+            (
+                serde_json::json!({ "code": "print(1)", "files": [], "entrypoint": "main.py" }),
+                StatusCode::BAD_REQUEST,
+                "not both",
+            ),
+            (serde_json::json!({}), StatusCode::BAD_REQUEST, "either"),
+            (
+                serde_json::json!({ "files": [{ "path": "main.py", "content": "" }] }),
+                StatusCode::BAD_REQUEST,
+                "either",
+            ),
+            (
+                serde_json::json!({ "files": [], "entrypoint": "main.py" }),
+                StatusCode::BAD_REQUEST,
+                "must not be empty",
+            ),
+            (
+                serde_json::json!({ "files": [{ "path": "/main.py", "content": "" }], "entrypoint": "/main.py" }),
+                StatusCode::BAD_REQUEST,
+                "absolute",
+            ),
+            (
+                serde_json::json!({ "files": [{ "path": "../main.py", "content": "" }], "entrypoint": "../main.py" }),
+                StatusCode::BAD_REQUEST,
+                "'..'",
+            ),
+            (
+                serde_json::json!({
+                    "files": [{ "path": "main.py", "content": "" }, { "path": "main.py", "content": "" }],
+                    "entrypoint": "main.py"
+                }),
+                StatusCode::BAD_REQUEST,
+                "Duplicate",
+            ),
+            (
+                serde_json::json!({ "files": [{ "path": "main.py", "content": "" }], "entrypoint": "app.py" }),
+                StatusCode::BAD_REQUEST,
+                "Entrypoint",
+            ),
+            (
+                serde_json::json!({
+                    "files": [{ "path": "main.py", "content": "1" }],
+                    "entrypoint": "main.py",
+                    "mode": "eval"
+                }),
+                StatusCode::BAD_REQUEST,
+                "'eval'",
+            ),
+            // Sizes add up across files: 2 * 5,001 exceeds the 10,000 limit
+            (
+                serde_json::json!({
+                    "files": [
+                        { "path": "main.py", "content": "a".repeat(5_001) },
+                        { "path": "utils.py", "content": "a".repeat(5_001) }
+                    ],
+                    "entrypoint": "main.py"
+                }),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "10000",
+            ),
+        ];
+        for (body, status, message) in cases {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/execute")
+                        .header(header::CONTENT_TYPE, "application/json")
+                        .body(Body::from(body.to_string()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{body}");
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let error: ErrorResponse = serde_json::from_slice(&bytes).unwrap();
+            assert!(error.error.contains(message), "{body}: {error:?}");
+        }
     }
 
     #[tokio::test]
     async fn test_execute_endpoint_invalid_json() {
-        let app = create_app();
+        let app = create_app(AppState::default());
 
         let response = app
             .oneshot(
@@ -186,7 +5153,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_execute_endpoint_missing_content_type() {
-        let app = create_app();
+        let app = create_app(AppState::default());
 
         let response = app
             .oneshot(
@@ -205,7 +5172,7 @@ mod tests {
     #[tokio::test]
     async fn test_execute_endpoint_structure() {
         // This test verifies the endpoint structure without actual VM execution
-        let app = create_app();
+        let app = create_app(AppState::default());
 
         let response = app
             .oneshot(
@@ -226,4 +5193,197 @@ mod tests {
                 || response.status() == StatusCode::INTERNAL_SERVER_ERROR
         );
     }
+
+    /// Serve `state` on an ephemeral local port for the typed client
+    #[cfg(feature = "client")]
+    async fn serve_locally(state: AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                create_app(state).into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .await
+        });
+        format!("http://{addr}")
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_client_execute_and_health() {
+        use firecracker_poc::client::{ClientError, FirecrackerPocClient};
+
+        let client = FirecrackerPocClient::new(serve_locally(AppState::default()).await);
+        assert_eq!(client.health().await.unwrap().status, "healthy");
+
+        let response = client
+            .execute(&ExecuteRequest {
+                code: Some("print('typed')".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(response.success);
+        assert_eq!(response.stdout, "Mock execution of: print('typed')\n");
+
+        let err = client
+            .execute(&ExecuteRequest {
+                code: Some(" ".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        let ClientError::Server {
+            status,
+            code,
+            message,
+            ..
+        } = err
+        else {
+            panic!("expected a server error, got {err}");
+        };
+        assert_eq!(status, 422);
+        assert_eq!(code.as_deref(), Some("empty_code"));
+        assert_eq!(message, "Empty code provided");
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_client_reports_error_codes() {
+        use firecracker_poc::client::{ClientError, FirecrackerPocClient};
+
+        let base_url = serve_locally(AppState::new(Config {
+            api_keys: vec!["secret".to_string()],
+            ..Default::default()
+        }))
+        .await;
+        let request = ExecuteRequest {
+            code: Some("print(1)".to_string()),
+            ..Default::default()
+        };
+
+        let err = FirecrackerPocClient::new(&base_url)
+            .execute(&request)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, ClientError::Server { status: 401, code: Some(code), .. } if code == "unauthorized"),
+            "{err}"
+        );
+
+        let client = FirecrackerPocClient::new(&base_url).with_api_key("secret");
+        assert!(client.execute(&request).await.unwrap().success);
+
+        // Nothing listens on the port once its listener is dropped
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let err = FirecrackerPocClient::new(url).health().await.unwrap_err();
+        assert!(matches!(err, ClientError::Transport(_)), "{err}");
+    }
+
+    /// App whose executions run as real python3 processes
+    #[cfg(feature = "local-backend")]
+    fn local_app() -> Router {
+        let state = AppState::default();
+        let runner = firecracker_poc::config::RunnerConfig {
+            backend: BackendKind::Local,
+            runtime_dir: std::env::temp_dir(),
+            ..Default::default()
+        };
+        create_app(AppState {
+            service: ExecutionService::new(
+                state.config.clone(),
+                ExecutorService::new(Arc::new(runner)),
+            ),
+            ..state
+        })
+    }
+
+    #[cfg(feature = "local-backend")]
+    #[tokio::test]
+    async fn test_local_backend_runs_real_python() {
+        let app = local_app();
+        let body = post_execute(
+            &app,
+            r#"{"code": "import sys\nprint(sum(range(10)))\nprint('oops', file=sys.stderr)"}"#,
+        )
+        .await;
+        assert_eq!(body["stdout"], "45\n");
+        assert_eq!(body["stderr"], "oops\n");
+        assert_eq!(body["success"], true);
+
+        let body = post_execute(&app, r#"{"code": "raise ValueError('bad input')"}"#).await;
+        assert_eq!(body["success"], false);
+        let stderr = body["stderr"].as_str().unwrap();
+        assert!(stderr.contains("ValueError: bad input"), "{stderr}");
+
+        // Stdin is empty and the environment is the guest's minimal one
+        let body = post_execute(
+            &app,
+            r#"{"code": "import os, sys\nprint(repr(sys.stdin.read()), 'CARGO' in os.environ)"}"#,
+        )
+        .await;
+        assert_eq!(body["stdout"], "'' False\n");
+    }
+
+    #[cfg(feature = "local-backend")]
+    #[tokio::test]
+    async fn test_local_backend_caps_output_and_runs_files() {
+        let app = local_app();
+        let body = post_execute(
+            &app,
+            r#"{"code": "print('x' * 100000)", "max_output_bytes": 10}"#,
+        )
+        .await;
+        assert_eq!(body["stdout"], "xxxxxxxxxx");
+        assert_eq!(body["stdout_truncated"], true);
+
+        let body = post_execute(
+            &app,
+            r#"{"files": [
+                {"path": "main.py", "content": "from pkg.greet import hello\nhello()"},
+                {"path": "pkg/__init__.py", "content": ""},
+                {"path": "pkg/greet.py", "content": "def hello():\n    print('hello from pkg')"}
+            ], "entrypoint": "main.py"}"#,
+        )
+        .await;
+        assert_eq!(body["stdout"], "hello from pkg\n");
+        assert_eq!(body["success"], true);
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test]
+    async fn test_loadtest_smoke_run() {
+        use firecracker_poc::loadtest::{self, LoadTestSpec};
+
+        let spec = LoadTestSpec::from_args(
+            [
+                "--mock",
+                "--duration",
+                "0.5",
+                "--code",
+                "print(1)",
+                "--code",
+                "print(2)",
+            ]
+            .map(String::from),
+        )
+        .unwrap();
+        let spec = LoadTestSpec {
+            target: serve_locally(AppState::default()).await,
+            ..spec
+        };
+        let report = loadtest::run(&spec).await;
+        assert!(report.requests > 0);
+        assert!(report.passes(0.0), "{}", report.render());
+        // Every response named its VM, and the pool served most of them from few VMs
+        assert_eq!(report.with_vm_id, report.requests);
+        assert!(
+            report.vm_reuse_ratio().unwrap() > 0.0,
+            "{}",
+            report.render()
+        );
+    }
 }
@@ -0,0 +1,116 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Console output read for OOM-killer lines after an execution; kernels print a few dozen
+/// lines per kill, so this covers several
+pub const CONSOLE_SCAN_BYTES: u64 = 64 * 1024;
+
+/// A process the guest kernel's OOM killer terminated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OomKill {
+    pub pid: u32,
+    /// Command name, e.g. `python3`
+    pub process: String,
+}
+
+/// The `<pid> (<name>)` after `Killed process ` or, on older kernels, `Kill process `
+fn parse_kill(line: &str) -> Option<OomKill> {
+    let rest = ["Killed process ", "Kill process "]
+        .into_iter()
+        .find_map(|marker| line.split_once(marker).map(|(_, rest)| rest))?;
+    let (pid, rest) = rest.split_once(' ')?;
+    let process = rest.strip_prefix('(')?.split_once(')')?.0;
+    Some(OomKill {
+        pid: pid.parse().ok()?,
+        process: process.to_string(),
+    })
+}
+
+/// Processes killed by the OOM killer according to kernel console output, in order. Older
+/// kernels name each victim twice, which counts once.
+pub fn oom_kills(console: &str) -> Vec<OomKill> {
+    let mut kills: Vec<OomKill> = Vec::new();
+    for kill in console.lines().filter_map(parse_kill) {
+        if kills.last() != Some(&kill) {
+            kills.push(kill);
+        }
+    }
+    kills
+}
+
+/// Appended to the stderr of code the OOM killer terminated, naming the limit it hit
+pub fn oom_note(mem_size_mib: u64) -> String {
+    format!(
+        "\nKilled by the guest's out-of-memory killer; the VM has {mem_size_mib} MiB of memory\n"
+    )
+}
+
+/// Size of the console log at `path`, to scan only what is written after it
+pub fn console_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+}
+
+/// Console output at `path` written since it was `from` bytes long, at most the last
+/// `CONSOLE_SCAN_BYTES` of it
+pub fn console_since(path: &Path, from: u64) -> String {
+    let Ok(mut file) = std::fs::File::open(path) else {
+        return String::new();
+    };
+    let len = file.metadata().map(|meta| meta.len()).unwrap_or(0);
+    let start = from.max(len.saturating_sub(CONSOLE_SCAN_BYTES)).min(len);
+    let mut bytes = Vec::new();
+    if file.seek(SeekFrom::Start(start)).is_err() || file.read_to_end(&mut bytes).is_err() {
+        return String::new();
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("fixtures/oom/{name}")).unwrap()
+    }
+
+    #[test]
+    fn test_finds_agent_kill_in_console_output() {
+        assert_eq!(
+            oom_kills(&fixture("agent-killed.log")),
+            [OomKill {
+                pid: 201,
+                process: "python3".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_older_kernels_name_the_victim_once() {
+        assert_eq!(
+            oom_kills(&fixture("child-killed.log")),
+            [OomKill {
+                pid: 318,
+                process: "python3".to_string(),
+            }]
+        );
+        assert!(oom_kills(&fixture("../boot-failure/console.log")).is_empty());
+        assert!(oom_kills("Killed process abc (python3)").is_empty());
+    }
+
+    #[test]
+    fn test_console_since_reads_only_new_output() {
+        let path = std::env::temp_dir().join(format!("fc-oom-console-{}", std::process::id()));
+        std::fs::write(&path, "[ 1.0] Out of memory: Killed process 7 (old)\n").unwrap();
+        let before = console_len(&path);
+        assert!(oom_kills(&console_since(&path, before)).is_empty());
+
+        let mut console = std::fs::read_to_string(&path).unwrap();
+        console.push_str(&fixture("agent-killed.log"));
+        std::fs::write(&path, console).unwrap();
+        let kills = oom_kills(&console_since(&path, before));
+        assert_eq!(kills.len(), 1);
+        assert_eq!(kills[0].pid, 201);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(console_since(&path, 0), "");
+    }
+}
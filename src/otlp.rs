@@ -0,0 +1,447 @@
+use crate::telemetry::{ExporterError, key_parts};
+use axum::http::HeaderMap;
+use once_cell::sync::OnceCell;
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter, MeterProvider as _};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Name of the service and instrumentation scope the telemetry is reported under
+const SERVICE_NAME: &str = "firecracker-poc";
+
+/// Provider behind the trace layer, kept to flush buffered spans on shutdown
+static TRACER_PROVIDER: OnceCell<SdkTracerProvider> = OnceCell::new();
+
+/// Resource the telemetry is reported under, named by `OTEL_SERVICE_NAME` when it is set
+fn resource() -> Resource {
+    let builder = Resource::builder();
+    if std::env::var_os("OTEL_SERVICE_NAME").is_some() {
+        builder.build()
+    } else {
+        builder.with_service_name(SERVICE_NAME).build()
+    }
+}
+
+/// `metrics` recorder forwarding to OpenTelemetry instruments, exported by the provider's
+/// reader. Instruments are created once per metric name and shared by all its label sets.
+#[derive(Clone)]
+pub struct OtlpRecorder {
+    meter: Meter,
+    /// Kept alive for as long as the recorder; dropping it stops the export
+    provider: SdkMeterProvider,
+    counters: Arc<Mutex<HashMap<String, Counter<u64>>>>,
+    gauges: Arc<Mutex<HashMap<String, Gauge<f64>>>>,
+    histograms: Arc<Mutex<HashMap<String, Histogram<f64>>>>,
+}
+
+impl std::fmt::Debug for OtlpRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OtlpRecorder").finish_non_exhaustive()
+    }
+}
+
+impl OtlpRecorder {
+    /// Export to the collector at `endpoint` over gRPC every `interval`; must be called
+    /// within a Tokio runtime, which drives the gRPC channel
+    pub fn connect(endpoint: &str, interval: Duration) -> Result<Self, ExporterError> {
+        let exporter = opentelemetry_otlp::MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| ExporterError::Otlp(e.to_string()))?;
+        let reader = PeriodicReader::builder(exporter)
+            .with_interval(interval)
+            .build();
+        Ok(Self::with_provider(
+            SdkMeterProvider::builder()
+                .with_reader(reader)
+                .with_resource(resource())
+                .build(),
+        ))
+    }
+
+    /// Record into instruments of `provider`, whatever its readers
+    pub fn with_provider(provider: SdkMeterProvider) -> Self {
+        Self {
+            meter: provider.meter(SERVICE_NAME),
+            provider,
+            counters: Arc::default(),
+            gauges: Arc::default(),
+            histograms: Arc::default(),
+        }
+    }
+
+    pub fn provider(&self) -> &SdkMeterProvider {
+        &self.provider
+    }
+}
+
+/// The instrument for `name` in `cache`, created on first use
+fn instrument<T: Clone>(
+    cache: &Mutex<HashMap<String, T>>,
+    name: &str,
+    create: impl FnOnce(String) -> T,
+) -> T {
+    cache
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(name.to_string())
+        .or_insert_with(|| create(name.to_string()))
+        .clone()
+}
+
+struct OtlpHandle<T> {
+    instrument: T,
+    attributes: Vec<KeyValue>,
+    /// Last value of a gauge, which OpenTelemetry can only set
+    value: Mutex<f64>,
+}
+
+impl<T> OtlpHandle<T> {
+    fn new(instrument: T, key: &metrics::Key) -> Self {
+        let (_, labels) = key_parts(key);
+        Self {
+            instrument,
+            attributes: labels
+                .into_iter()
+                .map(|(k, v)| KeyValue::new(k, v))
+                .collect(),
+            value: Mutex::new(0.0),
+        }
+    }
+}
+
+impl metrics::CounterFn for OtlpHandle<Counter<u64>> {
+    fn increment(&self, value: u64) {
+        self.instrument.add(value, &self.attributes);
+    }
+
+    fn absolute(&self, _value: u64) {
+        // Cumulative resets can't be expressed on an OpenTelemetry counter
+    }
+}
+
+impl metrics::GaugeFn for OtlpHandle<Gauge<f64>> {
+    fn increment(&self, value: f64) {
+        let mut current = self.value.lock().unwrap_or_else(|e| e.into_inner());
+        *current += value;
+        self.instrument.record(*current, &self.attributes);
+    }
+
+    fn decrement(&self, value: f64) {
+        metrics::GaugeFn::increment(self, -value);
+    }
+
+    fn set(&self, value: f64) {
+        *self.value.lock().unwrap_or_else(|e| e.into_inner()) = value;
+        self.instrument.record(value, &self.attributes);
+    }
+}
+
+impl metrics::HistogramFn for OtlpHandle<Histogram<f64>> {
+    fn record(&self, value: f64) {
+        self.instrument.record(value, &self.attributes);
+    }
+}
+
+impl metrics::Recorder for OtlpRecorder {
+    fn describe_counter(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_gauge(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_histogram(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn register_counter(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Counter {
+        let counter = instrument(&self.counters, key.name(), |name| {
+            self.meter.u64_counter(name).build()
+        });
+        metrics::Counter::from_arc(Arc::new(OtlpHandle::new(counter, key)))
+    }
+
+    fn register_gauge(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+        let gauge = instrument(&self.gauges, key.name(), |name| {
+            self.meter.f64_gauge(name).build()
+        });
+        metrics::Gauge::from_arc(Arc::new(OtlpHandle::new(gauge, key)))
+    }
+
+    fn register_histogram(
+        &self,
+        key: &metrics::Key,
+        _: &metrics::Metadata<'_>,
+    ) -> metrics::Histogram {
+        let histogram = instrument(&self.histograms, key.name(), |name| {
+            self.meter.f64_histogram(name).build()
+        });
+        metrics::Histogram::from_arc(Arc::new(OtlpHandle::new(histogram, key)))
+    }
+}
+
+/// Whether the standard `OTEL_*` variables, as returned by `var`, ask for OTLP trace export:
+/// `OTEL_TRACES_EXPORTER=otlp`, or an OTLP endpoint and no other `OTEL_TRACES_EXPORTER`
+pub fn traces_configured(var: impl Fn(&str) -> Option<String>) -> bool {
+    match var("OTEL_TRACES_EXPORTER").as_deref() {
+        Some("otlp") => true,
+        Some(_) => false,
+        None => {
+            var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_some()
+                || var("OTEL_EXPORTER_OTLP_ENDPOINT").is_some()
+        }
+    }
+}
+
+/// Layer exporting spans over OTLP/gRPC, or `None` when the environment doesn't ask for it.
+/// Endpoint, headers, timeout and batching follow the `OTEL_EXPORTER_OTLP_*` and `OTEL_BSP_*`
+/// variables. Must be called within a Tokio runtime, which drives the gRPC channel.
+pub fn trace_layer_from_env<S>() -> Result<Option<OpenTelemetryLayer<S, SdkTracer>>, ExporterError>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !traces_configured(|name| std::env::var(name).ok()) {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .build()
+        .map_err(|e| ExporterError::Otlp(e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource())
+        .build();
+    let layer = trace_layer(&provider);
+    if TRACER_PROVIDER.set(provider).is_err() {
+        return Err(ExporterError::AlreadyInstalled);
+    }
+    Ok(Some(layer))
+}
+
+/// Layer turning `tracing` spans into spans of `provider`
+pub fn trace_layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
+
+/// Export the spans still buffered and stop the trace export, if it was set up
+pub fn shutdown_traces() {
+    if let Some(provider) = TRACER_PROVIDER.get()
+        && let Err(e) = provider.shutdown()
+    {
+        tracing::warn!("Failed to flush traces: {}", e);
+    }
+}
+
+/// Make `span` a child of the caller's span named by the W3C `traceparent` header, if any.
+/// Does nothing unless the trace layer is installed.
+pub fn set_remote_parent(span: &tracing::Span, headers: &HeaderMap) {
+    if headers.contains_key("traceparent") {
+        span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(headers)));
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RunnerConfig;
+    use crate::executor::ExecutorService;
+    use crate::runner::ExecutionSpec;
+    use crate::telemetry;
+    use opentelemetry::trace::{SpanId, TraceId};
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::metrics::Temporality;
+    use opentelemetry_sdk::metrics::data::ResourceMetrics;
+    use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+    use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Exporter keeping the names of the metrics it was handed
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl PushMetricExporter for Capture {
+        async fn export(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+            let mut names = self.0.lock().unwrap();
+            for scope in metrics.scope_metrics() {
+                names.extend(scope.metrics().map(|metric| metric.name().to_string()));
+            }
+            Ok(())
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn temporality(&self) -> Temporality {
+            Temporality::Cumulative
+        }
+    }
+
+    #[test]
+    fn test_recorder_feeds_instruments() {
+        let capture = Capture::default();
+        let recorder = OtlpRecorder::with_provider(
+            SdkMeterProvider::builder()
+                .with_reader(PeriodicReader::builder(capture.clone()).build())
+                .build(),
+        );
+        metrics::with_local_recorder(&recorder, || {
+            telemetry::increment_counter("fc_cache_hits_total", &[], 1);
+            telemetry::set_gauge("fc_live_vms", &[], 2.0);
+            telemetry::observe_histogram("fc_execution_wall_ms", &[("phase", "run")], 5.0);
+        });
+        recorder.provider().force_flush().unwrap();
+
+        let mut names = capture.0.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["fc_cache_hits_total", "fc_execution_wall_ms", "fc_live_vms"]
+        );
+    }
+
+    /// Exporter keeping every span it was handed
+    #[derive(Clone, Debug, Default)]
+    struct SpanCapture(Arc<Mutex<Vec<SpanData>>>);
+
+    impl SpanExporter for SpanCapture {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_traces_configured_by_otel_variables() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert!(!traces_configured(env(&[])));
+        assert!(traces_configured(env(&[(
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            "http://collector:4317"
+        )])));
+        assert!(traces_configured(env(&[("OTEL_TRACES_EXPORTER", "otlp")])));
+        assert!(!traces_configured(env(&[
+            ("OTEL_TRACES_EXPORTER", "none"),
+            (
+                "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+                "http://collector:4317"
+            ),
+        ])));
+    }
+
+    #[tokio::test]
+    async fn test_execution_spans_join_the_callers_trace() {
+        let capture = SpanCapture::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(capture.clone())
+            .build();
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(trace_layer(&provider)),
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let request = tracing::info_span!("request");
+        set_remote_parent(&request, &headers);
+        let executor = ExecutorService::new(Arc::new(RunnerConfig::default()));
+        executor
+            .execute(ExecutionSpec::code("print(1)"))
+            .instrument(request)
+            .await
+            .unwrap();
+        executor.shutdown().await;
+
+        let spans = capture.0.lock().unwrap().clone();
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no {name} span"))
+        };
+        let attribute = |span: &SpanData, key: &str| {
+            span.attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+
+        let request = span("request");
+        let trace_id = TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap();
+        assert_eq!(request.span_context.trace_id(), trace_id);
+        assert_eq!(
+            request.parent_span_id,
+            SpanId::from_hex("00f067aa0ba902b7").unwrap()
+        );
+
+        let run = span("run_in_vm");
+        assert_eq!(run.parent_span_id, request.span_context.span_id());
+        assert!(matches!(
+            attribute(run, "vm_id"),
+            Some(opentelemetry::Value::String(id)) if !id.as_str().is_empty()
+        ));
+        // Nothing was warmed, so the VM was booted for this request
+        assert_eq!(attribute(run, "pool_hit"), Some(false.into()));
+
+        for phase in ["networking", "boot", "execute"] {
+            let phase = span(phase);
+            assert_eq!(phase.span_context.trace_id(), trace_id);
+            assert_eq!(phase.parent_span_id, run.span_context.span_id());
+        }
+    }
+}
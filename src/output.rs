@@ -0,0 +1,174 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Deserialize, Serialize};
+
+/// Default cap on each of stdout and stderr, in bytes
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// How an output stream is carried in a JSON string
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputEncoding {
+    /// Plain text; the stream was valid UTF-8
+    #[default]
+    Utf8,
+    /// Raw bytes, base64-encoded because they weren't valid UTF-8
+    Base64,
+}
+
+impl OutputEncoding {
+    pub fn is_utf8(&self) -> bool {
+        *self == OutputEncoding::Utf8
+    }
+
+    /// Parse the agent's `*_encoding` field; older agents only send text
+    pub fn from_agent(value: &serde_json::Value) -> Self {
+        serde_json::from_value(value.clone()).unwrap_or_default()
+    }
+}
+
+/// Represent raw output bytes as text when they are valid UTF-8, base64 otherwise
+pub fn encode_bytes(bytes: &[u8]) -> (String, OutputEncoding) {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.to_string(), OutputEncoding::Utf8),
+        Err(_) => (BASE64.encode(bytes), OutputEncoding::Base64),
+    }
+}
+
+/// Recover the raw bytes of a stream
+pub fn decode_stream(text: &str, encoding: OutputEncoding) -> Option<Vec<u8>> {
+    match encoding {
+        OutputEncoding::Utf8 => Some(text.as_bytes().to_vec()),
+        OutputEncoding::Base64 => BASE64.decode(text).ok(),
+    }
+}
+
+/// Cap a stream at `max_bytes` of decoded output; returns whether anything was cut
+pub fn truncate_stream(text: &mut String, encoding: OutputEncoding, max_bytes: usize) -> bool {
+    match encoding {
+        OutputEncoding::Utf8 => truncate_output(text, max_bytes),
+        OutputEncoding::Base64 => match BASE64.decode(text.as_bytes()) {
+            Ok(bytes) if bytes.len() > max_bytes => {
+                *text = BASE64.encode(&bytes[..max_bytes]);
+                true
+            }
+            Ok(_) => false,
+            // Undecodable payloads are bounded by their encoded length instead
+            Err(_) => truncate_output(text, max_bytes),
+        },
+    }
+}
+
+/// Truncate `text` to at most `max_bytes`, never splitting a UTF-8 codepoint.
+/// Returns whether anything was cut.
+pub fn truncate_output(text: &mut String, max_bytes: usize) -> bool {
+    if text.len() <= max_bytes {
+        return false;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    true
+}
+
+/// Effective output limit: a per-request value may lower the server limit but never raise it
+pub fn effective_limit(server_limit: usize, requested: Option<usize>) -> usize {
+    requested.map_or(server_limit, |requested| requested.min(server_limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exactly_at_limit_is_untouched() {
+        let mut text = "abcde".to_string();
+        assert!(!truncate_output(&mut text, 5));
+        assert_eq!(text, "abcde");
+    }
+
+    #[test]
+    fn test_over_limit_is_cut() {
+        let mut text = "x".repeat(100);
+        assert!(truncate_output(&mut text, 10));
+        assert_eq!(text.len(), 10);
+    }
+
+    #[test]
+    fn test_multibyte_boundary() {
+        // "é" is 2 bytes and "😀" is 4; a limit inside either backs up to the codepoint start
+        let mut text = "aé😀".to_string();
+        assert!(truncate_output(&mut text, 2));
+        assert_eq!(text, "a");
+
+        let mut text = "aé😀".to_string();
+        assert!(truncate_output(&mut text, 6));
+        assert_eq!(text, "aé");
+
+        let mut text = "aé😀".to_string();
+        assert!(!truncate_output(&mut text, 7));
+        assert_eq!(text, "aé😀");
+
+        let mut text = "😀".to_string();
+        assert!(truncate_output(&mut text, 0));
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let fixtures: [&[u8]; 4] = [
+            b"\xff\xfe\x00\x01binary",
+            // Valid text followed by a truncated multibyte sequence
+            b"ok \xe2\x82",
+            // Invalid byte in the middle of valid text
+            b"caf\xc3\xa9 \x80 done",
+            &[0u8, 159, 146, 150],
+        ];
+        for bytes in fixtures {
+            let (text, encoding) = encode_bytes(bytes);
+            assert_eq!(encoding, OutputEncoding::Base64);
+            assert_eq!(decode_stream(&text, encoding).unwrap(), bytes);
+        }
+
+        let (text, encoding) = encode_bytes("café 😀\n".as_bytes());
+        assert_eq!(encoding, OutputEncoding::Utf8);
+        assert_eq!(text, "café 😀\n");
+        assert_eq!(
+            decode_stream(&text, encoding).unwrap(),
+            "café 😀\n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_truncate_base64_stream() {
+        let (mut text, encoding) = encode_bytes(b"\xff\xfe0123456789");
+        assert!(truncate_stream(&mut text, encoding, 4));
+        assert_eq!(decode_stream(&text, encoding).unwrap(), b"\xff\xfe01");
+        assert!(!truncate_stream(&mut text, encoding, 4));
+    }
+
+    #[test]
+    fn test_encoding_serialization() {
+        assert_eq!(
+            serde_json::to_value(OutputEncoding::Base64).unwrap(),
+            "base64"
+        );
+        assert_eq!(
+            OutputEncoding::from_agent(&serde_json::json!("utf8")),
+            OutputEncoding::Utf8
+        );
+        assert_eq!(
+            OutputEncoding::from_agent(&serde_json::Value::Null),
+            OutputEncoding::Utf8
+        );
+    }
+
+    #[test]
+    fn test_effective_limit() {
+        assert_eq!(effective_limit(100, None), 100);
+        assert_eq!(effective_limit(100, Some(10)), 10);
+        assert_eq!(effective_limit(100, Some(1000)), 100);
+    }
+}
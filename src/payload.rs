@@ -0,0 +1,253 @@
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Media type of MessagePack bodies
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// Wire format of a request or response body
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    MsgPack,
+}
+
+impl Format {
+    /// Format named by a media type such as `application/json; charset=utf-8`
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        let essence = media_type.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(Format::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MsgPack)
+            }
+            other if other.starts_with("application/") && other.ends_with("+json") => {
+                Some(Format::Json)
+            }
+            _ => None,
+        }
+    }
+
+    /// Format of a request body, from its `Content-Type`
+    pub fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::from_media_type)
+    }
+
+    /// Response format asked for by `Accept`: the first supported media type listed, else JSON
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(Self::from_media_type)
+            .unwrap_or_default()
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MsgPack => MSGPACK_CONTENT_TYPE,
+        }
+    }
+
+    fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            // Named fields, so `default` and `skip_serializing_if` behave as they do in JSON
+            Format::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, PayloadRejection> {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| {
+                let status = match e.classify() {
+                    serde_json::error::Category::Data => StatusCode::UNPROCESSABLE_ENTITY,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                PayloadRejection::new(
+                    status,
+                    format!("Failed to deserialize the JSON body into the target type: {e}"),
+                )
+            }),
+            Format::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| {
+                PayloadRejection::new(
+                    StatusCode::BAD_REQUEST,
+                    format!("Failed to deserialize the MessagePack body: {e}"),
+                )
+            }),
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Format::Json => Format::MsgPack,
+            Format::MsgPack => Format::Json,
+        }
+    }
+}
+
+/// Why a request body could not be extracted
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayloadRejection {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl PayloadRejection {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Stable machine-readable name of the rejection, after its status
+    pub fn code(&self) -> &'static str {
+        match self.status {
+            StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+            _ => "bad_request",
+        }
+    }
+}
+
+impl IntoResponse for PayloadRejection {
+    fn into_response(self) -> Response {
+        (self.status, self.message).into_response()
+    }
+}
+
+/// A body in a negotiated format: extracted according to `Content-Type`, and rendered in
+/// `format` when returned. Generalizes axum's `Json` to MessagePack.
+#[derive(Debug, Clone)]
+pub struct Payload<T> {
+    pub format: Format,
+    pub value: T,
+}
+
+impl<T> Payload<T> {
+    pub fn new(format: Format, value: T) -> Self {
+        Self { format, value }
+    }
+}
+
+impl<T, S> FromRequest<S> for Payload<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = PayloadRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let format = Format::from_content_type(request.headers()).ok_or_else(|| {
+            PayloadRejection::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!(
+                    "Expected request with `Content-Type: application/json` or `{MSGPACK_CONTENT_TYPE}`"
+                ),
+            )
+        })?;
+        let bytes = Bytes::from_request(request, state)
+            .await
+            .map_err(|rejection| {
+                PayloadRejection::new(rejection.status(), rejection.body_text())
+            })?;
+        match format.decode(&bytes) {
+            Ok(value) => Ok(Self { format, value }),
+            // A body that is valid in the other format was sent under the wrong media type
+            Err(_) if format.other().decode::<T>(&bytes).is_ok() => Err(PayloadRejection::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!(
+                    "Body is not {}; set `Content-Type: {}`",
+                    format.content_type(),
+                    format.other().content_type()
+                ),
+            )),
+            Err(rejection) => Err(rejection),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Payload<T> {
+    fn into_response(self) -> Response {
+        match self.format.encode(&self.value) {
+            Ok(body) => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(self.format.content_type()),
+                )],
+                body,
+            )
+                .into_response(),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn test_format_negotiation() {
+        assert_eq!(
+            Format::from_content_type(&headers(
+                header::CONTENT_TYPE,
+                "application/json; charset=utf-8"
+            )),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            Format::from_content_type(&headers(header::CONTENT_TYPE, "application/x-msgpack")),
+            Some(Format::MsgPack)
+        );
+        assert_eq!(
+            Format::from_content_type(&headers(header::CONTENT_TYPE, "text/plain")),
+            None
+        );
+        assert_eq!(Format::from_content_type(&HeaderMap::new()), None);
+
+        assert_eq!(
+            Format::from_accept(&headers(
+                header::ACCEPT,
+                "text/html, application/msgpack, application/json"
+            )),
+            Format::MsgPack
+        );
+        assert_eq!(
+            Format::from_accept(&headers(header::ACCEPT, "*/*")),
+            Format::Json
+        );
+        assert_eq!(Format::from_accept(&HeaderMap::new()), Format::Json);
+    }
+
+    #[test]
+    fn test_msgpack_roundtrip_keeps_optional_fields() {
+        let response = crate::ExecuteResponse {
+            stdout: "hi\n".to_string(),
+            success: true,
+            hash_seed: Some(7),
+            ..Default::default()
+        };
+        let bytes = Format::MsgPack.encode(&response).unwrap();
+        let decoded: crate::ExecuteResponse = Format::MsgPack.decode(&bytes).unwrap();
+        assert_eq!(decoded.stdout, "hi\n");
+        assert_eq!(decoded.hash_seed, Some(7));
+        assert!(decoded.exception.is_none());
+    }
+}
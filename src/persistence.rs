@@ -0,0 +1,372 @@
+use crate::history::{ExecutionRecord, now_millis};
+use crate::jobs::{Job, JobStatus, JobStore, StoreError};
+use crate::output;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Schema migrations; entry `n` moves the database from `user_version` `n` to `n + 1`
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE jobs (
+        id TEXT PRIMARY KEY,
+        status TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        finished_at INTEGER,
+        document TEXT NOT NULL
+    );
+    CREATE INDEX jobs_status ON jobs (status);
+    CREATE INDEX jobs_finished_at ON jobs (finished_at);
+    CREATE TABLE executions (
+        request_id TEXT NOT NULL,
+        vm_id TEXT,
+        started_at INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        success INTEGER NOT NULL,
+        error_code TEXT,
+        code_sha256 TEXT NOT NULL,
+        stdout_len INTEGER NOT NULL,
+        stderr_len INTEGER NOT NULL
+    );
+    CREATE INDEX executions_started_at ON executions (started_at);
+",
+    "
+    CREATE TABLE quota_uses (
+        tenant TEXT NOT NULL,
+        at INTEGER NOT NULL
+    );
+    CREATE INDEX quota_uses_tenant_at ON quota_uses (tenant, at);
+",
+];
+
+/// Jobs and execution records in a SQLite database, surviving restarts. Each job is stored
+/// as its JSON document next to the columns queries filter on.
+#[derive(Debug)]
+pub struct SqliteJobStore {
+    connection: Mutex<Connection>,
+    /// Cap on each output stream of a stored result
+    max_output_bytes: usize,
+}
+
+impl SqliteJobStore {
+    /// Open or create the database at `path`, bringing its schema up to date
+    pub fn open(path: &Path, max_output_bytes: usize) -> Result<Self, StoreError> {
+        let connection = Connection::open(path).map_err(backend)?;
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .map_err(backend)?;
+        Self::with_connection(connection, max_output_bytes)
+    }
+
+    /// A database that lives only as long as the store, for tests and experiments
+    pub fn in_memory(max_output_bytes: usize) -> Result<Self, StoreError> {
+        Self::with_connection(
+            Connection::open_in_memory().map_err(backend)?,
+            max_output_bytes,
+        )
+    }
+
+    fn with_connection(
+        mut connection: Connection,
+        max_output_bytes: usize,
+    ) -> Result<Self, StoreError> {
+        connection
+            .busy_timeout(std::time::Duration::from_secs(5))
+            .map_err(backend)?;
+        migrate(&mut connection)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            max_output_bytes,
+        })
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// `job` with its result's output cut to `max_output_bytes`
+    fn capped(&self, job: &Job) -> Job {
+        let mut job = job.clone();
+        if let Some(result) = &mut job.result {
+            result.stdout_truncated |= output::truncate_stream(
+                &mut result.stdout,
+                result.stdout_encoding,
+                self.max_output_bytes,
+            );
+            result.stderr_truncated |= output::truncate_stream(
+                &mut result.stderr,
+                result.stderr_encoding,
+                self.max_output_bytes,
+            );
+        }
+        job
+    }
+}
+
+/// Apply the migrations the database hasn't seen yet, each in its own transaction
+fn migrate(connection: &mut Connection) -> Result<(), StoreError> {
+    let version: usize = connection
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(backend)?;
+    if version > MIGRATIONS.len() {
+        return Err(StoreError::Backend(format!(
+            "database schema version {version} is newer than the supported {}",
+            MIGRATIONS.len()
+        )));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction().map_err(backend)?;
+        transaction.execute_batch(migration).map_err(backend)?;
+        transaction
+            .pragma_update(None, "user_version", index + 1)
+            .map_err(backend)?;
+        transaction.commit().map_err(backend)?;
+    }
+    Ok(())
+}
+
+fn backend(e: impl std::fmt::Display) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
+
+fn encode(job: &Job) -> Result<String, StoreError> {
+    serde_json::to_string(job).map_err(backend)
+}
+
+fn decode(document: &str) -> Result<Job, StoreError> {
+    serde_json::from_str(document).map_err(backend)
+}
+
+fn put_job(connection: &Connection, job: &Job) -> Result<(), StoreError> {
+    connection
+        .execute(
+            "INSERT OR REPLACE INTO jobs (id, status, created_at, finished_at, document)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                job.id,
+                job.status.as_str(),
+                job.created_at as i64,
+                job.finished_at.map(|at| at as i64),
+                encode(job)?,
+            ],
+        )
+        .map_err(backend)?;
+    Ok(())
+}
+
+impl JobStore for SqliteJobStore {
+    fn put(&self, job: &Job) -> Result<(), StoreError> {
+        put_job(&self.connection(), &self.capped(job))
+    }
+
+    fn get(&self, id: &str) -> Result<Option<Job>, StoreError> {
+        let document: Option<String> = self
+            .connection()
+            .query_row(
+                "SELECT document FROM jobs WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(backend)?;
+        document.as_deref().map(decode).transpose()
+    }
+
+    fn fail_interrupted(&self) -> Result<usize, StoreError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(backend)?;
+        let documents = {
+            let mut statement = transaction
+                .prepare("SELECT document FROM jobs WHERE status IN (?1, ?2)")
+                .map_err(backend)?;
+            statement
+                .query_map(
+                    params![JobStatus::Queued.as_str(), JobStatus::Running.as_str()],
+                    |row| row.get::<_, String>(0),
+                )
+                .map_err(backend)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(backend)?
+        };
+        let now = now_millis();
+        for document in &documents {
+            let mut job = decode(document)?;
+            job.interrupt(now);
+            put_job(&transaction, &job)?;
+        }
+        transaction.commit().map_err(backend)?;
+        Ok(documents.len())
+    }
+
+    fn remove_expired(&self, cutoff: u64) -> Result<usize, StoreError> {
+        let connection = self.connection();
+        let cutoff = i64::try_from(cutoff).unwrap_or(i64::MAX);
+        let jobs = connection
+            .execute(
+                "DELETE FROM jobs WHERE finished_at IS NOT NULL AND finished_at < ?1",
+                params![cutoff],
+            )
+            .map_err(backend)?;
+        let executions = connection
+            .execute(
+                "DELETE FROM executions WHERE started_at < ?1",
+                params![cutoff],
+            )
+            .map_err(backend)?;
+        Ok(jobs + executions)
+    }
+
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<(), StoreError> {
+        self.connection()
+            .execute(
+                "INSERT INTO executions (request_id, vm_id, started_at, duration_ms, success,
+                     error_code, code_sha256, stdout_len, stderr_len)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    record.request_id,
+                    record.vm_id,
+                    record.started_at as i64,
+                    record.duration_ms as i64,
+                    record.success,
+                    record.error_code,
+                    record.code_sha256,
+                    record.stdout_len as i64,
+                    record.stderr_len as i64,
+                ],
+            )
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    fn recent_executions(&self, limit: usize) -> Result<Vec<ExecutionRecord>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare(
+                "SELECT request_id, vm_id, started_at, duration_ms, success, error_code,
+                     code_sha256, stdout_len, stderr_len
+                 FROM executions ORDER BY started_at DESC, rowid DESC LIMIT ?1",
+            )
+            .map_err(backend)?;
+        let records = statement
+            .query_map(params![i64::try_from(limit).unwrap_or(i64::MAX)], |row| {
+                Ok(ExecutionRecord {
+                    request_id: row.get(0)?,
+                    vm_id: row.get(1)?,
+                    started_at: row.get::<_, i64>(2)? as u64,
+                    duration_ms: row.get::<_, i64>(3)? as u64,
+                    success: row.get(4)?,
+                    error_code: row.get(5)?,
+                    code_sha256: row.get(6)?,
+                    stdout_len: row.get::<_, i64>(7)? as usize,
+                    stderr_len: row.get::<_, i64>(8)? as usize,
+                })
+            })
+            .map_err(backend)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(backend)?;
+        Ok(records)
+    }
+
+    fn record_quota_use(
+        &self,
+        tenant: &str,
+        at: u64,
+        expired_before: u64,
+    ) -> Result<(), StoreError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction().map_err(backend)?;
+        transaction
+            .execute(
+                "DELETE FROM quota_uses WHERE tenant = ?1 AND at < ?2",
+                params![tenant, i64::try_from(expired_before).unwrap_or(i64::MAX)],
+            )
+            .map_err(backend)?;
+        transaction
+            .execute(
+                "INSERT INTO quota_uses (tenant, at) VALUES (?1, ?2)",
+                params![tenant, at as i64],
+            )
+            .map_err(backend)?;
+        transaction.commit().map_err(backend)?;
+        Ok(())
+    }
+
+    fn quota_uses(&self, since: u64) -> Result<Vec<(String, u64)>, StoreError> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT tenant, at FROM quota_uses WHERE at >= ?1 ORDER BY at, rowid")
+            .map_err(backend)?;
+        let uses = statement
+            .query_map(params![i64::try_from(since).unwrap_or(i64::MAX)], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })
+            .map_err(backend)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(backend)?;
+        Ok(uses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::tests::{check_interrupted_jobs_fail, check_store_contract, record};
+
+    fn db_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("fc-jobs-{name}-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_sqlite_store_contract() {
+        check_store_contract(&SqliteJobStore::in_memory(1024).unwrap());
+        check_interrupted_jobs_fail(&SqliteJobStore::in_memory(1024).unwrap());
+    }
+
+    #[test]
+    fn test_sqlite_store_survives_reopen() {
+        let path = db_path("reopen");
+        {
+            let store = SqliteJobStore::open(&path, 4).unwrap();
+            let mut job = Job::new("job-1", None);
+            job.status = JobStatus::Completed;
+            job.result = Some(crate::ExecuteResponse {
+                stdout: "long output".to_string(),
+                success: true,
+                ..Default::default()
+            });
+            store.put(&job).unwrap();
+            store.put(&Job::new("job-2", None)).unwrap();
+            store.record_execution(&record("req-1", 1)).unwrap();
+        }
+
+        // Reopening runs no migration twice and keeps every row
+        let store = SqliteJobStore::open(&path, 4).unwrap();
+        let result = store.get("job-1").unwrap().unwrap().result.unwrap();
+        assert_eq!(result.stdout, "long");
+        assert!(result.stdout_truncated);
+        assert_eq!(
+            store.recent_executions(10).unwrap(),
+            vec![record("req-1", 1)]
+        );
+        assert_eq!(store.fail_interrupted().unwrap(), 1);
+        assert_eq!(
+            store.get("job-2").unwrap().unwrap().status,
+            JobStatus::Failed
+        );
+        drop(store);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_sqlite_store_rejects_newer_schema() {
+        let path = db_path("newer");
+        Connection::open(&path)
+            .unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(SqliteJobStore::open(&path, 1024).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
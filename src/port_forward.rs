@@ -0,0 +1,189 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Guest ports below this are well-known and never exposed
+pub const MIN_EXPOSED_PORT: u16 = 1024;
+
+/// Longest a forwarded connection waits for the guest to accept it
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Settings of `expose_port`, which forwards a host port to a guest port after the execution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposeConfig {
+    /// Most guest ports exposed at once; 0 turns `expose_port` off
+    pub max_exposed: usize,
+    /// How long a port stays exposed once the execution finishes, after which the VM goes
+    pub ttl: Duration,
+    /// Host address the forwards listen on
+    pub bind_addr: IpAddr,
+}
+
+impl Default for ExposeConfig {
+    fn default() -> Self {
+        Self {
+            max_exposed: 4,
+            ttl: Duration::from_secs(60),
+            bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        }
+    }
+}
+
+/// Check a guest port asked for in `expose_port`
+pub fn validate_port(port: u16) -> Result<(), String> {
+    if port < MIN_EXPOSED_PORT {
+        return Err(format!(
+            "expose_port {port} is a well-known port; use {MIN_EXPOSED_PORT} or above"
+        ));
+    }
+    Ok(())
+}
+
+/// Slots of the guest ports exposed at once
+#[derive(Debug, Clone)]
+pub struct ExposedPorts {
+    slots: Arc<Semaphore>,
+}
+
+impl ExposedPorts {
+    pub fn new(max_exposed: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_exposed)),
+        }
+    }
+
+    /// A slot held for as long as the port stays exposed, if any is free
+    pub fn try_reserve(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+}
+
+/// An ephemeral host port forwarding every connection to a guest port. Closing or dropping it
+/// stops the listener and every forwarded connection.
+#[derive(Debug)]
+pub struct PortForward {
+    host_addr: SocketAddr,
+    task: Option<JoinHandle<()>>,
+}
+
+impl PortForward {
+    /// Listen on an ephemeral port of `bind_addr` and forward connections to `target`
+    pub async fn start(bind_addr: IpAddr, target: SocketAddr) -> std::io::Result<Self> {
+        let listener = TcpListener::bind((bind_addr, 0)).await?;
+        let host_addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            // Dropped with the task, aborting the connections
+            let mut connections = JoinSet::new();
+            loop {
+                tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok((inbound, _)) => {
+                            connections.spawn(forward(inbound, target));
+                        }
+                        Err(e) => tracing::debug!("Failed to accept a connection for {}: {}", target, e),
+                    },
+                    Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                }
+            }
+        });
+        tracing::debug!("Forwarding {} to {}", host_addr, target);
+        Ok(Self {
+            host_addr,
+            task: Some(task),
+        })
+    }
+
+    pub fn host_port(&self) -> u16 {
+        self.host_addr.port()
+    }
+
+    /// Stop forwarding, returning once the listener is closed
+    pub async fn close(mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+/// Copy bytes both ways between a host connection and the guest port
+async fn forward(mut inbound: TcpStream, target: SocketAddr) {
+    let mut outbound = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(target)).await
+    {
+        Ok(Ok(outbound)) => outbound,
+        Ok(Err(e)) => {
+            tracing::debug!("Failed to reach exposed port {}: {}", target, e);
+            return;
+        }
+        Err(_) => {
+            tracing::debug!("Timed out reaching exposed port {}", target);
+            return;
+        }
+    };
+    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_forward_carries_bytes_both_ways_until_closed() {
+        // Stands in for a preview server in the guest
+        let guest = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = guest.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = guest.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0; 5];
+                    stream.read_exact(&mut buf).await.unwrap();
+                    stream.write_all(b"pong:").await.unwrap();
+                    stream.write_all(&buf).await.unwrap();
+                });
+            }
+        });
+
+        let forward = PortForward::start(IpAddr::V4(Ipv4Addr::LOCALHOST), target)
+            .await
+            .unwrap();
+        let host = SocketAddr::from((Ipv4Addr::LOCALHOST, forward.host_port()));
+        let mut client = TcpStream::connect(host).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut reply = [0; 10];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"pong:hello");
+
+        forward.close().await;
+        assert!(TcpStream::connect(host).await.is_err());
+        // Connections still open are cut too
+        let mut rest = Vec::new();
+        assert_eq!(client.read_to_end(&mut rest).await.unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn test_exposed_port_limits() {
+        assert!(validate_port(80).is_err());
+        assert!(validate_port(1023).is_err());
+        assert!(validate_port(5000).is_ok());
+
+        let ports = ExposedPorts::new(1);
+        let slot = ports.try_reserve().unwrap();
+        assert!(ports.try_reserve().is_none());
+        drop(slot);
+        assert!(ports.try_reserve().is_some());
+        assert!(ExposedPorts::new(0).try_reserve().is_none());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::ExecutionError;
+use crate::tap::{CommandRunner, HostCommands};
+use serde::{Deserialize, Serialize};
+
+/// Bit of `CAP_NET_ADMIN` in the capability masks of `/proc/<pid>/status`
+const CAP_NET_ADMIN: u32 = 12;
+
+/// How this process can set up VM networking: creating TAP devices and shaping them with `tc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NetworkPrivilege {
+    /// The process holds `CAP_NET_ADMIN` and runs `ip` and `tc` itself
+    CapNetAdmin,
+    /// `sudo -n` runs them without asking for a password
+    Sudo,
+    /// Neither, so no VM with networking can boot
+    Unavailable,
+}
+
+impl NetworkPrivilege {
+    /// `PrivilegeError` when VM networking can't be set up
+    pub fn check(self) -> Result<(), ExecutionError> {
+        match self {
+            NetworkPrivilege::Unavailable => Err(ExecutionError::PrivilegeError(
+                "the process lacks CAP_NET_ADMIN and sudo asks for a password".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the effective capabilities in `status`, the text of `/proc/<pid>/status`, include
+/// `CAP_NET_ADMIN`
+pub fn has_cap_net_admin(status: &str) -> bool {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|mask| u64::from_str_radix(mask.trim(), 16).ok())
+        .is_some_and(|mask| mask & (1 << CAP_NET_ADMIN) != 0)
+}
+
+/// Whether this process holds `CAP_NET_ADMIN`, read once
+pub fn process_has_cap_net_admin() -> bool {
+    static HAS_CAP: once_cell::sync::Lazy<bool> = once_cell::sync::Lazy::new(|| {
+        std::fs::read_to_string("/proc/self/status").is_ok_and(|status| has_cap_net_admin(&status))
+    });
+    *HAS_CAP
+}
+
+/// How networking can be set up by a process that holds `CAP_NET_ADMIN` or not: with the
+/// capability, else through sudo if `sudo -n true` passes without a password
+pub async fn probe(runner: &dyn CommandRunner, cap_net_admin: bool) -> NetworkPrivilege {
+    if cap_net_admin {
+        return NetworkPrivilege::CapNetAdmin;
+    }
+    match runner.run("sudo", &["-n", "true"]).await {
+        Ok(output) if output.success => NetworkPrivilege::Sudo,
+        Ok(output) => {
+            tracing::debug!("`sudo -n true` failed: {}", output.stderr.trim());
+            NetworkPrivilege::Unavailable
+        }
+        Err(e) => {
+            tracing::debug!("Failed to run sudo: {}", e);
+            NetworkPrivilege::Unavailable
+        }
+    }
+}
+
+/// How this process can set up networking on the host, probed on first use
+pub async fn network_privilege() -> NetworkPrivilege {
+    static PRIVILEGE: tokio::sync::OnceCell<NetworkPrivilege> = tokio::sync::OnceCell::const_new();
+    *PRIVILEGE
+        .get_or_init(|| probe(&HostCommands, process_has_cap_net_admin()))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tap::CommandOutput;
+    use std::sync::Mutex;
+
+    /// Answers `sudo` with a fixed outcome and records what was run
+    struct Sudo {
+        outcome: Option<bool>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl Sudo {
+        fn new(outcome: Option<bool>) -> Self {
+            Self {
+                outcome,
+                calls: Mutex::default(),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl CommandRunner for Sudo {
+        async fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{program} {}", args.join(" ")));
+            match self.outcome {
+                Some(success) => Ok(CommandOutput {
+                    success,
+                    stderr: "sudo: a password is required\n".to_string(),
+                    ..Default::default()
+                }),
+                None => Err(std::io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_cap_net_admin_is_read_from_the_effective_set() {
+        let status =
+            |eff| format!("Name:\tfirecracker-poc\nCapPrm:\t000001ffffffffff\nCapEff:\t{eff}\n");
+        assert!(has_cap_net_admin(&status("000001ffffffffff")));
+        assert!(has_cap_net_admin(&status("0000000000001000")));
+        // Permitted but not effective doesn't count
+        assert!(!has_cap_net_admin(&status("0000000000000000")));
+        assert!(!has_cap_net_admin(&status("0000000000000800")));
+        assert!(!has_cap_net_admin("Name:\tfirecracker-poc\n"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_prefers_the_capability_then_passwordless_sudo() {
+        let sudo = Sudo::new(Some(true));
+        assert_eq!(probe(&sudo, true).await, NetworkPrivilege::CapNetAdmin);
+        assert!(sudo.calls.lock().unwrap().is_empty());
+        assert_eq!(probe(&sudo, false).await, NetworkPrivilege::Sudo);
+        // Never a command that could wait for a password
+        assert_eq!(*sudo.calls.lock().unwrap(), ["sudo -n true"]);
+
+        assert_eq!(
+            probe(&Sudo::new(Some(false)), false).await,
+            NetworkPrivilege::Unavailable
+        );
+        // No sudo installed at all
+        let unavailable = probe(&Sudo::new(None), false).await;
+        assert_eq!(unavailable, NetworkPrivilege::Unavailable);
+        let err = unavailable.check().unwrap_err();
+        assert_eq!(err.code(), "privilege_error");
+        assert!(err.to_string().contains("CAP_NET_ADMIN"), "{err}");
+        assert!(NetworkPrivilege::Sudo.check().is_ok());
+    }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+/// Background checks of pooled VMs' guest agents, so a VM that died while idle is replaced
+/// before a request is handed it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthProbeConfig {
+    /// Time between rounds over the whole pool; `None` turns probing off
+    pub interval: Option<Duration>,
+    /// Time an agent has to answer `/health`
+    pub timeout: Duration,
+    /// Failed probes in a row after which a VM is discarded
+    pub failures_before_eviction: u32,
+}
+
+impl Default for HealthProbeConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(30)),
+            timeout: Duration::from_secs(1),
+            failures_before_eviction: 2,
+        }
+    }
+}
+
+/// Pause between probing one VM and the next, so a round of `pooled` probes spreads over
+/// `interval` instead of taking the pool lock back to back
+pub fn stagger(interval: Duration, pooled: usize) -> Duration {
+    match u32::try_from(pooled) {
+        Ok(0) | Err(_) => Duration::ZERO,
+        Ok(pooled) => interval / pooled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stagger_spreads_a_round_over_the_interval() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(stagger(interval, 3), Duration::from_secs(10));
+        assert_eq!(stagger(interval, 1), interval);
+        assert_eq!(stagger(interval, 0), Duration::ZERO);
+    }
+}
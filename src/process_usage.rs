@@ -0,0 +1,169 @@
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Clock ticks per second that `/proc/<pid>/stat` counts CPU time in; fixed at 100 on Linux
+/// for every architecture Firecracker runs on
+const USER_HZ: u64 = 100;
+
+/// Host resources held by a VM's Firecracker process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct ProcessUsage {
+    pub pid: u32,
+    /// Resident set size in KiB, guest memory the host has paged in included
+    pub rss_kib: u64,
+    /// User plus system CPU time, in milliseconds
+    pub cpu_time_ms: u64,
+    pub open_fds: u64,
+}
+
+/// Sampling Firecracker processes' host usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessUsageConfig {
+    /// Time between samples of every live VM for the metrics; `None` only samples on request
+    pub sample_interval: Option<Duration>,
+    /// Host RSS above which a VM is retired when it is released instead of pooled again
+    pub max_rss_mib: Option<u64>,
+}
+
+impl Default for ProcessUsageConfig {
+    fn default() -> Self {
+        Self {
+            sample_interval: Some(Duration::from_secs(15)),
+            max_rss_mib: None,
+        }
+    }
+}
+
+impl ProcessUsageConfig {
+    /// Whether `usage` is past the RSS ceiling
+    pub fn exceeded_by(&self, usage: &ProcessUsage) -> bool {
+        self.max_rss_mib
+            .is_some_and(|max| usage.rss_kib > max.saturating_mul(1024))
+    }
+}
+
+/// `VmRSS` from `/proc/<pid>/status` contents, in KiB
+pub fn parse_rss_kib(status: &str) -> Option<u64> {
+    status.lines().find_map(|line| {
+        let rest = line.strip_prefix("VmRSS:")?;
+        rest.trim().trim_end_matches("kB").trim().parse().ok()
+    })
+}
+
+/// User plus system CPU time from `/proc/<pid>/stat` contents, in milliseconds. The command
+/// name can hold spaces and parentheses, so fields are counted from its last `)`.
+pub fn parse_cpu_time_ms(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    // `utime` and `stime` are the 14th and 15th fields, the 2nd being the command name
+    let mut fields = fields.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some((utime + stime) * 1000 / USER_HZ)
+}
+
+/// Usage of process `pid` read from `proc` (normally `/proc`); `None` when any of it can't be
+/// read, as when the process exits in the middle
+pub fn sample_from(proc: &Path, pid: u32) -> Option<ProcessUsage> {
+    let dir = proc.join(pid.to_string());
+    let rss_kib = parse_rss_kib(&std::fs::read_to_string(dir.join("status")).ok()?)?;
+    let cpu_time_ms = parse_cpu_time_ms(&std::fs::read_to_string(dir.join("stat")).ok()?)?;
+    let open_fds = std::fs::read_dir(dir.join("fd")).ok()?.count() as u64;
+    Some(ProcessUsage {
+        pid,
+        rss_kib,
+        cpu_time_ms,
+        open_fds,
+    })
+}
+
+/// Usage of process `pid`, if it is still running
+pub fn sample(pid: u32) -> Option<ProcessUsage> {
+    sample_from(Path::new("/proc"), pid)
+}
+
+/// Export the total and largest RSS, total CPU time and total open fds of `usages` as gauges
+pub fn record(usages: &[ProcessUsage]) {
+    let rss_bytes = |usage: &ProcessUsage| (usage.rss_kib * 1024) as f64;
+    crate::telemetry::set_gauge(
+        "fc_firecracker_rss_bytes",
+        &[("aggregate", "sum")],
+        usages.iter().map(rss_bytes).sum(),
+    );
+    crate::telemetry::set_gauge(
+        "fc_firecracker_rss_bytes",
+        &[("aggregate", "max")],
+        usages.iter().map(rss_bytes).fold(0.0, f64::max),
+    );
+    crate::telemetry::set_gauge(
+        "fc_firecracker_cpu_seconds",
+        &[],
+        usages.iter().map(|usage| usage.cpu_time_ms).sum::<u64>() as f64 / 1000.0,
+    );
+    crate::telemetry::set_gauge(
+        "fc_firecracker_open_fds",
+        &[],
+        usages.iter().map(|usage| usage.open_fds).sum::<u64>() as f64,
+    );
+}
+
+/// Sample every live VM's process on the configured interval for the metrics; does nothing
+/// with sampling off
+pub fn spawn_sampler(config: ProcessUsageConfig) -> Option<tokio::task::JoinHandle<()>> {
+    let interval = config.sample_interval?;
+    Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            crate::debug_state::heartbeat("process_sampler");
+            let usages: Vec<_> = crate::runner::live_vm_pids()
+                .into_iter()
+                .filter_map(sample)
+                .collect();
+            record(&usages);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(format!("fixtures/proc/4242/{name}")).unwrap()
+    }
+
+    #[test]
+    fn test_parses_rss_and_cpu_time() {
+        assert_eq!(parse_rss_kib(&fixture("status")), Some(153_892));
+        assert_eq!(parse_rss_kib("Name:\tfirecracker\n"), None);
+        // 1234 + 566 ticks, despite the spaces and parentheses in the command name
+        assert_eq!(parse_cpu_time_ms(&fixture("stat")), Some(18_000));
+        assert_eq!(parse_cpu_time_ms("4242 (fc_vcpu 0) S 1"), None);
+    }
+
+    #[test]
+    fn test_sample_reads_a_process_directory() {
+        let proc = Path::new("fixtures/proc");
+        let usage = sample_from(proc, 4242).unwrap();
+        assert_eq!(
+            usage,
+            ProcessUsage {
+                pid: 4242,
+                rss_kib: 153_892,
+                cpu_time_ms: 18_000,
+                open_fds: 2,
+            }
+        );
+        // A process that exited leaves nothing to read
+        assert_eq!(sample_from(proc, 4243), None);
+        assert!(sample(std::process::id()).is_some_and(|usage| usage.rss_kib > 0));
+
+        let ceiling = ProcessUsageConfig {
+            max_rss_mib: Some(150),
+            ..Default::default()
+        };
+        assert!(ceiling.exceeded_by(&usage));
+        assert!(!ProcessUsageConfig::default().exceeded_by(&usage));
+    }
+}
@@ -0,0 +1,390 @@
+use crate::ExecuteRequest;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// A PEP 508 name with optional extras and version specifiers, e.g. `requests[socks]>=2,<3`
+static REQUIREMENT_SPEC: Lazy<Regex> = Lazy::new(|| {
+    let version = r"(===|==|!=|~=|>=|<=|>|<)\s*[A-Za-z0-9.*+!_-]+";
+    Regex::new(&format!(
+        r"^[A-Za-z0-9]([A-Za-z0-9._-]*[A-Za-z0-9])?(\[[A-Za-z0-9._-]+(\s*,\s*[A-Za-z0-9._-]+)*\])?\s*({version}(\s*,\s*{version})*)?$"
+    ))
+    .expect("requirement pattern is valid")
+});
+
+/// One file of a multi-file program
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct SourceFile {
+    /// Relative path inside the program's working directory, e.g. `pkg/utils.py`
+    pub path: String,
+    pub content: String,
+}
+
+/// How a snippet is run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecMode {
+    /// Run the code as a script; only its output is returned
+    #[default]
+    Script,
+    /// Run the code like a REPL cell, also returning the value of its final expression
+    Eval,
+}
+
+/// What to execute: a single snippet or a file tree run through its entrypoint
+#[derive(Debug, Clone, PartialEq)]
+pub enum Program {
+    Code(String),
+    /// A snippet whose final expression is evaluated and returned as `value`
+    Eval(String),
+    Files {
+        files: Vec<SourceFile>,
+        entrypoint: String,
+    },
+}
+
+/// Why a request doesn't describe a runnable program
+#[derive(Debug, Error, PartialEq)]
+pub enum ProgramError {
+    #[error("Provide either 'code' or 'files' with 'entrypoint', not both")]
+    Ambiguous,
+    #[error("Request must contain either 'code' or 'files' with 'entrypoint'")]
+    Missing,
+    #[error("'files' must not be empty")]
+    NoFiles,
+    #[error("Mode 'eval' takes 'code', not 'files'")]
+    EvalNeedsCode,
+    #[error("Invalid file path '{path}': {reason}")]
+    InvalidPath { path: String, reason: &'static str },
+    #[error("Duplicate file path '{0}'")]
+    DuplicatePath(String),
+    #[error("Entrypoint '{0}' is not one of the submitted files")]
+    UnknownEntrypoint(String),
+    #[error("Installing requirements requires network access, which is disabled")]
+    NetworkDisabled,
+    #[error("Invalid requirement '{requirement}': {reason}")]
+    InvalidRequirement {
+        requirement: String,
+        reason: &'static str,
+    },
+}
+
+impl Program {
+    /// Validate the request's `code` / `files` + `entrypoint` alternatives
+    pub fn from_request(request: &ExecuteRequest) -> Result<Self, ProgramError> {
+        match (&request.code, &request.files, &request.entrypoint) {
+            (Some(code), None, None) => Ok(match request.mode.unwrap_or_default() {
+                ExecMode::Script => Program::Code(code.clone()),
+                ExecMode::Eval => Program::Eval(code.clone()),
+            }),
+            (None, Some(_), Some(_)) if request.mode == Some(ExecMode::Eval) => {
+                Err(ProgramError::EvalNeedsCode)
+            }
+            (None, Some(files), Some(entrypoint)) => {
+                validate_files(files, entrypoint)?;
+                Ok(Program::Files {
+                    files: files.clone(),
+                    entrypoint: entrypoint.clone(),
+                })
+            }
+            (Some(_), _, _) => Err(ProgramError::Ambiguous),
+            _ => Err(ProgramError::Missing),
+        }
+    }
+
+    /// Source text of every file, for screening and cacheability checks
+    pub fn sources(&self) -> Vec<&str> {
+        match self {
+            Program::Code(code) | Program::Eval(code) => vec![code.as_str()],
+            Program::Files { files, .. } => files.iter().map(|f| f.content.as_str()).collect(),
+        }
+    }
+
+    /// Total source size in bytes, checked against the code-length limit
+    pub fn source_len(&self) -> usize {
+        self.sources().iter().map(|source| source.len()).sum()
+    }
+
+    /// Hex SHA-256 identifying the program in the execution history
+    pub fn sha256(&self) -> String {
+        match self {
+            Program::Code(code) | Program::Eval(code) => crate::history::code_sha256(code),
+            Program::Files { files, entrypoint } => {
+                let mut hasher = Sha256::new();
+                for field in std::iter::once(entrypoint)
+                    .chain(files.iter().flat_map(|f| [&f.path, &f.content]))
+                {
+                    hasher.update((field.len() as u64).to_le_bytes());
+                    hasher.update(field.as_bytes());
+                }
+                hex::encode(hasher.finalize())
+            }
+        }
+    }
+}
+
+fn validate_files(files: &[SourceFile], entrypoint: &str) -> Result<(), ProgramError> {
+    if files.is_empty() {
+        return Err(ProgramError::NoFiles);
+    }
+    let mut paths = HashSet::new();
+    for file in files {
+        validate_path(&file.path)?;
+        if !paths.insert(file.path.as_str()) {
+            return Err(ProgramError::DuplicatePath(file.path.clone()));
+        }
+    }
+    // A path can't be both a file and a directory of the tree
+    for path in &paths {
+        let mut prefix = *path;
+        while let Some((parent, _)) = prefix.rsplit_once('/') {
+            if paths.contains(parent) {
+                return Err(invalid(parent, "also used as a directory"));
+            }
+            prefix = parent;
+        }
+    }
+    if !paths.contains(entrypoint) {
+        return Err(ProgramError::UnknownEntrypoint(entrypoint.to_string()));
+    }
+    Ok(())
+}
+
+/// Paths must be relative, `/`-separated and stay inside the working directory
+pub(crate) fn validate_path(path: &str) -> Result<(), ProgramError> {
+    if path.is_empty() {
+        return Err(invalid(path, "empty path"));
+    }
+    if path.starts_with('/') || path.starts_with('\\') {
+        return Err(invalid(path, "absolute paths are not allowed"));
+    }
+    if path.contains('\\') || path.contains('\0') {
+        return Err(invalid(path, "use '/' as the separator"));
+    }
+    for component in path.split('/') {
+        match component {
+            ".." => return Err(invalid(path, "'..' is not allowed")),
+            "" | "." => return Err(invalid(path, "path must be normalized")),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check pip requirements; only plain package specifiers pass unless `allow_unsafe` is set
+pub fn validate_requirements(
+    requirements: &[String],
+    allow_unsafe: bool,
+) -> Result<(), ProgramError> {
+    for requirement in requirements {
+        let trimmed = requirement.trim();
+        let reason = if trimmed.is_empty() {
+            Some("empty requirement")
+        } else if trimmed.starts_with('-') {
+            // Options such as --index-url or -r would change what pip installs from
+            Some("pip options are not allowed")
+        } else if allow_unsafe || REQUIREMENT_SPEC.is_match(trimmed) {
+            None
+        } else if trimmed.contains("://") || trimmed.contains('@') {
+            Some("URLs are not allowed")
+        } else if trimmed.contains('/') || trimmed.contains('\\') || trimmed.starts_with('.') {
+            Some("local paths are not allowed")
+        } else {
+            Some("not a package specifier")
+        };
+        if let Some(reason) = reason {
+            return Err(ProgramError::InvalidRequirement {
+                requirement: requirement.clone(),
+                reason,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn invalid(path: &str, reason: &'static str) -> ProgramError {
+    ProgramError::InvalidPath {
+        path: path.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, content: &str) -> SourceFile {
+        SourceFile {
+            path: path.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    fn request(json: serde_json::Value) -> ExecuteRequest {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn files_program(files: Vec<SourceFile>, entrypoint: &str) -> Result<Program, ProgramError> {
+        Program::from_request(&ExecuteRequest {
+            files: Some(files),
+            entrypoint: Some(entrypoint.to_string()),
+            ..request(serde_json::json!({}))
+        })
+    }
+
+    #[test]
+    fn test_code_or_files_exactly_one() {
+        assert_eq!(
+            Program::from_request(&request(serde_json::json!({ "code": "print(1)" }))),
+            Ok(Program::Code("print(1)".to_string()))
+        );
+        let both = request(serde_json::json!({
+            "code": "print(1)",
+            "files": [{ "path": "main.py", "content": "" }],
+            "entrypoint": "main.py"
+        }));
+        assert_eq!(Program::from_request(&both), Err(ProgramError::Ambiguous));
+        assert_eq!(
+            Program::from_request(&request(serde_json::json!({}))),
+            Err(ProgramError::Missing)
+        );
+        let no_entrypoint = request(serde_json::json!({ "files": [] }));
+        assert_eq!(
+            Program::from_request(&no_entrypoint),
+            Err(ProgramError::Missing)
+        );
+        assert_eq!(files_program(vec![], "main.py"), Err(ProgramError::NoFiles));
+    }
+
+    #[test]
+    fn test_eval_mode_takes_code_only() {
+        assert_eq!(
+            Program::from_request(&request(
+                serde_json::json!({ "code": "1 + 1", "mode": "eval" })
+            )),
+            Ok(Program::Eval("1 + 1".to_string()))
+        );
+        assert_eq!(
+            Program::from_request(&request(
+                serde_json::json!({ "code": "1", "mode": "script" })
+            )),
+            Ok(Program::Code("1".to_string()))
+        );
+        let files = request(serde_json::json!({
+            "files": [{ "path": "main.py", "content": "1" }],
+            "entrypoint": "main.py",
+            "mode": "eval"
+        }));
+        assert_eq!(
+            Program::from_request(&files),
+            Err(ProgramError::EvalNeedsCode)
+        );
+    }
+
+    #[test]
+    fn test_valid_tree() {
+        let program = files_program(
+            vec![
+                file("main.py", "from pkg.utils import add\nprint(add(1, 2))"),
+                file("pkg/utils.py", "def add(a, b):\n    return a + b"),
+                file("pkg/__init__.py", ""),
+            ],
+            "main.py",
+        )
+        .unwrap();
+        assert_eq!(program.sources().len(), 3);
+        assert_eq!(program.source_len(), 73);
+    }
+
+    #[test]
+    fn test_rejects_bad_paths() {
+        for path in [
+            "",
+            "/etc/passwd",
+            "../escape.py",
+            "pkg/../../escape.py",
+            "pkg//utils.py",
+            "./main.py",
+            "pkg\\utils.py",
+        ] {
+            let err = files_program(vec![file(path, "")], path).unwrap_err();
+            assert!(
+                matches!(err, ProgramError::InvalidPath { .. }),
+                "{path:?} gave {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_duplicates_and_conflicts() {
+        assert_eq!(
+            files_program(vec![file("a.py", "1"), file("a.py", "2")], "a.py"),
+            Err(ProgramError::DuplicatePath("a.py".to_string()))
+        );
+        assert!(matches!(
+            files_program(vec![file("pkg", ""), file("pkg/a.py", "")], "pkg/a.py"),
+            Err(ProgramError::InvalidPath { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_unknown_entrypoint() {
+        assert_eq!(
+            files_program(vec![file("main.py", "")], "app.py"),
+            Err(ProgramError::UnknownEntrypoint("app.py".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_requirements_validation() {
+        let reqs = |list: &[&str]| list.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert!(
+            validate_requirements(
+                &reqs(&[
+                    "numpy",
+                    "requests[socks]>=2.31,<3",
+                    "pandas == 2.2.*",
+                    "py_mini-racer"
+                ]),
+                false
+            )
+            .is_ok()
+        );
+        for bad in [
+            "",
+            "--index-url=https://evil.example/simple",
+            "-rrequirements.txt",
+            "https://evil.example/pkg.tar.gz",
+            "pkg @ git+https://evil.example/pkg",
+            "./local_pkg",
+            "/tmp/wheel.whl",
+            "numpy; import os",
+        ] {
+            assert!(
+                matches!(
+                    validate_requirements(&reqs(&[bad]), false),
+                    Err(ProgramError::InvalidRequirement { .. })
+                ),
+                "{bad:?} should be rejected"
+            );
+        }
+
+        // The config flag permits URLs and paths, but never pip options
+        assert!(validate_requirements(&reqs(&["https://example.com/pkg.whl"]), true).is_ok());
+        assert!(validate_requirements(&reqs(&["-e ."]), true).is_err());
+    }
+
+    #[test]
+    fn test_sha256_distinguishes_trees() {
+        let a = files_program(vec![file("a.py", "x"), file("b.py", "")], "a.py").unwrap();
+        let b = files_program(vec![file("a.py", ""), file("b.py", "x")], "a.py").unwrap();
+        assert_ne!(a.sha256(), b.sha256());
+        assert_eq!(
+            Program::Code("x".to_string()).sha256(),
+            crate::history::code_sha256("x")
+        );
+    }
+}
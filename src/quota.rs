@@ -0,0 +1,550 @@
+use crate::history::now_millis;
+use crate::jobs::{JobStore, StoreError};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Default length of an execution quota's rolling window
+pub const DEFAULT_QUOTA_WINDOW_SECS: u64 = 3600;
+
+fn default_window_secs() -> u64 {
+    DEFAULT_QUOTA_WINDOW_SECS
+}
+
+/// Limits on one tenant's executions; an unset limit doesn't apply
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaLimit {
+    /// Executions started within any `window_secs`
+    #[serde(default)]
+    pub executions: Option<u32>,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// Executions running at once
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+}
+
+impl QuotaLimit {
+    fn window_millis(&self) -> u64 {
+        self.window_secs.saturating_mul(1000)
+    }
+}
+
+/// Per-tenant quotas as written in `FC_QUOTAS_FILE`. Tenants are API key identifiers (see
+/// `ApiKeyId`); requests without an API key are never limited.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaConfig {
+    /// Limits of every API key without its own entry
+    #[serde(default)]
+    pub default: Option<QuotaLimit>,
+    #[serde(default)]
+    pub tenants: HashMap<String, QuotaLimit>,
+}
+
+impl QuotaConfig {
+    /// Whether any quota is configured
+    pub fn is_enabled(&self) -> bool {
+        self.default.is_some() || !self.tenants.is_empty()
+    }
+
+    fn limit_for(&self, tenant: &str) -> Option<QuotaLimit> {
+        self.tenants.get(tenant).copied().or(self.default)
+    }
+
+    /// Longest window of any configured quota
+    fn max_window_millis(&self) -> u64 {
+        self.default
+            .iter()
+            .chain(self.tenants.values())
+            .map(QuotaLimit::window_millis)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Which of a tenant's limits a request ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    /// Executions per rolling window
+    Executions,
+    /// Executions running at once
+    Concurrency,
+}
+
+/// A request turned away by its tenant's quota
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct QuotaExceeded {
+    pub tenant: String,
+    pub limit: QuotaKind,
+    /// Value of the limit that was hit
+    pub allowed: u64,
+    /// Rolling window of an execution quota
+    pub window_secs: Option<u64>,
+    /// When another execution fits in the window, in milliseconds since the Unix epoch; `None`
+    /// for concurrency, which frees up as running executions finish
+    pub resets_at: Option<u64>,
+}
+
+impl QuotaExceeded {
+    /// Seconds until the quota lets another execution through, at least one
+    pub fn retry_after_secs(&self) -> u64 {
+        self.resets_at
+            .map_or(1, |at| at.saturating_sub(now_millis()).div_ceil(1000))
+            .max(1)
+    }
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.limit, self.window_secs) {
+            (QuotaKind::Executions, Some(window)) => write!(
+                f,
+                "Quota of {} executions per {window} seconds exceeded for tenant {}",
+                self.allowed, self.tenant
+            ),
+            _ => write!(
+                f,
+                "Quota of {} concurrent executions exceeded for tenant {}",
+                self.allowed, self.tenant
+            ),
+        }
+    }
+}
+
+/// Body of a `429` answering a request over its tenant's quota
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct QuotaErrorResponse {
+    /// Human-readable error message
+    pub error: String,
+    /// Always `quota_exceeded`
+    pub code: String,
+    pub quota: QuotaExceeded,
+}
+
+impl From<QuotaExceeded> for QuotaErrorResponse {
+    fn from(quota: QuotaExceeded) -> Self {
+        Self {
+            error: quota.to_string(),
+            code: "quota_exceeded".to_string(),
+            quota,
+        }
+    }
+}
+
+/// A tenant's usage of its quota, as reported by `GET /admin/quotas`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct TenantUsage {
+    pub tenant: String,
+    /// Executions started within the current window
+    pub executions: usize,
+    pub executions_limit: Option<u32>,
+    pub window_secs: u64,
+    /// When the oldest execution in the window leaves it, in milliseconds since the Unix epoch
+    pub resets_at: Option<u64>,
+    /// Executions running now
+    pub in_flight: usize,
+    pub max_concurrent: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    /// Start times within the window, oldest first; only kept under an execution quota
+    starts: VecDeque<u64>,
+    in_flight: usize,
+}
+
+impl Usage {
+    /// Forget executions that left the window ending at `now`
+    fn roll(&mut self, limit: &QuotaLimit, now: u64) {
+        let window = limit.window_millis();
+        while self
+            .starts
+            .front()
+            .is_some_and(|start| start.saturating_add(window) <= now)
+        {
+            self.starts.pop_front();
+        }
+    }
+
+    fn check(&self, tenant: &str, limit: &QuotaLimit) -> Result<(), QuotaExceeded> {
+        if let Some(allowed) = limit.executions
+            && self.starts.len() >= allowed as usize
+        {
+            return Err(QuotaExceeded {
+                tenant: tenant.to_string(),
+                limit: QuotaKind::Executions,
+                allowed: allowed as u64,
+                window_secs: Some(limit.window_secs),
+                resets_at: Some(
+                    self.starts
+                        .front()
+                        .map_or(0, |start| start.saturating_add(limit.window_millis())),
+                ),
+            });
+        }
+        if let Some(allowed) = limit.max_concurrent
+            && self.in_flight >= allowed
+        {
+            return Err(QuotaExceeded {
+                tenant: tenant.to_string(),
+                limit: QuotaKind::Concurrency,
+                allowed: allowed as u64,
+                window_secs: None,
+                resets_at: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Usage per tenant
+type SharedUsage = Arc<Mutex<HashMap<String, Usage>>>;
+
+/// Rolling-window execution counts and in-flight executions per tenant, checked against the
+/// configured quotas. Counts are optionally written through to a store, so a restart doesn't
+/// hand every tenant a fresh window.
+#[derive(Debug)]
+pub struct QuotaTracker {
+    config: QuotaConfig,
+    usage: SharedUsage,
+    store: OnceCell<Arc<dyn JobStore>>,
+}
+
+impl QuotaTracker {
+    pub fn new(config: QuotaConfig) -> Self {
+        Self {
+            config,
+            usage: Arc::new(Mutex::new(HashMap::new())),
+            store: OnceCell::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    /// Load the executions still within their windows from `store` and write every later one
+    /// through to it. Only the first store set takes effect.
+    pub fn persist_to(&self, store: Arc<dyn JobStore>) -> Result<(), StoreError> {
+        let since = now_millis().saturating_sub(self.config.max_window_millis());
+        let uses = store.quota_uses(since)?;
+        if self.store.set(store).is_err() {
+            return Ok(());
+        }
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        for (tenant, at) in uses {
+            if self
+                .config
+                .limit_for(&tenant)
+                .is_some_and(|limit| limit.executions.is_some())
+            {
+                usage.entry(tenant).or_default().starts.push_back(at);
+            }
+        }
+        Ok(())
+    }
+
+    /// Count an execution starting for `tenant` against its quota, failing when a limit is
+    /// reached. The returned guard holds the execution's concurrency slot until dropped.
+    pub fn acquire(&self, tenant: Option<&str>) -> Result<QuotaGuard, QuotaExceeded> {
+        self.acquire_at(tenant, now_millis())
+    }
+
+    /// Same as [`QuotaTracker::acquire`] with an explicit clock, for tests
+    pub fn acquire_at(&self, tenant: Option<&str>, now: u64) -> Result<QuotaGuard, QuotaExceeded> {
+        let slot = self
+            .start(tenant, now, true)?
+            .map(|tenant| (self.usage.clone(), tenant));
+        Ok(QuotaGuard { slot })
+    }
+
+    /// Count an execution for `tenant` that runs in a concurrency slot it already holds, as
+    /// one in a kept sandbox does, failing when its execution quota is reached
+    pub fn count(&self, tenant: Option<&str>) -> Result<(), QuotaExceeded> {
+        self.count_at(tenant, now_millis())
+    }
+
+    /// Same as [`QuotaTracker::count`] with an explicit clock, for tests
+    pub fn count_at(&self, tenant: Option<&str>, now: u64) -> Result<(), QuotaExceeded> {
+        self.start(tenant, now, false).map(|_| ())
+    }
+
+    /// Count an execution starting for `tenant`, taking a concurrency slot when `concurrent`;
+    /// returns the tenant when it has a quota
+    fn start(
+        &self,
+        tenant: Option<&str>,
+        now: u64,
+        concurrent: bool,
+    ) -> Result<Option<String>, QuotaExceeded> {
+        let Some((tenant, limit)) =
+            tenant.and_then(|tenant| Some((tenant, self.config.limit_for(tenant)?)))
+        else {
+            return Ok(None);
+        };
+        {
+            let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            let usage = usage.entry(tenant.to_string()).or_default();
+            usage.roll(&limit, now);
+            let checked = QuotaLimit {
+                max_concurrent: limit.max_concurrent.filter(|_| concurrent),
+                ..limit
+            };
+            usage.check(tenant, &checked)?;
+            if limit.executions.is_some() {
+                usage.starts.push_back(now);
+            }
+            if concurrent {
+                usage.in_flight += 1;
+            }
+        }
+        if limit.executions.is_some()
+            && let Some(store) = self.store.get()
+            && let Err(e) =
+                store.record_quota_use(tenant, now, now.saturating_sub(limit.window_millis()))
+        {
+            tracing::warn!("Failed to persist quota use of {}: {}", tenant, e);
+        }
+        Ok(Some(tenant.to_string()))
+    }
+
+    /// Whether `tenant` has room for another execution in its window, without counting one
+    pub fn check(&self, tenant: Option<&str>) -> Result<(), QuotaExceeded> {
+        self.check_at(tenant, now_millis())
+    }
+
+    /// Same as [`QuotaTracker::check`] with an explicit clock, for tests
+    pub fn check_at(&self, tenant: Option<&str>, now: u64) -> Result<(), QuotaExceeded> {
+        let Some((tenant, limit)) =
+            tenant.and_then(|tenant| Some((tenant, self.config.limit_for(tenant)?)))
+        else {
+            return Ok(());
+        };
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(usage) = usage.get_mut(tenant) else {
+            return Ok(());
+        };
+        usage.roll(&limit, now);
+        // Concurrency is only known once the execution actually runs
+        usage.check(
+            tenant,
+            &QuotaLimit {
+                max_concurrent: None,
+                ..limit
+            },
+        )
+    }
+
+    /// Usage of every tenant with a quota of its own or a tracked execution
+    pub fn usage(&self) -> Vec<TenantUsage> {
+        self.usage_at(now_millis())
+    }
+
+    /// Same as [`QuotaTracker::usage`] with an explicit clock, for tests
+    pub fn usage_at(&self, now: u64) -> Vec<TenantUsage> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        for tenant in self.config.tenants.keys() {
+            usage.entry(tenant.clone()).or_default();
+        }
+        let mut report: Vec<_> = usage
+            .iter_mut()
+            .filter_map(|(tenant, usage)| {
+                let limit = self.config.limit_for(tenant)?;
+                usage.roll(&limit, now);
+                Some(TenantUsage {
+                    tenant: tenant.clone(),
+                    executions: usage.starts.len(),
+                    executions_limit: limit.executions,
+                    window_secs: limit.window_secs,
+                    resets_at: usage
+                        .starts
+                        .front()
+                        .map(|start| start.saturating_add(limit.window_millis())),
+                    in_flight: usage.in_flight,
+                    max_concurrent: limit.max_concurrent,
+                })
+            })
+            .collect();
+        report.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        report
+    }
+}
+
+/// A running execution's place in its tenant's concurrency quota, given back when dropped
+#[derive(Debug)]
+pub struct QuotaGuard {
+    slot: Option<(SharedUsage, String)>,
+}
+
+impl Drop for QuotaGuard {
+    fn drop(&mut self) {
+        if let Some((usage, tenant)) = &self.slot {
+            let mut usage = usage.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(usage) = usage.get_mut(tenant) {
+                usage.in_flight = usage.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3_600_000;
+
+    fn tracker(executions: Option<u32>, max_concurrent: Option<usize>) -> QuotaTracker {
+        QuotaTracker::new(QuotaConfig {
+            default: None,
+            tenants: HashMap::from([(
+                "team-x".to_string(),
+                QuotaLimit {
+                    executions,
+                    window_secs: 3600,
+                    max_concurrent,
+                },
+            )]),
+        })
+    }
+
+    #[test]
+    fn test_execution_quota_rolls_over_at_the_window_edge() {
+        let tracker = tracker(Some(2), None);
+        let start = 1_000_000;
+        drop(tracker.acquire_at(Some("team-x"), start).unwrap());
+        drop(tracker.acquire_at(Some("team-x"), start + 10).unwrap());
+
+        let err = tracker
+            .acquire_at(Some("team-x"), start + HOUR - 1)
+            .unwrap_err();
+        assert_eq!(err.limit, QuotaKind::Executions);
+        assert_eq!((err.allowed, err.window_secs), (2, Some(3600)));
+        assert_eq!(err.resets_at, Some(start + HOUR));
+
+        // The first execution leaves the window exactly an hour after it started, the second
+        // ten milliseconds later
+        drop(tracker.acquire_at(Some("team-x"), start + HOUR).unwrap());
+        let err = tracker
+            .acquire_at(Some("team-x"), start + HOUR + 9)
+            .unwrap_err();
+        assert_eq!(err.resets_at, Some(start + HOUR + 10));
+        assert!(
+            tracker
+                .acquire_at(Some("team-x"), start + HOUR + 10)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_rejected_and_checked_requests_are_not_counted() {
+        let tracker = tracker(Some(1), None);
+        drop(tracker.acquire_at(Some("team-x"), 0).unwrap());
+        for _ in 0..5 {
+            assert!(tracker.acquire_at(Some("team-x"), 1).is_err());
+            assert!(tracker.check_at(Some("team-x"), 1).is_err());
+        }
+        // Checking at the window's edge leaves the freed slot to the next execution
+        assert!(tracker.check_at(Some("team-x"), HOUR).is_ok());
+        assert!(tracker.check_at(Some("team-x"), HOUR).is_ok());
+        assert!(tracker.acquire_at(Some("team-x"), HOUR).is_ok());
+    }
+
+    #[test]
+    fn test_concurrency_quota_frees_up_as_executions_finish() {
+        let tracker = tracker(None, Some(2));
+        let first = tracker.acquire_at(Some("team-x"), 0).unwrap();
+        let _second = tracker.acquire_at(Some("team-x"), 0).unwrap();
+        let err = tracker.acquire_at(Some("team-x"), 0).unwrap_err();
+        assert_eq!(err.limit, QuotaKind::Concurrency);
+        assert_eq!(err.resets_at, None);
+        // Submitting for later isn't held back by what runs now
+        assert!(tracker.check_at(Some("team-x"), 0).is_ok());
+
+        drop(first);
+        assert!(tracker.acquire_at(Some("team-x"), 0).is_ok());
+        assert_eq!(tracker.usage_at(0)[0].in_flight, 1);
+    }
+
+    #[test]
+    fn test_counted_executions_take_no_concurrency_slot() {
+        let tracker = tracker(Some(3), Some(1));
+        // As a sandbox holds its tenant's only slot, executions in it still run
+        let _sandbox = tracker.acquire_at(Some("team-x"), 0).unwrap();
+        assert!(tracker.count_at(Some("team-x"), 1).is_ok());
+        assert!(tracker.count_at(Some("team-x"), 2).is_ok());
+        let usage = &tracker.usage_at(2)[0];
+        assert_eq!((usage.executions, usage.in_flight), (3, 1));
+        // But they count against the window
+        let err = tracker.count_at(Some("team-x"), 3).unwrap_err();
+        assert_eq!(err.limit, QuotaKind::Executions);
+    }
+
+    #[test]
+    fn test_unlisted_tenants_get_the_default() {
+        let tracker = QuotaTracker::new(QuotaConfig {
+            default: Some(QuotaLimit {
+                executions: Some(1),
+                window_secs: 60,
+                max_concurrent: None,
+            }),
+            tenants: HashMap::from([(
+                "vip".to_string(),
+                QuotaLimit {
+                    executions: None,
+                    window_secs: 60,
+                    max_concurrent: None,
+                },
+            )]),
+        });
+        assert!(tracker.acquire_at(Some("other"), 0).is_ok());
+        assert!(tracker.acquire_at(Some("other"), 0).is_err());
+        for _ in 0..10 {
+            assert!(tracker.acquire_at(Some("vip"), 0).is_ok());
+            assert!(tracker.acquire_at(None, 0).is_ok());
+        }
+
+        let usage = tracker.usage_at(30_000);
+        let tenants: Vec<_> = usage.iter().map(|u| u.tenant.as_str()).collect();
+        assert_eq!(tenants, ["other", "vip"]);
+        assert_eq!((usage[0].executions, usage[0].resets_at), (1, Some(60_000)));
+        // The window rolled over for the report too
+        assert_eq!(tracker.usage_at(60_000)[0].executions, 0);
+    }
+
+    #[test]
+    fn test_quota_config_from_json() {
+        let config: QuotaConfig = serde_json::from_str(
+            r#"{"default": {"executions": 100}, "tenants": {"1a2b3c4d": {"executions": 500, "max_concurrent": 2}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.default.unwrap().window_secs,
+            DEFAULT_QUOTA_WINDOW_SECS
+        );
+        assert_eq!(config.tenants["1a2b3c4d"].max_concurrent, Some(2));
+        assert!(serde_json::from_str::<QuotaConfig>(r#"{"default": {"per_hour": 1}}"#).is_err());
+    }
+
+    #[cfg(feature = "persistence")]
+    #[test]
+    fn test_quota_usage_survives_a_restart() {
+        let store: Arc<dyn JobStore> =
+            Arc::new(crate::persistence::SqliteJobStore::in_memory(1024).unwrap());
+        let now = now_millis();
+        let first = tracker(Some(2), None);
+        first.persist_to(store.clone()).unwrap();
+        drop(first.acquire_at(Some("team-x"), now - 2 * HOUR).unwrap());
+        drop(first.acquire_at(Some("team-x"), now - 1000).unwrap());
+        drop(first.acquire_at(Some("team-x"), now).unwrap());
+
+        // Only the two executions within the last hour carry over
+        let restarted = tracker(Some(2), None);
+        restarted.persist_to(store).unwrap();
+        let err = restarted.acquire_at(Some("team-x"), now).unwrap_err();
+        assert_eq!(err.resets_at, Some(now - 1000 + HOUR));
+    }
+}
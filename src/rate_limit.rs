@@ -0,0 +1,281 @@
+use crate::ErrorResponse;
+use crate::auth::ApiKeyId;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Json, Response};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Buckets kept before idle, fully refilled ones are pruned
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// Token-bucket parameters for one client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Sustained rate at which tokens are refilled
+    pub per_minute: u32,
+    /// Maximum number of tokens, i.e. requests allowed back-to-back
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn refill_per_sec(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+/// Parse `RATE` or `RATE:BURST`; a missing burst defaults to the rate
+pub fn parse_rate_limit(raw: &str) -> Option<RateLimit> {
+    let (rate, burst) = match raw.split_once(':') {
+        Some((rate, burst)) => (rate.trim().parse().ok()?, burst.trim().parse().ok()?),
+        None => {
+            let rate = raw.trim().parse().ok()?;
+            (rate, rate)
+        }
+    };
+    (rate > 0 && burst > 0).then_some(RateLimit {
+        per_minute: rate,
+        burst,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The default limit and the per-client overrides of a `RateLimiter`
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Limits {
+    default: Option<RateLimit>,
+    overrides: HashMap<String, RateLimit>,
+}
+
+/// Per-client token-bucket rate limiter
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: RwLock<Limits>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Create a limiter; `default: None` leaves clients without an override unlimited
+    pub fn new(default: Option<RateLimit>, overrides: HashMap<String, RateLimit>) -> Self {
+        Self {
+            limits: RwLock::new(Limits { default, overrides }),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Replace the limits, as on a reload. Clients keep the tokens they have, capped at
+    /// their new burst on their next request.
+    pub fn set_limits(&self, default: Option<RateLimit>, overrides: HashMap<String, RateLimit>) {
+        *self.limits.write().unwrap_or_else(|e| e.into_inner()) = Limits { default, overrides };
+    }
+
+    /// Whether any limit is configured
+    pub fn is_enabled(&self) -> bool {
+        let limits = self.limits.read().unwrap_or_else(|e| e.into_inner());
+        limits.default.is_some() || !limits.overrides.is_empty()
+    }
+
+    fn limit_for(&self, client: &str) -> Option<RateLimit> {
+        let limits = self.limits.read().unwrap_or_else(|e| e.into_inner());
+        limits.overrides.get(client).copied().or(limits.default)
+    }
+
+    /// Take one token for `client`, or return how long to wait for the next one
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    /// Same as [`RateLimiter::check`] with an explicit clock, for tests
+    pub fn check_at(&self, client: &str, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limit_for(client) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_BUCKETS {
+            self.prune(&mut buckets, now);
+        }
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.refill_per_sec()).min(limit.burst as f64);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / limit.refill_per_sec()))
+        }
+    }
+
+    /// Drop buckets that would be full by now; they are indistinguishable from new ones
+    fn prune(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        buckets.retain(|client, bucket| {
+            let Some(limit) = self.limit_for(client) else {
+                return false;
+            };
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * limit.refill_per_sec() < limit.burst as f64
+        });
+    }
+
+    /// Tokens currently available to `client`, as of its last request
+    pub fn level(&self, client: &str) -> Option<f64> {
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.get(client).map(|bucket| bucket.tokens)
+    }
+}
+
+/// Identify the client for rate limiting: its API key, else its address
+fn client_key(request: &Request) -> (String, bool) {
+    if let Some(ApiKeyId(id)) = request.extensions().get::<ApiKeyId>() {
+        return (id.clone(), true);
+    }
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    (format!("ip:{ip}"), false)
+}
+
+/// Middleware rejecting requests over the client's rate limit with 429 and `Retry-After`
+pub async fn enforce_rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.is_enabled() {
+        return next.run(request).await;
+    }
+    let (client, is_api_key) = client_key(&request);
+    let result = limiter.check(&client);
+
+    // Only API key buckets are exported: their number is bounded by the configured keys
+    if is_api_key && let Some(level) = limiter.level(&client) {
+        crate::telemetry::set_gauge("fc_rate_limit_tokens", &[("client", &client)], level);
+    }
+
+    match result {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            crate::telemetry::increment_counter("fc_rate_limited_total", &[], 1);
+            tracing::debug!("Rate limited client {}", client);
+            let retry_after_secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(ErrorResponse::new(
+                    "rate_limited",
+                    format!("Rate limit exceeded, retry after {retry_after_secs} seconds"),
+                )),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_minute: u32, burst: u32) -> RateLimiter {
+        RateLimiter::new(Some(RateLimit { per_minute, burst }), HashMap::new())
+    }
+
+    #[test]
+    fn test_burst_then_reject() {
+        let limiter = limiter(60, 3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("client", now).is_ok());
+        }
+        let retry_after = limiter.check_at("client", now).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(1));
+        assert!(retry_after > Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_refill_over_time() {
+        let limiter = limiter(60, 1);
+        let now = Instant::now();
+        assert!(limiter.check_at("client", now).is_ok());
+        assert!(limiter.check_at("client", now).is_err());
+        assert!(
+            limiter
+                .check_at("client", now + Duration::from_millis(1500))
+                .is_ok()
+        );
+        // Refill never exceeds the burst size
+        let later = now + Duration::from_secs(3600);
+        assert!(limiter.check_at("client", later).is_ok());
+        assert!(limiter.check_at("client", later).is_err());
+    }
+
+    #[test]
+    fn test_clients_are_independent_and_overridable() {
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            "vip".to_string(),
+            RateLimit {
+                per_minute: 600,
+                burst: 5,
+            },
+        );
+        let limiter = RateLimiter::new(
+            Some(RateLimit {
+                per_minute: 1,
+                burst: 1,
+            }),
+            overrides,
+        );
+        let now = Instant::now();
+        assert!(limiter.check_at("a", now).is_ok());
+        assert!(limiter.check_at("a", now).is_err());
+        assert!(limiter.check_at("b", now).is_ok());
+        for _ in 0..5 {
+            assert!(limiter.check_at("vip", now).is_ok());
+        }
+        assert!(limiter.check_at("vip", now).is_err());
+    }
+
+    #[test]
+    fn test_disabled_limiter() {
+        let limiter = RateLimiter::new(None, HashMap::new());
+        assert!(!limiter.is_enabled());
+        for _ in 0..100 {
+            assert!(limiter.check("client").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(
+            parse_rate_limit("120:10"),
+            Some(RateLimit {
+                per_minute: 120,
+                burst: 10
+            })
+        );
+        assert_eq!(
+            parse_rate_limit("30"),
+            Some(RateLimit {
+                per_minute: 30,
+                burst: 30
+            })
+        );
+        assert_eq!(parse_rate_limit("0:5"), None);
+        assert_eq!(parse_rate_limit("abc"), None);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::admission::ResourceUsage;
+use crate::breaker::BreakerState;
+use crate::executor::ExecutorStats;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Weights of the readiness inputs; they add up to 1
+const POOL_WEIGHT: f64 = 0.35;
+const BREAKER_WEIGHT: f64 = 0.3;
+const BOOT_WEIGHT: f64 = 0.2;
+const MEMORY_WEIGHT: f64 = 0.15;
+
+/// VMs' worth of memory headroom that counts as plenty
+const COMFORTABLE_HEADROOM_VMS: f64 = 2.0;
+
+/// Thresholds `/ready` turns `503` below
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadinessConfig {
+    /// Lowest score that is still ready
+    pub min_score: f64,
+    /// Also require a warm VM, or a closed VM creation circuit to boot one
+    pub require_capacity: bool,
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            min_score: 0.5,
+            require_capacity: true,
+        }
+    }
+}
+
+/// What readiness is judged on, assembled from the executor's pool stats, its VM creation
+/// breaker and the host's memory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RunnerSnapshot {
+    /// Booted VMs waiting in the pool
+    pub idle_vms: usize,
+    /// Warm VMs the pool is kept at
+    pub pool_target: usize,
+    /// State of the VM creation circuit
+    pub vm_creation: BreakerState,
+    /// VM creations the failure rate is taken over
+    pub recent_boots: usize,
+    /// Share of the recent VM creations that failed; absent before the first
+    pub boot_failure_rate: Option<f64>,
+    /// Host memory available above the admission floor, in MiB; negative below it
+    pub memory_headroom_mib: Option<i64>,
+    /// Memory of one VM in MiB, the unit headroom is judged in
+    pub vm_memory_mib: u64,
+}
+
+impl RunnerSnapshot {
+    pub fn assemble(
+        stats: &ExecutorStats,
+        vm_creation: BreakerState,
+        (recent_boots, boot_failure_rate): (usize, Option<f64>),
+        usage: &ResourceUsage,
+        vm_memory_mib: u64,
+    ) -> Self {
+        Self {
+            idle_vms: stats.idle_vms,
+            pool_target: stats.pool_target,
+            vm_creation,
+            recent_boots,
+            boot_failure_rate,
+            memory_headroom_mib: usage
+                .host_available_memory_mib
+                .map(|available| available as i64 - usage.min_host_available_mib as i64),
+            vm_memory_mib,
+        }
+    }
+}
+
+/// Readiness score of a snapshot, with the inputs it was derived from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Readiness {
+    /// From 0 (can't serve) to 1 (a warm VM is waiting and nothing is amiss)
+    pub score: f64,
+    pub ready: bool,
+    pub inputs: RunnerSnapshot,
+}
+
+/// Score from 0 to 1 of how quickly `snapshot`'s node can serve a request: a full warm pool,
+/// a closed creation circuit, boots that succeed and memory to spare make it 1
+pub fn score(snapshot: &RunnerSnapshot) -> f64 {
+    let pool = if snapshot.pool_target == 0 {
+        1.0
+    } else {
+        (snapshot.idle_vms as f64 / snapshot.pool_target as f64).min(1.0)
+    };
+    let breaker = match snapshot.vm_creation {
+        BreakerState::Closed => 1.0,
+        BreakerState::HalfOpen => 0.5,
+        BreakerState::Open => 0.0,
+    };
+    let boots = 1.0 - snapshot.boot_failure_rate.unwrap_or(0.0);
+    let memory = match snapshot.memory_headroom_mib {
+        // Hosts whose memory can't be read aren't held against
+        None => 1.0,
+        Some(headroom) => {
+            let comfortable = snapshot.vm_memory_mib.max(1) as f64 * COMFORTABLE_HEADROOM_VMS;
+            (headroom as f64 / comfortable).clamp(0.0, 1.0)
+        }
+    };
+    let score = POOL_WEIGHT * pool
+        + BREAKER_WEIGHT * breaker
+        + BOOT_WEIGHT * boots
+        + MEMORY_WEIGHT * memory;
+    // Rounded so dashboards don't graph float noise
+    (score * 1000.0).round() / 1000.0
+}
+
+/// Judge `snapshot` against `config`
+pub fn assess(snapshot: RunnerSnapshot, config: &ReadinessConfig) -> Readiness {
+    let score = score(&snapshot);
+    let has_capacity = snapshot.idle_vms > 0 || snapshot.vm_creation == BreakerState::Closed;
+    Readiness {
+        score,
+        ready: score >= config.min_score && (has_capacity || !config.require_capacity),
+        inputs: snapshot,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> RunnerSnapshot {
+        RunnerSnapshot {
+            idle_vms: 2,
+            pool_target: 2,
+            vm_creation: BreakerState::Closed,
+            recent_boots: 10,
+            boot_failure_rate: Some(0.0),
+            memory_headroom_mib: Some(4096),
+            vm_memory_mib: 128,
+        }
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(score(&healthy()), 1.0);
+        // A cold pool still serves, a boot slower
+        let cold = RunnerSnapshot {
+            idle_vms: 0,
+            ..healthy()
+        };
+        assert_eq!(score(&cold), 0.65);
+        let half_warm = RunnerSnapshot {
+            idle_vms: 1,
+            ..healthy()
+        };
+        assert_eq!(score(&half_warm), 0.825);
+        let failing = RunnerSnapshot {
+            vm_creation: BreakerState::Open,
+            boot_failure_rate: Some(0.5),
+            ..healthy()
+        };
+        assert_eq!(score(&failing), 0.6);
+        // One VM's worth of headroom is half of a comfortable margin; below the floor is none
+        let tight = RunnerSnapshot {
+            memory_headroom_mib: Some(128),
+            ..healthy()
+        };
+        assert_eq!(score(&tight), 0.925);
+        let exhausted = RunnerSnapshot {
+            memory_headroom_mib: Some(-64),
+            ..healthy()
+        };
+        assert_eq!(score(&exhausted), 0.85);
+        // Unknown inputs count as fine
+        let unknown = RunnerSnapshot {
+            recent_boots: 0,
+            boot_failure_rate: None,
+            memory_headroom_mib: None,
+            pool_target: 0,
+            idle_vms: 0,
+            ..healthy()
+        };
+        assert_eq!(score(&unknown), 1.0);
+    }
+
+    #[test]
+    fn test_assess_thresholds() {
+        let config = ReadinessConfig::default();
+        assert!(assess(healthy(), &config).ready);
+
+        // Open circuit and a cold pool: nothing can be served quickly whatever the score
+        let stranded = RunnerSnapshot {
+            idle_vms: 0,
+            vm_creation: BreakerState::Open,
+            ..healthy()
+        };
+        let readiness = assess(stranded.clone(), &config);
+        assert!(!readiness.ready);
+        assert_eq!(readiness.score, 0.35);
+        let lenient = ReadinessConfig {
+            min_score: 0.3,
+            require_capacity: false,
+        };
+        assert!(assess(stranded, &lenient).ready);
+
+        // Warm VMs still serve while the circuit is open
+        let warm_but_failing = RunnerSnapshot {
+            vm_creation: BreakerState::Open,
+            boot_failure_rate: Some(1.0),
+            ..healthy()
+        };
+        let readiness = assess(warm_but_failing, &config);
+        assert_eq!(readiness.score, 0.5);
+        assert!(readiness.ready);
+        assert!(
+            !assess(
+                healthy(),
+                &ReadinessConfig {
+                    min_score: 1.01,
+                    ..config
+                }
+            )
+            .ready
+        );
+    }
+}
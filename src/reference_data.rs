@@ -0,0 +1,148 @@
+use crate::config::ConfigError;
+use crate::vm_config::Drive;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Kernel command-line parameter listing the reference drives as `name:device,...`; the guest
+/// agent mounts each at `/opt/reference/<name>`
+pub const REFERENCE_BOOT_ARG: &str = "fc_refdata";
+
+/// Most reference drives attached to a VM, keeping their guest devices within `/dev/vdz`
+pub const MAX_REFERENCE_DRIVES: usize = 16;
+
+/// Parse `NAME=PATH,...` into reference drives; malformed entries are skipped with a warning
+pub fn parse(raw: &str) -> BTreeMap<String, PathBuf> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                Some((name.trim().to_string(), PathBuf::from(path.trim())))
+            }
+            _ => {
+                tracing::warn!("Ignoring malformed reference drive {:?}", entry);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Whether `name` can appear in a drive ID, a boot argument and a guest directory name
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Check every reference image exists and is named usably, so a mistake fails at startup
+/// rather than at boot
+pub fn preflight(drives: &BTreeMap<String, PathBuf>) -> Result<(), ConfigError> {
+    if drives.len() > MAX_REFERENCE_DRIVES {
+        return Err(ConfigError::Invalid(format!(
+            "{} reference drives configured, at most {MAX_REFERENCE_DRIVES} are supported",
+            drives.len()
+        )));
+    }
+    for (name, path) in drives {
+        if !valid_name(name) {
+            return Err(ConfigError::Invalid(format!(
+                "reference drive '{name}': names may only hold letters, digits, '-' and '_'"
+            )));
+        }
+        if !path.is_file() {
+            return Err(ConfigError::Invalid(format!(
+                "reference drive '{name}': {} is not a file",
+                path.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Firecracker drive ID of reference drive `name`
+pub fn drive_id(name: &str) -> String {
+    format!("ref-{name}")
+}
+
+/// A reference drive; every VM shares its backing file, so it must stay read-only
+pub fn drive_config(name: &str, path_on_host: &str) -> Drive {
+    Drive {
+        drive_id: drive_id(name),
+        path_on_host: path_on_host.to_string(),
+        is_root_device: false,
+        is_read_only: true,
+    }
+}
+
+/// Guest device of the block device attached at `index`, counting the rootfs as 0
+fn guest_device(index: usize) -> String {
+    format!("/dev/vd{}", char::from(b'a' + index as u8))
+}
+
+/// Kernel boot argument announcing reference drives `names`, attached in this order after the
+/// rootfs and, when `deps_attached`, the dependencies drive
+pub fn boot_arg<'a>(names: impl IntoIterator<Item = &'a str>, deps_attached: bool) -> String {
+    let first = if deps_attached { 2 } else { 1 };
+    let manifest: Vec<_> = names
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| format!("{name}:{}", guest_device(first + i)))
+        .collect();
+    format!("{REFERENCE_BOOT_ARG}={}", manifest.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_preflight() {
+        let drives = parse("geo=/srv/ref/geo.squashfs, tables = /srv/ref/t.ext4,broken,=x");
+        assert_eq!(drives.len(), 2);
+        assert_eq!(drives["tables"], PathBuf::from("/srv/ref/t.ext4"));
+
+        let dir = std::env::temp_dir().join(format!("ref-preflight-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("geo.squashfs");
+        std::fs::write(&image, b"").unwrap();
+
+        let mut drives = BTreeMap::from([("geo".to_string(), image.clone())]);
+        assert!(preflight(&drives).is_ok());
+        drives.insert("geo lookup".to_string(), image);
+        let err = preflight(&drives).unwrap_err();
+        assert!(err.to_string().contains("'geo lookup'"), "{err}");
+        drives.remove("geo lookup");
+        drives.insert("missing".to_string(), dir.join("missing.ext4"));
+        let err = preflight(&drives).unwrap_err();
+        assert!(err.to_string().contains("'missing'"), "{err}");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_drive_config_json() {
+        let drive = drive_config("geo", "/srv/ref/geo.squashfs");
+        assert_eq!(
+            serde_json::to_value(&drive).unwrap(),
+            serde_json::json!({
+                "drive_id": "ref-geo",
+                "path_on_host": "/srv/ref/geo.squashfs",
+                "is_root_device": false,
+                "is_read_only": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_boot_arg_manifest_follows_attachment_order() {
+        assert_eq!(
+            boot_arg(["geo", "tables"], false),
+            "fc_refdata=geo:/dev/vdb,tables:/dev/vdc"
+        );
+        assert_eq!(boot_arg(["geo"], true), "fc_refdata=geo:/dev/vdc");
+        let names: Vec<_> = (0..MAX_REFERENCE_DRIVES).map(|i| format!("d{i}")).collect();
+        let arg = boot_arg(names.iter().map(String::as_str), true);
+        assert!(arg.ends_with("d15:/dev/vdr"), "{arg}");
+    }
+}
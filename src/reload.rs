@@ -0,0 +1,386 @@
+use crate::config::{Config, ConfigError, RunnerConfig, parse_rate_limit_overrides};
+use crate::executor::{ExecutorService, PoolTunables};
+use crate::rate_limit::{RateLimit, RateLimiter, parse_rate_limit};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use utoipa::ToSchema;
+
+/// Variables a reload re-reads; everything else is fixed at boot
+pub const TUNABLE_VARS: [&str; 5] = [
+    "FC_POOL_SIZE",
+    "FC_POOL_IDLE_TTL_SECS",
+    "FC_RATE_LIMIT",
+    "FC_RATE_LIMIT_OVERRIDES",
+    "FC_MAX_OUTPUT_BYTES",
+];
+
+/// Settings that change without a restart, on `SIGHUP` or `POST /admin/reload`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tunables {
+    pub pool: PoolTunables,
+    /// Default per-client limit on `/execute`; `None` disables rate limiting
+    pub rate_limit: Option<RateLimit>,
+    /// Per-client limits keyed by API key identifier
+    pub rate_limit_overrides: HashMap<String, RateLimit>,
+}
+
+impl Tunables {
+    /// The values the server booted with
+    pub fn from_config(config: &Config, runner: &RunnerConfig) -> Self {
+        Self {
+            pool: PoolTunables::from_config(runner),
+            rate_limit: config.rate_limit,
+            rate_limit_overrides: config.rate_limit_overrides.clone(),
+        }
+    }
+
+    /// Read the tunables from `lookup`, using the defaults for variables it lacks. Every
+    /// problem is collected, and any one of them rejects the lot.
+    pub fn parse(
+        lookup: impl Fn(&str) -> Option<String>,
+        runner: &RunnerConfig,
+    ) -> Result<Self, Vec<String>> {
+        let default = RunnerConfig::default();
+        let mut errors = Vec::new();
+        let mut number = |name: &str, fallback: u64| match lookup(name) {
+            Some(raw) => raw.trim().parse::<u64>().unwrap_or_else(|_| {
+                errors.push(format!("{name} must be a whole number, got {raw:?}"));
+                fallback
+            }),
+            None => fallback,
+        };
+        let pool_size = number("FC_POOL_SIZE", default.pool_size as u64) as usize;
+        let idle_ttl = Duration::from_secs(number(
+            "FC_POOL_IDLE_TTL_SECS",
+            default.autoscale.idle_ttl.as_secs(),
+        ));
+        let max_output_bytes =
+            number("FC_MAX_OUTPUT_BYTES", default.max_output_bytes as u64) as usize;
+
+        if max_output_bytes == 0 {
+            errors.push("FC_MAX_OUTPUT_BYTES must be above 0".to_string());
+        }
+        if let Some(max_vms) = runner.max_vms
+            && pool_size > max_vms
+        {
+            errors.push(format!(
+                "FC_POOL_SIZE of {pool_size} exceeds FC_MAX_VMS of {max_vms}"
+            ));
+        }
+        let rate_limit = match lookup("FC_RATE_LIMIT") {
+            Some(raw) => parse_rate_limit(&raw).or_else(|| {
+                errors.push(format!("FC_RATE_LIMIT must be RATE[:BURST], got {raw:?}"));
+                None
+            }),
+            None => None,
+        };
+        let rate_limit_overrides = match lookup("FC_RATE_LIMIT_OVERRIDES") {
+            Some(raw) => parse_rate_limit_overrides(&raw).unwrap_or_else(|e| {
+                errors.push(match e {
+                    ConfigError::Invalid(message) => message,
+                    e => e.to_string(),
+                });
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self {
+            pool: PoolTunables {
+                pool_size,
+                idle_ttl,
+                max_output_bytes,
+            },
+            rate_limit,
+            rate_limit_overrides,
+        })
+    }
+
+    /// Variables whose values differ between `self` and `other`
+    fn changed(&self, other: &Self) -> Vec<String> {
+        [
+            ("FC_POOL_SIZE", self.pool.pool_size != other.pool.pool_size),
+            (
+                "FC_POOL_IDLE_TTL_SECS",
+                self.pool.idle_ttl != other.pool.idle_ttl,
+            ),
+            ("FC_RATE_LIMIT", self.rate_limit != other.rate_limit),
+            (
+                "FC_RATE_LIMIT_OVERRIDES",
+                self.rate_limit_overrides != other.rate_limit_overrides,
+            ),
+            (
+                "FC_MAX_OUTPUT_BYTES",
+                self.pool.max_output_bytes != other.pool.max_output_bytes,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name.to_string())
+        .collect()
+    }
+}
+
+/// Parse a `KEY=VALUE` tunables file, skipping blanks and `#` comments. Keys other than
+/// `TUNABLE_VARS` are refused, since a reload couldn't apply them.
+pub fn parse_tunables_file(contents: &str) -> Result<HashMap<String, String>, Vec<String>> {
+    let mut values = HashMap::new();
+    let mut errors = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(format!(
+                "line {}: expected KEY=VALUE, got {line:?}",
+                number + 1
+            ));
+            continue;
+        };
+        let key = key.trim();
+        if !TUNABLE_VARS.contains(&key) {
+            errors.push(format!(
+                "line {}: {key} can't be changed without a restart",
+                number + 1
+            ));
+            continue;
+        }
+        let value = value.trim();
+        let unquoted = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+        values.insert(key.to_string(), unquoted.to_string());
+    }
+    if errors.is_empty() {
+        Ok(values)
+    } else {
+        Err(errors)
+    }
+}
+
+/// What a reload did, as logged and returned by `POST /admin/reload`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ReloadOutcome {
+    /// Whether the new values took effect; when not, the old ones stay
+    pub applied: bool,
+    /// Variables whose value changed
+    pub changed: Vec<String>,
+    /// Why the new values were rejected
+    pub errors: Vec<String>,
+}
+
+/// Holds the tunables in effect and re-reads them on request
+pub struct Reloader {
+    tunables: watch::Sender<Tunables>,
+    runner: Arc<RunnerConfig>,
+    file: Option<PathBuf>,
+}
+
+impl Reloader {
+    /// A reloader starting at `initial`, re-reading `file` over the environment
+    pub fn new(initial: Tunables, runner: Arc<RunnerConfig>, file: Option<PathBuf>) -> Self {
+        Self {
+            tunables: watch::Sender::new(initial),
+            runner,
+            file,
+        }
+    }
+
+    /// Receiver seeing every tunables change from now on
+    pub fn subscribe(&self) -> watch::Receiver<Tunables> {
+        self.tunables.subscribe()
+    }
+
+    pub fn current(&self) -> Tunables {
+        self.tunables.borrow().clone()
+    }
+
+    /// Re-read the environment and the tunables file, which takes precedence, swapping in
+    /// the new values only if all of them are valid
+    pub fn reload(&self) -> ReloadOutcome {
+        let outcome = match self.read() {
+            Ok(tunables) => self.apply(tunables),
+            Err(errors) => ReloadOutcome {
+                applied: false,
+                changed: Vec::new(),
+                errors,
+            },
+        };
+        if outcome.applied {
+            tracing::info!(changed = ?outcome.changed, "Reloaded tunables");
+        } else {
+            tracing::error!(errors = ?outcome.errors, "Rejected reloaded tunables; keeping the old ones");
+        }
+        outcome
+    }
+
+    fn read(&self) -> Result<Tunables, Vec<String>> {
+        let from_file = match &self.file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| vec![format!("cannot read {}: {e}", path.display())])?;
+                parse_tunables_file(&contents)?
+            }
+            None => HashMap::new(),
+        };
+        Tunables::parse(
+            |name| {
+                from_file
+                    .get(name)
+                    .cloned()
+                    .or_else(|| std::env::var(name).ok())
+            },
+            &self.runner,
+        )
+    }
+
+    /// Swap in `tunables`, which were validated already
+    pub fn apply(&self, tunables: Tunables) -> ReloadOutcome {
+        let changed = self.tunables.borrow().changed(&tunables);
+        self.tunables.send_replace(tunables);
+        ReloadOutcome {
+            applied: true,
+            changed,
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// Push every change of `tunables` into the executor and the rate limiter
+pub fn spawn_apply(
+    mut tunables: watch::Receiver<Tunables>,
+    executor: ExecutorService,
+    limiter: Arc<RateLimiter>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while tunables.changed().await.is_ok() {
+            let current = tunables.borrow_and_update().clone();
+            limiter.set_limits(current.rate_limit, current.rate_limit_overrides);
+            executor.apply_tunables(current.pool).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner() -> Arc<RunnerConfig> {
+        Arc::new(RunnerConfig {
+            backend: crate::backend::BackendKind::Mock,
+            max_vms: Some(8),
+            ..Default::default()
+        })
+    }
+
+    fn tunables_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("fc-tunables-{name}-{}.env", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_parse_tunables() {
+        let values = parse_tunables_file(
+            "# pool\nFC_POOL_SIZE=4\n\nFC_RATE_LIMIT=\"120:10\"\nFC_MAX_OUTPUT_BYTES = 4096\n",
+        )
+        .unwrap();
+        let tunables = Tunables::parse(|name| values.get(name).cloned(), &runner()).unwrap();
+        assert_eq!(tunables.pool.pool_size, 4);
+        assert_eq!(tunables.pool.max_output_bytes, 4096);
+        assert_eq!(
+            tunables.rate_limit,
+            Some(RateLimit {
+                per_minute: 120,
+                burst: 10
+            })
+        );
+        // Missing variables fall back to the defaults
+        assert_eq!(
+            tunables.pool.idle_ttl,
+            RunnerConfig::default().autoscale.idle_ttl
+        );
+
+        let errors =
+            parse_tunables_file("FC_POOL_SIZE=2\nFC_LISTEN=unix:///tmp/x\noops\n").unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "line 2: FC_LISTEN can't be changed without a restart",
+                "line 3: expected KEY=VALUE, got \"oops\""
+            ]
+        );
+
+        let values = HashMap::from([
+            ("FC_POOL_SIZE", "12"),
+            ("FC_RATE_LIMIT", "fast"),
+            ("FC_MAX_OUTPUT_BYTES", "0"),
+        ]);
+        let errors = Tunables::parse(
+            |name| values.get(name).map(|value| value.to_string()),
+            &runner(),
+        )
+        .unwrap_err();
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors.iter().any(|e| e.contains("FC_MAX_VMS of 8")));
+    }
+
+    #[tokio::test]
+    async fn test_reload_resizes_the_pool_and_rejects_bad_values_wholesale() {
+        let runner = runner();
+        let executor = ExecutorService::new(runner.clone());
+        let limiter = Arc::new(RateLimiter::new(None, HashMap::new()));
+        let initial = Tunables::from_config(&Config::default(), &runner);
+        let path = tunables_file("resize", "FC_POOL_SIZE=5\nFC_RATE_LIMIT=60:5\n");
+        let reloader = Reloader::new(initial, runner, Some(path.clone()));
+        let applier = spawn_apply(reloader.subscribe(), executor.clone(), limiter.clone());
+
+        let outcome = reloader.reload();
+        assert!(outcome.applied, "{outcome:?}");
+        assert_eq!(outcome.changed, ["FC_POOL_SIZE", "FC_RATE_LIMIT"]);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while executor.stats().await.idle_vms < 5 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the pool grows to the new size");
+        assert_eq!(executor.pool_target(), 5);
+        assert!(limiter.is_enabled());
+
+        // One bad value keeps every old one
+        std::fs::write(&path, "FC_POOL_SIZE=1\nFC_MAX_OUTPUT_BYTES=lots\n").unwrap();
+        let outcome = reloader.reload();
+        assert!(!outcome.applied);
+        assert_eq!(
+            outcome.errors,
+            ["FC_MAX_OUTPUT_BYTES must be a whole number, got \"lots\""]
+        );
+        assert_eq!(reloader.current().pool.pool_size, 5);
+
+        // A smaller pool size shuts down the surplus
+        std::fs::write(&path, "FC_POOL_SIZE=1\n").unwrap();
+        assert!(reloader.reload().applied);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while executor.stats().await.idle_vms > 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the pool shrinks to the new size");
+        assert!(!limiter.is_enabled());
+
+        applier.abort();
+        executor.shutdown().await;
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,465 @@
+use crate::runner::VMManager;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Which side of the runner an interaction was with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    /// Firecracker's API on the VM's Unix socket
+    Firecracker,
+    /// The guest agent's HTTP server
+    Agent,
+}
+
+/// One request the runner made and the response it got, as stored in a session file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub target: Target,
+    pub method: String,
+    pub path: String,
+    /// JSON request body; bodies that aren't JSON are kept as a string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<serde_json::Value>,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub response: String,
+}
+
+impl Interaction {
+    pub fn new(target: Target, method: &str, path: &str, body: Option<&str>, status: u16) -> Self {
+        Self {
+            target,
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.map(|body| {
+                serde_json::from_str(body)
+                    .unwrap_or_else(|_| serde_json::Value::String(body.to_string()))
+            }),
+            status,
+            response: String::new(),
+        }
+    }
+
+    pub fn with_response(mut self, response: impl Into<String>) -> Self {
+        self.response = response.into();
+        self
+    }
+
+    /// `METHOD /path` plus the pretty-printed body, the form mismatches are diffed in
+    fn describe(&self) -> String {
+        let target = match self.target {
+            Target::Firecracker => "firecracker",
+            Target::Agent => "agent",
+        };
+        let mut text = format!("{target} {} {}", self.method, self.path);
+        if let Some(body) = &self.body {
+            text.push('\n');
+            text.push_str(&serde_json::to_string_pretty(body).unwrap_or_default());
+        }
+        text
+    }
+
+    fn same_request(&self, other: &Interaction) -> bool {
+        self.target == other.target
+            && self.method == other.method
+            && self.path == other.path
+            && self.body == other.body
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("Failed to access {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid interaction on line {line} of {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        source: serde_json::Error,
+    },
+    /// The runner's requests strayed from the session
+    #[error("Replay diverged from the session:\n{0}")]
+    Diverged(String),
+}
+
+/// Interactions of one VM, stored one JSON object per line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Session {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ReplayError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let interactions = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|source| ReplayError::Parse {
+                    path: path.to_path_buf(),
+                    line: i + 1,
+                    source,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { interactions })
+    }
+}
+
+/// Append `interaction` to the session file at `path`
+pub fn append(path: &Path, interaction: &Interaction) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut line = serde_json::to_string(interaction).map_err(std::io::Error::other)?;
+    line.push('\n');
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Values that differ between VMs (ID, addresses, TAP device), swapped for `{vm_id}`-style
+/// tokens in recordings so a session replays against any VM
+#[derive(Debug, Clone)]
+pub struct Placeholders {
+    /// Value and token, longest values first so no token replaces part of another's value
+    pairs: Vec<(String, &'static str)>,
+}
+
+impl Placeholders {
+    pub fn for_vm(vm: &VMManager) -> Self {
+        let mut pairs = vec![
+            (vm.vm_id().to_string(), "{vm_id}"),
+            (vm.tap_interface().to_string(), "{tap}"),
+            (vm.vm_ip().to_string(), "{vm_ip}"),
+            (vm.host_ip(), "{host_ip}"),
+        ];
+        pairs.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+        Self { pairs }
+    }
+
+    /// `interaction` with this VM's values replaced by tokens
+    pub fn abstract_interaction(&self, mut interaction: Interaction) -> Interaction {
+        let replace = |text: &str| {
+            self.pairs
+                .iter()
+                .fold(text.to_string(), |text, (value, token)| {
+                    text.replace(value.as_str(), token)
+                })
+        };
+        interaction.path = replace(&interaction.path);
+        interaction.body = interaction.body.map(|body| map_strings(body, &replace));
+        interaction.response = replace(&interaction.response);
+        interaction
+    }
+
+    /// `interaction` with tokens replaced by this VM's values
+    pub fn expand_interaction(&self, mut interaction: Interaction) -> Interaction {
+        let replace = |text: &str| {
+            self.pairs
+                .iter()
+                .fold(text.to_string(), |text, (value, token)| {
+                    text.replace(token, value)
+                })
+        };
+        interaction.path = replace(&interaction.path);
+        interaction.body = interaction.body.map(|body| map_strings(body, &replace));
+        interaction.response = replace(&interaction.response);
+        interaction
+    }
+}
+
+fn map_strings(value: serde_json::Value, f: &impl Fn(&str) -> String) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        Value::String(s) => Value::String(f(&s)),
+        Value::Array(items) => Value::Array(items.into_iter().map(|v| map_strings(v, f)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k, map_strings(v, f)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+/// Line diff of `expected` against `actual`: common lines indented, removed ones marked `-`
+/// and added ones `+`
+pub fn diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+    // Longest common subsequence lengths of every pair of suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    out.join("\n")
+}
+
+#[derive(Debug, Default)]
+struct ReplayState {
+    expected: VecDeque<Interaction>,
+    served: usize,
+    mismatches: Vec<String>,
+}
+
+impl ReplayState {
+    /// The recorded answer to `actual`, or a 400 carrying the diff when it isn't the request
+    /// the session expects next
+    fn answer(&mut self, actual: Interaction) -> (u16, String) {
+        self.served += 1;
+        let message = match self.expected.pop_front() {
+            Some(expected) if expected.same_request(&actual) => {
+                return (expected.status, expected.response);
+            }
+            Some(expected) => format!(
+                "request {} differs from the session (- expected, + actual):\n{}",
+                self.served,
+                diff(&expected.describe(), &actual.describe())
+            ),
+            None => format!(
+                "request {} is past the end of the session:\n{}",
+                self.served,
+                actual.describe()
+            ),
+        };
+        self.mismatches.push(message.clone());
+        (400, message)
+    }
+}
+
+/// Stand-in for Firecracker and the guest agent answering from a recorded session, so the
+/// runner's API sequencing can be tested without a hypervisor
+pub struct ReplayServer {
+    socket_path: PathBuf,
+    agent_url: String,
+    state: Arc<Mutex<ReplayState>>,
+    tasks: tokio::task::JoinSet<()>,
+}
+
+impl ReplayServer {
+    /// Serve `session`, with its tokens filled in by `placeholders`, as Firecracker on
+    /// `socket_path` and the guest agent on an ephemeral localhost port
+    pub async fn start(
+        session: &Session,
+        socket_path: &Path,
+        placeholders: &Placeholders,
+    ) -> std::io::Result<Self> {
+        let _ = std::fs::remove_file(socket_path);
+        let firecracker = tokio::net::UnixListener::bind(socket_path)?;
+        let agent = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let agent_url = format!("http://{}", agent.local_addr()?);
+        let state = Arc::new(Mutex::new(ReplayState {
+            expected: session
+                .interactions
+                .iter()
+                .cloned()
+                .map(|interaction| placeholders.expand_interaction(interaction))
+                .collect(),
+            ..Default::default()
+        }));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        let fc_state = state.clone();
+        tasks.spawn(async move {
+            while let Ok((stream, _)) = firecracker.accept().await {
+                tokio::spawn(serve(
+                    TokioIo::new(stream),
+                    Target::Firecracker,
+                    fc_state.clone(),
+                ));
+            }
+        });
+        let agent_state = state.clone();
+        tasks.spawn(async move {
+            while let Ok((stream, _)) = agent.accept().await {
+                tokio::spawn(serve(
+                    TokioIo::new(stream),
+                    Target::Agent,
+                    agent_state.clone(),
+                ));
+            }
+        });
+        Ok(Self {
+            socket_path: socket_path.to_path_buf(),
+            agent_url,
+            state,
+            tasks,
+        })
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Base URL the guest agent is served on
+    pub fn agent_url(&self) -> &str {
+        &self.agent_url
+    }
+
+    /// Stop serving; fails with every mismatch, and the interactions never requested, when the
+    /// runner didn't follow the session exactly
+    pub fn finish(mut self) -> Result<(), ReplayError> {
+        self.tasks.abort_all();
+        let _ = std::fs::remove_file(&self.socket_path);
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut problems = state.mismatches.clone();
+        if !state.expected.is_empty() {
+            let unserved: Vec<String> = state
+                .expected
+                .iter()
+                .map(|interaction| format!("  {} {}", interaction.method, interaction.path))
+                .collect();
+            problems.push(format!(
+                "{} recorded requests were never made:\n{}",
+                unserved.len(),
+                unserved.join("\n")
+            ));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ReplayError::Diverged(problems.join("\n\n")))
+        }
+    }
+}
+
+async fn serve<I>(io: I, target: Target, state: Arc<Mutex<ReplayState>>)
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |request: Request<Incoming>| {
+        let state = state.clone();
+        async move {
+            let method = request.method().to_string();
+            let path = request.uri().path().to_string();
+            let body = request.into_body().collect().await?.to_bytes();
+            let body = String::from_utf8_lossy(&body);
+            let actual = Interaction::new(
+                target,
+                &method,
+                &path,
+                (!body.is_empty()).then_some(body.as_ref()),
+                0,
+            );
+            let (status, response) = state
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .answer(actual);
+            Ok::<_, hyper::Error>(
+                Response::builder()
+                    .status(StatusCode::from_u16(status).unwrap_or(StatusCode::OK))
+                    .header("content-type", "application/json")
+                    .body(Full::new(Bytes::from(response)))
+                    .unwrap_or_default(),
+            )
+        }
+    });
+    let _ = hyper::server::conn::http1::Builder::new()
+        .serve_connection(io, service)
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_marks_changed_lines() {
+        let expected = "PUT /machine-config\n{\n  \"mem_size_mib\": 128,\n  \"vcpu_count\": 1\n}";
+        let actual = "PUT /machine-config\n{\n  \"mem_size_mib\": 256,\n  \"vcpu_count\": 1\n}";
+        assert_eq!(
+            diff(expected, actual),
+            "  PUT /machine-config\n  {\n-   \"mem_size_mib\": 128,\n+   \"mem_size_mib\": 256,\n    \"vcpu_count\": 1\n  }"
+        );
+        assert_eq!(diff("a", "a"), "  a");
+    }
+
+    #[test]
+    fn test_placeholders_round_trip() {
+        let vm = VMManager::with_config(Arc::new(crate::config::RunnerConfig::default()));
+        let placeholders = Placeholders::for_vm(&vm);
+        let body = format!(
+            r#"{{"log_path": "/tmp/fc-log-{}.log", "host_dev_name": "{}"}}"#,
+            vm.vm_id(),
+            vm.tap_interface()
+        );
+        let recorded = Interaction::new(Target::Firecracker, "PUT", "/logger", Some(&body), 204);
+
+        let abstracted = placeholders.abstract_interaction(recorded.clone());
+        assert_eq!(
+            abstracted.body,
+            Some(serde_json::json!({
+                "log_path": "/tmp/fc-log-{vm_id}.log",
+                "host_dev_name": "{tap}"
+            }))
+        );
+        assert_eq!(placeholders.expand_interaction(abstracted), recorded);
+    }
+
+    #[test]
+    fn test_answers_in_session_order() {
+        let session = [
+            Interaction::new(Target::Firecracker, "GET", "/version", None, 200)
+                .with_response(r#"{"firecracker_version":"1.10.1"}"#),
+            Interaction::new(Target::Firecracker, "PUT", "/actions", Some("{}"), 204),
+        ];
+        let mut state = ReplayState {
+            expected: session.iter().cloned().collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            state.answer(session[0].clone()),
+            (200, r#"{"firecracker_version":"1.10.1"}"#.to_string())
+        );
+        let (status, message) = state.answer(Interaction::new(
+            Target::Firecracker,
+            "PUT",
+            "/actions",
+            Some(r#"{"action_type":"InstanceStart"}"#),
+            0,
+        ));
+        assert_eq!(status, 400);
+        assert!(message.starts_with("request 2 differs"), "{message}");
+        assert!(
+            message.contains("+ {\n+   \"action_type\": \"InstanceStart\"\n+ }"),
+            "{message}"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+/// Image written when `--output` is not given; the default `FC_ROOTFS_X86_64`
+pub const DEFAULT_OUTPUT: &str = "alpine-python-api.ext4";
+
+/// Free space left in the image on top of its content
+pub const DEFAULT_HEADROOM_MIB: u64 = 64;
+
+/// Guest agent copied from the agent directory into the image
+pub const AGENT_FILE: &str = "vm_api_server.py";
+
+/// Where the agent and the init live inside the image; `init=` in the boot arguments points here
+const GUEST_BIN_DIR: &str = "usr/local/bin";
+
+/// Init of the guest: bring up the network, start the agent and keep PID 1 alive
+const STARTUP_SCRIPT: &str = r#"#!/bin/sh
+# Wait for network to be ready
+sleep 2
+
+# Configure network interface (IP should be configured by kernel cmdline, but ensure it's up)
+ip link set eth0 up
+
+# Find python and start the API server
+PYTHON_CMD=""
+if command -v python3 > /dev/null 2>&1; then
+    PYTHON_CMD="python3"
+elif command -v python > /dev/null 2>&1; then
+    PYTHON_CMD="python"
+else
+    echo "ERROR: Python not found!" > /dev/console
+    exit 1
+fi
+
+echo "Starting VM API server with: $PYTHON_CMD" > /dev/console
+$PYTHON_CMD /usr/local/bin/vm_api_server.py &
+echo "VM API server started (PID: $!)" > /dev/console
+
+# Keep the system running
+tail -f /dev/null
+"#;
+
+/// Paths of the base rootfs that must provide a Python interpreter
+const PYTHON_CANDIDATES: &[&str] = &["usr/bin/python3", "usr/local/bin/python3"];
+
+#[derive(Debug, Error)]
+pub enum RootfsError {
+    #[error("{0} is required to build the rootfs but was not found on PATH")]
+    MissingTool(&'static str),
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{tool} failed ({status}): {stderr}")]
+    Command {
+        tool: &'static str,
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("archive entry {0:?} would be written outside the rootfs")]
+    UnsafeEntry(String),
+}
+
+pub(crate) fn io(path: &Path) -> impl FnOnce(std::io::Error) -> RootfsError + '_ {
+    move |source| RootfsError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// What `build-rootfs` assembles
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootfsSpec {
+    /// Alpine minirootfs tarball or `docker export` of a Python image, optionally compressed
+    pub base: PathBuf,
+    /// Directory holding the guest agent sources
+    pub agent_dir: PathBuf,
+    pub output: PathBuf,
+    pub headroom_mib: u64,
+}
+
+impl RootfsSpec {
+    /// Parse `--base TAR [--agent-dir DIR] [--output FILE] [--headroom-mib N]`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, RootfsError> {
+        let mut base = None;
+        let mut agent_dir = PathBuf::from(".");
+        let mut output = PathBuf::from(DEFAULT_OUTPUT);
+        let mut headroom_mib = DEFAULT_HEADROOM_MIB;
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| RootfsError::InvalidInput(format!("{flag} needs a value")))
+            };
+            match flag.as_str() {
+                "--base" => base = Some(PathBuf::from(value()?)),
+                "--agent-dir" => agent_dir = PathBuf::from(value()?),
+                "--output" => output = PathBuf::from(value()?),
+                "--headroom-mib" => {
+                    let raw = value()?;
+                    headroom_mib = raw.parse().map_err(|_| {
+                        RootfsError::InvalidInput(format!("--headroom-mib: invalid value {raw:?}"))
+                    })?;
+                }
+                other => {
+                    return Err(RootfsError::InvalidInput(format!(
+                        "unknown argument {other:?}; usage: build-rootfs --base TAR \
+                         [--agent-dir DIR] [--output FILE] [--headroom-mib N]"
+                    )));
+                }
+            }
+        }
+        Ok(Self {
+            base: base
+                .ok_or_else(|| RootfsError::InvalidInput("--base is required".to_string()))?,
+            agent_dir,
+            output,
+            headroom_mib,
+        })
+    }
+}
+
+/// A built image
+#[derive(Debug, Clone, PartialEq)]
+pub struct RootfsImage {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+impl fmt::Display for RootfsImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\n  size:   {} MiB ({} bytes)\n  sha256: {}",
+            self.path.display(),
+            self.size_bytes / (1024 * 1024),
+            self.size_bytes,
+            self.sha256
+        )
+    }
+}
+
+/// Assemble the ext4 image described by `spec` without mounting anything: the base is
+/// unpacked into a staging directory, the agent and init are added, and `mkfs.ext4 -d`
+/// writes the tree into a sparse file. Unprivileged builds get their files handed to root
+/// with `debugfs`, since the guest runs the agent as root.
+pub fn build(spec: &RootfsSpec) -> Result<RootfsImage, RootfsError> {
+    require_tool("tar")?;
+    build_with(
+        &spec.base,
+        &spec.agent_dir,
+        &spec.output,
+        spec.headroom_mib,
+        |staging, privileged| {
+            // Device nodes of a docker export can't be created unprivileged; devtmpfs
+            // replaces them
+            let mut tar = Command::new("tar");
+            tar.arg("-xf")
+                .arg(&spec.base)
+                .arg("-C")
+                .arg(staging)
+                .args(["--exclude=./dev/*", "--exclude=dev/*"]);
+            if !privileged {
+                tar.arg("--no-same-owner");
+            }
+            run("tar", &mut tar)
+        },
+    )
+}
+
+/// Build the image at `output` from the tree `populate` writes into a staging directory,
+/// adding the agent from `agent_dir` and the init. `populate` is told whether the build
+/// runs as root; `source` names the input in errors.
+pub(crate) fn build_with(
+    source: &Path,
+    agent_dir: &Path,
+    output: &Path,
+    headroom_mib: u64,
+    populate: impl FnOnce(&Path, bool) -> Result<(), RootfsError>,
+) -> Result<RootfsImage, RootfsError> {
+    let privileged = is_root();
+    require_tool("mkfs.ext4")?;
+    if !privileged {
+        require_tool("debugfs")?;
+    }
+    let agent = agent_dir.join(AGENT_FILE);
+    if !agent.is_file() {
+        return Err(RootfsError::InvalidInput(format!(
+            "{} not found; --agent-dir must hold the guest agent",
+            agent.display()
+        )));
+    }
+
+    let staging = output.with_extension("staging");
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(io(&staging))?;
+    }
+    fs::create_dir_all(&staging).map_err(io(&staging))?;
+    let result = populate(&staging, privileged)
+        .and_then(|()| assemble(source, &agent, &staging, output, headroom_mib, privileged));
+    let _ = fs::remove_dir_all(&staging);
+    result?;
+
+    Ok(RootfsImage {
+        size_bytes: fs::metadata(output).map_err(io(output))?.len(),
+        sha256: sha256_file(output)?,
+        path: output.to_path_buf(),
+    })
+}
+
+fn assemble(
+    source: &Path,
+    agent: &Path,
+    staging: &Path,
+    output: &Path,
+    headroom_mib: u64,
+    privileged: bool,
+) -> Result<(), RootfsError> {
+    // The interpreter is often an absolute symlink that only resolves inside the guest
+    if !PYTHON_CANDIDATES
+        .iter()
+        .any(|candidate| fs::symlink_metadata(staging.join(candidate)).is_ok())
+    {
+        return Err(RootfsError::InvalidInput(format!(
+            "{} has no python3; use a base with Python installed, e.g. \
+             `docker export $(docker create python:3-alpine)`",
+            source.display()
+        )));
+    }
+
+    let bin_dir = staging.join(GUEST_BIN_DIR);
+    fs::create_dir_all(&bin_dir).map_err(io(&bin_dir))?;
+    let installed_agent = bin_dir.join(AGENT_FILE);
+    fs::copy(agent, &installed_agent).map_err(io(&installed_agent))?;
+    set_mode(&installed_agent, 0o755)?;
+    let startup = bin_dir.join("startup.sh");
+    fs::write(&startup, STARTUP_SCRIPT).map_err(io(&startup))?;
+    set_mode(&startup, 0o755)?;
+
+    let content_bytes = tree_size(staging)?;
+    let size_bytes = (content_bytes + content_bytes / 5).div_ceil(1024 * 1024) * 1024 * 1024
+        + headroom_mib * 1024 * 1024;
+    // A sparse file: only the blocks mkfs writes take up disk space
+    let image = fs::File::create(output).map_err(io(output))?;
+    image.set_len(size_bytes).map_err(io(output))?;
+    drop(image);
+    run(
+        "mkfs.ext4",
+        Command::new("mkfs.ext4")
+            .args(["-F", "-q", "-L", "rootfs", "-d"])
+            .arg(staging)
+            .arg(output),
+    )?;
+    if !privileged {
+        chown_to_root(staging, output)?;
+    }
+    Ok(())
+}
+
+/// Give every inode copied from `staging` to root:root inside `image`
+fn chown_to_root(staging: &Path, image: &Path) -> Result<(), RootfsError> {
+    let mut script = String::from("sif / uid 0\nsif / gid 0\n");
+    let mut pending = vec![staging.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).map_err(io(&dir))? {
+            let path = entry.map_err(io(&dir))?.path();
+            let metadata = fs::symlink_metadata(&path).map_err(io(&path))?;
+            if metadata.uid() != 0 || metadata.gid() != 0 {
+                let guest = Path::new("/").join(path.strip_prefix(staging).unwrap_or(&path));
+                let guest = guest.display();
+                script.push_str(&format!("sif \"{guest}\" uid 0\nsif \"{guest}\" gid 0\n"));
+            }
+            if metadata.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+    let script_path = image.with_extension("debugfs");
+    fs::write(&script_path, script).map_err(io(&script_path))?;
+    let result = run(
+        "debugfs",
+        Command::new("debugfs")
+            .arg("-w")
+            .arg("-f")
+            .arg(&script_path)
+            .arg(image),
+    );
+    let _ = fs::remove_file(&script_path);
+    result
+}
+
+fn run(tool: &'static str, command: &mut Command) -> Result<(), RootfsError> {
+    let output = command.output().map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => RootfsError::MissingTool(tool),
+        _ => RootfsError::Io {
+            path: PathBuf::from(tool),
+            source: e,
+        },
+    })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(RootfsError::Command {
+        tool,
+        status: output.status,
+        stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+    })
+}
+
+/// Whether `tool` is an executable on `PATH` or in the sbin directories mkfs lives in
+pub fn tool_available(tool: &str) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(["/sbin", "/usr/sbin"].map(PathBuf::from))
+        .map(|dir| dir.join(tool))
+        .any(|candidate| {
+            fs::metadata(&candidate)
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+}
+
+fn require_tool(tool: &'static str) -> Result<(), RootfsError> {
+    if tool_available(tool) {
+        Ok(())
+    } else {
+        Err(RootfsError::MissingTool(tool))
+    }
+}
+
+fn is_root() -> bool {
+    fs::metadata("/proc/self")
+        .map(|m| m.uid() == 0)
+        .unwrap_or(false)
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<(), RootfsError> {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(io(path))
+}
+
+/// Bytes taken by the files under `root`, counting each inode at least one block
+fn tree_size(root: &Path) -> Result<u64, RootfsError> {
+    let mut total = 0;
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).map_err(io(&dir))? {
+            let path = entry.map_err(io(&dir))?.path();
+            let metadata = fs::symlink_metadata(&path).map_err(io(&path))?;
+            total += metadata.len().max(4096);
+            if metadata.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+    Ok(total)
+}
+
+fn sha256_file(path: &Path) -> Result<String, RootfsError> {
+    let mut file = fs::File::open(path).map_err(io(path))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut HashWriter(&mut hasher)).map_err(io(path))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+struct HashWriter<'a>(&'a mut Sha256);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_spec_from_args() {
+        let spec = RootfsSpec::from_args(args(&["--base", "alpine.tar"])).unwrap();
+        assert_eq!(spec.base, PathBuf::from("alpine.tar"));
+        assert_eq!(spec.agent_dir, PathBuf::from("."));
+        assert_eq!(spec.output, PathBuf::from(DEFAULT_OUTPUT));
+        assert_eq!(spec.headroom_mib, DEFAULT_HEADROOM_MIB);
+
+        let spec = RootfsSpec::from_args(args(&[
+            "--base",
+            "py.tar",
+            "--agent-dir",
+            "guest",
+            "--output",
+            "out.ext4",
+            "--headroom-mib",
+            "8",
+        ]))
+        .unwrap();
+        assert_eq!(spec.agent_dir, PathBuf::from("guest"));
+        assert_eq!(spec.output, PathBuf::from("out.ext4"));
+        assert_eq!(spec.headroom_mib, 8);
+
+        for invalid in [
+            &[][..],
+            &["--base"][..],
+            &["--base", "a.tar", "--headroom-mib", "lots"][..],
+            &["--base", "a.tar", "--verbose"][..],
+        ] {
+            assert!(
+                matches!(
+                    RootfsSpec::from_args(args(invalid)),
+                    Err(RootfsError::InvalidInput(_))
+                ),
+                "{invalid:?} should be rejected"
+            );
+        }
+    }
+}
@@ -1,14 +1,58 @@
-use crate::{ExecuteResponse, ExecutionError, generate_vm_id};
+use crate::admission;
+use crate::arch::Arch;
+use crate::backend::{self, VmBackend};
+use crate::balloon::{self, Balloon, BalloonDevice, BalloonUpdate};
+use crate::boot_report::{BOOT_REPORTS, BootLogs, BootReport};
+#[cfg(feature = "chaos")]
+use crate::chaos::{Fault, FaultPoint};
+use crate::clock::{GuestClock, GuestTime, SetTime};
+use crate::config::{RunnerConfig, runner_config, shared_runner_config};
+use crate::deadline::{Deadline, Phase};
+use crate::debug_events::{self, DebugEvent, DebugEventKind};
+use crate::deps;
+use crate::determinism::DeterministicSettings;
+use crate::dispatch::{Priority, QueuePosition};
+use crate::entropy::EntropyDevice;
+use crate::events::{self, DiscardReason, VmEvent};
+use crate::fd_limit::{self, VmFds};
+use crate::guest_client::{self, GuestClient, GuestTransport};
+use crate::guest_protocol::{self, GuestFeature};
+use crate::guest_response::{DecodeError, LiveChunk, LiveDecoder, ResponseDecoder};
+use crate::inputs::{RemoteInput, SetupError};
+use crate::jailer::{JAIL_SOCKET_PATH, Jail};
+use crate::machine::MachineConfig;
+use crate::oom;
+use crate::output::OutputEncoding;
+use crate::privilege;
+use crate::process_usage::{self, ProcessUsage};
+use crate::program::Program;
+use crate::reference_data;
+use crate::replay::{self, Interaction, Target};
+use crate::shaping::{self, TrafficShape};
+use crate::shutdown::{self, ShutdownMethod, ShutdownSteps};
+use crate::tap::{self, CommandRunner, HostCommands, TapRegistry};
+use crate::version::{self, FirecrackerVersion};
+use crate::vm_config::{
+    BootSource, Drive, Logger, Metrics, NetworkInterface, VmConfig, VsockDevice,
+};
+use crate::warmup::WarmupReport;
+use crate::warning::Warning;
+use crate::{
+    ExceptionInfo, ExecuteResponse, ExecutionError, ExecutionUsage, executor, fc_metrics,
+    generate_request_id, generate_vm_id, output,
+};
 use http_body_util::Full;
 use hyper::body::Bytes;
-use hyper::{Method, Request, Uri};
+use hyper::{Method, Request, StatusCode, Uri};
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
 use hyperlocal::UnixConnector;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::time::Duration;
 use tokio::process::Child;
 use tokio::time::timeout;
+use tracing::Instrument;
 
 /// VM Manager for handling Firecracker VM lifecycle with HTTP API
 #[allow(dead_code)]
@@ -18,39 +62,98 @@ pub struct VMManager {
     process: Option<Child>,
     stdout_log_path: String,
     stderr_log_path: String,
+    fc_log_path: String,
+    fc_metrics_path: String,
+    /// File passed to `--config-file` when VMs boot without API configuration
+    config_file_path: String,
+    kernel_image_path: String,
+    rootfs_path: String,
     vm_ip: String,
     tap_interface: String,
+    /// Host end of the VM's vsock device, when the agent is reached over vsock
+    vsock_path: String,
+    /// How the guest agent is reached
+    guest_transport: GuestTransport,
+    /// Chroot the VM runs in when the jailer is enabled
+    jail: Option<Jail>,
+    /// Dependency profile whose image is attached as a second, read-only drive
+    deps_profile: Option<String>,
+    deps_image_path: Option<String>,
+    /// Reference data drives attached after the deps drive, as (name, image path)
+    reference_drives: Vec<(String, String)>,
+    /// Named image booted instead of the default rootfs
+    image: Option<String>,
+    /// Settings passed to the guest when the VM boots for a deterministic run
+    deterministic: Option<DeterministicSettings>,
+    /// Name servers given by the request this VM was booted for, replacing the configured ones
+    dns: Option<Vec<String>>,
+    /// TAP shaping given by the request this VM was booted for, replacing the image's
+    traffic_shape: Option<TrafficShape>,
+    /// Shaping applied to the TAP device, removed again on cleanup
+    applied_shape: Option<TrafficShape>,
+    /// Release of the Firecracker running this VM, deciding which devices are attached
+    firecracker_version: Option<FirecrackerVersion>,
+    /// Settings of the executor that created this VM
+    config: Arc<RunnerConfig>,
+    /// Base URL of the guest agent when it isn't served from the VM, as by the replay server
+    agent_url: Option<String>,
+    /// Protocol version the guest agent reported once it came up; `None` before then, or when
+    /// the agent predates the handshake
+    agent_protocol: Option<u32>,
+    /// When the VM last went back into the pool
+    idle_since: Option<std::time::Instant>,
+    /// When the VM was created, for recycling by age
+    created_at: std::time::Instant,
+    /// Executions the VM has run
+    executions: u64,
+    /// Keep the console and Firecracker logs if the boot fails
+    debug_boot: bool,
+    /// Leave the logs in place on cleanup, as boot diagnostics retained them
+    keep_logs: bool,
+    /// How the warm-up code went, if any ran
+    warmup: Option<WarmupReport>,
+    /// Health probes failed in a row while pooled
+    probe_failures: u32,
+    /// When the health prober last checked the VM, in milliseconds since the epoch
+    last_probe_at: Option<u64>,
+    /// Fault injected into this VM that it can't recover from
+    #[cfg(feature = "chaos")]
+    fault: Option<Fault>,
 }
 
 // Constants
 const VM_BOOT_TIMEOUT_SECONDS: u64 = 15;
 const VM_EXECUTE_TIMEOUT_SECONDS: u64 = 35;
-const VM_POOL_SIZE: usize = 3;
-pub const VM_PREWARM_COUNT: usize = 2;
+// Matches the guest agent's pip install timeout
+const VM_SETUP_TIMEOUT_SECONDS: u64 = 300;
+// Matches the guest agent's timeout for downloading every input
+const VM_INPUTS_TIMEOUT_SECONDS: u64 = 120;
+/// Longest a VM may take to boot, install requirements, download inputs and run code before
+/// one of the runner's own timeouts fires
+pub const EXECUTION_BUDGET: Duration = Duration::from_secs(
+    VM_BOOT_TIMEOUT_SECONDS
+        + VM_SETUP_TIMEOUT_SECONDS
+        + VM_INPUTS_TIMEOUT_SECONDS
+        + VM_EXECUTE_TIMEOUT_SECONDS,
+);
+/// Name of the `--config-file` inside a jail
+const VM_CONFIG_FILE_NAME: &str = "vm-config.json";
+/// Name of the vsock device's Unix socket inside a jail
+const VSOCK_SOCKET_NAME: &str = "vsock.sock";
+/// Longest a Firecracker API request may take before the socket counts as wedged
+pub const API_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Longest the guest agent may take to report leftover workspaces before a VM goes back to
+/// the pool
+const WORKSPACE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
 
 impl Default for VMManager {
     fn default() -> Self {
-        let vm_id = generate_vm_id();
-        let tap_interface = format!("tap-{}", &vm_id[..8]);
-        // Generate unique subnet for each VM (172.16.x.0/24 where x is based on VM ID)
-        let subnet_id = u32::from_str_radix(&vm_id[..8], 16).unwrap_or(1) % 254 + 1;
-        let vm_ip = format!("172.16.{subnet_id}.2");
-
-        Self {
-            vm_id: vm_id.clone(),
-            socket_path: format!("/tmp/firecracker-{vm_id}.socket"),
-            process: None,
-            stdout_log_path: format!("/tmp/fc-stdout-{vm_id}.log"),
-            stderr_log_path: format!("/tmp/fc-stderr-{vm_id}.log"),
-            vm_ip,
-            tap_interface,
-        }
+        Self::with_vm_id(generate_vm_id(), shared_runner_config())
     }
 }
 
-use std::collections::VecDeque;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 
 /// Check if we're running in test mode
 fn is_test_mode() -> bool {
@@ -61,178 +164,1099 @@ fn is_test_mode() -> bool {
         || std::thread::current().name().unwrap_or("").contains("test")
 }
 
-/// VM Pool to reuse VMs and reduce latency
-pub static VM_POOL: once_cell::sync::Lazy<Arc<Mutex<VecDeque<VMManager>>>> =
-    once_cell::sync::Lazy::new(|| Arc::new(Mutex::new(VecDeque::new())));
+/// Paths needed to inspect a live VM from outside the runner
+#[derive(Debug, Clone)]
+pub struct LiveVm {
+    pub vm_id: String,
+    pub socket_path: String,
+    pub fc_metrics_path: String,
+    /// Host TAP device, which may differ from the one derived from the ID
+    pub tap_interface: String,
+    /// Firecracker (or jailer) process, once started
+    pub pid: Option<u32>,
+    /// `tc` shaping applied to the TAP device
+    pub traffic_shape: Option<TrafficShape>,
+    /// Protocol version its guest agent reported, once it came up
+    pub agent_protocol: Option<u32>,
+    /// Host file descriptors it is expected to take
+    pub fds: VmFds,
+}
 
-/// Execute Python code in a Firecracker microVM via HTTP API (optimized with VM pooling)
-pub async fn run_in_vm(code: &str) -> Result<ExecuteResponse, ExecutionError> {
-    // Try to get a VM from the pool first
-    let vm_manager = {
-        let mut pool = VM_POOL.lock().await;
-        if let Some(vm) = pool.pop_front() {
-            tracing::debug!("Reusing VM from pool (pool size: {})", pool.len());
-            vm
-        } else {
-            tracing::debug!("No VMs in pool, creating new one");
-            drop(pool);
-            create_new_vm().await?
+/// A live VM as `GET /vms/{id}` shows it
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, utoipa::ToSchema)]
+pub struct VmDetails {
+    pub vm_id: String,
+    pub tap_interface: String,
+    /// `tc` shaping of its TAP device; absent when it isn't shaped
+    pub traffic_shape: Option<TrafficShape>,
+    /// Host resources of its Firecracker process, sampled now; absent before the process
+    /// starts and once it has exited
+    pub usage: Option<ProcessUsage>,
+    /// Protocol version its guest agent reported; absent until the agent is up, and for
+    /// agents that predate the handshake
+    pub agent_protocol_version: Option<u32>,
+}
+
+impl LiveVm {
+    pub fn details(&self) -> VmDetails {
+        VmDetails {
+            vm_id: self.vm_id.clone(),
+            tap_interface: self.tap_interface.clone(),
+            traffic_shape: self.traffic_shape.clone(),
+            usage: self.pid.and_then(process_usage::sample),
+            agent_protocol_version: self.agent_protocol,
         }
+    }
+}
+
+/// Registry of every VM that has been created and not yet cleaned up, keyed by VM ID
+pub static VM_REGISTRY: once_cell::sync::Lazy<std::sync::Mutex<HashMap<String, LiveVm>>> =
+    once_cell::sync::Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Look up a live VM by ID
+pub fn live_vm(vm_id: &str) -> Option<LiveVm> {
+    VM_REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(vm_id)
+        .cloned()
+}
+
+/// Firecracker processes of the live VMs that have one
+pub fn live_vm_pids() -> Vec<u32> {
+    VM_REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .filter_map(|vm| vm.pid)
+        .collect()
+}
+
+/// Read the latest Firecracker metrics of a live VM, asking Firecracker to flush first
+pub async fn read_firecracker_metrics(vm: &LiveVm) -> Option<serde_json::Value> {
+    let flush = serde_json::json!({ "action_type": "FlushMetrics" });
+    if let Err(e) = api_request_to(
+        &vm.socket_path,
+        Method::PUT,
+        "/actions",
+        Some(&flush.to_string()),
+    )
+    .await
+    {
+        tracing::debug!("Failed to flush metrics for VM {}: {}", vm.vm_id, e);
+    }
+    let contents = tokio::fs::read_to_string(&vm.fc_metrics_path).await.ok()?;
+    let mut metrics = fc_metrics::parse_latest(&contents)?;
+    if runner_config().balloon.enabled
+        && version::host_version().is_some_and(FirecrackerVersion::supports_balloon)
+        && let Ok((_, stats)) =
+            api_request_to(&vm.socket_path, Method::GET, "/balloon/statistics", None).await
+        && let Ok(stats) = serde_json::from_slice::<serde_json::Value>(&stats)
+        && let Some(metrics) = metrics.as_object_mut()
+    {
+        metrics.insert("balloon".to_string(), stats);
+    }
+    Some(metrics)
+}
+
+/// Send HTTP request to the Firecracker API listening on `socket_path`, returning the status
+/// and body of a successful response
+async fn api_request_to(
+    socket_path: &str,
+    method: Method,
+    path: &str,
+    body: Option<&str>,
+) -> Result<(StatusCode, Bytes), ExecutionError> {
+    let (status, response) =
+        api_exchange(socket_path, method.clone(), path, body, API_REQUEST_TIMEOUT).await?;
+    successful_response(&method, path, status, response)
+}
+
+/// Status and body of a successful Firecracker API response, or an error carrying its details
+fn successful_response(
+    method: &Method,
+    path: &str,
+    status: StatusCode,
+    body: Bytes,
+) -> Result<(StatusCode, Bytes), ExecutionError> {
+    if !status.is_success() {
+        return Err(ExecutionError::ApiCommunicationError(format!(
+            "API returned error status: {status} for {method} {path}. Error details: {}",
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    Ok((status, body))
+}
+
+/// Status and body of the Firecracker API's response to a request, whatever the status. A
+/// socket that doesn't answer within `timeout` fails the request instead of hanging it.
+async fn api_exchange(
+    socket_path: &str,
+    method: Method,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<(StatusCode, Bytes), ExecutionError> {
+    tokio::time::timeout(
+        timeout,
+        api_exchange_untimed(socket_path, method.clone(), path, body),
+    )
+    .await
+    .map_err(|_| {
+        ExecutionError::ApiCommunicationError(format!(
+            "API request {method} {path} timed out after {}ms",
+            timeout.as_millis()
+        ))
+    })?
+}
+
+async fn api_exchange_untimed(
+    socket_path: &str,
+    method: Method,
+    path: &str,
+    body: Option<&str>,
+) -> Result<(StatusCode, Bytes), ExecutionError> {
+    let client: Client<UnixConnector, Full<Bytes>> =
+        Client::builder(TokioExecutor::new()).build(UnixConnector);
+    let uri: Uri = hyperlocal::Uri::new(socket_path, path).into();
+    let mut request_builder = Request::builder().method(method.clone()).uri(uri);
+
+    let request = if let Some(json_body) = body {
+        request_builder = request_builder.header("content-type", "application/json");
+        request_builder
+            .body(Full::new(Bytes::from(json_body.to_string())))
+            .map_err(|e| {
+                ExecutionError::ApiCommunicationError(format!("Request build failed: {e}"))
+            })?
+    } else {
+        request_builder.body(Full::new(Bytes::new())).map_err(|e| {
+            ExecutionError::ApiCommunicationError(format!("Request build failed: {e}"))
+        })?
     };
 
-    // Execute code via HTTP API
-    let result = vm_manager.execute_code_via_api(code).await;
-
-    match result {
-        Ok(response) => {
-            // VM is still healthy, return it to pool
-            {
-                let mut pool = VM_POOL.lock().await;
-                if pool.len() < VM_POOL_SIZE {
-                    pool.push_back(vm_manager);
-                    tracing::debug!("Returned VM to pool (pool size: {})", pool.len());
-                } else {
-                    // Pool is full, shutdown this VM
-                    tokio::spawn(async move {
-                        let mut vm = vm_manager;
-                        let _ = vm.shutdown_vm().await;
-                        let _ = vm.cleanup().await;
-                    });
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| ExecutionError::ApiCommunicationError(format!("API request failed: {e}")))?;
+
+    let status = response.status();
+    use http_body_util::BodyExt;
+    let body_bytes = response
+        .collect()
+        .await
+        .map_err(|e| {
+            ExecutionError::ApiCommunicationError(format!("Failed to read response: {e}"))
+        })?
+        .to_bytes();
+    Ok((status, body_bytes))
+}
+
+/// One execution to run on a pooled VM
+#[derive(Debug, Clone)]
+pub struct ExecutionSpec {
+    pub request_id: String,
+    pub program: Program,
+    /// Can lower the configured per-stream output cap
+    pub max_output_bytes: Option<usize>,
+    /// Packages installed before running; the VM is used once and discarded
+    pub requirements: Vec<String>,
+    /// Files downloaded into the guest before running; the VM is used once and discarded
+    pub inputs: Vec<RemoteInput>,
+    /// Run on a VM with this deps profile's image attached
+    pub deps_profile: Option<String>,
+    /// Run on a VM booted from this named image
+    pub image: Option<String>,
+    /// Run reproducibly; the VM is booted for this request alone and discarded
+    pub deterministic: Option<DeterministicSettings>,
+    /// Name servers replacing the configured ones; the VM is booted for this request alone
+    pub dns: Option<Vec<String>>,
+    /// TAP shaping replacing the image's; the VM is booted for this request alone
+    pub traffic_shape: Option<TrafficShape>,
+    /// Guest port forwarded from the host once the code has run; the VM is booted for this
+    /// request alone and kept until the forward ends
+    pub expose_port: Option<u16>,
+    /// Prefer the pooled VM that last ran a request with this key
+    pub affinity_key: Option<String>,
+    /// Place in the queue for an execution permit
+    pub priority: Priority,
+    /// Boot a fresh VM and keep its logs if the boot fails
+    pub debug_boot: bool,
+    /// Attach the reference data drives; without them the VM is booted for this request alone
+    pub mount_reference_data: bool,
+    /// Run only on a warm pooled VM and within a short timeout
+    pub quick: Option<QuickOptions>,
+    /// Return the console output printed during the execution; the VM is booted for this
+    /// request alone
+    pub capture_console: bool,
+    /// Return the execution's steps in the response's `events`
+    pub debug: bool,
+    /// Keep the VM this long as a sandbox after the execution; the VM is booted for this
+    /// request alone
+    pub keep_alive: Option<Duration>,
+    /// Longest the request may take end to end, waiting for a permit, booting and running
+    /// included; the runner's own `EXECUTION_BUDGET` when unset
+    pub timeout: Option<Duration>,
+    /// Receives the execution's place in the queue while it waits for a permit, then a
+    /// position of 0 once it has one
+    pub queue_updates: Option<tokio::sync::mpsc::UnboundedSender<QueuePosition>>,
+    /// Receives stdout and stderr as the guest prints them, all of it before the execution
+    /// returns
+    pub output: Option<OutputSink>,
+}
+
+/// Bounds of a quick execution, for one-liners where even a queue or a cold boot costs too
+/// much: it runs on a warm pooled VM or fails at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuickOptions {
+    /// Longest the execution may take, waiting for a permit included
+    pub timeout: Duration,
+}
+
+/// How a VM is set up at boot
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VmOptions {
+    pub deps_profile: Option<String>,
+    pub image: Option<String>,
+    pub deterministic: Option<DeterministicSettings>,
+    /// Name servers replacing the configured ones
+    pub dns: Option<Vec<String>>,
+    /// TAP shaping replacing the image's
+    pub traffic_shape: Option<TrafficShape>,
+    /// Keep the VM's logs if the boot fails, whatever the boot diagnostics setting
+    pub debug_boot: bool,
+    /// Leave out the reference data drives
+    pub no_reference_data: bool,
+}
+
+impl ExecutionSpec {
+    /// A single snippet under a fresh request ID
+    pub fn code(code: impl Into<String>) -> Self {
+        Self {
+            request_id: generate_request_id(),
+            program: Program::Code(code.into()),
+            max_output_bytes: None,
+            requirements: Vec::new(),
+            inputs: Vec::new(),
+            deps_profile: None,
+            image: None,
+            deterministic: None,
+            dns: None,
+            traffic_shape: None,
+            expose_port: None,
+            affinity_key: None,
+            priority: Priority::Normal,
+            debug_boot: false,
+            mount_reference_data: true,
+            quick: None,
+            capture_console: false,
+            debug: false,
+            keep_alive: None,
+            timeout: None,
+            queue_updates: None,
+            output: None,
+        }
+    }
+
+    /// Whether the request needs a VM of its own instead of a pooled one
+    pub fn needs_dedicated_vm(&self) -> bool {
+        // Installing packages dirties site-packages and inputs stay on disk; deterministic runs
+        // need a pristine guest, name servers and shaping are set at boot, diagnosing a boot
+        // needs one to boot, an exposed port or a sandbox keeps the VM busy past the execution,
+        // pooled VMs all have the reference data attached, and a pooled VM's console holds what
+        // earlier requests printed
+        !self.requirements.is_empty()
+            || !self.inputs.is_empty()
+            || self.deterministic.is_some()
+            || self.dns.is_some()
+            || self.traffic_shape.is_some()
+            || self.expose_port.is_some()
+            || self.keep_alive.is_some()
+            || self.debug_boot
+            || !self.mount_reference_data
+            || self.capture_console
+    }
+
+    /// Boot-time setup of the VM this request runs on
+    pub fn vm_options(&self) -> VmOptions {
+        VmOptions {
+            deps_profile: self.deps_profile.clone(),
+            image: self.image.clone(),
+            deterministic: self.deterministic,
+            dns: self.dns.clone(),
+            traffic_shape: self.traffic_shape.clone(),
+            debug_boot: self.debug_boot,
+            no_reference_data: !self.mount_reference_data,
+        }
+    }
+}
+
+/// Output chunk size of streamed executions; a multiple of 4 so base64 output chunks decode
+/// independently
+pub const OUTPUT_CHUNK_BYTES: usize = 16 * 1024;
+
+/// One message of a streamed execution, as sent by the gRPC and NDJSON transports
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutputEvent {
+    Stdout {
+        data: String,
+    },
+    Stderr {
+        data: String,
+    },
+    /// Final result; its `stdout` and `stderr` are empty since they were streamed
+    Result(Box<ExecuteResponse>),
+}
+
+impl OutputEvent {
+    /// The final event of an execution whose output was already streamed
+    pub fn result(mut response: ExecuteResponse) -> Self {
+        response.stdout.clear();
+        response.stderr.clear();
+        OutputEvent::Result(Box::new(response))
+    }
+}
+
+/// Receives a running execution's stdout and stderr as the guest prints them, through
+/// `ExecutionSpec::output`
+pub type OutputSink = tokio::sync::mpsc::UnboundedSender<OutputEvent>;
+
+/// Send the output of a finished execution to `output` in chunks, for backends and cached
+/// results that only have it once the code has exited
+pub fn send_output(output: &OutputSink, response: &ExecuteResponse) {
+    let stdout = output_chunks(&response.stdout)
+        .into_iter()
+        .map(|chunk| OutputEvent::Stdout {
+            data: chunk.to_string(),
+        });
+    let stderr = output_chunks(&response.stderr)
+        .into_iter()
+        .map(|chunk| OutputEvent::Stderr {
+            data: chunk.to_string(),
+        });
+    for event in stdout.chain(stderr) {
+        // The streaming client went away; the execution finishes anyway
+        let _ = output.send(event);
+    }
+}
+
+/// What a live execution produced next
+#[derive(Debug)]
+pub enum Live<T> {
+    /// Output printed by the code
+    Output(OutputEvent),
+    /// The execution's outcome, after all of its output
+    Finished(Result<T, tokio::task::JoinError>),
+}
+
+/// An execution running detached, read as it prints, by the streaming transports. Being
+/// detached, it finishes and returns its VM as usual whether or not anyone reads it to the end.
+pub struct LiveExecution<T> {
+    output: tokio::sync::mpsc::UnboundedReceiver<OutputEvent>,
+    execution: Option<tokio::task::JoinHandle<T>>,
+    outcome: Option<Result<T, tokio::task::JoinError>>,
+}
+
+impl<T: Send + 'static> LiveExecution<T> {
+    /// Spawn the execution `start` returns, handing it the sink for its output
+    pub fn start<F>(start: impl FnOnce(OutputSink) -> F) -> Self
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let (sink, output) = tokio::sync::mpsc::unbounded_channel();
+        Self {
+            output,
+            execution: Some(tokio::spawn(start(sink))),
+            outcome: None,
+        }
+    }
+
+    /// The next piece of output as soon as it is printed, then the outcome, then `None`.
+    /// Cancel-safe, so it can race a deadline.
+    pub async fn next(&mut self) -> Option<Live<T>> {
+        if let Some(execution) = self.execution.as_mut() {
+            tokio::select! {
+                biased;
+                Some(event) = self.output.recv() => return Some(Live::Output(event)),
+                outcome = execution => {
+                    self.execution = None;
+                    self.outcome = Some(outcome);
                 }
             }
-            Ok(response)
         }
-        Err(e) => {
-            // VM failed, shutdown and cleanup
-            tokio::spawn(async move {
-                let mut vm = vm_manager;
-                let _ = vm.shutdown_vm().await;
-                let _ = vm.cleanup().await;
-            });
-            Err(e)
+        // Output sent just before the execution finished still comes before its outcome
+        if let Ok(event) = self.output.try_recv() {
+            return Some(Live::Output(event));
         }
+        self.outcome.take().map(Live::Finished)
     }
+
+    /// Stop waiting for an execution nobody wants the result of any more; its VM is discarded
+    pub fn abort(&self) {
+        if let Some(execution) = &self.execution {
+            execution.abort();
+        }
+    }
+}
+
+/// Split `text` into pieces of at most `OUTPUT_CHUNK_BYTES`, on character boundaries
+fn output_chunks(text: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(OUTPUT_CHUNK_BYTES);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// A guest response that can't be decoded, counting those abandoned for being too large
+fn parse_error(e: DecodeError) -> ExecutionError {
+    if matches!(e, DecodeError::TooLarge(_)) {
+        crate::telemetry::increment_counter("fc_guest_responses_aborted_total", &[], 1);
+    }
+    ExecutionError::ApiCommunicationError(format!("Failed to parse response: {e}"))
+}
+
+/// Bytes of each stream of a live execution passed on so far
+#[derive(Debug, Default)]
+struct LiveOutputSent {
+    stdout: usize,
+    stderr: usize,
 }
 
-/// Create a new VM and wait for it to be ready
-pub async fn create_new_vm() -> Result<VMManager, ExecutionError> {
-    let mut vm_manager = VMManager::new().await?;
+impl LiveOutputSent {
+    /// Pass `chunk` on to `output`, dropping what lies past `max_output_bytes` of its stream,
+    /// as agents ignoring the cap would send
+    fn forward(&mut self, output: &OutputSink, chunk: LiveChunk, max_output_bytes: usize) {
+        let (is_stdout, mut data) = match chunk {
+            LiveChunk::Stdout(data) => (true, data),
+            LiveChunk::Stderr(data) => (false, data),
+        };
+        let sent = if is_stdout {
+            &mut self.stdout
+        } else {
+            &mut self.stderr
+        };
+        let mut room = max_output_bytes.saturating_sub(*sent).min(data.len());
+        while !data.is_char_boundary(room) {
+            room -= 1;
+        }
+        data.truncate(room);
+        if data.is_empty() {
+            return;
+        }
+        *sent += data.len();
+        let event = if is_stdout {
+            OutputEvent::Stdout { data }
+        } else {
+            OutputEvent::Stderr { data }
+        };
+        // The streaming client went away; the execution finishes anyway
+        let _ = output.send(event);
+    }
+}
 
-    // 1. Set up networking
-    vm_manager.setup_networking().await?;
+/// Execute Python code in a Firecracker microVM via HTTP API (optimized with VM pooling)
+pub async fn run_in_vm(code: &str) -> Result<ExecuteResponse, ExecutionError> {
+    run_in_vm_for_request(ExecutionSpec::code(code)).await
+}
 
-    // 2. Start Firecracker with the API server rootfs
-    vm_manager.start_firecracker().await?;
-    vm_manager.configure_and_run_vm().await?;
+/// Execute a request on the process-wide executor built from the global configuration
+pub async fn run_in_vm_for_request(
+    request: ExecutionSpec,
+) -> Result<ExecuteResponse, ExecutionError> {
+    executor::default_executor().execute(request).await
+}
 
-    // 3. Wait for VM to boot and API server to be ready
-    vm_manager.wait_for_api_server().await?;
+/// Create a new VM set up according to `options` and wait for it to be ready, failing once
+/// `deadline` passes
+pub async fn create_new_vm_with(
+    config: &Arc<RunnerConfig>,
+    options: &VmOptions,
+    deadline: Deadline,
+) -> Result<VMManager, ExecutionError> {
+    // A boot that can't finish in time would only hold resources until it fails
+    deadline.budget_for(Phase::Boot)?;
+    // Fail fast rather than invite the OOM killer
+    admission::admit(config, &admission::HostProbe)?;
+    let mut vm_manager = VMManager::with_config(config.clone());
+    if let Some(profile) = &options.deps_profile {
+        vm_manager.attach_deps_profile(profile)?;
+    }
+    if !options.no_reference_data {
+        vm_manager.attach_reference_data();
+    }
+    if let Some(image) = &options.image {
+        vm_manager.use_image(image)?;
+    }
+    vm_manager.deterministic = options.deterministic;
+    vm_manager.dns = options.dns.clone();
+    vm_manager.traffic_shape = options.traffic_shape.clone();
+    vm_manager.debug_boot |= options.debug_boot;
+    VM_REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(vm_manager.vm_id.clone(), vm_manager.live_record());
+    events::publish(VmEvent::Created {
+        vm_id: vm_manager.vm_id.clone(),
+    });
+    let boot_start = std::time::Instant::now();
+
+    let backend = vm_manager.backend();
+    let booted = deadline
+        .run(Phase::Boot, backend.boot(&mut vm_manager, deadline))
+        .await;
+    #[cfg(feature = "chaos")]
+    let booted = match booted {
+        Ok(()) => vm_manager.inject_fault(FaultPoint::AfterBoot).await,
+        Err(e) => Err(e),
+    };
+    match booted {
+        Ok(()) => {
+            events::publish(VmEvent::BootReady {
+                vm_id: vm_manager.vm_id.clone(),
+                boot_ms: boot_start.elapsed().as_millis() as u64,
+            });
+            record_boot_phase(&vm_manager.vm_id, "boot", boot_start);
+            let warmup_start = std::time::Instant::now();
+            let warmed_up = vm_manager.warm_up(deadline).await;
+            if vm_manager.warmup.is_some() {
+                record_boot_phase(&vm_manager.vm_id, "warmup", warmup_start);
+            }
+            if let Err(e) = warmed_up {
+                events::publish(VmEvent::Discarded {
+                    vm_id: vm_manager.vm_id.clone(),
+                    reason: DiscardReason::WarmupFailed,
+                });
+                let _ = vm_manager.cleanup().await;
+                return Err(e);
+            }
+            Ok(vm_manager)
+        }
+        Err(e) => {
+            // Don't leave the half-started VM's process, TAP and files behind
+            if let Some(exit_code) = vm_manager.exited() {
+                events::publish(VmEvent::Crashed {
+                    vm_id: vm_manager.vm_id.clone(),
+                    exit_code,
+                });
+            }
+            events::publish(VmEvent::Discarded {
+                vm_id: vm_manager.vm_id.clone(),
+                reason: DiscardReason::BootFailed,
+            });
+            let e = if vm_manager.debug_boot {
+                vm_manager.retain_boot_diagnostics(e).await
+            } else {
+                e
+            };
+            let _ = vm_manager.cleanup().await;
+            Err(e)
+        }
+    }
+}
 
-    Ok(vm_manager)
+/// Note for the execution being debugged that the boot phase `phase` of `vm_id`, begun at
+/// `started`, is done
+fn record_boot_phase(vm_id: &str, phase: &'static str, started: std::time::Instant) {
+    debug_events::record(
+        DebugEvent::new(DebugEventKind::BootPhase)
+            .vm(vm_id)
+            .detail(phase)
+            .duration(started.elapsed()),
+    );
 }
 
 impl VMManager {
     /// Create a new VM manager with a unique ID
     pub async fn new() -> Result<Self, ExecutionError> {
-        let vm_id = generate_vm_id();
-        let tap_interface = format!("tap-{}", &vm_id[..8]);
+        Ok(Self::default())
+    }
+
+    /// A VM manager with a unique ID, set up according to `config`
+    pub fn with_config(config: Arc<RunnerConfig>) -> Self {
+        Self::with_vm_id(generate_vm_id(), config)
+    }
+
+    /// Build a VM manager whose runtime files and network settings derive from `vm_id`
+    fn with_vm_id(vm_id: String, config: Arc<RunnerConfig>) -> Self {
+        let tap_interface = tap::tap_name(&vm_id, 0);
         // Generate unique subnet for each VM (172.16.x.0/24 where x is based on VM ID)
         let subnet_id = u32::from_str_radix(&vm_id[..8], 16).unwrap_or(1) % 254 + 1;
         let vm_ip = format!("172.16.{subnet_id}.2");
+        let stdout_log_path = config.runtime_path(&format!("fc-stdout-{vm_id}.log"));
+        let stderr_log_path = config.runtime_path(&format!("fc-stderr-{vm_id}.log"));
+        let debug_boot = config.boot_diagnostics.enabled;
+        let transport = config.guest_transport.kind_for(None);
+
+        // Everything Firecracker itself opens must live inside the jail's chroot
+        if let Some(jailer) = &config.jailer {
+            let jail = Jail::new(jailer, &vm_id);
+            let in_jail = |path: &str| jail.host_path(path).to_string_lossy().into_owned();
+            let vsock_path = in_jail(VSOCK_SOCKET_NAME);
+            let guest_transport = GuestTransport::new(transport, &vm_ip, &vsock_path);
+            return Self {
+                socket_path: in_jail(JAIL_SOCKET_PATH),
+                process: None,
+                stdout_log_path,
+                stderr_log_path,
+                fc_log_path: in_jail("fc.log"),
+                fc_metrics_path: in_jail("fc-metrics.json"),
+                config_file_path: in_jail(VM_CONFIG_FILE_NAME),
+                kernel_image_path: in_jail("vmlinux.bin"),
+                rootfs_path: in_jail("rootfs.ext4"),
+                vm_id,
+                vm_ip,
+                tap_interface,
+                vsock_path,
+                guest_transport,
+                jail: Some(jail),
+                deps_profile: None,
+                deps_image_path: None,
+                reference_drives: Vec::new(),
+                image: None,
+                deterministic: None,
+                dns: None,
+                traffic_shape: None,
+                applied_shape: None,
+                firecracker_version: version::host_version().cloned(),
+                config,
+                agent_url: None,
+                agent_protocol: None,
+                idle_since: None,
+                created_at: std::time::Instant::now(),
+                executions: 0,
+                debug_boot,
+                keep_logs: false,
+                warmup: None,
+                probe_failures: 0,
+                last_probe_at: None,
+                #[cfg(feature = "chaos")]
+                fault: None,
+            };
+        }
 
-        let socket_path = format!("/tmp/firecracker-{vm_id}.socket");
-        let stdout_log_path = format!("/tmp/fc-stdout-{vm_id}.log");
-        let stderr_log_path = format!("/tmp/fc-stderr-{vm_id}.log");
-
-        Ok(Self {
-            vm_id,
-            socket_path,
+        let artifacts = config.artifacts.for_host();
+        let vsock_path = config.runtime_path(&format!("fc-vsock-{vm_id}.sock"));
+        let guest_transport = GuestTransport::new(transport, &vm_ip, &vsock_path);
+        Self {
+            socket_path: config.runtime_path(&format!("firecracker-{vm_id}.socket")),
             process: None,
             stdout_log_path,
             stderr_log_path,
+            fc_log_path: config.runtime_path(&format!("fc-log-{vm_id}.log")),
+            fc_metrics_path: config.runtime_path(&format!("fc-metrics-{vm_id}.json")),
+            config_file_path: config.runtime_path(&format!("fc-config-{vm_id}.json")),
+            kernel_image_path: artifacts.kernel.to_string_lossy().into_owned(),
+            rootfs_path: artifacts.rootfs.to_string_lossy().into_owned(),
+            vm_id,
             vm_ip,
             tap_interface,
-        })
+            vsock_path,
+            guest_transport,
+            jail: None,
+            deps_profile: None,
+            deps_image_path: None,
+            reference_drives: Vec::new(),
+            image: None,
+            deterministic: None,
+            dns: None,
+            traffic_shape: None,
+            applied_shape: None,
+            firecracker_version: version::host_version().cloned(),
+            config,
+            agent_url: None,
+            agent_protocol: None,
+            idle_since: None,
+            created_at: std::time::Instant::now(),
+            executions: 0,
+            debug_boot,
+            keep_logs: false,
+            warmup: None,
+            probe_failures: 0,
+            last_probe_at: None,
+            #[cfg(feature = "chaos")]
+            fault: None,
+        }
+    }
+
+    /// Attach the image of deps profile `profile` when the VM boots
+    fn attach_deps_profile(&mut self, profile: &str) -> Result<(), ExecutionError> {
+        let source = self.config.deps_profiles.get(profile).ok_or_else(|| {
+            ExecutionError::ResourceError(format!("unknown deps profile '{profile}'"))
+        })?;
+        let image_path = match &self.jail {
+            Some(jail) => jail.host_path("deps.ext4").to_string_lossy().into_owned(),
+            None => source.to_string_lossy().into_owned(),
+        };
+        self.deps_profile = Some(profile.to_string());
+        self.deps_image_path = Some(image_path);
+        Ok(())
+    }
+
+    /// Attach every configured reference data drive when the VM boots
+    fn attach_reference_data(&mut self) {
+        self.reference_drives = self
+            .config
+            .reference_data
+            .iter()
+            .map(|(name, source)| {
+                let image_path = match &self.jail {
+                    Some(jail) => jail
+                        .host_path(&format!("{}.img", reference_data::drive_id(name)))
+                        .to_string_lossy()
+                        .into_owned(),
+                    None => source.to_string_lossy().into_owned(),
+                };
+                (name.clone(), image_path)
+            })
+            .collect();
+    }
+
+    /// Deps profile attached to this VM, if any
+    pub fn deps_profile(&self) -> Option<&str> {
+        self.deps_profile.as_deref()
+    }
+
+    /// Boot from the rootfs of image `name` instead of the default one
+    fn use_image(&mut self, name: &str) -> Result<(), ExecutionError> {
+        let source = self
+            .config
+            .image_path(name)
+            .ok_or_else(|| ExecutionError::ResourceError(format!("unknown image '{name}'")))?;
+        // In a jail the image is staged under the usual in-chroot name
+        if self.jail.is_none() {
+            self.rootfs_path = source.to_string_lossy().into_owned();
+        }
+        self.image = Some(name.to_string());
+        let transport = self.config.guest_transport.kind_for(Some(name));
+        self.guest_transport = GuestTransport::new(transport, &self.vm_ip, &self.vsock_path);
+        Ok(())
+    }
+
+    /// Named image this VM booted from, if not the default rootfs
+    pub fn image(&self) -> Option<&str> {
+        self.image.as_deref()
+    }
+
+    /// Path as Firecracker sees it: relative to the chroot when jailed, unchanged otherwise
+    fn firecracker_path(&self, host_path: &str) -> String {
+        match &self.jail {
+            Some(jail) => jail
+                .jail_path(std::path::Path::new(host_path))
+                .unwrap_or_else(|| host_path.to_string()),
+            None => host_path.to_string(),
+        }
+    }
+
+    /// Set up networking, start Firecracker and wait for the guest agent, by `deadline`
+    pub(crate) async fn boot_firecracker(
+        &mut self,
+        deadline: Deadline,
+    ) -> Result<(), ExecutionError> {
+        let vm_id = self.vm_id.clone();
+        // 1. Set up networking
+        let started = std::time::Instant::now();
+        self.setup_networking()
+            .instrument(tracing::info_span!("networking", vm_id = %vm_id))
+            .await?;
+        record_boot_phase(&vm_id, "networking", started);
+
+        async {
+            // 2. Start Firecracker with the API server rootfs
+            let started = std::time::Instant::now();
+            self.start_firecracker().await?;
+            self.configure_and_run_vm().await?;
+            record_boot_phase(&vm_id, "firecracker_started", started);
+
+            // 3. Wait for VM to boot and API server to be ready
+            let started = std::time::Instant::now();
+            self.wait_for_api_server(deadline).await?;
+            record_boot_phase(&vm_id, "agent_ready", started);
+            Ok(())
+        }
+        .instrument(tracing::info_span!("boot", vm_id = %vm_id))
+        .await
+    }
+
+    /// Backend this VM runs on
+    fn backend(&self) -> &'static dyn VmBackend {
+        backend::backend(self.config.backend)
+    }
+
+    /// Settings of the executor that created this VM
+    pub(crate) fn config(&self) -> &RunnerConfig {
+        &self.config
+    }
+
+    /// Settings passed to the guest for a deterministic run, if any
+    #[cfg(feature = "local-backend")]
+    pub(crate) fn deterministic(&self) -> Option<&DeterministicSettings> {
+        self.deterministic.as_ref()
+    }
+
+    /// Note that the VM is going back into the pool; the execution it just ran shows it
+    /// healthy, so earlier failed probes are forgotten
+    pub(crate) fn mark_idle(&mut self) {
+        self.idle_since = Some(std::time::Instant::now());
+        self.probe_failures = 0;
+    }
+
+    /// Note the outcome of a health probe; returns the failed probes in a row
+    pub(crate) fn record_probe(&mut self, healthy: bool) -> u32 {
+        self.last_probe_at = Some(crate::history::now_millis());
+        self.probe_failures = if healthy { 0 } else { self.probe_failures + 1 };
+        self.probe_failures
+    }
+
+    /// When the health prober last checked the VM, in milliseconds since the epoch
+    pub fn last_probe_at(&self) -> Option<u64> {
+        self.last_probe_at
+    }
+
+    /// Time the VM has sat in the pool; zero if it never has
+    pub(crate) fn idle_for(&self) -> Duration {
+        self.idle_since
+            .map(|since| since.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// When the VM was created
+    pub fn created_at(&self) -> std::time::Instant {
+        self.created_at
+    }
+
+    /// Time since the VM was created
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
+
+    /// Executions the VM has run
+    pub fn executions(&self) -> u64 {
+        self.executions
+    }
+
+    pub(crate) fn record_execution(&mut self) {
+        self.executions += 1;
+    }
+
+    /// How the warm-up code went on this VM, if any ran
+    pub fn warmup(&self) -> Option<WarmupReport> {
+        self.warmup
+    }
+
+    /// Run the warm-up code configured for this VM's deps profile or image, once, right after
+    /// boot. A failure is only logged unless warm-ups are fatal; deterministic VMs are left
+    /// pristine.
+    async fn warm_up(&mut self, deadline: Deadline) -> Result<(), ExecutionError> {
+        let config = self.config.clone();
+        let warmup = &config.warmup;
+        if self.deterministic.is_some() {
+            return Ok(());
+        }
+        let Some(code) = warmup.code_for(self.deps_profile(), self.image.as_deref()) else {
+            return Ok(());
+        };
+        let program = Program::Code(code.to_string());
+        let started = std::time::Instant::now();
+        let outcome = match timeout(
+            warmup.timeout,
+            self.execute_code_via_api(&program, &[], &[], config.max_output_bytes, deadline, None),
+        )
+        .await
+        {
+            Ok(Ok(response)) if response.success => Ok(()),
+            Ok(Ok(response)) => Err(format!(
+                "failed: {}",
+                response
+                    .stderr
+                    .trim()
+                    .lines()
+                    .last()
+                    .unwrap_or("no error output")
+            )),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!(
+                "did not finish within {}ms",
+                warmup.timeout.as_millis()
+            )),
+        };
+        let duration_ms = started.elapsed().as_millis() as u64;
+        self.warmup = Some(WarmupReport {
+            duration_ms,
+            success: outcome.is_ok(),
+        });
+        let label = if outcome.is_ok() {
+            "success"
+        } else {
+            "failure"
+        };
+        crate::telemetry::increment_counter("fc_vm_warmups_total", &[("outcome", label)], 1);
+        crate::telemetry::observe_histogram("fc_vm_warmup_duration_ms", &[], duration_ms as f64);
+        match outcome {
+            Ok(()) => Ok(()),
+            Err(problem) if warmup.fatal => Err(ExecutionError::ResourceError(format!(
+                "VM warm-up code {problem}"
+            ))),
+            Err(problem) => {
+                tracing::warn!(vm_id = %self.vm_id, "Warm-up code {}; pooling the VM anyway", problem);
+                Ok(())
+            }
+        }
+    }
+
+    /// Unique identifier of this VM
+    pub fn vm_id(&self) -> &str {
+        &self.vm_id
+    }
+
+    /// Kernel console log, which Firecracker writes to its stdout
+    pub fn console_log_path(&self) -> &Path {
+        Path::new(&self.stdout_log_path)
+    }
+
+    /// Guest address of this VM
+    pub fn vm_ip(&self) -> &str {
+        &self.vm_ip
+    }
+
+    /// Host end of this VM's subnet, the guest's gateway
+    pub fn host_ip(&self) -> String {
+        let subnet_id = self.vm_ip.split('.').nth(2).unwrap_or("1");
+        format!("172.16.{subnet_id}.1")
+    }
+
+    /// How the guest agent is reached
+    pub fn guest_transport(&self) -> &GuestTransport {
+        &self.guest_transport
+    }
+
+    /// Host TAP device of this VM
+    pub fn tap_interface(&self) -> &str {
+        &self.tap_interface
+    }
+
+    /// Whether the VM was booted for a deterministic run, and so must not be reused
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic.is_some()
+    }
+
+    /// Exit code of the Firecracker process if it has already exited on its own
+    pub(crate) fn exited(&mut self) -> Option<Option<i32>> {
+        #[cfg(feature = "chaos")]
+        if self.fault == Some(Fault::Kill) {
+            return Some(None);
+        }
+        let process = self.process.as_mut()?;
+        match process.try_wait() {
+            Ok(Some(status)) => Some(status.code()),
+            _ => None,
+        }
+    }
+
+    /// Snapshot of the paths needed to inspect this VM while it is alive
+    fn live_record(&self) -> LiveVm {
+        LiveVm {
+            vm_id: self.vm_id.clone(),
+            socket_path: self.socket_path.clone(),
+            fc_metrics_path: self.fc_metrics_path.clone(),
+            tap_interface: self.tap_interface.clone(),
+            pid: self.process.as_ref().and_then(Child::id),
+            traffic_shape: self.applied_shape.clone(),
+            agent_protocol: self.agent_protocol,
+            fds: VmFds::expected(self.process.is_some()),
+        }
+    }
+
+    /// Host resources the VM's Firecracker process holds, if it is running
+    pub fn process_usage(&self) -> Option<ProcessUsage> {
+        process_usage::sample(self.process.as_ref()?.id()?)
+    }
+
+    /// TAP devices created by this service, shared by every VM with the same runtime directory
+    fn tap_registry(&self) -> TapRegistry {
+        TapRegistry::new(self.config.runtime_path("fc-taps"))
     }
 
     /// Set up TAP interface for VM networking with unique subnet
-    pub async fn setup_networking(&self) -> Result<(), ExecutionError> {
+    pub async fn setup_networking(&mut self) -> Result<(), ExecutionError> {
         // Skip networking setup in test mode or for test TAP interfaces
         if is_test_mode() || self.tap_interface.starts_with("test-") {
             tracing::debug!("Skipping network setup in test mode");
             return Ok(());
         }
+        // The agent is reached over vsock, and the guest has no network
+        if !self.guest_transport.uses_tap() {
+            return Ok(());
+        }
+        // Without privileges every `ip` call below would fail, or once have hung on a password
+        privilege::network_privilege().await.check()?;
 
         // First, clean up any old TAP interfaces that might conflict
         self.cleanup_old_tap_interfaces().await;
 
-        // Create TAP interface
-        let tap_status = tokio::process::Command::new("sudo")
-            .arg("ip")
-            .arg("tuntap")
-            .arg("add")
-            .arg("dev")
-            .arg(&self.tap_interface)
-            .arg("mode")
-            .arg("tap")
-            .status()
-            .await
-            .map_err(|e| {
-                ExecutionError::ResourceError(format!("Failed to create TAP interface: {e}"))
-            })?;
-
-        if !tap_status.success() {
-            return Err(ExecutionError::ResourceError(
-                "Failed to create TAP interface".to_string(),
-            ));
+        // Create TAP interface, under another name if an orphaned one holds this VM's
+        let tap_interface =
+            tap::create_tap(&HostCommands, &self.tap_registry(), &self.vm_id).await?;
+        if tap_interface != self.tap_interface {
+            self.tap_interface = tap_interface;
+            VM_REGISTRY
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(self.vm_id.clone(), self.live_record());
         }
 
         // Configure TAP interface with host IP (VM subnet .1)
-        let host_ip = {
-            let vm_ip_parts: Vec<&str> = self.vm_ip.split('.').collect();
-            let subnet_id = vm_ip_parts[2];
-            format!("172.16.{subnet_id}.1/24")
-        };
-
-        let ip_status = tokio::process::Command::new("sudo")
-            .arg("ip")
-            .arg("addr")
-            .arg("add")
-            .arg(&host_ip)
-            .arg("dev")
-            .arg(&self.tap_interface)
-            .status()
+        let host_ip = format!("{}/24", self.host_ip());
+        let configured = HostCommands
+            .run(
+                "sudo",
+                &[
+                    "-n",
+                    "ip",
+                    "addr",
+                    "add",
+                    &host_ip,
+                    "dev",
+                    &self.tap_interface,
+                ],
+            )
             .await
             .map_err(|e| {
                 ExecutionError::ResourceError(format!("Failed to configure TAP interface: {e}"))
             })?;
-
-        if !ip_status.success() {
-            return Err(ExecutionError::ResourceError(
-                "Failed to configure TAP interface".to_string(),
-            ));
+        if !configured.success {
+            return Err(ExecutionError::ResourceError(format!(
+                "Failed to configure TAP interface: {}",
+                configured.stderr.trim()
+            )));
         }
 
         // Bring TAP interface up
-        let up_status = tokio::process::Command::new("sudo")
-            .arg("ip")
-            .arg("link")
-            .arg("set")
-            .arg("dev")
-            .arg(&self.tap_interface)
-            .arg("up")
-            .status()
+        let up = HostCommands
+            .run(
+                "sudo",
+                &["-n", "ip", "link", "set", "dev", &self.tap_interface, "up"],
+            )
             .await
             .map_err(|e| {
                 ExecutionError::ResourceError(format!("Failed to bring up TAP interface: {e}"))
             })?;
-
-        if !up_status.success() {
-            return Err(ExecutionError::ResourceError(
-                "Failed to bring up TAP interface".to_string(),
-            ));
+        if !up.success {
+            return Err(ExecutionError::ResourceError(format!(
+                "Failed to bring up TAP interface: {}",
+                up.stderr.trim()
+            )));
         }
 
+        self.shape_traffic(&HostCommands).await?;
+
         tracing::debug!(
             "TAP interface {} configured successfully with host IP {} and VM IP {}",
             self.tap_interface,
@@ -268,6 +1292,47 @@ impl VMManager {
         Ok(())
     }
 
+    /// Apply the request's or the image's `tc` shaping to the TAP device. Whether a failure
+    /// fails the boot or is only logged is up to the shaping config.
+    pub async fn shape_traffic(
+        &mut self,
+        runner: &dyn CommandRunner,
+    ) -> Result<(), ExecutionError> {
+        let shaping = &self.config.shaping;
+        let Some(shape) = self
+            .traffic_shape
+            .as_ref()
+            .or_else(|| shaping.shape_for(self.image.as_deref()))
+            .cloned()
+        else {
+            return Ok(());
+        };
+        match shaping::apply(runner, &self.tap_interface, &shape).await {
+            Ok(()) => {
+                tracing::debug!("Shaped {} with {:?}", self.tap_interface, shape);
+                self.applied_shape = Some(shape);
+                VM_REGISTRY
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(self.vm_id.clone(), self.live_record());
+                Ok(())
+            }
+            Err(e) if shaping.fatal => Err(ExecutionError::ResourceError(format!(
+                "Failed to shape traffic of {}: {e}",
+                self.tap_interface
+            ))),
+            Err(e) => {
+                tracing::warn!(
+                    "Running VM {} unshaped, shaping {} failed: {}",
+                    self.vm_id,
+                    self.tap_interface,
+                    e
+                );
+                Ok(())
+            }
+        }
+    }
+
     /// Clean up old TAP interfaces to prevent routing conflicts
     async fn cleanup_old_tap_interfaces(&self) {
         // Skip cleanup in test mode or for test TAP interfaces
@@ -277,85 +1342,62 @@ impl VMManager {
 
         tracing::debug!("Cleaning up old TAP interfaces...");
 
-        // Get list of currently active TAP interfaces from the VM pool
-        let active_interfaces = {
-            let pool = VM_POOL.lock().await;
-            pool.iter()
-                .map(|vm| vm.tap_interface.clone())
-                .collect::<std::collections::HashSet<_>>()
-        };
-
-        // Get list of existing TAP interfaces
-        let output = tokio::process::Command::new("ip")
-            .arg("link")
-            .arg("show")
-            .arg("type")
-            .arg("tun")
-            .output()
-            .await;
-
-        if let Ok(output) = output {
-            let output_str = String::from_utf8_lossy(&output.stdout);
-            let mut cleanup_count = 0;
-            for line in output_str.lines() {
-                if line.contains("tap-") {
-                    // Extract TAP interface name
-                    if let Some(start) = line.find("tap-") {
-                        if let Some(end) = line[start..].find(':') {
-                            let tap_name = &line[start..start + end];
-
-                            // Only clean up if this interface is not currently in use by the VM pool
-                            // and it's not the current VM's interface
-                            if !active_interfaces.contains(tap_name)
-                                && tap_name != self.tap_interface
-                            {
-                                tracing::debug!("Removing unused TAP interface: {}", tap_name);
-                                let _ = tokio::process::Command::new("sudo")
-                                    .arg("ip")
-                                    .arg("link")
-                                    .arg("delete")
-                                    .arg(tap_name)
-                                    .status()
-                                    .await;
-                                cleanup_count += 1;
-                            } else {
-                                tracing::debug!("Skipping active TAP interface: {}", tap_name);
-                            }
-                        }
-                    }
-                }
-            }
-            if cleanup_count > 0 {
-                tracing::info!("Cleaned up {} unused TAP interfaces", cleanup_count);
-            }
+        // TAP interfaces of every live VM, pooled or busy, across all executors
+        let mut in_use = VM_REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .map(|vm| vm.tap_interface.clone())
+            .collect::<std::collections::HashSet<_>>();
+        in_use.insert(self.tap_interface.clone());
+
+        let removed =
+            tap::cleanup_orphaned_taps(&HostCommands, &self.tap_registry(), &in_use).await;
+        if removed > 0 {
+            tracing::info!("Cleaned up {} unused TAP interfaces", removed);
         }
     }
 
     /// Clean up TAP interface
     pub async fn cleanup_networking(&self) -> Result<(), ExecutionError> {
         // Only attempt cleanup if not in test mode
-        if !is_test_mode() && !self.tap_interface.starts_with("test-") {
-            let _ = tokio::process::Command::new("sudo")
-                .arg("ip")
-                .arg("link")
-                .arg("delete")
-                .arg(&self.tap_interface)
-                .status()
-                .await;
-        }
+        if !is_test_mode()
+            && !self.tap_interface.starts_with("test-")
+            && self.guest_transport.uses_tap()
+        {
+            if self.applied_shape.is_some() {
+                shaping::remove(&HostCommands, &self.tap_interface).await;
+            }
+            let deleted = HostCommands
+                .run("sudo", &["-n", "ip", "link", "delete", &self.tap_interface])
+                .await
+                .is_ok_and(|output| output.success);
+            if !deleted {
+                return Err(ExecutionError::ResourceError(format!(
+                    "Failed to delete TAP interface {}",
+                    self.tap_interface
+                )));
+            }
+            self.tap_registry().release(&self.tap_interface);
+        }
 
         Ok(())
     }
 
-    /// Wait for the VM API server to be ready
-    pub async fn wait_for_api_server(&self) -> Result<(), ExecutionError> {
+    /// Wait for the VM API server to be ready, noting the protocol version it reports. Waits
+    /// the boot timeout or what is left before `deadline`, whichever is shorter.
+    pub async fn wait_for_api_server(&mut self, deadline: Deadline) -> Result<(), ExecutionError> {
+        // Too little time left to see the agent come up
+        deadline.budget_for(Phase::Boot)?;
+        let wait = deadline.cap(Duration::from_secs(VM_BOOT_TIMEOUT_SECONDS));
         // In test mode, simulate successful API server readiness
-        if is_test_mode() {
+        if self.simulated() {
             tracing::debug!("Skipping API server wait in test mode");
+            self.set_agent_protocol(Some(guest_protocol::HOST_PROTOCOL_VERSION));
             return Ok(());
         }
-        let client = reqwest::Client::new();
-        let health_url = format!("http://{}:8080/health", self.vm_ip);
+        let guest = self.guest();
+        let give_up = tokio::time::Instant::now() + wait;
 
         // Wait for the API server to be ready with more aggressive timing
         let mut attempt = 0;
@@ -370,24 +1412,37 @@ impl VMManager {
                 delay_ms = (delay_ms * 2).min(max_delay_ms);
             }
 
-            if attempt > VM_BOOT_TIMEOUT_SECONDS * 10 {
-                // ~15 seconds total with exponential backoff
+            let left = give_up.saturating_duration_since(tokio::time::Instant::now());
+            if left.is_zero() {
                 break;
             }
 
-            match client
-                .get(&health_url)
-                .timeout(Duration::from_secs(2))
-                .send()
-                .await
-            {
+            let response = guest.get("/health", left.min(Duration::from_secs(2))).await;
+            if let Ok(response) = &response {
+                self.record(Interaction::new(
+                    Target::Agent,
+                    "GET",
+                    "/health",
+                    None,
+                    response.status().as_u16(),
+                ));
+            }
+            match response {
                 Ok(response) if response.status().is_success() => {
                     tracing::info!(
                         "VM API server at {} is ready after {} attempts ({:.1}s)",
-                        self.vm_ip,
+                        self.guest_transport,
                         attempt,
                         (attempt as f64 * delay_ms as f64 / 2000.0)
                     );
+                    // Agents from before the handshake answer without a version
+                    let reported = response
+                        .json::<serde_json::Value>()
+                        .await
+                        .ok()
+                        .and_then(|health| health["protocol_version"].as_u64())
+                        .and_then(|version| u32::try_from(version).ok());
+                    self.set_agent_protocol(reported);
                     return Ok(());
                 }
                 Ok(response) => {
@@ -398,7 +1453,12 @@ impl VMManager {
                     );
                 }
                 Err(e) => {
-                    tracing::debug!("Health check attempt {} for {}: {}", attempt, self.vm_ip, e);
+                    tracing::debug!(
+                        "Health check attempt {} for {}: {}",
+                        attempt,
+                        self.guest_transport,
+                        e
+                    );
                 }
             }
         }
@@ -412,43 +1472,281 @@ impl VMManager {
             .unwrap_or_else(|e| format!("Failed to read stderr log: {e}"));
 
         let log_details = format!(
-            "VM API server at {} did not become ready within {} seconds\n\nFirecracker stdout:\n{}\n\nFirecracker stderr:\n{}",
-            self.vm_ip, VM_BOOT_TIMEOUT_SECONDS, stdout_log, stderr_log
+            "VM API server at {} did not become ready within {:.1} seconds\n\nFirecracker stdout:\n{}\n\nFirecracker stderr:\n{}",
+            self.guest_transport,
+            wait.as_secs_f64(),
+            stdout_log,
+            stderr_log
         );
 
         Err(ExecutionError::TimeoutErrorWithLogs(log_details))
     }
 
-    /// Execute code via the VM's HTTP API
+    /// Note the protocol version the guest agent reported, if any
+    fn set_agent_protocol(&mut self, reported: Option<u32>) {
+        self.agent_protocol = reported;
+        if reported.is_none_or(|version| version < guest_protocol::HOST_PROTOCOL_VERSION) {
+            tracing::warn!(
+                vm_id = %self.vm_id,
+                agent = guest_protocol::negotiate(reported),
+                host = guest_protocol::HOST_PROTOCOL_VERSION,
+                "Guest agent speaks an older protocol; rebuild the rootfs for every feature"
+            );
+        }
+        VM_REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(self.vm_id.clone())
+            .and_modify(|vm| vm.agent_protocol = reported);
+    }
+
+    /// Protocol spoken with the guest agent, as negotiated when it came up
+    pub fn agent_protocol(&self) -> u32 {
+        guest_protocol::negotiate(self.agent_protocol)
+    }
+
+    /// Execute code via the VM's HTTP API, capping each output stream at `max_output_bytes`,
+    /// sending the output to `output` as it is printed, and giving up once `deadline` passes
     pub async fn execute_code_via_api(
         &self,
-        code: &str,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        max_output_bytes: usize,
+        deadline: Deadline,
+        output: Option<&OutputSink>,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        let live = output.filter(|_| self.backend().streams_output(self));
+        let execution =
+            self.backend()
+                .execute(self, program, requirements, inputs, max_output_bytes, live);
+        let mut response = deadline.run(Phase::Execute, execution).await?;
+        // Backends without guest workspaces still name the execution for tracing
+        response
+            .exec_id
+            .get_or_insert_with(|| uuid::Uuid::new_v4().to_string());
+        // Older agents ignore the limit, so enforce it here as well
+        response.stdout_truncated |= output::truncate_stream(
+            &mut response.stdout,
+            response.stdout_encoding,
+            max_output_bytes,
+        );
+        response.stderr_truncated |= output::truncate_stream(
+            &mut response.stderr,
+            response.stderr_encoding,
+            max_output_bytes,
+        );
+        response.warnings.extend(Warning::output_truncated(
+            &[
+                ("stdout", response.stdout_truncated),
+                ("stderr", response.stderr_truncated),
+            ],
+            max_output_bytes,
+        ));
+        if let Some(usage) = &response.usage {
+            usage.record();
+        }
+        // Backends that see output only once the code exits send it all now
+        if let Some(output) = output
+            && live.is_none()
+        {
+            send_output(output, &response);
+        }
+        Ok(response)
+    }
+
+    /// Check on this pooled VM with its backend, giving it `timeout` to answer
+    pub(crate) async fn check_health(&self, timeout: Duration) -> Result<(), ExecutionError> {
+        self.backend().check_health(self, timeout).await
+    }
+
+    /// Check the guest agent deleted the workspace of every execution it ran, so no execution
+    /// can read files another left; agents without workspaces have nothing to check
+    pub(crate) async fn verify_workspaces_removed(&self) -> Result<(), ExecutionError> {
+        if !guest_protocol::supports(self.agent_protocol(), GuestFeature::Workspace) {
+            return Ok(());
+        }
+        let leftover = self
+            .backend()
+            .leftover_workspaces(self, WORKSPACE_CHECK_TIMEOUT)
+            .await;
+        debug_events::record(
+            DebugEvent::new(DebugEventKind::HealthProbe)
+                .vm(&self.vm_id)
+                .detail(match leftover {
+                    Ok(0) => "clean",
+                    Ok(_) => "workspace_leftover",
+                    Err(_) => "unreachable",
+                }),
+        );
+        match leftover? {
+            0 => Ok(()),
+            leftover => Err(ExecutionError::ResourceError(format!(
+                "{leftover} execution workspaces were left behind in the guest"
+            ))),
+        }
+    }
+
+    /// Ask the guest agent how many execution workspaces it failed to delete, giving it
+    /// `timeout` to answer
+    pub(crate) async fn agent_leftover_workspaces(
+        &self,
+        timeout: Duration,
+    ) -> Result<u64, ExecutionError> {
+        if self.simulated() {
+            return Ok(0);
+        }
+        let response = self.guest().get("/health", timeout).await.map_err(|e| {
+            ExecutionError::ApiCommunicationError(format!("Workspace check failed: {e}"))
+        })?;
+        self.record(Interaction::new(
+            Target::Agent,
+            "GET",
+            "/health",
+            None,
+            response.status().as_u16(),
+        ));
+        if !response.status().is_success() {
+            return Err(ExecutionError::ApiCommunicationError(format!(
+                "Workspace check failed with status {}",
+                response.status()
+            )));
+        }
+        let health: serde_json::Value = response.json().await.map_err(|e| {
+            ExecutionError::ApiCommunicationError(format!("Workspace check failed: {e}"))
+        })?;
+        health["leftover_workspaces"].as_u64().ok_or_else(|| {
+            ExecutionError::ApiCommunicationError(
+                "Workspace check failed: the agent reported no leftover_workspaces".to_string(),
+            )
+        })
+    }
+
+    /// Ask the guest agent whether it is up, giving it `timeout` to answer
+    pub(crate) async fn check_agent_health(&self, timeout: Duration) -> Result<(), ExecutionError> {
+        if self.simulated() {
+            #[cfg(feature = "chaos")]
+            if self.fault.is_some() {
+                return Err(ExecutionError::ApiCommunicationError(
+                    "Health check failed: VM unreachable".to_string(),
+                ));
+            }
+            return Ok(());
+        }
+        let response = self.guest().get("/health", timeout).await.map_err(|e| {
+            ExecutionError::ApiCommunicationError(format!("Health check failed: {e}"))
+        })?;
+        self.record(Interaction::new(
+            Target::Agent,
+            "GET",
+            "/health",
+            None,
+            response.status().as_u16(),
+        ));
+        if !response.status().is_success() {
+            return Err(ExecutionError::ApiCommunicationError(format!(
+                "Health check failed with status {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Send the execution to the guest agent
+    pub(crate) async fn request_execution(
+        &self,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        max_output_bytes: usize,
+        output: Option<&OutputSink>,
     ) -> Result<ExecuteResponse, ExecutionError> {
+        let setup_requested = !requirements.is_empty();
+        // Fail before sending what the agent would silently ignore
+        let protocol = self.agent_protocol();
+        guest_protocol::check(
+            protocol,
+            guest_protocol::features_of(program, requirements, inputs),
+        )?;
+        let exec_id = uuid::Uuid::new_v4().to_string();
         // In test mode, return a mock response to test the handler logic
-        if is_test_mode() {
+        if self.simulated() {
+            // A real request would fail to reach a killed or disconnected VM
+            #[cfg(feature = "chaos")]
+            if self.fault.is_some() {
+                return Err(ExecutionError::ApiCommunicationError(
+                    "Failed to send request: VM unreachable".to_string(),
+                ));
+            }
             tracing::debug!("Returning mock response in test mode");
-            return Ok(ExecuteResponse {
-                stdout: format!("Mock execution of: {code}\n"),
-                stderr: "".to_string(),
-                success: true,
-            });
+            let mut response = backend::mock_response(program, requirements, inputs);
+            response.exec_id = Some(exec_id);
+            return Ok(response);
+        }
+        let mut request_body = match program {
+            Program::Code(code) => serde_json::json!({ "code": code }),
+            Program::Eval(code) => serde_json::json!({ "code": code, "mode": "eval" }),
+            Program::Files { files, entrypoint } => {
+                serde_json::json!({ "files": files, "entrypoint": entrypoint })
+            }
+        };
+        // Output is truncated on the host as well, so older agents can go without
+        if guest_protocol::supports(protocol, GuestFeature::OutputLimit) {
+            request_body["max_output_bytes"] = max_output_bytes.into();
+        }
+        // Older agents run everything in their own directory, which the pool check can't see
+        if guest_protocol::supports(protocol, GuestFeature::Workspace) {
+            request_body["exec_id"] = exec_id.clone().into();
+        }
+        // Only asked of agents that stream, as `streams_output` tells
+        if output.is_some() {
+            request_body["stream"] = true.into();
+        }
+        // Installation has its own, longer timeout inside the guest
+        let mut request_timeout = Duration::from_secs(VM_EXECUTE_TIMEOUT_SECONDS);
+        if setup_requested {
+            request_body["requirements"] = requirements.into();
+            request_timeout += Duration::from_secs(VM_SETUP_TIMEOUT_SECONDS);
+        }
+        if !inputs.is_empty() {
+            request_body["inputs"] = serde_json::json!(inputs);
+            request_body["max_input_bytes"] = self.config.inputs.max_bytes.into();
+            request_timeout += Duration::from_secs(VM_INPUTS_TIMEOUT_SECONDS);
         }
-        let client = reqwest::Client::new();
-        let execute_url = format!("http://{}:8080/execute", self.vm_ip);
-
-        let request_body = serde_json::json!({
-            "code": code
-        });
 
-        let response = client
-            .post(&execute_url)
-            .json(&request_body)
-            .timeout(Duration::from_secs(VM_EXECUTE_TIMEOUT_SECONDS)) // 5 seconds buffer over the VM's 30s timeout
-            .send()
+        // Only kernel messages printed during this execution say whether it ran out of memory
+        let console = Path::new(&self.stdout_log_path);
+        let console_start = oom::console_len(console);
+        // 5 seconds buffer over the VM's 30s timeout
+        let mut response = match self
+            .guest()
+            .post_json("/execute", &request_body, request_timeout)
             .await
-            .map_err(|e| {
-                ExecutionError::ApiCommunicationError(format!("Failed to send request: {e}"))
-            })?;
+        {
+            Ok(response) => {
+                debug_events::record(DebugEvent::new(DebugEventKind::FirstByte).vm(&self.vm_id));
+                response
+            }
+            Err(e) => {
+                // Code run inside the agent takes it down when the OOM killer picks it
+                if let Some(kill) =
+                    oom::oom_kills(&oom::console_since(console, console_start)).pop()
+                {
+                    crate::telemetry::increment_counter(
+                        "fc_guest_oom_kills_total",
+                        &[("victim", "agent")],
+                        1,
+                    );
+                    return Err(ExecutionError::GuestOutOfMemory {
+                        process: format!("{} (pid {})", kill.process, kill.pid),
+                        mem_size_mib: self.mem_size_mib(),
+                    });
+                }
+                return Err(ExecutionError::ApiCommunicationError(format!(
+                    "Failed to send request: {e}"
+                )));
+            }
+        };
 
         if !response.status().is_success() {
             return Err(ExecutionError::ApiCommunicationError(format!(
@@ -457,35 +1755,90 @@ impl VMManager {
             )));
         }
 
-        let api_response: serde_json::Value = response.json().await.map_err(|e| {
-            ExecutionError::ApiCommunicationError(format!("Failed to parse response: {e}"))
-        })?;
+        // Decoded as it arrives, so output past the cap is never buffered and a guest that
+        // ignores the cap is cut off instead of read to the end
+        let read_error = |e: guest_client::GuestError| {
+            ExecutionError::ApiCommunicationError(format!("Failed to read response: {e}"))
+        };
+        let api_response = match output {
+            Some(output) => {
+                let mut decoder = LiveDecoder::new(max_output_bytes);
+                let mut sent = LiveOutputSent::default();
+                while let Some(chunk) = response.chunk().await.map_err(read_error)? {
+                    for line in decoder.feed(&chunk).map_err(parse_error)? {
+                        sent.forward(output, line, max_output_bytes);
+                    }
+                }
+                decoder.finish().map_err(parse_error)?
+            }
+            None => {
+                let mut decoder = ResponseDecoder::new(max_output_bytes);
+                while let Some(chunk) = response.chunk().await.map_err(read_error)? {
+                    decoder.feed(&chunk).map_err(parse_error)?;
+                }
+                decoder.finish().map_err(parse_error)?
+            }
+        };
 
+        let oom_killed = api_response["oom_killed"].as_bool().unwrap_or(false);
+        let mut stderr = api_response["stderr"].as_str().unwrap_or("").to_string();
+        if oom_killed {
+            crate::telemetry::increment_counter(
+                "fc_guest_oom_kills_total",
+                &[("victim", "code")],
+                1,
+            );
+            stderr.push_str(&oom::oom_note(self.mem_size_mib()));
+        }
         Ok(ExecuteResponse {
             stdout: api_response["stdout"].as_str().unwrap_or("").to_string(),
-            stderr: api_response["stderr"].as_str().unwrap_or("").to_string(),
+            stderr,
+            oom_killed,
             success: api_response["success"].as_bool().unwrap_or(false),
+            stdout_truncated: api_response["stdout_truncated"].as_bool().unwrap_or(false),
+            stderr_truncated: api_response["stderr_truncated"].as_bool().unwrap_or(false),
+            stdout_encoding: OutputEncoding::from_agent(&api_response["stdout_encoding"]),
+            stderr_encoding: OutputEncoding::from_agent(&api_response["stderr_encoding"]),
+            exception: ExceptionInfo::from_agent(&api_response["exception"]),
+            usage: ExecutionUsage::from_agent(&api_response["usage"]),
+            // `null` is the value of expressions like `print(...)`; it isn't reported
+            value: Some(api_response["value"].clone()).filter(|value| !value.is_null()),
+            value_repr: api_response["value_repr"].as_str().map(str::to_string),
+            setup_stdout: api_response["setup_stdout"].as_str().map(str::to_string),
+            setup_stderr: api_response["setup_stderr"].as_str().map(str::to_string),
+            setup_error: SetupError::from_agent(&api_response["setup_error"]),
+            exec_id: Some(exec_id),
+            ..Default::default()
         })
     }
 
-    /// Shutdown the VM via API
-    pub async fn shutdown_vm(&mut self) -> Result<(), ExecutionError> {
-        let client = reqwest::Client::new();
-        let shutdown_url = format!("http://{}:8080/shutdown", self.vm_ip);
-
-        // Send shutdown request, but don't wait for response since VM will shutdown
-        let _ = client
-            .post(&shutdown_url)
-            .timeout(Duration::from_secs(5))
-            .send()
-            .await;
-
-        // Wait for the VM process to exit
-        if let Some(mut process) = self.process.take() {
-            let _ = timeout(Duration::from_secs(10), process.wait()).await;
+    /// Shut the VM down, escalating from the guest agent to Ctrl+Alt+Del to killing the process
+    pub async fn shutdown_vm(&mut self) -> Result<ShutdownMethod, ExecutionError> {
+        #[cfg(feature = "chaos")]
+        self.inject_fault(FaultPoint::Shutdown).await?;
+        let method = if self.process.is_none() {
+            ShutdownMethod::NotRunning
+        } else {
+            let vm_id = self.vm_id.clone();
+            shutdown::escalate(&vm_id, self).await
+        };
+        crate::telemetry::increment_counter(
+            "fc_vm_shutdowns_total",
+            &[("method", method.as_str())],
+            1,
+        );
+        if method != ShutdownMethod::Failed {
+            self.process = None;
         }
+        Ok(method)
+    }
 
-        Ok(())
+    /// Whether the Firecracker process exits within `deadline`
+    async fn wait_for_exit(&mut self, deadline: Duration) -> bool {
+        match self.process.as_mut() {
+            Some(process) => timeout(deadline, process.wait()).await.is_ok(),
+            None => true,
+        }
     }
 
     /// Start the Firecracker process
@@ -496,208 +1849,1263 @@ impl VMManager {
             return Ok(());
         }
         let stdout_log_file = std::fs::File::create(&self.stdout_log_path)
-            .map_err(|e| ExecutionError::ResourceError(format!("cannot create stdout log: {e}")))?;
+            .map_err(|e| fd_limit::open_error("cannot create stdout log", e))?;
         let stderr_log_file = std::fs::File::create(&self.stderr_log_path)
-            .map_err(|e| ExecutionError::ResourceError(format!("cannot create stderr log: {e}")))?;
+            .map_err(|e| fd_limit::open_error("cannot create stderr log", e))?;
+
+        let mut command = if let Some(jail) = &self.jail {
+            self.prepare_jail(jail)?;
+            let mut command = tokio::process::Command::new(&jail.config().jailer_bin);
+            command.args(jail.args());
+            if self.config.boot_from_config_file {
+                command.arg("--config-file").arg(self.write_config_file()?);
+            }
+            command
+        } else {
+            // Firecracker's logger and metrics sinks must exist before they are configured
+            std::fs::File::create(&self.fc_log_path)
+                .map_err(|e| fd_limit::open_error("cannot create firecracker log", e))?;
+            std::fs::File::create(&self.fc_metrics_path)
+                .map_err(|e| fd_limit::open_error("cannot create firecracker metrics file", e))?;
+            let mut command = tokio::process::Command::new("firecracker");
+            command.arg("--api-sock").arg(&self.socket_path);
+            if self.config.boot_from_config_file {
+                command.arg("--config-file").arg(self.write_config_file()?);
+            }
+            command
+        };
 
-        let child = tokio::process::Command::new("firecracker")
-            .arg("--api-sock")
-            .arg(&self.socket_path)
+        let child = command
             .stdin(Stdio::null())
             .stdout(stdout_log_file)
             .stderr(stderr_log_file)
             .spawn()
             .map_err(|e| {
+                if fd_limit::is_exhausted(&e) {
+                    return fd_limit::open_error("Failed to start Firecracker", e);
+                }
                 ExecutionError::ProcessSpawnError(format!("Failed to start Firecracker: {e}"))
             })?;
         self.process = Some(child);
-        tokio::time::sleep(Duration::from_millis(100)).await; // Give time for socket to be created
+        VM_REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(self.vm_id.clone(), self.live_record());
+        // Give the socket up to 100ms to be created
+        let deadline = std::time::Instant::now() + Duration::from_millis(100);
+        while !std::path::Path::new(&self.socket_path).exists()
+            && std::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        Ok(())
+    }
+
+    /// Populate the chroot with the kernel, rootfs and the files Firecracker writes to
+    fn prepare_jail(&self, jail: &Jail) -> Result<(), ExecutionError> {
+        let stage = |source: &str, name: &str| {
+            jail.stage(std::path::Path::new(source), name).map_err(|e| {
+                ExecutionError::ResourceError(format!("cannot stage {source} into jail: {e}"))
+            })
+        };
+        let artifacts = self.config.artifacts.for_host();
+        stage(&artifacts.kernel.to_string_lossy(), "vmlinux.bin")?;
+        let rootfs = self
+            .image
+            .as_ref()
+            .and_then(|name| self.config.image_path(name))
+            .unwrap_or_else(|| artifacts.rootfs.clone());
+        stage(&rootfs.to_string_lossy(), "rootfs.ext4")?;
+        if let Some(source) = self
+            .deps_profile
+            .as_ref()
+            .and_then(|profile| self.config.deps_profiles.get(profile))
+        {
+            stage(&source.to_string_lossy(), "deps.ext4")?;
+        }
+        for (name, _) in &self.reference_drives {
+            if let Some(source) = self.config.reference_data.get(name) {
+                let staged = format!("{}.img", reference_data::drive_id(name));
+                stage(&source.to_string_lossy(), &staged)?;
+            }
+        }
+        for path in [&self.fc_log_path, &self.fc_metrics_path] {
+            jail.create_file(&self.firecracker_path(path))
+                .map_err(|e| {
+                    ExecutionError::ResourceError(format!("cannot create {path} in jail: {e}"))
+                })?;
+        }
         Ok(())
     }
 
-    /// Send HTTP request to Firecracker API via Unix socket
+    /// Send HTTP request to Firecracker API via Unix socket, returning the status and body of
+    /// a successful response; fails after `API_REQUEST_TIMEOUT` without one
     async fn send_api_request(
         &self,
         method: Method,
         path: &str,
         body: Option<&str>,
-    ) -> Result<(), ExecutionError> {
-        let client: Client<UnixConnector, Full<Bytes>> =
-            Client::builder(TokioExecutor::new()).build(UnixConnector);
-        let uri: Uri = hyperlocal::Uri::new(&self.socket_path, path).into();
-        let mut request_builder = Request::builder().method(method.clone()).uri(uri);
-
-        let request = if let Some(json_body) = body {
-            request_builder = request_builder.header("content-type", "application/json");
-            request_builder
-                .body(Full::new(Bytes::from(json_body.to_string())))
-                .map_err(|e| {
-                    ExecutionError::ApiCommunicationError(format!("Request build failed: {e}"))
-                })?
-        } else {
-            request_builder.body(Full::new(Bytes::new())).map_err(|e| {
-                ExecutionError::ApiCommunicationError(format!("Request build failed: {e}"))
-            })?
-        };
+    ) -> Result<(StatusCode, Bytes), ExecutionError> {
+        let (status, response) = api_exchange(
+            &self.socket_path,
+            method.clone(),
+            path,
+            body,
+            API_REQUEST_TIMEOUT,
+        )
+        .await?;
+        self.record(
+            Interaction::new(
+                Target::Firecracker,
+                method.as_str(),
+                path,
+                body,
+                status.as_u16(),
+            )
+            .with_response(String::from_utf8_lossy(&response)),
+        );
+        successful_response(&method, path, status, response)
+    }
 
-        let response = client.request(request).await.map_err(|e| {
-            ExecutionError::ApiCommunicationError(format!("API request failed: {e}"))
-        })?;
+    /// Body of the Firecracker API's answer to `GET path`
+    async fn api_get(&self, path: &str) -> Result<Bytes, ExecutionError> {
+        let (_, body) = self.send_api_request(Method::GET, path, None).await?;
+        Ok(body)
+    }
 
-        let status = response.status();
-        if !status.is_success() {
-            use http_body_util::BodyExt;
-            let body_bytes = response
-                .collect()
-                .await
-                .map_err(|e| {
-                    ExecutionError::ApiCommunicationError(format!(
-                        "Failed to read error response: {e}"
-                    ))
-                })?
-                .to_bytes();
-            let error_body = String::from_utf8_lossy(&body_bytes);
-            return Err(ExecutionError::ApiCommunicationError(format!(
-                "API returned error status: {status} for {method} {path}. Error details: {error_body}"
-            )));
+    /// Set the VM resource at `path` to the JSON `body`
+    async fn api_put(&self, path: &str, body: &str) -> Result<(), ExecutionError> {
+        self.send_api_request(Method::PUT, path, Some(body))
+            .await
+            .map(drop)
+    }
+
+    /// Change some fields of the part of the VM at `path` to those in the JSON `body`
+    async fn api_patch(&self, path: &str, body: &str) -> Result<(), ExecutionError> {
+        self.send_api_request(Method::PATCH, path, Some(body))
+            .await
+            .map(drop)
+    }
+
+    /// Append `interaction` to this VM's session when API recording is on
+    fn record(&self, interaction: Interaction) {
+        let Some(dir) = &self.config.api_record_dir else {
+            return;
+        };
+        let interaction = replay::Placeholders::for_vm(self).abstract_interaction(interaction);
+        let path = dir.join(format!("api-{}.jsonl", self.vm_id));
+        if let Err(e) = replay::append(&path, &interaction) {
+            tracing::warn!("Failed to record API session {}: {}", path.display(), e);
         }
-        Ok(())
     }
 
-    /// Configure the VM via HTTP API and starts it
-    pub async fn configure_and_run_vm(&self) -> Result<(), ExecutionError> {
-        // In test mode, simulate successful configuration
-        if is_test_mode() {
-            tracing::debug!("Skipping VM configuration in test mode");
-            return Ok(());
+    /// Talk to the Firecracker API at `socket_path` and the guest agent at `agent_url`
+    /// instead of the VM's own, as when they are served by a replay server. The version is
+    /// asked of the new API, and the calls are made even in test mode.
+    pub fn use_api_endpoints(&mut self, socket_path: &str, agent_url: &str) {
+        self.socket_path = socket_path.to_string();
+        self.agent_url = Some(agent_url.trim_end_matches('/').to_string());
+        self.firecracker_version = None;
+    }
+
+    /// Whether API calls are skipped and their results made up, as in test mode
+    pub(crate) fn simulated(&self) -> bool {
+        is_test_mode() && self.agent_url.is_none()
+    }
+
+    /// Client of the guest agent, over the VM's transport
+    fn guest(&self) -> GuestClient {
+        GuestClient::new(self.guest_transport.clone()).with_base_url(self.agent_url.clone())
+    }
+
+    /// Give idle memory back to the host before the VM is parked in the pool
+    pub async fn inflate_balloon(&mut self) {
+        let config = self.config.clone();
+        if let Err(e) = balloon::on_release(self, &config.balloon).await {
+            tracing::warn!("Failed to inflate balloon of VM {}: {}", self.vm_id, e);
         }
-        let machine_config = tokio::fs::read_to_string("fixtures/machine.json")
-            .await
-            .map_err(|e| {
-                ExecutionError::ResourceError(format!("Failed to read machine config: {e}"))
-            })?;
-        let machine_config: serde_json::Value = serde_json::from_str(&machine_config).unwrap();
-        self.send_api_request(
-            Method::PUT,
-            "/machine-config",
-            Some(&machine_config.to_string()),
-        )
-        .await
-        .map_err(|e| {
-            ExecutionError::ApiCommunicationError(format!("Machine config failed: {e}"))
-        })?;
+    }
 
-        let host_ip = {
-            let vm_ip_parts: Vec<&str> = self.vm_ip.split('.').collect();
-            let subnet_id = vm_ip_parts[2];
-            format!("172.16.{subnet_id}.1")
+    /// Return the guest its full memory before it runs code
+    pub async fn deflate_balloon(&mut self) -> Result<(), ExecutionError> {
+        let config = self.config.clone();
+        balloon::on_acquire(self, &config.balloon).await
+    }
+
+    /// Kernel command line: networking plus whatever the guest agent needs for this VM
+    fn boot_args(&self) -> String {
+        let arch = Arch::host().unwrap_or(Arch::X86_64);
+        let mut boot_args = match &self.guest_transport {
+            GuestTransport::TcpOverTap { .. } => format!(
+                "{} ip={}::{}:255.255.255.0::eth0:off",
+                arch.boot_args(),
+                self.vm_ip,
+                self.host_ip()
+            ),
+            // The agent listens on the vsock port instead of the network
+            GuestTransport::Vsock { port, .. } => {
+                format!("{} fc_agent_vsock_port={port}", arch.boot_args())
+            }
         };
-        let boot_args = format!(
-            "console=ttyS0 reboot=k panic=1 pci=off init=/usr/local/bin/startup.sh ip={}::{}:255.255.255.0::eth0:off",
-            self.vm_ip, host_ip
-        );
-        let boot_source = serde_json::json!({ "kernel_image_path": "./hello-vmlinux.bin", "boot_args": boot_args });
-        self.send_api_request(Method::PUT, "/boot-source", Some(&boot_source.to_string()))
-            .await
-            .map_err(|e| {
-                ExecutionError::ApiCommunicationError(format!("Boot source config failed: {e}"))
-            })?;
+        if self.deps_image_path.is_some() {
+            boot_args.push(' ');
+            boot_args.push_str(&deps::boot_arg());
+        }
+        if !self.reference_drives.is_empty() {
+            boot_args.push(' ');
+            boot_args.push_str(&reference_data::boot_arg(
+                self.reference_drives.iter().map(|(name, _)| name.as_str()),
+                self.deps_image_path.is_some(),
+            ));
+        }
+        if let Some(settings) = &self.deterministic {
+            for arg in settings.boot_args() {
+                boot_args.push(' ');
+                boot_args.push_str(&arg);
+            }
+        }
+        if self.guest_transport.uses_tap() {
+            for arg in self.config.guest_network.boot_args(self.dns.as_deref()) {
+                boot_args.push(' ');
+                boot_args.push_str(&arg);
+            }
+        }
+        boot_args
+    }
 
-        let rootfs = serde_json::json!({ "drive_id": "rootfs", "path_on_host": "./alpine-python-api.ext4", "is_root_device": true, "is_read_only": false });
-        self.send_api_request(Method::PUT, "/drives/rootfs", Some(&rootfs.to_string()))
-            .await
-            .map_err(|e| {
-                ExecutionError::ApiCommunicationError(format!("Rootfs config failed: {e}"))
-            })?;
+    /// Guest memory in MiB, as sent in `PUT /machine-config`
+    fn mem_size_mib(&self) -> u64 {
+        self.vm_config()
+            .map(|config| config.machine_config.mem_size_mib)
+            .unwrap_or(self.config.vm_memory_mib)
+    }
 
-        // Configure network interface
-        let network_config = serde_json::json!({
-            "iface_id": "eth0",
-            "guest_mac": "AA:FC:00:00:00:01",
-            "host_dev_name": self.tap_interface
-        });
-        self.send_api_request(
-            Method::PUT,
-            "/network-interfaces/eth0",
-            Some(&network_config.to_string()),
-        )
-        .await
-        .map_err(|e| {
-            ExecutionError::ApiCommunicationError(format!("Network config failed: {e}"))
+    /// Everything Firecracker is configured with before the instance starts
+    fn vm_config(&self) -> Result<VmConfig, ExecutionError> {
+        let config = &self.config;
+        let machine_config = std::fs::read_to_string("fixtures/machine.json").map_err(|e| {
+            ExecutionError::ResourceError(format!("Failed to read machine config: {e}"))
         })?;
+        let machine_config: MachineConfig = serde_json::from_str(&machine_config)
+            .map_err(|e| ExecutionError::ResourceError(format!("Invalid machine config: {e}")))?;
+
+        let mut drives = vec![Drive {
+            drive_id: "rootfs".to_string(),
+            path_on_host: self.firecracker_path(&self.rootfs_path),
+            is_root_device: true,
+            is_read_only: false,
+        }];
+        // Attached after the rootfs, so the guest sees it as the second block device
+        if let Some(image_path) = &self.deps_image_path {
+            drives.push(deps::drive_config(&self.firecracker_path(image_path)));
+        }
+        // In the order the boot argument announces them
+        for (name, image_path) in &self.reference_drives {
+            drives.push(reference_data::drive_config(
+                name,
+                &self.firecracker_path(image_path),
+            ));
+        }
 
-        let start_action = serde_json::json!({ "action_type": "InstanceStart" });
-        self.send_api_request(Method::PUT, "/actions", Some(&start_action.to_string()))
-            .await
-            .map_err(|e| ExecutionError::ApiCommunicationError(format!("VM start failed: {e}")))?;
-        Ok(())
+        Ok(VmConfig {
+            machine_config: machine_config
+                .with_options(&config.machine_options(self.deps_profile.as_deref())),
+            boot_source: BootSource {
+                kernel_image_path: self.firecracker_path(&self.kernel_image_path),
+                boot_args: self.boot_args(),
+            },
+            drives,
+            // A guest reached over vsock has no network
+            network_interfaces: if self.guest_transport.uses_tap() {
+                vec![NetworkInterface {
+                    iface_id: "eth0".to_string(),
+                    guest_mac: "AA:FC:00:00:00:01".to_string(),
+                    host_dev_name: self.tap_interface.clone(),
+                }]
+            } else {
+                Vec::new()
+            },
+            balloon: self
+                .balloon_enabled()
+                .then(|| BalloonDevice::new(&config.balloon)),
+            entropy: self.entropy_enabled().then(EntropyDevice::default),
+            vsock: (!self.guest_transport.uses_tap()).then(|| VsockDevice {
+                guest_cid: guest_client::GUEST_CID,
+                uds_path: self.firecracker_path(&self.vsock_path),
+            }),
+            // Firecracker's own logger and metrics output
+            logger: Logger {
+                log_path: self.firecracker_path(&self.fc_log_path),
+                level: config.firecracker_log_level.clone(),
+                show_level: true,
+                show_log_origin: false,
+            },
+            metrics: config.firecracker_metrics.then(|| Metrics {
+                metrics_path: self.firecracker_path(&self.fc_metrics_path),
+            }),
+        })
     }
 
-    /// Clean up VM resources
-    pub async fn cleanup(mut self) -> Result<(), ExecutionError> {
-        if let Some(mut process) = self.process.take() {
-            let _ = process.kill().await;
-            let _ = process.wait().await;
+    /// Keep the logs of this VM, which failed to boot with `error`, for boot diagnostics and
+    /// attach their report to a boot timeout's logs
+    async fn retain_boot_diagnostics(&mut self, error: ExecutionError) -> ExecutionError {
+        let diagnostics = &self.config.boot_diagnostics;
+        let firecracker_config = self
+            .vm_config()
+            .map(|config| config.config_file())
+            .unwrap_or_default();
+        let logs = BootLogs::read(
+            Path::new(&self.stdout_log_path),
+            Path::new(&self.fc_log_path),
+            &firecracker_config,
+        );
+        let message = error.to_string();
+        let report = BootReport::assemble(
+            &self.vm_id,
+            message.lines().next().unwrap_or_default(),
+            &self.boot_args(),
+            &logs,
+            diagnostics.console_lines,
+        );
+
+        let mut files = vec![
+            PathBuf::from(&self.stdout_log_path),
+            PathBuf::from(&self.stderr_log_path),
+        ];
+        if self.jail.is_some() {
+            // Cleanup removes the chroot whole, so the Firecracker log moves out of it
+            let copy = self
+                .config
+                .runtime_path(&format!("fc-log-{}.log", self.vm_id));
+            if tokio::fs::copy(&self.fc_log_path, &copy).await.is_ok() {
+                files.push(PathBuf::from(copy));
+            }
+        } else {
+            files.push(PathBuf::from(&self.fc_log_path));
+            files.push(PathBuf::from(&self.config_file_path));
         }
+        files.retain(|file| file.exists());
+        tracing::warn!(
+            vm_id = %self.vm_id,
+            files = files.len(),
+            "Keeping the logs of a VM that failed to boot"
+        );
+        let summary = report.summary();
+        BOOT_REPORTS.retain(report, files, diagnostics.retention);
+        self.keep_logs = true;
 
-        // Clean up networking
-        let _ = self.cleanup_networking().await;
+        match error {
+            ExecutionError::TimeoutErrorWithLogs(logs) => {
+                ExecutionError::TimeoutErrorWithLogs(format!("{logs}\n\n{summary}"))
+            }
+            error => error,
+        }
+    }
 
-        if tokio::fs::try_exists(&self.socket_path)
-            .await
-            .unwrap_or(false)
-        {
-            tokio::fs::remove_file(&self.socket_path)
-                .await
-                .map_err(|e| {
-                    ExecutionError::ResourceError(format!("Failed to remove socket: {e}"))
-                })?;
+    /// Write the `--config-file` of this VM, returning the path Firecracker should open
+    fn write_config_file(&self) -> Result<String, ExecutionError> {
+        let contents = self.vm_config()?.config_file();
+        let write_error = |e: std::io::Error| {
+            ExecutionError::ResourceError(format!("cannot write VM config: {e}"))
+        };
+        if let Some(jail) = &self.jail {
+            jail.create_file(VM_CONFIG_FILE_NAME).map_err(write_error)?;
         }
-        if tokio::fs::try_exists(&self.stdout_log_path)
-            .await
-            .unwrap_or(false)
-        {
-            tokio::fs::remove_file(&self.stdout_log_path)
-                .await
-                .map_err(|e| {
-                    ExecutionError::ResourceError(format!("Failed to remove stdout log: {e}"))
-                })?;
+        std::fs::write(&self.config_file_path, contents).map_err(write_error)?;
+        Ok(self.firecracker_path(&self.config_file_path))
+    }
+
+    /// Version reported by the Firecracker behind the API socket
+    pub async fn firecracker_version(&self) -> Option<FirecrackerVersion> {
+        let body = self.api_get("/version").await.ok()?;
+        FirecrackerVersion::from_api_body(&String::from_utf8_lossy(&body))
+    }
+
+    /// Whether this VM's Firecracker can take a balloon device, as configured
+    fn balloon_enabled(&self) -> bool {
+        self.config.balloon.enabled
+            && version::feature_available(
+                "the balloon device",
+                self.firecracker_version.as_ref(),
+                FirecrackerVersion::supports_balloon,
+            )
+    }
+
+    /// Whether this VM's Firecracker can take an entropy device, as configured
+    fn entropy_enabled(&self) -> bool {
+        self.config.entropy_device
+            && version::feature_available(
+                "the entropy device",
+                self.firecracker_version.as_ref(),
+                FirecrackerVersion::supports_entropy,
+            )
+    }
+
+    /// Configure the VM via HTTP API and starts it; a VM booted from a config file is
+    /// already running
+    pub async fn configure_and_run_vm(&mut self) -> Result<(), ExecutionError> {
+        // In test mode, simulate successful configuration
+        if self.simulated() {
+            tracing::debug!("Skipping VM configuration in test mode");
+            return Ok(());
         }
-        if tokio::fs::try_exists(&self.stderr_log_path)
-            .await
-            .unwrap_or(false)
-        {
-            tokio::fs::remove_file(&self.stderr_log_path)
+        if self.config.boot_from_config_file {
+            return Ok(());
+        }
+        // `--version` may have failed on this host; the API knows for sure
+        if self.firecracker_version.is_none() {
+            self.firecracker_version = self.firecracker_version().await;
+        }
+        let vm_config = self.vm_config()?;
+        for call in vm_config.api_calls() {
+            self.api_put(&call.path, &call.body)
                 .await
                 .map_err(|e| {
-                    ExecutionError::ResourceError(format!("Failed to remove stderr log: {e}"))
+                    if call.path == "/machine-config"
+                        && let Some(template) = &vm_config.machine_config.cpu_template
+                    {
+                        tracing::warn!(
+                            "Machine config with CPU template {} was rejected; the host CPU may not support it",
+                            template
+                        );
+                    }
+                    ExecutionError::ApiCommunicationError(format!("{} failed: {e}", call.what))
                 })?;
         }
+
+        let start_action = serde_json::json!({ "action_type": "InstanceStart" });
+        self.api_put("/actions", &start_action.to_string())
+            .await
+            .map_err(|e| ExecutionError::ApiCommunicationError(format!("VM start failed: {e}")))?;
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Inject the fault, if any, the configured injector picks for `point`
+    #[cfg(feature = "chaos")]
+    pub(crate) async fn inject_fault(&mut self, point: FaultPoint) -> Result<(), ExecutionError> {
+        match self
+            .config
+            .faults
+            .as_ref()
+            .and_then(|faults| faults.next(point))
+        {
+            Some(fault) => self.apply_fault(fault, point).await,
+            None => Ok(()),
+        }
+    }
 
-    #[tokio::test]
-    async fn test_vm_manager_creation() {
-        let vm_manager = VMManager::default();
-        assert!(!vm_manager.vm_id.is_empty());
-        assert!(vm_manager.socket_path.contains("/tmp/firecracker-"));
+    #[cfg(feature = "chaos")]
+    async fn apply_fault(&mut self, fault: Fault, point: FaultPoint) -> Result<(), ExecutionError> {
+        tracing::warn!(
+            "Injecting {:?} into VM {} at {:?}",
+            fault,
+            self.vm_id,
+            point
+        );
+        match fault {
+            Fault::Kill => {
+                if let Some(mut process) = self.process.take() {
+                    let _ = process.kill().await;
+                }
+            }
+            Fault::DropTap => {
+                let _ = self.cleanup_networking().await;
+            }
+            Fault::Error => {
+                return Err(ExecutionError::ApiCommunicationError(format!(
+                    "injected fault at {point:?}"
+                )));
+            }
+        }
+        self.fault = Some(fault);
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn test_vm_manager_cleanup() {
+    /// `execute_code_via_api`, with the mid-execute fault the injector picks, if any, striking
+    /// once the execution has run for the injector's delay
+    #[cfg(feature = "chaos")]
+    pub async fn execute_code_injecting_faults(
+        &mut self,
+        program: &Program,
+        requirements: &[String],
+        inputs: &[RemoteInput],
+        max_output_bytes: usize,
+        deadline: Deadline,
+        output: Option<&OutputSink>,
+    ) -> Result<ExecuteResponse, ExecutionError> {
+        let Some(faults) = self.config.faults.clone() else {
+            return self
+                .execute_code_via_api(
+                    program,
+                    requirements,
+                    inputs,
+                    max_output_bytes,
+                    deadline,
+                    output,
+                )
+                .await;
+        };
+        let Some(fault) = faults.next(FaultPoint::MidExecute) else {
+            return self
+                .execute_code_via_api(
+                    program,
+                    requirements,
+                    inputs,
+                    max_output_bytes,
+                    deadline,
+                    output,
+                )
+                .await;
+        };
+        let delay = faults.mid_execute_delay;
+        let finished = tokio::select! {
+            biased;
+            // A zero delay strikes as the request goes out, even against an instant mock
+            _ = async { if !delay.is_zero() { tokio::time::sleep(delay).await } } => None,
+            result = self.execute_code_via_api(program, requirements, inputs, max_output_bytes, deadline, output) => Some(result),
+        };
+        if let Some(result) = finished {
+            return result;
+        }
+        self.apply_fault(fault, FaultPoint::MidExecute).await?;
+        // The abandoned request is what a VM dying under it would have failed
+        Err(ExecutionError::ApiCommunicationError(
+            "Failed to read response: connection reset".to_string(),
+        ))
+    }
+
+    /// Signal the Firecracker process to die without waiting, for when no runtime is left to
+    /// run `cleanup`
+    pub(crate) fn kill_now(mut self) {
+        if let Some(process) = self.process.as_mut() {
+            let _ = process.start_kill();
+        }
+    }
+
+    /// Clean up VM resources
+    pub async fn cleanup(mut self) -> Result<(), ExecutionError> {
+        let mut report = CleanupReport::default();
+        if let Some(mut process) = self.process.take() {
+            report.record(
+                "process",
+                match process.kill().await {
+                    Ok(()) => StepOutcome::Done,
+                    // Already reaped, as after an injected kill
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => StepOutcome::Skipped,
+                    Err(e) => StepOutcome::Failed(e.to_string()),
+                },
+            );
+        }
+
+        report.record(
+            "networking",
+            match self.backend().tear_down(&self).await {
+                Ok(()) => StepOutcome::Done,
+                Err(e) => StepOutcome::Failed(e.to_string()),
+            },
+        );
+
+        VM_REGISTRY
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.vm_id);
+
+        // Fold this VM's Firecracker counters into the exporter before the file goes away
+        if let Ok(contents) = tokio::fs::read_to_string(&self.fc_metrics_path).await {
+            fc_metrics::summarize(&contents).record();
+        }
+
+        report.record("socket", remove_file(&self.socket_path).await);
+        if !self.guest_transport.uses_tap() {
+            report.record("vsock", remove_file(&self.vsock_path).await);
+        }
+        for (step, path) in [
+            ("stdout_log", &self.stdout_log_path),
+            ("stderr_log", &self.stderr_log_path),
+            ("firecracker_log", &self.fc_log_path),
+            ("config_file", &self.config_file_path),
+        ] {
+            let outcome = if self.keep_logs {
+                StepOutcome::Skipped
+            } else {
+                remove_file(path).await
+            };
+            report.record(step, outcome);
+        }
+        report.record(
+            "firecracker_metrics",
+            remove_file(&self.fc_metrics_path).await,
+        );
+        // The chroot also holds the staged kernel and rootfs
+        if let Some(jail) = &self.jail {
+            let outcome = match tokio::fs::remove_dir_all(jail.vm_dir()).await {
+                Ok(()) => StepOutcome::Done,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => StepOutcome::Skipped,
+                Err(e) => StepOutcome::Failed(e.to_string()),
+            };
+            report.record("jail", outcome);
+        }
+
+        if report.is_partial() {
+            crate::telemetry::increment_counter("fc_partial_cleanups_total", &[], 1);
+            tracing::warn!(vm_id = %self.vm_id, steps = %report, "cleanup_report");
+        } else {
+            tracing::debug!(vm_id = %self.vm_id, steps = %report, "cleanup_report");
+        }
+        report.into_result(&self.vm_id)
+    }
+}
+
+/// How one step of `VMManager::cleanup` went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    Done,
+    /// Nothing to do, as the file was already gone or kept on purpose
+    Skipped,
+    Failed(String),
+}
+
+/// Outcome of every step of `VMManager::cleanup`, which attempts them all whatever fails
+#[derive(Debug, Default)]
+pub struct CleanupReport {
+    steps: Vec<(&'static str, StepOutcome)>,
+}
+
+impl CleanupReport {
+    fn record(&mut self, step: &'static str, outcome: StepOutcome) {
+        self.steps.push((step, outcome));
+    }
+
+    /// Whether some steps failed
+    pub fn is_partial(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|(_, outcome)| matches!(outcome, StepOutcome::Failed(_)))
+    }
+
+    /// A `ResourceError` naming the failed steps and the ones that succeeded, if any failed
+    fn into_result(self, vm_id: &str) -> Result<(), ExecutionError> {
+        if !self.is_partial() {
+            return Ok(());
+        }
+        let failed: Vec<String> = self
+            .steps
+            .iter()
+            .filter_map(|(step, outcome)| match outcome {
+                StepOutcome::Failed(e) => Some(format!("{step} ({e})")),
+                _ => None,
+            })
+            .collect();
+        let done: Vec<&str> = self
+            .steps
+            .iter()
+            .filter(|(_, outcome)| *outcome == StepOutcome::Done)
+            .map(|(step, _)| *step)
+            .collect();
+        Err(ExecutionError::ResourceError(format!(
+            "Cleanup of VM {vm_id} failed: {}; done: {}",
+            failed.join(", "),
+            if done.is_empty() {
+                "nothing".to_string()
+            } else {
+                done.join(", ")
+            }
+        )))
+    }
+}
+
+impl std::fmt::Display for CleanupReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (step, outcome)) in self.steps.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match outcome {
+                StepOutcome::Done => write!(f, "{step}=done")?,
+                StepOutcome::Skipped => write!(f, "{step}=skipped")?,
+                StepOutcome::Failed(e) => write!(f, "{step}=failed({e})")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove one of a VM's files; one already gone is skipped
+async fn remove_file(path: &str) -> StepOutcome {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => StepOutcome::Done,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => StepOutcome::Skipped,
+        Err(e) => StepOutcome::Failed(e.to_string()),
+    }
+}
+
+impl Balloon for VMManager {
+    async fn set_balloon_target(&mut self, amount_mib: u64) -> Result<(), ExecutionError> {
+        let attached = self
+            .firecracker_version
+            .as_ref()
+            .is_some_and(FirecrackerVersion::supports_balloon);
+        if is_test_mode() || !attached {
+            return Ok(());
+        }
+        let body = serde_json::to_string(&BalloonUpdate { amount_mib }).unwrap_or_default();
+        self.api_patch("/balloon", &body).await
+    }
+}
+
+impl GuestClock for VMManager {
+    fn can_set_time(&self) -> bool {
+        guest_protocol::supports(self.agent_protocol(), GuestFeature::SetTime)
+    }
+
+    async fn set_guest_time(&self, epoch_ms: u64) -> Result<GuestTime, ExecutionError> {
+        // Host processes and mock VMs share the host clock
+        if self.simulated() || !self.config.backend.is_firecracker() {
+            return Ok(GuestTime {
+                previous_ms: epoch_ms as i64,
+                now_ms: epoch_ms as i64,
+            });
+        }
+        let response = self
+            .guest()
+            .post_json("/set-time", &SetTime { epoch_ms }, Duration::from_secs(1))
+            .await
+            .map_err(|e| {
+                ExecutionError::ApiCommunicationError(format!("Failed to set guest time: {e}"))
+            })?;
+        if !response.status().is_success() {
+            return Err(ExecutionError::ApiCommunicationError(format!(
+                "Failed to set guest time: status {}",
+                response.status()
+            )));
+        }
+        response.json().await.map_err(|e| {
+            ExecutionError::SerializationError(format!("Failed to parse set-time response: {e}"))
+        })
+    }
+}
+
+impl ShutdownSteps for VMManager {
+    async fn agent_shutdown(&mut self) -> bool {
+        // The agent replies before rebooting, so only the process exit tells us it worked
+        let _ = self
+            .guest()
+            .request(
+                Method::POST,
+                "/shutdown",
+                None,
+                shutdown::AGENT_SHUTDOWN_TIMEOUT,
+            )
+            .await;
+        self.wait_for_exit(shutdown::SHUTDOWN_STEP_TIMEOUT).await
+    }
+
+    async fn ctrl_alt_del(&mut self) -> bool {
+        let body = r#"{"action_type": "SendCtrlAltDel"}"#;
+        if let Err(e) = self.api_put("/actions", body).await {
+            tracing::debug!("SendCtrlAltDel failed for VM {}: {}", self.vm_id, e);
+            return false;
+        }
+        self.wait_for_exit(shutdown::SHUTDOWN_STEP_TIMEOUT).await
+    }
+
+    async fn kill(&mut self) -> bool {
+        match self.process.as_mut() {
+            Some(process) => process.kill().await.is_ok(),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_chunks_split_on_char_boundaries() {
+        assert!(output_chunks("").is_empty());
+        assert_eq!(output_chunks("hello"), vec!["hello"]);
+
+        let text = "é".repeat(OUTPUT_CHUNK_BYTES);
+        let pieces = output_chunks(&text);
+        assert!(pieces.iter().all(|piece| piece.len() <= OUTPUT_CHUNK_BYTES));
+        assert_eq!(pieces.concat(), text);
+    }
+
+    #[tokio::test]
+    async fn test_live_execution_sends_all_output_before_its_outcome() {
+        // A sink kept past the end of the execution doesn't hold the stream open either
+        let mut kept = None;
+        let mut live = LiveExecution::start(|output| {
+            kept = Some(output.clone());
+            async move {
+                let response = ExecuteResponse {
+                    stdout: "x".repeat(OUTPUT_CHUNK_BYTES + 1),
+                    stderr: "oops".to_string(),
+                    ..Default::default()
+                };
+                send_output(&output, &response);
+                7
+            }
+        });
+        let mut events = Vec::new();
+        while let Some(event) = live.next().await {
+            events.push(event);
+        }
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[1], Live::Output(OutputEvent::Stdout { data }) if data == "x"));
+        assert!(matches!(&events[2], Live::Output(OutputEvent::Stderr { data }) if data == "oops"));
+        assert!(matches!(events[3], Live::Finished(Ok(7))));
+        assert!(kept.is_some());
+
+        let OutputEvent::Result(result) = OutputEvent::result(ExecuteResponse {
+            stdout: "x".to_string(),
+            success: true,
+            ..Default::default()
+        }) else {
+            panic!("a result");
+        };
+        assert!(result.success && result.stdout.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events_for_mock_execution() {
+        let mut events = events::subscribe();
+        run_in_vm_for_request(ExecutionSpec {
+            request_id: "events-test-request".to_string(),
+            ..ExecutionSpec::code("print('events')")
+        })
+        .await
+        .unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        let vm_id = received
+            .iter()
+            .find_map(|event| match event {
+                VmEvent::Acquired { vm_id, request_id } if request_id == "events-test-request" => {
+                    Some(vm_id.clone())
+                }
+                _ => None,
+            })
+            .expect("acquired event should be published");
+        let names: Vec<_> = received
+            .iter()
+            .filter(|event| event.vm_id() == vm_id)
+            .map(VmEvent::name)
+            .collect();
+        assert_eq!(names, vec!["created", "boot_ready", "acquired", "released"]);
+    }
+
+    #[tokio::test]
+    async fn test_requirements_use_a_dedicated_vm() {
+        let mut events = events::subscribe();
+        let response = run_in_vm_for_request(ExecutionSpec {
+            request_id: "requirements-test-request".to_string(),
+            requirements: vec!["numpy".to_string()],
+            ..ExecutionSpec::code("import numpy")
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            response.setup_stdout.as_deref(),
+            Some("Mock install of: numpy\n")
+        );
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        let vm_id = received
+            .iter()
+            .find_map(|event| match event {
+                VmEvent::Acquired { vm_id, request_id }
+                    if request_id == "requirements-test-request" =>
+                {
+                    Some(vm_id.clone())
+                }
+                _ => None,
+            })
+            .expect("acquired event should be published");
+        // Never taken from nor returned to the shared pool
+        let names: Vec<_> = received
+            .iter()
+            .filter(|event| event.vm_id() == vm_id)
+            .map(VmEvent::name)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["created", "boot_ready", "acquired", "discarded"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_requests_use_a_fresh_vm() {
+        let mut events = events::subscribe();
+        let response = run_in_vm_for_request(ExecutionSpec {
+            request_id: "deterministic-test-request".to_string(),
+            deterministic: Some(DeterministicSettings::new(Some(1_700_000_000))),
+            ..ExecutionSpec::code("print(hash('a'))")
+        })
+        .await
+        .unwrap();
+        assert!(response.deterministic);
+        assert_eq!(response.hash_seed, Some(0));
+        assert_eq!(response.fake_time, Some(1_700_000_000));
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        let vm_id = received
+            .iter()
+            .find_map(|event| match event {
+                VmEvent::Acquired { vm_id, request_id }
+                    if request_id == "deterministic-test-request" =>
+                {
+                    Some(vm_id.clone())
+                }
+                _ => None,
+            })
+            .expect("acquired event should be published");
+        let names: Vec<_> = received
+            .iter()
+            .filter(|event| event.vm_id() == vm_id)
+            .map(VmEvent::name)
+            .collect();
+        assert_eq!(
+            names,
+            vec!["created", "boot_ready", "acquired", "discarded"]
+        );
+    }
+
+    #[test]
+    fn test_deterministic_boot_args() {
+        let vm = VMManager {
+            deterministic: Some(DeterministicSettings::new(Some(1_700_000_000))),
+            ..VMManager::default()
+        };
+        let boot_args = vm.boot_args();
+        for arg in [
+            "fc_deterministic=1",
+            "fc_hash_seed=0",
+            "fc_tz=UTC",
+            "fc_locale=C.UTF-8",
+            "fc_fake_time=1700000000",
+        ] {
+            assert!(boot_args.split(' ').any(|a| a == arg), "missing {arg}");
+        }
+        assert!(
+            !VMManager::default()
+                .boot_args()
+                .contains("fc_deterministic")
+        );
+    }
+
+    #[test]
+    fn test_vsock_vms_boot_without_a_network() {
+        let config = Arc::new(RunnerConfig {
+            guest_transport: crate::guest_client::GuestTransportConfig {
+                default: crate::guest_client::GuestTransportKind::Vsock,
+                ..Default::default()
+            },
+            guest_network: crate::guest_network::GuestNetworkConfig {
+                enabled: true,
+                dns: vec!["192.168.1.1".to_string()],
+                ..Default::default()
+            },
+            ..RunnerConfig::default()
+        });
+        let vm = VMManager::with_config(config);
+        assert!(!vm.guest_transport().uses_tap());
+        let boot_args = vm.boot_args();
+        assert!(!boot_args.contains("ip=") && !boot_args.contains("fc_dns"));
+        assert!(boot_args.ends_with(" fc_agent_vsock_port=8080"));
+        let vm_config = vm.vm_config().unwrap();
+        assert!(vm_config.network_interfaces.is_empty());
+        assert_eq!(vm_config.vsock.unwrap().uds_path, vm.vsock_path);
+        assert_eq!(
+            vm.guest().target("/health").endpoint,
+            crate::guest_client::Endpoint::Vsock {
+                uds_path: PathBuf::from(&vm.vsock_path),
+                port: 8080,
+            }
+        );
+
+        // The default transport keeps the TAP device
+        let vm = VMManager::default();
+        assert!(vm.boot_args().contains(&format!("ip={}::", vm.vm_ip)));
+        assert!(vm.vm_config().unwrap().vsock.is_none());
+    }
+
+    #[test]
+    fn test_guest_network_boot_args() {
+        let config = Arc::new(RunnerConfig {
+            guest_network: crate::guest_network::GuestNetworkConfig {
+                enabled: true,
+                dns: vec!["192.168.1.1".to_string()],
+                https_proxy: Some("http://proxy.lan:3128".to_string()),
+                ..Default::default()
+            },
+            ..RunnerConfig::default()
+        });
+        let pooled = VMManager {
+            config: config.clone(),
+            ..VMManager::default()
+        };
+        let boot_args = pooled.boot_args();
+        assert!(boot_args.contains(" fc_dns=192.168.1.1 "), "{boot_args}");
+        assert!(boot_args.ends_with(" fc_https_proxy=http://proxy.lan:3128"));
+
+        let overridden = VMManager {
+            config,
+            dns: Some(vec!["10.0.0.2".to_string(), "10.0.0.3".to_string()]),
+            ..VMManager::default()
+        };
+        assert!(
+            overridden
+                .boot_args()
+                .contains(" fc_dns=10.0.0.2,10.0.0.3 ")
+        );
+
+        // Without network access the guest is told nothing
+        assert!(!VMManager::default().boot_args().contains("fc_dns"));
+        assert!(
+            ExecutionSpec {
+                dns: Some(vec!["10.0.0.2".to_string()]),
+                ..ExecutionSpec::code("1")
+            }
+            .needs_dedicated_vm()
+        );
+    }
+
+    /// Runs every command, successfully or not
+    struct Tc {
+        success: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl CommandRunner for Tc {
+        async fn run(&self, _: &str, _: &[&str]) -> std::io::Result<tap::CommandOutput> {
+            Ok(tap::CommandOutput {
+                success: self.success,
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shape_traffic_is_fatal_or_warn_only() {
+        let shape = TrafficShape {
+            qdisc: crate::shaping::Qdisc::Tbf,
+            rate_kbit: 1000,
+            burst_bytes: 16384,
+            latency_ms: 50,
+        };
+        let config = |fatal| {
+            Arc::new(RunnerConfig {
+                shaping: crate::shaping::ShapingConfig {
+                    default: Some(shape.clone()),
+                    fatal,
+                    ..Default::default()
+                },
+                ..RunnerConfig::default()
+            })
+        };
+
+        let mut vm = VMManager::with_config(config(true));
+        vm.shape_traffic(&Tc { success: true }).await.unwrap();
+        assert_eq!(vm.applied_shape.as_ref(), Some(&shape));
+        assert_eq!(
+            live_vm(&vm.vm_id).unwrap().details().traffic_shape,
+            Some(shape.clone())
+        );
+        VM_REGISTRY.lock().unwrap().remove(&vm.vm_id);
+
+        let mut vm = VMManager::with_config(config(true));
+        assert!(matches!(
+            vm.shape_traffic(&Tc { success: false }).await,
+            Err(ExecutionError::ResourceError(_))
+        ));
+        let mut vm = VMManager::with_config(config(false));
+        vm.shape_traffic(&Tc { success: false }).await.unwrap();
+        assert!(vm.applied_shape.is_none());
+
+        // A request's shape replaces the configured one, and needs a VM of its own
+        let requested = TrafficShape {
+            rate_kbit: 50,
+            ..shape.clone()
+        };
+        let mut vm = VMManager {
+            traffic_shape: Some(requested.clone()),
+            ..VMManager::with_config(config(false))
+        };
+        vm.shape_traffic(&Tc { success: true }).await.unwrap();
+        assert_eq!(vm.applied_shape, Some(requested.clone()));
+        VM_REGISTRY.lock().unwrap().remove(&vm.vm_id);
+        assert!(
+            ExecutionSpec {
+                traffic_shape: Some(requested),
+                ..ExecutionSpec::code("1")
+            }
+            .needs_dedicated_vm()
+        );
+    }
+
+    #[test]
+    fn test_vm_config_includes_deps_drive() {
+        let vm = VMManager {
+            deps_image_path: Some("/srv/deps/numpy.ext4".to_string()),
+            ..VMManager::default()
+        };
+        let config = vm.vm_config().unwrap();
+        let drives: Vec<_> = config.drives.iter().map(|d| d.drive_id.as_str()).collect();
+        assert_eq!(drives, vec!["rootfs", "deps"]);
+        assert_eq!(config.network_interfaces[0].host_dev_name, vm.tap_interface);
+    }
+
+    #[test]
+    fn test_vm_config_includes_reference_drives() {
+        let vm = VMManager {
+            deps_image_path: Some("/srv/deps/numpy.ext4".to_string()),
+            reference_drives: vec![
+                ("geo".to_string(), "/srv/ref/geo.squashfs".to_string()),
+                ("tables".to_string(), "/srv/ref/tables.ext4".to_string()),
+            ],
+            ..VMManager::default()
+        };
+        let config = vm.vm_config().unwrap();
+        let drives: Vec<_> = config.drives.iter().map(|d| d.drive_id.as_str()).collect();
+        assert_eq!(drives, vec!["rootfs", "deps", "ref-geo", "ref-tables"]);
+        assert!(config.drives[2..].iter().all(|d| d.is_read_only));
+        assert!(
+            config
+                .boot_source
+                .boot_args
+                .contains("fc_refdata=geo:/dev/vdc,tables:/dev/vdd"),
+            "{}",
+            config.boot_source.boot_args
+        );
+
+        let opted_out = ExecutionSpec {
+            mount_reference_data: false,
+            ..ExecutionSpec::code("1")
+        };
+        assert!(opted_out.needs_dedicated_vm());
+        assert!(opted_out.vm_options().no_reference_data);
+        assert!(!ExecutionSpec::code("1").vm_options().no_reference_data);
+    }
+
+    #[test]
+    fn test_vm_config_gates_devices_on_version() {
+        let vm = |version: Option<&str>| VMManager {
+            firecracker_version: version.and_then(FirecrackerVersion::parse),
+            ..VMManager::default()
+        };
+        assert!(vm(Some("1.7.0")).vm_config().unwrap().entropy.is_some());
+        assert!(vm(Some("1.3.0")).vm_config().unwrap().entropy.is_none());
+        assert!(vm(None).vm_config().unwrap().entropy.is_none());
+    }
+
+    #[test]
+    fn test_pool_matches_deps_profile() {
+        let vm = |profile: Option<&str>| VMManager {
+            deps_profile: profile.map(str::to_string),
+            ..VMManager::default()
+        };
+        let mut pool =
+            std::collections::VecDeque::from([vm(Some("numpy")), vm(None), vm(Some("ml"))]);
+        let base_id = pool[1].vm_id.clone();
+
+        assert_eq!(
+            executor::take_pooled_vm(&mut pool, None, None, None)
+                .unwrap()
+                .vm_id,
+            base_id
+        );
+        assert!(executor::take_pooled_vm(&mut pool, None, None, None).is_none());
+        assert_eq!(
+            executor::take_pooled_vm(&mut pool, Some("ml"), None, None)
+                .unwrap()
+                .deps_profile(),
+            Some("ml")
+        );
+        assert!(executor::take_pooled_vm(&mut pool, Some("pandas"), None, None).is_none());
+        assert_eq!(pool.len(), 1);
+
+        // Nor does a VM booted from another image
+        let mut pool = std::collections::VecDeque::from([VMManager {
+            image: Some("ds".to_string()),
+            ..VMManager::default()
+        }]);
+        assert!(executor::take_pooled_vm(&mut pool, None, None, None).is_none());
+        assert!(executor::take_pooled_vm(&mut pool, None, Some("ds"), None).is_some());
+
+        // A deterministic VM never serves a pooled request
+        let mut pool = std::collections::VecDeque::from([VMManager {
+            deterministic: Some(DeterministicSettings::new(None)),
+            ..VMManager::default()
+        }]);
+        assert!(executor::take_pooled_vm(&mut pool, None, None, None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_vm_manager_creation() {
+        let vm_manager = VMManager::default();
+        assert!(!vm_manager.vm_id.is_empty());
+        assert!(vm_manager.socket_path.contains("/tmp/firecracker-"));
+    }
+
+    #[tokio::test]
+    async fn test_jailed_vm_paths_and_cleanup() {
+        let jailer = crate::jailer::JailerConfig {
+            chroot_base_dir: std::path::PathBuf::from("/tmp/test-jailer"),
+            ..Default::default()
+        };
+        let jail = Jail::new(&jailer, "jailed-test-vm");
+        let vm_manager = VMManager {
+            vm_id: "jailed-test-vm".to_string(),
+            fc_log_path: jail.host_path("fc.log").to_string_lossy().into_owned(),
+            tap_interface: "test-tap-jailed".to_string(),
+            jail: Some(jail.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vm_manager.firecracker_path(&vm_manager.fc_log_path),
+            "/fc.log"
+        );
+        // Paths outside the chroot are passed through untouched
+        assert_eq!(vm_manager.firecracker_path("/var/log/x"), "/var/log/x");
+
+        tokio::fs::create_dir_all(jail.host_path("run"))
+            .await
+            .unwrap();
+        tokio::fs::write(&vm_manager.fc_log_path, "log")
+            .await
+            .unwrap();
+        vm_manager.cleanup().await.unwrap();
+        assert!(!tokio::fs::try_exists(jail.vm_dir()).await.unwrap());
+        let _ = tokio::fs::remove_dir_all("/tmp/test-jailer").await;
+    }
+
+    #[tokio::test]
+    async fn test_vm_manager_cleanup() {
         let socket_path = "/tmp/test-socket.socket";
         let stdout_log_path = "/tmp/test-stdout.log";
         let stderr_log_path = "/tmp/test-stderr.log";
+        let fc_log_path = "/tmp/test-fc-log.log";
+        let fc_metrics_path = "/tmp/test-fc-metrics.json";
 
         // Create test files
         tokio::fs::File::create(socket_path).await.unwrap();
         tokio::fs::File::create(stdout_log_path).await.unwrap();
         tokio::fs::File::create(stderr_log_path).await.unwrap();
+        tokio::fs::File::create(fc_log_path).await.unwrap();
+        tokio::fs::write(fc_metrics_path, "{\"net\":{\"rx_bytes_count\":1}}\n")
+            .await
+            .unwrap();
 
         assert!(tokio::fs::try_exists(socket_path).await.unwrap());
         assert!(tokio::fs::try_exists(stdout_log_path).await.unwrap());
@@ -708,14 +3116,691 @@ mod tests {
             socket_path: socket_path.to_string(),
             stdout_log_path: stdout_log_path.to_string(),
             stderr_log_path: stderr_log_path.to_string(),
+            fc_log_path: fc_log_path.to_string(),
+            fc_metrics_path: fc_metrics_path.to_string(),
             tap_interface: "test-tap-nonexistent".to_string(), // Non-existent interface to avoid sudo issues
             ..Default::default()
         };
 
+        let vm_id = vm_manager.vm_id.clone();
+        VM_REGISTRY
+            .lock()
+            .unwrap()
+            .insert(vm_id.clone(), vm_manager.live_record());
+
         // Cleanup should remove the files (networking cleanup will fail silently)
         vm_manager.cleanup().await.unwrap();
         assert!(!tokio::fs::try_exists(socket_path).await.unwrap());
         assert!(!tokio::fs::try_exists(stdout_log_path).await.unwrap());
         assert!(!tokio::fs::try_exists(stderr_log_path).await.unwrap());
+        assert!(!tokio::fs::try_exists(fc_log_path).await.unwrap());
+        assert!(!tokio::fs::try_exists(fc_metrics_path).await.unwrap());
+        assert!(live_vm(&vm_id).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_continues_past_failed_steps() {
+        let dir = std::path::PathBuf::from("/tmp/test-partial-cleanup");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(dir.join("socket/held"))
+            .await
+            .unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let vm_manager = VMManager {
+            // A non-empty directory where the socket should be can't be removed as a file
+            socket_path: path("socket"),
+            stdout_log_path: path("stdout.log"),
+            stderr_log_path: path("stderr.log"),
+            fc_log_path: path("fc.log"),
+            fc_metrics_path: path("fc-metrics.json"),
+            config_file_path: path("vm-config.json"),
+            tap_interface: "test-tap-partial".to_string(),
+            ..Default::default()
+        };
+        for file in ["stdout.log", "stderr.log", "fc.log", "vm-config.json"] {
+            tokio::fs::write(dir.join(file), "x").await.unwrap();
+        }
+
+        let err = vm_manager.cleanup().await.unwrap_err().to_string();
+        assert!(err.contains("socket ("), "{err}");
+        assert!(
+            err.contains("done: networking, stdout_log, stderr_log, firecracker_log, config_file"),
+            "{err}"
+        );
+        for file in ["stdout.log", "stderr.log", "fc.log", "vm-config.json"] {
+            assert!(
+                !tokio::fs::try_exists(dir.join(file)).await.unwrap(),
+                "{file}"
+            );
+        }
+        assert!(tokio::fs::try_exists(dir.join("socket")).await.unwrap());
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[test]
+    fn test_cleanup_report() {
+        let mut report = CleanupReport::default();
+        report.record("socket", StepOutcome::Done);
+        report.record("stdout_log", StepOutcome::Skipped);
+        assert!(!report.is_partial());
+        assert_eq!(report.to_string(), "socket=done stdout_log=skipped");
+        report.record("jail", StepOutcome::Failed("busy".to_string()));
+        assert!(report.is_partial());
+        let err = report.into_result("vm-1").unwrap_err().to_string();
+        assert!(
+            err.ends_with("Cleanup of VM vm-1 failed: jail (busy); done: socket"),
+            "{err}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_guest_clock_is_set_through_the_agent() {
+        use axum::Json;
+        use axum::routing::post;
+
+        // Agent whose clock is an hour behind until set
+        let app = axum::Router::new().route(
+            "/set-time",
+            post(|Json(body): Json<serde_json::Value>| async move {
+                let epoch_ms = body["epoch_ms"].as_i64().unwrap();
+                Json(serde_json::json!({
+                    "previous_ms": epoch_ms - 3_600_000,
+                    "now_ms": epoch_ms,
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        let drift = crate::clock::sync(&vm, &crate::clock::ClockSyncConfig::default())
+            .await
+            .unwrap();
+        assert!((drift.before_ms + 3_600_000).abs() < 100, "{drift:?}");
+        assert!(drift.after_ms.abs() < 100, "{drift:?}");
+    }
+
+    #[tokio::test]
+    async fn test_agent_without_set_time_comes_up_and_keeps_its_clock() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // A legacy agent: no protocol version and no `/set-time`, counting calls to anything else
+        let unknown_calls = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new()
+            .route("/health", agent_health(None))
+            .fallback({
+                let unknown_calls = unknown_calls.clone();
+                move || async move {
+                    unknown_calls.fetch_add(1, Ordering::SeqCst);
+                    axum::http::StatusCode::NOT_FOUND
+                }
+            });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+        assert!(!vm.can_set_time());
+        let config = crate::clock::ClockSyncConfig::default();
+        crate::clock::on_acquire(&vm, Duration::from_secs(600), &config)
+            .await
+            .unwrap();
+        assert_eq!(unknown_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_firecracker_api_returns_bodies_and_times_out() {
+        use axum::http::StatusCode;
+        use axum::routing::{get, patch};
+
+        let socket = std::env::temp_dir().join(format!("fc-api-{}.socket", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let app = axum::Router::new()
+            .route(
+                "/version",
+                get(|| async { r#"{"firecracker_version":"1.10.1"}"# }),
+            )
+            .route(
+                "/balloon",
+                patch(|| async {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        r#"{"fault_message":"Balloon device not configured"}"#,
+                    )
+                }),
+            )
+            .route(
+                "/wedged",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    ""
+                }),
+            );
+        let listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints(socket.to_str().unwrap(), "http://127.0.0.1:9");
+        let body = vm.api_get("/version").await.unwrap();
+        assert_eq!(&body[..], br#"{"firecracker_version":"1.10.1"}"#);
+        let version = vm.firecracker_version().await.unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 10, 1));
+
+        let err = vm.api_patch("/balloon", "{}").await.unwrap_err();
+        assert!(err.to_string().contains("400 Bad Request"), "{err}");
+        assert!(err.to_string().contains("not configured"), "{err}");
+
+        let err = api_exchange(
+            socket.to_str().unwrap(),
+            Method::GET,
+            "/wedged",
+            None,
+            Duration::from_millis(50),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out after 50ms"), "{err}");
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    /// Print an OOM kill of the agent on the console and drop the connection unanswered, as
+    /// a killed agent does
+    async fn killed_agent(console: PathBuf) -> &'static str {
+        let mut log = std::fs::read_to_string(&console).unwrap();
+        log.push_str(&std::fs::read_to_string("fixtures/oom/agent-killed.log").unwrap());
+        std::fs::write(&console, log).unwrap();
+        panic!("killed by the OOM killer");
+    }
+
+    /// `/health` of an agent reporting protocol `version`, or none like agents that predate
+    /// the handshake
+    fn agent_health(version: Option<u32>) -> axum::routing::MethodRouter {
+        axum::routing::get(move || async move {
+            let mut health = serde_json::json!({ "status": "healthy" });
+            if let Some(version) = version {
+                health["protocol_version"] = version.into();
+            }
+            axum::Json(health)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_eval_mode_returns_the_agent_value() {
+        use axum::routing::post;
+
+        // Answers like the agent does for `1 + 1` and `{1, 2}`, echoing the mode it was sent
+        let app = axum::Router::new().route(
+            "/execute",
+            post(
+                |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                    let mut response = serde_json::json!({
+                        "stdout": format!("mode={}\n", body["mode"]),
+                        "stderr": "",
+                        "success": true,
+                    });
+                    match body["code"].as_str() {
+                        _ if body["mode"] != "eval" => {}
+                        Some("1 + 1") => response["value"] = 2.into(),
+                        Some("{1, 2}") => response["value_repr"] = "{1, 2}".into(),
+                        _ => response["value"] = serde_json::Value::Null,
+                    }
+                    axum::Json(response)
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app.route(
+            "/health",
+            agent_health(Some(guest_protocol::HOST_PROTOCOL_VERSION)),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+
+        let eval = |code: &str| Program::Eval(code.to_string());
+        let response = vm
+            .request_execution(&eval("1 + 1"), &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, "mode=\"eval\"\n");
+        assert_eq!(response.value, Some(serde_json::json!(2)));
+        assert!(response.value_repr.is_none());
+
+        let response = vm
+            .request_execution(&eval("{1, 2}"), &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert!(response.value.is_none());
+        assert_eq!(response.value_repr.as_deref(), Some("{1, 2}"));
+
+        // `None` isn't reported, and scripts don't ask for a value
+        let response = vm
+            .request_execution(&eval("None"), &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert!(response.value.is_none() && response.value_repr.is_none());
+        let script = Program::Code("1 + 1".to_string());
+        let response = vm
+            .request_execution(&script, &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, "mode=null\n");
+        assert!(response.value.is_none());
+        vm.cleanup().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_legacy_agent_gets_only_the_fields_it_understands() {
+        use axum::routing::post;
+
+        // An agent from before the handshake, echoing which fields it was sent
+        let app = axum::Router::new()
+            .route("/health", agent_health(None))
+            .route(
+                "/execute",
+                post(
+                    |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        let mut fields: Vec<_> = body
+                            .as_object()
+                            .unwrap()
+                            .keys()
+                            .map(String::as_str)
+                            .collect();
+                        fields.sort_unstable();
+                        axum::Json(serde_json::json!({
+                            "stdout": fields.join(","),
+                            "stderr": "",
+                            "success": true,
+                        }))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        VM_REGISTRY
+            .lock()
+            .unwrap()
+            .insert(vm.vm_id.clone(), vm.live_record());
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+        assert_eq!(vm.agent_protocol(), guest_protocol::LEGACY_PROTOCOL_VERSION);
+        assert_eq!(
+            live_vm(&vm.vm_id).unwrap().details().agent_protocol_version,
+            None
+        );
+
+        // The output limit is left out, as the host truncates output itself
+        let code = Program::Code("print(1)".to_string());
+        let response = vm
+            .request_execution(&code, &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert_eq!(response.stdout, "code");
+
+        // Eval mode would silently run as a script, so it fails before anything is sent
+        let eval = Program::Eval("1 + 1".to_string());
+        let err = vm
+            .request_execution(&eval, &[], &[], 1024, None)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ExecutionError::GuestProtocolError {
+                    required: 2,
+                    agent: 1,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+        VM_REGISTRY.lock().unwrap().remove(&vm.vm_id);
+        vm.cleanup().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_each_execution_names_its_workspace() {
+        use axum::routing::{get, post};
+
+        // Echoes the workspace it was asked to run in, and reports one it failed to delete
+        let app = axum::Router::new()
+            .route(
+                "/health",
+                get(|| async {
+                    axum::Json(serde_json::json!({
+                        "status": "healthy",
+                        "protocol_version": guest_protocol::HOST_PROTOCOL_VERSION,
+                        "leftover_workspaces": 1,
+                    }))
+                }),
+            )
+            .route(
+                "/execute",
+                post(
+                    |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        axum::Json(serde_json::json!({
+                            "stdout": body["exec_id"],
+                            "stderr": "",
+                            "success": true,
+                        }))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+
+        let code = Program::Code("print(1)".to_string());
+        let first = vm
+            .request_execution(&code, &[], &[], 1024, None)
+            .await
+            .unwrap();
+        let second = vm
+            .request_execution(&code, &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert_eq!(first.exec_id.as_deref(), Some(first.stdout.as_str()));
+        assert_ne!(first.exec_id, second.exec_id);
+
+        let err = vm.verify_workspaces_removed().await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Resource management error: 1 execution workspaces were left behind in the guest"
+        );
+        // Agents without workspaces aren't asked
+        vm.set_agent_protocol(Some(2));
+        assert!(vm.verify_workspaces_removed().await.is_ok());
+        vm.cleanup().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_large_guest_responses_are_streamed_within_the_cap() {
+        use axum::routing::post;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // An agent ignoring the output cap: `big` prints 4 MiB and then reports its status,
+        // `flood` never stops printing
+        let sent = Arc::new(AtomicU64::new(0));
+        let counter = sent.clone();
+        let app = axum::Router::new()
+            .route(
+                "/health",
+                agent_health(Some(guest_protocol::HOST_PROTOCOL_VERSION)),
+            )
+            .route(
+                "/execute",
+                post(
+                    move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        let chunk = Bytes::from(vec![b'a'; 64 * 1024]);
+                        let flood = body["code"] == "flood";
+                        let chunks = std::iter::once(Bytes::from_static(b"{\"stdout\": \""))
+                            .chain(std::iter::repeat_n(chunk, if flood { usize::MAX } else { 64 }))
+                            .chain(std::iter::once(Bytes::from_static(
+                                b"\", \"stderr\": \"\", \"stdout_truncated\": false, \"success\": true}",
+                            )))
+                            .inspect(move |chunk| {
+                                counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                            })
+                            .map(Ok::<_, std::convert::Infallible>);
+                        axum::body::Body::from_stream(tokio_stream::iter(chunks))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+
+        // Only the cap is kept, and the status after the output still arrives
+        let big = Program::Code("big".to_string());
+        let response = vm
+            .request_execution(&big, &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.stdout.len(),
+            crate::guest_response::stream_budget(1024)
+        );
+        assert!(response.stdout_truncated);
+        assert!(response.success);
+
+        // A body past any honest agent's is abandoned instead of read to the end
+        sent.store(0, Ordering::Relaxed);
+        let flood = Program::Code("flood".to_string());
+        let err = vm
+            .request_execution(&flood, &[], &[], 1024, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exceeded"), "{err}");
+        let limit = crate::guest_response::body_limit(1024);
+        let sent = sent.load(Ordering::Relaxed);
+        assert!(sent > limit && sent < 2 * limit, "{sent} bytes sent");
+        vm.cleanup().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_live_agent_output_arrives_as_printed() {
+        use axum::routing::post;
+
+        // An agent that prints a line, then holds the rest until `release` is notified
+        let release = Arc::new(tokio::sync::Notify::new());
+        let held = release.clone();
+        let app = axum::Router::new()
+            .route(
+                "/health",
+                agent_health(Some(guest_protocol::HOST_PROTOCOL_VERSION)),
+            )
+            .route(
+                "/execute",
+                post(
+                    move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                        assert_eq!(body["stream"], true);
+                        let (lines, body) = tokio::sync::mpsc::channel(4);
+                        tokio::spawn(async move {
+                            let line = |line: &str| Ok::<_, std::convert::Infallible>(Bytes::from(line.to_string()));
+                            lines.send(line("{\"type\": \"stdout\", \"data\": \"first\\n\"}\n")).await.unwrap();
+                            held.notified().await;
+                            lines.send(line("{\"type\": \"stderr\", \"data\": \"second line\\n\"}\n")).await.unwrap();
+                            lines.send(line("{\"type\": \"result\", \"stdout\": \"first\\n\", \"stderr\": \"second line\\n\", \"success\": true}\n")).await.unwrap();
+                        });
+                        axum::body::Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(body))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+
+        let (sink, mut output) = tokio::sync::mpsc::unbounded_channel();
+        let execution = tokio::spawn(async move {
+            let code = Program::Code("print('first')".to_string());
+            // Room for all of stdout but only part of stderr
+            let deadline = Deadline::after(EXECUTION_BUDGET);
+            let response = vm
+                .execute_code_via_api(&code, &[], &[], 9, deadline, Some(&sink))
+                .await;
+            vm.cleanup().await.unwrap();
+            response
+        });
+        let Some(OutputEvent::Stdout { data }) = output.recv().await else {
+            panic!("stdout comes first");
+        };
+        assert_eq!(data, "first\n");
+        assert!(!execution.is_finished());
+
+        release.notify_one();
+        let response = execution.await.unwrap().unwrap();
+        assert_eq!(response.stdout, "first\n");
+        assert!(response.success);
+        // Output past the cap isn't passed on, and nothing is sent twice
+        let Some(OutputEvent::Stderr { data }) = output.recv().await else {
+            panic!("then stderr");
+        };
+        assert_eq!(data, "second li");
+        assert!(output.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_input_checksum_mismatch_is_a_setup_error() {
+        use crate::inputs::SetupErrorCode;
+        use axum::routing::post;
+
+        // An agent whose download didn't match the requested checksum
+        let app = axum::Router::new().route(
+            "/execute",
+            post(
+                |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                    let input = &body["inputs"][0];
+                    axum::Json(serde_json::json!({
+                        "stdout": body["max_input_bytes"].to_string(),
+                        "stderr": "Failed to download inputs",
+                        "exit_code": 1,
+                        "success": false,
+                        "setup_error": {
+                            "code": "checksum_mismatch",
+                            "url": input["url"],
+                            "path": input["path"],
+                            "message": "sha256 of the download doesn't match",
+                            "expected_sha256": input["sha256"],
+                            "actual_sha256": "ab".repeat(32),
+                        },
+                    }))
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app.route(
+            "/health",
+            agent_health(Some(guest_protocol::HOST_PROTOCOL_VERSION)),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}"));
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+
+        let input = RemoteInput {
+            url: "https://store.example/sales.csv".to_string(),
+            path: "sales.csv".to_string(),
+            sha256: Some("cd".repeat(32)),
+        };
+        let program = Program::Code("print(open('/inputs/sales.csv').read())".to_string());
+        let response = vm
+            .request_execution(&program, &[], std::slice::from_ref(&input), 1024, None)
+            .await
+            .unwrap();
+        assert!(!response.success);
+        // The download cap is sent along
+        assert_eq!(response.stdout, (64 * 1024 * 1024).to_string());
+        assert_eq!(response.stderr, "Failed to download inputs");
+        let error = response.setup_error.clone().unwrap();
+        assert_eq!(error.code, SetupErrorCode::ChecksumMismatch);
+        assert_eq!(error.path, "sales.csv");
+        assert_eq!(error.expected_sha256, input.sha256);
+        assert_eq!(error.actual_sha256, Some("ab".repeat(32)));
+
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(json["setup_error"]["code"], "checksum_mismatch");
+        assert_eq!(
+            json["setup_error"]["url"],
+            "https://store.example/sales.csv"
+        );
+        // Successful runs carry no setup error
+        assert!(
+            serde_json::to_value(ExecuteResponse::default())
+                .unwrap()
+                .get("setup_error")
+                .is_none()
+        );
+        vm.cleanup().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_guest_oom_kills_are_reported() {
+        use axum::routing::post;
+
+        let vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+        let console = PathBuf::from(&vm.stdout_log_path);
+        std::fs::write(&console, "Starting VM API server on 0.0.0.0:8080\n").unwrap();
+        // An agent reporting its child killed, and one killed itself while running the code
+        let app = axum::Router::new()
+            .route(
+                "/child/execute",
+                post(|| async {
+                    axum::Json(serde_json::json!({
+                        "stdout": "",
+                        "stderr": "",
+                        "success": false,
+                        "term_signal": 9,
+                        "oom_killed": true,
+                    }))
+                }),
+            )
+            .route(
+                "/agent/execute",
+                post(move || killed_agent(console.clone())),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let program = Program::Code("x = ' ' * 10**9".to_string());
+
+        let mut vm = vm;
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}/child"));
+        let response = vm
+            .request_execution(&program, &[], &[], 1024, None)
+            .await
+            .unwrap();
+        assert!(response.oom_killed);
+        assert!(!response.success);
+        assert!(response.stderr.contains("128 MiB"), "{}", response.stderr);
+
+        vm.use_api_endpoints("/nonexistent.socket", &format!("http://{addr}/agent"));
+        let err = vm
+            .request_execution(&program, &[], &[], 1024, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "guest_out_of_memory");
+        assert!(err.to_string().contains("python3 (pid 201)"), "{err}");
+        assert!(err.to_string().contains("128 MiB"), "{err}");
+
+        // A kill printed before the execution started isn't blamed on it
+        vm.use_api_endpoints("/nonexistent.socket", "http://127.0.0.1:9");
+        let err = vm
+            .request_execution(&program, &[], &[], 1024, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "api_communication_error");
+        vm.cleanup().await.unwrap();
     }
 }
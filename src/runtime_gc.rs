@@ -0,0 +1,222 @@
+use crate::boot_report::BOOT_REPORTS;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Runtime files a VM leaves in the runtime directory, as `<prefix><vm_id><suffix>`
+const RUNTIME_FILE_PATTERNS: &[(&str, &str)] = &[
+    ("firecracker-", ".socket"),
+    ("fc-stdout-", ".log"),
+    ("fc-stderr-", ".log"),
+    ("fc-log-", ".log"),
+    ("fc-metrics-", ".json"),
+    ("fc-config-", ".json"),
+    ("fc-vsock-", ".sock"),
+];
+
+/// Removing runtime files that VMs which are gone left behind, e.g. after a crash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeGcConfig {
+    /// Time between sweeps of the runtime directory; `None` never sweeps
+    pub interval: Option<Duration>,
+    /// Files modified more recently than this are kept, so VMs being created aren't raced
+    pub min_age: Duration,
+}
+
+impl Default for RuntimeGcConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(300)),
+            min_age: Duration::from_secs(600),
+        }
+    }
+}
+
+/// What a sweep did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcSummary {
+    pub removed: usize,
+    pub bytes_freed: u64,
+    pub failed: usize,
+}
+
+/// VM ID of a runtime file named `name`, if it follows one of our naming patterns
+pub fn vm_id_of(name: &str) -> Option<&str> {
+    RUNTIME_FILE_PATTERNS.iter().find_map(|(prefix, suffix)| {
+        let vm_id = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        uuid::Uuid::parse_str(vm_id).is_ok().then_some(vm_id)
+    })
+}
+
+/// Remove the runtime files in `dir` of VMs `is_live` doesn't know, last modified `min_age`
+/// before `now`. Files kept for a boot report are left to its own retention.
+pub fn sweep(
+    dir: &Path,
+    min_age: Duration,
+    now: SystemTime,
+    is_live: impl Fn(&str) -> bool,
+) -> GcSummary {
+    let mut summary = GcSummary::default();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("Failed to read runtime directory {}: {}", dir.display(), e);
+            return summary;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(vm_id) = name.to_str().and_then(vm_id_of) else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        let path = entry.path();
+        if metadata.is_dir() || age < min_age || is_live(vm_id) || BOOT_REPORTS.is_retained(&path) {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                summary.removed += 1;
+                summary.bytes_freed += metadata.len();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                summary.failed += 1;
+                tracing::warn!(
+                    "Failed to remove stale runtime file {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+    summary
+}
+
+/// Sweep `dir` on the configured interval against the live-VM registry; does nothing with
+/// the sweeps off
+pub fn spawn(
+    dir: std::path::PathBuf,
+    config: RuntimeGcConfig,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let interval = config.interval?;
+    Some(tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            crate::debug_state::heartbeat("runtime_gc");
+            let dir = dir.clone();
+            let summary = tokio::task::spawn_blocking(move || {
+                sweep(&dir, config.min_age, SystemTime::now(), |vm_id| {
+                    crate::runner::live_vm(vm_id).is_some()
+                })
+            })
+            .await
+            .unwrap_or_default();
+            if summary.removed > 0 || summary.failed > 0 {
+                tracing::info!(
+                    removed = summary.removed,
+                    bytes_freed = summary.bytes_freed,
+                    failed = summary.failed,
+                    "Removed stale runtime files"
+                );
+            }
+            crate::telemetry::increment_counter(
+                "fc_runtime_gc_removed_total",
+                &[],
+                summary.removed as u64,
+            );
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const LIVE: &str = "11111111-1111-4111-8111-111111111111";
+    const STALE: &str = "22222222-2222-4222-8222-222222222222";
+    const RETAINED: &str = "33333333-3333-4333-8333-333333333333";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fc-runtime-gc-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, "x").unwrap();
+        path
+    }
+
+    #[test]
+    fn test_vm_id_of() {
+        assert_eq!(vm_id_of(&format!("fc-stdout-{STALE}.log")), Some(STALE));
+        assert_eq!(
+            vm_id_of(&format!("firecracker-{STALE}.socket")),
+            Some(STALE)
+        );
+        assert_eq!(vm_id_of(&format!("fc-metrics-{STALE}.json")), Some(STALE));
+        assert_eq!(vm_id_of("fc-stdout-notes.log"), None);
+        assert_eq!(vm_id_of(&format!("api-{STALE}.jsonl")), None);
+        assert_eq!(vm_id_of(&format!("fc-stdout-{STALE}.txt")), None);
+    }
+
+    #[test]
+    fn test_sweep_removes_only_stale_files() {
+        let dir = temp_dir("sweep");
+        let live = [
+            touch(&dir, &format!("firecracker-{LIVE}.socket")),
+            touch(&dir, &format!("fc-log-{LIVE}.log")),
+        ];
+        let stale = [
+            touch(&dir, &format!("firecracker-{STALE}.socket")),
+            touch(&dir, &format!("fc-stdout-{STALE}.log")),
+            touch(&dir, &format!("fc-stderr-{STALE}.log")),
+            touch(&dir, &format!("fc-log-{STALE}.log")),
+            touch(&dir, &format!("fc-metrics-{STALE}.json")),
+            touch(&dir, &format!("fc-config-{STALE}.json")),
+        ];
+        let foreign = [
+            touch(&dir, "notes.log"),
+            touch(&dir, "fc-stdout-somebody-else.log"),
+            touch(&dir, &format!("api-{STALE}.jsonl")),
+        ];
+        let retained = touch(&dir, &format!("fc-stdout-{RETAINED}.log"));
+        BOOT_REPORTS.retain(
+            crate::boot_report::BootReport::assemble(
+                RETAINED,
+                "boot failed",
+                "",
+                &Default::default(),
+                0,
+            ),
+            vec![retained.clone()],
+            Duration::from_secs(3600),
+        );
+        let is_live = |vm_id: &str| vm_id == LIVE;
+
+        // Nothing is old enough yet
+        let summary = sweep(&dir, Duration::from_secs(60), SystemTime::now(), is_live);
+        assert_eq!(summary, GcSummary::default());
+
+        let later = SystemTime::now() + Duration::from_secs(120);
+        let summary = sweep(&dir, Duration::from_secs(60), later, is_live);
+        assert_eq!(summary.removed, stale.len());
+        assert_eq!(summary.bytes_freed, stale.len() as u64);
+        assert!(stale.iter().all(|path| !path.exists()));
+        assert!(live.iter().chain(&foreign).all(|path| path.exists()));
+        assert!(retained.exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::ExecutionError;
+use crate::events::DiscardReason;
+use crate::history::now_millis;
+use crate::port_forward::PortForward;
+use crate::quota::QuotaGuard;
+use crate::runner::VMManager;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// IDs of expired and deleted sandboxes remembered, so requests for them are told they're gone
+const MAX_TOMBSTONES: usize = 1024;
+
+/// Settings of `keep_alive_seconds`, which keeps a fresh VM as a sandbox after its execution
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxConfig {
+    /// Most sandboxes kept at once; 0 turns `keep_alive_seconds` off
+    pub max_sandboxes: usize,
+    /// Longest a request may keep its sandbox
+    pub max_keep_alive: Duration,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            max_sandboxes: 4,
+            max_keep_alive: Duration::from_secs(600),
+        }
+    }
+}
+
+impl SandboxConfig {
+    /// Check a `keep_alive_seconds` asked for
+    pub fn validate(&self, keep_alive_seconds: u32) -> Result<(), String> {
+        if self.max_sandboxes == 0 {
+            return Err("keep_alive_seconds is disabled on this server".to_string());
+        }
+        if keep_alive_seconds == 0 || u64::from(keep_alive_seconds) > self.max_keep_alive.as_secs()
+        {
+            return Err(format!(
+                "keep_alive_seconds must be between 1 and {}",
+                self.max_keep_alive.as_secs()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A VM kept after its execution for follow-up executions, until its deadline or deletion
+struct Sandbox {
+    /// `None` while an execution runs on it
+    vm: Option<VMManager>,
+    /// API key identifier of the tenant it belongs to; only that tenant can use it
+    owner: Option<String>,
+    expires_at: Instant,
+    expires_at_ms: u64,
+    /// Its place in the holding area
+    _slot: OwnedSemaphorePermit,
+    /// Its tenant's concurrency slot, held for as long as it is kept
+    _quota: Option<QuotaGuard>,
+    /// Forward of the `expose_port` asked for with it, closed along with it
+    _forward: Option<(PortForward, OwnedSemaphorePermit)>,
+}
+
+#[derive(Default)]
+struct Holding {
+    sandboxes: HashMap<String, Sandbox>,
+    /// IDs of sandboxes gone and why, oldest first
+    tombstones: VecDeque<(String, DiscardReason)>,
+}
+
+impl Holding {
+    fn bury(&mut self, sandbox_id: String, reason: DiscardReason) {
+        if self.tombstones.len() >= MAX_TOMBSTONES {
+            self.tombstones.pop_front();
+        }
+        self.tombstones.push_back((sandbox_id, reason));
+    }
+
+    fn tombstone(&self, sandbox_id: &str) -> Option<DiscardReason> {
+        self.tombstones
+            .iter()
+            .find(|(id, _)| id == sandbox_id)
+            .map(|(_, reason)| *reason)
+    }
+
+    /// The live sandbox `sandbox_id` if `owner` may use it
+    fn get(
+        &mut self,
+        sandbox_id: &str,
+        owner: Option<&str>,
+    ) -> Result<&mut Sandbox, ExecutionError> {
+        match self.sandboxes.get(sandbox_id) {
+            Some(sandbox) if sandbox.owner.as_deref() == owner => {}
+            // Another tenant's sandbox is none of the caller's business
+            Some(_) => return Err(ExecutionError::SandboxNotFound(sandbox_id.to_string())),
+            None if self.tombstone(sandbox_id).is_some() => {
+                return Err(ExecutionError::SandboxExpired(sandbox_id.to_string()));
+            }
+            None => return Err(ExecutionError::SandboxNotFound(sandbox_id.to_string())),
+        }
+        let sandbox = self.sandboxes.get_mut(sandbox_id).expect("checked above");
+        if sandbox.expires_at <= Instant::now() {
+            return Err(ExecutionError::SandboxExpired(sandbox_id.to_string()));
+        }
+        Ok(sandbox)
+    }
+}
+
+/// Sandboxes kept after their first execution, by ID, bounded to `max_sandboxes`
+#[derive(Clone)]
+pub struct Sandboxes {
+    slots: Arc<Semaphore>,
+    holding: Arc<Mutex<Holding>>,
+}
+
+impl Sandboxes {
+    pub fn new(max_sandboxes: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_sandboxes)),
+            holding: Arc::default(),
+        }
+    }
+
+    fn holding(&self) -> std::sync::MutexGuard<'_, Holding> {
+        self.holding.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// A place in the holding area for a sandbox to come, if any is free
+    pub fn try_reserve(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    /// Keep `vm` for `ttl` in the place `slot`, with the forward of its exposed port if any;
+    /// returns the sandbox's ID and when it expires, in milliseconds since the epoch
+    pub fn park(
+        &self,
+        vm: VMManager,
+        ttl: Duration,
+        slot: OwnedSemaphorePermit,
+        forward: Option<(PortForward, OwnedSemaphorePermit)>,
+    ) -> (String, u64) {
+        let sandbox_id = format!("sbx-{}", uuid::Uuid::new_v4());
+        let expires_at_ms = now_millis() + ttl.as_millis() as u64;
+        self.holding().sandboxes.insert(
+            sandbox_id.clone(),
+            Sandbox {
+                vm: Some(vm),
+                owner: None,
+                expires_at: Instant::now() + ttl,
+                expires_at_ms,
+                _slot: slot,
+                _quota: None,
+                _forward: forward,
+            },
+        );
+        (sandbox_id, expires_at_ms)
+    }
+
+    /// Give sandbox `sandbox_id` to the tenant `owner`, whose concurrency slot `quota` it holds
+    /// from now on
+    pub fn assign(&self, sandbox_id: &str, owner: Option<&str>, quota: QuotaGuard) {
+        if let Some(sandbox) = self.holding().sandboxes.get_mut(sandbox_id) {
+            sandbox.owner = owner.map(str::to_string);
+            sandbox._quota = Some(quota);
+        }
+    }
+
+    /// Take the VM of sandbox `sandbox_id` to run an execution for `owner`, with when the
+    /// sandbox expires
+    pub fn take(
+        &self,
+        sandbox_id: &str,
+        owner: Option<&str>,
+    ) -> Result<(VMManager, u64), ExecutionError> {
+        let mut holding = self.holding();
+        let sandbox = holding.get(sandbox_id, owner)?;
+        let vm = sandbox
+            .vm
+            .take()
+            .ok_or_else(|| ExecutionError::SandboxBusy(sandbox_id.to_string()))?;
+        Ok((vm, sandbox.expires_at_ms))
+    }
+
+    /// Put `vm` back into sandbox `sandbox_id` after an execution; hands it back with the
+    /// reason to discard it when the sandbox expired or was deleted meanwhile
+    pub fn put_back(&self, sandbox_id: &str, vm: VMManager) -> Option<(VMManager, DiscardReason)> {
+        let mut holding = self.holding();
+        match holding.sandboxes.get_mut(sandbox_id) {
+            Some(sandbox) if sandbox.expires_at > Instant::now() => {
+                sandbox.vm = Some(vm);
+                None
+            }
+            // Reclaimed by the next sweep
+            Some(_) => Some((vm, DiscardReason::SandboxExpired)),
+            None => {
+                let reason = holding.tombstone(sandbox_id);
+                Some((vm, reason.unwrap_or(DiscardReason::SandboxDeleted)))
+            }
+        }
+    }
+
+    /// Delete sandbox `sandbox_id` of `owner`; returns its VM unless an execution still runs
+    /// on it, which then discards it when done
+    pub fn remove(
+        &self,
+        sandbox_id: &str,
+        owner: Option<&str>,
+    ) -> Result<Option<VMManager>, ExecutionError> {
+        let mut holding = self.holding();
+        holding.get(sandbox_id, owner)?;
+        let sandbox = holding.sandboxes.remove(sandbox_id).expect("checked above");
+        holding.bury(sandbox_id.to_string(), DiscardReason::SandboxDeleted);
+        Ok(sandbox.vm)
+    }
+
+    /// Remove the sandboxes past their deadline, returning their idle VMs
+    pub fn remove_expired(&self) -> Vec<VMManager> {
+        let now = Instant::now();
+        self.remove_if(DiscardReason::SandboxExpired, |sandbox| {
+            sandbox.expires_at <= now
+        })
+    }
+
+    /// Remove every sandbox, returning their idle VMs
+    pub fn drain(&self) -> Vec<VMManager> {
+        self.remove_if(DiscardReason::Shutdown, |_| true)
+    }
+
+    fn remove_if(
+        &self,
+        reason: DiscardReason,
+        condition: impl Fn(&Sandbox) -> bool,
+    ) -> Vec<VMManager> {
+        let mut holding = self.holding();
+        let removed: Vec<String> = holding
+            .sandboxes
+            .iter()
+            .filter(|(_, sandbox)| condition(sandbox))
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut vms = Vec::new();
+        for sandbox_id in removed {
+            if let Some(sandbox) = holding.sandboxes.remove(&sandbox_id) {
+                vms.extend(sandbox.vm);
+            }
+            holding.bury(sandbox_id, reason);
+        }
+        vms
+    }
+
+    /// Sandboxes kept now
+    pub fn len(&self) -> usize {
+        self.holding().sandboxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
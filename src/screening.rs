@@ -0,0 +1,388 @@
+use crate::config::ConfigError;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashSet;
+
+/// Deny rule as written in the screening configuration
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct DenyRuleConfig {
+    /// Rule name reported to the client when it matches
+    pub name: String,
+    /// Substring, or regular expression when `regex` is set
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool,
+}
+
+/// Static screening configuration; screening is off unless `enabled` is set
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct ScreeningConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Include the built-in ruleset (requires the `screening-defaults` feature)
+    #[serde(default)]
+    pub use_default_rules: bool,
+    #[serde(default)]
+    pub deny: Vec<DenyRuleConfig>,
+    /// When set, only these top-level modules may be imported
+    #[serde(default)]
+    pub allowed_modules: Option<Vec<String>>,
+    /// API key identifiers trusted to skip screening
+    #[serde(default)]
+    pub bypass_key_ids: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Substring(String),
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+struct DenyRule {
+    name: String,
+    matcher: Matcher,
+}
+
+/// Rule violated by submitted code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: String,
+}
+
+/// Compiled screening rules applied to submitted code before it reaches a VM
+#[derive(Debug, Default)]
+pub struct Screener {
+    enabled: bool,
+    deny: Vec<DenyRule>,
+    allowed_modules: Option<HashSet<String>>,
+    bypass_key_ids: HashSet<String>,
+}
+
+/// Built-in rules matching obviously hostile snippets
+#[cfg(feature = "screening-defaults")]
+fn default_rules() -> Vec<DenyRuleConfig> {
+    let rule = |name: &str, pattern: &str| DenyRuleConfig {
+        name: name.to_string(),
+        pattern: pattern.to_string(),
+        regex: true,
+    };
+    vec![
+        rule("ctypes", r"\bctypes\b"),
+        rule("device_access", r#"["']/dev/"#),
+        rule("fork", r"\bos\.fork\s*\("),
+        rule("raw_socket", r"\bSOCK_RAW\b"),
+    ]
+}
+
+#[cfg(not(feature = "screening-defaults"))]
+fn default_rules() -> Vec<DenyRuleConfig> {
+    tracing::warn!("Default screening rules requested but the screening-defaults feature is off");
+    Vec::new()
+}
+
+impl Screener {
+    /// Compile the configuration, failing on invalid regular expressions
+    pub fn new(config: &ScreeningConfig) -> Result<Self, ConfigError> {
+        let mut rules = config.deny.clone();
+        if config.use_default_rules {
+            rules.extend(default_rules());
+        }
+        let deny = rules
+            .into_iter()
+            .map(|rule| {
+                let matcher = if rule.regex {
+                    Matcher::Regex(Regex::new(&rule.pattern).map_err(|e| {
+                        ConfigError::Invalid(format!(
+                            "Screening rule {} has an invalid pattern: {e}",
+                            rule.name
+                        ))
+                    })?)
+                } else {
+                    Matcher::Substring(rule.pattern)
+                };
+                Ok(DenyRule {
+                    name: rule.name,
+                    matcher,
+                })
+            })
+            .collect::<Result<_, ConfigError>>()?;
+
+        Ok(Self {
+            enabled: config.enabled,
+            deny,
+            allowed_modules: config
+                .allowed_modules
+                .as_ref()
+                .map(|modules| modules.iter().cloned().collect()),
+            bypass_key_ids: config.bypass_key_ids.iter().cloned().collect(),
+        })
+    }
+
+    /// Whether requests authenticated with `key_id` skip screening
+    pub fn bypasses(&self, key_id: Option<&str>) -> bool {
+        key_id.is_some_and(|id| self.bypass_key_ids.contains(id))
+    }
+
+    /// Check code against the deny rules and module allowlist
+    pub fn screen(&self, code: &str) -> Result<(), Violation> {
+        if !self.enabled {
+            return Ok(());
+        }
+        // Comments never execute, so they can't trigger a rule
+        let code = strip_comments(code);
+
+        for rule in &self.deny {
+            let matched = match &rule.matcher {
+                Matcher::Substring(pattern) => code.contains(pattern.as_str()),
+                Matcher::Regex(regex) => regex.is_match(&code),
+            };
+            if matched {
+                return Err(Violation {
+                    rule: rule.name.clone(),
+                });
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_modules {
+            for module in imported_modules(&code) {
+                if !allowed.contains(&module) {
+                    return Err(Violation {
+                        rule: format!("module_not_allowed:{module}"),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remove `#` comments, leaving string literals (including triple-quoted ones) intact
+fn strip_comments(code: &str) -> String {
+    let chars: Vec<char> = code.chars().collect();
+    let mut out = String::with_capacity(code.len());
+    let mut quote: Option<(char, bool)> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let triple = |q: char| i + 2 < chars.len() && chars[i + 1] == q && chars[i + 2] == q;
+        match quote {
+            Some((q, is_triple)) => {
+                if c == '\\' && i + 1 < chars.len() {
+                    out.push(c);
+                    out.push(chars[i + 1]);
+                    i += 2;
+                    continue;
+                }
+                if c == q && (!is_triple || triple(q)) {
+                    let len = if is_triple { 3 } else { 1 };
+                    out.extend(&chars[i..i + len]);
+                    i += len;
+                    quote = None;
+                    continue;
+                }
+                if c == '\n' && !is_triple {
+                    // Unterminated single-line string; recover at end of line
+                    quote = None;
+                }
+                out.push(c);
+            }
+            None => match c {
+                '#' => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    continue;
+                }
+                '\'' | '"' => {
+                    let is_triple = triple(c);
+                    let len = if is_triple { 3 } else { 1 };
+                    out.extend(&chars[i..i + len]);
+                    i += len;
+                    quote = Some((c, is_triple));
+                    continue;
+                }
+                _ => out.push(c),
+            },
+        }
+        i += 1;
+    }
+    out
+}
+
+/// Top-level modules imported by `import`/`from` statements and `__import__`/`import_module` calls
+fn imported_modules(code: &str) -> Vec<String> {
+    static IMPORT: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
+        Regex::new(r"(?m)(?:^|;)\s*import\s+([\w.\s,]+?)\s*(?:;|$)").unwrap()
+    });
+    static FROM: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
+        Regex::new(r"(?m)(?:^|;)\s*from\s+([\w.]+)\s+import\b").unwrap()
+    });
+    static DYNAMIC: once_cell::sync::Lazy<Regex> = once_cell::sync::Lazy::new(|| {
+        Regex::new(r#"(?:__import__|import_module)\s*\(\s*["']([\w.]+)["']"#).unwrap()
+    });
+
+    // Join backslash continuations so `import a, \<newline> b` is one logical line
+    let code = code.replace("\\\n", " ");
+    let top_level = |module: &str| module.split('.').next().unwrap_or_default().to_string();
+
+    let mut modules = Vec::new();
+    for caps in IMPORT.captures_iter(&code) {
+        for item in caps[1].split(',') {
+            // `import a.b as c` -> `a`
+            if let Some(name) = item.split_whitespace().next() {
+                modules.push(top_level(name));
+            }
+        }
+    }
+    for caps in FROM.captures_iter(&code) {
+        // Relative imports (`from . import x`) stay inside the user's own files
+        if !caps[1].starts_with('.') {
+            modules.push(top_level(&caps[1]));
+        }
+    }
+    for caps in DYNAMIC.captures_iter(&code) {
+        modules.push(top_level(&caps[1]));
+    }
+    modules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screener(deny: &[(&str, &str, bool)], allowed: Option<&[&str]>) -> Screener {
+        Screener::new(&ScreeningConfig {
+            enabled: true,
+            deny: deny
+                .iter()
+                .map(|(name, pattern, regex)| DenyRuleConfig {
+                    name: name.to_string(),
+                    pattern: pattern.to_string(),
+                    regex: *regex,
+                })
+                .collect(),
+            allowed_modules: allowed.map(|m| m.iter().map(|s| s.to_string()).collect()),
+            bypass_key_ids: vec!["trusted1".to_string()],
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_substring_and_regex_rules() {
+        let screener = screener(
+            &[("dev", "/dev/", false), ("fork", r"os\.fork\s*\(", true)],
+            None,
+        );
+        assert_eq!(screener.screen("open('/dev/mem')").unwrap_err().rule, "dev");
+        assert_eq!(
+            screener
+                .screen("import os\nwhile True:\n    os.fork ()\n")
+                .unwrap_err()
+                .rule,
+            "fork"
+        );
+        assert!(screener.screen("print('hello')\nx = 1 + 2\n").is_ok());
+    }
+
+    #[test]
+    fn test_comments_are_ignored_but_strings_are_not() {
+        let screener = screener(&[("ctypes", "ctypes", false)], None);
+        assert!(screener.screen("# import ctypes\nprint(1)").is_ok());
+        assert!(screener.screen("x = 1  # ctypes would be bad\n").is_ok());
+        assert!(screener.screen("s = 'a # b'; import ctypes").is_err());
+        assert!(
+            screener
+                .screen("doc = \"\"\"\n# not a comment ctypes\n\"\"\"\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_module_allowlist() {
+        let screener = screener(&[], Some(&["math", "json", "os"]));
+        assert!(
+            screener
+                .screen("import math\nimport os.path as p\n")
+                .is_ok()
+        );
+        assert!(
+            screener
+                .screen("from json import (\n    dumps,\n    loads,\n)\n")
+                .is_ok()
+        );
+        assert_eq!(
+            screener
+                .screen("import math, \\\n    socket\n")
+                .unwrap_err()
+                .rule,
+            "module_not_allowed:socket"
+        );
+        assert_eq!(
+            screener
+                .screen("x = 1; from subprocess import run")
+                .unwrap_err()
+                .rule,
+            "module_not_allowed:subprocess"
+        );
+        assert_eq!(
+            screener
+                .screen("m = __import__('ctypes')")
+                .unwrap_err()
+                .rule,
+            "module_not_allowed:ctypes"
+        );
+        assert!(screener.screen("# import socket\nimport math").is_ok());
+        assert!(screener.screen("print('import socket')").is_ok());
+    }
+
+    #[test]
+    fn test_disabled_screening_allows_everything() {
+        let screener = Screener::new(&ScreeningConfig {
+            deny: vec![DenyRuleConfig {
+                name: "all".to_string(),
+                pattern: "print".to_string(),
+                regex: false,
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(screener.screen("print(1)").is_ok());
+    }
+
+    #[test]
+    fn test_bypass_and_invalid_regex() {
+        let screener = screener(&[], None);
+        assert!(screener.bypasses(Some("trusted1")));
+        assert!(!screener.bypasses(Some("other")));
+        assert!(!screener.bypasses(None));
+
+        let invalid = Screener::new(&ScreeningConfig {
+            deny: vec![DenyRuleConfig {
+                name: "broken".to_string(),
+                pattern: "(".to_string(),
+                regex: true,
+            }],
+            ..Default::default()
+        });
+        assert!(invalid.unwrap_err().to_string().contains("broken"));
+    }
+
+    #[cfg(feature = "screening-defaults")]
+    #[test]
+    fn test_default_rules() {
+        let screener = Screener::new(&ScreeningConfig {
+            enabled: true,
+            use_default_rules: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(screener.screen("import ctypes").unwrap_err().rule, "ctypes");
+        assert_eq!(
+            screener.screen("open('/dev/kmsg')").unwrap_err().rule,
+            "device_access"
+        );
+        assert!(screener.screen("print(sum(range(10)))").is_ok());
+    }
+}
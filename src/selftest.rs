@@ -0,0 +1,259 @@
+use crate::debug_events::DebugEvent;
+use crate::executor::ExecutorService;
+use crate::history::now_millis;
+use crate::runner::ExecutionSpec;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// Snippet the self-test runs
+pub const CANARY_CODE: &str = "print(\"canary-ok\")";
+
+/// What the canary must print
+pub const CANARY_OUTPUT: &str = "canary-ok";
+
+/// Running a canary snippet end to end once the pool is warm, before the server counts as
+/// ready
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestConfig {
+    /// Whether `/ready` waits for a self-test to pass
+    pub enabled: bool,
+    /// Longest one canary may take, waiting for a permit and booting included
+    pub timeout: Duration,
+    /// Wait before retrying a failed self-test, doubled after every failure
+    pub retry_backoff: Duration,
+    /// Longest wait between retries
+    pub max_retry_backoff: Duration,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout: Duration::from_secs(30),
+            retry_backoff: Duration::from_secs(1),
+            max_retry_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Outcome of one canary run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    /// When it finished, in milliseconds since the Unix epoch
+    pub at: u64,
+    /// Attempt of the startup self-test it was, from 1; 0 for one run on demand
+    pub attempt: u32,
+    /// From submitting the canary to checking its output
+    pub total_ms: u64,
+    /// VM the canary ran on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vm_id: Option<String>,
+    /// Why it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Steps of the canary with their timings: permit, pool or boot, guest request
+    #[serde(default)]
+    pub events: Vec<DebugEvent>,
+    /// Latency of the first canary that passed, to compare this one with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_ms: Option<u64>,
+}
+
+/// The self-test results, shared by the startup retries, `POST /admin/selftest` and `/ready`
+#[derive(Debug, Default)]
+pub struct SelfTest {
+    state: Mutex<SelfTestState>,
+}
+
+#[derive(Debug, Default)]
+struct SelfTestState {
+    /// Whether any canary has passed; once it has, failures no longer make the server unready
+    passed: bool,
+    /// Latency of the first canary that passed, what later runs are compared with
+    baseline_ms: Option<u64>,
+    last: Option<SelfTestReport>,
+}
+
+impl SelfTest {
+    fn state(&self) -> std::sync::MutexGuard<'_, SelfTestState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keep `report` as the latest result, returning it with the baseline
+    pub fn record(&self, mut report: SelfTestReport) -> SelfTestReport {
+        let mut state = self.state();
+        if report.passed {
+            state.passed = true;
+            state.baseline_ms.get_or_insert(report.total_ms);
+        }
+        report.baseline_ms = state.baseline_ms;
+        state.last = Some(report.clone());
+        report
+    }
+
+    pub fn passed(&self) -> bool {
+        self.state().passed
+    }
+
+    /// Latency of the first canary that passed, in milliseconds
+    pub fn baseline_ms(&self) -> Option<u64> {
+        self.state().baseline_ms
+    }
+
+    pub fn last(&self) -> Option<SelfTestReport> {
+        self.state().last.clone()
+    }
+}
+
+/// Run the canary through `executor` as any request runs, with `timeout` to finish, and
+/// check what it printed
+pub async fn run(executor: &ExecutorService, timeout: Duration, attempt: u32) -> SelfTestReport {
+    let mut spec = ExecutionSpec::code(CANARY_CODE);
+    spec.request_id = format!("selftest-{}", uuid::Uuid::new_v4());
+    spec.debug = true;
+    spec.timeout = Some(timeout);
+    let started = Instant::now();
+    let result = executor.execute(spec).await;
+    let total_ms = started.elapsed().as_millis() as u64;
+    let (vm_id, events, error) = match result {
+        Ok(response) => {
+            let error = if !response.success {
+                Some(format!("the canary failed: {}", response.stderr.trim()))
+            } else if !response.stdout.contains(CANARY_OUTPUT) {
+                Some(format!(
+                    "the canary printed {:?} instead of {CANARY_OUTPUT:?}",
+                    response.stdout
+                ))
+            } else {
+                None
+            };
+            (response.vm_id, response.events.unwrap_or_default(), error)
+        }
+        Err(e) => (None, Vec::new(), Some(format!("{}: {e}", e.code()))),
+    };
+    SelfTestReport {
+        passed: error.is_none(),
+        at: now_millis(),
+        attempt,
+        total_ms,
+        vm_id,
+        error,
+        events,
+        baseline_ms: None,
+    }
+}
+
+/// Run the canary until it passes, waiting longer after every failure; `/ready` answers
+/// `503` until then
+pub fn spawn(
+    executor: ExecutorService,
+    config: SelfTestConfig,
+    selftest: std::sync::Arc<SelfTest>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut backoff = config.retry_backoff;
+        for attempt in 1.. {
+            let report = run(&executor, config.timeout, attempt).await;
+            let passed = report.passed;
+            if passed {
+                tracing::info!(
+                    "Self-test passed in {}ms on attempt {}",
+                    report.total_ms,
+                    attempt
+                );
+            } else {
+                tracing::warn!(
+                    "Self-test attempt {} failed, retrying in {}ms: {}",
+                    attempt,
+                    backoff.as_millis(),
+                    report.error.as_deref().unwrap_or_default()
+                );
+            }
+            selftest.record(report);
+            if passed {
+                break;
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(config.max_retry_backoff);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::BackendKind;
+    use crate::config::RunnerConfig;
+    use std::sync::Arc;
+
+    fn mock_executor() -> ExecutorService {
+        ExecutorService::new(Arc::new(RunnerConfig {
+            backend: BackendKind::Mock,
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_canary_passes_with_timings() {
+        let executor = mock_executor();
+        let report = run(&executor, Duration::from_secs(5), 0).await;
+        assert!(report.passed, "{report:?}");
+        assert!(report.vm_id.is_some());
+        assert!(!report.events.is_empty());
+
+        let selftest = SelfTest::default();
+        selftest.record(report.clone());
+        assert!(selftest.passed());
+        assert_eq!(selftest.baseline_ms(), Some(report.total_ms));
+        // A later failure is reported but neither unreadies the server nor moves the baseline
+        selftest.record(SelfTestReport {
+            passed: false,
+            total_ms: report.total_ms + 1000,
+            ..report.clone()
+        });
+        assert!(selftest.passed());
+        assert_eq!(selftest.baseline_ms(), Some(report.total_ms));
+        assert!(!selftest.last().unwrap().passed);
+        executor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_failed_self_test_is_retried_until_it_passes() {
+        // The canary's timeout leaves too little to boot, so it fails until a VM is pooled
+        let executor = mock_executor();
+        let selftest = Arc::new(SelfTest::default());
+        let task = spawn(
+            executor.clone(),
+            SelfTestConfig {
+                enabled: true,
+                timeout: Duration::from_millis(400),
+                retry_backoff: Duration::from_millis(10),
+                max_retry_backoff: Duration::from_millis(50),
+            },
+            selftest.clone(),
+        );
+        while selftest.last().is_none_or(|report| report.attempt < 2) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!selftest.passed());
+        let failure = selftest.last().unwrap();
+        assert!(
+            failure.error.as_deref().unwrap().starts_with("timeout"),
+            "{failure:?}"
+        );
+        assert_eq!(selftest.baseline_ms(), None);
+
+        assert_eq!(executor.warm(1).await, 1);
+        tokio::time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(selftest.passed());
+        assert!(selftest.last().unwrap().attempt > 2);
+        assert!(selftest.baseline_ms().is_some());
+        executor.shutdown().await;
+    }
+}
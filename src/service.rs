@@ -0,0 +1,465 @@
+use crate::cache::{self, ResultCache};
+use crate::config::{Config, runner_config};
+use crate::determinism::DeterministicSettings;
+use crate::dispatch::QueuePosition;
+use crate::executor::ExecutorService;
+use crate::inputs;
+use crate::output;
+use crate::program::Program;
+use crate::quota::{QuotaExceeded, QuotaTracker};
+use crate::runner::{self, ExecutionSpec, OutputSink, QuickOptions};
+use crate::screening::Screener;
+use crate::validation::{self, Limits, ValidationError};
+use crate::warning::Warning;
+use crate::{ExecuteRequest, ExecuteResponse, ExecutionError, telemetry};
+use axum::http::StatusCode;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::{debug, error, info};
+
+/// Why an execute request was turned away, with its HTTP status; other transports map it to
+/// their own status codes
+#[derive(Debug, Error, PartialEq)]
+pub enum Rejection {
+    /// The request is malformed or asks for something unavailable
+    #[error("{0}")]
+    BadRequest(String),
+    /// The request failed validation against the server's limits
+    #[error("{0}")]
+    Invalid(ValidationError),
+    /// Screening found a rule violation in the source
+    #[error("{0}")]
+    Screened(String),
+    /// The caller's tenant reached one of its quotas
+    #[error("{0}")]
+    QuotaExceeded(QuotaExceeded),
+    /// Running the code failed or was refused, from no capacity to a sandbox that is gone;
+    /// answered with the error's own status and code
+    #[error("{0}")]
+    Execution(ExecutionError),
+    /// Something the server depends on failed
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl Rejection {
+    /// Stable machine-readable name of the rejection
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rejection::BadRequest(_) => "bad_request",
+            Rejection::Invalid(e) => e.code(),
+            Rejection::Screened(_) => "screened",
+            Rejection::QuotaExceeded(_) => "quota_exceeded",
+            Rejection::Execution(e) => e.code(),
+            Rejection::Internal(_) => "internal",
+        }
+    }
+
+    /// HTTP status the rejection is answered with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Rejection::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Rejection::Invalid(e) => e.status(),
+            Rejection::Screened(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Rejection::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            Rejection::Execution(e) => e.status(),
+            Rejection::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Seconds after which a retry may succeed, for rejections that go away on their own
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        match self {
+            Rejection::QuotaExceeded(quota) => Some(quota.retry_after_secs()),
+            Rejection::Execution(e) => e.retry_after_secs(),
+            _ => None,
+        }
+    }
+}
+
+/// Where an execution reports on itself while it runs
+#[derive(Debug, Default)]
+pub struct ExecutionUpdates {
+    /// Receives the place in the queue while the execution waits for a permit
+    pub queue: Option<UnboundedSender<QueuePosition>>,
+    /// Receives stdout and stderr as the guest prints them
+    pub output: Option<OutputSink>,
+}
+
+/// Validation, screening, caching and execution of `/execute` requests, shared by the HTTP
+/// and gRPC transports so both accept and reject exactly the same requests
+#[derive(Clone)]
+pub struct ExecutionService {
+    pub config: Arc<Config>,
+    pub screener: Arc<Screener>,
+    pub cache: Arc<ResultCache>,
+    pub quotas: Arc<QuotaTracker>,
+    pub executor: ExecutorService,
+}
+
+impl ExecutionService {
+    pub fn new(config: Arc<Config>, executor: ExecutorService) -> Self {
+        Self {
+            executor,
+            screener: Arc::new(
+                Screener::new(&config.screening)
+                    .expect("screening rules are validated when the config is loaded"),
+            ),
+            cache: Arc::new(ResultCache::new(config.cache.capacity, config.cache.ttl)),
+            quotas: Arc::new(QuotaTracker::new(config.quotas.clone())),
+            config,
+        }
+    }
+
+    /// Validate `payload` and run it, or serve it from the cache. `key_id` identifies the
+    /// caller's API key for screening bypasses and quotas.
+    pub async fn execute(
+        &self,
+        payload: ExecuteRequest,
+        request_id: String,
+        key_id: Option<&str>,
+    ) -> Result<ExecuteResponse, Rejection> {
+        self.run(
+            payload,
+            request_id,
+            key_id,
+            ExecutionUpdates::default(),
+            None,
+            None,
+        )
+        .await
+    }
+
+    /// Like `execute`, failing with a timeout once `timeout` has passed from now, whichever
+    /// of waiting, booting and running it is in
+    pub async fn execute_within(
+        &self,
+        payload: ExecuteRequest,
+        request_id: String,
+        key_id: Option<&str>,
+        timeout: Duration,
+    ) -> Result<ExecuteResponse, Rejection> {
+        self.run(
+            payload,
+            request_id,
+            key_id,
+            ExecutionUpdates::default(),
+            None,
+            Some(timeout),
+        )
+        .await
+    }
+
+    /// Like `execute`, sending the execution's place in the queue to `queue_updates` while it
+    /// waits for a permit
+    pub async fn execute_with_queue_updates(
+        &self,
+        payload: ExecuteRequest,
+        request_id: String,
+        key_id: Option<&str>,
+        queue_updates: Option<UnboundedSender<QueuePosition>>,
+    ) -> Result<ExecuteResponse, Rejection> {
+        let updates = ExecutionUpdates {
+            queue: queue_updates,
+            output: None,
+        };
+        self.run(payload, request_id, key_id, updates, None, None)
+            .await
+    }
+
+    /// Like `execute`, reporting on the execution to `updates` while it runs and, given a
+    /// `timeout`, bounded like `execute_within`; for the streaming transports
+    pub async fn execute_streaming(
+        &self,
+        payload: ExecuteRequest,
+        request_id: String,
+        key_id: Option<&str>,
+        updates: ExecutionUpdates,
+        timeout: Option<Duration>,
+    ) -> Result<ExecuteResponse, Rejection> {
+        self.run(payload, request_id, key_id, updates, None, timeout)
+            .await
+    }
+
+    /// Run `code` on a warm pooled VM within `quick`'s bounds, for `/execute/quick`
+    pub async fn execute_quick(
+        &self,
+        code: String,
+        request_id: String,
+        key_id: Option<&str>,
+        quick: QuickOptions,
+    ) -> Result<ExecuteResponse, Rejection> {
+        let payload = ExecuteRequest {
+            code: Some(code),
+            ..Default::default()
+        };
+        self.run(
+            payload,
+            request_id,
+            key_id,
+            ExecutionUpdates::default(),
+            Some(quick),
+            None,
+        )
+        .await
+    }
+
+    async fn run(
+        &self,
+        payload: ExecuteRequest,
+        request_id: String,
+        key_id: Option<&str>,
+        updates: ExecutionUpdates,
+        quick: Option<QuickOptions>,
+        timeout: Option<Duration>,
+    ) -> Result<ExecuteResponse, Rejection> {
+        let program = self.validate(&payload, key_id)?;
+        let has_inputs = payload
+            .inputs
+            .as_ref()
+            .is_some_and(|inputs| !inputs.is_empty());
+        let request = ExecutionSpec {
+            request_id,
+            program,
+            max_output_bytes: payload.max_output_bytes,
+            requirements: payload.requirements,
+            inputs: payload.inputs.unwrap_or_default(),
+            deps_profile: payload.deps_profile,
+            image: payload.image,
+            deterministic: payload
+                .deterministic
+                .then(|| DeterministicSettings::new(payload.fake_time)),
+            dns: payload.dns,
+            traffic_shape: payload.traffic_shape,
+            expose_port: payload.expose_port,
+            affinity_key: payload.affinity_key,
+            priority: payload.priority.unwrap_or_default(),
+            debug_boot: payload.debug_boot,
+            // Opting out only needs a VM of its own when there is something to leave out
+            mount_reference_data: payload.mount_reference_data.unwrap_or(true)
+                || runner_config().reference_data.is_empty(),
+            quick,
+            capture_console: payload.capture_console.unwrap_or(false),
+            debug: payload.debug.unwrap_or(false),
+            keep_alive: payload
+                .keep_alive_seconds
+                .map(|secs| Duration::from_secs(secs.into())),
+            timeout,
+            queue_updates: updates.queue,
+            output: updates.output,
+        };
+
+        // Serve repeated snippets without a VM round-trip
+        // A boot being diagnosed must actually happen, a port can only be exposed by a run,
+        // remote inputs may have changed since, and a cached result has no console, events or
+        // sandbox to show
+        let use_cache = payload.cache.unwrap_or(self.config.cache.enabled)
+            && !payload.debug_boot
+            && payload.expose_port.is_none()
+            && payload.keep_alive_seconds.is_none()
+            && !has_inputs
+            && !request.capture_console
+            && !request.debug;
+        let max_output_bytes = output::effective_limit(
+            self.executor.tunables().max_output_bytes,
+            request.max_output_bytes,
+        );
+        let cache_key = use_cache.then(|| cache::cache_key(&request, max_output_bytes));
+        if let Some(key) = &cache_key
+            && !payload.cache_bypass
+        {
+            if let Some(mut response) = self.cache.get(key) {
+                telemetry::increment_counter("fc_cache_hits_total", &[], 1);
+                response.cached = true;
+                // No VM ran it this time
+                response.vm_id = None;
+                response.exec_id = None;
+                response.warnings.retain(Warning::describes_output);
+                if let Some(output) = &request.output {
+                    runner::send_output(output, &response);
+                }
+                return Ok(response);
+            }
+            telemetry::increment_counter("fc_cache_misses_total", &[], 1);
+        }
+
+        // Cached results cost no VM time, so only executions count against the quota
+        let quota = self
+            .quotas
+            .acquire(key_id)
+            .map_err(Rejection::QuotaExceeded)?;
+
+        // Execute code in VM
+        let deterministic = cache::looks_deterministic(&request.program);
+        let response = self
+            .executor
+            .execute(request)
+            .await
+            .map_err(Rejection::from)?;
+        info!("Code execution completed successfully");
+        // A kept sandbox holds its tenant's concurrency slot until it goes
+        if let Some(sandbox_id) = &response.sandbox_id {
+            self.executor.assign_sandbox(sandbox_id, key_id, quota);
+        }
+        // Failures are never cached, nor is output that likely changes between runs
+        if let Some(key) = cache_key
+            && response.success
+            && deterministic
+        {
+            self.cache.insert(key, response.clone());
+        }
+        Ok(response)
+    }
+
+    /// Validate `payload` and run it in sandbox `sandbox_id`, which must belong to the caller's
+    /// API key `key_id`. Options that set up a VM at boot are refused, as the sandbox's VM is
+    /// already running, and so are those choosing a VM, caching the result or observing the
+    /// boot, which a sandbox run can't honor.
+    pub async fn execute_in_sandbox(
+        &self,
+        sandbox_id: &str,
+        payload: ExecuteRequest,
+        request_id: String,
+        key_id: Option<&str>,
+    ) -> Result<ExecuteResponse, Rejection> {
+        let unsupported = [
+            ("deps_profile", payload.deps_profile.is_some()),
+            ("image", payload.image.is_some()),
+            ("dns", payload.dns.is_some()),
+            ("traffic_shape", payload.traffic_shape.is_some()),
+            ("expose_port", payload.expose_port.is_some()),
+            ("deterministic", payload.deterministic),
+            ("debug_boot", payload.debug_boot),
+            (
+                "mount_reference_data",
+                payload.mount_reference_data.is_some(),
+            ),
+            ("keep_alive_seconds", payload.keep_alive_seconds.is_some()),
+            ("capture_console", payload.capture_console.is_some()),
+            ("debug", payload.debug.is_some()),
+            ("priority", payload.priority.is_some()),
+            ("affinity_key", payload.affinity_key.is_some()),
+            ("cache", payload.cache.is_some()),
+            ("cache_bypass", payload.cache_bypass),
+        ];
+        if let Some((option, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(Rejection::Invalid(ValidationError::NotForSandbox(option)));
+        }
+        let program = self.validate(&payload, key_id)?;
+        // The sandbox already holds the tenant's concurrency slot
+        self.quotas
+            .count(key_id)
+            .map_err(Rejection::QuotaExceeded)?;
+        let request = ExecutionSpec {
+            request_id,
+            program,
+            max_output_bytes: payload.max_output_bytes,
+            requirements: payload.requirements,
+            inputs: payload.inputs.unwrap_or_default(),
+            ..ExecutionSpec::code("")
+        };
+        self.executor
+            .execute_in_sandbox(sandbox_id, key_id, request)
+            .await
+            .map_err(Rejection::from)
+    }
+
+    /// Reject `payload` as `execute` would, without running it. Only the tenant's execution
+    /// quota is checked; its concurrency is up to when the execution runs.
+    pub fn check(&self, payload: &ExecuteRequest, key_id: Option<&str>) -> Result<(), Rejection> {
+        self.validate(payload, key_id)?;
+        self.quotas.check(key_id).map_err(Rejection::QuotaExceeded)
+    }
+
+    /// Every check a request must pass before a VM is spent on it
+    fn validate(
+        &self,
+        payload: &ExecuteRequest,
+        key_id: Option<&str>,
+    ) -> Result<Program, Rejection> {
+        validation::validate_request(payload, &Limits::from_config(&self.config))
+            .map_err(Rejection::Invalid)?;
+        let program = Program::from_request(payload)
+            .map_err(|e| Rejection::Invalid(ValidationError::Program(e)))?;
+        debug!("Received execute request: {:?}", program);
+
+        // Reject obviously hostile code before spending a VM on it
+        if !self.screener.bypasses(key_id)
+            && let Some(violation) = program
+                .sources()
+                .into_iter()
+                .find_map(|source| self.screener.screen(source).err())
+        {
+            info!("Code rejected by screening rule {}", violation.rule);
+            telemetry::increment_counter("fc_screening_rejections_total", &[], 1);
+            return Err(Rejection::Screened(format!(
+                "Code rejected by screening rule '{}'",
+                violation.rule
+            )));
+        }
+
+        if let Some(profile) = &payload.deps_profile
+            && !runner_config().deps_profiles.contains_key(profile)
+        {
+            return Err(Rejection::Invalid(ValidationError::UnknownDepsProfile(
+                profile.clone(),
+            )));
+        }
+
+        if let Some(inputs) = &payload.inputs {
+            inputs::validate(inputs, &runner_config().inputs)
+                .map_err(|e| Rejection::Invalid(e.into()))?;
+        }
+
+        if payload.traffic_shape.is_some() && !runner_config().shaping.allows_request_shape(key_id)
+        {
+            return Err(Rejection::Invalid(ValidationError::TrafficShapeNotAllowed));
+        }
+
+        if payload.capture_console == Some(true) && !self.executor.config().console_capture.enabled
+        {
+            return Err(Rejection::Invalid(ValidationError::ConsoleCaptureDisabled));
+        }
+
+        if let Some(secs) = payload.keep_alive_seconds {
+            self.executor
+                .config()
+                .sandbox
+                .validate(secs)
+                .map_err(|e| Rejection::Invalid(ValidationError::InvalidKeepAlive(e)))?;
+        }
+
+        if payload.debug == Some(true) && !self.config.debug_events.allows(key_id) {
+            return Err(Rejection::Invalid(ValidationError::DebugNotAllowed));
+        }
+
+        if let Some(image) = &payload.image
+            && runner_config().image_path(image).is_none()
+        {
+            return Err(Rejection::Invalid(ValidationError::UnknownImage(
+                image.clone(),
+            )));
+        }
+
+        Ok(program)
+    }
+}
+
+/// How a failed execution is reported to the caller: as the error itself, logged by whether
+/// the server or the request is at fault
+impl From<ExecutionError> for Rejection {
+    fn from(e: ExecutionError) -> Self {
+        match e {
+            ExecutionError::SandboxNotFound(_)
+            | ExecutionError::SandboxExpired(_)
+            | ExecutionError::SandboxBusy(_) => {}
+            ref e if e.status().is_server_error() && e.retry_after_secs().is_none() => {
+                error!("Code execution failed: {}", e)
+            }
+            ref e => tracing::warn!("Rejected execution: {}", e),
+        }
+        Rejection::Execution(e)
+    }
+}
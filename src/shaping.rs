@@ -0,0 +1,257 @@
+use crate::tap::CommandRunner;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Queueing discipline a TAP device is shaped with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Qdisc {
+    /// Token bucket filter: `rate`, `burst` and `latency` as `tc-tbf(8)` takes them
+    #[default]
+    Tbf,
+    /// A single hierarchical token bucket class; `latency` doesn't apply
+    Htb,
+}
+
+/// Host-side shaping of a VM's TAP device, in `tc` terms
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TrafficShape {
+    #[serde(default)]
+    pub qdisc: Qdisc,
+    /// Sustained rate in kbit/s
+    pub rate_kbit: u64,
+    /// Bytes that may be sent at once above the rate
+    pub burst_bytes: u64,
+    /// Longest a packet may wait in the tbf queue before it is dropped
+    #[serde(default = "default_latency_ms")]
+    pub latency_ms: u64,
+}
+
+fn default_latency_ms() -> u64 {
+    50
+}
+
+impl TrafficShape {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rate_kbit == 0 || self.burst_bytes == 0 {
+            return Err("traffic shape needs a non-zero rate_kbit and burst_bytes".to_string());
+        }
+        if self.qdisc == Qdisc::Tbf && self.latency_ms == 0 {
+            return Err("tbf traffic shape needs a non-zero latency_ms".to_string());
+        }
+        Ok(())
+    }
+
+    /// `tc` arguments that shape `device`; `replace` so they can be applied again
+    pub fn tc_commands(&self, device: &str) -> Vec<Vec<String>> {
+        let rate = format!("{}kbit", self.rate_kbit);
+        let burst = self.burst_bytes.to_string();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+        match self.qdisc {
+            Qdisc::Tbf => vec![args(&[
+                "qdisc",
+                "replace",
+                "dev",
+                device,
+                "root",
+                "tbf",
+                "rate",
+                &rate,
+                "burst",
+                &burst,
+                "latency",
+                &format!("{}ms", self.latency_ms),
+            ])],
+            Qdisc::Htb => vec![
+                args(&[
+                    "qdisc", "replace", "dev", device, "root", "handle", "1:", "htb", "default",
+                    "10",
+                ]),
+                args(&[
+                    "class", "replace", "dev", device, "parent", "1:", "classid", "1:10", "htb",
+                    "rate", &rate, "burst", &burst,
+                ]),
+            ],
+        }
+    }
+}
+
+/// Traffic shaping of VM TAP devices, loaded from `FC_TRAFFIC_SHAPING_FILE`; empty shapes
+/// nothing
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ShapingConfig {
+    /// Shape of VMs booted from the default rootfs, and of images without their own
+    pub default: Option<TrafficShape>,
+    /// Shapes of VMs booted from a named image
+    pub images: HashMap<String, TrafficShape>,
+    /// API key identifiers allowed to ask for a shape of their own per request
+    pub request_key_ids: Vec<String>,
+    /// Fail the boot when shaping can't be applied, instead of logging it
+    pub fatal: bool,
+}
+
+impl ShapingConfig {
+    /// Shape of a VM booted from `image`, if it is shaped at all
+    pub fn shape_for(&self, image: Option<&str>) -> Option<&TrafficShape> {
+        image
+            .and_then(|image| self.images.get(image))
+            .or(self.default.as_ref())
+    }
+
+    /// Whether the caller with API key `key_id` may choose a shape per request
+    pub fn allows_request_shape(&self, key_id: Option<&str>) -> bool {
+        key_id.is_some_and(|id| self.request_key_ids.iter().any(|allowed| allowed == id))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, shape) in self.default.iter().map(|s| ("default", s)).chain(
+            self.images
+                .iter()
+                .map(|(image, shape)| (image.as_str(), shape)),
+        ) {
+            shape.validate().map_err(|e| format!("{name}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Shape `device` with `shape`
+pub async fn apply(
+    runner: &dyn CommandRunner,
+    device: &str,
+    shape: &TrafficShape,
+) -> Result<(), String> {
+    for args in shape.tc_commands(device) {
+        let mut command = vec!["-n", "tc"];
+        command.extend(args.iter().map(String::as_str));
+        let output = runner
+            .run("sudo", &command)
+            .await
+            .map_err(|e| format!("failed to run tc: {e}"))?;
+        if !output.success {
+            return Err(format!(
+                "`{}` failed: {}",
+                command[1..].join(" "),
+                output.stderr.trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Remove the shaping of `device`; failures are only logged, as deleting the device drops it
+pub async fn remove(runner: &dyn CommandRunner, device: &str) {
+    let removed = runner
+        .run("sudo", &["-n", "tc", "qdisc", "del", "dev", device, "root"])
+        .await
+        .is_ok_and(|output| output.success);
+    if !removed {
+        tracing::debug!("Failed to remove the traffic shaping of {}", device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tap::CommandOutput;
+    use std::sync::Mutex;
+
+    /// Records commands, failing those that start with `fail`
+    #[derive(Default)]
+    struct RecordedCommands {
+        fail: Option<&'static str>,
+        calls: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl CommandRunner for RecordedCommands {
+        async fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            let call = format!("{program} {}", args.join(" "));
+            let success = !self.fail.is_some_and(|fail| call.starts_with(fail));
+            self.calls.lock().unwrap().push(call);
+            Ok(CommandOutput {
+                success,
+                stderr: if success {
+                    String::new()
+                } else {
+                    "RTNETLINK answers: Operation not permitted\n".to_string()
+                },
+                ..Default::default()
+            })
+        }
+    }
+
+    fn shape(qdisc: Qdisc) -> TrafficShape {
+        TrafficShape {
+            qdisc,
+            rate_kbit: 10_000,
+            burst_bytes: 32_768,
+            latency_ms: 50,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tbf_and_htb_invocations() {
+        let commands = RecordedCommands::default();
+        apply(&commands, "tap-0a1b2c3d", &shape(Qdisc::Tbf))
+            .await
+            .unwrap();
+        apply(&commands, "tap-0a1b2c3d", &shape(Qdisc::Htb))
+            .await
+            .unwrap();
+        remove(&commands, "tap-0a1b2c3d").await;
+        assert_eq!(
+            *commands.calls.lock().unwrap(),
+            [
+                "sudo -n tc qdisc replace dev tap-0a1b2c3d root tbf rate 10000kbit burst 32768 latency 50ms",
+                "sudo -n tc qdisc replace dev tap-0a1b2c3d root handle 1: htb default 10",
+                "sudo -n tc class replace dev tap-0a1b2c3d parent 1: classid 1:10 htb rate 10000kbit burst 32768",
+                "sudo -n tc qdisc del dev tap-0a1b2c3d root",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failure_names_the_command() {
+        let commands = RecordedCommands {
+            fail: Some("sudo -n tc class"),
+            ..Default::default()
+        };
+        let err = apply(&commands, "tap-x", &shape(Qdisc::Htb))
+            .await
+            .unwrap_err();
+        assert!(err.contains("tc class replace dev tap-x"), "{err}");
+        assert!(err.contains("Operation not permitted"), "{err}");
+    }
+
+    #[test]
+    fn test_shape_for_image_and_request_keys() {
+        let config: ShapingConfig = serde_json::from_str(
+            r#"{
+                "default": {"rate_kbit": 1000, "burst_bytes": 16384},
+                "images": {"ds": {"qdisc": "htb", "rate_kbit": 50000, "burst_bytes": 65536}},
+                "request_key_ids": ["trusted"]
+            }"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.shape_for(None).unwrap().latency_ms, 50);
+        assert_eq!(config.shape_for(Some("other")).unwrap().rate_kbit, 1000);
+        assert_eq!(config.shape_for(Some("ds")).unwrap().qdisc, Qdisc::Htb);
+        assert!(ShapingConfig::default().shape_for(Some("ds")).is_none());
+
+        assert!(config.allows_request_shape(Some("trusted")));
+        assert!(!config.allows_request_shape(Some("other")));
+        assert!(!config.allows_request_shape(None));
+        assert!(
+            TrafficShape {
+                rate_kbit: 0,
+                ..shape(Qdisc::Tbf)
+            }
+            .validate()
+            .is_err()
+        );
+    }
+}
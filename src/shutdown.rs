@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// Time the guest agent gets to acknowledge `/shutdown`
+pub const AGENT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Time the Firecracker process gets to exit after each graceful step
+pub const SHUTDOWN_STEP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How a VM was finally stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMethod {
+    /// There was no Firecracker process to stop
+    NotRunning,
+    /// The guest agent rebooted the VM
+    Agent,
+    /// Firecracker delivered Ctrl+Alt+Del to the guest
+    CtrlAltDel,
+    /// The Firecracker process was killed
+    Kill,
+    /// Every step failed; the process may still be running
+    Failed,
+}
+
+impl ShutdownMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ShutdownMethod::NotRunning => "not_running",
+            ShutdownMethod::Agent => "agent",
+            ShutdownMethod::CtrlAltDel => "ctrl_alt_del",
+            ShutdownMethod::Kill => "kill",
+            ShutdownMethod::Failed => "failed",
+        }
+    }
+}
+
+/// The rungs of the shutdown ladder, injectable for tests.
+/// Each step returns whether the Firecracker process is gone once it finishes.
+pub trait ShutdownSteps {
+    /// Ask the guest agent to reboot the VM
+    fn agent_shutdown(&mut self) -> impl Future<Output = bool> + Send;
+    /// Send Ctrl+Alt+Del through the Firecracker API
+    fn ctrl_alt_del(&mut self) -> impl Future<Output = bool> + Send;
+    /// Kill the Firecracker process
+    fn kill(&mut self) -> impl Future<Output = bool> + Send;
+}
+
+/// Try each step in turn, from most to least graceful, until the VM is gone
+pub async fn escalate<S: ShutdownSteps>(vm_id: &str, steps: &mut S) -> ShutdownMethod {
+    let method = if steps.agent_shutdown().await {
+        ShutdownMethod::Agent
+    } else if steps.ctrl_alt_del().await {
+        ShutdownMethod::CtrlAltDel
+    } else if steps.kill().await {
+        ShutdownMethod::Kill
+    } else {
+        ShutdownMethod::Failed
+    };
+    match method {
+        ShutdownMethod::Failed => tracing::warn!("VM {} could not be shut down", vm_id),
+        _ => tracing::info!("VM {} shut down via {}", vm_id, method.as_str()),
+    }
+    method
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the steps tried; each succeeds only if listed in `succeeds`
+    struct FakeSteps {
+        succeeds: &'static [&'static str],
+        calls: Vec<&'static str>,
+    }
+
+    impl FakeSteps {
+        fn step(&mut self, name: &'static str) -> bool {
+            self.calls.push(name);
+            self.succeeds.contains(&name)
+        }
+    }
+
+    impl ShutdownSteps for FakeSteps {
+        async fn agent_shutdown(&mut self) -> bool {
+            self.step("agent")
+        }
+
+        async fn ctrl_alt_del(&mut self) -> bool {
+            self.step("ctrl_alt_del")
+        }
+
+        async fn kill(&mut self) -> bool {
+            self.step("kill")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_escalation_order() {
+        let cases: [(&[&str], ShutdownMethod, &[&str]); 4] = [
+            (&["agent", "kill"], ShutdownMethod::Agent, &["agent"]),
+            (
+                &["ctrl_alt_del"],
+                ShutdownMethod::CtrlAltDel,
+                &["agent", "ctrl_alt_del"],
+            ),
+            (
+                &["kill"],
+                ShutdownMethod::Kill,
+                &["agent", "ctrl_alt_del", "kill"],
+            ),
+            (
+                &[],
+                ShutdownMethod::Failed,
+                &["agent", "ctrl_alt_del", "kill"],
+            ),
+        ];
+        for (succeeds, expected, calls) in cases {
+            let mut steps = FakeSteps {
+                succeeds,
+                calls: Vec::new(),
+            };
+            assert_eq!(escalate("vm-test", &mut steps).await, expected);
+            assert_eq!(steps.calls, calls);
+        }
+    }
+}
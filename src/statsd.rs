@@ -0,0 +1,340 @@
+use crate::telemetry::key_parts;
+use std::collections::BTreeMap;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Statsd agent address when `FC_STATSD_ADDR` is unset
+pub const DEFAULT_STATSD_ADDR: &str = "127.0.0.1:8125";
+
+/// Largest datagram sent, small enough to avoid fragmentation on a 1500-byte MTU
+const MAX_DATAGRAM_BYTES: usize = 1432;
+
+/// Name and sorted labels of one series
+type SeriesKey = (String, Vec<(String, String)>);
+
+#[derive(Debug, Default)]
+struct Pending {
+    /// Increments since the last flush
+    counters: BTreeMap<SeriesKey, u64>,
+    /// Latest values; resent on every flush so the agent never sees them go stale
+    gauges: BTreeMap<SeriesKey, f64>,
+    /// Observations since the last flush
+    timings: BTreeMap<SeriesKey, Vec<f64>>,
+}
+
+/// `metrics` recorder buffering what is recorded and pushing it to a statsd agent on an
+/// interval. Counters and histograms are sent as deltas, gauges as their latest value.
+#[derive(Debug, Clone)]
+pub struct StatsdRecorder {
+    prefix: Option<String>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl StatsdRecorder {
+    pub fn new(prefix: Option<String>) -> Self {
+        Self {
+            prefix,
+            pending: Arc::default(),
+        }
+    }
+
+    fn pending(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Statsd lines for everything recorded since the last drain
+    pub fn drain(&self) -> Vec<String> {
+        let prefix = self.prefix.as_deref();
+        let mut pending = self.pending();
+        let mut lines = Vec::new();
+        for ((name, labels), value) in std::mem::take(&mut pending.counters) {
+            lines.push(encode(prefix, &name, &value.to_string(), "c", &labels));
+        }
+        for ((name, labels), value) in &pending.gauges {
+            lines.push(encode(prefix, name, &value.to_string(), "g", labels));
+        }
+        for ((name, labels), values) in std::mem::take(&mut pending.timings) {
+            for value in values {
+                lines.push(encode(prefix, &name, &value.to_string(), "ms", &labels));
+            }
+        }
+        lines
+    }
+
+    /// Send the recorded metrics through `socket` every `interval`
+    pub fn spawn_flusher(&self, socket: UdpSocket, interval: Duration) {
+        let recorder = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                for packet in packets(&recorder.drain()) {
+                    // Statsd is lossy by design; a missed flush isn't worth more than a log line
+                    if let Err(e) = socket.send(packet.as_bytes()) {
+                        tracing::debug!("Failed to send statsd packet: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    fn handle(&self, key: &metrics::Key) -> StatsdHandle {
+        StatsdHandle {
+            pending: self.pending.clone(),
+            key: key_parts(key),
+        }
+    }
+}
+
+/// UDP socket connected to the statsd agent at `addr`
+pub fn connect(addr: &str) -> std::io::Result<UdpSocket> {
+    let target = addr.to_socket_addrs()?.next().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{addr} did not resolve"),
+        )
+    })?;
+    let socket = UdpSocket::bind(if target.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })?;
+    socket.connect(target)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// One statsd line: `prefix.name:value|kind|#key:value,...`
+pub fn encode(
+    prefix: Option<&str>,
+    name: &str,
+    value: &str,
+    kind: &str,
+    labels: &[(String, String)],
+) -> String {
+    let mut line = match prefix {
+        Some(prefix) => format!("{}.{}", sanitize(prefix), sanitize(name)),
+        None => sanitize(name),
+    };
+    line.push(':');
+    line.push_str(value);
+    line.push('|');
+    line.push_str(kind);
+    if !labels.is_empty() {
+        let tags: Vec<String> = labels
+            .iter()
+            .map(|(k, v)| format!("{}:{}", sanitize(k), sanitize(v)))
+            .collect();
+        line.push_str("|#");
+        line.push_str(&tags.join(","));
+    }
+    line
+}
+
+/// Replace the characters statsd uses as separators
+fn sanitize(part: &str) -> String {
+    part.chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | ',' | '#' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Lines joined by newlines into datagrams of at most `MAX_DATAGRAM_BYTES`
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM_BYTES {
+            packets.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        packets.push(current);
+    }
+    packets
+}
+
+struct StatsdHandle {
+    pending: Arc<Mutex<Pending>>,
+    key: SeriesKey,
+}
+
+impl StatsdHandle {
+    fn pending(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl metrics::CounterFn for StatsdHandle {
+    fn increment(&self, value: u64) {
+        *self.pending().counters.entry(self.key.clone()).or_default() += value;
+    }
+
+    fn absolute(&self, value: u64) {
+        self.pending().counters.insert(self.key.clone(), value);
+    }
+}
+
+impl metrics::GaugeFn for StatsdHandle {
+    fn increment(&self, value: f64) {
+        *self.pending().gauges.entry(self.key.clone()).or_default() += value;
+    }
+
+    fn decrement(&self, value: f64) {
+        *self.pending().gauges.entry(self.key.clone()).or_default() -= value;
+    }
+
+    fn set(&self, value: f64) {
+        self.pending().gauges.insert(self.key.clone(), value);
+    }
+}
+
+impl metrics::HistogramFn for StatsdHandle {
+    fn record(&self, value: f64) {
+        self.pending()
+            .timings
+            .entry(self.key.clone())
+            .or_default()
+            .push(value);
+    }
+}
+
+impl metrics::Recorder for StatsdRecorder {
+    fn describe_counter(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_gauge(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_histogram(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn register_counter(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Counter {
+        metrics::Counter::from_arc(Arc::new(self.handle(key)))
+    }
+
+    fn register_gauge(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+        metrics::Gauge::from_arc(Arc::new(self.handle(key)))
+    }
+
+    fn register_histogram(
+        &self,
+        key: &metrics::Key,
+        _: &metrics::Metadata<'_>,
+    ) -> metrics::Histogram {
+        metrics::Histogram::from_arc(Arc::new(self.handle(key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry;
+
+    fn labels(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_names_and_tags() {
+        assert_eq!(
+            encode(None, "fc_cache_hits_total", "1", "c", &[]),
+            "fc_cache_hits_total:1|c"
+        );
+        assert_eq!(
+            encode(
+                Some("sandbox"),
+                "fc_vm_shutdowns_total",
+                "2",
+                "c",
+                &labels(&[("method", "agent"), ("region", "eu")])
+            ),
+            "sandbox.fc_vm_shutdowns_total:2|c|#method:agent,region:eu"
+        );
+        // Separators inside names and values can't split a line
+        assert_eq!(
+            encode(None, "a:b", "0.5", "g", &labels(&[("client", "k|1,#x")])),
+            "a_b:0.5|g|#client:k_1__x"
+        );
+    }
+
+    #[test]
+    fn test_packets_stay_under_the_datagram_limit() {
+        let lines: Vec<String> = (0..100).map(|i| format!("fc_metric_{i}:1|c")).collect();
+        let packets = packets(&lines);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= MAX_DATAGRAM_BYTES));
+        assert_eq!(packets.join("\n").lines().count(), 100);
+    }
+
+    #[test]
+    fn test_recorder_drains_deltas_and_keeps_gauges() {
+        let recorder = StatsdRecorder::new(Some("fc".to_string()));
+        metrics::with_local_recorder(&recorder, || {
+            telemetry::increment_counter("fc_rate_limited_total", &[], 1);
+            telemetry::increment_counter("fc_rate_limited_total", &[], 2);
+            telemetry::set_gauge("fc_live_vms", &[], 3.0);
+            telemetry::observe_histogram("fc_execution_wall_ms", &[("phase", "run")], 12.5);
+        });
+        assert_eq!(
+            recorder.drain(),
+            vec![
+                "fc.fc_rate_limited_total:3|c",
+                "fc.fc_live_vms:3|g",
+                "fc.fc_execution_wall_ms:12.5|ms|#phase:run",
+            ]
+        );
+        assert_eq!(recorder.drain(), vec!["fc.fc_live_vms:3|g"]);
+    }
+
+    #[tokio::test]
+    async fn test_flusher_sends_to_the_agent() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recorder = StatsdRecorder::new(None);
+        metrics::with_local_recorder(&recorder, || {
+            telemetry::increment_counter("fc_cache_hits_total", &[], 1);
+        });
+        recorder.spawn_flusher(
+            connect(&agent.local_addr().unwrap().to_string()).unwrap(),
+            Duration::from_millis(10),
+        );
+
+        let received = tokio::task::spawn_blocking(move || {
+            agent
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut buf = [0; MAX_DATAGRAM_BYTES];
+            let len = agent.recv(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        })
+        .await
+        .unwrap();
+        assert_eq!(received, "fc_cache_hits_total:1|c");
+    }
+}
@@ -0,0 +1,150 @@
+use crate::config::ConfigError;
+use crate::executor::PooledVm;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+/// Name `/pool` gives the pool of VMs on the default rootfs
+pub const DEFAULT_SUB_POOL: &str = "default";
+
+/// Warm VMs kept for one image, next to the default pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubPoolConfig {
+    /// VMs booted ahead of requests and kept warm
+    pub min_warm: usize,
+    /// Most idle VMs kept; VMs coming back from an execution beyond it are shut down
+    pub max: usize,
+}
+
+/// Sub-pools by image name from `FC_SUB_POOLS`, e.g. `scientific=2:4,bash=1:1` keeps 2 to 4
+/// VMs of image `scientific` and one of image `bash`
+pub fn parse_sub_pools(raw: &str) -> Result<BTreeMap<String, SubPoolConfig>, ConfigError> {
+    let invalid =
+        |entry: &str, why: &str| ConfigError::Invalid(format!("sub-pool {entry:?}: {why}"));
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (image, sizes) = entry
+                .split_once('=')
+                .ok_or_else(|| invalid(entry, "expected IMAGE=MIN:MAX"))?;
+            let image = image.trim();
+            if image.is_empty() || image == DEFAULT_SUB_POOL {
+                return Err(invalid(
+                    entry,
+                    "the default rootfs is sized by FC_POOL_SIZE",
+                ));
+            }
+            let (min_warm, max) = sizes
+                .split_once(':')
+                .ok_or_else(|| invalid(entry, "expected IMAGE=MIN:MAX"))?;
+            let parse = |value: &str| {
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| invalid(entry, "sizes must be whole numbers"))
+            };
+            let config = SubPoolConfig {
+                min_warm: parse(min_warm)?,
+                max: parse(max)?,
+            };
+            if config.max == 0 || config.min_warm > config.max {
+                return Err(invalid(entry, "MAX must be at least 1 and MIN at most MAX"));
+            }
+            Ok((image.to_string(), config))
+        })
+        .collect()
+}
+
+/// Pool a VM with `image` and `deps_profile` goes back to after an execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolKey<'a> {
+    /// The default rootfs, deps profile or not, sized by the pool size or the autoscaler
+    Default,
+    /// The configured sub-pool of this image
+    Sub(&'a str, SubPoolConfig),
+    /// An image without a sub-pool, or one with a deps profile: booted on demand and
+    /// discarded after its execution
+    OnDemand,
+}
+
+impl<'a> PoolKey<'a> {
+    pub fn of(
+        sub_pools: &BTreeMap<String, SubPoolConfig>,
+        image: Option<&'a str>,
+        deps_profile: Option<&str>,
+    ) -> Self {
+        match (image, deps_profile) {
+            (None, _) => PoolKey::Default,
+            (Some(image), None) => match sub_pools.get(image) {
+                Some(config) => PoolKey::Sub(image, *config),
+                None => PoolKey::OnDemand,
+            },
+            (Some(_), Some(_)) => PoolKey::OnDemand,
+        }
+    }
+}
+
+/// One sub-pool as `GET /pool` shows it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SubPoolState {
+    /// Image of the sub-pool, or `default` for the default rootfs
+    pub name: String,
+    /// VMs kept warm: the pool target for the default pool
+    pub min_warm: usize,
+    /// Most idle VMs kept
+    pub max: usize,
+    /// Its VMs, oldest pooled first
+    pub vms: Vec<PooledVm>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sub_pools() {
+        let pools = parse_sub_pools(" scientific=2:4, bash=1:1 ,").unwrap();
+        assert_eq!(
+            pools["scientific"],
+            SubPoolConfig {
+                min_warm: 2,
+                max: 4
+            }
+        );
+        assert_eq!(
+            pools["bash"],
+            SubPoolConfig {
+                min_warm: 1,
+                max: 1
+            }
+        );
+        assert!(parse_sub_pools("").unwrap().is_empty());
+        for invalid in [
+            "scientific",
+            "scientific=2",
+            "scientific=3:2",
+            "scientific=0:0",
+            "scientific=a:2",
+            "default=1:2",
+        ] {
+            assert!(parse_sub_pools(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn test_pool_key() {
+        let pools = parse_sub_pools("bash=1:2").unwrap();
+        assert_eq!(PoolKey::of(&pools, None, None), PoolKey::Default);
+        assert_eq!(PoolKey::of(&pools, None, Some("numpy")), PoolKey::Default);
+        assert_eq!(
+            PoolKey::of(&pools, Some("bash"), None),
+            PoolKey::Sub("bash", pools["bash"])
+        );
+        assert_eq!(
+            PoolKey::of(&pools, Some("bash"), Some("numpy")),
+            PoolKey::OnDemand
+        );
+        assert_eq!(PoolKey::of(&pools, Some("ruby"), None), PoolKey::OnDemand);
+    }
+}
@@ -0,0 +1,269 @@
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// First file descriptor systemd passes with socket activation
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// An `sd_notify` message of `KEY=VALUE` lines
+pub fn message(fields: &[(&str, &str)]) -> String {
+    fields
+        .iter()
+        .map(|(key, value)| format!("{key}={value}\n"))
+        .collect()
+}
+
+/// Whether variables naming a process, as `LISTEN_PID` and `WATCHDOG_PID` do, are meant for
+/// `our_pid`; unset means any process
+fn for_us(pid: Option<&str>, our_pid: u32) -> bool {
+    pid.is_none_or(|pid| pid.trim().parse() == Ok(our_pid))
+}
+
+/// Sockets passed by systemd, from `LISTEN_PID` and `LISTEN_FDS`
+pub fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, our_pid: u32) -> usize {
+    // Without LISTEN_PID the variables may have been inherited from a parent
+    if listen_pid.is_none() || !for_us(listen_pid, our_pid) {
+        return 0;
+    }
+    listen_fds
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// How often to ping the watchdog, from `WATCHDOG_USEC` and `WATCHDOG_PID`: half the
+/// timeout, as `sd_watchdog_enabled` advises
+pub fn watchdog_interval(
+    watchdog_usec: Option<&str>,
+    watchdog_pid: Option<&str>,
+    our_pid: u32,
+) -> Option<Duration> {
+    if !for_us(watchdog_pid, our_pid) {
+        return None;
+    }
+    let usec: u64 = watchdog_usec?.trim().parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// The listening socket systemd passed this process, if it was socket activated. Only the
+/// first is used when several are passed.
+pub fn listen_fd() -> Option<OwnedFd> {
+    let count = listen_fds(
+        var("LISTEN_PID").as_deref(),
+        var("LISTEN_FDS").as_deref(),
+        std::process::id(),
+    );
+    if count == 0 {
+        return None;
+    }
+    if count > 1 {
+        tracing::warn!(
+            "systemd passed {} sockets; serving on the first only",
+            count
+        );
+    }
+    // SAFETY: with LISTEN_PID naming this process, systemd hands over fd 3 for it to own
+    let inherited = unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) };
+    // The copy is close-on-exec, so Firecracker and the jailer don't inherit the port
+    match inherited.try_clone() {
+        Ok(fd) => Some(fd),
+        Err(e) => {
+            tracing::warn!("Failed to take the socket passed by systemd: {}", e);
+            None
+        }
+    }
+}
+
+/// The timeout of `WatchdogSec=`, halved, if systemd watches this process
+pub fn watchdog_interval_from_env() -> Option<Duration> {
+    watchdog_interval(
+        var("WATCHDOG_USEC").as_deref(),
+        var("WATCHDOG_PID").as_deref(),
+        std::process::id(),
+    )
+}
+
+/// Sends `sd_notify` messages to `$NOTIFY_SOCKET`; does nothing when not run by systemd
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    socket: Option<String>,
+}
+
+impl Notifier {
+    /// A notifier for the socket at `socket`, a path or an abstract name starting with `@`
+    pub fn new(socket: Option<String>) -> Self {
+        Self {
+            socket: socket.filter(|socket| !socket.is_empty()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(var("NOTIFY_SOCKET"))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Send `fields` as one datagram
+    pub fn notify(&self, fields: &[(&str, &str)]) -> std::io::Result<()> {
+        let Some(socket) = &self.socket else {
+            return Ok(());
+        };
+        let datagram = UnixDatagram::unbound()?;
+        let message = message(fields);
+        match socket.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                datagram.send_to_addr(message.as_bytes(), &addr)?;
+            }
+            _ => {
+                datagram.send_to(message.as_bytes(), socket)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&self, fields: &[(&str, &str)]) {
+        if let Err(e) = self.notify(fields) {
+            tracing::warn!("Failed to notify systemd: {}", e);
+        }
+    }
+
+    /// The service is ready, with `status` shown by `systemctl status`
+    pub fn ready(&self, status: &str) {
+        self.send(&[("READY", "1"), ("STATUS", status)]);
+    }
+
+    pub fn stopping(&self) {
+        self.send(&[("STOPPING", "1"), ("STATUS", "Draining in-flight requests")]);
+    }
+
+    pub fn watchdog(&self) {
+        self.send(&[("WATCHDOG", "1")]);
+    }
+}
+
+/// Ping the watchdog every `interval` while `healthy` holds. Once it fails the pings stop,
+/// so systemd restarts the service when `WatchdogSec=` runs out.
+pub fn spawn_watchdog(
+    notifier: Notifier,
+    interval: Duration,
+    healthy: impl Fn() -> bool + Send + 'static,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        loop {
+            ticks.tick().await;
+            if !healthy() {
+                tracing::error!("Service unhealthy; no longer pinging the systemd watchdog");
+                notifier.send(&[(
+                    "STATUS",
+                    "Unhealthy; waiting for the watchdog to restart it",
+                )]);
+                return;
+            }
+            notifier.watchdog();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A fake `NOTIFY_SOCKET` and a notifier sending to it
+    fn notify_socket(name: &str) -> (UnixDatagram, Notifier) {
+        let path = std::env::temp_dir().join(format!("fc-notify-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        (socket, Notifier::new(Some(path.display().to_string())))
+    }
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 256];
+        let read = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..read]).into_owned()
+    }
+
+    #[test]
+    fn test_env_parsing() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 2);
+        // Meant for another process, or not meant for anyone
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("many"), 42), 0);
+
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_notify_messages() {
+        assert_eq!(message(&[("WATCHDOG", "1")]), "WATCHDOG=1\n");
+        let (socket, notifier) = notify_socket("messages");
+        assert!(notifier.enabled());
+        notifier.ready("4 VMs warm");
+        assert_eq!(receive(&socket), "READY=1\nSTATUS=4 VMs warm\n");
+        notifier.stopping();
+        assert!(receive(&socket).starts_with("STOPPING=1\n"));
+        notifier.watchdog();
+        assert_eq!(receive(&socket), "WATCHDOG=1\n");
+
+        // Without NOTIFY_SOCKET nothing is sent and nothing fails
+        let absent = Notifier::new(None);
+        assert!(!absent.enabled());
+        absent.notify(&[("READY", "1")]).unwrap();
+        assert!(!Notifier::new(Some(String::new())).enabled());
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_stops_once_unhealthy() {
+        let (socket, notifier) = notify_socket("watchdog");
+        let healthy = Arc::new(AtomicBool::new(true));
+        let flag = healthy.clone();
+        let pinger = spawn_watchdog(notifier, Duration::from_millis(20), move || {
+            flag.load(Ordering::SeqCst)
+        });
+        let socket = tokio::task::spawn_blocking(move || {
+            assert_eq!(receive(&socket), "WATCHDOG=1\n");
+            socket
+        })
+        .await
+        .unwrap();
+        healthy.store(false, Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(2), pinger)
+            .await
+            .expect("the watchdog loop ends")
+            .unwrap();
+        // The last message is the unhealthy status, not another ping
+        socket.set_nonblocking(true).unwrap();
+        let mut last = String::new();
+        let mut buffer = [0; 256];
+        while let Ok(read) = socket.recv(&mut buffer) {
+            last = String::from_utf8_lossy(&buffer[..read]).into_owned();
+        }
+        assert!(last.starts_with("STATUS=Unhealthy"), "{last}");
+    }
+}
@@ -0,0 +1,330 @@
+use crate::ExecutionError;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Names tried for a VM's TAP device before giving up
+pub const TAP_CREATE_ATTEMPTS: u32 = 4;
+
+/// Prefix of every TAP device this service creates
+pub const TAP_PREFIX: &str = "tap-";
+
+/// What a host command printed and whether it succeeded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs the host commands that manage TAP devices, injectable for tests
+#[async_trait::async_trait]
+pub trait CommandRunner: Send + Sync {
+    async fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput>;
+}
+
+/// Runner spawning the commands on the host. Privileged commands are given as `sudo -n ...`,
+/// which fails rather than waits for a password; a process holding `CAP_NET_ADMIN` runs them
+/// itself instead.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostCommands;
+
+#[async_trait::async_trait]
+impl CommandRunner for HostCommands {
+    async fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+        let (program, args) = match args {
+            ["-n", command, rest @ ..]
+                if program == "sudo" && crate::privilege::process_has_cap_net_admin() =>
+            {
+                (*command, rest)
+            }
+            _ => (program, args),
+        };
+        let output = tokio::process::Command::new(program)
+            .args(args)
+            .output()
+            .await?;
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// TAP device name of VM `vm_id` for the `attempt`th try. Later attempts add a counter, since
+/// only the ID's first 8 hex digits fit Linux's 15-byte interface names.
+pub fn tap_name(vm_id: &str, attempt: u32) -> String {
+    let prefix = &vm_id[..vm_id.len().min(8)];
+    match attempt {
+        0 => format!("{TAP_PREFIX}{prefix}"),
+        n => format!("{TAP_PREFIX}{prefix}-{n}"),
+    }
+}
+
+/// Whether `ip tuntap add` failed because the name is taken
+fn name_taken(stderr: &str) -> bool {
+    let stderr = stderr.to_ascii_lowercase();
+    stderr.contains("file exists") || stderr.contains("device or resource busy")
+}
+
+/// TAP devices created by this service, recorded as marker files under the runtime directory
+/// so they are told apart from other software's devices across restarts
+#[derive(Debug, Clone)]
+pub struct TapRegistry {
+    dir: PathBuf,
+}
+
+impl TapRegistry {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Record that `name` was created by this service
+    pub fn own(&self, name: &str) {
+        if let Err(e) = std::fs::create_dir_all(&self.dir)
+            .and_then(|()| std::fs::write(self.dir.join(name), ""))
+        {
+            tracing::warn!("Failed to record TAP interface {} as ours: {}", name, e);
+        }
+    }
+
+    /// Forget `name` once it is deleted
+    pub fn release(&self, name: &str) {
+        let _ = std::fs::remove_file(self.dir.join(name));
+    }
+
+    /// Whether `name` was created by this service
+    pub fn owns(&self, name: &str) -> bool {
+        self.dir.join(name).is_file()
+    }
+}
+
+/// Create a TAP device for VM `vm_id` and record it in `registry`, returning its name. A name
+/// left taken by an orphaned device is skipped for the next one, up to `TAP_CREATE_ATTEMPTS`.
+pub async fn create_tap(
+    runner: &dyn CommandRunner,
+    registry: &TapRegistry,
+    vm_id: &str,
+) -> Result<String, ExecutionError> {
+    let mut last_error = String::new();
+    for attempt in 0..TAP_CREATE_ATTEMPTS {
+        let name = tap_name(vm_id, attempt);
+        let output = runner
+            .run(
+                "sudo",
+                &["-n", "ip", "tuntap", "add", "dev", &name, "mode", "tap"],
+            )
+            .await
+            .map_err(|e| {
+                ExecutionError::ResourceError(format!("Failed to create TAP interface {name}: {e}"))
+            })?;
+        if output.success {
+            registry.own(&name);
+            return Ok(name);
+        }
+        let stderr = output.stderr.trim().to_string();
+        if !name_taken(&stderr) {
+            return Err(ExecutionError::ResourceError(format!(
+                "Failed to create TAP interface {name}: {stderr}"
+            )));
+        }
+        tracing::warn!("TAP interface {} already exists; trying another name", name);
+        last_error = format!("{name}: {stderr}");
+    }
+    Err(ExecutionError::ResourceError(format!(
+        "Failed to create a TAP interface for VM {vm_id} after {TAP_CREATE_ATTEMPTS} names \
+         (last {last_error}); delete the stale tap-* interfaces shown by `ip link show type tun`"
+    )))
+}
+
+/// Names of the `tap-*` devices in `ip link show type tun` output
+pub fn listed_taps(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let start = line.find(TAP_PREFIX)?;
+            let end = line[start..].find(':')?;
+            Some(line[start..start + end].to_string())
+        })
+        .collect()
+}
+
+/// Delete the TAP devices this service created that no live VM uses, leaving devices of other
+/// software alone even when they share the prefix. Returns how many were deleted.
+pub async fn cleanup_orphaned_taps(
+    runner: &dyn CommandRunner,
+    registry: &TapRegistry,
+    in_use: &HashSet<String>,
+) -> usize {
+    let Ok(output) = runner.run("ip", &["link", "show", "type", "tun"]).await else {
+        return 0;
+    };
+    let mut removed = 0;
+    for name in listed_taps(&output.stdout) {
+        if in_use.contains(&name) {
+            tracing::debug!("Skipping active TAP interface: {}", name);
+        } else if !registry.owns(&name) {
+            tracing::debug!(
+                "Leaving TAP interface {} alone; this service didn't create it",
+                name
+            );
+        } else {
+            tracing::debug!("Removing orphaned TAP interface: {}", name);
+            if runner
+                .run("sudo", &["-n", "ip", "link", "delete", &name])
+                .await
+                .is_ok_and(|output| output.success)
+            {
+                registry.release(&name);
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Answers commands from a script and records them
+    #[derive(Default)]
+    struct ScriptedCommands {
+        /// Device names `ip tuntap add` finds already taken
+        taken: HashSet<String>,
+        listing: String,
+        calls: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl CommandRunner for ScriptedCommands {
+        async fn run(&self, program: &str, args: &[&str]) -> std::io::Result<CommandOutput> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("{program} {}", args.join(" ")));
+            Ok(match args {
+                ["-n", "ip", "tuntap", "add", "dev", name, ..] if self.taken.contains(*name) => {
+                    CommandOutput {
+                        success: false,
+                        stderr: "ioctl(TUNSETIFF): File exists\n".to_string(),
+                        ..Default::default()
+                    }
+                }
+                ["link", "show", ..] => CommandOutput {
+                    success: true,
+                    stdout: self.listing.clone(),
+                    ..Default::default()
+                },
+                _ => CommandOutput {
+                    success: true,
+                    ..Default::default()
+                },
+            })
+        }
+    }
+
+    fn registry(name: &str) -> TapRegistry {
+        let dir = std::env::temp_dir().join(format!("fc-taps-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        TapRegistry::new(dir)
+    }
+
+    const VM_ID: &str = "0a1b2c3d-0000-4000-8000-000000000000";
+
+    #[tokio::test]
+    async fn test_taken_names_are_skipped() {
+        let commands = ScriptedCommands {
+            taken: ["tap-0a1b2c3d", "tap-0a1b2c3d-1"].map(String::from).into(),
+            ..Default::default()
+        };
+        let registry = registry("rename");
+        let name = create_tap(&commands, &registry, VM_ID).await.unwrap();
+        assert_eq!(name, "tap-0a1b2c3d-2");
+        assert!(registry.owns(&name));
+        assert!(!registry.owns("tap-0a1b2c3d"));
+        assert_eq!(commands.calls.lock().unwrap().len(), 3);
+        assert!(name.len() <= 15);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_with_the_name_and_stderr() {
+        let commands = ScriptedCommands {
+            taken: (0..TAP_CREATE_ATTEMPTS)
+                .map(|attempt| tap_name(VM_ID, attempt))
+                .collect(),
+            ..Default::default()
+        };
+        let err = create_tap(&commands, &registry("exhausted"), VM_ID)
+            .await
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("tap-0a1b2c3d-3"), "{message}");
+        assert!(message.contains("File exists"), "{message}");
+        assert!(message.contains("ip link show type tun"), "{message}");
+    }
+
+    #[tokio::test]
+    async fn test_other_failures_are_not_retried() {
+        struct Denied;
+        #[async_trait::async_trait]
+        impl CommandRunner for Denied {
+            async fn run(&self, _: &str, _: &[&str]) -> std::io::Result<CommandOutput> {
+                Ok(CommandOutput {
+                    success: false,
+                    stderr: "ioctl(TUNSETIFF): Operation not permitted".to_string(),
+                    ..Default::default()
+                })
+            }
+        }
+        let err = create_tap(&Denied, &registry("denied"), VM_ID)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Resource management error: Failed to create TAP interface tap-0a1b2c3d: ioctl(TUNSETIFF): Operation not permitted"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_deletes_only_our_orphans() {
+        let commands = ScriptedCommands {
+            listing: "\
+5: tap-aaaaaaaa: <BROADCAST,MULTICAST> mtu 1500 qdisc noop state DOWN
+6: tap-bbbbbbbb-1: <BROADCAST,MULTICAST> mtu 1500 qdisc noop state DOWN
+7: tap-cccccccc: <BROADCAST,MULTICAST> mtu 1500 qdisc noop state DOWN
+8: tap-vpn0: <POINTOPOINT,MULTICAST> mtu 1500 qdisc noop state DOWN
+"
+            .to_string(),
+            ..Default::default()
+        };
+        let registry = registry("cleanup");
+        for name in ["tap-aaaaaaaa", "tap-bbbbbbbb-1", "tap-cccccccc"] {
+            registry.own(name);
+        }
+        let in_use = HashSet::from(["tap-cccccccc".to_string()]);
+
+        assert_eq!(
+            cleanup_orphaned_taps(&commands, &registry, &in_use).await,
+            2
+        );
+        let deletes: Vec<String> = commands
+            .calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.contains("link delete"))
+            .cloned()
+            .collect();
+        assert_eq!(
+            deletes,
+            [
+                "sudo -n ip link delete tap-aaaaaaaa",
+                "sudo -n ip link delete tap-bbbbbbbb-1"
+            ]
+        );
+        assert!(!registry.owns("tap-aaaaaaaa"));
+        assert!(registry.owns("tap-cccccccc"));
+    }
+}
@@ -0,0 +1,507 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Interval at which push exporters send what was recorded
+pub const DEFAULT_PUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Collector endpoint of the OTLP exporter when `FC_OTLP_ENDPOINT` is unset
+pub const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4317";
+
+/// Where metrics go. Instrumentation always goes through the `metrics` facade, so the choice
+/// only decides which recorder is installed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricsExporter {
+    /// Kept in `METRICS` and scraped from `GET /metrics`
+    Prometheus,
+    /// Pushed as statsd lines over UDP, with DogStatsD-style tags
+    Statsd {
+        addr: String,
+        /// Prepended to every metric name, joined with a `.`
+        prefix: Option<String>,
+    },
+    /// Pushed to an OpenTelemetry collector over gRPC
+    Otlp { endpoint: String },
+}
+
+/// Metrics export settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsConfig {
+    pub exporter: MetricsExporter,
+    /// How often the statsd and OTLP exporters push
+    pub push_interval: Duration,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            exporter: MetricsExporter::Prometheus,
+            push_interval: DEFAULT_PUSH_INTERVAL,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ExporterError {
+    #[error("a metrics exporter is already installed")]
+    AlreadyInstalled,
+    #[error("statsd exporter: {0}")]
+    Statsd(#[from] std::io::Error),
+    #[error("OTLP exporter: {0}")]
+    Otlp(String),
+}
+
+/// Default histogram buckets, suitable for millisecond latencies
+const DEFAULT_BUCKETS: &[f64] = &[
+    5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0, 30000.0,
+];
+
+/// Sorted label pairs identifying one series within a metric family
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+#[derive(Debug, Clone)]
+enum Series {
+    Counter(u64),
+    Gauge(f64),
+    Histogram {
+        bucket_counts: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+#[derive(Debug)]
+struct Family {
+    kind: Kind,
+    series: BTreeMap<Labels, Series>,
+}
+
+/// In-process metrics registry rendered in the Prometheus text exposition format; clones
+/// share their series. As a `metrics::Recorder` it is the Prometheus exporter.
+#[derive(Debug, Default, Clone)]
+pub struct Registry {
+    families: Arc<Mutex<BTreeMap<String, Family>>>,
+}
+
+/// Global metrics registry served by `GET /metrics`
+pub static METRICS: once_cell::sync::Lazy<Registry> = once_cell::sync::Lazy::new(Registry::default);
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+    let mut labels: Labels = labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+impl Registry {
+    fn with_series(
+        &self,
+        name: &str,
+        kind: Kind,
+        labels: &[(&str, &str)],
+        f: impl FnOnce(&mut Series),
+    ) {
+        let mut families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            kind,
+            series: BTreeMap::new(),
+        });
+        if family.kind != kind {
+            tracing::warn!("Metric {} used with conflicting types", name);
+            return;
+        }
+        let series = family
+            .series
+            .entry(to_labels(labels))
+            .or_insert_with(|| match kind {
+                Kind::Counter => Series::Counter(0),
+                Kind::Gauge => Series::Gauge(0.0),
+                Kind::Histogram => Series::Histogram {
+                    bucket_counts: vec![0; DEFAULT_BUCKETS.len()],
+                    sum: 0.0,
+                    count: 0,
+                },
+            });
+        f(series);
+    }
+
+    /// Increase a counter by `value`
+    pub fn increment_counter(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.with_series(name, Kind::Counter, labels, |series| {
+            if let Series::Counter(v) = series {
+                *v += value;
+            }
+        });
+    }
+
+    /// Set a gauge to `value`
+    pub fn set_gauge(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.with_series(name, Kind::Gauge, labels, |series| {
+            if let Series::Gauge(v) = series {
+                *v = value;
+            }
+        });
+    }
+
+    /// Record one observation in a histogram
+    pub fn observe_histogram(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.with_series(name, Kind::Histogram, labels, |series| {
+            if let Series::Histogram {
+                bucket_counts,
+                sum,
+                count,
+            } = series
+            {
+                for (bucket, upper) in bucket_counts.iter_mut().zip(DEFAULT_BUCKETS) {
+                    if value <= *upper {
+                        *bucket += 1;
+                    }
+                }
+                *sum += value;
+                *count += 1;
+            }
+        });
+    }
+
+    /// Current value of a counter, if it has been recorded
+    pub fn counter_value(&self, name: &str, labels: &[(&str, &str)]) -> Option<u64> {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        match families.get(name)?.series.get(&to_labels(labels))? {
+            Series::Counter(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Current value of a gauge, if it has been recorded
+    pub fn gauge_value(&self, name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        match families.get(name)?.series.get(&to_labels(labels))? {
+            Series::Gauge(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let kind = match family.kind {
+                Kind::Counter => "counter",
+                Kind::Gauge => "gauge",
+                Kind::Histogram => "histogram",
+            };
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, series) in &family.series {
+                match series {
+                    Series::Counter(v) => {
+                        let _ = writeln!(out, "{name}{} {v}", format_labels(labels, None));
+                    }
+                    Series::Gauge(v) => {
+                        let _ = writeln!(out, "{name}{} {v}", format_labels(labels, None));
+                    }
+                    Series::Histogram {
+                        bucket_counts,
+                        sum,
+                        count,
+                    } => {
+                        for (bucket, upper) in bucket_counts.iter().zip(DEFAULT_BUCKETS) {
+                            let le = upper.to_string();
+                            let _ = writeln!(
+                                out,
+                                "{name}_bucket{} {bucket}",
+                                format_labels(labels, Some(&le))
+                            );
+                        }
+                        let _ = writeln!(
+                            out,
+                            "{name}_bucket{} {count}",
+                            format_labels(labels, Some("+Inf"))
+                        );
+                        let _ = writeln!(out, "{name}_sum{} {sum}", format_labels(labels, None));
+                        let _ =
+                            writeln!(out, "{name}_count{} {count}", format_labels(labels, None));
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn format_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", escape_label_value(v)))
+        .collect();
+    if let Some(le) = le {
+        parts.push(format!("le=\"{le}\""));
+    }
+    if parts.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// One series of a `Registry`, handed out to the `metrics` facade
+struct RegistryHandle {
+    registry: Registry,
+    name: String,
+    labels: Labels,
+}
+
+impl RegistryHandle {
+    fn labels(&self) -> Vec<(&str, &str)> {
+        self.labels
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+}
+
+impl metrics::CounterFn for RegistryHandle {
+    fn increment(&self, value: u64) {
+        self.registry
+            .increment_counter(&self.name, &self.labels(), value);
+    }
+
+    fn absolute(&self, value: u64) {
+        self.registry
+            .with_series(&self.name, Kind::Counter, &self.labels(), |series| {
+                if let Series::Counter(v) = series {
+                    *v = (*v).max(value);
+                }
+            });
+    }
+}
+
+impl metrics::GaugeFn for RegistryHandle {
+    fn increment(&self, value: f64) {
+        self.registry
+            .with_series(&self.name, Kind::Gauge, &self.labels(), |series| {
+                if let Series::Gauge(v) = series {
+                    *v += value;
+                }
+            });
+    }
+
+    fn decrement(&self, value: f64) {
+        metrics::GaugeFn::increment(self, -value);
+    }
+
+    fn set(&self, value: f64) {
+        self.registry.set_gauge(&self.name, &self.labels(), value);
+    }
+}
+
+impl metrics::HistogramFn for RegistryHandle {
+    fn record(&self, value: f64) {
+        self.registry
+            .observe_histogram(&self.name, &self.labels(), value);
+    }
+}
+
+/// Name and sorted labels of a facade key
+pub(crate) fn key_parts(key: &metrics::Key) -> (String, Labels) {
+    let mut labels: Labels = key
+        .labels()
+        .map(|label| (label.key().to_string(), label.value().to_string()))
+        .collect();
+    labels.sort();
+    (key.name().to_string(), labels)
+}
+
+impl metrics::Recorder for Registry {
+    fn describe_counter(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_gauge(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_histogram(
+        &self,
+        _: metrics::KeyName,
+        _: Option<metrics::Unit>,
+        _: metrics::SharedString,
+    ) {
+    }
+
+    fn register_counter(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Counter {
+        metrics::Counter::from_arc(Arc::new(self.handle(key)))
+    }
+
+    fn register_gauge(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+        metrics::Gauge::from_arc(Arc::new(self.handle(key)))
+    }
+
+    fn register_histogram(
+        &self,
+        key: &metrics::Key,
+        _: &metrics::Metadata<'_>,
+    ) -> metrics::Histogram {
+        metrics::Histogram::from_arc(Arc::new(self.handle(key)))
+    }
+}
+
+impl Registry {
+    fn handle(&self, key: &metrics::Key) -> RegistryHandle {
+        let (name, labels) = key_parts(key);
+        RegistryHandle {
+            registry: self.clone(),
+            name,
+            labels,
+        }
+    }
+}
+
+/// Set once a global recorder is in place
+static INSTALLED: once_cell::sync::OnceCell<()> = once_cell::sync::OnceCell::new();
+
+/// Install the exporter chosen by `config` as the global recorder; call it before anything
+/// records. Push exporters start their background task, so this needs a Tokio runtime.
+pub fn install(config: &MetricsConfig) -> Result<(), ExporterError> {
+    let mut result = Err(ExporterError::AlreadyInstalled);
+    INSTALLED.get_or_init(|| {
+        result = install_exporter(config);
+    });
+    result
+}
+
+fn install_exporter(config: &MetricsConfig) -> Result<(), ExporterError> {
+    let installed = match &config.exporter {
+        MetricsExporter::Prometheus => metrics::set_global_recorder(METRICS.clone()).is_ok(),
+        MetricsExporter::Statsd { addr, prefix } => {
+            let recorder = crate::statsd::StatsdRecorder::new(prefix.clone());
+            recorder.spawn_flusher(crate::statsd::connect(addr)?, config.push_interval);
+            metrics::set_global_recorder(recorder).is_ok()
+        }
+        #[cfg(feature = "otlp")]
+        MetricsExporter::Otlp { endpoint } => metrics::set_global_recorder(
+            crate::otlp::OtlpRecorder::connect(endpoint, config.push_interval)?,
+        )
+        .is_ok(),
+        #[cfg(not(feature = "otlp"))]
+        MetricsExporter::Otlp { .. } => {
+            return Err(ExporterError::Otlp(
+                "built without the otlp feature".to_string(),
+            ));
+        }
+    };
+    if installed {
+        Ok(())
+    } else {
+        Err(ExporterError::AlreadyInstalled)
+    }
+}
+
+/// Fall back to the Prometheus registry when nothing was installed, as in tests
+fn ensure_recorder() {
+    INSTALLED.get_or_init(|| {
+        let _ = metrics::set_global_recorder(METRICS.clone());
+    });
+}
+
+fn facade_labels(labels: &[(&str, &str)]) -> Vec<metrics::Label> {
+    labels
+        .iter()
+        .map(|(k, v)| metrics::Label::new(k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Increase a counter through the installed exporter
+pub fn increment_counter(name: &str, labels: &[(&str, &str)], value: u64) {
+    ensure_recorder();
+    metrics::counter!(name.to_string(), facade_labels(labels)).increment(value);
+}
+
+/// Set a gauge through the installed exporter
+pub fn set_gauge(name: &str, labels: &[(&str, &str)], value: f64) {
+    ensure_recorder();
+    metrics::gauge!(name.to_string(), facade_labels(labels)).set(value);
+}
+
+/// Record a histogram observation through the installed exporter
+pub fn observe_histogram(name: &str, labels: &[(&str, &str)], value: f64) {
+    ensure_recorder();
+    metrics::histogram!(name.to_string(), facade_labels(labels)).record(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_render() {
+        let registry = Registry::default();
+        registry.increment_counter("fc_test_total", &[], 2);
+        registry.increment_counter("fc_test_total", &[], 3);
+        registry.increment_counter("fc_labeled_total", &[("reason", "a\"b")], 1);
+
+        assert_eq!(registry.counter_value("fc_test_total", &[]), Some(5));
+        let text = registry.render();
+        assert!(text.contains("# TYPE fc_test_total counter"));
+        assert!(text.contains("fc_test_total 5"));
+        assert!(text.contains("fc_labeled_total{reason=\"a\\\"b\"} 1"));
+    }
+
+    #[test]
+    fn test_gauge_and_histogram_render() {
+        let registry = Registry::default();
+        registry.set_gauge("fc_pool_size", &[], 3.0);
+        registry.observe_histogram("fc_latency_ms", &[("phase", "boot")], 42.0);
+        registry.observe_histogram("fc_latency_ms", &[("phase", "boot")], 20000.0);
+
+        let text = registry.render();
+        assert!(text.contains("fc_pool_size 3"));
+        assert!(text.contains("fc_latency_ms_bucket{phase=\"boot\",le=\"25\"} 0"));
+        assert!(text.contains("fc_latency_ms_bucket{phase=\"boot\",le=\"50\"} 1"));
+        assert!(text.contains("fc_latency_ms_bucket{phase=\"boot\",le=\"+Inf\"} 2"));
+        assert!(text.contains("fc_latency_ms_count{phase=\"boot\"} 2"));
+    }
+
+    #[test]
+    fn test_helpers_record_through_the_facade() {
+        let registry = Registry::default();
+        metrics::with_local_recorder(&registry, || {
+            increment_counter("fc_facade_total", &[("reason", "x")], 2);
+            increment_counter("fc_facade_total", &[("reason", "x")], 1);
+            set_gauge("fc_facade_gauge", &[], 4.0);
+            observe_histogram("fc_facade_ms", &[], 30.0);
+        });
+        assert_eq!(
+            registry.counter_value("fc_facade_total", &[("reason", "x")]),
+            Some(3)
+        );
+        assert_eq!(registry.gauge_value("fc_facade_gauge", &[]), Some(4.0));
+        assert!(registry.render().contains("fc_facade_ms_count 1"));
+    }
+}
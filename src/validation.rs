@@ -0,0 +1,274 @@
+use crate::config::Config;
+use crate::guest_network;
+use crate::inputs::InputError;
+use crate::port_forward;
+use crate::program::{self, Program, ProgramError};
+use crate::{ErrorResponse, ExecuteRequest};
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use thiserror::Error;
+
+/// Server limits an execute request is checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest source accepted, in characters; files count in total
+    pub max_code_length: usize,
+    /// Whether requirements may be installed, which needs network access
+    pub allow_network: bool,
+    /// Accept requirements that aren't plain PyPI package specifiers
+    pub allow_unsafe_requirements: bool,
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_code_length: config.max_code_length,
+            allow_network: config.allow_network,
+            allow_unsafe_requirements: config.allow_unsafe_requirements,
+        }
+    }
+}
+
+/// Why an execute request was refused before anything ran
+#[derive(Debug, Error, PartialEq)]
+pub enum ValidationError {
+    #[error("Empty code provided")]
+    EmptyCode,
+    #[error("Code exceeds maximum length of {max} characters ({got} given)")]
+    CodeTooLong { max: usize, got: usize },
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+    #[error("fake_time requires deterministic mode")]
+    FakeTimeWithoutDeterministic,
+    #[error("dns requires network access, which is disabled")]
+    DnsWithoutNetwork,
+    #[error("inputs require network access, which is disabled")]
+    InputsWithoutNetwork,
+    #[error(transparent)]
+    Input(#[from] InputError),
+    #[error("{0}")]
+    InvalidDns(String),
+    #[error("{0}")]
+    InvalidTrafficShape(String),
+    #[error("{0}")]
+    InvalidExposePort(String),
+    #[error("{0}")]
+    InvalidKeepAlive(String),
+    #[error("{0} can't be set for a run in a sandbox")]
+    NotForSandbox(&'static str),
+    #[error("This API key may not choose a traffic_shape")]
+    TrafficShapeNotAllowed,
+    #[error("capture_console is disabled on this server")]
+    ConsoleCaptureDisabled,
+    #[error("This API key may not request debug events")]
+    DebugNotAllowed,
+    #[error("Unknown deps profile '{0}'")]
+    UnknownDepsProfile(String),
+    #[error("Unknown image '{0}'")]
+    UnknownImage(String),
+}
+
+impl ValidationError {
+    /// Stable machine-readable name of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::EmptyCode => "empty_code",
+            ValidationError::CodeTooLong { .. } => "code_too_long",
+            ValidationError::Program(_) => "invalid_program",
+            ValidationError::FakeTimeWithoutDeterministic
+            | ValidationError::DnsWithoutNetwork
+            | ValidationError::InputsWithoutNetwork
+            | ValidationError::InvalidDns(_)
+            | ValidationError::InvalidTrafficShape(_)
+            | ValidationError::InvalidExposePort(_)
+            | ValidationError::InvalidKeepAlive(_)
+            | ValidationError::NotForSandbox(_) => "invalid_options",
+            ValidationError::TrafficShapeNotAllowed => "traffic_shape_not_allowed",
+            ValidationError::ConsoleCaptureDisabled => "console_capture_disabled",
+            ValidationError::DebugNotAllowed => "debug_not_allowed",
+            ValidationError::Input(InputError::Invalid(_)) => "invalid_inputs",
+            ValidationError::Input(InputError::Forbidden(_)) => "input_host_forbidden",
+            ValidationError::UnknownDepsProfile(_) => "unknown_deps_profile",
+            ValidationError::UnknownImage(_) => "unknown_image",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ValidationError::EmptyCode => StatusCode::UNPROCESSABLE_ENTITY,
+            ValidationError::CodeTooLong { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ValidationError::TrafficShapeNotAllowed
+            | ValidationError::DebugNotAllowed
+            | ValidationError::Input(InputError::Forbidden(_)) => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        (
+            self.status(),
+            Json(ErrorResponse::new(self.code(), self.to_string())),
+        )
+            .into_response()
+    }
+}
+
+/// Check `request` against `limits` without the server state; deps profiles and images are
+/// looked up separately since they can change while the server runs
+pub fn validate_request(request: &ExecuteRequest, limits: &Limits) -> Result<(), ValidationError> {
+    let program = Program::from_request(request)?;
+    if !request.requirements.is_empty() {
+        if !limits.allow_network {
+            return Err(ProgramError::NetworkDisabled.into());
+        }
+        program::validate_requirements(&request.requirements, limits.allow_unsafe_requirements)?;
+    }
+    if let Program::Code(code) | Program::Eval(code) = &program
+        && code.trim().is_empty()
+    {
+        return Err(ValidationError::EmptyCode);
+    }
+    // Prevent extremely large payloads
+    let length = program.source_len();
+    if length > limits.max_code_length {
+        return Err(ValidationError::CodeTooLong {
+            max: limits.max_code_length,
+            got: length,
+        });
+    }
+    if request.fake_time.is_some() && !request.deterministic {
+        return Err(ValidationError::FakeTimeWithoutDeterministic);
+    }
+    if let Some(dns) = &request.dns {
+        if !limits.allow_network {
+            return Err(ValidationError::DnsWithoutNetwork);
+        }
+        guest_network::validate_dns(dns).map_err(ValidationError::InvalidDns)?;
+    }
+    if request
+        .inputs
+        .as_ref()
+        .is_some_and(|inputs| !inputs.is_empty())
+        && !limits.allow_network
+    {
+        return Err(ValidationError::InputsWithoutNetwork);
+    }
+    if let Some(shape) = &request.traffic_shape {
+        shape
+            .validate()
+            .map_err(ValidationError::InvalidTrafficShape)?;
+    }
+    if let Some(port) = request.expose_port {
+        port_forward::validate_port(port).map_err(ValidationError::InvalidExposePort)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        max_code_length: 10,
+        allow_network: false,
+        allow_unsafe_requirements: false,
+    };
+
+    fn code(code: &str) -> ExecuteRequest {
+        ExecuteRequest {
+            code: Some(code.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_request() {
+        assert_eq!(validate_request(&code("print(1)"), &LIMITS), Ok(()));
+        assert_eq!(
+            validate_request(&code(" \n"), &LIMITS),
+            Err(ValidationError::EmptyCode)
+        );
+        assert_eq!(
+            validate_request(&code("print(12345)"), &LIMITS),
+            Err(ValidationError::CodeTooLong { max: 10, got: 12 })
+        );
+        assert_eq!(
+            validate_request(&ExecuteRequest::default(), &LIMITS),
+            Err(ValidationError::Program(ProgramError::Missing))
+        );
+        let needs_network = ExecuteRequest {
+            requirements: vec!["requests".to_string()],
+            ..code("1")
+        };
+        assert_eq!(
+            validate_request(&needs_network, &LIMITS),
+            Err(ValidationError::Program(ProgramError::NetworkDisabled))
+        );
+        let fake_time = ExecuteRequest {
+            fake_time: Some(0),
+            ..code("1")
+        };
+        assert_eq!(
+            validate_request(&fake_time, &LIMITS),
+            Err(ValidationError::FakeTimeWithoutDeterministic)
+        );
+        let dns = |servers: &[&str]| ExecuteRequest {
+            dns: Some(servers.iter().map(|s| s.to_string()).collect()),
+            ..code("1")
+        };
+        assert_eq!(
+            validate_request(&dns(&["10.0.0.2"]), &LIMITS),
+            Err(ValidationError::DnsWithoutNetwork)
+        );
+        let networked = Limits {
+            allow_network: true,
+            ..LIMITS
+        };
+        assert_eq!(validate_request(&dns(&["10.0.0.2"]), &networked), Ok(()));
+        let inputs = ExecuteRequest {
+            inputs: Some(vec![crate::inputs::RemoteInput {
+                url: "https://store.example/data.csv".to_string(),
+                path: "data.csv".to_string(),
+                sha256: None,
+            }]),
+            ..code("1")
+        };
+        assert_eq!(
+            validate_request(&inputs, &LIMITS),
+            Err(ValidationError::InputsWithoutNetwork)
+        );
+        assert_eq!(validate_request(&inputs, &networked), Ok(()));
+        assert_eq!(
+            validate_request(&dns(&["resolver.lan"]), &networked).map_err(|e| e.code()),
+            Err("invalid_options")
+        );
+        let expose = |port| ExecuteRequest {
+            expose_port: Some(port),
+            ..code("1")
+        };
+        assert_eq!(validate_request(&expose(5000), &LIMITS), Ok(()));
+        assert_eq!(
+            validate_request(&expose(80), &LIMITS).map_err(|e| e.code()),
+            Err("invalid_options")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors_share_the_error_envelope() {
+        let response = ValidationError::CodeTooLong { max: 10, got: 12 }.into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.code, "code_too_long");
+        assert!(body.error.contains("10 characters"), "{}", body.error);
+        assert_eq!(
+            ValidationError::EmptyCode.status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+}
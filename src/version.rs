@@ -0,0 +1,189 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Version of this service
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit this service was built from, or "unknown" outside a checkout
+pub const GIT_SHA: &str = env!("FC_GIT_SHA");
+
+/// A Firecracker release, e.g. `1.7.0` or `1.10.0-dev`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirecrackerVersion {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// Pre-release or build suffix after `-`, if any
+    pub pre: Option<String>,
+}
+
+impl FirecrackerVersion {
+    /// Parse `1.7.0`, `v1.7.0` or `1.10.0-dev`; a missing patch number counts as 0
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches('v');
+        let (numbers, pre) = match version.split_once('-') {
+            Some((numbers, pre)) => (numbers, Some(pre.to_string())),
+            None => (version, None),
+        };
+        let mut parts = numbers.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = match parts.next() {
+            Some(patch) => patch.parse().ok()?,
+            None => 0,
+        };
+        Some(Self {
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+
+    /// Parse the body of `GET /version`, e.g. `{"firecracker_version":"1.7.0"}`
+    pub fn from_api_body(body: &str) -> Option<Self> {
+        let value: serde_json::Value = serde_json::from_str(body).ok()?;
+        Self::parse(value["firecracker_version"].as_str()?)
+    }
+
+    /// Parse `firecracker --version` output, whose first line is e.g. `Firecracker v1.7.0`
+    pub fn from_cli_output(output: &str) -> Option<Self> {
+        let line = output.lines().next()?.trim();
+        Self::parse(line.strip_prefix("Firecracker")?)
+    }
+
+    fn at_least(&self, major: u64, minor: u64) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+
+    /// virtio-rng entropy device (`PUT /entropy`)
+    pub fn supports_entropy(&self) -> bool {
+        self.at_least(1, 4)
+    }
+
+    /// Balloon device with statistics (`PUT /balloon`, `GET /balloon/statistics`)
+    pub fn supports_balloon(&self) -> bool {
+        self.at_least(1, 0)
+    }
+
+    /// Stable snapshot create/load API
+    pub fn supports_snapshots(&self) -> bool {
+        self.at_least(1, 0)
+    }
+}
+
+impl fmt::Display for FirecrackerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(pre) = &self.pre {
+            write!(f, "-{pre}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for FirecrackerVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FirecrackerVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        Self::parse(&version)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid version {version:?}")))
+    }
+}
+
+/// Whether a configured feature can be used with `version`; unknown or older versions get a
+/// warning and the feature is left out rather than sending a request Firecracker would reject
+pub fn feature_available(
+    feature: &str,
+    version: Option<&FirecrackerVersion>,
+    supported: fn(&FirecrackerVersion) -> bool,
+) -> bool {
+    match version {
+        Some(version) if supported(version) => true,
+        Some(version) => {
+            tracing::warn!(
+                "Firecracker {} does not support {}, skipping",
+                version,
+                feature
+            );
+            false
+        }
+        None => {
+            tracing::warn!("Firecracker version unknown, skipping {}", feature);
+            false
+        }
+    }
+}
+
+/// Version of the Firecracker binary the runner launches, detected once via `--version`
+pub fn host_version() -> Option<&'static FirecrackerVersion> {
+    static HOST_VERSION: once_cell::sync::OnceCell<Option<FirecrackerVersion>> =
+        once_cell::sync::OnceCell::new();
+    HOST_VERSION
+        .get_or_init(|| {
+            let binary = match &crate::config::runner_config().jailer {
+                Some(jailer) => jailer.exec_file.clone(),
+                None => std::path::PathBuf::from("firecracker"),
+            };
+            let output = std::process::Command::new(binary)
+                .arg("--version")
+                .output()
+                .ok()?;
+            FirecrackerVersion::from_cli_output(&String::from_utf8_lossy(&output.stdout))
+        })
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_real_version_strings() {
+        let cli = "Firecracker v1.7.0\n\nSupported snapshot data format versions: v1.0.0, v2.0.0\n";
+        let version = FirecrackerVersion::from_cli_output(cli).unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 7, 0));
+        assert_eq!(version.to_string(), "1.7.0");
+
+        let api = r#"{"firecracker_version":"1.10.1"}"#;
+        let version = FirecrackerVersion::from_api_body(api).unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 10, 1));
+
+        let dev = FirecrackerVersion::parse("v1.11.0-dev").unwrap();
+        assert_eq!(dev.pre.as_deref(), Some("dev"));
+        assert_eq!(
+            serde_json::to_value(&dev).unwrap(),
+            serde_json::json!("1.11.0-dev")
+        );
+        let roundtrip: FirecrackerVersion =
+            serde_json::from_value(serde_json::json!("1.11.0-dev")).unwrap();
+        assert_eq!(roundtrip, dev);
+
+        assert_eq!(FirecrackerVersion::parse("0.25").unwrap().patch, 0);
+        assert!(FirecrackerVersion::parse("garbage").is_none());
+        assert!(FirecrackerVersion::from_api_body("not json").is_none());
+        assert!(FirecrackerVersion::from_cli_output("command not found").is_none());
+    }
+
+    #[test]
+    fn test_feature_gates() {
+        let old = FirecrackerVersion::parse("1.3.3").unwrap();
+        let new = FirecrackerVersion::parse("1.4.0").unwrap();
+        let ancient = FirecrackerVersion::parse("0.25.2").unwrap();
+        assert!(!old.supports_entropy());
+        assert!(new.supports_entropy());
+        assert!(old.supports_balloon());
+        assert!(!ancient.supports_balloon());
+        assert!(!ancient.supports_snapshots());
+
+        let entropy = FirecrackerVersion::supports_entropy;
+        assert!(feature_available("entropy", Some(&new), entropy));
+        assert!(!feature_available("entropy", Some(&old), entropy));
+        assert!(!feature_available("entropy", None, entropy));
+    }
+}
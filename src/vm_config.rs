@@ -0,0 +1,258 @@
+use crate::balloon::BalloonDevice;
+use crate::entropy::EntropyDevice;
+use crate::machine::MachineConfig;
+use serde::Serialize;
+
+/// Body of `PUT /boot-source`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BootSource {
+    pub kernel_image_path: String,
+    pub boot_args: String,
+}
+
+/// Body of `PUT /drives/{drive_id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Drive {
+    pub drive_id: String,
+    pub path_on_host: String,
+    pub is_root_device: bool,
+    pub is_read_only: bool,
+}
+
+/// Body of `PUT /network-interfaces/{iface_id}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetworkInterface {
+    pub iface_id: String,
+    pub guest_mac: String,
+    pub host_dev_name: String,
+}
+
+/// Body of `PUT /vsock`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VsockDevice {
+    pub guest_cid: u32,
+    /// Unix socket Firecracker serves the device on, for the host to connect to
+    pub uds_path: String,
+}
+
+/// Body of `PUT /logger`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Logger {
+    pub log_path: String,
+    pub level: String,
+    pub show_level: bool,
+    pub show_log_origin: bool,
+}
+
+/// Body of `PUT /metrics`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Metrics {
+    pub metrics_path: String,
+}
+
+/// Everything Firecracker is told before the instance starts. Both boot modes render it:
+/// as a sequence of API calls, or as the file passed to `--config-file`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct VmConfig {
+    pub machine_config: MachineConfig,
+    pub boot_source: BootSource,
+    pub drives: Vec<Drive>,
+    pub network_interfaces: Vec<NetworkInterface>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balloon: Option<BalloonDevice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<EntropyDevice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vsock: Option<VsockDevice>,
+    pub logger: Logger,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<Metrics>,
+}
+
+/// One configuration request of the API boot mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiCall {
+    /// Resource path, e.g. `/drives/rootfs`
+    pub path: String,
+    /// What is being configured, for error messages
+    pub what: &'static str,
+    pub body: String,
+}
+
+impl VmConfig {
+    /// The `PUT` requests that configure the VM over the API socket, in order, without the
+    /// final `InstanceStart`
+    pub fn api_calls(&self) -> Vec<ApiCall> {
+        fn call(path: String, what: &'static str, body: &impl Serialize) -> ApiCall {
+            ApiCall {
+                path,
+                what,
+                body: serde_json::to_string(body).unwrap_or_default(),
+            }
+        }
+        let mut calls = vec![
+            call(
+                "/machine-config".to_string(),
+                "Machine config",
+                &self.machine_config,
+            ),
+            call(
+                "/boot-source".to_string(),
+                "Boot source config",
+                &self.boot_source,
+            ),
+        ];
+        calls.extend(
+            self.drives
+                .iter()
+                .map(|drive| call(format!("/drives/{}", drive.drive_id), "Drive config", drive)),
+        );
+        calls.extend(self.network_interfaces.iter().map(|iface| {
+            call(
+                format!("/network-interfaces/{}", iface.iface_id),
+                "Network config",
+                iface,
+            )
+        }));
+        if let Some(balloon) = &self.balloon {
+            calls.push(call("/balloon".to_string(), "Balloon config", balloon));
+        }
+        if let Some(entropy) = &self.entropy {
+            calls.push(call(
+                "/entropy".to_string(),
+                "Entropy device config",
+                entropy,
+            ));
+        }
+        if let Some(vsock) = &self.vsock {
+            calls.push(call("/vsock".to_string(), "Vsock config", vsock));
+        }
+        calls.push(call("/logger".to_string(), "Logger config", &self.logger));
+        if let Some(metrics) = &self.metrics {
+            calls.push(call("/metrics".to_string(), "Metrics config", metrics));
+        }
+        calls
+    }
+
+    /// Contents of the file passed to `firecracker --config-file`, which boots the VM directly
+    pub fn config_file(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> VmConfig {
+        VmConfig {
+            machine_config: MachineConfig {
+                vcpu_count: 2,
+                mem_size_mib: 128,
+                cpu_template: None,
+                smt: Some(false),
+                track_dirty_pages: None,
+            },
+            boot_source: BootSource {
+                kernel_image_path: "vmlinux.bin".to_string(),
+                boot_args: "console=ttyS0".to_string(),
+            },
+            drives: vec![
+                Drive {
+                    drive_id: "rootfs".to_string(),
+                    path_on_host: "rootfs.ext4".to_string(),
+                    is_root_device: true,
+                    is_read_only: false,
+                },
+                Drive {
+                    drive_id: "deps".to_string(),
+                    path_on_host: "deps.ext4".to_string(),
+                    is_root_device: false,
+                    is_read_only: true,
+                },
+            ],
+            network_interfaces: vec![NetworkInterface {
+                iface_id: "eth0".to_string(),
+                guest_mac: "AA:FC:00:00:00:01".to_string(),
+                host_dev_name: "tap-0".to_string(),
+            }],
+            balloon: None,
+            entropy: Some(EntropyDevice::default()),
+            vsock: None,
+            logger: Logger {
+                log_path: "fc.log".to_string(),
+                level: "Warning".to_string(),
+                show_level: true,
+                show_log_origin: false,
+            },
+            metrics: None,
+        }
+    }
+
+    #[test]
+    fn test_api_calls_order() {
+        let paths: Vec<_> = config()
+            .api_calls()
+            .into_iter()
+            .map(|call| call.path)
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/machine-config",
+                "/boot-source",
+                "/drives/rootfs",
+                "/drives/deps",
+                "/network-interfaces/eth0",
+                "/entropy",
+                "/logger",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_file_matches_api_bodies() {
+        let config = config();
+        let file: serde_json::Value = serde_json::from_str(&config.config_file()).unwrap();
+        let body = |path: &str| -> serde_json::Value {
+            let call = config.api_calls().into_iter().find(|c| c.path == path);
+            serde_json::from_str(&call.unwrap().body).unwrap()
+        };
+        assert_eq!(file["machine-config"], body("/machine-config"));
+        assert_eq!(file["boot-source"], body("/boot-source"));
+        assert_eq!(file["drives"][1], body("/drives/deps"));
+        assert_eq!(
+            file["network-interfaces"][0],
+            body("/network-interfaces/eth0")
+        );
+        assert_eq!(file["entropy"], body("/entropy"));
+        assert_eq!(file["logger"], body("/logger"));
+        assert!(file.get("balloon").is_none());
+        assert!(file.get("metrics").is_none());
+        assert!(file.get("vsock").is_none());
+    }
+
+    #[test]
+    fn test_vsock_replaces_the_network_interface() {
+        let config = VmConfig {
+            network_interfaces: Vec::new(),
+            vsock: Some(VsockDevice {
+                guest_cid: 3,
+                uds_path: "fc-vsock.sock".to_string(),
+            }),
+            ..config()
+        };
+        let calls = config.api_calls();
+        assert!(
+            calls
+                .iter()
+                .all(|c| !c.path.starts_with("/network-interfaces"))
+        );
+        let vsock = calls.iter().find(|c| c.path == "/vsock").unwrap();
+        assert_eq!(vsock.body, r#"{"guest_cid":3,"uds_path":"fc-vsock.sock"}"#);
+        let file: serde_json::Value = serde_json::from_str(&config.config_file()).unwrap();
+        assert_eq!(file["vsock"]["uds_path"], "fc-vsock.sock");
+        assert_eq!(file["network-interfaces"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::config::ConfigError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// Code run once on every freshly created VM before it serves anything, so slow first
+/// imports are paid for during prewarm instead of by a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WarmupConfig {
+    /// Snippet for VMs whose deps profile and image have none of their own
+    pub code: Option<String>,
+    /// Snippets keyed by deps profile or image name, used instead of `code`
+    pub profiles: BTreeMap<String, String>,
+    /// Time the snippet may run before the warm-up counts as failed
+    pub timeout: Duration,
+    /// Give up on a VM whose warm-up fails instead of pooling it anyway
+    pub fatal: bool,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            code: None,
+            profiles: BTreeMap::new(),
+            timeout: Duration::from_secs(10),
+            fatal: false,
+        }
+    }
+}
+
+impl WarmupConfig {
+    /// Snippet for a VM with `deps_profile` and `image`; the deps profile's wins over the
+    /// image's, and either over the default
+    pub fn code_for(&self, deps_profile: Option<&str>, image: Option<&str>) -> Option<&str> {
+        [deps_profile, image]
+            .into_iter()
+            .flatten()
+            .find_map(|name| self.profiles.get(name))
+            .or(self.code.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// How a VM's warm-up went, as shown in `/pool`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct WarmupReport {
+    pub duration_ms: u64,
+    pub success: bool,
+}
+
+/// Read `NAME=PATH,...` into warm-up snippets, one Python file per profile
+pub fn load_profiles(raw: &str) -> Result<BTreeMap<String, String>, ConfigError> {
+    crate::deps::parse_profiles(raw)
+        .into_iter()
+        .map(|(name, path)| {
+            std::fs::read_to_string(&path)
+                .map(|code| (name.clone(), code))
+                .map_err(|e| {
+                    ConfigError::Invalid(format!(
+                        "warm-up code of '{name}': cannot read {}: {e}",
+                        path.display()
+                    ))
+                })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_snippets_replace_the_default() {
+        let config = WarmupConfig {
+            code: Some("import json".to_string()),
+            profiles: BTreeMap::from([
+                ("numpy".to_string(), "import numpy".to_string()),
+                ("ml".to_string(), "import torch".to_string()),
+            ]),
+            ..WarmupConfig::default()
+        };
+        assert_eq!(config.code_for(None, None), Some("import json"));
+        assert_eq!(config.code_for(Some("numpy"), None), Some("import numpy"));
+        assert_eq!(config.code_for(None, Some("ml")), Some("import torch"));
+        assert_eq!(
+            config.code_for(Some("numpy"), Some("ml")),
+            Some("import numpy")
+        );
+        assert_eq!(config.code_for(Some("other"), None), Some("import json"));
+        assert_eq!(WarmupConfig::default().code_for(Some("numpy"), None), None);
+    }
+
+    #[test]
+    fn test_load_profiles_reads_each_file() {
+        let dir = std::env::temp_dir().join(format!("fc-warmup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("numpy.py");
+        std::fs::write(&path, "import numpy\n").unwrap();
+        let profiles = load_profiles(&format!("numpy={}", path.display())).unwrap();
+        assert_eq!(profiles["numpy"], "import numpy\n");
+
+        let err = load_profiles(&format!("ml={}", dir.join("missing.py").display())).unwrap_err();
+        assert!(err.to_string().contains("'ml'"), "{err}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Soft condition of an execution that succeeded anyway. Codes are stable: clients may match
+/// on them, so one is never renamed or reused for another condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    /// stdout or stderr was cut at the output limit
+    OutputTruncated,
+    /// No warm VM matched the request, so one was booted for it
+    ColdStart,
+    /// The VM taken for the request had exited before the code was sent, so it ran on another
+    RetriedOnFreshVm,
+}
+
+impl WarningCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WarningCode::OutputTruncated => "output_truncated",
+            WarningCode::ColdStart => "cold_start",
+            WarningCode::RetriedOnFreshVm => "retried_on_fresh_vm",
+        }
+    }
+}
+
+/// One entry of a response's `warnings`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Warning {
+    pub code: WarningCode,
+    /// Human-readable details; not meant to be matched on
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(code: WarningCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The streams of `truncated`, `(name, truncated)` pairs, that were cut at `limit` bytes
+    pub fn output_truncated(truncated: &[(&str, bool)], limit: usize) -> Option<Self> {
+        let streams: Vec<_> = truncated
+            .iter()
+            .filter(|(_, truncated)| *truncated)
+            .map(|(stream, _)| *stream)
+            .collect();
+        (!streams.is_empty()).then(|| {
+            Self::new(
+                WarningCode::OutputTruncated,
+                format!("{} cut at {limit} bytes", streams.join(" and ")),
+            )
+        })
+    }
+
+    /// Whether the warning still holds for a result served again from the cache, where no VM
+    /// was acquired
+    pub fn describes_output(&self) -> bool {
+        self.code == WarningCode::OutputTruncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_serialize_as_their_stable_names() {
+        for code in [
+            WarningCode::OutputTruncated,
+            WarningCode::ColdStart,
+            WarningCode::RetriedOnFreshVm,
+        ] {
+            let json = serde_json::to_value(code).unwrap();
+            assert_eq!(json, code.as_str());
+            assert_eq!(serde_json::from_value::<WarningCode>(json).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn test_output_truncated_names_the_streams() {
+        assert_eq!(
+            Warning::output_truncated(&[("stdout", false), ("stderr", false)], 10),
+            None
+        );
+        let warning = Warning::output_truncated(&[("stdout", true), ("stderr", true)], 10).unwrap();
+        assert_eq!(warning.message, "stdout and stderr cut at 10 bytes");
+        assert!(warning.describes_output());
+    }
+}
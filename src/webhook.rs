@@ -0,0 +1,309 @@
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Deliveries attempted before a callback is marked failed
+pub const CALLBACK_ATTEMPTS: u32 = 3;
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of the body>` when a secret is configured
+pub const SIGNATURE_HEADER: &str = "x-fc-signature-256";
+
+/// Time allowed for each delivery attempt
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Job completion callback settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookConfig {
+    /// Hosts callbacks may target; empty allows any host outside loopback and link-local ranges
+    pub allowed_hosts: Vec<String>,
+    /// Key of the HMAC signature header; no signature is sent without one
+    pub secret: Option<String>,
+    /// Delay before the first retry, doubled for each further one
+    pub retry_backoff: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            secret: None,
+            retry_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Why a callback URL was refused
+#[derive(Debug, Error, PartialEq)]
+pub enum CallbackError {
+    #[error("Invalid callback_url: {0}")]
+    InvalidUrl(String),
+    #[error("callback_url host {0} is not allowed")]
+    Forbidden(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallbackStatus {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+/// Delivery state of a job's callback, reported on the job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct CallbackDelivery {
+    pub url: String,
+    pub status: CallbackStatus,
+    pub attempts: u32,
+    /// Error of the last failed attempt
+    pub last_error: Option<String>,
+}
+
+impl CallbackDelivery {
+    pub fn pending(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            status: CallbackStatus::Pending,
+            attempts: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Check a submitted callback URL: http(s), and a host that is allowlisted or, without an
+/// allowlist, not loopback or link-local. Names are resolved and checked again on delivery.
+pub fn validate_callback_url(raw: &str, config: &WebhookConfig) -> Result<Url, CallbackError> {
+    let url = Url::parse(raw).map_err(|e| CallbackError::InvalidUrl(e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(CallbackError::InvalidUrl(format!(
+            "unsupported scheme {}",
+            url.scheme()
+        )));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| CallbackError::InvalidUrl("missing host".to_string()))?;
+    if is_allowlisted(host, config) {
+        return Ok(url);
+    }
+    if !config.allowed_hosts.is_empty() || is_local_name(host) {
+        return Err(CallbackError::Forbidden(host.to_string()));
+    }
+    if let Some(ip) = host_ip(host)
+        && is_forbidden(ip)
+    {
+        return Err(CallbackError::Forbidden(host.to_string()));
+    }
+    Ok(url)
+}
+
+fn is_allowlisted(host: &str, config: &WebhookConfig) -> bool {
+    config.allowed_hosts.iter().any(|allowed| {
+        allowed
+            .trim_matches(['[', ']'])
+            .eq_ignore_ascii_case(host_bare(host))
+    })
+}
+
+/// `host` without the brackets of an IPv6 literal
+fn host_bare(host: &str) -> &str {
+    host.trim_matches(['[', ']'])
+}
+
+fn host_ip(host: &str) -> Option<IpAddr> {
+    host_bare(host).parse().ok()
+}
+
+pub(crate) fn is_local_name(host: &str) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    host == "localhost" || host.ends_with(".localhost")
+}
+
+/// Addresses a callback must never reach: the host itself and link-local services such as
+/// cloud metadata endpoints
+pub(crate) fn is_forbidden(ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    };
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_link_local() || v4.is_unspecified(),
+        IpAddr::V6(v6) => {
+            v6.is_loopback() || v6.is_unspecified() || (v6.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC of `body` under `secret`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POST `body` to `delivery.url`, retrying with exponential backoff until a 2xx answer or
+/// `CALLBACK_ATTEMPTS` attempts. `on_attempt` sees the delivery state after every attempt.
+pub async fn deliver(
+    config: &WebhookConfig,
+    mut delivery: CallbackDelivery,
+    body: Vec<u8>,
+    mut on_attempt: impl FnMut(&CallbackDelivery),
+) -> CallbackDelivery {
+    let signature = config.secret.as_deref().map(|secret| sign(secret, &body));
+    let mut backoff = config.retry_backoff;
+    while delivery.attempts < CALLBACK_ATTEMPTS {
+        if delivery.attempts > 0 {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        delivery.attempts += 1;
+        match post_once(config, &delivery.url, &body, signature.as_deref()).await {
+            Ok(()) => {
+                delivery.status = CallbackStatus::Delivered;
+                delivery.last_error = None;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Callback to {} failed (attempt {}): {}",
+                    delivery.url,
+                    delivery.attempts,
+                    e
+                );
+                delivery.last_error = Some(e);
+                if delivery.attempts == CALLBACK_ATTEMPTS {
+                    delivery.status = CallbackStatus::Failed;
+                }
+            }
+        }
+        on_attempt(&delivery);
+        if delivery.status == CallbackStatus::Delivered {
+            break;
+        }
+    }
+    crate::telemetry::increment_counter(
+        "fc_callbacks_total",
+        &[(
+            "status",
+            match delivery.status {
+                CallbackStatus::Delivered => "delivered",
+                _ => "failed",
+            },
+        )],
+        1,
+    );
+    delivery
+}
+
+async fn post_once(
+    config: &WebhookConfig,
+    url: &str,
+    body: &[u8],
+    signature: Option<&str>,
+) -> Result<(), String> {
+    let url = validate_callback_url(url, config).map_err(|e| e.to_string())?;
+    let client = pinned_client(&url, config).await?;
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_vec());
+    if let Some(signature) = signature {
+        request = request.header(SIGNATURE_HEADER, signature);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("receiver answered {}", response.status()))
+    }
+}
+
+/// Client connecting to an address of `url`'s host that passed the range check, so a DNS
+/// answer changing between the check and the request cannot redirect the callback
+async fn pinned_client(url: &Url, config: &WebhookConfig) -> Result<reqwest::Client, String> {
+    let builder = reqwest::Client::builder()
+        .timeout(CALLBACK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    let host = url.host_str().unwrap_or_default();
+    if host_ip(host).is_some() {
+        return builder.build().map_err(|e| e.to_string());
+    }
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut addrs = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Failed to resolve {host}: {e}"))?;
+    let allowlisted = is_allowlisted(host, config);
+    let addr: SocketAddr = addrs
+        .find(|addr| allowlisted || !is_forbidden(addr.ip()))
+        .ok_or_else(|| format!("{host} resolves only to forbidden addresses"))?;
+    builder
+        .resolve(host, addr)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_callback_url() {
+        let open = WebhookConfig::default();
+        assert!(validate_callback_url("https://hooks.example.com/done", &open).is_ok());
+        assert!(validate_callback_url("http://10.0.0.5:8080/done", &open).is_ok());
+        for forbidden in [
+            "http://localhost/done",
+            "http://api.localhost/done",
+            "http://127.0.0.1:3000/done",
+            "http://169.254.169.254/latest/meta-data",
+            "http://0.0.0.0/done",
+            "http://[::1]/done",
+            "http://[fe80::1]/done",
+            "http://[::ffff:127.0.0.1]/done",
+        ] {
+            assert!(
+                matches!(
+                    validate_callback_url(forbidden, &open),
+                    Err(CallbackError::Forbidden(_))
+                ),
+                "{forbidden} should be rejected"
+            );
+        }
+        assert!(matches!(
+            validate_callback_url("ftp://example.com/done", &open),
+            Err(CallbackError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            validate_callback_url("not a url", &open),
+            Err(CallbackError::InvalidUrl(_))
+        ));
+
+        // An allowlist admits only its hosts, which may then be local
+        let allowlist = WebhookConfig {
+            allowed_hosts: vec!["127.0.0.1".to_string(), "Hooks.Example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(validate_callback_url("http://127.0.0.1:9000/done", &allowlist).is_ok());
+        assert!(validate_callback_url("https://hooks.example.com/done", &allowlist).is_ok());
+        assert!(matches!(
+            validate_callback_url("https://other.example.com/done", &allowlist),
+            Err(CallbackError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_sign_is_verifiable() {
+        let body = br#"{"id":"job-1"}"#;
+        let signature = sign("secret", body);
+        let hex = signature.strip_prefix("sha256=").unwrap();
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(body);
+        assert!(mac.verify_slice(&hex::decode(hex).unwrap()).is_ok());
+        assert_ne!(signature, sign("other", body));
+    }
+}
@@ -0,0 +1,171 @@
+use crate::config::RunnerConfig;
+use crate::runner::ExecutionSpec;
+use crate::telemetry;
+use crate::{ExecuteResponse, ExecutionError};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Language executions are labeled with; the guest agent only runs Python
+pub const LANGUAGE: &str = "python";
+
+/// Label of executions on the default rootfs, or without a deps profile
+pub const DEFAULT_LABEL: &str = "default";
+
+/// Label of an image or deps profile the server doesn't have, which the execution failed on
+pub const OTHER_LABEL: &str = "other";
+
+/// What an execution is counted under. Only names the server is configured with become
+/// labels, never what a request sent, so the number of series stays bounded.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WorkloadLabels {
+    pub language: &'static str,
+    /// Rootfs image the execution asked for
+    pub image: String,
+    /// Deps profile the execution asked for
+    pub profile: String,
+}
+
+impl WorkloadLabels {
+    /// Labels of `spec` on an executor running with `config`
+    pub fn of(spec: &ExecutionSpec, config: &RunnerConfig) -> Self {
+        let image = match &spec.image {
+            None => DEFAULT_LABEL,
+            Some(name) if config.image_path(name).is_some() => name,
+            Some(_) => OTHER_LABEL,
+        };
+        let profile = match &spec.deps_profile {
+            None => DEFAULT_LABEL,
+            Some(name) if config.deps_profiles.contains_key(name) => name,
+            Some(_) => OTHER_LABEL,
+        };
+        Self {
+            language: LANGUAGE,
+            image: image.to_string(),
+            profile: profile.to_string(),
+        }
+    }
+
+    fn pairs(&self) -> [(&str, &str); 3] {
+        [
+            ("language", self.language),
+            ("image", &self.image),
+            ("profile", &self.profile),
+        ]
+    }
+}
+
+/// How an execution ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The code ran and exited cleanly
+    Success,
+    /// The code ran and failed
+    Failure,
+    /// The execution ran out of time
+    Timeout,
+    /// The host couldn't run it
+    Error,
+}
+
+impl Outcome {
+    pub fn of(result: &Result<ExecuteResponse, ExecutionError>) -> Self {
+        match result {
+            Ok(response) if response.success => Outcome::Success,
+            Ok(_) => Outcome::Failure,
+            Err(e) if e.code() == "timeout" => Outcome::Timeout,
+            Err(_) => Outcome::Error,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Timeout => "timeout",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// Executions of one language, image and deps profile, as served by `GET /admin/stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+pub struct WorkloadStats {
+    pub language: String,
+    pub image: String,
+    pub profile: String,
+    pub executions: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub timed_out: u64,
+    /// Executions the host couldn't run
+    pub errors: u64,
+    /// Source bytes submitted, summed over the executions
+    pub code_bytes_total: u64,
+    pub code_bytes_max: u64,
+    /// Time from taking a permit to finishing, summed over the executions
+    pub duration_ms_total: u64,
+    pub duration_ms_max: u64,
+}
+
+/// Code size, duration and outcome of every execution, by `WorkloadLabels`. Each is kept here
+/// for deployments without Prometheus and recorded as metrics as well.
+#[derive(Debug, Default)]
+pub struct WorkloadRecorder {
+    series: Mutex<BTreeMap<WorkloadLabels, WorkloadStats>>,
+}
+
+impl WorkloadRecorder {
+    /// Count an execution of `code_bytes` of source that took `duration` and ended in `outcome`
+    pub fn record(
+        &self,
+        labels: &WorkloadLabels,
+        outcome: Outcome,
+        code_bytes: usize,
+        duration: Duration,
+    ) {
+        let pairs = labels.pairs();
+        let [language, image, profile] = pairs;
+        telemetry::increment_counter(
+            "fc_executions_total",
+            &[language, image, profile, ("outcome", outcome.as_str())],
+            1,
+        );
+        telemetry::observe_histogram("fc_execution_code_bytes", &pairs, code_bytes as f64);
+        telemetry::observe_histogram(
+            "fc_execution_duration_ms",
+            &pairs,
+            duration.as_secs_f64() * 1000.0,
+        );
+
+        let code_bytes = code_bytes as u64;
+        let duration_ms = duration.as_millis() as u64;
+        let mut series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = series
+            .entry(labels.clone())
+            .or_insert_with(|| WorkloadStats {
+                language: labels.language.to_string(),
+                image: labels.image.clone(),
+                profile: labels.profile.clone(),
+                ..Default::default()
+            });
+        stats.executions += 1;
+        match outcome {
+            Outcome::Success => stats.succeeded += 1,
+            Outcome::Failure => stats.failed += 1,
+            Outcome::Timeout => stats.timed_out += 1,
+            Outcome::Error => stats.errors += 1,
+        }
+        stats.code_bytes_total += code_bytes;
+        stats.code_bytes_max = stats.code_bytes_max.max(code_bytes);
+        stats.duration_ms_total += duration_ms;
+        stats.duration_ms_max = stats.duration_ms_max.max(duration_ms);
+    }
+
+    /// Every series recorded so far, ordered by language, image and profile
+    pub fn snapshot(&self) -> Vec<WorkloadStats> {
+        let series = self.series.lock().unwrap_or_else(|e| e.into_inner());
+        series.values().cloned().collect()
+    }
+}
@@ -0,0 +1,177 @@
+//! Injects faults into mock VMs at every fault point and checks the executor recovers
+#![cfg(feature = "chaos")]
+
+use firecracker_poc::ExecutionError;
+use firecracker_poc::boot_report::BOOT_REPORTS;
+use firecracker_poc::chaos::{Fault, FaultInjector, FaultPoint};
+use firecracker_poc::config::RunnerConfig;
+use firecracker_poc::events::{self, VmEvent};
+use firecracker_poc::executor::ExecutorService;
+use firecracker_poc::runner::{ExecutionSpec, live_vm};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An executor whose VMs get `faults`, injected in order
+fn executor(faults: &[(FaultPoint, Fault)]) -> (ExecutorService, Arc<FaultInjector>) {
+    let injector = Arc::new(
+        FaultInjector::script(faults.iter().copied()).with_mid_execute_delay(Duration::ZERO),
+    );
+    let config = RunnerConfig {
+        faults: Some(injector.clone()),
+        ..Default::default()
+    };
+    (ExecutorService::new(Arc::new(config)), injector)
+}
+
+async fn execute(executor: &ExecutorService) -> Result<String, ExecutionError> {
+    executor
+        .execute(ExecutionSpec::code("print('chaos')"))
+        .await
+        .map(|response| response.stdout)
+}
+
+/// IDs of the VMs `events` saw created, including those of tests running alongside
+fn created(events: &mut tokio::sync::broadcast::Receiver<VmEvent>) -> Vec<String> {
+    std::iter::from_fn(|| events.try_recv().ok())
+        .filter_map(|event| match event {
+            VmEvent::Created { vm_id } => Some(vm_id),
+            _ => None,
+        })
+        .collect()
+}
+
+async fn assert_cleaned_up(vm_ids: &[String]) {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    for vm_id in vm_ids {
+        while live_vm(vm_id).is_some() {
+            assert!(std::time::Instant::now() < deadline, "VM {vm_id} leaked");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_vm_dying_in_the_pool_is_replaced() {
+    let (executor, injector) = executor(&[(FaultPoint::AfterBoot, Fault::Kill)]);
+    let mut events = events::subscribe();
+    assert_eq!(executor.warm(1).await, 1);
+
+    assert_eq!(
+        execute(&executor).await.unwrap(),
+        "Mock execution of: print('chaos')\n"
+    );
+    let stats = executor.stats().await;
+    // The dead VM was discarded and a fresh one took its place in the pool
+    assert_eq!((stats.vms_created, stats.idle_vms), (2, 1));
+    assert_eq!(injector.remaining(), 0);
+
+    let vm_ids = created(&mut events);
+    executor.shutdown().await;
+    assert_cleaned_up(&vm_ids).await;
+}
+
+#[tokio::test]
+async fn test_vm_dying_between_acquire_and_execute_is_replaced() {
+    let (executor, _) = executor(&[(FaultPoint::BeforeExecute, Fault::Kill)]);
+    assert_eq!(executor.warm(1).await, 1);
+
+    assert!(execute(&executor).await.is_ok());
+    assert_eq!(executor.stats().await.vms_created, 2);
+    executor.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_fresh_vm_dying_before_execute_is_an_error() {
+    let (executor, _) = executor(&[(FaultPoint::BeforeExecute, Fault::Kill)]);
+    let mut events = events::subscribe();
+
+    // No second boot for a VM that died right away, but nothing is left behind either
+    let err = execute(&executor).await.unwrap_err();
+    assert_eq!(err.code(), "api_communication_error");
+    assert_cleaned_up(&created(&mut events)).await;
+
+    assert!(execute(&executor).await.is_ok());
+    executor.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_vm_killed_mid_execute_fails_cleanly() {
+    let (executor, _) = executor(&[(FaultPoint::MidExecute, Fault::Kill)]);
+    let mut events = events::subscribe();
+    assert_eq!(executor.warm(1).await, 1);
+
+    let err = execute(&executor).await.unwrap_err();
+    assert!(
+        matches!(err, ExecutionError::ApiCommunicationError(_)),
+        "{err}"
+    );
+    // The killed VM never goes back to the pool
+    assert_eq!(executor.stats().await.idle_vms, 0);
+    assert_cleaned_up(&created(&mut events)).await;
+
+    assert!(execute(&executor).await.is_ok());
+    assert_eq!(executor.stats().await.idle_vms, 1);
+    executor.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_dropped_tap_fails_the_execution() {
+    let (executor, _) = executor(&[(FaultPoint::BeforeExecute, Fault::DropTap)]);
+    assert_eq!(executor.warm(1).await, 1);
+
+    // The process is alive, so only the execution itself can tell the guest is unreachable
+    let err = execute(&executor).await.unwrap_err();
+    assert_eq!(err.code(), "api_communication_error");
+    assert_eq!(executor.stats().await.idle_vms, 0);
+    executor.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_boot_error_leaves_nothing_behind() {
+    let (executor, _) = executor(&[(FaultPoint::AfterBoot, Fault::Error)]);
+    let mut events = events::subscribe();
+
+    assert!(execute(&executor).await.is_err());
+    assert_cleaned_up(&created(&mut events)).await;
+
+    assert!(execute(&executor).await.is_ok());
+    executor.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_debug_boot_keeps_a_report_of_the_failed_boot() {
+    let (executor, _) = executor(&[(FaultPoint::AfterBoot, Fault::Error)]);
+    let mut events = events::subscribe();
+
+    let spec = ExecutionSpec {
+        debug_boot: true,
+        ..ExecutionSpec::code("print('chaos')")
+    };
+    assert!(executor.execute(spec).await.is_err());
+    let vm_ids = created(&mut events);
+    assert_cleaned_up(&vm_ids).await;
+
+    // Tests running alongside create VMs too, but only this one kept a report
+    let report = vm_ids
+        .iter()
+        .find_map(|vm_id| BOOT_REPORTS.get(vm_id))
+        .expect("the failed boot should have a report");
+    assert!(report.error.contains("injected fault"), "{}", report.error);
+    assert!(report.boot_args.contains("ip=172.16."));
+    assert!(report.expires_at > report.failed_at);
+    executor.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_shutdown_faults_still_clean_up() {
+    let (executor, injector) = executor(&[
+        (FaultPoint::Shutdown, Fault::Error),
+        (FaultPoint::Shutdown, Fault::Kill),
+    ]);
+    let mut events = events::subscribe();
+    assert_eq!(executor.warm(2).await, 2);
+
+    executor.shutdown().await;
+    assert_eq!(injector.remaining(), 0);
+    assert_cleaned_up(&created(&mut events)).await;
+}
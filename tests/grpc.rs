@@ -0,0 +1,177 @@
+//! Drives the gRPC `Executor` service with a tonic client against the mock VM backend
+#![cfg(feature = "grpc")]
+
+use firecracker_poc::auth::ApiKeys;
+use firecracker_poc::backend::BackendKind;
+use firecracker_poc::config::{Config, RunnerConfig};
+use firecracker_poc::executor::ExecutorService;
+use firecracker_poc::grpc::proto::execute_event::Event;
+use firecracker_poc::grpc::proto::executor_client::ExecutorClient;
+use firecracker_poc::grpc::proto::{self, ExecuteRequest};
+use firecracker_poc::grpc::{self, GrpcExecutor};
+use firecracker_poc::rate_limit::RateLimiter;
+use firecracker_poc::service::ExecutionService;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::transport::Channel;
+
+/// Start the service on an ephemeral port, returning a client and the shutdown trigger
+async fn start(api_keys: &[String]) -> (ExecutorClient<Channel>, tokio::sync::oneshot::Sender<()>) {
+    start_with(api_keys, RunnerConfig::default()).await
+}
+
+/// Like `start`, with the VM backend configured by `runner`
+async fn start_with(
+    api_keys: &[String],
+    runner: RunnerConfig,
+) -> (ExecutorClient<Channel>, tokio::sync::oneshot::Sender<()>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let executor = GrpcExecutor {
+        service: ExecutionService::new(
+            Arc::new(Config::default()),
+            ExecutorService::new(Arc::new(runner)),
+        ),
+        api_keys: Arc::new(ApiKeys::new(api_keys)),
+        rate_limiter: Arc::new(RateLimiter::new(None, HashMap::new())),
+    };
+    let (stop, stopped) = tokio::sync::oneshot::channel();
+    tokio::spawn(grpc::serve(listener, executor, async {
+        let _ = stopped.await;
+    }));
+    let client = ExecutorClient::connect(format!("http://{addr}"))
+        .await
+        .unwrap();
+    (client, stop)
+}
+
+fn code(code: &str) -> ExecuteRequest {
+    ExecuteRequest {
+        code: Some(code.to_string()),
+        ..ExecuteRequest::default()
+    }
+}
+
+#[tokio::test]
+async fn test_grpc_execute() {
+    let (mut client, _stop) = start(&[]).await;
+
+    let response = client
+        .execute(code("print('hello grpc')"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.success);
+    assert_eq!(response.stdout, "Mock execution of: print('hello grpc')\n");
+    assert_eq!(response.stdout_encoding(), proto::OutputEncoding::Utf8);
+    assert!(response.value_json.is_none());
+
+    // The mock backend stands a final JSON literal in for the evaluated value
+    let response = client
+        .execute(ExecuteRequest {
+            mode: Some(proto::ExecMode::Eval.into()),
+            ..code("x = 1\n[1, 2]")
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.value_json.as_deref(), Some("[1,2]"));
+
+    // The same validation as `/execute` applies
+    let status = client.execute(code("   ")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(status.message(), "Empty code provided");
+
+    let status = client
+        .execute(ExecuteRequest {
+            fake_time: Some(0),
+            ..code("print(1)")
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_grpc_execute_stream() {
+    let (mut client, _stop) = start(&[]).await;
+
+    let mut stream = client
+        .execute_stream(code("print('streamed')"))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut stdout = String::new();
+    let mut result = None;
+    while let Some(event) = stream.message().await.unwrap() {
+        match event.event.unwrap() {
+            Event::Stdout(chunk) => stdout.push_str(&chunk),
+            Event::Stderr(_) => {}
+            Event::Result(response) => result = Some(response),
+        }
+    }
+    assert_eq!(stdout, "Mock execution of: print('streamed')\n");
+    let result = result.expect("stream ends with the result");
+    assert!(result.success);
+    assert!(result.stdout.is_empty());
+
+    // Rejections end the stream with their status
+    let status = match client.execute_stream(code("   ")).await {
+        Ok(response) => response.into_inner().message().await.unwrap_err(),
+        Err(status) => status,
+    };
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert_eq!(status.message(), "Empty code provided");
+}
+
+#[tokio::test]
+async fn test_grpc_execute_stream_sends_output_as_printed() {
+    let (mut client, _stop) = start_with(
+        &[],
+        RunnerConfig {
+            backend: BackendKind::Mock,
+            mock_latency: std::time::Duration::from_millis(400),
+            ..RunnerConfig::default()
+        },
+    )
+    .await;
+
+    let started = std::time::Instant::now();
+    let mut stream = client
+        .execute_stream(code("print(1)\nprint(2)"))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut chunks = Vec::new();
+    let mut result_at = None;
+    while let Some(event) = stream.message().await.unwrap() {
+        match event.event.unwrap() {
+            Event::Stdout(chunk) => chunks.push((chunk, started.elapsed())),
+            Event::Stderr(_) => {}
+            Event::Result(_) => result_at = Some(started.elapsed()),
+        }
+    }
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].0, "Mock execution of: print(1)\n");
+    assert_eq!(chunks[1].0, "print(2)\n");
+    // The mock prints a line every 200ms, so the first came well before the end
+    let result_at = result_at.expect("stream ends with the result");
+    assert!(
+        chunks[0].1 + std::time::Duration::from_millis(100) < result_at,
+        "{chunks:?} {result_at:?}"
+    );
+}
+
+#[tokio::test]
+async fn test_grpc_requires_api_key() {
+    let (mut client, _stop) = start(&["secret".to_string()]).await;
+
+    let status = client.execute(code("print(1)")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(code("print(1)"));
+    request
+        .metadata_mut()
+        .insert("authorization", "Bearer secret".parse().unwrap());
+    assert!(client.execute(request).await.unwrap().into_inner().success);
+}
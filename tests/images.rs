@@ -0,0 +1,256 @@
+//! Imports container images built from in-memory layers and inspects the result with debugfs
+use firecracker_poc::images::{self, ImportSpec};
+use firecracker_poc::rootfs::{self, RootfsError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn tools_present() -> bool {
+    ["mkfs.ext4", "debugfs"]
+        .iter()
+        .all(|tool| rootfs::tool_available(tool))
+}
+
+fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fc-images-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("agent")).unwrap();
+    std::fs::write(
+        dir.join("agent").join(rootfs::AGENT_FILE),
+        "print('agent')\n",
+    )
+    .unwrap();
+    dir
+}
+
+fn spec(dir: &Path, source: PathBuf) -> ImportSpec {
+    ImportSpec {
+        source,
+        name: "ds".to_string(),
+        agent_dir: dir.join("agent"),
+        image_dir: dir.join("images"),
+        headroom_mib: 4,
+    }
+}
+
+/// A header with the numeric fields docker fills in
+fn header() -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header
+}
+
+enum Item {
+    File(&'static str, &'static str),
+    Symlink(&'static str, &'static str),
+    /// A file whose name is written into the header as is, bypassing the builder's checks
+    Raw(&'static str),
+}
+
+fn layer(items: &[Item]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for item in items {
+        let mut header = header();
+        match item {
+            Item::File(path, content) => {
+                header.set_size(content.len() as u64);
+                header.set_mode(0o644);
+                builder
+                    .append_data(&mut header, path, content.as_bytes())
+                    .unwrap();
+            }
+            Item::Symlink(path, target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, path, target).unwrap();
+            }
+            Item::Raw(path) => {
+                header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+                header.set_size(0);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append(&header, std::io::empty()).unwrap();
+            }
+        }
+    }
+    builder.into_inner().unwrap()
+}
+
+/// A `docker save` tarball of `layers`, lowest first
+fn docker_save(dir: &Path, layers: &[Vec<u8>]) -> PathBuf {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut names = Vec::new();
+    for (index, layer) in layers.iter().enumerate() {
+        let name = format!("layer{index}/layer.tar");
+        let mut header = header();
+        header.set_size(layer.len() as u64);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, &name, layer.as_slice())
+            .unwrap();
+        names.push(name);
+    }
+    let manifest = serde_json::json!([{"Config": "config.json", "Layers": names}]).to_string();
+    let mut header = header();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    builder
+        .append_data(&mut header, "manifest.json", manifest.as_bytes())
+        .unwrap();
+    let path = dir.join("image.tar");
+    std::fs::write(&path, builder.into_inner().unwrap()).unwrap();
+    path
+}
+
+fn debugfs(image: &Path, request: &str) -> String {
+    let output = Command::new("debugfs")
+        .arg("-R")
+        .arg(request)
+        .arg(image)
+        .output()
+        .unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn exists(image: &Path, path: &str) -> bool {
+    debugfs(image, &format!("stat {path}")).contains("Inode:")
+}
+
+/// Two layers: the second hides a file with a whiteout, replaces a directory's contents
+/// with an opaque whiteout and overrides a file
+fn two_layers() -> Vec<Vec<u8>> {
+    vec![
+        layer(&[
+            Item::File("usr/bin/python3", "#!/bin/sh\n"),
+            Item::File("etc/old.conf", "old\n"),
+            Item::File("etc/motd", "v1\n"),
+            Item::File("opt/app/a.py", "a\n"),
+        ]),
+        layer(&[
+            Item::File("etc/.wh.old.conf", ""),
+            Item::File("etc/motd", "v2\n"),
+            Item::File("opt/app/.wh..wh..opq", ""),
+            Item::File("opt/app/b.py", "b\n"),
+        ]),
+    ]
+}
+
+fn check_flattened(image: &Path) {
+    assert_eq!(debugfs(image, "cat /etc/motd"), "v2\n");
+    assert!(!exists(image, "/etc/old.conf"));
+    assert!(!exists(image, "/opt/app/a.py"));
+    assert!(!exists(image, "/opt/app/.wh..wh..opq"));
+    assert_eq!(debugfs(image, "cat /opt/app/b.py"), "b\n");
+    assert_eq!(
+        debugfs(image, "cat /usr/local/bin/vm_api_server.py"),
+        "print('agent')\n"
+    );
+    assert!(exists(image, "/usr/local/bin/startup.sh"));
+}
+
+#[test]
+fn test_import_docker_save_tarball() {
+    if !tools_present() {
+        eprintln!("skipping: mkfs.ext4 and debugfs are required");
+        return;
+    }
+    let dir = workdir("docker");
+    let spec = spec(&dir, docker_save(&dir, &two_layers()));
+
+    let image = images::import(&spec).unwrap();
+    assert_eq!(image.path, dir.join("images/ds.ext4"));
+    check_flattened(&image.path);
+    // The image is now selectable by name, and no work files are left behind
+    assert_eq!(
+        images::discover(&spec.image_dir)
+            .into_keys()
+            .collect::<Vec<_>>(),
+        vec!["ds".to_string()]
+    );
+    assert_eq!(std::fs::read_dir(&spec.image_dir).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_import_oci_layout() {
+    if !tools_present() {
+        eprintln!("skipping: mkfs.ext4 and debugfs are required");
+        return;
+    }
+    let dir = workdir("oci");
+    let layout = dir.join("layout");
+    let blobs = layout.join("blobs/sha256");
+    std::fs::create_dir_all(&blobs).unwrap();
+    let write_blob = |content: &[u8]| {
+        let digest = sha256_hex(content);
+        std::fs::write(blobs.join(&digest), content).unwrap();
+        format!("sha256:{digest}")
+    };
+    // OCI layers are usually gzipped
+    let layers: Vec<_> = two_layers()
+        .iter()
+        .map(|layer| {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+            encoder.write_all(layer).unwrap();
+            serde_json::json!({
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": write_blob(&encoder.finish().unwrap()),
+            })
+        })
+        .collect();
+    let manifest = write_blob(
+        serde_json::json!({"schemaVersion": 2, "layers": layers})
+            .to_string()
+            .as_bytes(),
+    );
+    std::fs::write(
+        layout.join("index.json"),
+        serde_json::json!({"schemaVersion": 2, "manifests": [{
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "digest": manifest,
+        }]})
+        .to_string(),
+    )
+    .unwrap();
+
+    let image = images::import(&spec(&dir, layout)).unwrap();
+    check_flattened(&image.path);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_import_rejects_escaping_entries() {
+    if !tools_present() {
+        eprintln!("skipping: mkfs.ext4 and debugfs are required");
+        return;
+    }
+    let dir = workdir("escape");
+    let base = layer(&[Item::File("usr/bin/python3", "")]);
+    for layers in [
+        vec![base.clone(), layer(&[Item::Raw("../escaped")])],
+        // A link planted by one layer must not redirect the writes of the next
+        vec![
+            base.clone(),
+            layer(&[Item::Symlink("etc", "/tmp")]),
+            layer(&[Item::File("etc/escaped", "x")]),
+        ],
+    ] {
+        let spec = spec(&dir, docker_save(&dir, &layers));
+        assert!(matches!(
+            images::import(&spec),
+            Err(RootfsError::UnsafeEntry(_))
+        ));
+        assert!(!spec.output().exists());
+    }
+    assert!(!dir.join("escaped").exists());
+    assert!(!Path::new("/tmp/escaped").exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    use sha2::Digest;
+    hex::encode(sha2::Sha256::digest(content))
+}
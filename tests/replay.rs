@@ -0,0 +1,91 @@
+//! Runs the runner's boot sequence against recorded Firecracker API sessions
+use firecracker_poc::config::RunnerConfig;
+use firecracker_poc::deadline::Deadline;
+use firecracker_poc::replay::{Placeholders, ReplayError, ReplayServer, Session};
+use firecracker_poc::runner::{EXECUTION_BUDGET, VMManager};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn golden_session() -> Session {
+    Session::load(Path::new("fixtures/api-session-boot.jsonl")).unwrap()
+}
+
+/// Scratch directory of one test
+fn scratch_dir(vm: &VMManager) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fc-replay-{}", vm.vm_id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Configure, boot and wait for `vm` with its APIs served from `session`
+async fn replay(vm: &mut VMManager, session: &Session) -> Result<(), ReplayError> {
+    let dir = scratch_dir(vm);
+    let socket_path = dir.join("firecracker.socket");
+    let server = ReplayServer::start(session, &socket_path, &Placeholders::for_vm(vm))
+        .await
+        .unwrap();
+    vm.use_api_endpoints(socket_path.to_str().unwrap(), server.agent_url());
+    if vm.configure_and_run_vm().await.is_ok() {
+        vm.wait_for_api_server(Deadline::after(EXECUTION_BUDGET))
+            .await
+            .unwrap();
+    }
+    let result = server.finish();
+    std::fs::remove_dir_all(dir).unwrap();
+    result
+}
+
+// Boot arguments and artifacts differ per architecture; the session was recorded on x86_64
+#[cfg(target_arch = "x86_64")]
+#[tokio::test]
+async fn test_boot_follows_the_golden_session() {
+    let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+    replay(&mut vm, &golden_session()).await.unwrap();
+}
+
+#[cfg(target_arch = "x86_64")]
+#[tokio::test]
+async fn test_recording_reproduces_the_golden_session() {
+    let dir = std::env::temp_dir().join(format!("fc-record-{}", firecracker_poc::generate_vm_id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = RunnerConfig {
+        api_record_dir: Some(dir.clone()),
+        ..Default::default()
+    };
+    let mut vm = VMManager::with_config(Arc::new(config));
+    replay(&mut vm, &golden_session()).await.unwrap();
+
+    let recorded = Session::load(&dir.join(format!("api-{}.jsonl", vm.vm_id()))).unwrap();
+    assert_eq!(recorded, golden_session());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_divergence_is_reported_as_a_diff() {
+    let mut session = golden_session();
+    let machine_config = session
+        .interactions
+        .iter_mut()
+        .find(|interaction| interaction.path == "/machine-config")
+        .unwrap();
+    machine_config.body = Some(serde_json::json!({ "mem_size_mib": 256, "vcpu_count": 2 }));
+
+    let mut vm = VMManager::with_config(Arc::new(RunnerConfig::default()));
+    let ReplayError::Diverged(report) = replay(&mut vm, &session).await.unwrap_err() else {
+        panic!("expected a divergence");
+    };
+    assert!(
+        report.contains("request 2 differs from the session (- expected, + actual):"),
+        "{report}"
+    );
+    assert!(
+        report.contains("-   \"mem_size_mib\": 256,\n+   \"mem_size_mib\": 128,"),
+        "{report}"
+    );
+    // The rejected call stopped the boot, leaving the rest of the session unserved
+    assert!(
+        report.contains("recorded requests were never made"),
+        "{report}"
+    );
+    assert!(report.contains("PUT /actions"), "{report}");
+}
@@ -0,0 +1,110 @@
+//! Builds a rootfs image from a tiny fake base and inspects it with debugfs
+use firecracker_poc::rootfs::{self, RootfsError, RootfsSpec};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn tools_present() -> bool {
+    ["tar", "mkfs.ext4", "debugfs"]
+        .iter()
+        .all(|tool| rootfs::tool_available(tool))
+}
+
+fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fc-rootfs-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A tarball shaped like an Alpine minirootfs, with `python3` only if asked for
+fn base_tar(dir: &Path, with_python: bool) -> PathBuf {
+    let tree = dir.join("base");
+    std::fs::create_dir_all(tree.join("usr/bin")).unwrap();
+    std::fs::create_dir_all(tree.join("etc")).unwrap();
+    std::fs::write(tree.join("etc/alpine-release"), "3.20.0\n").unwrap();
+    if with_python {
+        std::fs::write(tree.join("usr/bin/python3"), "#!/bin/sh\n").unwrap();
+    }
+    let tar = dir.join("base.tar");
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(&tar)
+        .arg("-C")
+        .arg(&tree)
+        .arg(".")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    tar
+}
+
+fn debugfs(image: &Path, request: &str) -> String {
+    let output = Command::new("debugfs")
+        .arg("-R")
+        .arg(request)
+        .arg(image)
+        .output()
+        .unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn test_build_rootfs_image() {
+    if !tools_present() {
+        eprintln!("skipping: tar, mkfs.ext4 and debugfs are required");
+        return;
+    }
+    let dir = workdir("build");
+    let agent_dir = dir.join("agent");
+    std::fs::create_dir_all(&agent_dir).unwrap();
+    std::fs::write(agent_dir.join(rootfs::AGENT_FILE), "print('agent')\n").unwrap();
+    let spec = RootfsSpec {
+        base: base_tar(&dir, true),
+        agent_dir,
+        output: dir.join("rootfs.ext4"),
+        headroom_mib: 4,
+    };
+
+    let image = rootfs::build(&spec).unwrap();
+    assert_eq!(image.path, spec.output);
+    assert_eq!(
+        image.size_bytes,
+        std::fs::metadata(&spec.output).unwrap().len()
+    );
+    assert_eq!(image.sha256.len(), 64);
+    assert!(image.to_string().contains(&image.sha256));
+    assert!(!dir.join("rootfs.staging").exists());
+
+    assert_eq!(
+        debugfs(&spec.output, "cat /usr/local/bin/vm_api_server.py"),
+        "print('agent')\n"
+    );
+    assert_eq!(debugfs(&spec.output, "cat /etc/alpine-release"), "3.20.0\n");
+    let startup = debugfs(&spec.output, "stat /usr/local/bin/startup.sh");
+    assert!(startup.contains("Mode:  0755"), "{startup}");
+    assert!(startup.contains("User:     0"), "{startup}");
+    assert!(debugfs(&spec.output, "cat /usr/local/bin/startup.sh").contains("vm_api_server.py"));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_build_rootfs_requires_python() {
+    if !tools_present() {
+        eprintln!("skipping: tar, mkfs.ext4 and debugfs are required");
+        return;
+    }
+    let dir = workdir("no-python");
+    std::fs::write(dir.join(rootfs::AGENT_FILE), "").unwrap();
+    let spec = RootfsSpec {
+        base: base_tar(&dir, false),
+        agent_dir: dir.clone(),
+        output: dir.join("rootfs.ext4"),
+        headroom_mib: 4,
+    };
+
+    let error = rootfs::build(&spec).unwrap_err();
+    assert!(matches!(&error, RootfsError::InvalidInput(message) if message.contains("python3")));
+    assert!(!spec.output.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -3,16 +3,417 @@
 VM API Server - runs inside the Firecracker VM to execute Python code
 """
 
+import ast
+import base64
+import codecs
+import hashlib
 import json
 import sys
 import subprocess
 import traceback
 import tempfile
+import io
 import os
+import re
+import resource
+import shutil
+import socket
+import socketserver
 from http.server import HTTPServer, BaseHTTPRequestHandler
 from urllib.parse import urlparse, parse_qs
 import threading
 import time
+import urllib.error
+import urllib.request
+
+
+# Per-stream output cap used when the host doesn't send one
+DEFAULT_MAX_OUTPUT_BYTES = 1024 * 1024
+
+# Version of the host/agent protocol this agent speaks, reported by `/health`. Bump it with
+# every payload field the host may send, and record the new field in the host's
+# `guest_protocol` module.
+PROTOCOL_VERSION = 4
+
+# Maximum number of chained exceptions reported in the `exception` field
+MAX_EXCEPTION_CHAIN = 10
+
+# Time allowed for installing `requirements`, separate from the execution timeout
+SETUP_TIMEOUT_SECONDS = 300
+
+# Where `inputs` are downloaded, given to the code as FC_INPUTS_DIR
+INPUTS_DIR = "/inputs"
+
+# Time allowed for downloading every input, separate from the execution timeout
+INPUTS_TIMEOUT_SECONDS = 120
+
+# Download cap per input used when the host doesn't send one
+DEFAULT_MAX_INPUT_BYTES = 64 * 1024 * 1024
+
+# Where each execution gets a working directory of its own, `<root>/<exec_id>`, deleted once
+# its response is sent
+WORKSPACE_ROOT = "/workspace"
+
+# Execution IDs the host may send; anything else could escape the workspace root
+EXEC_ID_PATTERN = re.compile(r"[A-Za-z0-9-]{1,64}")
+
+# Where the read-only dependencies drive announced by `fc_deps=<device>` is mounted
+DEPS_MOUNT_POINT = "/opt/deps"
+
+# Where each read-only reference drive announced by `fc_refdata=<name>:<device>,...` is
+# mounted, as `<dir>/<name>`, given to the code as FC_REFERENCE_DIR
+REFERENCE_DIR = "/opt/reference"
+
+# Set by `fc_deterministic=1`; the agent's own hash seed is fixed at startup, so code then
+# always runs in a subprocess that inherits the pinned environment
+DETERMINISTIC = False
+
+
+def safe_text(text):
+    """Escape lone surrogates so the text can be encoded as strict JSON/UTF-8"""
+    return text.encode("utf-8", "backslashreplace").decode("utf-8")
+
+
+def describe_exception(exc, depth=0):
+    """Structured form of an exception: type, message, frames and its cause"""
+    frames = [
+        {
+            "filename": safe_text(frame.filename),
+            "line": frame.lineno or 0,
+            "function": safe_text(frame.name),
+        }
+        for frame in traceback.extract_tb(exc.__traceback__)
+        # The agent's own frames are noise to the user
+        if frame.filename != __file__
+    ]
+    info = {
+        "type": type(exc).__name__,
+        "message": safe_text(str(exc)),
+        "traceback": frames,
+    }
+    cause = exc.__cause__ or (None if exc.__suppress_context__ else exc.__context__)
+    if cause is not None and depth + 1 < MAX_EXCEPTION_CHAIN:
+        info["cause"] = describe_exception(cause, depth + 1)
+    return info
+
+
+def evaluate(code, namespace, filename="<string>"):
+    """Run code like a REPL cell: the value of a final expression statement is returned as
+    the `value` field, or `value_repr` when it isn't JSON-serializable"""
+    tree = ast.parse(code, filename)
+    last = tree.body.pop() if tree.body and isinstance(tree.body[-1], ast.Expr) else None
+    exec(compile(tree, filename, "exec"), namespace)
+    if last is None:
+        return {}
+    value = eval(compile(ast.Expression(last.value), filename, "eval"), namespace)
+    try:
+        # Round-tripped so keys and containers come out as the host will see them
+        return {"value": json.loads(json.dumps(value, allow_nan=False))}
+    except (TypeError, ValueError, RecursionError):
+        return {"value_repr": safe_text(repr(value))}
+
+
+def encode_output(data, truncated=False):
+    """Represent output bytes as (text, encoding): UTF-8 when valid, base64 otherwise"""
+    try:
+        return data.decode("utf-8"), "utf8"
+    except UnicodeDecodeError as e:
+        # A cut at the output limit may split the final character; that alone isn't binary
+        if truncated and e.reason == "unexpected end of data":
+            return data[: e.start].decode("utf-8"), "utf8"
+    return base64.b64encode(data).decode("ascii"), "base64"
+
+
+class LiveOutput:
+    """Sends output to the host as JSON lines while the code runs, for `stream` requests,
+    each stream capped at max_bytes; the result line follows once the code exits"""
+
+    # Bytes of output per line, so every line stays far below the host's line limit
+    CHUNK_BYTES = 4096
+
+    def __init__(self, wfile, max_bytes):
+        self.wfile = wfile
+        self.lock = threading.Lock()
+        self.remaining = {"stdout": max_bytes, "stderr": max_bytes}
+        # Lines carry text; a character split between two reads is sent whole with the second
+        self.decoders = {
+            name: codecs.getincrementaldecoder("utf-8")("replace") for name in self.remaining
+        }
+
+    def write(self, name, data):
+        with self.lock:
+            data = bytes(data[: max(self.remaining[name], 0)])
+            self.remaining[name] -= len(data)
+            for start in range(0, len(data), self.CHUNK_BYTES):
+                text = self.decoders[name].decode(data[start : start + self.CHUNK_BYTES])
+                if text:
+                    self.send({"type": name, "data": text})
+
+    def send(self, message):
+        try:
+            self.wfile.write((json.dumps(message) + "\n").encode())
+            self.wfile.flush()
+        except OSError:
+            # The host went away; the code runs to the end anyway
+            pass
+
+
+class LimitedBytesIO(io.BytesIO):
+    """BytesIO that stops accumulating once max_bytes have been written, passing what it
+    keeps to on_write as well"""
+
+    def __init__(self, max_bytes, on_write=None):
+        super().__init__()
+        self.max_bytes = max_bytes
+        self.truncated = False
+        self.on_write = on_write
+
+    def write(self, data):
+        remaining = self.max_bytes - self.tell()
+        if len(data) > remaining:
+            self.truncated = True
+            data = bytes(data[: max(remaining, 0)])
+        super().write(data)
+        if self.on_write is not None and data:
+            self.on_write(data)
+        return len(data)
+
+
+class OutputCapture:
+    """Text stream for redirect_stdout whose .buffer accepts raw bytes as well"""
+
+    def __init__(self, max_bytes, on_write=None):
+        self.raw = LimitedBytesIO(max_bytes, on_write)
+        # surrogateescape round-trips bytes that came in through os.fsdecode and friends
+        self.stream = io.TextIOWrapper(
+            self.raw, encoding="utf-8", errors="surrogateescape", write_through=True
+        )
+
+    def result(self):
+        self.stream.flush()
+        return encode_output(self.raw.getvalue(), self.raw.truncated)
+
+    @property
+    def truncated(self):
+        return self.raw.truncated
+
+
+def output_fields(stdout_capture, stderr_capture):
+    """Response fields describing both captured streams"""
+    stdout, stdout_encoding = stdout_capture.result()
+    stderr, stderr_encoding = stderr_capture.result()
+    return {
+        "stdout": stdout,
+        "stderr": stderr,
+        "stdout_encoding": stdout_encoding,
+        "stderr_encoding": stderr_encoding,
+        "stdout_truncated": stdout_capture.truncated,
+        "stderr_truncated": stderr_capture.truncated,
+    }
+
+
+def usage_fields(wall_seconds, cpu_seconds, max_rss_kb):
+    """The `usage` response field; ru_maxrss is already in KiB on Linux"""
+    return {
+        "cpu_time_ms": round(cpu_seconds * 1000),
+        "max_rss_kb": max_rss_kb,
+        "wall_ms": round(wall_seconds * 1000),
+    }
+
+
+def run_with_usage(args, timeout, cwd=None, live=None):
+    """Run args capturing output, and reap the child with wait4 to get its own rusage. Output
+    is passed to live as it is read, when given."""
+    start = time.monotonic()
+    env = None
+    if live is not None:
+        # Python children flush every write, so their output reaches the host as printed
+        env = {**os.environ, "PYTHONUNBUFFERED": "1"}
+    proc = subprocess.Popen(
+        args, stdout=subprocess.PIPE, stderr=subprocess.PIPE, cwd=cwd, env=env
+    )
+    output = {}
+
+    def drain(name, pipe):
+        chunks = []
+        for chunk in iter(lambda: pipe.read1(65536), b""):
+            chunks.append(chunk)
+            if live is not None:
+                live.write(name, chunk)
+        output[name] = b"".join(chunks)
+        pipe.close()
+
+    readers = [
+        threading.Thread(target=drain, args=("stdout", proc.stdout)),
+        threading.Thread(target=drain, args=("stderr", proc.stderr)),
+    ]
+    for reader in readers:
+        reader.start()
+
+    # Poll so the timeout can be enforced while still reaping through wait4
+    while True:
+        pid, status, rusage = os.wait4(proc.pid, os.WNOHANG)
+        if pid:
+            break
+        if time.monotonic() - start > timeout:
+            proc.kill()
+            os.wait4(proc.pid, 0)
+            proc.returncode = -9
+            for reader in readers:
+                reader.join()
+            raise subprocess.TimeoutExpired(args, timeout)
+        time.sleep(0.01)
+    wall = time.monotonic() - start
+    proc.returncode = os.waitstatus_to_exitcode(status)
+    for reader in readers:
+        reader.join()
+
+    result = subprocess.CompletedProcess(
+        args, proc.returncode, output.get("stdout", b""), output.get("stderr", b"")
+    )
+    result.pid = proc.pid
+    usage = usage_fields(wall, rusage.ru_utime + rusage.ru_stime, rusage.ru_maxrss)
+    return result, usage
+
+
+def oom_killed(pid):
+    """Whether the kernel log says the OOM killer terminated pid"""
+    try:
+        log = subprocess.run(
+            ["dmesg"], capture_output=True, text=True, timeout=5
+        ).stdout
+    except (OSError, subprocess.SubprocessError):
+        return False
+    # "Killed process <pid> (<name>)", or "Kill process" on older kernels
+    return re.search(rf"Kill(ed)? process {pid} \(", log) is not None
+
+
+def termination_fields(result):
+    """The signal that ended the process, and whether it was the OOM killer's"""
+    if result.returncode >= 0:
+        return {}
+    fields = {"term_signal": -result.returncode}
+    if -result.returncode == 9 and oom_killed(getattr(result, "pid", None)):
+        fields["oom_killed"] = True
+    return fields
+
+
+def completed_fields(result, usage, max_output_bytes):
+    """Response fields for a finished subprocess, capping each stream at max_output_bytes"""
+    stdout_truncated = len(result.stdout) > max_output_bytes
+    stderr_truncated = len(result.stderr) > max_output_bytes
+    stdout, stdout_encoding = encode_output(
+        result.stdout[:max_output_bytes], stdout_truncated
+    )
+    stderr, stderr_encoding = encode_output(
+        result.stderr[:max_output_bytes], stderr_truncated
+    )
+    return {
+        "stdout": stdout,
+        "stderr": stderr,
+        "stdout_encoding": stdout_encoding,
+        "stderr_encoding": stderr_encoding,
+        "stdout_truncated": stdout_truncated,
+        "stderr_truncated": stderr_truncated,
+        "exit_code": result.returncode,
+        "success": result.returncode == 0,
+        "usage": usage,
+        **termination_fields(result),
+    }
+
+
+class NoRedirect(urllib.request.HTTPRedirectHandler):
+    """Refuse redirects: the host only checked the URL it was given"""
+
+    def redirect_request(self, req, fp, code, msg, headers, newurl):
+        raise urllib.error.HTTPError(
+            req.full_url, code, f"redirect to {newurl} not followed", headers, fp
+        )
+
+
+def download_inputs(inputs, max_bytes, root=INPUTS_DIR, timeout=INPUTS_TIMEOUT_SECONDS):
+    """Download each input under root, returning the `setup_error` of the first that fails"""
+    opener = urllib.request.build_opener(NoRedirect)
+    deadline = time.monotonic() + timeout
+    for item in inputs:
+        url, path, expected = item["url"], item["path"], item.get("sha256")
+
+        def failure(code, message, **extra):
+            return {"code": code, "url": url, "path": path, "message": safe_text(message), **extra}
+
+        # Paths were validated by the host; re-checked so nothing escapes root
+        target = os.path.realpath(os.path.join(root, path))
+        if not target.startswith(os.path.realpath(root) + os.sep):
+            return failure("download_failed", "path escapes the inputs directory")
+        digest = hashlib.sha256()
+        size = 0
+        try:
+            os.makedirs(os.path.dirname(target), exist_ok=True)
+            remaining = deadline - time.monotonic()
+            if remaining <= 0:
+                raise TimeoutError(f"downloads timed out ({timeout} seconds)")
+            with opener.open(url, timeout=remaining) as response, open(target, "wb") as f:
+                while chunk := response.read(64 * 1024):
+                    size += len(chunk)
+                    if size > max_bytes:
+                        f.close()
+                        os.unlink(target)
+                        return failure("too_large", f"download exceeds {max_bytes} bytes")
+                    if time.monotonic() > deadline:
+                        raise TimeoutError(f"downloads timed out ({timeout} seconds)")
+                    digest.update(chunk)
+                    f.write(chunk)
+        except (OSError, ValueError) as e:
+            if os.path.exists(target):
+                os.unlink(target)
+            return failure("download_failed", str(e))
+        actual = digest.hexdigest()
+        if expected and actual != expected.lower():
+            os.unlink(target)
+            return failure(
+                "checksum_mismatch",
+                "sha256 of the download doesn't match",
+                expected_sha256=expected,
+                actual_sha256=actual,
+            )
+    return None
+
+
+def write_project(root, files):
+    """Write files under root; paths are re-checked so nothing escapes it"""
+    root = os.path.realpath(root)
+    for file in files:
+        target = os.path.realpath(os.path.join(root, file["path"]))
+        if os.path.isabs(file["path"]) or not target.startswith(root + os.sep):
+            raise ValueError(f"Invalid file path: {file['path']}")
+        os.makedirs(os.path.dirname(target), exist_ok=True)
+        with open(target, "w", encoding="utf-8") as f:
+            f.write(file["content"])
+
+
+def open_workspace(exec_id, root=WORKSPACE_ROOT):
+    """Create the execution's workspace and make it the working directory"""
+    if not EXEC_ID_PATTERN.fullmatch(exec_id):
+        raise ValueError(f"Invalid exec_id: {exec_id!r}")
+    workspace = os.path.join(root, exec_id)
+    os.makedirs(workspace)
+    os.chdir(workspace)
+    return workspace
+
+
+def close_workspace(workspace):
+    """Leave and delete an execution's workspace"""
+    os.chdir("/")
+    shutil.rmtree(workspace, ignore_errors=True)
+
+
+def leftover_workspaces(root=WORKSPACE_ROOT):
+    """Number of workspaces not deleted, reported by `/health` so the host can check for
+    files one execution could leave to the next"""
+    try:
+        return len(os.listdir(root))
+    except FileNotFoundError:
+        return 0
 
 
 class CodeExecutionHandler(BaseHTTPRequestHandler):
@@ -21,6 +422,8 @@ class CodeExecutionHandler(BaseHTTPRequestHandler):
             self.handle_execute()
         elif self.path == "/shutdown":
             self.handle_shutdown()
+        elif self.path == "/set-time":
+            self.handle_set_time()
         else:
             self.send_error(404, "Not Found")
 
@@ -35,34 +438,141 @@ class CodeExecutionHandler(BaseHTTPRequestHandler):
         self.send_response(200)
         self.send_header("Content-Type", "application/json")
         self.end_headers()
-        response = {"status": "healthy", "message": "VM API server is running"}
+        response = {
+            "status": "healthy",
+            "message": "VM API server is running",
+            "protocol_version": PROTOCOL_VERSION,
+            "leftover_workspaces": leftover_workspaces(),
+        }
         self.wfile.write(json.dumps(response).encode())
 
     def handle_execute(self):
-        """Execute Python code"""
+        """Execute Python code; with `stream`, output lines are sent as the code prints them,
+        then the result as the last line"""
         try:
             content_length = int(self.headers["Content-Length"])
             post_data = self.rfile.read(content_length)
             request_data = json.loads(post_data.decode("utf-8"))
 
-            if "code" not in request_data:
-                self.send_error(400, "Missing 'code' field")
+            if "code" not in request_data and "files" not in request_data:
+                self.send_error(400, "Missing 'code' or 'files' field")
                 return
-
-            code = request_data["code"]
-
-            # Execute the code
-            result = self.execute_python_code(code)
-
+        except json.JSONDecodeError:
+            self.send_error(400, "Invalid JSON")
+            return
+        except Exception as e:
+            self.send_error(500, f"Internal server error: {str(e)}")
+            return
+
+        max_output_bytes = int(
+            request_data.get("max_output_bytes") or DEFAULT_MAX_OUTPUT_BYTES
+        )
+        live = None
+        if request_data.get("stream"):
+            # The status goes out before the code runs, so failures end up in the result
+            self.send_response(200)
+            self.send_header("Content-Type", "application/x-ndjson")
+            self.end_headers()
+            live = LiveOutput(self.wfile, max_output_bytes)
+        try:
+            result = self.run_execution(request_data, max_output_bytes, live)
+        except Exception as e:
+            if live is None:
+                self.send_error(500, f"Internal server error: {str(e)}")
+                return
+            result = {
+                "stdout": "",
+                "stderr": f"Internal server error: {str(e)}",
+                "exit_code": 1,
+                "success": False,
+            }
+        if live is None:
             self.send_response(200)
             self.send_header("Content-Type", "application/json")
             self.end_headers()
             self.wfile.write(json.dumps(result).encode())
+        else:
+            live.send({"type": "result", **result})
+
+    def run_execution(self, request_data, max_output_bytes, live=None):
+        """Set up and run an `/execute` request, returning the response fields"""
+        # Requests are served one at a time, so the workspace is gone before the host's
+        # next request, e.g. the `/health` check that nothing was left behind
+        exec_id = request_data.get("exec_id")
+        workspace = open_workspace(exec_id) if exec_id else None
+        try:
+            # Install requirements first; a failed install skips execution
+            requirements = request_data.get("requirements") or []
+            setup = self.install_requirements(requirements, max_output_bytes) if requirements else None
+
+            # Then download inputs; a failed download skips execution as well
+            inputs = request_data.get("inputs") or []
+            setup_error = None
+            if inputs and (setup is None or setup["success"]):
+                max_input_bytes = int(
+                    request_data.get("max_input_bytes") or DEFAULT_MAX_INPUT_BYTES
+                )
+                setup_error = download_inputs(inputs, max_input_bytes)
+                os.environ["FC_INPUTS_DIR"] = INPUTS_DIR
 
-        except json.JSONDecodeError:
-            self.send_error(400, "Invalid JSON")
-        except Exception as e:
-            self.send_error(500, f"Internal server error: {str(e)}")
+            # Execute the code
+            if setup is not None and not setup.pop("success"):
+                result = {
+                    "stdout": "",
+                    "stderr": "Failed to install requirements",
+                    "exit_code": 1,
+                    "success": False,
+                }
+            elif setup_error is not None:
+                result = {
+                    "stdout": "",
+                    "stderr": "Failed to download inputs",
+                    "exit_code": 1,
+                    "success": False,
+                    "setup_error": setup_error,
+                }
+            elif "files" in request_data:
+                result = self.execute_project(
+                    request_data["files"],
+                    request_data.get("entrypoint", ""),
+                    max_output_bytes,
+                    workspace,
+                    live,
+                )
+            else:
+                result = self.execute_python_code(
+                    request_data["code"],
+                    max_output_bytes,
+                    evaluate_last=request_data.get("mode") == "eval",
+                    live=live,
+                )
+            if setup is not None:
+                result.update(setup)
+            return result
+        finally:
+            if workspace is not None:
+                close_workspace(workspace)
+
+    def handle_set_time(self):
+        """Set the guest clock to the host's, reporting it before and after"""
+        try:
+            content_length = int(self.headers["Content-Length"])
+            request_data = json.loads(self.rfile.read(content_length).decode("utf-8"))
+            epoch_ms = int(request_data["epoch_ms"])
+        except (KeyError, TypeError, ValueError):
+            self.send_error(400, "Missing or invalid 'epoch_ms' field")
+            return
+        previous_ms = time.time_ns() // 1_000_000
+        try:
+            time.clock_settime(time.CLOCK_REALTIME, epoch_ms / 1000)
+        except OSError as e:
+            self.send_error(500, f"Failed to set clock: {e}")
+            return
+        response = {"previous_ms": previous_ms, "now_ms": time.time_ns() // 1_000_000}
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.end_headers()
+        self.wfile.write(json.dumps(response).encode())
 
     def handle_shutdown(self):
         """Shutdown the VM"""
@@ -79,54 +589,81 @@ class CodeExecutionHandler(BaseHTTPRequestHandler):
 
         threading.Thread(target=shutdown_vm, daemon=True).start()
 
-    def execute_python_code(self, code):
+    def execute_python_code(
+        self, code, max_output_bytes=DEFAULT_MAX_OUTPUT_BYTES, evaluate_last=False, live=None
+    ):
         """Execute Python code and return the result"""
+        if DETERMINISTIC:
+            return self.execute_code_subprocess(code, max_output_bytes, evaluate_last, live)
         try:
             # First, try direct execution without subprocess (safer in restricted environments)
-            return self.execute_code_directly(code)
+            return self.execute_code_directly(code, max_output_bytes, evaluate_last, live)
         except Exception as direct_error:
             print(f"Direct execution failed: {direct_error}")
             # Fallback to subprocess method
-            return self.execute_code_subprocess(code)
+            return self.execute_code_subprocess(code, max_output_bytes, evaluate_last, live)
 
-    def execute_code_directly(self, code):
+    def execute_code_directly(
+        self, code, max_output_bytes=DEFAULT_MAX_OUTPUT_BYTES, evaluate_last=False, live=None
+    ):
         """Execute Python code directly in the current process"""
-        import io
         import contextlib
-        import sys
 
-        # Capture stdout and stderr
-        stdout_capture = io.StringIO()
-        stderr_capture = io.StringIO()
+        # Capture stdout and stderr, dropping anything past the output limit
+        def on_write(name):
+            return None if live is None else lambda data: live.write(name, data)
+
+        stdout_capture = OutputCapture(max_output_bytes, on_write("stdout"))
+        stderr_capture = OutputCapture(max_output_bytes, on_write("stderr"))
+
+        start_wall = time.monotonic()
+        start_cpu = time.thread_time()
+
+        def usage():
+            # In-process, peak RSS is the agent's own high-water mark
+            return usage_fields(
+                time.monotonic() - start_wall,
+                time.thread_time() - start_cpu,
+                resource.getrusage(resource.RUSAGE_SELF).ru_maxrss,
+            )
 
         try:
             # Redirect stdout and stderr
-            with contextlib.redirect_stdout(stdout_capture), contextlib.redirect_stderr(
-                stderr_capture
-            ):
+            with contextlib.redirect_stdout(
+                stdout_capture.stream
+            ), contextlib.redirect_stderr(stderr_capture.stream):
                 # Create a new namespace for execution
                 exec_globals = {"__name__": "__main__", "__builtins__": __builtins__}
                 exec_locals = {}
 
                 # Execute the code
-                exec(code, exec_globals, exec_locals)
+                if evaluate_last:
+                    value = evaluate(code, exec_globals)
+                else:
+                    exec(code, exec_globals, exec_locals)
+                    value = {}
 
             return {
-                "stdout": stdout_capture.getvalue(),
-                "stderr": stderr_capture.getvalue(),
+                **output_fields(stdout_capture, stderr_capture),
                 "exit_code": 0,
                 "success": True,
+                "usage": usage(),
+                **value,
             }
 
         except Exception as e:
+            stderr_capture.stream.write(f"\nExecution error: {str(e)}")
             return {
-                "stdout": stdout_capture.getvalue(),
-                "stderr": stderr_capture.getvalue() + f"\nExecution error: {str(e)}",
+                **output_fields(stdout_capture, stderr_capture),
                 "exit_code": 1,
                 "success": False,
+                "exception": describe_exception(e),
+                "usage": usage(),
             }
 
-    def execute_code_subprocess(self, code):
+    def execute_code_subprocess(
+        self, code, max_output_bytes=DEFAULT_MAX_OUTPUT_BYTES, evaluate_last=False, live=None
+    ):
         """Execute Python code in a subprocess (fallback method)"""
         try:
             # Ensure /tmp directory exists and is writable
@@ -162,23 +699,27 @@ class CodeExecutionHandler(BaseHTTPRequestHandler):
             )
             print(f"Python executable: {sys.executable}")
 
-            # Execute the Python code
-            result = subprocess.run(
-                [sys.executable, temp_file],
-                capture_output=True,
-                text=True,
+            # Execute the Python code; evaluation runs this script's `--eval` mode instead
+            value_file = f"{temp_file}.value.json"
+            args = [sys.executable, temp_file]
+            if evaluate_last:
+                args = [sys.executable, os.path.abspath(__file__), "--eval", temp_file, value_file]
+            result, usage = run_with_usage(
+                args,
                 timeout=30,  # 30 second timeout
+                live=live,
             )
 
+            fields = completed_fields(result, usage, max_output_bytes)
+            if os.path.exists(value_file):
+                with open(value_file) as f:
+                    fields.update(json.load(f))
+                os.unlink(value_file)
+
             # Clean up
             os.unlink(temp_file)
 
-            return {
-                "stdout": result.stdout,
-                "stderr": result.stderr,
-                "exit_code": result.returncode,
-                "success": result.returncode == 0,
-            }
+            return fields
 
         except subprocess.TimeoutExpired:
             if "temp_file" in locals():
@@ -199,17 +740,225 @@ class CodeExecutionHandler(BaseHTTPRequestHandler):
                 "success": False,
             }
 
+    def install_requirements(self, requirements, max_output_bytes=DEFAULT_MAX_OUTPUT_BYTES):
+        """pip install requirements, returning setup_stdout/setup_stderr and success"""
+        args = [
+            sys.executable,
+            "-m",
+            "pip",
+            "install",
+            "--no-input",
+            "--disable-pip-version-check",
+            # Keep requirement strings from being read as options
+            "--",
+            *requirements,
+        ]
+        try:
+            result, _ = run_with_usage(args, timeout=SETUP_TIMEOUT_SECONDS)
+        except subprocess.TimeoutExpired:
+            return {
+                "setup_stdout": "",
+                "setup_stderr": f"Installation timed out ({SETUP_TIMEOUT_SECONDS} seconds)",
+                "success": False,
+            }
+        except Exception as e:
+            return {"setup_stdout": "", "setup_stderr": f"Installation error: {e}", "success": False}
+        return {
+            "setup_stdout": result.stdout[:max_output_bytes].decode("utf-8", "replace"),
+            "setup_stderr": result.stderr[:max_output_bytes].decode("utf-8", "replace"),
+            "success": result.returncode == 0,
+        }
+
+    def execute_project(
+        self,
+        files,
+        entrypoint,
+        max_output_bytes=DEFAULT_MAX_OUTPUT_BYTES,
+        workspace=None,
+        live=None,
+    ):
+        """Write a multi-file program into the execution's workspace, or a fresh working
+        directory without one, and run its entrypoint"""
+        workdir = workspace or tempfile.mkdtemp(prefix="project-", dir="/tmp")
+        try:
+            write_project(workdir, files)
+            # Running by path puts the working directory first on sys.path for imports
+            result, usage = run_with_usage(
+                [sys.executable, entrypoint],
+                timeout=30,  # 30 second timeout
+                cwd=workdir,
+                live=live,
+            )
+            return completed_fields(result, usage, max_output_bytes)
+        except subprocess.TimeoutExpired:
+            return {
+                "stdout": "",
+                "stderr": "Code execution timed out (30 seconds)",
+                "exit_code": 1,
+                "success": False,
+            }
+        except Exception as e:
+            return {
+                "stdout": "",
+                "stderr": f"Execution error: {str(e)}",
+                "exit_code": 1,
+                "success": False,
+            }
+        finally:
+            if workspace is None:
+                shutil.rmtree(workdir, ignore_errors=True)
+
     def log_message(self, format, *args):
         """Override to reduce logging noise"""
         pass
 
 
-def main():
-    # Start the HTTP server
-    server_address = ("0.0.0.0", 8080)
-    httpd = HTTPServer(server_address, CodeExecutionHandler)
+def boot_arg(name, cmdline_path="/proc/cmdline"):
+    """Value of a `name=value` kernel command-line parameter, or None"""
+    try:
+        with open(cmdline_path) as f:
+            params = f.read().split()
+    except OSError:
+        return None
+    for param in params:
+        key, _, value = param.partition("=")
+        if key == name:
+            return value
+    return None
+
+
+def mount_deps():
+    """Mount the dependencies drive and make its packages importable.
+
+    The image holds `site-packages/`, added to the import path, and optionally `wheels/`,
+    which pip uses as a local index when installing `requirements`.
+    """
+    device = boot_arg("fc_deps")
+    if not device:
+        return
+    os.makedirs(DEPS_MOUNT_POINT, exist_ok=True)
+    result = subprocess.run(
+        ["mount", "-o", "ro", device, DEPS_MOUNT_POINT], capture_output=True, text=True
+    )
+    if result.returncode != 0:
+        print(f"Failed to mount deps drive {device}: {result.stderr.strip()}")
+        return
+    site_packages = os.path.join(DEPS_MOUNT_POINT, "site-packages")
+    if os.path.isdir(site_packages):
+        sys.path.insert(0, site_packages)
+        # Subprocess executions inherit the environment
+        os.environ["PYTHONPATH"] = os.pathsep.join(
+            p for p in [site_packages, os.environ.get("PYTHONPATH")] if p
+        )
+    wheels = os.path.join(DEPS_MOUNT_POINT, "wheels")
+    if os.path.isdir(wheels):
+        os.environ["PIP_FIND_LINKS"] = wheels
+    print(f"Mounted deps drive {device} at {DEPS_MOUNT_POINT}")
+
+
+def mount_reference_data(cmdline_path="/proc/cmdline"):
+    """Mount every reference data drive read-only under REFERENCE_DIR"""
+    manifest = boot_arg("fc_refdata", cmdline_path)
+    if not manifest:
+        return
+    for entry in manifest.split(","):
+        name, _, device = entry.partition(":")
+        if not name or not device:
+            print(f"Ignoring malformed reference drive {entry!r}")
+            continue
+        mount_point = os.path.join(REFERENCE_DIR, name)
+        os.makedirs(mount_point, exist_ok=True)
+        # The filesystem, ext4 or squashfs, is detected by mount
+        result = subprocess.run(
+            ["mount", "-o", "ro", device, mount_point], capture_output=True, text=True
+        )
+        if result.returncode != 0:
+            print(f"Failed to mount reference drive {device}: {result.stderr.strip()}")
+            continue
+        print(f"Mounted reference drive {device} at {mount_point}")
+    # Subprocess executions inherit the environment
+    os.environ["FC_REFERENCE_DIR"] = REFERENCE_DIR
+
+
+def apply_deterministic_settings(cmdline_path="/proc/cmdline"):
+    """Pin the hash seed, time zone, locale and optionally the clock for deterministic runs"""
+    global DETERMINISTIC
+    if boot_arg("fc_deterministic", cmdline_path) != "1":
+        return
+    DETERMINISTIC = True
+    os.environ["PYTHONHASHSEED"] = boot_arg("fc_hash_seed", cmdline_path) or "0"
+    os.environ["TZ"] = boot_arg("fc_tz", cmdline_path) or "UTC"
+    time.tzset()
+    locale_name = boot_arg("fc_locale", cmdline_path) or "C.UTF-8"
+    os.environ["LC_ALL"] = locale_name
+    os.environ["LANG"] = locale_name
+    fake_time = boot_arg("fc_fake_time", cmdline_path)
+    if fake_time:
+        result = subprocess.run(["date", "-s", f"@{fake_time}"], capture_output=True, text=True)
+        if result.returncode != 0:
+            print(f"Failed to set clock to {fake_time}: {result.stderr.strip()}")
+    print("Deterministic mode enabled")
+
+
+def apply_network_settings(cmdline_path="/proc/cmdline", resolv_conf="/etc/resolv.conf"):
+    """Point the resolver at the name servers and export the proxies the host passed in"""
+    dns = boot_arg("fc_dns", cmdline_path)
+    if dns:
+        servers = [server for server in dns.split(",") if server]
+        try:
+            with open(resolv_conf, "w") as f:
+                f.writelines(f"nameserver {server}\n" for server in servers)
+            print(f"Using name servers {', '.join(servers)}")
+        except OSError as e:
+            print(f"Failed to write {resolv_conf}: {e}")
+    # Both spellings, since tools disagree on which one they read
+    for name in ("http_proxy", "https_proxy", "no_proxy"):
+        value = boot_arg(f"fc_{name}", cmdline_path)
+        if value:
+            os.environ[name] = value
+            os.environ[name.upper()] = value
+
+
+def eval_main(code_path, value_path):
+    """`--eval`: evaluate the file at code_path, writing its value fields to value_path"""
+    with open(code_path) as f:
+        code = f.read()
+    value = evaluate(code, {"__name__": "__main__"}, code_path)
+    with open(value_path, "w") as f:
+        json.dump(value, f)
 
-    print(f"VM API Server starting on {server_address[0]}:{server_address[1]}")
+
+class VsockHTTPServer(HTTPServer):
+    """HTTP server on a vsock port, for hosts reaching the agent over a vsock device"""
+
+    address_family = socket.AF_VSOCK
+
+    def server_bind(self):
+        # HTTPServer looks the address up as a host name, which a vsock address isn't
+        socketserver.TCPServer.server_bind(self)
+        self.server_name = "localhost"
+        self.server_port = self.server_address[1]
+
+
+def main():
+    if sys.argv[1:2] == ["--eval"]:
+        eval_main(*sys.argv[2:4])
+        return
+    mount_deps()
+    mount_reference_data()
+    apply_deterministic_settings()
+    apply_network_settings()
+
+    # Start the HTTP server, on the vsock port the host reaches the agent on if it uses one
+    vsock_port = boot_arg("fc_agent_vsock_port")
+    if vsock_port:
+        server_address = (socket.VMADDR_CID_ANY, int(vsock_port))
+        httpd = VsockHTTPServer(server_address, CodeExecutionHandler)
+        print(f"VM API Server starting on vsock port {vsock_port}")
+    else:
+        server_address = ("0.0.0.0", 8080)
+        httpd = HTTPServer(server_address, CodeExecutionHandler)
+        print(f"VM API Server starting on {server_address[0]}:{server_address[1]}")
     print("Ready to receive code execution requests")
 
     try: